@@ -0,0 +1,480 @@
+//! Alerting for critical governance events
+//!
+//! Every alert is written to the audit log. When the gateway is built with the
+//! `smtp` feature and `SMTP_HOST`/`SMTP_FROM`/`SMTP_TO` are set, alerts are
+//! also emailed to each recipient, subject to a per-recipient rate limit
+//! (`SMTP_RATE_LIMIT_PER_HOUR`) so an incident cannot flood an inbox.
+//!
+//! With `ALERT_WEBHOOK_URL` set, every alert is also POSTed there as JSON
+//! (the [`Alert`] fields), with a `ALERT_WEBHOOK_TIMEOUT_MS` timeout.
+//!
+//! Subject and body are rendered from templates with `{kind}`, `{agent_id}`,
+//! `{team}`, `{org}`, `{detail}`, and `{ts}` placeholders; override the defaults with
+//! `ALERT_SUBJECT_TEMPLATE` / `ALERT_BODY_TEMPLATE`.
+//!
+//! Alerts raised by mirrored requests on a shadow gateway are audited only.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    env, fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+
+use crate::{env_parse, mirror, now_unix_sec};
+
+const DEFAULT_SUBJECT_TEMPLATE: &str = "[policy-gateway] {kind}: {agent_id}";
+const DEFAULT_BODY_TEMPLATE: &str = "Governance alert\n\n\
+    Event:    {kind}\n\
+    Agent:    {agent_id}\n\
+    Team:     {team} ({org})\n\
+    Time:     {ts}\n\n\
+    {detail}\n";
+
+// =============================================================================
+// Alerts
+// =============================================================================
+
+/// Critical event kinds that warrant an out-of-band notification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    AgentSuspended,
+    ChainVerificationFailed,
+    ReportFraudDetected,
+    SloBurnRate,
+    QuotaExceeded,
+    ProtocolMismatchSuspected,
+    SoftLimitApproached,
+    RiskTierRaised,
+    TrialGraduated,
+    RecertificationDue,
+    RecertificationLapsed,
+    AgentDormant,
+    AgentOffboarded,
+    UndeclaredProtocolSuspected,
+    StoreWritesShed,
+    Test,
+}
+
+impl fmt::Display for AlertKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::AgentSuspended => "agent_suspended",
+            Self::ChainVerificationFailed => "chain_verification_failed",
+            Self::ReportFraudDetected => "report_fraud_detected",
+            Self::SloBurnRate => "slo_burn_rate",
+            Self::QuotaExceeded => "quota_exceeded",
+            Self::ProtocolMismatchSuspected => "protocol_mismatch_suspected",
+            Self::SoftLimitApproached => "soft_limit_approached",
+            Self::RiskTierRaised => "risk_tier_raised",
+            Self::TrialGraduated => "trial_graduated",
+            Self::RecertificationDue => "recertification_due",
+            Self::RecertificationLapsed => "recertification_lapsed",
+            Self::AgentDormant => "agent_dormant",
+            Self::AgentOffboarded => "agent_offboarded",
+            Self::UndeclaredProtocolSuspected => "possible_undeclared_protocol",
+            Self::StoreWritesShed => "store_writes_shed",
+            Self::Test => "test",
+        })
+    }
+}
+
+/// A single alert
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub agent_id: Option<String>,
+    /// Team owning the agent, if known
+    pub team: Option<String>,
+    /// Org owning the team, if known
+    pub org: Option<String>,
+    pub detail: String,
+    pub ts: u64,
+}
+
+impl Alert {
+    pub fn new(kind: AlertKind, agent_id: Option<&str>, detail: impl Into<String>) -> Self {
+        Self {
+            kind,
+            agent_id: agent_id.map(str::to_string),
+            team: None,
+            org: None,
+            detail: detail.into(),
+            ts: now_unix_sec(),
+        }
+    }
+
+    /// Attribute the alert to the agent's owning team and org
+    pub fn with_owner(mut self, team: Option<String>, org: Option<String>) -> Self {
+        self.team = team;
+        self.org = org;
+        self
+    }
+}
+
+/// Substitute alert fields into a template
+pub fn render_template(template: &str, alert: &Alert) -> String {
+    template
+        .replace("{kind}", &alert.kind.to_string())
+        .replace("{agent_id}", alert.agent_id.as_deref().unwrap_or("-"))
+        .replace("{team}", alert.team.as_deref().unwrap_or("-"))
+        .replace("{org}", alert.org.as_deref().unwrap_or("-"))
+        .replace("{detail}", &alert.detail)
+        .replace("{ts}", &alert.ts.to_string())
+}
+
+// =============================================================================
+// Rate Limiting
+// =============================================================================
+
+/// Sliding-window limit on alerts per recipient
+#[derive(Debug)]
+pub struct RecipientRateLimiter {
+    max: usize,
+    window: Duration,
+    sent: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl RecipientRateLimiter {
+    pub fn new(max: usize, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            sent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a send to `recipient` if it is under its limit
+    pub fn try_acquire(&self, recipient: &str, now: Instant) -> bool {
+        let mut sent = self.sent.lock().unwrap();
+        let times = sent.entry(recipient.to_string()).or_default();
+        while times
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= self.window)
+        {
+            times.pop_front();
+        }
+        if times.len() >= self.max {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+}
+
+// =============================================================================
+// Alerter
+// =============================================================================
+
+/// Outcome of dispatching an alert
+#[derive(Debug, Default, Serialize)]
+pub struct Dispatch {
+    pub delivered: Vec<String>,
+    pub rate_limited: Vec<String>,
+    pub failed: Vec<String>,
+}
+
+/// Alert dispatcher: audit log plus optional webhook and email channels
+pub struct Alerter {
+    subject_template: String,
+    body_template: String,
+    limiter: RecipientRateLimiter,
+    webhook: Option<WebhookChannel>,
+    #[cfg(feature = "smtp")]
+    smtp: Option<smtp::SmtpChannel>,
+}
+
+impl Default for Alerter {
+    fn default() -> Self {
+        Self {
+            subject_template: DEFAULT_SUBJECT_TEMPLATE.to_string(),
+            body_template: DEFAULT_BODY_TEMPLATE.to_string(),
+            limiter: RecipientRateLimiter::new(20, Duration::from_secs(3600)),
+            webhook: None,
+            #[cfg(feature = "smtp")]
+            smtp: None,
+        }
+    }
+}
+
+impl Alerter {
+    /// Load templates, rate limit, webhook, and (with the `smtp` feature) SMTP settings
+    pub fn from_env() -> Self {
+        let per_hour = env_parse("SMTP_RATE_LIMIT_PER_HOUR").unwrap_or(20);
+        Self {
+            subject_template: env::var("ALERT_SUBJECT_TEMPLATE")
+                .unwrap_or_else(|_| DEFAULT_SUBJECT_TEMPLATE.to_string()),
+            body_template: env::var("ALERT_BODY_TEMPLATE")
+                .unwrap_or_else(|_| DEFAULT_BODY_TEMPLATE.to_string()),
+            limiter: RecipientRateLimiter::new(per_hour, Duration::from_secs(3600)),
+            webhook: WebhookChannel::from_env(),
+            #[cfg(feature = "smtp")]
+            smtp: smtp::SmtpChannel::from_env(),
+        }
+    }
+
+    /// Whether a webhook or email channel is configured
+    pub fn has_channels(&self) -> bool {
+        self.webhook.is_some() || !self.recipients().is_empty()
+    }
+
+    /// Email recipients of every alert
+    fn recipients(&self) -> &[String] {
+        #[cfg(feature = "smtp")]
+        if let Some(smtp) = &self.smtp {
+            return smtp.recipients();
+        }
+        &[]
+    }
+
+    #[cfg_attr(not(feature = "smtp"), allow(unused_variables))]
+    async fn deliver(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        #[cfg(feature = "smtp")]
+        if let Some(smtp) = &self.smtp {
+            return smtp.send(to, subject, body).await;
+        }
+        Err("no email channel configured".to_string())
+    }
+
+    /// Log the alert and deliver it to every configured recipient
+    pub async fn notify(&self, alert: &Alert) -> Dispatch {
+        warn!(
+            event = "governance_alert",
+            kind = %alert.kind,
+            agent_id = alert.agent_id.as_deref(),
+            team = alert.team.as_deref(),
+            org = alert.org.as_deref(),
+            detail = %alert.detail,
+            "Governance alert"
+        );
+
+        let mut dispatch = Dispatch::default();
+        if mirror::is_mirrored() {
+            return dispatch;
+        }
+        if let Some(webhook) = &self.webhook {
+            match webhook.send(alert).await {
+                Ok(()) => dispatch.delivered.push("webhook".to_string()),
+                Err(e) => {
+                    error!(
+                        event = "alert_delivery_failed",
+                        recipient = "webhook",
+                        error = %e,
+                        "Alert webhook failed"
+                    );
+                    dispatch.failed.push("webhook".to_string());
+                }
+            }
+        }
+        let subject = render_template(&self.subject_template, alert);
+        let body = render_template(&self.body_template, alert);
+        for to in self.recipients() {
+            if !self.limiter.try_acquire(to, Instant::now()) {
+                dispatch.rate_limited.push(to.clone());
+                continue;
+            }
+            match self.deliver(to, &subject, &body).await {
+                Ok(()) => dispatch.delivered.push(to.clone()),
+                Err(e) => {
+                    error!(
+                        event = "alert_delivery_failed",
+                        recipient = %to,
+                        error = %e,
+                        "Alert email failed"
+                    );
+                    dispatch.failed.push(to.clone());
+                }
+            }
+        }
+
+        info!(
+            event = "alert_dispatched",
+            kind = %alert.kind,
+            delivered = dispatch.delivered.len(),
+            rate_limited = dispatch.rate_limited.len(),
+            failed = dispatch.failed.len(),
+            "Alert dispatched"
+        );
+        dispatch
+    }
+}
+
+// =============================================================================
+// Webhook Channel
+// =============================================================================
+
+/// JSON POST of each alert to a fixed URL
+struct WebhookChannel {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl WebhookChannel {
+    fn from_env() -> Option<Self> {
+        let url = env::var("ALERT_WEBHOOK_URL").ok().filter(|u| !u.trim().is_empty())?;
+        let timeout_ms = env_parse("ALERT_WEBHOOK_TIMEOUT_MS").unwrap_or(5_000);
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(timeout_ms))
+            .build()
+            .ok()?;
+        Some(Self { url, client })
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), String> {
+        let resp = self
+            .client
+            .post(&self.url)
+            .json(alert)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("webhook returned {}", resp.status()))
+        }
+    }
+}
+
+// =============================================================================
+// SMTP Channel
+// =============================================================================
+
+#[cfg(feature = "smtp")]
+mod smtp {
+    use lettre::{
+        message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+        AsyncTransport, Message, Tokio1Executor,
+    };
+    use std::env;
+    use tracing::warn;
+
+    use crate::env_parse;
+
+    /// Email delivery over SMTP (STARTTLS unless `SMTP_STARTTLS=false`)
+    pub struct SmtpChannel {
+        transport: AsyncSmtpTransport<Tokio1Executor>,
+        from: Mailbox,
+        to: Vec<String>,
+    }
+
+    impl SmtpChannel {
+        /// Build from `SMTP_*` variables; `None` unless host, sender, and recipients are set
+        pub fn from_env() -> Option<Self> {
+            let host = env::var("SMTP_HOST").ok()?;
+            let from = env::var("SMTP_FROM").ok()?;
+            let to: Vec<String> = env::var("SMTP_TO")
+                .ok()?
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+            if to.is_empty() {
+                return None;
+            }
+            let from = match from.parse() {
+                Ok(m) => m,
+                Err(e) => {
+                    warn!(event = "config_invalid", error = %e, "Invalid SMTP_FROM, email alerts disabled");
+                    return None;
+                }
+            };
+
+            let starttls = env::var("SMTP_STARTTLS").map_or(true, |v| v != "false");
+            let builder = if starttls {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host).ok()?
+            } else {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host)
+            };
+            let mut builder = builder;
+            if let Some(port) = env_parse("SMTP_PORT") {
+                builder = builder.port(port);
+            }
+            if let (Ok(user), Ok(pass)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+                builder = builder.credentials(Credentials::new(user, pass));
+            }
+
+            Some(Self {
+                transport: builder.build(),
+                from,
+                to,
+            })
+        }
+
+        pub fn recipients(&self) -> &[String] {
+            &self.to
+        }
+
+        pub async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+            let message = Message::builder()
+                .from(self.from.clone())
+                .to(to.parse().map_err(|e| format!("invalid recipient: {e}"))?)
+                .subject(subject)
+                .body(body.to_string())
+                .map_err(|e| e.to_string())?;
+            self.transport
+                .send(message)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{Method, StatusCode};
+
+    #[test]
+    fn test_render_template() {
+        let alert = Alert {
+            kind: AlertKind::AgentSuspended,
+            agent_id: Some("agent-7".into()),
+            team: Some("red".into()),
+            org: None,
+            detail: "3 overdue reports".into(),
+            ts: 1700000000,
+        };
+        assert_eq!(
+            render_template(DEFAULT_SUBJECT_TEMPLATE, &alert),
+            "[policy-gateway] agent_suspended: agent-7"
+        );
+        assert_eq!(
+            render_template("{detail} @ {ts}", &alert),
+            "3 overdue reports @ 1700000000"
+        );
+        assert_eq!(render_template("{team}/{org}", &alert), "red/-");
+    }
+
+    #[test]
+    fn test_rate_limit_per_recipient() {
+        let limiter = RecipientRateLimiter::new(2, Duration::from_secs(60));
+        let t0 = Instant::now();
+        assert!(limiter.try_acquire("a@x", t0));
+        assert!(limiter.try_acquire("a@x", t0));
+        assert!(!limiter.try_acquire("a@x", t0));
+        assert!(limiter.try_acquire("b@x", t0));
+        assert!(limiter.try_acquire("a@x", t0 + Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_test_alert_refused() {
+        let gw = crate::testing::TestGateway::new();
+        let bad = serde_json::json!({"kind": "no_such_kind"});
+        let resp = gw.admin(Method::POST, "/admin/alerts/test", Some(&bad)).await;
+        assert_eq!(resp.status, StatusCode::BAD_REQUEST);
+        assert_eq!(resp.body["body_error"]["field"], "kind");
+
+        // No body previews the test template; here no channel is configured
+        let resp = gw.admin(Method::POST, "/admin/alerts/test", None::<&()>).await;
+        assert_eq!(resp.body["code"], "no_alert_channel");
+    }
+}
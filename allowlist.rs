@@ -0,0 +1,372 @@
+//! Allowlist of benign machine-output formats
+//!
+//! JSON payloads, stack traces and URLs from legitimate tool use look nothing
+//! like English and would otherwise be flagged as novel language. Content
+//! matching an allowlisted pattern is classified as benign machine output and
+//! passes like English, without a protocol declaration.
+//!
+//! Patterns come from `BENIGN_PATTERNS`, either a comma-separated list of
+//! built-in names or a JSON array of entries:
+//!
+//! ```json
+//! [
+//!   {"name": "json"},
+//!   {"name": "build_id", "pattern": "build-[0-9]{6}"},
+//!   {"name": "trace", "pattern": "(?s)panicked at .*", "content_types": ["text/x-rust-backtrace"]}
+//! ]
+//! ```
+//!
+//! - `pattern` must match the whole message (it is anchored at both ends);
+//!   omit it to use the built-in pattern of the same name
+//! - `content_types` restricts the pattern to sends whose `content_type` hint
+//!   is listed; a hint never allowlists content the pattern does not match
+//!
+//! Built-ins: `json` (content that parses as a JSON object or array),
+//! `stack_trace` (Python, JVM, JavaScript and Rust traces with at least one
+//! frame line) and `url` (one or more whitespace-separated http(s) URLs).
+//! Nothing is allowlisted by default. JSON and URLs can carry arbitrary text,
+//! so a `json` or `url` match also returns the words in the document's keys
+//! and string values, or in the URLs' paths and queries; the caller runs them
+//! through the language detector before passing the send.
+//!
+//! Every pattern counts its matches so an overly broad one shows up in
+//! `/metrics` and `/admin/patterns`.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tracing::warn;
+
+/// Built-in patterns: (name, regex)
+const BUILTIN: &[(&str, &str)] = &[
+    ("json", r"(?s)\s*(?:\{.*\}|\[.*\])\s*"),
+    (
+        "stack_trace",
+        concat!(
+            r"\s*(?:",
+            // Python: frames, then the exception
+            r#"Traceback \(most recent call last\):(?:\n  File "[^"\n]+", line \d+(?:, in [^\n]+)?(?:\n    [^\n]*)?)+\n[\w.]+(?:: [^\n]*)?"#,
+            // JVM: the exception, then `at` frames, optionally chained causes
+            r#"|(?:Exception in thread "[^"\n]*" )?[\w$.]*(?:Error|Exception|Throwable)(?:: [^\n]*)?(?:\n[ \t]+at [\w$.<>/]+\([^()\n]*\))+(?:\n[ \t]+\.\.\. \d+ more|\nCaused by: [\w$.]+(?:: [^\n]*)?(?:\n[ \t]+at [\w$.<>/]+\([^()\n]*\))+)*"#,
+            // JavaScript: the error, then `at` frames with line and column
+            r"|[\w$.]*Error(?:: [^\n]*)?(?:\n[ \t]+at (?:[^\n()]+ \([^()\n]+:\d+:\d+\)|[^\s()]+:\d+:\d+))+",
+            // Rust: the panic and its message, then a numbered backtrace
+            r"|thread '[^'\n]*' panicked at [^\n]+(?:\n[^\n]*)?\nstack backtrace:(?:\n[ \t]+\d+: [^\n]+(?:\n[ \t]+at [^\n]+:\d+(?::\d+)?)?)+(?:\nnote: [^\n]*)?",
+            r")\s*"
+        ),
+    ),
+    ("url", r"\s*https?://\S+(?:\s+https?://\S+)*\s*"),
+];
+
+/// Built-ins whose matches can carry free text
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Carrier {
+    Json,
+    Url,
+}
+
+impl Carrier {
+    fn of(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Self::Json),
+            "url" => Some(Self::Url),
+            _ => None,
+        }
+    }
+
+    /// Words carried by matched `content`, or `None` when it is not in the
+    /// format after all
+    fn text(self, content: &str) -> Option<String> {
+        let mut fragments = Vec::new();
+        match self {
+            Self::Json => collect_strings(&serde_json::from_str(content).ok()?, &mut fragments),
+            Self::Url => {
+                for url in content.split_whitespace() {
+                    let url = reqwest::Url::parse(url).ok()?;
+                    fragments.extend(url.path_segments().into_iter().flatten().map(|s| {
+                        percent_decode(s)
+                    }));
+                    fragments.extend(url.query_pairs().flat_map(|(k, v)| [k.into_owned(), v.into_owned()]));
+                    fragments.extend(url.fragment().map(percent_decode));
+                }
+            }
+        }
+        Some(words(&fragments))
+    }
+}
+
+fn collect_strings(value: &serde_json::Value, out: &mut Vec<String>) {
+    match value {
+        serde_json::Value::String(s) => out.push(s.clone()),
+        serde_json::Value::Array(items) => items.iter().for_each(|v| collect_strings(v, out)),
+        serde_json::Value::Object(map) => {
+            for (k, v) in map {
+                out.push(k.clone());
+                collect_strings(v, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Words of `fragments`, leaving out numbers and hex identifiers, which
+/// carry no language
+fn words(fragments: &[String]) -> String {
+    fragments
+        .iter()
+        .flat_map(|f| f.split(|c: char| !c.is_alphanumeric()))
+        .filter(|w| w.chars().any(char::is_alphabetic))
+        .filter(|w| !(w.chars().all(|c| c.is_ascii_hexdigit()) && w.chars().any(|c| c.is_ascii_digit())))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// One configured pattern as written in `BENIGN_PATTERNS`
+#[derive(Debug, Clone, Deserialize)]
+struct PatternSpec {
+    name: String,
+    #[serde(default)]
+    pattern: Option<String>,
+    #[serde(default)]
+    content_types: Vec<String>,
+}
+
+/// A compiled allowlist pattern with its match counter
+#[derive(Debug)]
+struct ContentPattern {
+    name: String,
+    /// Pattern as configured, before anchoring
+    source: String,
+    regex: Regex,
+    /// Lowercased media types the pattern is restricted to, if any
+    content_types: Vec<String>,
+    /// Built-in `json` or `url`: the regex only checks the outer shape, so
+    /// the content must also parse, and the text it carries is returned
+    carrier: Option<Carrier>,
+    matches: AtomicU64,
+}
+
+/// A send matched by the allowlist
+#[derive(Debug, Clone, PartialEq)]
+pub struct Benign<'a> {
+    pub pattern: &'a str,
+    /// Words carried in JSON strings or URL paths and queries, to be
+    /// classified before the send passes; empty for other patterns
+    pub text: String,
+}
+
+/// A pattern and its match count, as listed by `/admin/patterns`
+#[derive(Debug, Clone, Serialize)]
+pub struct PatternStats {
+    pub name: String,
+    pub pattern: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub content_types: Vec<String>,
+    pub matches: u64,
+}
+
+/// Media type of a `content_type` hint, without parameters
+fn media_type(hint: &str) -> String {
+    hint.split(';').next().unwrap_or_default().trim().to_ascii_lowercase()
+}
+
+impl ContentPattern {
+    fn compile(spec: PatternSpec) -> Result<Self, String> {
+        let carrier = spec.pattern.is_none().then(|| Carrier::of(&spec.name)).flatten();
+        let source = match spec.pattern {
+            Some(p) => p,
+            None => BUILTIN
+                .iter()
+                .find(|(name, _)| *name == spec.name)
+                .map(|(_, p)| p.to_string())
+                .ok_or_else(|| format!("no built-in pattern named {:?}", spec.name))?,
+        };
+        let regex = Regex::new(&format!(r"\A(?:{source})\z")).map_err(|e| e.to_string())?;
+        Ok(Self {
+            name: spec.name,
+            source,
+            regex,
+            content_types: spec.content_types.iter().map(|t| media_type(t)).collect(),
+            carrier,
+            matches: AtomicU64::new(0),
+        })
+    }
+
+    /// Carried text when `content` matches
+    fn is_match(&self, content: &str, hint: Option<&str>) -> Option<String> {
+        if !self.content_types.is_empty() && !hint.is_some_and(|h| self.content_types.contains(&media_type(h))) {
+            return None;
+        }
+        if !self.regex.is_match(content) {
+            return None;
+        }
+        match self.carrier {
+            Some(carrier) => carrier.text(content),
+            None => Some(String::new()),
+        }
+    }
+}
+
+/// Configured benign-content patterns, checked in order
+#[derive(Debug, Default)]
+pub struct ContentAllowlist {
+    patterns: Vec<ContentPattern>,
+}
+
+impl ContentAllowlist {
+    pub fn from_env() -> Self {
+        match env::var("BENIGN_PATTERNS") {
+            Ok(raw) if !raw.trim().is_empty() => Self::parse(&raw),
+            _ => Self::default(),
+        }
+    }
+
+    /// Parse a JSON array of entries or a comma-separated list of built-in
+    /// names; invalid entries are logged and skipped
+    pub fn parse(raw: &str) -> Self {
+        let specs: Vec<PatternSpec> = if raw.trim_start().starts_with('[') {
+            serde_json::from_str(raw).unwrap_or_else(|e| {
+                warn!(event = "config_invalid", error = %e, "BENIGN_PATTERNS is not a valid pattern list");
+                Vec::new()
+            })
+        } else {
+            raw.split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| PatternSpec {
+                    name: name.to_string(),
+                    pattern: None,
+                    content_types: Vec::new(),
+                })
+                .collect()
+        };
+        let patterns = specs
+            .into_iter()
+            .filter_map(|spec| {
+                let name = spec.name.clone();
+                ContentPattern::compile(spec)
+                    .inspect_err(|e| warn!(event = "config_invalid", pattern = %name, error = %e, "Ignoring invalid benign pattern"))
+                    .ok()
+            })
+            .collect();
+        Self { patterns }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.patterns.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    /// First pattern matching `content`, counting the match
+    pub fn matches(&self, content: &str, content_type: Option<&str>) -> Option<Benign<'_>> {
+        let (pattern, text) = self
+            .patterns
+            .iter()
+            .find_map(|p| Some((p, p.is_match(content, content_type)?)))?;
+        pattern.matches.fetch_add(1, Ordering::Relaxed);
+        Some(Benign {
+            pattern: &pattern.name,
+            text,
+        })
+    }
+
+    pub fn stats(&self) -> Vec<PatternStats> {
+        self.patterns
+            .iter()
+            .map(|p| PatternStats {
+                name: p.name.clone(),
+                pattern: p.source.clone(),
+                content_types: p.content_types.clone(),
+                matches: p.matches.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_and_custom_patterns() {
+        let allow = ContentAllowlist::parse(
+            r#"[
+                {"name": "json"},
+                {"name": "stack_trace"},
+                {"name": "url"},
+                {"name": "build_id", "pattern": "build-[0-9]{6}", "content_types": ["text/x-build-id"]},
+                {"name": "broken", "pattern": "("},
+                {"name": "unknown"}
+            ]"#,
+        );
+        assert_eq!(allow.names(), ["json", "stack_trace", "url", "build_id"]);
+        let name = |content: &str, hint: Option<&str>| allow.matches(content, hint).map(|b| b.pattern.to_string());
+        let text = |content: &str| allow.matches(content, None).map(|b| b.text);
+
+        let id = "3f2a9c01-77aa-4b1e-9d2c-00ff11ee22dd";
+        let json = format!(r#"{{"status": "ok", "items": [1, 2], "id": "{id}"}}"#);
+        assert_eq!(text(&json).as_deref(), Some("id items status ok"), "numbers and hex ids carry no text");
+        assert_eq!(text(r#"{"note": "zq vex mor"}"#).as_deref(), Some("note zq vex mor"));
+        assert_eq!(name("{zq vex} {mor}", None), None, "braces alone are not JSON");
+        let py = "Traceback (most recent call last):\n  File \"app.py\", line 3, in <module>\n    main()\nValueError: bad input";
+        assert_eq!(name(py, None).as_deref(), Some("stack_trace"));
+        let js = "TypeError: x is undefined\n    at run (/srv/app.js:10:5)\n    at main (/srv/app.js:20:1)";
+        assert_eq!(name(js, None).as_deref(), Some("stack_trace"));
+        let jvm = "java.lang.IllegalStateException: closed\n\tat com.example.Pool.get(Pool.java:42)\n\tat java.base/java.lang.Thread.run(Thread.java:833)";
+        assert_eq!(name(jvm, None).as_deref(), Some("stack_trace"));
+        let rust = "thread 'main' panicked at src/main.rs:2:5:\nboom\nstack backtrace:\n   0: app::main\n             at ./src/main.rs:2:5";
+        assert_eq!(name(rust, None).as_deref(), Some("stack_trace"));
+        // Without frame lines, an error header is just a line of text
+        assert_eq!(name("ValueError: zq vex mor kel", None), None);
+        assert_eq!(name("MyError zq vex\n    mor kel", None), None);
+        assert_eq!(name("thread 'x' panicked at zq vex\nmor kel", None), None);
+
+        assert_eq!(
+            text("https://example.com/docs/get%20started?lang=en#intro https://example.org").as_deref(),
+            Some("docs get started lang en intro")
+        );
+        assert_eq!(text("https://example.com/zqvex/morkel?q=1").as_deref(), Some("zqvex morkel q"));
+
+        // Anchored: a match inside other content does not count
+        assert_eq!(name("zorp https://example.com vel", None), None);
+        // The hint restricts a pattern but never widens it
+        assert_eq!(name("build-123456", None), None);
+        assert_eq!(name("build-123456", Some("text/x-build-id; charset=utf-8")).as_deref(), Some("build_id"));
+        assert_eq!(name("zorp vel", Some("text/x-build-id")), None);
+
+        let counts: Vec<_> = allow.stats().into_iter().map(|s| (s.name, s.matches)).collect();
+        assert_eq!(counts[0], ("json".to_string(), 2));
+        assert_eq!(counts[1], ("stack_trace".to_string(), 4));
+        assert_eq!(counts[2], ("url".to_string(), 2));
+        assert_eq!(counts[3], ("build_id".to_string(), 1));
+
+        assert_eq!(ContentAllowlist::parse("url, json").names(), ["url", "json"]);
+        assert!(ContentAllowlist::parse("").names().is_empty());
+    }
+}
@@ -0,0 +1,238 @@
+//! Bulk annotation of audit events
+//!
+//! Incident reviews tag groups of audit events, such as everything an agent
+//! was refused during an outage (`incident-2024-07`) or a batch of
+//! violations later judged wrong (`false-positive batch`).
+//! `POST /audit/annotations` attaches a label and an optional note to every
+//! retained event matching an [`AuditFilter`]. Labelled events carry their
+//! labels in `GET /audit/export`, which can also be limited to one label with
+//! `?label=`.
+//!
+//! Events recorded after an annotation are not labelled by it; annotate again
+//! to cover them. Deleting an annotation removes its label from the events it
+//! labelled, unless another annotation with the same label also covers them.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, sync::RwLock};
+
+use crate::audit::AuditEvent;
+
+/// Longest label accepted, in characters
+pub const MAX_LABEL_LEN: usize = 64;
+
+/// Longest note accepted, in characters
+pub const MAX_NOTE_LEN: usize = 2_000;
+
+/// Which audit events an annotation covers; every condition set must hold
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditFilter {
+    /// First sequence number covered (inclusive)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_seq: Option<u64>,
+    /// Sequence number the range ends before (exclusive)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until_seq: Option<u64>,
+    /// Earliest event time, Unix seconds (inclusive)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<f64>,
+    /// Time the range ends before, Unix seconds (exclusive)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<f64>,
+    /// Event names such as `msg_rejected`; any event when empty
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<String>,
+    /// Agent the event is about, as its `from` or `agent_id` field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agent_id: Option<String>,
+    /// `reason` field, e.g. `protocol_not_registered`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<String>,
+    /// Only events that counted as a compliance violation
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub violations: bool,
+    /// Only events already carrying this label
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        let field = |name: &str| event.fields.get(name).and_then(Value::as_str);
+        self.from_seq.is_none_or(|from| event.seq >= from)
+            && self.until_seq.is_none_or(|until| event.seq < until)
+            && self.since.is_none_or(|since| event.ts >= since)
+            && self.until.is_none_or(|until| event.ts < until)
+            && (self.events.is_empty() || self.events.contains(&event.event))
+            && self
+                .agent_id
+                .as_deref()
+                .is_none_or(|agent| field("from") == Some(agent) || field("agent_id") == Some(agent))
+            && self.reason.as_deref().is_none_or(|reason| field("reason") == Some(reason))
+            && self.thread_id.as_deref().is_none_or(|thread| field("thread_id") == Some(thread))
+            && (!self.violations || event.fields.get("violation") == Some(&Value::Bool(true)))
+            && self.label.as_ref().is_none_or(|label| event.labels.contains(label))
+    }
+
+    /// Whether the filter selects anything narrower than the whole trail
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Body of `POST /audit/annotations`
+#[derive(Debug, Clone, Deserialize)]
+pub struct AnnotateRequest {
+    pub label: String,
+    #[serde(default)]
+    pub note: Option<String>,
+    pub filter: AuditFilter,
+}
+
+impl AnnotateRequest {
+    /// Trim the label and check the sizes; an empty filter would label the
+    /// whole trail and is refused
+    pub fn validate(&mut self) -> Result<(), String> {
+        self.label = self.label.trim().to_string();
+        if self.label.is_empty() || self.label.chars().count() > MAX_LABEL_LEN {
+            return Err(format!("label must be 1 to {MAX_LABEL_LEN} characters"));
+        }
+        if self.label.chars().any(char::is_control) {
+            return Err("label must not contain control characters".to_string());
+        }
+        if self.note.as_ref().is_some_and(|n| n.chars().count() > MAX_NOTE_LEN) {
+            return Err(format!("note must be at most {MAX_NOTE_LEN} characters"));
+        }
+        if self.filter.is_empty() {
+            return Err("filter must select something; set at least one condition".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A label attached to a set of audit events
+#[derive(Debug, Clone, Serialize)]
+pub struct Annotation {
+    pub id: u64,
+    pub label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    pub filter: AuditFilter,
+    /// Events labelled when the annotation was made
+    pub matched: usize,
+    pub created_by: String,
+    pub created_at: u64,
+    /// Sequence numbers of the labelled events
+    #[serde(skip)]
+    pub seqs: Vec<u64>,
+}
+
+/// Annotations made so far
+#[derive(Debug, Default)]
+pub struct Annotations {
+    entries: RwLock<BTreeMap<u64, Annotation>>,
+}
+
+impl Annotations {
+    /// Record an annotation, assigning its id
+    pub fn add(&self, mut annotation: Annotation) -> Annotation {
+        let mut entries = self.entries.write().unwrap();
+        annotation.id = entries.keys().next_back().map_or(1, |last| last + 1);
+        entries.insert(annotation.id, annotation.clone());
+        annotation
+    }
+
+    /// Annotations, oldest first, optionally only those with `label`
+    pub fn list(&self, label: Option<&str>) -> Vec<Annotation> {
+        self.entries
+            .read()
+            .unwrap()
+            .values()
+            .filter(|a| label.is_none_or(|l| a.label == l))
+            .cloned()
+            .collect()
+    }
+
+    /// Remove an annotation, returning it with the events whose label no
+    /// other annotation keeps
+    pub fn remove(&self, id: u64) -> Option<(Annotation, Vec<u64>)> {
+        let mut entries = self.entries.write().unwrap();
+        let removed = entries.remove(&id)?;
+        let mut orphaned = removed.seqs.clone();
+        for other in entries.values().filter(|a| a.label == removed.label) {
+            orphaned.retain(|seq| other.seqs.binary_search(seq).is_err());
+        }
+        Some((removed, orphaned))
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditLog;
+    use serde_json::{json, Map};
+
+    fn fields(value: Value) -> Map<String, Value> {
+        value.as_object().cloned().unwrap()
+    }
+
+    #[test]
+    fn test_annotate_filter_and_remove() {
+        let log = AuditLog::new(100);
+        log.append("WARN", "msg_rejected", fields(json!({"from": "a", "reason": "encrypted_content", "violation": true})));
+        log.append("WARN", "msg_rejected", fields(json!({"from": "a", "reason": "report_overdue"})));
+        log.append("WARN", "report_rejected", fields(json!({"agent_id": "b", "violation": true})));
+        let annotations = Annotations::default();
+
+        let violations = AuditFilter {
+            violations: true,
+            ..AuditFilter::default()
+        };
+        let seqs = log.annotate(&violations, "incident-7");
+        assert_eq!(seqs, [1, 3]);
+        let first = annotations.add(Annotation {
+            id: 0,
+            label: "incident-7".into(),
+            note: None,
+            filter: violations,
+            matched: seqs.len(),
+            created_by: "admin".into(),
+            created_at: 0,
+            seqs,
+        });
+
+        let of_a = AuditFilter {
+            agent_id: Some("a".into()),
+            label: Some("incident-7".into()),
+            ..AuditFilter::default()
+        };
+        let seqs = log.annotate(&of_a, "incident-7");
+        assert_eq!(seqs, [1], "an event is labelled once");
+        annotations.add(Annotation { id: 0, seqs, ..first.clone() });
+
+        let (_, orphaned) = annotations.remove(first.id).unwrap();
+        assert_eq!(orphaned, [3], "event 1 keeps the label of the second annotation");
+        log.unlabel(&orphaned, "incident-7");
+        let labelled: Vec<_> = log
+            .read_page(0, u64::MAX, 10)
+            .into_iter()
+            .filter(|e| !e.labels.is_empty())
+            .map(|e| e.seq)
+            .collect();
+        assert_eq!(labelled, [1]);
+
+        let mut empty = AnnotateRequest {
+            label: " x ".into(),
+            note: None,
+            filter: AuditFilter::default(),
+        };
+        assert!(empty.validate().is_err());
+    }
+}
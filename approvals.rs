@@ -0,0 +1,377 @@
+//! Dual control for destructive admin actions
+//!
+//! Admins are named: `ADMIN_TOKEN` is `admin`, and `ADMIN_TOKENS`
+//! (`token:name,...`) adds more. Every admin action is logged with the admin
+//! who took it.
+//!
+//! Actions listed in `DUAL_CONTROL_ACTIONS` take two admins. Calling the
+//! endpoint only proposes the action: it is queued, answered with 202 and the
+//! proposal, and logged as `admin_action_proposed`. A different admin then
+//! approves it with `POST /admin/approvals/{id}/approve` within
+//! `DUAL_CONTROL_TTL_SEC`, which runs it and answers as the endpoint would
+//! have, or rejects it with `POST /admin/approvals/{id}/reject`. The proposer
+//! may withdraw a proposal the same way but never approve it. Proposals past
+//! their TTL are dropped and logged as `admin_action_expired`.
+//!
+//! Agents revising a guarded field of a protocol descriptor (see
+//! [`crate::revision`]) propose the revision here too, as `agent:<id>`; one
+//! admin's approval registers it.
+//!
+//! The queue lives in memory: a restart or failover drops pending proposals.
+
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fmt,
+    sync::Mutex,
+};
+use tracing::warn;
+
+use crate::{env_parse, ids::AgentId, policy::Policy, revision::FieldChange, ProtocolDescriptor};
+
+/// Seconds a proposal waits for approval unless `DUAL_CONTROL_TTL_SEC` is set
+pub const DEFAULT_TTL_SEC: u64 = 3_600;
+
+/// Admin actions that can be placed under dual control
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    DeleteAgent,
+    DiscardQuarantined,
+    ReleaseQuarantined,
+    ReinstateProtocol,
+    ExpandProtocolScope,
+    LoadPolicy,
+    RotateKeys,
+    RepairState,
+    /// An agent's revision of a guarded descriptor field; never under dual
+    /// control, always awaiting an admin
+    ChangeProtocolDescriptor,
+}
+
+impl ActionKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().replace('-', "_").as_str() {
+            "delete_agent" => Some(Self::DeleteAgent),
+            "discard_quarantined" => Some(Self::DiscardQuarantined),
+            "release_quarantined" => Some(Self::ReleaseQuarantined),
+            "reinstate_protocol" => Some(Self::ReinstateProtocol),
+            "expand_protocol_scope" => Some(Self::ExpandProtocolScope),
+            "load_policy" => Some(Self::LoadPolicy),
+            "rotate_keys" => Some(Self::RotateKeys),
+            "repair_state" => Some(Self::RepairState),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ActionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::DeleteAgent => "delete_agent",
+            Self::DiscardQuarantined => "discard_quarantined",
+            Self::ReleaseQuarantined => "release_quarantined",
+            Self::ReinstateProtocol => "reinstate_protocol",
+            Self::ExpandProtocolScope => "expand_protocol_scope",
+            Self::LoadPolicy => "load_policy",
+            Self::RotateKeys => "rotate_keys",
+            Self::RepairState => "repair_state",
+            Self::ChangeProtocolDescriptor => "change_protocol_descriptor",
+        })
+    }
+}
+
+/// An admin action with its arguments
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AdminAction {
+    DeleteAgent { agent_id: String },
+    DiscardQuarantined { id: u64 },
+    ReleaseQuarantined { id: u64 },
+    ReinstateProtocol { agent_id: String, name: String, version: String },
+    /// Add recipients to a protocol's allowlist
+    ExpandProtocolScope { agent_id: String, protocol: String, add: Box<[String]> },
+    LoadPolicy { policy: Box<Policy> },
+    RotateKeys,
+    /// Apply the repairs `POST /admin/fsck` finds when the action runs
+    RepairState,
+    /// Register an agent's revised descriptor
+    ChangeProtocolDescriptor {
+        agent_id: AgentId,
+        descriptor: Box<ProtocolDescriptor>,
+        changes: Box<[FieldChange]>,
+    },
+}
+
+impl AdminAction {
+    pub fn kind(&self) -> ActionKind {
+        match self {
+            Self::DeleteAgent { .. } => ActionKind::DeleteAgent,
+            Self::DiscardQuarantined { .. } => ActionKind::DiscardQuarantined,
+            Self::ReleaseQuarantined { .. } => ActionKind::ReleaseQuarantined,
+            Self::ReinstateProtocol { .. } => ActionKind::ReinstateProtocol,
+            Self::ExpandProtocolScope { .. } => ActionKind::ExpandProtocolScope,
+            Self::LoadPolicy { .. } => ActionKind::LoadPolicy,
+            Self::RotateKeys => ActionKind::RotateKeys,
+            Self::RepairState => ActionKind::RepairState,
+            Self::ChangeProtocolDescriptor { .. } => ActionKind::ChangeProtocolDescriptor,
+        }
+    }
+
+    /// What the action applies to, for the audit log
+    pub fn target(&self) -> String {
+        match self {
+            Self::DeleteAgent { agent_id } => agent_id.clone(),
+            Self::DiscardQuarantined { id } | Self::ReleaseQuarantined { id } => id.to_string(),
+            Self::ReinstateProtocol { agent_id, name, version } => format!("{agent_id}/{name}:{version}"),
+            Self::ExpandProtocolScope { agent_id, protocol, .. } => format!("{agent_id}/{protocol}"),
+            Self::ChangeProtocolDescriptor { agent_id, descriptor, .. } => {
+                format!("{agent_id}/{}:{}", descriptor.name, descriptor.version)
+            }
+            Self::LoadPolicy { policy } => format!("{:016x}", policy.version_id()),
+            Self::RotateKeys | Self::RepairState => String::new(),
+        }
+    }
+}
+
+/// An action awaiting a second admin
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Proposal {
+    pub id: u64,
+    #[serde(flatten)]
+    pub action: AdminAction,
+    pub proposed_by: String,
+    pub proposed_at: u64,
+    pub expires_at: u64,
+}
+
+/// Why a proposal could not be approved
+#[derive(Debug, Clone, PartialEq)]
+pub enum Refusal {
+    Unknown,
+    Expired(Proposal),
+    SelfApproval,
+}
+
+/// Body of `GET /admin/approvals`
+#[derive(Debug, Clone, Serialize)]
+pub struct ApprovalQueue {
+    /// Actions that take two admins
+    pub actions: Vec<ActionKind>,
+    pub ttl_sec: u64,
+    pub pending: Vec<Proposal>,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    next_id: u64,
+    pending: BTreeMap<u64, Proposal>,
+}
+
+impl Queue {
+    /// Drop proposals past their TTL, returning them
+    fn expire(&mut self, now: u64) -> Vec<Proposal> {
+        let expired: Vec<u64> = self
+            .pending
+            .values()
+            .filter(|p| p.expires_at <= now)
+            .map(|p| p.id)
+            .collect();
+        expired.iter().filter_map(|id| self.pending.remove(id)).collect()
+    }
+}
+
+/// Dual-control settings and the queue of pending proposals
+#[derive(Debug, Default)]
+pub struct Approvals {
+    actions: BTreeSet<ActionKind>,
+    ttl_sec: u64,
+    queue: Mutex<Queue>,
+}
+
+impl Approvals {
+    pub fn new(actions: impl IntoIterator<Item = ActionKind>, ttl_sec: u64) -> Self {
+        Self {
+            actions: actions.into_iter().collect(),
+            ttl_sec,
+            queue: Mutex::default(),
+        }
+    }
+
+    /// Read `DUAL_CONTROL_ACTIONS` (comma-separated) and `DUAL_CONTROL_TTL_SEC`
+    pub fn from_env() -> Self {
+        let mut actions = Vec::new();
+        for name in env::var("DUAL_CONTROL_ACTIONS").unwrap_or_default().split(',') {
+            if name.trim().is_empty() {
+                continue;
+            }
+            match ActionKind::parse(name) {
+                Some(kind) => actions.push(kind),
+                None => warn!(action = %name.trim(), event = "config_invalid", "Ignoring unknown dual-control action"),
+            }
+        }
+        let ttl_sec = env_parse("DUAL_CONTROL_TTL_SEC")
+            .filter(|&s| s > 0)
+            .unwrap_or(DEFAULT_TTL_SEC);
+        Self::new(actions, ttl_sec)
+    }
+
+    pub fn actions(&self) -> impl Iterator<Item = ActionKind> + '_ {
+        self.actions.iter().copied()
+    }
+
+    /// Whether `kind` takes two admins
+    pub fn requires(&self, kind: ActionKind) -> bool {
+        self.actions.contains(&kind)
+    }
+
+    /// Queue `action` for approval; `None` when the same action is already pending
+    pub fn propose(&self, action: AdminAction, admin: &str, now: u64) -> Option<Proposal> {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.pending.values().any(|p| p.action == action && p.expires_at > now) {
+            return None;
+        }
+        queue.next_id += 1;
+        let proposal = Proposal {
+            id: queue.next_id,
+            action,
+            proposed_by: admin.to_string(),
+            proposed_at: now,
+            expires_at: now + self.ttl_sec,
+        };
+        queue.pending.insert(proposal.id, proposal.clone());
+        Some(proposal)
+    }
+
+    /// Take proposal `id` for `admin` to approve
+    pub fn approve(&self, id: u64, admin: &str, now: u64) -> Result<Proposal, Refusal> {
+        let mut queue = self.queue.lock().unwrap();
+        let proposal = queue.pending.get(&id).ok_or(Refusal::Unknown)?;
+        if proposal.expires_at <= now {
+            return Err(queue.pending.remove(&id).map_or(Refusal::Unknown, Refusal::Expired));
+        }
+        if proposal.proposed_by == admin {
+            return Err(Refusal::SelfApproval);
+        }
+        queue.pending.remove(&id).ok_or(Refusal::Unknown)
+    }
+
+    /// Remove proposal `id` without running it
+    pub fn reject(&self, id: u64) -> Option<Proposal> {
+        self.queue.lock().unwrap().pending.remove(&id)
+    }
+
+    /// Drop proposals past their TTL, returning them
+    pub fn expire(&self, now: u64) -> Vec<Proposal> {
+        self.queue.lock().unwrap().expire(now)
+    }
+
+    pub fn queue(&self) -> ApprovalQueue {
+        ApprovalQueue {
+            actions: self.actions().collect(),
+            ttl_sec: self.ttl_sec,
+            pending: self.queue.lock().unwrap().pending.values().cloned().collect(),
+        }
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{AgentFixture, ProtocolFixture, TestGateway};
+    use axum::http::{Method, StatusCode};
+    use std::time::Duration;
+
+    fn delete(agent_id: &str) -> AdminAction {
+        AdminAction::DeleteAgent { agent_id: agent_id.to_string() }
+    }
+
+    #[test]
+    fn test_two_admins_within_ttl() {
+        let approvals = Approvals::new([ActionKind::DeleteAgent], 600);
+        assert!(approvals.requires(ActionKind::DeleteAgent));
+        assert!(!approvals.requires(ActionKind::RotateKeys));
+
+        let proposal = approvals.propose(delete("a"), "alice", 1_000).unwrap();
+        assert_eq!((proposal.id, proposal.expires_at), (1, 1_600));
+        // The same action is not queued twice
+        assert!(approvals.propose(delete("a"), "bob", 1_001).is_none());
+
+        assert_eq!(approvals.approve(1, "alice", 1_100), Err(Refusal::SelfApproval));
+        assert_eq!(approvals.approve(1, "bob", 1_100).unwrap().action, delete("a"));
+        assert_eq!(approvals.approve(1, "bob", 1_100), Err(Refusal::Unknown));
+        assert!(approvals.queue().pending.is_empty());
+    }
+
+    #[test]
+    fn test_expiry_and_rejection() {
+        let approvals = Approvals::new([ActionKind::DeleteAgent], 600);
+        approvals.propose(delete("a"), "alice", 1_000).unwrap();
+        approvals.propose(delete("b"), "alice", 1_300).unwrap();
+
+        let expired = approvals.approve(1, "bob", 1_600);
+        assert!(matches!(expired, Err(Refusal::Expired(p)) if p.id == 1));
+        assert_eq!(approvals.expire(1_900).len(), 1);
+
+        approvals.propose(delete("c"), "alice", 2_000).unwrap();
+        assert_eq!(approvals.reject(3).unwrap().proposed_by, "alice");
+        assert!(approvals.reject(3).is_none());
+    }
+
+    #[test]
+    fn test_proposal_shape() {
+        let approvals = Approvals::new([ActionKind::ReinstateProtocol], 600);
+        let action = AdminAction::ReinstateProtocol {
+            agent_id: "a".into(),
+            name: "coord".into(),
+            version: "1.0".into(),
+        };
+        assert_eq!(action.target(), "a/coord:1.0");
+        let proposal = approvals.propose(action, "alice", 1_000).unwrap();
+        let json = serde_json::to_value(&proposal).unwrap();
+        assert_eq!(json["action"], "reinstate_protocol");
+        assert_eq!(json["name"], "coord");
+        assert_eq!(json["proposed_by"], "alice");
+
+        assert_eq!(ActionKind::parse("Delete-Agent"), Some(ActionKind::DeleteAgent));
+        assert_eq!(ActionKind::parse("drop_tables"), None);
+    }
+
+    #[tokio::test]
+    async fn test_delete_needs_second_admin() {
+        let gw = TestGateway::with_dual_control(&[("bob-token", "bob")], [ActionKind::DeleteAgent], 600);
+        let protocol = ProtocolFixture::new("coord", "1.0").build();
+        for agent in ["a", "b"] {
+            gw.setup_agent(AgentFixture::new(agent).protocol(protocol.clone())).await;
+        }
+
+        let proposed = gw.admin(Method::DELETE, "/agents/a", None::<&()>).await;
+        assert_eq!(proposed.status, StatusCode::ACCEPTED);
+        assert_eq!(proposed.body["proposed_by"], "admin");
+        let again = gw.admin(Method::DELETE, "/agents/a", None::<&()>).await;
+        assert_eq!(again.status, StatusCode::CONFLICT);
+
+        let own = gw.admin(Method::POST, "/admin/approvals/1/approve", None::<&()>).await;
+        assert_eq!(own.body["code"], "self_approval");
+        let approved = gw.call(Method::POST, "/admin/approvals/1/approve", None::<&()>, Some("bob-token")).await;
+        assert_eq!(approved.status, StatusCode::OK);
+        assert!(gw.state().inner.read().unwrap().is_deleted("a"));
+
+        // Proposals lapse after the TTL
+        gw.call(Method::DELETE, "/agents/b", None::<&()>, Some("bob-token")).await;
+        gw.advance(Duration::from_secs(600));
+        let late = gw.admin(Method::POST, "/admin/approvals/2/approve", None::<&()>).await;
+        assert_eq!(late.status, StatusCode::GONE);
+        assert!(!gw.state().inner.read().unwrap().is_deleted("b"));
+
+        // Actions outside dual control run at once
+        let rotated = gw.admin(Method::POST, "/admin/keys/rotate", None::<&()>).await;
+        assert_eq!(rotated.status, StatusCode::OK);
+        let queue = gw.admin(Method::GET, "/admin/approvals", None::<&()>).await;
+        assert_eq!(queue.body["pending"], serde_json::json!([]));
+    }
+}
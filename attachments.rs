@@ -0,0 +1,295 @@
+//! Binary attachments on sends
+//!
+//! An agent uploads each attachment before the send that carries it:
+//! `POST /attachments/{agent_id}` takes the raw bytes (any `Content-Type`) and
+//! answers an `attachment_id` with the bytes' SHA-256. The send then lists the
+//! ids in its `attachments` field. The gateway keeps only the digest, size,
+//! and media type, never the bytes: the sender delivers the bytes itself, and
+//! recipients check them against the digests signed into the send's receipt.
+//!
+//! Attachments have their own policy, whatever the language of the message
+//! text:
+//!
+//! - they are novel content: the send must declare a protocol registered for
+//!   the sender (403 `attachment_requires_protocol` or
+//!   `protocol_not_registered`)
+//! - an upload is at most `ATTACHMENT_MAX_BYTES` (413 `attachment_too_large`)
+//!   and a send carries at most `ATTACHMENT_MAX_PER_MESSAGE`
+//! - an attachment is sent once, by the agent that uploaded it, within
+//!   `ATTACHMENT_TTL_SEC` of the upload
+//!
+//! Uploads are audited as `attachment_uploaded`. Each attachment of an
+//! accepted send gets its own `attachment_sent` record, linked to the parent
+//! message by its `message_id` (when delivery tracking or the sender gives
+//! one) and by the SHA-256 of its content. A refused send leaves its
+//! attachments staged for the retry. Attachments are staged on the gateway
+//! they were uploaded to, so a peer gateway refuses them on forwarded sends.
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, HeaderMap},
+    Json,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, sync::Mutex};
+use tracing::info;
+
+use crate::{
+    env_parse,
+    error::GatewayError,
+    ids, protocol_key,
+    signing::{content_digest, hex},
+    AppState, SendMessageRequest,
+};
+
+/// Default largest attachment, in bytes
+const DEFAULT_MAX_BYTES: usize = 1024 * 1024;
+
+/// Default most attachments on one send
+const DEFAULT_MAX_PER_MESSAGE: usize = 4;
+
+/// Default seconds an upload waits for its send
+const DEFAULT_TTL_SEC: u64 = 3_600;
+
+/// Uploads one agent may have waiting at once
+const MAX_STAGED_PER_AGENT: usize = 32;
+
+/// Media type of uploads sent without one
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+#[derive(Debug, Clone)]
+pub struct AttachmentConfig {
+    /// Largest upload; 0 disables attachments
+    pub max_bytes: usize,
+    pub max_per_message: usize,
+    pub ttl_sec: u64,
+}
+
+impl Default for AttachmentConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_per_message: DEFAULT_MAX_PER_MESSAGE,
+            ttl_sec: DEFAULT_TTL_SEC,
+        }
+    }
+}
+
+impl AttachmentConfig {
+    /// Load `ATTACHMENT_MAX_BYTES`, `ATTACHMENT_MAX_PER_MESSAGE`, and
+    /// `ATTACHMENT_TTL_SEC`
+    pub fn from_env() -> Self {
+        let d = Self::default();
+        let var = |key: &str| env_parse::<u64>(key);
+        Self {
+            max_bytes: var("ATTACHMENT_MAX_BYTES").map_or(d.max_bytes, |n| n as usize),
+            max_per_message: var("ATTACHMENT_MAX_PER_MESSAGE")
+                .filter(|&n| n > 0)
+                .map_or(d.max_per_message, |n| n as usize),
+            ttl_sec: var("ATTACHMENT_TTL_SEC").filter(|&n| n > 0).unwrap_or(d.ttl_sec),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+}
+
+/// An uploaded attachment awaiting its send
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Attachment {
+    pub attachment_id: String,
+    #[serde(skip)]
+    pub agent_id: String,
+    /// SHA-256 of the bytes, hex-encoded
+    pub sha256: String,
+    pub bytes: usize,
+    pub content_type: String,
+    /// When the upload lapses unsent
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct Staging {
+    uploads: BTreeMap<String, Attachment>,
+    next_id: u64,
+}
+
+/// Uploads awaiting their sends
+#[derive(Debug, Default)]
+pub struct Attachments {
+    config: AttachmentConfig,
+    staging: Mutex<Staging>,
+}
+
+impl Attachments {
+    pub fn new(config: AttachmentConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Stage an upload of `agent_id`
+    fn stage(&self, agent_id: &str, body: &[u8], content_type: &str, now: u64) -> Result<Attachment, GatewayError> {
+        if !self.config.enabled() {
+            return Err(GatewayError::Invalid("Attachments are disabled".to_string()));
+        }
+        if body.is_empty() {
+            return Err(GatewayError::Invalid("Attachment is empty".to_string()));
+        }
+        if body.len() > self.config.max_bytes {
+            return Err(GatewayError::AttachmentTooLarge { limit: self.config.max_bytes });
+        }
+        let mut staging = self.staging.lock().unwrap();
+        staging.uploads.retain(|_, a| a.expires_at > now);
+        if staging.uploads.values().filter(|a| a.agent_id == agent_id).count() >= MAX_STAGED_PER_AGENT {
+            return Err(GatewayError::Conflict("Too many attachments awaiting a send"));
+        }
+        staging.next_id += 1;
+        let attachment = Attachment {
+            attachment_id: format!("att-{}", staging.next_id),
+            agent_id: agent_id.to_string(),
+            sha256: hex(&Sha256::digest(body)),
+            bytes: body.len(),
+            content_type: content_type.to_string(),
+            expires_at: now + self.config.ttl_sec,
+        };
+        staging.uploads.insert(attachment.attachment_id.clone(), attachment.clone());
+        Ok(attachment)
+    }
+
+    /// The uploads a send lists, once its sender may send them
+    pub fn claim(&self, state: &AppState, req: &SendMessageRequest) -> Result<Vec<Attachment>, GatewayError> {
+        if req.attachments.is_empty() {
+            return Ok(Vec::new());
+        }
+        if !self.config.enabled() {
+            return Err(GatewayError::Invalid("Attachments are disabled".to_string()));
+        }
+        if req.attachments.len() > self.config.max_per_message {
+            return Err(GatewayError::Invalid(format!(
+                "A send carries at most {} attachments",
+                self.config.max_per_message
+            )));
+        }
+        let Some(pref) = &req.protocol else {
+            return Err(GatewayError::AttachmentRequiresProtocol);
+        };
+        let key = protocol_key(&pref.name, &pref.version);
+        let registered = {
+            let st = state.inner.read().unwrap();
+            st.protocols.get(req.from.as_str()).is_some_and(|m| m.contains_key(&key))
+        };
+        if !registered {
+            return Err(GatewayError::NotRegistered);
+        }
+        let now = state.clock.now();
+        let staging = self.staging.lock().unwrap();
+        req.attachments
+            .iter()
+            .map(|id| match staging.uploads.get(id) {
+                Some(a) if a.agent_id == req.from.as_str() && a.expires_at > now => Ok(a.clone()),
+                _ => Err(GatewayError::Invalid(format!("Unknown or expired attachment {id}"))),
+            })
+            .collect()
+    }
+
+    /// Record the attachments of an accepted send, and unstage them
+    pub fn release(&self, req: &SendMessageRequest, attached: &[Attachment], message_id: Option<&str>) {
+        let parent_sha256 = content_digest(&req.content);
+        let protocol = req.protocol.as_ref().map(|p| protocol_key(&p.name, &p.version));
+        let mut staging = self.staging.lock().unwrap();
+        for attachment in attached {
+            staging.uploads.remove(&attachment.attachment_id);
+            info!(
+                from = %req.from,
+                protocol = protocol.as_deref(),
+                attachment_id = %attachment.attachment_id,
+                sha256 = %attachment.sha256,
+                bytes = attachment.bytes,
+                content_type = %attachment.content_type,
+                message_id,
+                parent_sha256 = %parent_sha256,
+                thread_id = req.thread_id.as_deref(),
+                event = "attachment_sent",
+                "Attachment sent with an accepted message"
+            );
+        }
+    }
+}
+
+/// Upload an attachment for a later send
+pub async fn upload(
+    State(state): State<AppState>,
+    Path(agent_id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Attachment>, GatewayError> {
+    ids::validate("Agent id", &agent_id, ids::MAX_AGENT_ID_LEN).map_err(GatewayError::Invalid)?;
+    if state.inner.read().unwrap().is_deleted(&agent_id) {
+        return Err(GatewayError::AgentDeleted { action: "to send attachments" });
+    }
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or(DEFAULT_CONTENT_TYPE);
+    let attachment = state.attachments.stage(&agent_id, &body, content_type, state.clock.now())?;
+    info!(
+        agent_id = %agent_id,
+        attachment_id = %attachment.attachment_id,
+        sha256 = %attachment.sha256,
+        bytes = attachment.bytes,
+        content_type = %attachment.content_type,
+        event = "attachment_uploaded",
+        "Attachment uploaded"
+    );
+    Ok(Json(attachment))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{AgentFixture, ProtocolFixture, SendFixture, TestGateway};
+    use axum::{body::Body, http::Request, http::StatusCode};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    async fn upload(gw: &TestGateway, agent_id: &str, bytes: &'static [u8]) -> Value {
+        let req = Request::post(format!("/attachments/{agent_id}"))
+            .header("content-type", "application/x-state-diff")
+            .body(Body::from(bytes))
+            .unwrap();
+        let resp = gw.router().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_attachments_require_registered_protocol() {
+        let gw = TestGateway::new();
+        let coord = ProtocolFixture::new("coord", "1.0").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(coord.clone()).reported()).await;
+        let ticket = upload(&gw, "a", b"\x00\x01weights-diff").await;
+        assert_eq!(ticket["bytes"], 14);
+        assert_eq!(ticket["content_type"], "application/x-state-diff");
+        let id = ticket["attachment_id"].as_str().unwrap();
+
+        // English text does not make its attachments English
+        let refused = gw.send(&SendFixture::english("a", "b").attach(id).build()).await;
+        assert_eq!(refused.status, StatusCode::FORBIDDEN);
+        assert_eq!(refused.body["code"], "attachment_requires_protocol");
+
+        let novel = SendFixture::novel("a", "b", &coord, "Here is the diff.").attach(id).build();
+        let sent = gw.send(&novel).await;
+        assert_eq!(sent.status, StatusCode::OK, "{:?}", sent.body);
+        let resent = gw.send(&novel).await;
+        assert_eq!(resent.status, StatusCode::BAD_REQUEST, "an attachment is sent once");
+    }
+}
@@ -0,0 +1,692 @@
+//! Audit trail
+//!
+//! Every structured log point carrying an `event` field (`msg_accepted`,
+//! `report_rejected`, ...) is captured by [`AuditLayer`] into the in-memory
+//! [`AuditLog`], independent of the `RUST_LOG` filter applied to console
+//! output. Each event gets a monotonically increasing sequence number that
+//! doubles as the export cursor.
+//!
+//! Events recording an agent's own requests ([`AGENT_EVENTS`] attributed to
+//! it by a `from` or `agent_id` field) count against its storage quota once a
+//! [`QuotaTracker`] is attached; over quota they are stored with their fields
+//! replaced by a digest, or not stored at all. Events the gateway or an admin
+//! raises about an agent, such as suspensions, deletions and anything logged
+//! under an admin action, are neither counted nor metered and are always
+//! stored in full, as are all events of an agent under an investigation hold
+//! (see [`holds`](crate::holds)).
+//!
+//! Historical events imported through `POST /admin/backfill` keep their
+//! original timestamp but get the next sequence number, so exports stay in
+//! ingest order (see [`backfill`](crate::backfill)).
+//!
+//! Events logged inside a span carrying a `thread_id` field get that field too
+//! and are indexed by thread for `GET /threads/{id}`. Likewise a `flags` span
+//! field, the feature flags active for the agent a decision is about, is
+//! copied onto every event under it, and so are the `admin` and
+//! `approved_by` fields naming the admins behind an admin action, a
+//! `trial` field marking decisions about a protocol on trial, a
+//! `retry_of` field linking a retried send to the attempt it retries, and
+//! the `trace_id` of a traced request (see [`exemplars`](crate::exemplars)).
+//!
+//! Events of the core governance kinds follow versioned schemas, and carry
+//! the `schema_version` of their kind (see
+//! [`audit_schema`](crate::audit_schema)). Each live event of a registered
+//! kind is checked against its schema as it is appended; one that does not
+//! conform is stored all the same and counted by kind in
+//! `audit_schema_violations_total`.
+//!
+//! Live events are also fed to the usage [`Meter`] once one is attached (see
+//! [`metering`](crate::metering)).
+//!
+//! Events can be labelled in bulk after the fact (see
+//! [`annotations`](crate::annotations)); labels are exported with them.
+//!
+//! With a state store configured, every event is also written to it (see
+//! [`store`](crate::store)), and sequence numbers continue after the last
+//! stored event across restarts.
+//!
+//! `GET /audit/export` streams events as NDJSON. Pages are read from the log
+//! only as the client consumes the body, so a slow reader never forces the
+//! whole export into memory; an interrupted export resumes from
+//! `?cursor=<last seq + 1>`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    sync::{Arc, Mutex, OnceLock, RwLock},
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::{
+    annotations::AuditFilter,
+    audit_schema,
+    env_parse,
+    holds::Holds,
+    metering::Meter,
+    quota::{EventAdmission, QuotaTracker, Resource},
+    signing::content_digest,
+    store::{Persistence, Record},
+};
+
+/// Default maximum number of retained events
+const DEFAULT_MAX_EVENTS: usize = 1_000_000;
+
+/// A single audit record
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub seq: u64,
+    /// Unix timestamp with sub-second precision
+    pub ts: f64,
+    pub level: String,
+    pub event: String,
+    /// Version of the event kind's schema; 0 for unregistered kinds (see
+    /// [`audit_schema`](crate::audit_schema))
+    #[serde(default)]
+    pub schema_version: u32,
+    /// Policy version in force when the event was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy_version: Option<String>,
+    pub fields: Map<String, Value>,
+    /// Labels attached by annotations, sorted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    events: VecDeque<AuditEvent>,
+    next_seq: u64,
+    /// thread_id -> sequence numbers of retained events, ascending
+    threads: HashMap<String, VecDeque<u64>>,
+}
+
+/// Events recording an agent's own requests, which count against its storage
+/// quota and are metered
+pub const AGENT_EVENTS: &[&str] = &[
+    "msg_accepted",
+    "msg_rejected",
+    "msg_quarantined",
+    "msg_delivered",
+    "msg_glossed",
+    "msg_forwarded",
+    "msg_forward_received",
+    "send_parked",
+    "send_reserved",
+    "park_refused",
+    "retry_offered",
+    "retry_redeemed",
+    "attachment_uploaded",
+    "attachment_sent",
+    "content_allowlisted",
+    "content_allowlist_text_refused",
+    "language_ensemble",
+    "decision_webhook_allowed",
+    "decision_webhook_failed",
+    "decision_webhook_skipped",
+    "report_accepted",
+    "report_rejected",
+    "report_batch_filed",
+    "report_redacted",
+    "report_sample_checked",
+    "report_consistency_scored",
+    "report_honesty_scored",
+    "report_clock_inherited",
+    "protocol_registered",
+    "registration_rejected",
+    "descriptor_change_proposed",
+    "stream_opened",
+    "stream_closed",
+];
+
+/// Whether an event records an agent's own traffic, rather than governance
+/// acting on the agent
+pub fn is_agent_traffic(event: &str, fields: &Map<String, Value>) -> bool {
+    AGENT_EVENTS.contains(&event) && !fields.contains_key("admin")
+}
+
+/// Append-only, bounded in-memory audit store
+#[derive(Debug)]
+pub struct AuditLog {
+    inner: RwLock<Inner>,
+    policy_version: RwLock<Option<String>>,
+    quota: RwLock<Option<Arc<QuotaTracker>>>,
+    store: OnceLock<Arc<Persistence>>,
+    meter: OnceLock<Arc<Meter>>,
+    holds: OnceLock<Arc<Holds>>,
+    /// Live events that did not conform to their kind's schema, by kind
+    schema_violations: Mutex<BTreeMap<&'static str, u64>>,
+    max_events: usize,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_EVENTS)
+    }
+}
+
+impl AuditLog {
+    pub fn new(max_events: usize) -> Self {
+        Self {
+            inner: RwLock::new(Inner {
+                events: VecDeque::new(),
+                next_seq: 1,
+                threads: HashMap::new(),
+            }),
+            policy_version: RwLock::new(None),
+            quota: RwLock::new(None),
+            store: OnceLock::new(),
+            meter: OnceLock::new(),
+            holds: OnceLock::new(),
+            schema_violations: Mutex::default(),
+            max_events: max_events.max(1),
+        }
+    }
+
+    /// Size from `AUDIT_MAX_EVENTS`
+    pub fn from_env() -> Self {
+        let max = env_parse("AUDIT_MAX_EVENTS").unwrap_or(DEFAULT_MAX_EVENTS);
+        Self::new(max)
+    }
+
+    /// Stamp subsequent events with `version`
+    pub fn set_policy_version(&self, version: &str) {
+        *self.policy_version.write().unwrap() = Some(version.to_string());
+    }
+
+    /// Count agent-attributed events against `quota`
+    pub fn set_quota(&self, quota: Arc<QuotaTracker>) {
+        *self.quota.write().unwrap() = Some(quota);
+    }
+
+    /// Write subsequent events to the state store too
+    pub fn set_store(&self, store: Arc<Persistence>) {
+        let _ = self.store.set(store);
+    }
+
+    /// Meter subsequent live events
+    pub fn set_meter(&self, meter: Arc<Meter>) {
+        let _ = self.meter.set(meter);
+    }
+
+    /// Store events of agents under an investigation hold in full, whatever
+    /// their quota
+    pub fn set_holds(&self, holds: Arc<Holds>) {
+        let _ = self.holds.set(holds);
+    }
+
+    /// Continue sequence numbers after `seq`, the last event stored before a
+    /// restart
+    pub fn resume_after(&self, seq: u64) {
+        let mut inner = self.inner.write().unwrap();
+        inner.next_seq = inner.next_seq.max(seq + 1);
+    }
+
+    /// Append an event, evicting the oldest once full; returns its sequence
+    /// number, or `None` when the agent's quota suppressed it
+    pub fn append(&self, level: &str, event: &str, mut fields: Map<String, Value>) -> Option<u64> {
+        let agent = ["from", "agent_id"]
+            .iter()
+            .find_map(|k| fields.get(*k).and_then(Value::as_str))
+            .filter(|_| is_agent_traffic(event, &fields))
+            .map(str::to_string);
+        let quota = self.quota.read().unwrap().clone();
+        let held = agent
+            .as_deref()
+            .zip(self.holds.get())
+            .is_some_and(|(agent, holds)| holds.active(agent));
+        if let (Some(quota), Some(agent)) = (quota, agent) {
+            // Events under an investigation hold are stored in full, yet still count
+            let admission = if held {
+                quota.record(&agent, Resource::Events, 1);
+                EventAdmission::Store
+            } else {
+                quota.admit_event(&agent)
+            };
+            match admission {
+                EventAdmission::Store => {}
+                EventAdmission::Digest => {
+                    let digest = content_digest(&Value::Object(fields).to_string());
+                    fields = Map::from_iter([
+                        ("agent".to_string(), Value::from(agent)),
+                        ("fields_sha256".to_string(), Value::from(digest)),
+                    ]);
+                }
+                EventAdmission::Suppress => return None,
+            }
+        }
+        if let Some(schema) = audit_schema::schema_of(event) {
+            if !schema.validate(&fields).is_empty() {
+                *self.schema_violations.lock().unwrap().entry(schema.event).or_insert(0) += 1;
+            }
+        }
+        let ts = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        // The version the request was decided under; events raised outside a
+        // request carry the latest loaded
+        let policy_version = crate::policy::pinned_version()
+            .or_else(|| self.policy_version.read().unwrap().clone());
+        Some(self.insert(level, event, ts, policy_version, fields, true))
+    }
+
+    /// Live events that did not conform to their kind's schema, by kind
+    pub fn schema_violations(&self) -> BTreeMap<&'static str, u64> {
+        self.schema_violations.lock().unwrap().clone()
+    }
+
+    /// Append a historical event with its original timestamp
+    ///
+    /// Backfilled history predates the policy in force, so it carries no
+    /// policy version, and is not counted against storage quotas or metered,
+    /// which measure live traffic.
+    pub fn backfill(&self, event: &str, ts: f64, fields: Map<String, Value>) -> u64 {
+        self.insert("INFO", event, ts, None, fields, false)
+    }
+
+    fn insert(
+        &self,
+        level: &str,
+        event: &str,
+        ts: f64,
+        policy_version: Option<String>,
+        fields: Map<String, Value>,
+        live: bool,
+    ) -> u64 {
+        let mut inner = self.inner.write().unwrap();
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+        if inner.events.len() == self.max_events {
+            if let Some(evicted) = inner.events.pop_front() {
+                if let Some(thread) = evicted.fields.get("thread_id").and_then(Value::as_str) {
+                    let thread = thread.to_string();
+                    if let Some(seqs) = inner.threads.get_mut(&thread) {
+                        seqs.pop_front();
+                        if seqs.is_empty() {
+                            inner.threads.remove(&thread);
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(thread) = fields.get("thread_id").and_then(Value::as_str) {
+            inner.threads.entry(thread.to_string()).or_default().push_back(seq);
+        }
+        let event = AuditEvent {
+            seq,
+            ts,
+            level: level.to_string(),
+            event: event.to_string(),
+            schema_version: audit_schema::version_of(event),
+            policy_version,
+            fields,
+            labels: Vec::new(),
+        };
+        if let Some(store) = self.store.get() {
+            store.write(Record::Audit(event.clone()));
+        }
+        if let Some(meter) = self.meter.get().filter(|_| live) {
+            meter.observe(&event);
+        }
+        inner.events.push_back(event);
+        seq
+    }
+
+    /// Attach `label` to every retained event matching `filter`; returns
+    /// their sequence numbers, ascending
+    pub fn annotate(&self, filter: &AuditFilter, label: &str) -> Vec<u64> {
+        let mut inner = self.inner.write().unwrap();
+        let start = filter.from_seq.map_or(0, |from| inner.events.partition_point(|e| e.seq < from));
+        let mut seqs = Vec::new();
+        for event in inner.events.range_mut(start..) {
+            if filter.until_seq.is_some_and(|until| event.seq >= until) {
+                break;
+            }
+            if filter.matches(event) {
+                if let Err(at) = event.labels.binary_search_by(|l| l.as_str().cmp(label)) {
+                    event.labels.insert(at, label.to_string());
+                }
+                seqs.push(event.seq);
+            }
+        }
+        seqs
+    }
+
+    /// Remove `label` from the retained events among `seqs`
+    pub fn unlabel(&self, seqs: &[u64], label: &str) {
+        let mut inner = self.inner.write().unwrap();
+        for seq in seqs {
+            let Ok(idx) = inner.events.binary_search_by_key(seq, |e| e.seq) else {
+                continue;
+            };
+            if let Some(event) = inner.events.get_mut(idx) {
+                event.labels.retain(|l| l != label);
+            }
+        }
+    }
+
+    /// Sequence number the next appended event will receive
+    pub fn next_seq(&self) -> u64 {
+        self.inner.read().unwrap().next_seq
+    }
+
+    /// Oldest retained sequence number, if any
+    pub fn first_seq(&self) -> Option<u64> {
+        self.inner.read().unwrap().events.front().map(|e| e.seq)
+    }
+
+    /// Retained events recorded for `thread_id`, oldest first
+    pub fn thread_events(&self, thread_id: &str) -> Vec<AuditEvent> {
+        let inner = self.inner.read().unwrap();
+        let Some(seqs) = inner.threads.get(thread_id) else {
+            return Vec::new();
+        };
+        seqs.iter()
+            .filter_map(|seq| {
+                let idx = inner.events.binary_search_by_key(seq, |e| e.seq).ok()?;
+                inner.events.get(idx).cloned()
+            })
+            .collect()
+    }
+
+    /// Up to `limit` events with `from <= seq < until`
+    pub fn read_page(&self, from: u64, until: u64, limit: usize) -> Vec<AuditEvent> {
+        let inner = self.inner.read().unwrap();
+        let start = inner.events.partition_point(|e| e.seq < from);
+        inner
+            .events
+            .range(start..)
+            .take_while(|e| e.seq < until)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Visit up to `limit` events with `from <= seq < until` in place,
+    /// stopping early once `visit` returns false; returns the last sequence
+    /// number visited
+    pub fn visit(&self, from: u64, until: u64, limit: usize, mut visit: impl FnMut(&AuditEvent) -> bool) -> Option<u64> {
+        let inner = self.inner.read().unwrap();
+        let start = inner.events.partition_point(|e| e.seq < from);
+        let mut last = None;
+        for event in inner.events.range(start..).take_while(|e| e.seq < until).take(limit) {
+            last = Some(event.seq);
+            if !visit(event) {
+                break;
+            }
+        }
+        last
+    }
+}
+
+// =============================================================================
+// Tracing Layer
+// =============================================================================
+
+/// Tracing layer recording `event = "..."` log points into an [`AuditLog`]
+pub struct AuditLayer {
+    log: Arc<AuditLog>,
+}
+
+impl AuditLayer {
+    pub fn new(log: Arc<AuditLog>) -> Self {
+        Self { log }
+    }
+}
+
+#[derive(Default)]
+struct FieldVisitor {
+    fields: Map<String, Value>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.fields.insert(field.name().into(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.fields.insert(field.name().into(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.fields.insert(field.name().into(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.fields.insert(field.name().into(), Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields.insert(field.name().into(), Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.fields
+            .insert(field.name().into(), Value::from(format!("{value:?}")));
+    }
+}
+
+/// `thread_id` of a span, kept in its extensions
+struct ThreadId(String);
+
+/// Active feature flags of a span, kept in its extensions
+struct Flags(String);
+
+/// A span about a protocol on trial, kept in its extensions
+struct OnTrial;
+
+/// `retry_of` of a span, kept in its extensions
+struct RetryOf(String);
+
+/// A span serving a mirrored request, kept in its extensions
+struct Mirrored;
+
+/// `trace_id` of a span, kept in its extensions
+struct TraceId(String);
+
+/// Span fields naming the admins behind an admin action
+const ADMIN_FIELDS: [&str; 2] = ["admin", "approved_by"];
+
+/// [`ADMIN_FIELDS`] of a span, kept in its extensions
+struct Admins(Vec<(&'static str, Value)>);
+
+impl<S> Layer<S> for AuditLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        if let Some(Value::String(thread)) = visitor.fields.remove("thread_id") {
+            span.extensions_mut().insert(ThreadId(thread));
+        }
+        if let Some(Value::String(flags)) = visitor.fields.remove("flags") {
+            span.extensions_mut().insert(Flags(flags));
+        }
+        if let Some(Value::Bool(true)) = visitor.fields.remove("trial") {
+            span.extensions_mut().insert(OnTrial);
+        }
+        if let Some(Value::String(attempt)) = visitor.fields.remove("retry_of") {
+            span.extensions_mut().insert(RetryOf(attempt));
+        }
+        if let Some(Value::Bool(true)) = visitor.fields.remove("mirrored") {
+            span.extensions_mut().insert(Mirrored);
+        }
+        if let Some(Value::String(trace)) = visitor.fields.remove("trace_id") {
+            span.extensions_mut().insert(TraceId(trace));
+        }
+        let admins: Vec<_> = ADMIN_FIELDS
+            .into_iter()
+            .filter_map(|name| visitor.fields.remove(name).map(|v| (name, v)))
+            .collect();
+        if !admins.is_empty() {
+            span.extensions_mut().insert(Admins(admins));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let Some(Value::String(kind)) = visitor.fields.remove("event") else {
+            return;
+        };
+        if !visitor.fields.contains_key("thread_id") {
+            let thread = ctx.event_scope(event).into_iter().flatten().find_map(|span| {
+                span.extensions().get::<ThreadId>().map(|t| t.0.clone())
+            });
+            if let Some(thread) = thread {
+                visitor.fields.insert("thread_id".into(), Value::from(thread));
+            }
+        }
+        if !visitor.fields.contains_key("flags") {
+            let flags = ctx.event_scope(event).into_iter().flatten().find_map(|span| {
+                span.extensions().get::<Flags>().map(|f| f.0.clone())
+            });
+            if let Some(flags) = flags {
+                visitor.fields.insert("flags".into(), Value::from(flags));
+            }
+        }
+        if !visitor.fields.contains_key("trial")
+            && ctx.event_scope(event).into_iter().flatten().any(|span| span.extensions().get::<OnTrial>().is_some())
+        {
+            visitor.fields.insert("trial".into(), Value::from(true));
+        }
+        if !visitor.fields.contains_key("retry_of") {
+            let attempt = ctx.event_scope(event).into_iter().flatten().find_map(|span| {
+                span.extensions().get::<RetryOf>().map(|r| r.0.clone())
+            });
+            if let Some(attempt) = attempt {
+                visitor.fields.insert("retry_of".into(), Value::from(attempt));
+            }
+        }
+        if ctx.event_scope(event).into_iter().flatten().any(|span| span.extensions().get::<Mirrored>().is_some()) {
+            visitor.fields.insert("mirrored".into(), Value::from(true));
+        }
+        if !visitor.fields.contains_key("trace_id") {
+            let trace = ctx.event_scope(event).into_iter().flatten().find_map(|span| {
+                span.extensions().get::<TraceId>().map(|t| t.0.clone())
+            });
+            if let Some(trace) = trace {
+                visitor.fields.insert("trace_id".into(), Value::from(trace));
+            }
+        }
+        let admins = ctx.event_scope(event).into_iter().flatten().find_map(|span| {
+            span.extensions().get::<Admins>().map(|a| a.0.clone())
+        });
+        for (name, value) in admins.into_iter().flatten() {
+            visitor.fields.entry(name.to_string()).or_insert(value);
+        }
+        self.log
+            .append(event.metadata().level().as_str(), &kind, visitor.fields);
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn test_layer_captures_events_and_pages() {
+        let log = Arc::new(AuditLog::new(3));
+        let subscriber = tracing_subscriber::registry().with(AuditLayer::new(log.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("not an audit event");
+            for i in 0..4u64 {
+                let _thread = (i % 2 == 1).then(|| tracing::info_span!("thread", thread_id = "t1").entered());
+                let _flags = (i == 2).then(|| tracing::info_span!("flags", flags = %"strict_vocab").entered());
+                tracing::warn!(event = "msg_rejected", n = i, from = %"a", "Rejected");
+            }
+        });
+
+        // Capacity 3: the first event was evicted
+        assert_eq!(log.first_seq(), Some(2));
+        assert_eq!(log.next_seq(), 5);
+        let page = log.read_page(0, u64::MAX, 2);
+        assert_eq!(page.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(page[0].event, "msg_rejected");
+        assert_eq!(page[0].level, "WARN");
+        assert_eq!(page[0].fields["n"], Value::from(1u64));
+        assert_eq!(page[0].fields["from"], Value::from("a"));
+        assert_eq!(log.read_page(4, 5, 10).len(), 1);
+
+        // Events inside a thread span are tagged and indexed by thread
+        assert_eq!(page[0].fields["thread_id"], Value::from("t1"));
+        assert!(!page[1].fields.contains_key("thread_id"));
+        assert!(!page[0].fields.contains_key("flags"));
+        assert_eq!(page[1].fields["flags"], Value::from("strict_vocab"));
+        let thread = log.thread_events("t1");
+        assert_eq!(thread.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![2, 4]);
+        assert!(log.thread_events("t2").is_empty());
+    }
+
+    #[test]
+    fn test_admin_span_fields() {
+        let log = Arc::new(AuditLog::new(10));
+        let subscriber = tracing_subscriber::registry().with(AuditLayer::new(log.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let _admin = tracing::info_span!("admin", admin = "alice", approved_by = "bob").entered();
+            tracing::info!(event = "agent_deleted", agent_id = %"a", "Deleted");
+        });
+        let event = &log.read_page(0, u64::MAX, 1)[0];
+        assert_eq!(event.fields["admin"], Value::from("alice"));
+        assert_eq!(event.fields["approved_by"], Value::from("bob"));
+    }
+
+    #[test]
+    fn test_quota_counts_only_agent_traffic() {
+        use crate::{
+            clock::Clock,
+            quota::{Limits, QuotaConfig, QuotaLimits},
+        };
+        let log = AuditLog::new(10);
+        let config = QuotaConfig {
+            limits: QuotaLimits {
+                agent: Limits {
+                    events: Some(1),
+                    ..Limits::default()
+                },
+                ..QuotaLimits::default()
+            },
+            ..QuotaConfig::default()
+        };
+        log.set_quota(Arc::new(QuotaTracker::new(config, Arc::new(Clock::manual(1_000)))));
+        let fields = |pairs: &[(&str, &str)]| Map::from_iter(pairs.iter().map(|(k, v)| (k.to_string(), Value::from(*v))));
+
+        assert!(log.append("INFO", "msg_accepted", fields(&[("from", "a")])).is_some());
+        assert!(log.append("WARN", "msg_rejected", fields(&[("from", "a")])).is_none(), "over quota");
+        // Governance about the agent is stored in full, even its admin-driven sends
+        assert!(log.append("WARN", "protocol_suspended", fields(&[("agent_id", "a")])).is_some());
+        assert!(log.append("INFO", "agent_deleted", fields(&[("agent_id", "a")])).is_some());
+        let admin = fields(&[("from", "a"), ("admin", "alice")]);
+        assert!(log.append("WARN", "msg_rejected", admin.clone()).is_some());
+        assert_eq!(log.read_page(0, u64::MAX, 10).last().unwrap().fields, admin);
+    }
+
+    #[test]
+    fn test_nonconforming_events_are_counted() {
+        let log = AuditLog::new(10);
+        let fields = |pairs: &[(&str, Value)]| Map::from_iter(pairs.iter().map(|(k, v)| (k.to_string(), v.clone())));
+        let registered = fields(&[("agent_id", "a".into()), ("protocol", "coord:1.0".into())]);
+        log.append("INFO", "protocol_registered", registered);
+        log.append("INFO", "protocol_registered", fields(&[("agent_id", "a".into()), ("protocol", 7.into())]));
+        log.append("INFO", "protocol_registered", fields(&[("agent_id", "a".into())]));
+        log.append("INFO", "unregistered_kind", fields(&[]));
+        // Stored all the same
+        assert_eq!(log.next_seq(), 5);
+        assert_eq!(log.schema_violations(), BTreeMap::from([("protocol_registered", 2)]));
+    }
+}
@@ -0,0 +1,537 @@
+//! Typed schemas of audit events
+//!
+//! Consumers of `GET /audit/export` parse event fields, so the fields of the
+//! core governance events are a versioned contract. Each registered event
+//! kind has a schema listing its fields, with each field's JSON type, whether
+//! every event of the kind carries it, and the schema version that introduced
+//! it. Every stored event carries the `schema_version` of its kind, and live
+//! events are validated against their schema as they are appended (see
+//! [`audit`](crate::audit)). Kinds outside the registry carry 0, and their
+//! fields may change in any release.
+//!
+//! # Compatibility rules
+//!
+//! Within a registered kind:
+//! - fields are only added, never removed, renamed, or retyped
+//! - a field added after version 1 is optional, and bumps the kind's version
+//! - consumers ignore fields they do not know
+//!
+//! A change breaking these rules needs a new event kind. The tests check the
+//! rules against the registry, and check the events a gateway actually emits
+//! against their schemas.
+//!
+//! Span fields such as `thread_id`, `trial`, or `mirrored` (see
+//! [`audit`](crate::audit)) may appear on any event and belong to the
+//! envelope. An event digested by a storage quota carries only `agent` and
+//! `fields_sha256`, and is exempt from its kind's schema.
+//!
+//! `GET /audit/schema` serves the envelope and every registered kind as JSON
+//! Schema (draft 2020-12). The registry is compiled in, so the document is
+//! fixed for a given build, and the build script emits the same document as
+//! `audit-schema.json`. This module depends only on serde for that reason.
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+const JSON_SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// JSON type of an event field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+}
+
+impl FieldType {
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            Self::String => value.is_string(),
+            Self::Integer => value.is_i64() || value.is_u64(),
+            Self::Number => value.is_number(),
+            Self::Boolean => value.is_boolean(),
+        }
+    }
+}
+
+/// One field of an event kind
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FieldSchema {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub ty: FieldType,
+    /// Carried by every event of the kind
+    pub required: bool,
+    /// Schema version that introduced the field
+    pub since: u32,
+    pub description: &'static str,
+}
+
+const fn req(name: &'static str, ty: FieldType, description: &'static str) -> FieldSchema {
+    FieldSchema {
+        name,
+        ty,
+        required: true,
+        since: 1,
+        description,
+    }
+}
+
+const fn opt(name: &'static str, ty: FieldType, description: &'static str) -> FieldSchema {
+    FieldSchema {
+        name,
+        ty,
+        required: false,
+        since: 1,
+        description,
+    }
+}
+
+/// Schema of one registered event kind
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct EventSchema {
+    pub event: &'static str,
+    pub version: u32,
+    pub description: &'static str,
+    pub fields: &'static [FieldSchema],
+}
+
+use FieldType::{Boolean, Integer, Number, String as Str};
+
+/// Fields any event may carry, copied from the span it was logged in or
+/// left by a storage quota
+const ENVELOPE_FIELDS: &[FieldSchema] = &[
+    opt("thread_id", Str, "Conversation the event belongs to"),
+    opt("flags", Str, "Feature flags active for the agent the decision is about"),
+    opt("trial", Boolean, "Decision about a protocol on trial"),
+    opt("retry_of", Str, "Attempt a retried send retries"),
+    opt("admin", Str, "Admin behind an admin action"),
+    opt("approved_by", Str, "Second admin approving a dual-control action"),
+    opt("mirrored", Boolean, "Event of a request mirrored to this shadow gateway"),
+    opt("agent", Str, "Agent of an event digested by its storage quota"),
+    opt("fields_sha256", Str, "Digest of the fields of an event digested by its storage quota"),
+];
+
+/// Registered event kinds
+pub const EVENTS: &[EventSchema] = &[
+    EventSchema {
+        event: "msg_accepted",
+        version: 1,
+        description: "A send was accepted for one recipient",
+        fields: &[
+            req("from", Str, "Sending agent"),
+            req("to", Str, "Recipient"),
+            req("kind", Str, "`english`, `novel`, or `degraded`"),
+            opt("protocol", Str, "Protocol key (`name:version`) of a novel-language send"),
+            opt("bytes", Integer, "Content length of a novel-language send"),
+            opt("upgraded_from", Str, "Protocol key the send named, when it was upgraded to a compatible version"),
+            opt("message_id", Str, "Message id tracked for delivery confirmation"),
+            opt("detector", Str, "Source of the language verdict"),
+            opt("cached", Boolean, "Whether the sender's decision came from the decision cache"),
+            opt("degraded", Boolean, "Accepted undecided by a fail-open degradation rule"),
+            opt("content", Str, "Message content of a backfilled send, or of a send under an investigation hold"),
+            opt("backfilled", Boolean, "Imported through `POST /admin/backfill`"),
+            opt("source", Str, "Source system of a backfilled send"),
+        ],
+    },
+    EventSchema {
+        event: "msg_rejected",
+        version: 1,
+        description: "A send, or one of its recipients, was refused",
+        fields: &[
+            req("from", Str, "Sending agent"),
+            req("reason", Str, "Why the send was refused, e.g. `report_overdue` or `recipient_refused`"),
+            opt("to", Str, "Refused recipient"),
+            opt("protocol", Str, "Protocol key (`name:version`)"),
+            opt("detail", Str, "Receiver-side reason for a refused recipient"),
+            opt("error", Str, "Error behind the refusal"),
+            opt("errors", Str, "Message schema violations"),
+            opt("webhook_reason", Str, "Reason a decision webhook gave for denying"),
+            opt("violation", Boolean, "Recorded as a compliance violation"),
+            opt("repeats", Integer, "Sends on the unregistered protocol refused so far"),
+            opt("successor", Str, "Protocol key superseding the one named"),
+            opt("tier", Str, "Sender's reputation tier"),
+            opt("seconds_since_report", Integer, "Time since the sender's last accepted report"),
+            opt("encoding", Str, "Encoding detected in encrypted content"),
+            opt("policy", Str, "Encrypted-content policy applied"),
+            opt("quarantine_id", Integer, "Quarantine entry of a discarded message"),
+        ],
+    },
+    EventSchema {
+        event: "msg_quarantined",
+        version: 1,
+        description: "An encrypted message was held for review",
+        fields: &[
+            req("from", Str, "Sending agent"),
+            req("to", Str, "Recipients"),
+            req("reason", Str, "Always `encrypted_content`"),
+            req("encoding", Str, "Encoding detected in the content"),
+            req("quarantine_id", Integer, "Quarantine entry"),
+        ],
+    },
+    EventSchema {
+        event: "msg_delivered",
+        version: 1,
+        description: "A recipient confirmed delivery of a message",
+        fields: &[
+            req("from", Str, "Sending agent"),
+            req("to", Str, "Confirming recipient"),
+            req("message_id", Str, "Confirmed message"),
+            req("late", Boolean, "Confirmed after the delivery timeout"),
+            opt("protocol", Str, "Protocol key of a novel-language message"),
+        ],
+    },
+    EventSchema {
+        event: "report_accepted",
+        version: 1,
+        description: "An English report was accepted, resetting the report clock",
+        fields: &[
+            req("agent_id", Str, "Reporting agent"),
+            req("protocol", Str, "Protocol key (`name:version`)"),
+            opt("message_count", Integer, "Messages the report covers"),
+            opt("coverage", Number, "Share of messages the report covers, within [0, 1]"),
+            opt("degraded", Boolean, "Accepted undecided by a fail-open degradation rule"),
+            opt("window_start_ts", Number, "Start of a backfilled report's window"),
+            opt("window_end_ts", Number, "End of a backfilled report's window"),
+            opt("english_summary", Str, "Summary of a backfilled report"),
+            opt("backfilled", Boolean, "Imported through `POST /admin/backfill`"),
+            opt("source", Str, "Source system of a backfilled report"),
+        ],
+    },
+    EventSchema {
+        event: "report_rejected",
+        version: 1,
+        description: "An English report was refused",
+        fields: &[
+            req("agent_id", Str, "Reporting agent"),
+            req("protocol", Str, "Protocol key (`name:version`)"),
+            req("reason", Str, "Why the report was refused, e.g. `coverage_low`"),
+            opt("coverage", Number, "Coverage of a report below the minimum"),
+            opt("successor", Str, "Protocol key superseding the one reported on"),
+            opt("expected", Str, "Languages of record the summary should be in"),
+            opt("source", Str, "Source of the language verdict"),
+            opt("review_id", Integer, "Review that rejected a held report"),
+            opt("consistency", Number, "Consistency score of a rejected held report"),
+            opt("violation", Boolean, "Recorded as a compliance violation"),
+        ],
+    },
+    EventSchema {
+        event: "protocol_registered",
+        version: 1,
+        description: "A protocol was registered or revised for an agent",
+        fields: &[
+            req("agent_id", Str, "Registering agent"),
+            req("protocol", Str, "Protocol key (`name:version`)"),
+            opt("docs", Str, "Documentation artifacts attached"),
+        ],
+    },
+    EventSchema {
+        event: "protocol_descriptor_changed",
+        version: 2,
+        description: "A re-registration revised a protocol descriptor",
+        fields: &[
+            req("agent_id", Str, "Registering agent"),
+            req("protocol", Str, "Protocol key (`name:version`)"),
+            req("fields", Str, "Comma-separated paths of the revised fields"),
+            req("changes", Str, "JSON list of `{field, before, after}`"),
+            req("approved", Boolean, "Registered by an admin's approval of the revision"),
+            FieldSchema {
+                since: 2,
+                ..opt("peer", Str, "Registry sync peer the revision was imported from")
+            },
+        ],
+    },
+    EventSchema {
+        event: "descriptor_change_proposed",
+        version: 1,
+        description: "A revision of guarded descriptor fields awaits an admin",
+        fields: &[
+            req("agent_id", Str, "Registering agent"),
+            req("protocol", Str, "Protocol key (`name:version`)"),
+            req("approval_id", Integer, "Proposal to approve or reject"),
+            req("fields", Str, "Comma-separated guarded fields revised"),
+            req("changes", Str, "JSON list of `{field, before, after}`"),
+            req("expires_at", Integer, "When the proposal lapses unapproved"),
+        ],
+    },
+    EventSchema {
+        event: "registration_rejected",
+        version: 1,
+        description: "A protocol registration was refused",
+        fields: &[
+            req("agent_id", Str, "Registering agent"),
+            req("protocol", Str, "Protocol key (`name:version`)"),
+            req("reason", Str, "Why the registration was refused, e.g. `protocol_name_taken`"),
+            opt("error", Str, "Validation error"),
+            opt("owner", Str, "Owner of a protocol name taken in the global namespace"),
+            opt("tier", Str, "Agent's reputation tier"),
+        ],
+    },
+    EventSchema {
+        event: "protocol_adopted",
+        version: 1,
+        description: "An agent registered another agent's descriptor",
+        fields: &[
+            req("agent_id", Str, "Adopting agent"),
+            req("protocol", Str, "Protocol key (`name:version`)"),
+            req("owner", Str, "Agent whose descriptor was adopted"),
+        ],
+    },
+    EventSchema {
+        event: "protocol_suspended",
+        version: 1,
+        description: "A protocol was suspended after repeated held reports",
+        fields: &[
+            req("agent_id", Str, "Agent whose protocol was suspended"),
+            req("protocol", Str, "Protocol key (`name:version`)"),
+            req("strikes", Integer, "Held reports counted against the protocol"),
+        ],
+    },
+    EventSchema {
+        event: "protocol_reinstated",
+        version: 1,
+        description: "A protocol suspension was lifted",
+        fields: &[
+            req("agent_id", Str, "Agent whose protocol was reinstated"),
+            req("protocol", Str, "Protocol key (`name:version`)"),
+            req("via", Str, "What lifted the suspension"),
+            req("strikes", Integer, "Strikes cleared"),
+        ],
+    },
+    EventSchema {
+        event: "agent_deleted",
+        version: 1,
+        description: "An agent was soft-deleted",
+        fields: &[
+            req("agent_id", Str, "Deleted agent"),
+            opt("retention_sec", Integer, "How long the agent can be restored before it is purged"),
+        ],
+    },
+    EventSchema {
+        event: "agent_restored",
+        version: 1,
+        description: "A soft-deleted agent was restored",
+        fields: &[
+            req("agent_id", Str, "Restored agent"),
+            opt("deleted_at", Integer, "When the agent was deleted, Unix seconds"),
+            opt("source", Str, "Registry the agent returned to"),
+        ],
+    },
+    EventSchema {
+        event: "agent_purged",
+        version: 1,
+        description: "A soft-deleted agent was removed for good",
+        fields: &[req("agent_id", Str, "Purged agent")],
+    },
+    EventSchema {
+        event: "agent_owner_set",
+        version: 1,
+        description: "An agent's owning team was set",
+        fields: &[
+            req("agent_id", Str, "Agent"),
+            req("team", Str, "Owning team"),
+            opt("previous_team", Str, "Team that owned the agent before"),
+            opt("source", Str, "Registry the ownership came from"),
+        ],
+    },
+    EventSchema {
+        event: "governance_alert",
+        version: 1,
+        description: "A critical governance event was alerted on",
+        fields: &[
+            req("kind", Str, "Alert kind, e.g. `report_fraud_detected`"),
+            req("detail", Str, "What happened"),
+            opt("agent_id", Str, "Agent the alert is about"),
+            opt("team", Str, "Agent's owning team"),
+            opt("org", Str, "Team's organisation"),
+        ],
+    },
+    EventSchema {
+        event: "policy_loaded",
+        version: 1,
+        description: "Policy thresholds were put in force",
+        fields: &[
+            req("policy_version", Str, "Version put in force"),
+            req("policy", Str, "Thresholds as JSON text"),
+            opt("previous_version", Str, "Version replaced"),
+        ],
+    },
+    EventSchema {
+        event: "enforcement_mode_changed",
+        version: 1,
+        description: "A scheduled enforcement mode took effect",
+        fields: &[
+            req("schedule", Str, "Schedule entry that took effect"),
+            req("from", Str, "Mode before"),
+            req("to", Str, "Mode after"),
+            req("policy_version", Str, "Policy version in force"),
+        ],
+    },
+];
+
+/// Schema of `event`, when it is a registered kind
+pub fn schema_of(event: &str) -> Option<&'static EventSchema> {
+    EVENTS.iter().find(|s| s.event == event)
+}
+
+/// `schema_version` stamped on `event`; 0 for kinds outside the registry
+pub fn version_of(event: &str) -> u32 {
+    schema_of(event).map_or(0, |s| s.version)
+}
+
+fn field_properties(fields: &[FieldSchema]) -> Map<String, Value> {
+    fields
+        .iter()
+        .map(|f| {
+            let property = json!({"type": f.ty, "description": f.description, "x-since": f.since});
+            (f.name.to_string(), property)
+        })
+        .collect()
+}
+
+impl EventSchema {
+    /// JSON Schema of a whole event of this kind
+    pub fn json_schema(&self) -> Value {
+        let required: Vec<&str> = self.fields.iter().filter(|f| f.required).map(|f| f.name).collect();
+        json!({
+            "$schema": JSON_SCHEMA_DIALECT,
+            "title": self.event,
+            "description": self.description,
+            "type": "object",
+            "required": ["event", "schema_version", "fields"],
+            "properties": {
+                "event": {"const": self.event},
+                "schema_version": {"const": self.version},
+                "fields": {
+                    "type": "object",
+                    "required": required,
+                    "properties": field_properties(self.fields),
+                    "additionalProperties": true,
+                },
+            },
+        })
+    }
+
+    /// Problems with `fields` under this schema; digested events pass
+    pub fn validate(&self, fields: &Map<String, Value>) -> Vec<String> {
+        if fields.contains_key("fields_sha256") {
+            return Vec::new();
+        }
+        let mut problems = Vec::new();
+        for field in self.fields.iter().chain(ENVELOPE_FIELDS) {
+            match fields.get(field.name) {
+                Some(value) if !field.ty.matches(value) => {
+                    problems.push(format!("{}.{}: expected {:?}, got {value}", self.event, field.name, field.ty));
+                }
+                None if field.required => problems.push(format!("{}.{}: missing", self.event, field.name)),
+                _ => {}
+            }
+        }
+        problems
+    }
+}
+
+/// JSON Schema of the envelope every audit event shares
+pub fn envelope() -> Value {
+    json!({
+        "$schema": JSON_SCHEMA_DIALECT,
+        "title": "audit event",
+        "type": "object",
+        "required": ["seq", "ts", "level", "event", "schema_version", "fields"],
+        "properties": {
+            "seq": {"type": "integer", "description": "Sequence number, the export cursor"},
+            "ts": {"type": "number", "description": "Unix timestamp with sub-second precision"},
+            "level": {"type": "string"},
+            "event": {"type": "string", "description": "Event kind"},
+            "schema_version": {"type": "integer", "description": "Version of the kind's schema; 0 when unregistered"},
+            "policy_version": {"type": "string", "description": "Policy version in force when recorded"},
+            "fields": {
+                "type": "object",
+                "properties": field_properties(ENVELOPE_FIELDS),
+                "additionalProperties": true,
+            },
+            "labels": {"type": "array", "items": {"type": "string"}, "description": "Annotation labels"},
+        },
+    })
+}
+
+/// Body of `GET /audit/schema`
+pub fn document() -> Value {
+    let events: Map<String, Value> = EVENTS
+        .iter()
+        .map(|s| (s.event.to_string(), s.json_schema()))
+        .collect();
+    json!({"envelope": envelope(), "events": events})
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::{AuditLayer, AuditLog};
+    use crate::testing::{AgentFixture, ProtocolFixture, ReportFixture, SendFixture, TestGateway};
+    use std::{collections::HashSet, sync::Arc};
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn test_registry_follows_compatibility_rules() {
+        let mut kinds = HashSet::new();
+        for schema in EVENTS {
+            assert!(kinds.insert(schema.event), "{} registered twice", schema.event);
+            assert!(schema.version >= 1, "{} has no version", schema.event);
+            let mut names = HashSet::new();
+            for field in schema.fields {
+                assert!(names.insert(field.name), "{}.{} listed twice", schema.event, field.name);
+                assert!(field.since >= 1 && field.since <= schema.version, "{}.{} is from the future", schema.event, field.name);
+                assert!(field.since == 1 || !field.required, "{}.{} was added as required", schema.event, field.name);
+                assert!(!ENVELOPE_FIELDS.iter().any(|f| f.name == field.name), "{}.{} shadows an envelope field", schema.event, field.name);
+            }
+        }
+        assert_eq!(version_of("msg_accepted"), 1);
+        assert_eq!(version_of("slow_request"), 0);
+    }
+
+    #[test]
+    fn test_build_emits_the_served_document() {
+        let built: Value = serde_json::from_str(include_str!(concat!(env!("OUT_DIR"), "/audit-schema.json"))).unwrap();
+        assert_eq!(built, document());
+    }
+
+    #[tokio::test]
+    async fn test_emitted_events_match_their_schemas() {
+        let log = Arc::new(AuditLog::new(10_000));
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry().with(AuditLayer::new(log.clone())));
+
+        let gw = TestGateway::new();
+        let protocol = ProtocolFixture::new("coord", "1.0").build();
+        gw.setup_agent(AgentFixture::new("a").team("ops").protocol(protocol.clone()).reported()).await;
+        gw.send(&SendFixture::novel("a", "b", &protocol, "SHP|eta=7f").build()).await;
+        gw.send(&SendFixture::english("a", "b").build()).await;
+        gw.report(&ReportFixture::new("a", &protocol).coverage(0.1).build()).await;
+        let unregistered = ProtocolFixture::new("other", "1.0").build();
+        gw.send(&SendFixture::novel("a", "b", &unregistered, "XQ|1").build()).await;
+
+        let events = log.read_page(0, u64::MAX, 10_000);
+        let mut checked = HashSet::new();
+        for event in &events {
+            if let Some(schema) = schema_of(&event.event) {
+                assert_eq!(event.schema_version, schema.version);
+                assert_eq!(schema.validate(&event.fields), Vec::<String>::new());
+                checked.insert(event.event.as_str());
+            } else {
+                assert_eq!(event.schema_version, 0);
+            }
+        }
+        for kind in ["protocol_registered", "agent_owner_set", "report_accepted", "report_rejected", "msg_accepted", "msg_rejected"] {
+            assert!(checked.contains(kind), "no {kind} event emitted");
+        }
+        let document = document();
+        assert_eq!(document["events"]["msg_accepted"]["properties"]["schema_version"]["const"], 1);
+        assert_eq!(document["events"]["report_accepted"]["properties"]["fields"]["properties"]["coverage"]["type"], "number");
+    }
+}
@@ -0,0 +1,768 @@
+//! Query language for `POST /audit/search`
+//!
+//! Investigations need more than the filters of `GET /audit/export`, e.g.
+//! refused reports of one agent under high-risk protocols with low coverage:
+//!
+//! ```text
+//! event = "report_rejected" and agent = "agent-007"
+//!   and protocol.risk_tier = "high" and coverage < 0.9
+//! ```
+//!
+//! A query compares fields with `=`, `!=`, `<`, `<=`, `>`, `>=`, or
+//! `contains` (substring, or array member), tests membership with
+//! `in [...]`, and presence with `has(field)`, combined with `and`, `or`,
+//! `not`, and parentheses. Literals are JSON strings, numbers, `true`,
+//! `false`, and `null`. Fields are:
+//!
+//! - `seq`, `ts`, `level`, `event`, `policy_version`, `labels` of the event
+//! - `agent`: its `from` or `agent_id` field
+//! - `protocol.name`, `protocol.version`, `protocol.risk_tier` (effective),
+//!   `protocol.registered_risk_tier`: the event's agent protocol as currently
+//!   registered
+//! - any other name, or `fields.<name>`: a field of the event
+//!
+//! A comparison with a missing field is false, except `!=`, which is true.
+//!
+//! Queries are compiled before they run. Top-level `seq` bounds become the
+//! range of the log scanned, and top-level `event` conditions a set of event
+//! names checked before anything else; the response's `plan` shows both. A
+//! query is refused with 400 when it is longer than [`MAX_QUERY_LEN`], has
+//! more than [`MAX_NODES`] conditions and operators, nests deeper than
+//! [`MAX_DEPTH`], or lists more than [`MAX_LIST_LEN`] values. A search stops
+//! at `limit` matches (at most `AUDIT_SEARCH_MAX_RESULTS`), after
+//! `AUDIT_SEARCH_MAX_SCAN` events, or after `AUDIT_SEARCH_TIMEOUT_MS`,
+//! whichever comes first; a search stopped early answers the matches so far
+//! with a `next_cursor` to resume from and the reason it `stopped`.
+
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+use crate::{
+    audit::{AuditEvent, AuditLog},
+    codec::Payload,
+    env_parse,
+    error::GatewayError,
+    identity::AuthedAuditor,
+    AppState,
+};
+
+/// Longest query accepted, in bytes
+pub const MAX_QUERY_LEN: usize = 4_096;
+
+/// Most conditions and operators in one query
+pub const MAX_NODES: usize = 64;
+
+/// Deepest nesting of operators and parentheses
+pub const MAX_DEPTH: usize = 16;
+
+/// Most values in one `in` list
+pub const MAX_LIST_LEN: usize = 256;
+
+/// Events visited per read lock of the log
+const SCAN_PAGE: usize = 4_096;
+
+/// Descriptor fields `protocol.<name>` can read
+const PROTOCOL_FIELDS: &[&str] = &["name", "version", "risk_tier", "registered_risk_tier"];
+
+#[derive(Debug, Clone)]
+pub struct SearchConfig {
+    pub max_results: usize,
+    pub max_scan: usize,
+    pub timeout: Duration,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            max_results: 1_000,
+            max_scan: 1_000_000,
+            timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+impl SearchConfig {
+    /// Load `AUDIT_SEARCH_MAX_RESULTS`, `AUDIT_SEARCH_MAX_SCAN`, and
+    /// `AUDIT_SEARCH_TIMEOUT_MS`
+    pub fn from_env() -> Self {
+        let d = Self::default();
+        let var = |key: &str| env_parse::<u64>(key).filter(|&n| n > 0);
+        Self {
+            max_results: var("AUDIT_SEARCH_MAX_RESULTS").map_or(d.max_results, |n| n as usize),
+            max_scan: var("AUDIT_SEARCH_MAX_SCAN").map_or(d.max_scan, |n| n as usize),
+            timeout: var("AUDIT_SEARCH_TIMEOUT_MS").map_or(d.timeout, Duration::from_millis),
+        }
+    }
+}
+
+// =============================================================================
+// Parsing
+// =============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(Value),
+    Op(Op),
+    Open,
+    Close,
+    OpenList,
+    CloseList,
+    Comma,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Field {
+    Seq,
+    Ts,
+    Level,
+    Event,
+    PolicyVersion,
+    Labels,
+    Agent,
+    Protocol(String),
+    Fields(String),
+}
+
+impl Field {
+    fn parse(path: &str) -> Result<Self, String> {
+        Ok(match path {
+            "seq" => Self::Seq,
+            "ts" => Self::Ts,
+            "level" => Self::Level,
+            "event" => Self::Event,
+            "policy_version" => Self::PolicyVersion,
+            "labels" => Self::Labels,
+            "agent" => Self::Agent,
+            _ => match path.split_once('.') {
+                Some(("protocol", field)) if PROTOCOL_FIELDS.contains(&field) => Self::Protocol(field.to_string()),
+                Some(("protocol", field)) => {
+                    return Err(format!(
+                        "unknown protocol field {field:?}; expected one of {}",
+                        PROTOCOL_FIELDS.join(", ")
+                    ))
+                }
+                Some(("fields", field)) => Self::Fields(field.to_string()),
+                _ => Self::Fields(path.to_string()),
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Field, Op, Value),
+    In(Field, Vec<Value>),
+    Has(Field),
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = query.char_indices().peekable();
+    while let Some(&(at, c)) = chars.peek() {
+        let token = match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '(' | ')' | '[' | ']' | ',' => {
+                chars.next();
+                match c {
+                    '(' => Token::Open,
+                    ')' => Token::Close,
+                    '[' => Token::OpenList,
+                    ']' => Token::CloseList,
+                    _ => Token::Comma,
+                }
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let eq = chars.next_if(|&(_, n)| n == '=').is_some();
+                Token::Op(match (c, eq) {
+                    ('=', _) => Op::Eq,
+                    ('!', true) => Op::Ne,
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Le,
+                    ('>', false) => Op::Gt,
+                    ('>', true) => Op::Ge,
+                    _ => return Err(format!("unexpected '!' at {at}; use != or not")),
+                })
+            }
+            '"' => {
+                // Reuse JSON string syntax, escapes included
+                let rest = &query[at..];
+                let mut stream = serde_json::Deserializer::from_str(rest).into_iter::<String>();
+                let value = stream.next().ok_or("unterminated string")?.map_err(|e| format!("bad string at {at}: {e}"))?;
+                let end = at + stream.byte_offset();
+                while chars.next_if(|&(i, _)| i < end).is_some() {}
+                Token::Literal(Value::String(value))
+            }
+            _ if c == '-' || c.is_ascii_digit() => {
+                let mut end = at;
+                while let Some((i, n)) = chars.next_if(|&(_, n)| n.is_ascii_alphanumeric() || "-+.".contains(n)) {
+                    end = i + n.len_utf8();
+                }
+                let number: serde_json::Number =
+                    query[at..end].parse().map_err(|_| format!("bad number {:?} at {at}", &query[at..end]))?;
+                Token::Literal(Value::Number(number))
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let mut end = at;
+                while let Some((i, n)) = chars.next_if(|&(_, n)| n.is_alphanumeric() || n == '_' || n == '.') {
+                    end = i + n.len_utf8();
+                }
+                match &query[at..end] {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    "contains" => Token::Op(Op::Contains),
+                    word => Token::Ident(word.to_string()),
+                }
+            }
+            _ => return Err(format!("unexpected {c:?} at {at}")),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+    nodes: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.at).cloned();
+        self.at += 1;
+        token
+    }
+
+    fn keyword(&mut self, word: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Ident(w)) if w.eq_ignore_ascii_case(word));
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, token: Token, what: &str) -> Result<(), String> {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            other => Err(format!("expected {what}, found {}", describe(other.as_ref()))),
+        }
+    }
+
+    fn node(&mut self, depth: usize) -> Result<(), String> {
+        self.nodes += 1;
+        if self.nodes > MAX_NODES {
+            return Err(format!("query has more than {MAX_NODES} conditions and operators"));
+        }
+        if depth > MAX_DEPTH {
+            return Err(format!("query nests deeper than {MAX_DEPTH}"));
+        }
+        Ok(())
+    }
+
+    fn or(&mut self, depth: usize) -> Result<Expr, String> {
+        let mut left = self.and(depth)?;
+        while self.keyword("or") {
+            self.node(depth)?;
+            left = Expr::Or(Box::new(left), Box::new(self.and(depth + 1)?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self, depth: usize) -> Result<Expr, String> {
+        let mut left = self.unary(depth)?;
+        while self.keyword("and") {
+            self.node(depth)?;
+            left = Expr::And(Box::new(left), Box::new(self.unary(depth + 1)?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self, depth: usize) -> Result<Expr, String> {
+        if self.keyword("not") {
+            self.node(depth)?;
+            return Ok(Expr::Not(Box::new(self.unary(depth + 1)?)));
+        }
+        if self.peek() == Some(&Token::Open) {
+            self.at += 1;
+            self.node(depth + 1)?;
+            let inner = self.or(depth + 1)?;
+            self.expect(Token::Close, "')'")?;
+            return Ok(inner);
+        }
+        self.node(depth)?;
+        self.condition()
+    }
+
+    fn condition(&mut self) -> Result<Expr, String> {
+        let path = match self.next() {
+            Some(Token::Ident(path)) => path,
+            other => return Err(format!("expected a field, found {}", describe(other.as_ref()))),
+        };
+        if path.eq_ignore_ascii_case("has") {
+            self.expect(Token::Open, "'(' after has")?;
+            let field = match self.next() {
+                Some(Token::Ident(path)) => Field::parse(&path)?,
+                other => return Err(format!("expected a field, found {}", describe(other.as_ref()))),
+            };
+            self.expect(Token::Close, "')'")?;
+            return Ok(Expr::Has(field));
+        }
+        let field = Field::parse(&path)?;
+        if self.keyword("in") {
+            self.expect(Token::OpenList, "'[' after in")?;
+            let mut values = Vec::new();
+            loop {
+                match self.next() {
+                    Some(Token::Literal(value)) => values.push(value),
+                    other => return Err(format!("expected a value, found {}", describe(other.as_ref()))),
+                }
+                if values.len() > MAX_LIST_LEN {
+                    return Err(format!("an in list holds at most {MAX_LIST_LEN} values"));
+                }
+                match self.next() {
+                    Some(Token::Comma) => continue,
+                    Some(Token::CloseList) => break,
+                    other => return Err(format!("expected ',' or ']', found {}", describe(other.as_ref()))),
+                }
+            }
+            return Ok(Expr::In(field, values));
+        }
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            other => return Err(format!("expected a comparison after {path}, found {}", describe(other.as_ref()))),
+        };
+        match self.next() {
+            Some(Token::Literal(value)) => Ok(Expr::Compare(field, op, value)),
+            other => Err(format!("expected a value after {path}, found {}", describe(other.as_ref()))),
+        }
+    }
+}
+
+fn describe(token: Option<&Token>) -> String {
+    match token {
+        None => "the end of the query".to_string(),
+        Some(Token::Ident(word)) => format!("{word:?}"),
+        Some(Token::Literal(value)) => value.to_string(),
+        Some(token) => format!("{token:?}"),
+    }
+}
+
+// =============================================================================
+// Compilation
+// =============================================================================
+
+/// How a query reads the log
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Plan {
+    /// First sequence number scanned
+    pub from_seq: u64,
+    /// Sequence number the scan ends before
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until_seq: Option<u64>,
+    /// Event names checked before the rest of the query
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub events: Option<BTreeSet<String>>,
+    /// Whether current protocol registrations are consulted
+    pub protocol_lookup: bool,
+}
+
+/// A compiled query
+#[derive(Debug, Clone)]
+pub struct Query {
+    expr: Expr,
+    plan: Plan,
+}
+
+impl Query {
+    pub fn compile(query: &str) -> Result<Self, String> {
+        if query.len() > MAX_QUERY_LEN {
+            return Err(format!("query longer than {MAX_QUERY_LEN} bytes"));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(query)?,
+            at: 0,
+            nodes: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Err("empty query".to_string());
+        }
+        let expr = parser.or(0)?;
+        if let Some(extra) = parser.peek() {
+            return Err(format!("unexpected {} after a complete query", describe(Some(extra))));
+        }
+        let mut plan = Plan::default();
+        let mut conjuncts = Vec::new();
+        flatten_and(&expr, &mut conjuncts);
+        for conjunct in conjuncts {
+            plan.narrow(conjunct);
+        }
+        plan.protocol_lookup = reads_protocols(&expr);
+        Ok(Self { expr, plan })
+    }
+}
+
+fn flatten_and<'a>(expr: &'a Expr, out: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::And(left, right) => {
+            flatten_and(left, out);
+            flatten_and(right, out);
+        }
+        _ => out.push(expr),
+    }
+}
+
+fn reads_protocols(expr: &Expr) -> bool {
+    match expr {
+        Expr::And(l, r) | Expr::Or(l, r) => reads_protocols(l) || reads_protocols(r),
+        Expr::Not(e) => reads_protocols(e),
+        Expr::Compare(f, ..) | Expr::In(f, _) | Expr::Has(f) => matches!(f, Field::Protocol(_)),
+    }
+}
+
+impl Plan {
+    /// Narrow the scan by a condition every match must meet
+    fn narrow(&mut self, conjunct: &Expr) {
+        let names = match conjunct {
+            Expr::Compare(Field::Seq, op, Value::Number(n)) => {
+                let Some(n) = n.as_f64().filter(|n| *n >= 0.0) else {
+                    return;
+                };
+                let (from, until) = match op {
+                    Op::Eq if n.fract() == 0.0 => (Some(n as u64), Some(n as u64 + 1)),
+                    Op::Gt => (Some(n.floor() as u64 + 1), None),
+                    Op::Ge => (Some(n.ceil() as u64), None),
+                    Op::Lt => (None, Some(n.ceil() as u64)),
+                    Op::Le => (None, Some(n.floor() as u64 + 1)),
+                    _ => (None, None),
+                };
+                self.from_seq = self.from_seq.max(from.unwrap_or(0));
+                self.until_seq = match (self.until_seq, until) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+                return;
+            }
+            Expr::Compare(Field::Event, Op::Eq, Value::String(name)) => BTreeSet::from([name.clone()]),
+            Expr::In(Field::Event, values) => values.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+            _ => return,
+        };
+        self.events = Some(match self.events.take() {
+            Some(known) => known.intersection(&names).cloned().collect(),
+            None => names,
+        });
+    }
+}
+
+// =============================================================================
+// Evaluation
+// =============================================================================
+
+/// Current registrations, by `agent::protocol`, for `protocol.*` fields
+#[derive(Debug, Default)]
+pub struct Registrations(HashMap<String, Map<String, Value>>);
+
+impl Registrations {
+    /// Snapshot every registration of the gateway
+    fn snapshot(state: &AppState) -> Self {
+        let st = state.inner.read().unwrap();
+        let mut registrations = HashMap::new();
+        for (agent_id, protocols) in &st.protocols {
+            for (key, descriptor) in protocols {
+                let report_key = format!("{agent_id}::{key}");
+                let effective = st
+                    .protocol_stats
+                    .get(&report_key)
+                    .map_or_else(|| descriptor.risk_tier.clone(), |s| s.risk.effective(&descriptor.risk_tier));
+                let fields = Map::from_iter([
+                    ("name".to_string(), Value::from(descriptor.name.as_str())),
+                    ("version".to_string(), Value::from(descriptor.version.as_str())),
+                    ("risk_tier".to_string(), Value::from(effective)),
+                    ("registered_risk_tier".to_string(), Value::from(descriptor.risk_tier.as_str())),
+                ]);
+                registrations.insert(report_key, fields);
+            }
+        }
+        Self(registrations)
+    }
+}
+
+fn agent_of(event: &AuditEvent) -> Option<&Value> {
+    event.fields.get("from").or_else(|| event.fields.get("agent_id"))
+}
+
+fn value_of<'a>(field: &Field, event: &'a AuditEvent, registrations: &'a Registrations) -> Option<Cow<'a, Value>> {
+    let owned = |v: Value| Some(Cow::Owned(v));
+    match field {
+        Field::Seq => owned(Value::from(event.seq)),
+        Field::Ts => owned(Value::from(event.ts)),
+        Field::Level => owned(Value::from(event.level.as_str())),
+        Field::Event => owned(Value::from(event.event.as_str())),
+        Field::PolicyVersion => event.policy_version.as_deref().and_then(|v| owned(Value::from(v))),
+        Field::Labels => owned(Value::from(event.labels.clone())),
+        Field::Agent => agent_of(event).map(Cow::Borrowed),
+        Field::Protocol(name) => {
+            let agent = agent_of(event)?.as_str()?;
+            let key = event.fields.get("protocol")?.as_str()?;
+            registrations.0.get(&format!("{agent}::{key}"))?.get(name).map(Cow::Borrowed)
+        }
+        Field::Fields(name) => event.fields.get(name).map(Cow::Borrowed),
+    }
+}
+
+/// Equality with numbers compared by value, so `1` equals `1.0`
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        _ => a == b,
+    }
+}
+
+fn order(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64()?.partial_cmp(&y.as_f64()?),
+        (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+        _ => None,
+    }
+}
+
+fn compare(value: &Value, op: Op, literal: &Value) -> bool {
+    match op {
+        Op::Eq => equal(value, literal),
+        Op::Ne => !equal(value, literal),
+        Op::Lt => order(value, literal) == Some(Ordering::Less),
+        Op::Le => matches!(order(value, literal), Some(Ordering::Less | Ordering::Equal)),
+        Op::Gt => order(value, literal) == Some(Ordering::Greater),
+        Op::Ge => matches!(order(value, literal), Some(Ordering::Greater | Ordering::Equal)),
+        Op::Contains => match (value, literal) {
+            (Value::String(s), Value::String(part)) => s.contains(part.as_str()),
+            (Value::Array(items), _) => items.iter().any(|item| equal(item, literal)),
+            _ => false,
+        },
+    }
+}
+
+fn eval(expr: &Expr, event: &AuditEvent, registrations: &Registrations) -> bool {
+    match expr {
+        Expr::And(l, r) => eval(l, event, registrations) && eval(r, event, registrations),
+        Expr::Or(l, r) => eval(l, event, registrations) || eval(r, event, registrations),
+        Expr::Not(e) => !eval(e, event, registrations),
+        Expr::Compare(field, op, literal) => match value_of(field, event, registrations) {
+            Some(value) => compare(&value, *op, literal),
+            None => *op == Op::Ne,
+        },
+        Expr::In(field, values) => {
+            value_of(field, event, registrations).is_some_and(|value| values.iter().any(|v| equal(&value, v)))
+        }
+        Expr::Has(field) => value_of(field, event, registrations).is_some_and(|value| !value.is_null()),
+    }
+}
+
+// =============================================================================
+// Search
+// =============================================================================
+
+/// Body of `POST /audit/search`
+#[derive(Debug, Deserialize)]
+pub struct SearchRequest {
+    query: String,
+    /// Sequence number to resume from, as a previous `next_cursor`
+    #[serde(default)]
+    cursor: Option<u64>,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+/// Body of a `POST /audit/search` response
+#[derive(Debug, Serialize)]
+pub struct SearchResponse {
+    events: Vec<AuditEvent>,
+    /// Events read to find them
+    scanned: usize,
+    /// Why the search ended before the end of the log: `limit`,
+    /// `scan_budget`, or `timeout`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stopped: Option<&'static str>,
+    /// Cursor to resume a stopped search from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<u64>,
+    plan: Plan,
+}
+
+/// Run `query` over the log from `cursor`, within `config`'s budget
+pub fn search(
+    log: &AuditLog,
+    query: &Query,
+    registrations: &Registrations,
+    cursor: Option<u64>,
+    limit: usize,
+    config: &SearchConfig,
+) -> SearchResponse {
+    let plan = &query.plan;
+    let deadline = Instant::now() + config.timeout;
+    let mut from = cursor.unwrap_or(0).max(plan.from_seq);
+    let until = plan.until_seq.unwrap_or_else(|| log.next_seq());
+    let (mut events, mut scanned, mut stopped) = (Vec::new(), 0, None);
+    while from < until && stopped.is_none() {
+        let page = SCAN_PAGE.min(config.max_scan - scanned);
+        let last = log.visit(from, until, page, |event| {
+            scanned += 1;
+            let named = plan.events.as_ref().is_none_or(|names| names.contains(&event.event));
+            if named && eval(&query.expr, event, registrations) {
+                events.push(event.clone());
+            }
+            events.len() < limit
+        });
+        let Some(last) = last else {
+            break;
+        };
+        from = last + 1;
+        stopped = if events.len() >= limit {
+            Some("limit")
+        } else if scanned >= config.max_scan {
+            Some("scan_budget")
+        } else if Instant::now() >= deadline {
+            Some("timeout")
+        } else {
+            None
+        };
+    }
+    SearchResponse {
+        events,
+        scanned,
+        next_cursor: stopped.filter(|_| from < until).map(|_| from),
+        stopped: stopped.filter(|_| from < until),
+        plan: plan.clone(),
+    }
+}
+
+/// Search the audit trail with a query
+pub async fn handle(
+    _: AuthedAuditor,
+    State(state): State<AppState>,
+    Payload(req): Payload<SearchRequest>,
+) -> Result<Json<SearchResponse>, GatewayError> {
+    let query = Query::compile(&req.query).map_err(|e| GatewayError::Invalid(format!("Invalid query: {e}")))?;
+    let registrations = if query.plan.protocol_lookup {
+        Registrations::snapshot(&state)
+    } else {
+        Registrations::default()
+    };
+    let config = state.audit_search.as_ref().clone();
+    let limit = req.limit.unwrap_or(config.max_results).clamp(1, config.max_results);
+    let log = state.audit.clone();
+    // The scan holds the log's read lock a page at a time, off the async workers
+    let response = tokio::task::spawn_blocking(move || search(&log, &query, &registrations, req.cursor, limit, &config))
+        .await
+        .map_err(|e| GatewayError::Internal(format!("audit search failed: {e}")))?;
+    info!(
+        query = %req.query,
+        matched = response.events.len(),
+        scanned = response.scanned,
+        stopped = response.stopped,
+        event = "audit_searched",
+        "Audit trail searched"
+    );
+    Ok(Json(response))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{AgentFixture, ProtocolFixture, TestGateway, ADMIN_TOKEN};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    fn fields(value: Value) -> Map<String, Value> {
+        value.as_object().cloned().unwrap()
+    }
+
+    #[test]
+    fn test_query_compiles_to_plan_and_budget() {
+        let query = Query::compile(r#"seq >= 10 and seq < 20 and event in ["a", "b"] and (event = "b" or x > 1)"#).unwrap();
+        assert_eq!(query.plan.from_seq, 10);
+        assert_eq!(query.plan.until_seq, Some(20));
+        assert_eq!(query.plan.events, Some(BTreeSet::from(["a".to_string(), "b".to_string()])));
+        assert!(!query.plan.protocol_lookup);
+
+        for bad in ["", "a =", "a = 1 b", "protocol.owner = \"x\"", "(a = 1", "a ! 1", "a in [1 2]"] {
+            assert!(Query::compile(bad).is_err(), "{bad}");
+        }
+        let wide = vec!["a = 1"; MAX_NODES].join(" or ");
+        assert!(Query::compile(&wide).unwrap_err().contains("more than"));
+        let deep = format!("{}a = 1{}", "(".repeat(MAX_DEPTH + 1), ")".repeat(MAX_DEPTH + 1));
+        assert!(Query::compile(&deep).unwrap_err().contains("deeper"));
+
+        let log = AuditLog::new(100);
+        for i in 0..30 {
+            log.append("INFO", "tick", fields(json!({ "n": i })));
+        }
+        let budget = SearchConfig {
+            max_scan: 5,
+            ..SearchConfig::default()
+        };
+        let partial = search(&log, &Query::compile("n >= 0").unwrap(), &Registrations::default(), None, 100, &budget);
+        assert_eq!((partial.events.len(), partial.stopped, partial.next_cursor), (5, Some("scan_budget"), Some(6)));
+    }
+
+    #[tokio::test]
+    async fn test_search_joins_protocol_registrations() {
+        let gw = TestGateway::new();
+        let risky = ProtocolFixture::new("risky", "1.0").risk_tier("high").build();
+        let calm = ProtocolFixture::new("calm", "1.0").build();
+        gw.setup_agent(AgentFixture::new("x").protocol(risky).protocol(calm)).await;
+        let log = &gw.state().audit;
+        for (protocol, coverage) in [("risky:1.0", 0.5), ("risky:1.0", 0.95), ("calm:1.0", 0.5)] {
+            let event = json!({ "agent_id": "x", "protocol": protocol, "coverage": coverage, "reason": "coverage_low" });
+            log.append("WARN", "report_rejected", fields(event));
+        }
+        log.append("WARN", "report_rejected", fields(json!({ "agent_id": "y", "protocol": "risky:1.0", "coverage": 0.1 })));
+
+        let query = r#"event = "report_rejected" and agent = "x" and protocol.risk_tier = "high" and coverage < 0.9"#;
+        let body = json!({ "query": query });
+        let resp = gw.call(Method::POST, "/audit/search", Some(&body), Some(ADMIN_TOKEN)).await;
+        assert_eq!(resp.status, StatusCode::OK, "{:?}", resp.body);
+        let events = resp.body["events"].as_array().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["fields"]["coverage"], 0.5);
+        assert_eq!(resp.body["plan"]["events"], json!(["report_rejected"]));
+
+        let bad = gw.call(Method::POST, "/audit/search", Some(&json!({ "query": "coverage <" })), Some(ADMIN_TOKEN)).await;
+        assert_eq!(bad.status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,349 @@
+//! Per-route authorization policy
+//!
+//! Every route has a rule naming the roles that may call it:
+//!
+//! - `admin`: an `ADMIN_TOKEN` or `ADMIN_TOKENS` token
+//! - `auditor`: an `AUDITOR_TOKENS` token
+//! - `team`: a `TEAM_TOKENS` token
+//! - `open`: no token, while no team tokens are configured
+//! - `public`: anyone; the route checks its own credentials, if any
+//!
+//! The built-in rules match what each handler expects. `AUTHZ_POLICY` (JSON)
+//! adds rules that take precedence, e.g. to restrict the stats reads to admins
+//! or disable a route with `"roles": []`:
+//!
+//! ```json
+//! [{"method": "GET", "path": "/stats/slo", "roles": ["admin"]}]
+//! ```
+//!
+//! A rule applies to one method or, with `"*"` (the default), to every method
+//! of its path; the exact method wins, then configured rules over built-in
+//! ones. The [`authorize`] middleware looks up the rule for the matched route
+//! and refuses callers it does not admit with 403 `forbidden`, or with the
+//! credential error the route's own check would give. A route without a rule
+//! is refused. Handlers keep their own checks, so a rule can narrow who calls
+//! a route but never lets a caller past a check the handler makes itself.
+//!
+//! At startup, every route the router registers must have a rule and every
+//! configured rule must name a registered route; otherwise the gateway
+//! refuses to start.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+    Router,
+};
+use serde::Deserialize;
+use std::env;
+use tracing::warn;
+
+use crate::{admin_caller, error::GatewayError, ownership::Caller, read_access, AppState};
+
+/// A kind of caller a rule may admit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Admin,
+    Auditor,
+    Team,
+    Open,
+    Public,
+}
+
+impl Role {
+    fn admits(self, caller: &Caller) -> bool {
+        matches!(
+            (self, caller),
+            (Self::Admin, Caller::Admin)
+                | (Self::Auditor, Caller::Auditor(_))
+                | (Self::Team, Caller::Team(_))
+                | (Self::Open, Caller::Open)
+        )
+    }
+}
+
+const PUBLIC: &[Role] = &[Role::Public];
+const READ: &[Role] = &[Role::Admin, Role::Auditor, Role::Team, Role::Open];
+const AUDIT: &[Role] = &[Role::Admin, Role::Auditor];
+const OWNER: &[Role] = &[Role::Admin, Role::Team];
+const ADMIN: &[Role] = &[Role::Admin];
+
+/// Built-in rules: method (`*` for any), route, roles
+const DEFAULT_RULES: &[(&str, &str, &[Role])] = &[
+    ("*", "/health", PUBLIC),
+    ("*", "/health/ready", PUBLIC),
+    ("*", "/status", READ),
+    ("*", "/metrics", PUBLIC),
+    ("*", "/register_protocol_for_agent", PUBLIC),
+    ("*", "/report", PUBLIC),
+    ("*", "/report_batch", PUBLIC),
+    ("*", "/send", PUBLIC),
+    ("*", "/send/stream", READ),
+    ("*", "/send/reserve", PUBLIC),
+    ("*", "/send/commit", PUBLIC),
+    ("*", "/send/abort", PUBLIC),
+    ("*", "/attachments/:agent_id", PUBLIC),
+    ("*", "/parked/:id", READ),
+    ("*", "/delivered/:message_id", PUBLIC),
+    ("*", "/threads/:id", READ),
+    ("*", "/graph/edges", READ),
+    ("*", "/protocols/:agent_id/:name/:version/stats", READ),
+    ("*", "/protocols/:agent_id/:name/:version/docs", READ),
+    ("*", "/protocols/:agent_id/:name/:version/adopt", PUBLIC),
+    ("*", "/protocols/conflicts", AUDIT),
+    ("*", "/protocols/:agent_id/:name/:version/reinstate", ADMIN),
+    ("*", "/protocols/:agent_id/:name/:version/risk/reset", ADMIN),
+    ("*", "/protocols/:agent_id/:name/:version/recertify", ADMIN),
+    ("*", "/protocols/:agent_id/:name/:version/scope", ADMIN),
+    ("*", "/reviews", AUDIT),
+    ("*", "/reviews/:id/approve", ADMIN),
+    ("*", "/reviews/:id/reject", ADMIN),
+    ("*", "/reviews/findings", AUDIT),
+    ("*", "/reviews/findings/:id/confirm", ADMIN),
+    ("*", "/reviews/findings/:id/dismiss", ADMIN),
+    ("*", "/quarantine", ADMIN),
+    ("*", "/quarantine/:id", ADMIN),
+    ("*", "/quarantine/:id/release", ADMIN),
+    ("*", "/quarantine/:id/discard", ADMIN),
+    ("*", "/agents", READ),
+    ("*", "/agents/:agent_id", ADMIN),
+    ("*", "/agents/:agent_id/restore", ADMIN),
+    ("*", "/agents/:agent_id/dormancy/keep", OWNER),
+    ("*", "/agents/:agent_id/dormancy/confirm", OWNER),
+    ("*", "/agents/:agent_id/owner", ADMIN),
+    ("*", "/agents/:agent_id/reputation", READ),
+    ("*", "/agents/:agent_id/summary-quality", READ),
+    ("*", "/teams/:team", ADMIN),
+    ("*", "/teams/:team/stats", READ),
+    ("*", "/orgs/:org/stats", READ),
+    ("*", "/stats/slo", READ),
+    ("*", "/stats/latency", READ),
+    ("*", "/stats/ips", READ),
+    ("*", "/billing/usage", READ),
+    ("*", "/policies/:version", PUBLIC),
+    ("*", "/audit/schema", PUBLIC),
+    ("*", "/audit/export", AUDIT),
+    ("*", "/audit/search", AUDIT),
+    ("GET", "/audit/annotations", AUDIT),
+    ("POST", "/audit/annotations", ADMIN),
+    ("*", "/audit/annotations/:id", ADMIN),
+    ("*", "/admin/backfill", ADMIN),
+    ("*", "/admin/outbox", ADMIN),
+    ("*", "/admin/dormant", ADMIN),
+    ("*", "/admin/alerts/test", ADMIN),
+    ("*", "/admin/policy", ADMIN),
+    ("*", "/admin/policy/experiment", ADMIN),
+    ("*", "/admin/patterns", ADMIN),
+    ("*", "/admin/quotas", ADMIN),
+    ("*", "/admin/keys", ADMIN),
+    ("*", "/admin/keys/rotate", ADMIN),
+    ("*", "/admin/fsck", ADMIN),
+    ("*", "/admin/approvals", ADMIN),
+    ("*", "/admin/approvals/:id/approve", ADMIN),
+    ("*", "/admin/approvals/:id/reject", ADMIN),
+    ("*", "/.well-known/jwks.json", PUBLIC),
+    ("*", "/receipts/verify", PUBLIC),
+    ("*", "/admin/chaos", ADMIN),
+    ("*", "/admin/maintenance", ADMIN),
+    ("*", "/admin/flags", ADMIN),
+    ("*", "/admin/logging", ADMIN),
+    ("*", "/admin/ips", ADMIN),
+    ("*", "/admin/ips/:ip", ADMIN),
+    ("*", "/admin/holds", ADMIN),
+    ("*", "/admin/holds/:agent_id", ADMIN),
+    ("*", "/admin/replication", ADMIN),
+    ("*", "/admin/replication/promote", ADMIN),
+    ("*", "/replication/stream", PUBLIC),
+    ("*", "/admin/registry-sync", ADMIN),
+    ("*", "/registry/snapshot", PUBLIC),
+    ("*", "/federation/suspensions", PUBLIC),
+    ("*", "/forward", PUBLIC),
+    ("*", "/debug/runtime", ADMIN),
+    ("*", "/ui", PUBLIC),
+    ("*", "/ui/:asset", PUBLIC),
+];
+
+/// A configured rule
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    #[serde(default = "any_method")]
+    method: String,
+    path: String,
+    roles: Vec<Role>,
+}
+
+fn any_method() -> String {
+    "*".to_string()
+}
+
+/// Configured rules over the built-in ones
+#[derive(Debug, Clone, Default)]
+pub struct AuthzPolicy {
+    rules: Vec<Rule>,
+}
+
+impl AuthzPolicy {
+    /// Parse `AUTHZ_POLICY`; unset keeps the built-in rules alone
+    pub fn from_env() -> Result<Self, String> {
+        match env::var("AUTHZ_POLICY") {
+            Ok(json) if !json.trim().is_empty() => Self::parse(&json),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let mut rules: Vec<Rule> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        for rule in &mut rules {
+            rule.method = rule.method.trim().to_ascii_uppercase();
+            if rule.method != "*" && Method::from_bytes(rule.method.as_bytes()).is_err() {
+                return Err(format!("invalid method {:?} for {}", rule.method, rule.path));
+            }
+        }
+        Ok(Self { rules })
+    }
+
+    /// Roles admitted to `method` on `route`, if any rule covers it
+    pub fn roles(&self, method: &Method, route: &str) -> Option<&[Role]> {
+        let method = if method == Method::HEAD { "GET" } else { method.as_str() };
+        let configured =
+            |m: &str| self.rules.iter().find(|r| r.path == route && r.method == m).map(|r| r.roles.as_slice());
+        let built_in =
+            |m: &str| DEFAULT_RULES.iter().find(|(rm, p, _)| *p == route && *rm == m).map(|(_, _, roles)| *roles);
+        configured(method).or_else(|| configured("*")).or_else(|| built_in(method)).or_else(|| built_in("*"))
+    }
+
+    /// Check that every route has a rule and every configured rule a route
+    pub fn check_routes(&self, routes: &[&str]) -> Result<(), String> {
+        let covered = |route: &str| {
+            self.rules.iter().any(|r| r.path == route) || DEFAULT_RULES.iter().any(|(_, p, _)| *p == route)
+        };
+        let missing: Vec<&str> = routes.iter().copied().filter(|r| !covered(r)).collect();
+        if !missing.is_empty() {
+            return Err(format!("no authorization rule for {}", missing.join(", ")));
+        }
+        let unknown: Vec<&str> = self.rules.iter().map(|r| r.path.as_str()).filter(|p| !routes.contains(p)).collect();
+        if !unknown.is_empty() {
+            return Err(format!("authorization rules for unknown routes {}", unknown.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+/// A router that remembers the routes it registers, for
+/// [`AuthzPolicy::check_routes`]
+#[derive(Default)]
+pub struct Routes {
+    router: Router<AppState>,
+    paths: Vec<&'static str>,
+}
+
+impl Routes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(mut self, path: &'static str, method_router: MethodRouter<AppState>) -> Self {
+        self.router = self.router.route(path, method_router);
+        self.paths.push(path);
+        self
+    }
+
+    pub fn paths(&self) -> &[&'static str] {
+        &self.paths
+    }
+
+    pub fn into_router(self) -> Router<AppState> {
+        self.router
+    }
+}
+
+/// Refusal for a caller `roles` do not admit: the credential error of an
+/// admin-only route, or `forbidden` for a known caller
+fn refusal(
+    state: &AppState,
+    req: &Request,
+    roles: &[Role],
+    caller: Result<Caller, GatewayError>,
+    route: String,
+) -> GatewayError {
+    let privileged = !roles.is_empty() && roles.iter().all(|r| matches!(r, Role::Admin | Role::Auditor));
+    match caller {
+        Ok(Caller::Admin) => GatewayError::Forbidden(route),
+        _ if privileged => admin_caller(state, req.headers()).err().unwrap_or(GatewayError::Forbidden(route)),
+        Err(e) => e,
+        Ok(_) => GatewayError::Forbidden(route),
+    }
+}
+
+/// Middleware refusing callers the rule of the matched route does not admit
+pub(crate) async fn authorize(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    // Unmatched requests fall through to the 404
+    let Some(route) = req.extensions().get::<MatchedPath>().map(|p| p.as_str().to_string()) else {
+        return next.run(req).await;
+    };
+    let roles = state.authz.roles(req.method(), &route).unwrap_or(&[]);
+    if roles.contains(&Role::Public) {
+        return next.run(req).await;
+    }
+    let caller = read_access(&state, req.headers());
+    if caller.as_ref().is_ok_and(|c| roles.iter().any(|r| r.admits(c))) {
+        return next.run(req).await;
+    }
+    let method = req.method().clone();
+    let err = refusal(&state, &req, roles, caller, format!("{method} {route}"));
+    warn!(
+        method = %method,
+        route = %route,
+        code = err.code(),
+        event = "authz_denied",
+        "Request refused by the authorization policy"
+    );
+    err.into_response()
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestGateway, ADMIN_TOKEN};
+    use axum::http::StatusCode;
+
+    #[test]
+    fn test_every_route_has_a_rule() {
+        let routes = crate::routes();
+        assert_eq!(AuthzPolicy::default().check_routes(routes.paths()), Ok(()));
+
+        let policy = AuthzPolicy::parse(
+            r#"[{"path": "/stats/slo", "roles": ["admin"]},
+                                            {"method": "get", "path": "/nope", "roles": []}]"#,
+        )
+        .unwrap();
+        assert_eq!(policy.roles(&Method::GET, "/stats/slo"), Some(ADMIN));
+        assert_eq!(policy.roles(&Method::GET, "/stats/latency"), Some(READ));
+        assert_eq!(policy.roles(&Method::POST, "/audit/annotations"), Some(ADMIN));
+        assert_eq!(policy.roles(&Method::PATCH, "/audit/annotations"), None, "no rule, refused");
+        let err = policy.check_routes(routes.paths()).unwrap_err();
+        assert!(err.contains("/nope"), "{err}");
+        assert!(AuthzPolicy::parse(r#"[{"path": "/x", "roles": ["root"]}]"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_configured_rule_restricts_route() {
+        let gw = TestGateway::new();
+        assert_eq!(gw.get("/stats/slo").await.status, StatusCode::OK);
+
+        let gw = TestGateway::with_authz(AuthzPolicy::parse(r#"[{"path": "/stats/slo", "roles": []}]"#).unwrap());
+        let resp = gw.get("/stats/slo").await;
+        assert_eq!(resp.status, StatusCode::FORBIDDEN);
+        assert_eq!(resp.body["code"], "forbidden");
+        let admin = gw.call(Method::GET, "/stats/slo", None::<&()>, Some(ADMIN_TOKEN)).await;
+        assert_eq!(admin.status, StatusCode::FORBIDDEN, "a route without roles admits nobody");
+    }
+}
@@ -0,0 +1,281 @@
+//! Backfill of historical messages and reports
+//!
+//! Teams migrating from ad-hoc logging import their history with
+//! `POST /admin/backfill`. Each historical message becomes one `msg_accepted`
+//! audit event per recipient, and each report a `report_accepted` event, at
+//! its original timestamp and marked `backfilled: true` with the batch's
+//! `source`. Threaded records are indexed under their thread like live ones.
+//!
+//! Only the audit store is written. Report deadlines, message counters,
+//! protocol stats, quotas, and SLOs stay driven by live traffic, so imported
+//! history neither satisfies nor breaches a live compliance clock, and
+//! records need not match a registered protocol.
+//!
+//! A batch is validated as a whole and refused, naming the first invalid
+//! record, before anything is written.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    audit::AuditLog,
+    ids::{AgentId, ProtocolName, ProtocolVersion},
+    protocol_key, threads, ProtocolRef, Recipients,
+};
+
+/// Most records (messages plus reports) in one batch
+pub const MAX_BACKFILL_RECORDS: usize = 10_000;
+
+/// Longest accepted `source`
+const MAX_SOURCE_LEN: usize = 128;
+
+/// Batch of historical records from one source
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackfillRequest {
+    /// System the history comes from, recorded on every event
+    pub source: String,
+    #[serde(default)]
+    pub messages: Vec<HistoricalMessage>,
+    #[serde(default)]
+    pub reports: Vec<HistoricalReport>,
+}
+
+/// A message delivered before the gateway was in place
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoricalMessage {
+    /// Original send time, Unix seconds
+    pub ts: f64,
+    pub from: AgentId,
+    pub to: Recipients,
+    pub content: String,
+    /// Protocol of a novel-language message
+    #[serde(default)]
+    pub protocol: Option<ProtocolRef>,
+    #[serde(default)]
+    pub thread_id: Option<String>,
+}
+
+/// A report filed before the gateway was in place
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoricalReport {
+    /// Original filing time, Unix seconds
+    pub ts: f64,
+    pub agent_id: AgentId,
+    pub protocol_name: ProtocolName,
+    pub protocol_version: ProtocolVersion,
+    pub window_start_ts: f64,
+    pub window_end_ts: f64,
+    #[serde(default)]
+    pub message_ids: Vec<String>,
+    pub english_summary: String,
+    pub coverage: f64,
+    #[serde(default)]
+    pub thread_id: Option<String>,
+}
+
+/// Outcome of an import
+#[derive(Debug, Clone, Serialize)]
+pub struct BackfillSummary {
+    pub source: String,
+    pub messages: usize,
+    pub reports: usize,
+    /// Sequence numbers of the events written, for export cursors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_seq: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seq: Option<u64>,
+}
+
+fn validate_ts(ts: f64, now: u64) -> Result<(), String> {
+    if !ts.is_finite() || ts <= 0.0 || ts > now as f64 {
+        return Err(format!("ts {ts} must be a past Unix time"));
+    }
+    Ok(())
+}
+
+fn validate_thread(thread_id: Option<&str>) -> Result<(), String> {
+    thread_id.map_or(Ok(()), threads::validate_id)
+}
+
+impl HistoricalMessage {
+    fn validate(&self, now: u64) -> Result<(), String> {
+        validate_ts(self.ts, now)?;
+        if self.content.is_empty() {
+            return Err("content must not be empty".to_string());
+        }
+        if self.to.list().is_empty() {
+            return Err("to must name at least one recipient".to_string());
+        }
+        validate_thread(self.thread_id.as_deref())
+    }
+}
+
+impl HistoricalReport {
+    fn validate(&self, now: u64) -> Result<(), String> {
+        validate_ts(self.ts, now)?;
+        if !(self.window_start_ts <= self.window_end_ts && self.window_end_ts <= self.ts) {
+            return Err("window must end no later than ts and not before it starts".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.coverage) {
+            return Err("coverage must be within [0, 1]".to_string());
+        }
+        if self.english_summary.trim().is_empty() {
+            return Err("english_summary must not be empty".to_string());
+        }
+        validate_thread(self.thread_id.as_deref())
+    }
+}
+
+impl BackfillRequest {
+    /// Check every record against `now`, the gateway's Unix time
+    pub fn validate(&self, now: u64) -> Result<(), String> {
+        if self.source.trim().is_empty() || self.source.len() > MAX_SOURCE_LEN {
+            return Err(format!("source must be 1-{MAX_SOURCE_LEN} characters"));
+        }
+        let records = self.messages.len() + self.reports.len();
+        if records == 0 || records > MAX_BACKFILL_RECORDS {
+            return Err(format!("a batch holds 1-{MAX_BACKFILL_RECORDS} records, got {records}"));
+        }
+        for (i, message) in self.messages.iter().enumerate() {
+            message.validate(now).map_err(|e| format!("messages[{i}]: {e}"))?;
+        }
+        for (i, report) in self.reports.iter().enumerate() {
+            report.validate(now).map_err(|e| format!("reports[{i}]: {e}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Validate `req` and write its records to `audit`
+pub fn import(audit: &AuditLog, req: BackfillRequest, now: u64) -> Result<BackfillSummary, String> {
+    req.validate(now)?;
+
+    let marker = |fields: &mut Map<String, Value>, thread_id: &Option<String>| {
+        if let Some(thread_id) = thread_id {
+            fields.insert("thread_id".into(), Value::from(thread_id.as_str()));
+        }
+        fields.insert("backfilled".into(), Value::from(true));
+        fields.insert("source".into(), Value::from(req.source.as_str()));
+    };
+    let mut seqs = Vec::new();
+    for message in &req.messages {
+        for to in message.to.list() {
+            let mut fields = Map::new();
+            fields.insert("from".into(), Value::from(message.from.as_str()));
+            fields.insert("to".into(), Value::from(to));
+            match &message.protocol {
+                Some(protocol) => {
+                    fields.insert("kind".into(), Value::from("novel"));
+                    fields.insert("protocol".into(), Value::from(protocol_key(&protocol.name, &protocol.version)));
+                }
+                None => {
+                    fields.insert("kind".into(), Value::from("english"));
+                }
+            }
+            fields.insert("content".into(), Value::from(message.content.as_str()));
+            marker(&mut fields, &message.thread_id);
+            seqs.push(audit.backfill("msg_accepted", message.ts, fields));
+        }
+    }
+    for report in &req.reports {
+        let mut fields = Map::new();
+        fields.insert("agent_id".into(), Value::from(report.agent_id.as_str()));
+        fields.insert(
+            "protocol".into(),
+            Value::from(protocol_key(&report.protocol_name, &report.protocol_version)),
+        );
+        fields.insert("message_count".into(), Value::from(report.message_ids.len()));
+        fields.insert("coverage".into(), Value::from(report.coverage));
+        fields.insert("window_start_ts".into(), Value::from(report.window_start_ts));
+        fields.insert("window_end_ts".into(), Value::from(report.window_end_ts));
+        fields.insert("english_summary".into(), Value::from(report.english_summary.as_str()));
+        marker(&mut fields, &report.thread_id);
+        seqs.push(audit.backfill("report_accepted", report.ts, fields));
+    }
+
+    Ok(BackfillSummary {
+        source: req.source.clone(),
+        messages: req.messages.len(),
+        reports: req.reports.len(),
+        first_seq: seqs.first().copied(),
+        last_seq: seqs.last().copied(),
+    })
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn batch(messages: Value, reports: Value) -> BackfillRequest {
+        serde_json::from_value(serde_json::json!({
+            "source": "legacy-log",
+            "messages": messages,
+            "reports": reports,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_import_marks_and_timestamps_events() {
+        let audit = AuditLog::new(100);
+        let req = batch(
+            serde_json::json!([
+                {"ts": 1_600_000_000.5, "from": "a", "to": ["b", "c"], "content": "SHP|q=7f",
+                 "protocol": {"name": "coord", "version": "1.0"}, "thread_id": "t1"},
+                {"ts": 1_600_000_100.0, "from": "a", "to": "b", "content": "Shipment due Friday"},
+            ]),
+            serde_json::json!([
+                {"ts": 1_600_000_200.0, "agent_id": "a", "protocol_name": "coord", "protocol_version": "1.0",
+                 "window_start_ts": 1_600_000_000.0, "window_end_ts": 1_600_000_150.0,
+                 "english_summary": "Shipment status sent to b and c", "coverage": 1.0},
+            ]),
+        );
+        let summary = import(&audit, req, NOW).unwrap();
+        assert_eq!((summary.messages, summary.reports), (2, 1));
+        assert_eq!((summary.first_seq, summary.last_seq), (Some(1), Some(4)));
+
+        let events = audit.read_page(0, u64::MAX, 10);
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].ts, 1_600_000_000.5);
+        assert_eq!(events[0].event, "msg_accepted");
+        assert_eq!(events[0].fields["protocol"], "coord:1.0");
+        assert_eq!(events[2].fields["kind"], "english");
+        assert_eq!(events[3].event, "report_accepted");
+        assert!(events.iter().all(|e| e.fields["backfilled"] == true && e.fields["source"] == "legacy-log"));
+        assert!(events.iter().all(|e| e.policy_version.is_none()));
+        assert_eq!(audit.thread_events("t1").len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_batch_writes_nothing() {
+        let audit = AuditLog::new(100);
+        let future = NOW as f64 + 60.0;
+        let req = batch(
+            serde_json::json!([
+                {"ts": 1_600_000_000.0, "from": "a", "to": "b", "content": "ok"},
+                {"ts": future, "from": "a", "to": "b", "content": "from the future"},
+            ]),
+            serde_json::json!([]),
+        );
+        let err = import(&audit, req, NOW).unwrap_err();
+        assert!(err.starts_with("messages[1]"), "{err}");
+        assert_eq!(audit.next_seq(), 1);
+
+        let req = batch(
+            serde_json::json!([]),
+            serde_json::json!([
+                {"ts": 1_600_000_000.0, "agent_id": "a", "protocol_name": "coord", "protocol_version": "1.0",
+                 "window_start_ts": 1_600_000_000.0, "window_end_ts": 1_600_000_900.0,
+                 "english_summary": "Ends after it was filed", "coverage": 1.0},
+            ]),
+        );
+        assert!(import(&audit, req, NOW).unwrap_err().starts_with("reports[0]"));
+        assert!(import(&audit, batch(serde_json::json!([]), serde_json::json!([])), NOW).is_err());
+    }
+}
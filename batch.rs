@@ -0,0 +1,175 @@
+//! Batch report ingestion
+//!
+//! `POST /report_batch` takes `{"reports": [...]}`, each item the body of a
+//! `POST /report`, and files every report independently: one refused report
+//! does not affect the others. Items are filed concurrently, at most
+//! `REPORT_BATCH_CONCURRENCY` at a time, and the response lists one result per
+//! item in submission order, each with the status and body `POST /report`
+//! would have answered. The batch answers 200 when every report was accepted
+//! and 207 otherwise. A batch must hold 1 to `REPORT_BATCH_MAX_ITEMS` reports
+//! (400 otherwise).
+//!
+//! Reports for the same agent and protocol may be filed in any order, so an
+//! orchestrator that needs them applied in sequence should submit them in
+//! separate batches.
+
+use axum::{extract::State, http::StatusCode, Json};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    codec::Payload, env_parse, error::GatewayError, phrase_refusal, protocol_key, submit_report_outcome, ApiResponse,
+    AppState, EnglishReport,
+};
+
+/// Default most reports in one batch
+const DEFAULT_MAX_ITEMS: usize = 100;
+
+/// Default most reports filed at once
+const DEFAULT_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    pub max_items: usize,
+    pub concurrency: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_items: DEFAULT_MAX_ITEMS,
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+}
+
+impl BatchConfig {
+    /// Load `REPORT_BATCH_MAX_ITEMS` and `REPORT_BATCH_CONCURRENCY`
+    pub fn from_env() -> Self {
+        let d = Self::default();
+        let var = |key: &str| env_parse::<usize>(key).filter(|&n| n > 0);
+        Self {
+            max_items: var("REPORT_BATCH_MAX_ITEMS").unwrap_or(d.max_items),
+            concurrency: var("REPORT_BATCH_CONCURRENCY").unwrap_or(d.concurrency),
+        }
+    }
+}
+
+/// Body of `POST /report_batch`
+#[derive(Debug, Deserialize)]
+pub struct ReportBatch {
+    reports: Vec<EnglishReport>,
+}
+
+/// Outcome of one report of a batch
+#[derive(Debug, Serialize)]
+pub struct BatchItem {
+    /// Position of the report in the batch
+    index: usize,
+    agent_id: String,
+    protocol: String,
+    /// Accepted outright; a report held for review is not
+    accepted: bool,
+    /// Status `POST /report` would have answered
+    status: u16,
+    #[serde(flatten)]
+    response: ApiResponse,
+}
+
+/// Body of a `POST /report_batch` response
+#[derive(Debug, Serialize)]
+pub struct BatchResponse {
+    accepted: usize,
+    rejected: usize,
+    results: Vec<BatchItem>,
+}
+
+/// File every report of a batch and answer per-item results
+pub async fn submit(
+    State(state): State<AppState>,
+    Payload(batch): Payload<ReportBatch>,
+) -> Result<(StatusCode, Json<BatchResponse>), GatewayError> {
+    let config = &state.report_batches;
+    if batch.reports.is_empty() || batch.reports.len() > config.max_items {
+        return Err(GatewayError::Invalid(format!(
+            "A report batch holds 1-{} reports, got {}",
+            config.max_items,
+            batch.reports.len()
+        )));
+    }
+
+    let items = batch.reports.into_iter().enumerate().map(|(index, report)| {
+        let state = state.clone();
+        let agent_id = report.agent_id.to_string();
+        let protocol = protocol_key(&report.protocol_name, &report.protocol_version);
+        let filed = tokio::spawn(submit_report_outcome(state.clone(), report));
+        async move {
+            let outcome = filed
+                .await
+                .unwrap_or_else(|e| Err(GatewayError::Internal(format!("report task failed: {e}"))));
+            let (status, response) = match phrase_refusal(&state, &agent_id, outcome) {
+                Ok((status, Json(response))) => (status, response),
+                Err(e) => (e.status(), ApiResponse::from(e)),
+            };
+            BatchItem {
+                index,
+                agent_id,
+                protocol,
+                accepted: status == StatusCode::OK && response.ok,
+                status: status.as_u16(),
+                response,
+            }
+        }
+    });
+    let results: Vec<BatchItem> = futures::stream::iter(items).buffered(config.concurrency).collect().await;
+
+    let accepted = results.iter().filter(|r| r.accepted).count();
+    let rejected = results.len() - accepted;
+    info!(
+        reports = results.len(),
+        accepted,
+        rejected,
+        event = "report_batch_filed",
+        "Report batch filed"
+    );
+    let status = if rejected == 0 { StatusCode::OK } else { StatusCode::MULTI_STATUS };
+    Ok((status, Json(BatchResponse { accepted, rejected, results })))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{AgentFixture, ProtocolFixture, ReportFixture, TestGateway};
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_batch_reports_each_item() {
+        let gw = TestGateway::new();
+        let coord = ProtocolFixture::new("coord", "1.0").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(coord.clone())).await;
+        let other = ProtocolFixture::new("other", "1.0").build();
+
+        let reports = [
+            ReportFixture::new("a", &coord).build(),
+            ReportFixture::new("a", &other).build(),
+            ReportFixture::new("a", &coord).coverage(0.1).build(),
+        ];
+        let resp = gw.post("/report_batch", &json!({ "reports": reports })).await;
+        assert_eq!(resp.status, StatusCode::MULTI_STATUS);
+        assert_eq!((resp.body["accepted"].as_u64(), resp.body["rejected"].as_u64()), (Some(1), Some(2)));
+        let results = resp.body["results"].as_array().unwrap();
+        assert_eq!(results[0]["accepted"], true);
+        assert_eq!(results[1]["status"], 403);
+        assert_eq!(results[1]["code"], "protocol_not_registered");
+        assert_eq!(results[2]["code"], "coverage_low");
+        assert_eq!(results[2]["index"], 2);
+
+        let empty = gw.post("/report_batch", &json!({ "reports": [] })).await;
+        assert_eq!(empty.status, StatusCode::BAD_REQUEST);
+    }
+}
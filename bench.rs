@@ -0,0 +1,243 @@
+//! Send hot-path benchmarks and allocation budgets
+//!
+//! Test builds count heap allocations per thread with [`CountingAllocator`].
+//! `test_send_allocation_budget` drives representative sends through the full
+//! router and fails when one allocates more than its budget, so a change that
+//! reintroduces per-request copies shows up in `cargo test`.
+//!
+//! [`Bench`] is the fixture the criterion benchmarks in `benches/gateway.rs`
+//! run against: the same sends through the router, and the stages underneath
+//! them on their own, the English detector (heuristic and ensemble) and
+//! sender-side policy evaluation, and body encoding and decoding per wire
+//! format. Run them with `cargo bench --features test-harness`.
+//!
+//! These measure the gateway per request, in process. For the request rate a
+//! running server sustains under concurrency, see the `bench` binary.
+//!
+//! Compiled for unit tests and behind the `test-harness` feature.
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+#[cfg(test)]
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+use std::time::Duration;
+use tower::ServiceExt;
+
+use crate::{
+    cache::DecisionCache,
+    detector::{Detector, DetectorConfig},
+    ensemble::EnsembleConfig,
+    evaluate_sender,
+    latency::PipelineTiming,
+    testing::{AgentFixture, ProtocolFixture, SendFixture, TestGateway},
+    AppState, EnglishReport, SendMessageRequest,
+};
+
+pub use crate::codec::BodyFormat;
+
+// =============================================================================
+// Allocation counting
+// =============================================================================
+
+#[cfg(test)]
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// System allocator that counts allocations made by the current thread
+#[cfg(test)]
+pub struct CountingAllocator;
+
+#[cfg(test)]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// Thread-local storage may already be gone while a thread exits
+#[cfg(test)]
+fn count_allocation() {
+    let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+}
+
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Allocations made by this thread so far
+#[cfg(test)]
+fn allocations() -> u64 {
+    ALLOCATIONS.with(Cell::get)
+}
+
+// =============================================================================
+// Scenarios
+// =============================================================================
+
+/// A gateway with the decision cache on and pre-encoded send bodies
+pub struct Bench {
+    runtime: tokio::runtime::Runtime,
+    state: AppState,
+    router: Router,
+    /// English send from a registered agent
+    pub english: Bytes,
+    /// Novel-language send under a registered, reported protocol
+    pub novel: Bytes,
+    /// Novel-language send under an unregistered protocol, refused with 403
+    pub rejected: Bytes,
+}
+
+impl Default for Bench {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bench {
+    pub fn new() -> Self {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let gw = TestGateway::with_decision_cache(DecisionCache::new(1_000, Duration::from_secs(60)));
+        let coord = ProtocolFixture::new("coord", "1.0").build();
+        runtime.block_on(gw.setup_agent(AgentFixture::new("a").team("red").protocol(coord.clone()).reported()));
+        let unregistered = ProtocolFixture::new("covert", "1.0").build();
+        let encode = |send: SendFixture| Bytes::from(serde_json::to_vec(&send.build()).unwrap());
+        Self {
+            runtime,
+            state: gw.state().clone(),
+            router: gw.router(),
+            english: encode(SendFixture::english("a", "b")),
+            novel: encode(SendFixture::novel("a", "b", &coord, "SHP|eta=7f;q=0x3e;z=9")),
+            rejected: encode(SendFixture::novel("a", "b", &unregistered, "SHP|eta=7f;q=0x3e;z=9")),
+        }
+    }
+
+    /// `POST /send` with `body`, returning the status once the response is read
+    pub fn send(&self, body: &Bytes) -> StatusCode {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/send")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.clone()))
+            .unwrap();
+        self.runtime.block_on(async {
+            let response = self.router.clone().oneshot(req).await.unwrap();
+            let status = response.status();
+            to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            status
+        })
+    }
+
+    /// Sender-side policy evaluation of `req` alone, bypassing the decision
+    /// cache; returns whether the sender passed
+    pub fn evaluate(&self, req: &SendMessageRequest) -> bool {
+        let policy = self.state.policy.current();
+        self.runtime.block_on(async {
+            let mut timing = PipelineTiming::start(Duration::ZERO);
+            matches!(evaluate_sender(&self.state, req, &policy.policy, &mut timing).await, Ok(Ok(_)))
+        })
+    }
+
+    /// Whether `detector` classifies `content` as English
+    pub fn classify(&self, detector: &Detector, content: &str) -> Option<bool> {
+        self.runtime.block_on(detector.classify(content)).is_english
+    }
+
+    /// Mean allocations per send of `body`, after warming the decision cache
+    #[cfg(test)]
+    fn allocations_per_send(&self, body: &Bytes, expected: StatusCode) -> u64 {
+        const SENDS: u64 = 50;
+        assert_eq!(self.send(body), expected);
+        let before = allocations();
+        for _ in 0..SENDS {
+            self.send(body);
+        }
+        (allocations() - before) / SENDS
+    }
+}
+
+/// The detectors benchmarked, by name: the heuristic alone and an ensemble
+pub fn detectors() -> Vec<(&'static str, Detector)> {
+    let ensemble = EnsembleConfig::parse(r#"{"detectors": {"heuristic": {}, "ngram": {}, "entropy": {}}}"#).unwrap();
+    vec![
+        ("heuristic", Detector::default()),
+        (
+            "ensemble",
+            Detector::new(DetectorConfig {
+                ensemble: Some(ensemble),
+                ..DetectorConfig::default()
+            })
+            .unwrap(),
+        ),
+    ]
+}
+
+/// `n` distinct, valid reports, as a `/report_batch` body would carry
+pub fn report_batch(n: usize) -> Vec<EnglishReport> {
+    (0..n)
+        .map(|i| EnglishReport {
+            agent_id: format!("agent-{i:04}").try_into().unwrap(),
+            protocol_name: "compressed_coord".try_into().unwrap(),
+            protocol_version: "1.0".try_into().unwrap(),
+            window_start_ts: 1_706_745_600.0 + i as f64,
+            window_end_ts: 1_706_745_660.0 + i as f64,
+            message_ids: (0..8).map(|m| format!("{i:08x}{m:08x}")).collect(),
+            english_summary: "Exchanged task queue updates and acknowledged completion.".into(),
+            coverage: 0.98,
+            self_confidence: 0.9,
+            notes: None,
+            thread_id: None,
+        })
+        .collect()
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Allocations per request above which `test_send_allocation_budget` fails
+    ///
+    /// These cover the whole router (routing, middleware, audit events, response
+    /// encoding), not just the send handler, and leave headroom for differences
+    /// between dependency versions. Lower them when the hot path gets cheaper.
+    const ENGLISH_BUDGET: u64 = 256;
+    const NOVEL_BUDGET: u64 = 384;
+    const REJECTED_BUDGET: u64 = 256;
+
+    #[test]
+    fn test_send_allocation_budget() {
+        let bench = Bench::new();
+        for (name, body, expected, budget) in [
+            ("english", &bench.english, StatusCode::OK, ENGLISH_BUDGET),
+            ("novel", &bench.novel, StatusCode::OK, NOVEL_BUDGET),
+            ("rejected", &bench.rejected, StatusCode::FORBIDDEN, REJECTED_BUDGET),
+        ] {
+            let per_send = bench.allocations_per_send(body, expected);
+            println!("{name}: {per_send} allocations per send");
+            assert!(per_send <= budget, "{name} send made {per_send} allocations, budget {budget}");
+        }
+    }
+}
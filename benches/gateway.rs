@@ -0,0 +1,86 @@
+//! Criterion benchmarks of the send path, the stages underneath it, and the
+//! body codecs
+//!
+//! Run with `cargo bench --features test-harness`; the fixtures live in the
+//! library's `bench` module.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::time::Duration;
+
+use policy_gateway::{
+    bench::{self, Bench, BodyFormat},
+    testing::{ProtocolFixture, SendFixture},
+    EnglishReport,
+};
+
+fn config() -> Criterion {
+    Criterion::default().warm_up_time(Duration::from_secs(1))
+}
+
+/// `POST /send` through the full router
+fn send_path(c: &mut Criterion) {
+    let bench = Bench::new();
+    let mut group = c.benchmark_group("send");
+    group.bench_function("english", |b| b.iter(|| bench.send(&bench.english)));
+    group.bench_function("novel", |b| b.iter(|| bench.send(&bench.novel)));
+    group.bench_function("rejected", |b| b.iter(|| bench.send(&bench.rejected)));
+    group.finish();
+}
+
+/// English detection, heuristic alone and as an ensemble
+fn detector(c: &mut Criterion) {
+    let bench = Bench::new();
+    let long = "The shipment left the dock this morning and should arrive on Friday. ".repeat(30);
+    let samples = [
+        ("english", "Please confirm the shipment arrives on Friday"),
+        ("novel", "SHP|eta=7f;q=0x3e;z=9"),
+        ("long", long.as_str()),
+    ];
+
+    let mut group = c.benchmark_group("detector");
+    for (name, detector) in &bench::detectors() {
+        for (sample, content) in samples {
+            group.bench_function(format!("{name}/{sample}"), |b| b.iter(|| bench.classify(detector, content)));
+        }
+    }
+    group.finish();
+}
+
+/// Sender-side policy evaluation, bypassing the decision cache
+fn policy_evaluation(c: &mut Criterion) {
+    let bench = Bench::new();
+    let coord = ProtocolFixture::new("coord", "1.0").build();
+    let unregistered = ProtocolFixture::new("covert", "1.0").build();
+    let english = SendFixture::english("a", "b").build();
+    let novel = SendFixture::novel("a", "b", &coord, "SHP|eta=7f;q=0x3e;z=9").build();
+    let rejected = SendFixture::novel("a", "b", &unregistered, "SHP|eta=7f;q=0x3e;z=9").build();
+    assert!(bench.evaluate(&english) && bench.evaluate(&novel) && !bench.evaluate(&rejected));
+
+    let mut group = c.benchmark_group("policy");
+    group.bench_function("english", |b| b.iter(|| bench.evaluate(&english)));
+    group.bench_function("novel", |b| b.iter(|| bench.evaluate(&novel)));
+    group.bench_function("rejected", |b| b.iter(|| bench.evaluate(&rejected)));
+    group.finish();
+}
+
+/// Encoding and decoding a large report batch, per wire format
+fn codec(c: &mut Criterion) {
+    let batch = bench::report_batch(10_000);
+    let mut group = c.benchmark_group("codec");
+    for format in [BodyFormat::Json, BodyFormat::Cbor, BodyFormat::MsgPack] {
+        let bytes = format.encode(&batch).unwrap();
+        println!("{}: {} bytes for {} reports", format.mime(), bytes.len(), batch.len());
+        group.bench_function(format!("{format:?}/encode"), |b| b.iter(|| format.encode(&batch).unwrap()));
+        group.bench_function(format!("{format:?}/decode"), |b| {
+            b.iter(|| format.decode::<Vec<EnglishReport>>(&bytes).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = config();
+    targets = send_path, detector, policy_evaluation, codec
+}
+criterion_main!(benches);
@@ -0,0 +1,551 @@
+//! Load generator for a running gateway
+//!
+//! Drives `POST /send` on a live gateway at one or more concurrency levels and
+//! writes a latency/throughput report, so the request rate a deployment
+//! sustains can be measured and tracked across releases:
+//!
+//! ```text
+//! cargo run --release --bin bench -- --url http://127.0.0.1:8080 \
+//!     --concurrency 1,8,32,128 --duration 15 --out bench-report.json
+//! ```
+//!
+//! The run registers a throwaway agent with a `bench:1.0` protocol, files its
+//! reports as the report interval requires, and sends a mix of English,
+//! novel-language, and unregistered-protocol messages (`--mix`, by weight).
+//! Each stage warms up for `--warmup` seconds before `--duration` seconds are
+//! measured. A request counts as an error when it fails or answers anything
+//! but the status its kind should get (200, 200, and 403).
+//!
+//! The highest throughput among stages with an error rate at or below
+//! `--max-error-rate`, and a p99 latency at or below `--max-p99-ms` when set,
+//! is reported as the maximum sustainable rate. With `--baseline` pointing at
+//! an earlier report, the run exits with status 1 when that rate dropped, or
+//! the p99 latency at a concurrency level both runs measured rose, by more
+//! than `--max-regression`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    env, fs, process,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Seconds between the bench agent's reports, well inside the default interval
+const REPORT_EVERY_SEC: u64 = 20;
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+struct Options {
+    url: String,
+    concurrency: Vec<usize>,
+    duration: Duration,
+    warmup: Duration,
+    /// Weights of English, novel, and rejected sends
+    mix: [u32; 3],
+    out: String,
+    baseline: Option<String>,
+    max_regression: f64,
+    max_error_rate: f64,
+    max_p99_ms: Option<f64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:8080".to_string(),
+            concurrency: vec![1, 8, 32, 128],
+            duration: Duration::from_secs(10),
+            warmup: Duration::from_secs(2),
+            mix: [6, 3, 1],
+            out: "bench-report.json".to_string(),
+            baseline: None,
+            max_regression: 0.1,
+            max_error_rate: 0.01,
+            max_p99_ms: None,
+        }
+    }
+}
+
+const USAGE: &str = "usage: bench [--url URL] [--concurrency N,N,...] [--duration SEC] [--warmup SEC]
+             [--mix ENGLISH:NOVEL:REJECTED] [--out FILE] [--baseline FILE]
+             [--max-regression RATIO] [--max-error-rate RATIO] [--max-p99-ms MS]";
+
+impl Options {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        fn number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+            value.trim().parse().map_err(|_| format!("{flag}: invalid value {value:?}"))
+        }
+        let mut opts = Self::default();
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or_else(|| format!("{flag} needs a value"))?;
+            match flag.as_str() {
+                "--url" => opts.url = value.trim_end_matches('/').to_string(),
+                "--concurrency" => {
+                    opts.concurrency = value
+                        .split(',')
+                        .map(|n| number(&flag, n))
+                        .collect::<Result<_, _>>()?;
+                }
+                "--duration" => opts.duration = Duration::from_secs(number(&flag, &value)?),
+                "--warmup" => opts.warmup = Duration::from_secs(number(&flag, &value)?),
+                "--mix" => {
+                    let weights: Vec<u32> = value.split(':').map(|w| number(&flag, w)).collect::<Result<_, _>>()?;
+                    opts.mix = weights
+                        .try_into()
+                        .map_err(|_| "--mix takes three weights, e.g. 6:3:1".to_string())?;
+                }
+                "--out" => opts.out = value,
+                "--baseline" => opts.baseline = Some(value),
+                "--max-regression" => opts.max_regression = number(&flag, &value)?,
+                "--max-error-rate" => opts.max_error_rate = number(&flag, &value)?,
+                "--max-p99-ms" => opts.max_p99_ms = Some(number(&flag, &value)?),
+                _ => return Err(format!("unknown option {flag}")),
+            }
+        }
+        if opts.concurrency.is_empty() || opts.concurrency.contains(&0) {
+            return Err("--concurrency levels must be positive".to_string());
+        }
+        if opts.duration.is_zero() {
+            return Err("--duration must be positive".to_string());
+        }
+        if opts.mix.iter().all(|&w| w == 0) {
+            return Err("--mix needs a positive weight".to_string());
+        }
+        Ok(opts)
+    }
+}
+
+// =============================================================================
+// Report
+// =============================================================================
+
+/// Latency percentiles in milliseconds
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Latency {
+    p50_ms: f64,
+    p90_ms: f64,
+    p99_ms: f64,
+    max_ms: f64,
+}
+
+impl Latency {
+    /// Percentiles of `samples`, in microseconds
+    fn of(samples: &mut [u64]) -> Self {
+        samples.sort_unstable();
+        let at = |q: f64| {
+            if samples.is_empty() {
+                return 0.0;
+            }
+            let rank = ((q * samples.len() as f64).ceil() as usize).clamp(1, samples.len());
+            samples[rank - 1] as f64 / 1_000.0
+        };
+        Self {
+            p50_ms: at(0.5),
+            p90_ms: at(0.9),
+            p99_ms: at(0.99),
+            max_ms: at(1.0),
+        }
+    }
+}
+
+/// Results at one concurrency level
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Stage {
+    concurrency: usize,
+    requests: u64,
+    errors: u64,
+    error_rate: f64,
+    throughput_rps: f64,
+    latency: Latency,
+    /// Requests by send kind
+    by_kind: BTreeMap<String, u64>,
+}
+
+/// What a run writes to `--out`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Report {
+    url: String,
+    started_at: u64,
+    duration_sec: u64,
+    mix: [u32; 3],
+    stages: Vec<Stage>,
+    /// Highest throughput of a stage within the error and latency limits
+    max_sustainable_rps: Option<f64>,
+}
+
+impl Report {
+    fn sustainable(stages: &[Stage], opts: &Options) -> Option<f64> {
+        stages
+            .iter()
+            .filter(|s| s.error_rate <= opts.max_error_rate)
+            .filter(|s| opts.max_p99_ms.is_none_or(|max| s.latency.p99_ms <= max))
+            .map(|s| s.throughput_rps)
+            .reduce(f64::max)
+    }
+
+    /// Regressions against `baseline` beyond `max_regression`
+    fn regressions(&self, baseline: &Report, max_regression: f64) -> Vec<String> {
+        let mut found = Vec::new();
+        match (baseline.max_sustainable_rps, self.max_sustainable_rps) {
+            (Some(before), Some(now)) if now < before * (1.0 - max_regression) => {
+                found.push(format!("max sustainable rate fell from {before:.0} to {now:.0} req/s"));
+            }
+            (Some(before), None) => {
+                found.push(format!("no stage is sustainable, baseline sustained {before:.0} req/s"));
+            }
+            _ => {}
+        }
+        for stage in &self.stages {
+            let Some(before) = baseline.stages.iter().find(|s| s.concurrency == stage.concurrency) else {
+                continue;
+            };
+            let (before, now) = (before.latency.p99_ms, stage.latency.p99_ms);
+            if now > before * (1.0 + max_regression) {
+                found.push(format!(
+                    "p99 at concurrency {} rose from {before:.2} to {now:.2} ms",
+                    stage.concurrency
+                ));
+            }
+        }
+        found
+    }
+}
+
+// =============================================================================
+// Load
+// =============================================================================
+
+/// Send kinds, with the status each should get
+const KINDS: [(&str, u16); 3] = [("english", 200), ("novel", 200), ("rejected", 403)];
+
+/// The bench agent and its pre-encoded sends
+struct Target {
+    client: reqwest::Client,
+    url: String,
+    agent_id: String,
+    bodies: [String; 3],
+    /// Kind of the n-th send, cycling through the mix
+    schedule: Vec<usize>,
+}
+
+impl Target {
+    fn new(opts: &Options, agent_id: String) -> Self {
+        let send = |content: &str, protocol: Option<&str>| {
+            let mut body = json!({"from": agent_id, "to": "bench-sink", "content": content});
+            if let Some(name) = protocol {
+                body["protocol"] = json!({"name": name, "version": "1.0"});
+            }
+            body.to_string()
+        };
+        let schedule = opts
+            .mix
+            .iter()
+            .enumerate()
+            .flat_map(|(kind, &weight)| std::iter::repeat_n(kind, weight as usize))
+            .collect();
+        Self {
+            client: reqwest::Client::new(),
+            url: opts.url.clone(),
+            bodies: [
+                send("Please confirm the shipment arrives on Friday", None),
+                send("SHP|eta=7f;q=0x3e;z=9", Some("bench")),
+                send("SHP|eta=7f;q=0x3e;z=9", Some("bench-unregistered")),
+            ],
+            agent_id,
+            schedule,
+        }
+    }
+
+    async fn post(&self, path: &str, body: String) -> Result<u16, reqwest::Error> {
+        let response = self
+            .client
+            .post(format!("{}{path}", self.url))
+            .header("content-type", "application/json")
+            .body(body)
+            .send()
+            .await?;
+        let status = response.status().as_u16();
+        response.bytes().await?;
+        Ok(status)
+    }
+
+    /// Register the bench protocol
+    async fn register(&self) -> Result<(), String> {
+        let body = json!({
+            "agent_id": self.agent_id,
+            "protocol": {
+                "name": "bench",
+                "version": "1.0",
+                "purpose": "Gateway load testing",
+                "scope": "Benchmark traffic only",
+                "risk_tier": "low",
+                "translation_method": "dictionary",
+                "codebook": {"SHP": "shipment", "eta": "estimated arrival", "q": "quantity"},
+            },
+        });
+        match self.post("/register_protocol_for_agent", body.to_string()).await {
+            Ok(status) if (200..300).contains(&status) => Ok(()),
+            Ok(status) => Err(format!("registration answered {status}")),
+            Err(e) => Err(format!("registration failed: {e}")),
+        }
+    }
+
+    /// File a report so novel sends stay allowed
+    async fn report(&self) -> Result<(), String> {
+        let body = json!({
+            "agent_id": self.agent_id,
+            "protocol_name": "bench",
+            "protocol_version": "1.0",
+            "window_start_ts": 0.0,
+            "window_end_ts": f64::from(u32::MAX),
+            "message_ids": [],
+            "english_summary": "Synthetic shipment status updates sent by the load generator",
+            "coverage": 1.0,
+            "self_confidence": 1.0,
+        });
+        match self.post("/report", body.to_string()).await {
+            Ok(status) if (200..300).contains(&status) => Ok(()),
+            Ok(status) => Err(format!("report answered {status}")),
+            Err(e) => Err(format!("report failed: {e}")),
+        }
+    }
+}
+
+/// What one worker saw during a measured stage
+#[derive(Default)]
+struct Tally {
+    latencies_us: Vec<u64>,
+    errors: u64,
+    by_kind: [u64; 3],
+}
+
+/// Send from one worker until `until`, recording only after `measure_from`
+async fn worker(target: Arc<Target>, offset: usize, measure_from: Instant, until: Instant) -> Tally {
+    let mut tally = Tally::default();
+    let mut n = offset;
+    loop {
+        let started = Instant::now();
+        if started >= until {
+            return tally;
+        }
+        let kind = target.schedule[n % target.schedule.len()];
+        n += 1;
+        let outcome = target.post("/send", target.bodies[kind].clone()).await;
+        if started < measure_from {
+            continue;
+        }
+        tally.latencies_us.push(started.elapsed().as_micros() as u64);
+        tally.by_kind[kind] += 1;
+        if !matches!(outcome, Ok(status) if status == KINDS[kind].1) {
+            tally.errors += 1;
+        }
+    }
+}
+
+async fn run_stage(target: &Arc<Target>, concurrency: usize, opts: &Options) -> Stage {
+    let measure_from = Instant::now() + opts.warmup;
+    let until = measure_from + opts.duration;
+    let workers: Vec<_> = (0..concurrency)
+        .map(|i| tokio::spawn(worker(target.clone(), i, measure_from, until)))
+        .collect();
+
+    let mut latencies = Vec::new();
+    let mut errors = 0;
+    let mut by_kind = [0; 3];
+    for handle in workers {
+        let Ok(tally) = handle.await else {
+            continue;
+        };
+        latencies.extend(tally.latencies_us);
+        errors += tally.errors;
+        for (total, n) in by_kind.iter_mut().zip(tally.by_kind) {
+            *total += n;
+        }
+    }
+    let requests = latencies.len() as u64;
+    Stage {
+        concurrency,
+        requests,
+        errors,
+        error_rate: if requests == 0 { 0.0 } else { errors as f64 / requests as f64 },
+        throughput_rps: requests as f64 / opts.duration.as_secs_f64(),
+        latency: Latency::of(&mut latencies),
+        by_kind: KINDS
+            .iter()
+            .zip(by_kind)
+            .map(|((kind, _), n)| (kind.to_string(), n))
+            .collect(),
+    }
+}
+
+// =============================================================================
+// Main
+// =============================================================================
+
+#[tokio::main]
+async fn main() {
+    let opts = match Options::parse(env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    let baseline = opts.baseline.as_ref().map(|path| {
+        fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|raw| serde_json::from_str::<Report>(&raw).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                eprintln!("baseline {path}: {e}");
+                process::exit(2);
+            })
+    });
+
+    let started_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let target = Arc::new(Target::new(&opts, format!("bench-{started_at}-{}", process::id())));
+    if let Err(e) = async {
+        target.register().await?;
+        target.report().await
+    }
+    .await
+    {
+        eprintln!("{}: {e}", opts.url);
+        process::exit(2);
+    }
+    let reporter = {
+        let target = target.clone();
+        tokio::spawn(async move {
+            let mut every = tokio::time::interval(Duration::from_secs(REPORT_EVERY_SEC));
+            every.tick().await;
+            loop {
+                every.tick().await;
+                if let Err(e) = target.report().await {
+                    eprintln!("{e}");
+                }
+            }
+        })
+    };
+
+    let mut stages = Vec::with_capacity(opts.concurrency.len());
+    for &concurrency in &opts.concurrency {
+        let stage = run_stage(&target, concurrency, &opts).await;
+        println!(
+            "concurrency {:>4}: {:>9.1} req/s  p50 {:>8.2} ms  p99 {:>8.2} ms  errors {:.2}%",
+            stage.concurrency,
+            stage.throughput_rps,
+            stage.latency.p50_ms,
+            stage.latency.p99_ms,
+            stage.error_rate * 100.0
+        );
+        stages.push(stage);
+    }
+    reporter.abort();
+
+    let report = Report {
+        url: opts.url.clone(),
+        started_at,
+        duration_sec: opts.duration.as_secs(),
+        mix: opts.mix,
+        max_sustainable_rps: Report::sustainable(&stages, &opts),
+        stages,
+    };
+    match report.max_sustainable_rps {
+        Some(rps) => println!("max sustainable rate: {rps:.1} req/s"),
+        None => println!("no stage stayed within the error and latency limits"),
+    }
+    let encoded = serde_json::to_string_pretty(&report).unwrap_or_default();
+    if let Err(e) = fs::write(&opts.out, encoded) {
+        eprintln!("{}: {e}", opts.out);
+        process::exit(2);
+    }
+    println!("report written to {}", opts.out);
+
+    if let Some(baseline) = baseline {
+        let regressions = report.regressions(&baseline, opts.max_regression);
+        for regression in &regressions {
+            eprintln!("regression: {regression}");
+        }
+        if !regressions.is_empty() {
+            process::exit(1);
+        }
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stage(concurrency: usize, throughput_rps: f64, p99_ms: f64, error_rate: f64) -> Stage {
+        Stage {
+            concurrency,
+            requests: 1_000,
+            errors: (error_rate * 1_000.0) as u64,
+            error_rate,
+            throughput_rps,
+            latency: Latency {
+                p99_ms,
+                ..Latency::default()
+            },
+            by_kind: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_options() {
+        let args = ["--concurrency", "2,16", "--mix", "1:0:0", "--max-p99-ms", "25"];
+        let opts = Options::parse(args.map(String::from)).unwrap();
+        assert_eq!((opts.concurrency, opts.mix, opts.max_p99_ms), (vec![2, 16], [1, 0, 0], Some(25.0)));
+
+        assert!(Options::parse(["--concurrency", "0"].map(String::from)).is_err());
+        assert!(Options::parse(["--mix", "1:2"].map(String::from)).is_err());
+        assert!(Options::parse(["--duration"].map(String::from)).is_err());
+        assert!(Options::parse(["--rate", "9"].map(String::from)).is_err());
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut samples: Vec<u64> = (1..=100).rev().map(|ms| ms * 1_000).collect();
+        let latency = Latency::of(&mut samples);
+        assert_eq!((latency.p50_ms, latency.p90_ms, latency.p99_ms, latency.max_ms), (50.0, 90.0, 99.0, 100.0));
+        assert_eq!(Latency::of(&mut []), Latency::default());
+    }
+
+    #[test]
+    fn test_sustainable_rate_and_regressions() {
+        let opts = Options {
+            max_p99_ms: Some(50.0),
+            ..Options::default()
+        };
+        let stages = vec![stage(1, 900.0, 2.0, 0.0), stage(8, 4_000.0, 20.0, 0.0), stage(32, 5_000.0, 80.0, 0.0)];
+        assert_eq!(Report::sustainable(&stages, &opts), Some(4_000.0));
+        let failing = vec![stage(8, 6_000.0, 20.0, 0.2)];
+        assert_eq!(Report::sustainable(&failing, &opts), None);
+
+        let report = |stages: Vec<Stage>| Report {
+            url: String::new(),
+            started_at: 0,
+            duration_sec: 10,
+            mix: [6, 3, 1],
+            max_sustainable_rps: Report::sustainable(&stages, &opts),
+            stages,
+        };
+        let baseline = report(stages);
+        let steady = report(vec![stage(8, 3_800.0, 21.0, 0.0)]);
+        assert!(steady.regressions(&baseline, 0.1).is_empty());
+
+        let slower = report(vec![stage(8, 3_000.0, 30.0, 0.0), stage(64, 3_000.0, 30.0, 0.0)]);
+        let found = slower.regressions(&baseline, 0.1);
+        assert_eq!(found.len(), 2, "{found:?}");
+        assert!(found[0].contains("4000 to 3000") && found[1].contains("concurrency 8"));
+    }
+}
@@ -0,0 +1,29 @@
+//! Emits the audit event schemas at build time
+//!
+//! The registry in `src/audit_schema.rs` depends only on serde, so it is
+//! compiled into this script as well and its document written to
+//! `$OUT_DIR/audit-schema.json`, the same document `GET /audit/schema` serves.
+//! With `AUDIT_SCHEMA_OUT` set, a copy is written there too, so a release can
+//! publish the schemas next to its binaries:
+//!
+//! ```text
+//! AUDIT_SCHEMA_OUT=target/audit-schema.json cargo build --release
+//! ```
+
+use std::{env, fs, path::PathBuf};
+
+#[allow(dead_code)]
+#[path = "src/audit_schema.rs"]
+mod audit_schema;
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/audit_schema.rs");
+    println!("cargo:rerun-if-env-changed=AUDIT_SCHEMA_OUT");
+
+    let document = serde_json::to_string_pretty(&audit_schema::document()).expect("audit schema serializes");
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR is set by cargo"));
+    fs::write(out_dir.join("audit-schema.json"), &document).expect("write audit-schema.json");
+    if let Some(path) = env::var_os("AUDIT_SCHEMA_OUT").filter(|p| !p.is_empty()) {
+        fs::write(&path, &document).unwrap_or_else(|e| panic!("write {}: {e}", PathBuf::from(&path).display()));
+    }
+}
@@ -0,0 +1,432 @@
+//! Decision cache for repeated identical sends
+//!
+//! Agents re-send the same handshake strings many times per minute. The
+//! sender-side part of a decision (language verdict, protocol registration,
+//! version resolution, report freshness) is cached in an LRU keyed by
+//! (sender, protocol, content and content-type hint digest, policy version).
+//! Keys hold the interned sender id and a 128-bit keyed digest of the declared
+//! protocol name and version, content and hint, so building one on a repeated
+//! send allocates nothing and two different sends never share an entry.
+//!
+//! Entries expire after `DECISION_CACHE_TTL_MS`, or earlier at the sender's
+//! report deadline, and every entry for an agent is invalidated as soon as its
+//! compliance state changes (registration, accepted report, violation). Only
+//! accepted decisions are cached; rejections carry side effects (violation
+//! counting, audit logging) and are always re-evaluated.
+//!
+//! The one exception is [`MissCache`]: misconfigured agents retry sends under
+//! a protocol they never registered thousands of times. Such (agent,
+//! protocol) misses are remembered for `NEGATIVE_CACHE_TTL_MS`, refused
+//! without touching shared state, and logged once per TTL with the number of
+//! repeats in between. Registering the protocol drops the entry.
+
+use lru::LruCache;
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{detector::VerdictSource, env_parse};
+
+/// Cache key: (sender, digest of declared protocol, content and content-type
+/// hint, policy version)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DecisionKey {
+    sender: Arc<str>,
+    /// Two independently keyed 64-bit hashes, compared in full on a lookup
+    content_digest: (u64, u64),
+    policy_version: u64,
+}
+
+/// Sender-side outcome of an accepted send
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendKind {
+    English,
+    Novel {
+        /// Interned protocol key
+        key: Arc<str>,
+        upgraded_from: Option<String>,
+    },
+}
+
+/// Cached sender-side decision
+#[derive(Debug, Clone)]
+pub struct SenderDecision {
+    pub kind: SendKind,
+    pub source: VerdictSource,
+}
+
+#[derive(Debug)]
+struct Entry {
+    decision: SenderDecision,
+    expires_at: Instant,
+    generation: u64,
+}
+
+/// Compliance generation of a sender with cached entries
+#[derive(Debug, Default)]
+struct Sender {
+    /// Bumping it invalidates the sender's entries
+    generation: u64,
+    /// Entries still in the LRU; the sender is dropped with its last one
+    entries: usize,
+}
+
+#[derive(Debug)]
+struct Inner {
+    entries: LruCache<DecisionKey, Entry>,
+    /// Only senders with cached entries, so bounded by the LRU capacity
+    senders: HashMap<Arc<str>, Sender>,
+}
+
+impl Inner {
+    fn generation(&self, sender: &str) -> u64 {
+        self.senders.get(sender).map_or(0, |s| s.generation)
+    }
+
+    /// Account for an entry of `sender` leaving the LRU
+    fn forget(&mut self, sender: &str) {
+        if let Some(s) = self.senders.get_mut(sender) {
+            s.entries = s.entries.saturating_sub(1);
+            if s.entries == 0 {
+                self.senders.remove(sender);
+            }
+        }
+    }
+}
+
+/// LRU cache of accepted sender-side decisions
+#[derive(Debug)]
+pub struct DecisionCache {
+    inner: Option<Mutex<Inner>>,
+    ttl: Duration,
+    hashers: (RandomState, RandomState),
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+}
+
+impl Default for DecisionCache {
+    fn default() -> Self {
+        Self::new(10_000, Duration::from_secs(2))
+    }
+}
+
+impl DecisionCache {
+    /// Create a cache holding up to `capacity` decisions; zero disables caching
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            inner: NonZeroUsize::new(capacity).map(|cap| {
+                Mutex::new(Inner {
+                    entries: LruCache::new(cap),
+                    senders: HashMap::new(),
+                })
+            }),
+            ttl,
+            hashers: (RandomState::new(), RandomState::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Load size and TTL from `DECISION_CACHE_SIZE` / `DECISION_CACHE_TTL_MS`
+    pub fn from_env() -> Self {
+        let size = env_parse("DECISION_CACHE_SIZE").unwrap_or(10_000);
+        let ttl = env_parse("DECISION_CACHE_TTL_MS")
+            .map(Duration::from_millis)
+            .unwrap_or(Duration::from_secs(2));
+        Self::new(size, ttl)
+    }
+
+    /// Key for a send from `sender` declaring `protocol` as (name, version)
+    pub fn key(
+        &self,
+        sender: Arc<str>,
+        protocol: Option<(&str, &str)>,
+        content: &str,
+        content_type: Option<&str>,
+        policy_version: u64,
+    ) -> DecisionKey {
+        DecisionKey {
+            sender,
+            content_digest: (
+                self.hashers.0.hash_one((protocol, content, content_type)),
+                self.hashers.1.hash_one((protocol, content, content_type)),
+            ),
+            policy_version,
+        }
+    }
+
+    pub fn get(&self, key: &DecisionKey) -> Option<SenderDecision> {
+        let inner = self.inner.as_ref()?;
+        let mut inner = inner.lock().unwrap();
+        let generation = inner.generation(&key.sender);
+        let hit = match inner.entries.get(key) {
+            Some(e) if e.generation == generation && e.expires_at > Instant::now() => {
+                Some(e.decision.clone())
+            }
+            Some(_) => {
+                inner.entries.pop(key);
+                inner.forget(&key.sender);
+                None
+            }
+            None => None,
+        };
+        let counter = if hit.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        hit
+    }
+
+    /// Cache `decision` until the TTL elapses or `valid_for`, whichever is sooner
+    pub fn insert(&self, key: DecisionKey, decision: SenderDecision, valid_for: Option<Duration>) {
+        let Some(inner) = self.inner.as_ref() else {
+            return;
+        };
+        let ttl = valid_for.map_or(self.ttl, |v| v.min(self.ttl));
+        if ttl.is_zero() {
+            return;
+        }
+        let mut inner = inner.lock().unwrap();
+        let sender = inner.senders.entry(key.sender.clone()).or_default();
+        sender.entries += 1;
+        let generation = sender.generation;
+        let replaced = inner.entries.push(
+            key,
+            Entry {
+                decision,
+                expires_at: Instant::now() + ttl,
+                generation,
+            },
+        );
+        // Either the same key's old entry or the evicted LRU tail
+        if let Some((old, _)) = replaced {
+            inner.forget(&old.sender);
+        }
+    }
+
+    /// Drop every cached decision for `agent_id`
+    pub fn invalidate_agent(&self, agent_id: &str) {
+        if let Some(inner) = self.inner.as_ref() {
+            // Nothing to invalidate for a sender without cached entries
+            if let Some(sender) = inner.lock().unwrap().senders.get_mut(agent_id) {
+                sender.generation += 1;
+            }
+        }
+    }
+
+    /// Drop every cached decision, e.g. once the flags behind them change
+    pub fn clear(&self) {
+        if let Some(inner) = self.inner.as_ref() {
+            let mut inner = inner.lock().unwrap();
+            inner.entries.clear();
+            inner.senders.clear();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner
+            .as_ref()
+            .map_or(0, |i| i.lock().unwrap().entries.len())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner
+            .as_ref()
+            .map_or(0, |i| i.lock().unwrap().entries.cap().get())
+    }
+}
+
+// =============================================================================
+// Negative cache
+// =============================================================================
+
+#[derive(Debug)]
+struct Miss {
+    expires_at: Instant,
+    /// Refusals served from the cache since the miss was last logged
+    repeats: u64,
+}
+
+/// LRU cache of (agent, protocol) registration misses, keyed `agent::protocol`
+#[derive(Debug)]
+pub struct MissCache {
+    inner: Option<Mutex<LruCache<String, Miss>>>,
+    ttl: Duration,
+    pub hits: AtomicU64,
+}
+
+impl Default for MissCache {
+    fn default() -> Self {
+        Self::new(10_000, Duration::from_secs(5))
+    }
+}
+
+impl MissCache {
+    /// Create a cache holding up to `capacity` misses; zero capacity or TTL disables it
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            inner: NonZeroUsize::new(capacity)
+                .filter(|_| !ttl.is_zero())
+                .map(|cap| Mutex::new(LruCache::new(cap))),
+            ttl,
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Load size and TTL from `NEGATIVE_CACHE_SIZE` / `NEGATIVE_CACHE_TTL_MS`
+    pub fn from_env() -> Self {
+        let size = env_parse("NEGATIVE_CACHE_SIZE").unwrap_or(10_000);
+        let ttl = env_parse("NEGATIVE_CACHE_TTL_MS")
+            .map(Duration::from_millis)
+            .unwrap_or(Duration::from_secs(5));
+        Self::new(size, ttl)
+    }
+
+    /// Whether `report_key` is a known miss; counts the repeat when it is
+    pub fn contains(&self, report_key: &str) -> bool {
+        let Some(inner) = self.inner.as_ref() else {
+            return false;
+        };
+        let mut inner = inner.lock().unwrap();
+        match inner.get_mut(report_key) {
+            Some(miss) if miss.expires_at > Instant::now() => {
+                miss.repeats += 1;
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Remember a miss; returns the repeats served since it was last recorded
+    pub fn record(&self, report_key: &str) -> u64 {
+        let Some(inner) = self.inner.as_ref() else {
+            return 0;
+        };
+        let mut inner = inner.lock().unwrap();
+        let repeats = inner.pop(report_key).map_or(0, |m| m.repeats);
+        inner.put(
+            report_key.to_string(),
+            Miss {
+                expires_at: Instant::now() + self.ttl,
+                repeats: 0,
+            },
+        );
+        repeats
+    }
+
+    /// Forget the miss for `report_key`, e.g. once the protocol is registered
+    pub fn invalidate(&self, report_key: &str) {
+        if let Some(inner) = self.inner.as_ref() {
+            inner.lock().unwrap().pop(report_key);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.as_ref().map_or(0, |i| i.lock().unwrap().len())
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn english() -> SenderDecision {
+        SenderDecision {
+            kind: SendKind::English,
+            source: VerdictSource::Heuristic,
+        }
+    }
+
+    #[test]
+    fn test_hit_and_agent_invalidation() {
+        let cache = DecisionCache::new(4, Duration::from_secs(60));
+        let key = cache.key(Arc::from("a"), None, "hello there", None, 1);
+        assert!(cache.get(&key).is_none());
+
+        cache.insert(key.clone(), english(), None);
+        assert_eq!(cache.get(&key).map(|d| d.kind), Some(SendKind::English));
+        assert_ne!(cache.key(Arc::from("a"), None, "hello there!", None, 1), key);
+        assert_ne!(cache.key(Arc::from("a"), Some(("hello", "there")), "", None, 1), key);
+
+        cache.invalidate_agent("b");
+        assert!(cache.get(&key).is_some());
+        cache.invalidate_agent("a");
+        assert!(cache.get(&key).is_none());
+        assert_eq!(cache.hits.load(Ordering::Relaxed), 2);
+        assert_eq!(cache.misses.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_senders_are_dropped_with_their_last_entry() {
+        let cache = DecisionCache::new(2, Duration::from_secs(60));
+        let senders = |cache: &DecisionCache| cache.inner.as_ref().unwrap().lock().unwrap().senders.len();
+        cache.invalidate_agent("a");
+        assert_eq!(senders(&cache), 0);
+
+        let a = cache.key(Arc::from("a"), None, "hello there", None, 1);
+        cache.insert(a.clone(), english(), None);
+        cache.insert(a.clone(), english(), None);
+        cache.insert(cache.key(Arc::from("b"), None, "hello there", None, 1), english(), None);
+        assert_eq!(senders(&cache), 2);
+
+        // Evicting a's only entry drops a; a stale hit on b's drops b
+        cache.insert(cache.key(Arc::from("b"), None, "good morning", None, 1), english(), None);
+        assert_eq!(senders(&cache), 1);
+        cache.invalidate_agent("b");
+        assert!(cache.get(&cache.key(Arc::from("b"), None, "hello there", None, 1)).is_none());
+        assert!(cache.get(&cache.key(Arc::from("b"), None, "good morning", None, 1)).is_none());
+        assert_eq!(senders(&cache), 0);
+
+        // A sender re-cached after being dropped starts afresh
+        cache.insert(a.clone(), english(), None);
+        assert!(cache.get(&a).is_some());
+    }
+
+    #[test]
+    fn test_deadline_and_policy_version() {
+        let cache = DecisionCache::new(4, Duration::from_secs(60));
+        let key = cache.key(Arc::from("a"), Some(("p", "1")), "X9|a=1", None, 1);
+        cache.insert(key.clone(), english(), Some(Duration::ZERO));
+        assert!(cache.get(&key).is_none());
+
+        cache.insert(key.clone(), english(), None);
+        assert!(cache.get(&key).is_some());
+        assert!(cache.get(&cache.key(Arc::from("a"), Some(("p", "1")), "X9|a=1", None, 2)).is_none());
+        assert!(cache.get(&cache.key(Arc::from("a"), Some(("p", "2")), "X9|a=1", None, 1)).is_none());
+    }
+
+    #[test]
+    fn test_miss_cache_repeats_and_invalidation() {
+        let misses = MissCache::new(4, Duration::from_secs(60));
+        assert!(!misses.contains("a::p:1"));
+        assert_eq!(misses.record("a::p:1"), 0);
+        assert!(misses.contains("a::p:1") && misses.contains("a::p:1"));
+        assert!(!misses.contains("a::p:2"));
+        assert_eq!(misses.record("a::p:1"), 2);
+        assert_eq!(misses.hits.load(Ordering::Relaxed), 2);
+
+        misses.invalidate("a::p:1");
+        assert!(!misses.contains("a::p:1"));
+
+        let expired = MissCache::new(4, Duration::from_nanos(1));
+        expired.record("a::p:1");
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(!expired.contains("a::p:1"));
+        assert_eq!(MissCache::new(4, Duration::ZERO).record("a::p:1"), 0);
+        assert_eq!(MissCache::new(0, Duration::from_secs(1)).len(), 0);
+    }
+}
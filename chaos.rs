@@ -0,0 +1,263 @@
+//! Fault injection for client resilience testing
+//!
+//! Disabled unless the gateway is started with `CHAOS_ENABLED=true`; the
+//! middleware is not even installed otherwise. Once enabled, per-route rules
+//! inject latency, 5xx errors, and truncated (malformed) responses at
+//! configurable rates. Rules start from `CHAOS_RULES` (JSON) and can be
+//! replaced at runtime via `PUT /admin/chaos`.
+//!
+//! Rules are keyed by request path: an exact path (`/send`), a prefix ending
+//! in `*` (`/protocols/*`), or `*` for every route. The most specific match
+//! wins. `/admin/*` is never faulted so chaos can always be switched off.
+//! Every faulted response carries `X-Fault-Injected` naming the fault.
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env,
+    hash::{BuildHasher, RandomState},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
+use tracing::{info, warn};
+
+use crate::AppState;
+
+/// Largest response body buffered when truncating it
+const MAX_MALFORM_BYTES: usize = 1024 * 1024;
+
+const FAULT_HEADER: HeaderName = HeaderName::from_static("x-fault-injected");
+
+/// Faults applied to requests matching one route pattern
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FaultRule {
+    /// Probability (0.0-1.0) of delaying the request
+    pub delay_rate: f64,
+    /// Added latency when delayed
+    pub delay_ms: u64,
+    /// Probability of answering with `error_status` instead of handling the request
+    pub error_rate: f64,
+    /// 5xx status used for injected errors (503 when unset)
+    pub error_status: Option<u16>,
+    /// Probability of handling the request but truncating the response body
+    pub malformed_rate: f64,
+}
+
+impl FaultRule {
+    fn validate(&self) -> Result<(), String> {
+        for (name, rate) in [
+            ("delay_rate", self.delay_rate),
+            ("error_rate", self.error_rate),
+            ("malformed_rate", self.malformed_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format!("{name} must be within [0, 1]"));
+            }
+        }
+        match self.error_status {
+            Some(code) if !(500..=599).contains(&code) => {
+                Err("error_status must be a 5xx code".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Complete set of fault rules, keyed by route pattern
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChaosConfig {
+    pub rules: BTreeMap<String, FaultRule>,
+}
+
+impl ChaosConfig {
+    /// Check every rate and status code
+    pub fn validate(&self) -> Result<(), String> {
+        for (pattern, rule) in &self.rules {
+            rule.validate().map_err(|e| format!("{pattern}: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// Most specific rule for `path`: exact, then longest `prefix*`, then `*`
+    fn rule_for(&self, path: &str) -> Option<&FaultRule> {
+        if let Some(rule) = self.rules.get(path) {
+            return Some(rule);
+        }
+        self.rules
+            .iter()
+            .filter_map(|(pattern, rule)| {
+                let prefix = pattern.strip_suffix('*')?;
+                path.starts_with(prefix).then_some((prefix.len(), rule))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, rule)| rule)
+    }
+}
+
+/// Runtime-controllable fault injector
+#[derive(Debug)]
+pub struct FaultInjector {
+    enabled: bool,
+    config: RwLock<ChaosConfig>,
+    /// splitmix64 state for fault rolls
+    rng: AtomicU64,
+}
+
+impl Default for FaultInjector {
+    fn default() -> Self {
+        Self::new(false, ChaosConfig::default())
+    }
+}
+
+impl FaultInjector {
+    pub fn new(enabled: bool, config: ChaosConfig) -> Self {
+        Self {
+            enabled,
+            config: RwLock::new(config),
+            rng: AtomicU64::new(RandomState::new().hash_one(0u64)),
+        }
+    }
+
+    /// Load `CHAOS_ENABLED` and the initial `CHAOS_RULES`
+    pub fn from_env() -> Self {
+        let enabled = env::var("CHAOS_ENABLED").is_ok_and(|v| matches!(v.trim(), "1" | "true" | "yes"));
+        let config = match env::var("CHAOS_RULES") {
+            Ok(json) => match serde_json::from_str::<ChaosConfig>(&json)
+                .map_err(|e| e.to_string())
+                .and_then(|c| c.validate().map(|_| c))
+            {
+                Ok(config) => config,
+                Err(e) => {
+                    warn!(event = "config_invalid", error = %e, "Invalid CHAOS_RULES, starting without faults");
+                    ChaosConfig::default()
+                }
+            },
+            Err(_) => ChaosConfig::default(),
+        };
+        Self::new(enabled, config)
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn config(&self) -> ChaosConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    pub fn set_config(&self, config: ChaosConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Uniform value in [0, 1)
+    fn roll(&self) -> f64 {
+        let mut z = self
+            .rng
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn hit(&self, rate: f64) -> bool {
+        rate > 0.0 && self.roll() < rate
+    }
+}
+
+fn mark(response: &mut Response, fault: &'static str) {
+    response
+        .headers_mut()
+        .insert(FAULT_HEADER, HeaderValue::from_static(fault));
+}
+
+/// Middleware applying the configured faults to each request
+pub(crate) async fn inject_faults(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let path = req.uri().path().to_string();
+    if path.starts_with("/admin/") {
+        return next.run(req).await;
+    }
+    let Some(rule) = state.chaos.config.read().unwrap().rule_for(&path).cloned() else {
+        return next.run(req).await;
+    };
+    let chaos = &state.chaos;
+
+    let delayed = chaos.hit(rule.delay_rate) && rule.delay_ms > 0;
+    if delayed {
+        info!(event = "fault_injected", fault = "delay", path = %path, delay_ms = rule.delay_ms, "Injected delay");
+        tokio::time::sleep(Duration::from_millis(rule.delay_ms)).await;
+    }
+
+    if chaos.hit(rule.error_rate) {
+        let status = rule
+            .error_status
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+        info!(event = "fault_injected", fault = "error", path = %path, status = status.as_u16(), "Injected error");
+        let mut response = (status, "Injected fault").into_response();
+        mark(&mut response, "error");
+        return response;
+    }
+
+    let mut response = next.run(req).await;
+    if chaos.hit(rule.malformed_rate) {
+        info!(event = "fault_injected", fault = "malformed", path = %path, "Injected malformed response");
+        let (mut parts, body) = response.into_parts();
+        let bytes = to_bytes(body, MAX_MALFORM_BYTES).await.unwrap_or_default();
+        let truncated = bytes.slice(..bytes.len() / 2);
+        parts.headers.remove(header::CONTENT_LENGTH);
+        response = Response::from_parts(parts, Body::from(truncated));
+        mark(&mut response, "malformed");
+    } else if delayed {
+        mark(&mut response, "delay");
+    }
+    response
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rule_matching_and_rates() {
+        let rule = |error_rate| FaultRule { error_rate, ..FaultRule::default() };
+        let config = ChaosConfig {
+            rules: BTreeMap::from([
+                ("*".to_string(), rule(0.1)),
+                ("/protocols/*".to_string(), rule(0.2)),
+                ("/protocols/a/*".to_string(), rule(0.3)),
+                ("/send".to_string(), rule(0.4)),
+            ]),
+        };
+        assert_eq!(config.rule_for("/send").unwrap().error_rate, 0.4);
+        assert_eq!(config.rule_for("/protocols/a/p/1/stats").unwrap().error_rate, 0.3);
+        assert_eq!(config.rule_for("/protocols/b/p/1/stats").unwrap().error_rate, 0.2);
+        assert_eq!(config.rule_for("/report").unwrap().error_rate, 0.1);
+        assert!(ChaosConfig::default().rule_for("/send").is_none());
+
+        let chaos = FaultInjector::default();
+        assert!((0..1000).all(|_| !chaos.hit(0.0) && chaos.hit(1.0)));
+        let hits = (0..10_000).filter(|_| chaos.hit(0.25)).count();
+        assert!((2_000..3_000).contains(&hits), "{hits} hits at rate 0.25");
+
+        assert!(ChaosConfig { rules: BTreeMap::from([("*".into(), rule(1.5))]) }.validate().is_err());
+        let bad_status = FaultRule { error_status: Some(404), ..FaultRule::default() };
+        assert!(bad_status.validate().is_err());
+    }
+}
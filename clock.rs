@@ -0,0 +1,267 @@
+//! Time source for compliance decisions
+//!
+//! Handlers read the time through [`Clock`] instead of the system clock so
+//! report deadlines, retention, and stats timestamps can be driven by tests.
+//!
+//! The system clock reads the wall clock once at startup and advances it with
+//! the monotonic clock, so a host clock stepped backward or forward cannot
+//! stretch or cut short a report interval. [`SkewMonitor`] periodically
+//! compares that timeline with the wall clock. When they drift apart by more
+//! than `CLOCK_SKEW_THRESHOLD_SEC`, a `clock_skew` event is logged. Under
+//! `CLOCK_SKEW_MODE=grace_allow` (the default), report-overdue refusals are
+//! also suspended for `CLOCK_SKEW_GRACE_SEC`. Timestamps that come from
+//! elsewhere, such as client `ts` fields, audit records, and report times
+//! replicated from a primary, may then be on a different timeline. During
+//! the grace period agents are not locked out over a clock fault they did not
+//! cause. A restart re-anchors the gateway to the wall clock.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+use crate::env_parse;
+
+/// Wall-clock time in Unix milliseconds
+fn wall_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_millis() as u64
+}
+
+#[derive(Debug)]
+pub enum Clock {
+    /// Wall-clock time at startup, advanced monotonically
+    System { anchor_ms: u64, started: Instant },
+    /// Fixed time that only moves when advanced, in Unix milliseconds
+    #[cfg_attr(not(any(test, feature = "test-harness")), allow(dead_code))]
+    Manual(AtomicU64),
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::System {
+            anchor_ms: wall_ms(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Clock {
+    /// A manual clock starting at `unix_sec`
+    #[cfg_attr(not(any(test, feature = "test-harness")), allow(dead_code))]
+    pub fn manual(unix_sec: u64) -> Self {
+        Self::Manual(AtomicU64::new(unix_sec * 1_000))
+    }
+
+    fn now_ms(&self) -> u64 {
+        match self {
+            Self::System { anchor_ms, started } => anchor_ms + started.elapsed().as_millis() as u64,
+            Self::Manual(ms) => ms.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Current Unix timestamp in seconds
+    pub fn now(&self) -> u64 {
+        self.now_ms() / 1_000
+    }
+
+    /// Current Unix timestamp with sub-second precision
+    pub fn now_f64(&self) -> f64 {
+        self.now_ms() as f64 / 1_000.0
+    }
+
+    /// How far the wall clock is ahead of this clock, in milliseconds; `None`
+    /// for a manual clock
+    pub fn wall_offset_ms(&self) -> Option<i64> {
+        match self {
+            Self::System { .. } => Some(wall_ms() as i64 - self.now_ms() as i64),
+            Self::Manual(_) => None,
+        }
+    }
+
+    /// Move a manual clock forward; no effect on the system clock
+    #[cfg_attr(not(any(test, feature = "test-harness")), allow(dead_code))]
+    pub fn advance(&self, by: Duration) {
+        if let Self::Manual(ms) = self {
+            ms.fetch_add(by.as_millis() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// What to do once the wall clock jumps away from the gateway's timeline
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkewMode {
+    /// Log, and stop refusing overdue reports for the grace period
+    #[default]
+    GraceAllow,
+    /// Log only
+    Enforce,
+}
+
+impl SkewMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "grace_allow" | "grace-allow" => Some(Self::GraceAllow),
+            "enforce" => Some(Self::Enforce),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SkewConfig {
+    /// Drift from the last observed offset that counts as a jump
+    pub threshold_sec: u64,
+    /// How long overdue reports are tolerated after a jump
+    pub grace_sec: u64,
+    pub mode: SkewMode,
+}
+
+impl Default for SkewConfig {
+    fn default() -> Self {
+        Self {
+            threshold_sec: 30,
+            grace_sec: 300,
+            mode: SkewMode::GraceAllow,
+        }
+    }
+}
+
+impl SkewConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let parse = |name: &str| env_parse::<u64>(name);
+        let mode = match env::var("CLOCK_SKEW_MODE") {
+            Ok(raw) => SkewMode::parse(&raw).unwrap_or_else(|| {
+                warn!(event = "config_invalid", mode = %raw, "Unknown CLOCK_SKEW_MODE, using grace_allow");
+                defaults.mode
+            }),
+            Err(_) => defaults.mode,
+        };
+        Self {
+            threshold_sec: parse("CLOCK_SKEW_THRESHOLD_SEC").unwrap_or(defaults.threshold_sec).max(1),
+            grace_sec: parse("CLOCK_SKEW_GRACE_SEC").unwrap_or(defaults.grace_sec),
+            mode,
+        }
+    }
+}
+
+/// A detected wall-clock jump
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkewEvent {
+    /// Wall clock minus the gateway's timeline, in seconds
+    pub offset_sec: f64,
+    /// Change since the previous observation; negative when the wall clock
+    /// went backward
+    pub jump_sec: f64,
+    /// End of the grace period, if one started
+    pub grace_until: Option<u64>,
+}
+
+/// Watches the wall clock for jumps and drift
+#[derive(Debug, Default)]
+pub struct SkewMonitor {
+    config: SkewConfig,
+    /// Offset as of the last reported jump, in milliseconds
+    baseline_ms: AtomicI64,
+    /// Latest observed offset, in milliseconds
+    offset_ms: AtomicI64,
+    /// Grace period end on the gateway's timeline (0 when none)
+    grace_until: AtomicU64,
+    pub events: AtomicU64,
+}
+
+impl SkewMonitor {
+    pub fn new(config: SkewConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn config(&self) -> &SkewConfig {
+        &self.config
+    }
+
+    /// Compare `clock` with the wall clock; no-op for a manual clock
+    pub fn check(&self, clock: &Clock) -> Option<SkewEvent> {
+        self.observe(clock.wall_offset_ms()?, clock.now())
+    }
+
+    /// Record a wall-clock offset observed at `now`, returning an event when
+    /// it moved past the threshold since the last one
+    pub fn observe(&self, offset_ms: i64, now: u64) -> Option<SkewEvent> {
+        self.offset_ms.store(offset_ms, Ordering::Relaxed);
+        let jump_ms = offset_ms - self.baseline_ms.load(Ordering::Relaxed);
+        if jump_ms.unsigned_abs() <= self.config.threshold_sec * 1_000 {
+            return None;
+        }
+        self.baseline_ms.store(offset_ms, Ordering::Relaxed);
+        self.events.fetch_add(1, Ordering::Relaxed);
+        let grace_until = (self.config.mode == SkewMode::GraceAllow).then(|| now + self.config.grace_sec);
+        if let Some(until) = grace_until {
+            self.grace_until.fetch_max(until, Ordering::Relaxed);
+        }
+        Some(SkewEvent {
+            offset_sec: offset_ms as f64 / 1_000.0,
+            jump_sec: jump_ms as f64 / 1_000.0,
+            grace_until,
+        })
+    }
+
+    /// Latest observed wall-clock offset in seconds
+    pub fn offset_sec(&self) -> f64 {
+        self.offset_ms.load(Ordering::Relaxed) as f64 / 1_000.0
+    }
+
+    /// Whether overdue reports are currently tolerated
+    pub fn in_grace(&self, now: u64) -> bool {
+        now < self.grace_until.load(Ordering::Relaxed)
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skew_detection_and_grace() {
+        let clock = Clock::default();
+        assert!(clock.wall_offset_ms().unwrap().abs() < 1_000);
+        assert!(SkewMonitor::default().check(&Clock::manual(0)).is_none());
+
+        let monitor = SkewMonitor::new(SkewConfig::default());
+        let t0 = 1_700_000_000;
+        // NTP slewing within the threshold goes unreported
+        assert!(monitor.observe(250, t0).is_none());
+        assert!(!monitor.in_grace(t0));
+
+        // Wall clock stepped back an hour
+        let event = monitor.observe(-3_600_000, t0 + 10).unwrap();
+        assert_eq!(event.jump_sec, -3_600.0);
+        assert_eq!(event.grace_until, Some(t0 + 310));
+        assert!(monitor.in_grace(t0 + 309));
+        assert!(!monitor.in_grace(t0 + 310));
+        // Reported once, then measured from the new offset
+        assert!(monitor.observe(-3_590_000, t0 + 20).is_none());
+        assert_eq!(monitor.offset_sec(), -3_590.0);
+        assert_eq!(monitor.events.load(Ordering::Relaxed), 1);
+
+        let enforce = SkewMonitor::new(SkewConfig {
+            mode: SkewMode::Enforce,
+            ..SkewConfig::default()
+        });
+        assert_eq!(enforce.observe(120_000, t0).unwrap().grace_until, None);
+        assert!(!enforce.in_grace(t0));
+    }
+}
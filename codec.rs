@@ -0,0 +1,497 @@
+//! Content-type negotiation for request and response bodies
+//!
+//! Every endpoint accepts `application/json`, `application/cbor`, and
+//! `application/msgpack` request bodies via the [`Payload`] extractor, which
+//! decodes straight into the existing serde types. A body that does not
+//! decode is refused with a [`BodyError`] naming the field at fault and the
+//! type expected there, logged under the request's `X-Request-Id`.
+//!
+//! Responses are produced as JSON by the handlers; [`negotiate_response`]
+//! re-encodes them as CBOR or MessagePack when the client's `Accept` header
+//! asks for it. Gateway responses are small, so the transcode is cheap
+//! compared to decoding large request bodies twice.
+//!
+//! Independently of the format, request bodies may be sent with
+//! `Content-Encoding: gzip` or `zstd`, and responses are compressed when the
+//! client sends `Accept-Encoding`; see [`with_content_encoding`]. The body
+//! limit (`MAX_BODY_BYTES`) applies to the decompressed size, so a small
+//! compressed payload cannot expand without bound.
+
+use axum::{
+    async_trait,
+    body::{to_bytes, Body, Bytes},
+    extract::{DefaultBodyLimit, FromRequest, Request},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Router,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+use tower_http::{compression::CompressionLayer, decompression::RequestDecompressionLayer};
+use tracing::warn;
+
+use crate::{env_parse, error::GatewayError, signing};
+
+/// Largest response body the transcoder will buffer
+const MAX_TRANSCODE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Request header naming the request in logs
+const REQUEST_ID: &str = "x-request-id";
+
+/// Default limit on a request body after decompression
+pub const DEFAULT_MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Supported wire formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFormat {
+    Json,
+    Cbor,
+    MsgPack,
+}
+
+impl BodyFormat {
+    pub fn mime(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Cbor => "application/cbor",
+            Self::MsgPack => "application/msgpack",
+        }
+    }
+
+    /// Match a single media type, ignoring parameters such as `charset`
+    fn from_mime(mime: &str) -> Option<Self> {
+        let essence = mime
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        match essence.as_str() {
+            "application/json" => Some(Self::Json),
+            "application/cbor" => Some(Self::Cbor),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Self::MsgPack)
+            }
+            _ if essence.starts_with("application/") && essence.ends_with("+json") => {
+                Some(Self::Json)
+            }
+            _ => None,
+        }
+    }
+
+    /// Format of a request body, from its `Content-Type`
+    pub fn from_content_type(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(Self::from_mime)
+    }
+
+    /// Preferred response format from `Accept`: the supported entry with the
+    /// highest `q`, the first of them on a tie, never one with `q=0`
+    pub fn from_accept(headers: &HeaderMap) -> Self {
+        let Some(accept) = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()) else {
+            return Self::Json;
+        };
+        let mut best: Option<(f64, Self)> = None;
+        for item in accept.split(',') {
+            let Some(format) = Self::from_mime(item) else {
+                continue;
+            };
+            let q = item
+                .split(';')
+                .skip(1)
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f64>().ok());
+            match q {
+                Some(q) if q > 0.0 && best.is_none_or(|(top, _)| q > top) => best = Some((q, format)),
+                _ => {}
+            }
+        }
+        best.map_or(Self::Json, |(_, format)| format)
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            Self::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Self::Cbor => ciborium::from_reader(bytes).map_err(|e| e.to_string()),
+            Self::MsgPack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Decode `bytes`, locating a failure at the field that caused it
+    ///
+    /// Tracking the path costs allocations, so it only happens on a second
+    /// pass over a body that already failed.
+    pub fn decode_traced<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, BodyError> {
+        self.decode(bytes)
+            .map_err(|detail| self.locate::<T>(bytes).unwrap_or_else(|| BodyError::new(None, &detail)))
+    }
+
+    /// The failure of decoding `bytes` as `T`, with its path
+    ///
+    /// CBOR has no public streaming deserializer to track, so a CBOR body is
+    /// decoded to a generic value first and the value is tracked instead.
+    fn locate<T: DeserializeOwned>(self, bytes: &[u8]) -> Option<BodyError> {
+        match self {
+            Self::Json => {
+                let mut de = serde_json::Deserializer::from_slice(bytes);
+                match serde_path_to_error::deserialize::<_, T>(&mut de) {
+                    Ok(_) => de.end().err().map(|e| BodyError::new(None, &e)),
+                    Err(e) => Some(BodyError::at(e)),
+                }
+            }
+            Self::Cbor => {
+                let value = ciborium::from_reader::<serde_json::Value, _>(bytes).ok()?;
+                serde_path_to_error::deserialize::<_, T>(value).err().map(BodyError::at)
+            }
+            Self::MsgPack => {
+                let mut de = rmp_serde::Deserializer::from_read_ref(bytes);
+                serde_path_to_error::deserialize::<_, T>(&mut de).err().map(BodyError::at)
+            }
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Self::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(value, &mut buf).map_err(|e| e.to_string())?;
+                Ok(buf)
+            }
+            Self::MsgPack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Why a request body did not decode into the route's type
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BodyError {
+    /// Path to the offending field, like `recipients[1]` or `report.coverage`;
+    /// absent when the body as a whole is malformed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Type or values the field should have held
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    pub detail: String,
+    /// `X-Request-Id` of the request, or one generated for it
+    pub request_id: String,
+}
+
+impl BodyError {
+    fn new(field: Option<String>, error: &impl fmt::Display) -> Self {
+        let detail = error.to_string();
+        // serde's messages read "invalid type: string \"x\", expected u64"
+        let expected = detail.split_once(", expected ").map(|(_, rest)| {
+            rest.split_once(" at line ").map_or(rest, |(expected, _)| expected).to_string()
+        });
+        Self {
+            field,
+            expected,
+            detail,
+            request_id: String::new(),
+        }
+    }
+
+    fn at<E: fmt::Display>(e: serde_path_to_error::Error<E>) -> Self {
+        let path = e.path().to_string();
+        let parent = (path != ".").then_some(path);
+        let message = e.inner().to_string();
+        // A missing field is reported at its parent; name the field itself
+        let field = match message.strip_prefix("missing field `").and_then(|m| m.split('`').next()) {
+            Some(missing) => Some(parent.map_or(missing.to_string(), |p| format!("{p}.{missing}"))),
+            None => parent,
+        };
+        Self::new(field, &message)
+    }
+}
+
+/// Request body extractor accepting JSON, CBOR, or MessagePack
+///
+/// A body that does not decode is refused with 400 `invalid_request`, naming
+/// the offending field and the type expected there, and logged as a
+/// `request_body_invalid` audit event under the request's id.
+#[derive(Debug, Clone)]
+pub struct Payload<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for Payload<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = GatewayError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(format) = BodyFormat::from_content_type(req.headers()) else {
+            return Err(GatewayError::UnsupportedMediaType);
+        };
+        let uri = req.uri().clone();
+        let given_id = req.headers().get(REQUEST_ID).cloned();
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| GatewayError::BodyRejected {
+                status: e.status(),
+                message: e.body_text(),
+            })?;
+        format.decode_traced(&bytes).map(Payload).map_err(|mut e| {
+            e.request_id = request_id(given_id.as_ref());
+            warn!(
+                request_id = %e.request_id,
+                route = uri.path(),
+                format = format.mime(),
+                field = e.field.as_deref().unwrap_or(""),
+                expected = e.expected.as_deref().unwrap_or(""),
+                detail = %e.detail,
+                event = "request_body_invalid",
+                "Request body did not decode"
+            );
+            GatewayError::BodyInvalid(Box::new(e))
+        })
+    }
+}
+
+/// [`Payload`] for an endpoint whose body may be left out
+///
+/// A request with neither a `Content-Type` nor a body yields `None`. Any
+/// body that is sent must decode; a malformed one is refused as by
+/// [`Payload`], never read as no body at all.
+#[derive(Debug, Clone)]
+pub struct OptionalPayload<T>(pub Option<T>);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for OptionalPayload<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = GatewayError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if req.headers().contains_key(header::CONTENT_TYPE) {
+            return Payload::from_request(req, state).await.map(|Payload(body)| Self(Some(body)));
+        }
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| GatewayError::BodyRejected {
+                status: e.status(),
+                message: e.body_text(),
+            })?;
+        if bytes.is_empty() {
+            Ok(Self(None))
+        } else {
+            Err(GatewayError::UnsupportedMediaType)
+        }
+    }
+}
+
+/// The request's `X-Request-Id`, or a fresh random id when it has none
+fn request_id(given: Option<&HeaderValue>) -> String {
+    let given = given.and_then(|v| v.to_str().ok()).map(str::trim);
+    match given.filter(|id| !id.is_empty() && id.len() <= 128) {
+        Some(id) => id.to_string(),
+        None => signing::random_hex::<16>(),
+    }
+}
+
+/// Extractor that also reports how long `E` took to read and decode the body
+#[derive(Debug, Clone)]
+pub struct Timed<E>(pub E, pub Duration);
+
+#[async_trait]
+impl<S, E> FromRequest<S> for Timed<E>
+where
+    S: Send + Sync,
+    E: FromRequest<S>,
+{
+    type Rejection = E::Rejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let started = Instant::now();
+        let extracted = E::from_request(req, state).await?;
+        Ok(Timed(extracted, started.elapsed()))
+    }
+}
+
+/// Decompressed request body limit from `MAX_BODY_BYTES`
+pub fn max_body_bytes_from_env() -> usize {
+    env_parse("MAX_BODY_BYTES").unwrap_or(DEFAULT_MAX_BODY_BYTES)
+}
+
+/// Wrap `router` with gzip/zstd request decompression, a decompressed body
+/// limit, and response compression negotiated from `Accept-Encoding`
+///
+/// Bodies over the limit are refused with 413, unsupported encodings with 415.
+pub fn with_content_encoding<S: Clone + Send + Sync + 'static>(
+    router: Router<S>,
+    max_body_bytes: usize,
+) -> Router<S> {
+    router
+        .layer(DefaultBodyLimit::max(max_body_bytes))
+        .layer(RequestDecompressionLayer::new())
+        .layer(CompressionLayer::new())
+}
+
+/// Middleware re-encoding JSON responses per the request's `Accept` header
+pub async fn negotiate_response(req: Request, next: Next) -> Response {
+    let format = BodyFormat::from_accept(req.headers());
+    let response = next.run(req).await;
+    if format == BodyFormat::Json
+        || BodyFormat::from_content_type(response.headers()) != Some(BodyFormat::Json)
+    {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let transcoded = match to_bytes(body, MAX_TRANSCODE_BYTES).await {
+        Ok(bytes) => BodyFormat::Json
+            .decode::<serde_json::Value>(&bytes)
+            .and_then(|value| format.encode(&value)),
+        Err(e) => Err(e.to_string()),
+    };
+    match transcoded {
+        Ok(encoded) => {
+            parts.headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(format.mime()),
+            );
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(encoded))
+        }
+        Err(e) => GatewayError::Encoding(e).into_response(),
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bench::report_batch, EnglishReport};
+
+    #[test]
+    fn test_roundtrip_all_formats() {
+        let batch = report_batch(3);
+        for format in [BodyFormat::Json, BodyFormat::Cbor, BodyFormat::MsgPack] {
+            let bytes = format.encode(&batch).unwrap();
+            let decoded: Vec<EnglishReport> = format.decode(&bytes).unwrap();
+            assert_eq!(decoded.len(), 3);
+            assert_eq!(decoded[2].agent_id, "agent-0002");
+            assert_eq!(decoded[1].message_ids, batch[1].message_ids);
+        }
+    }
+
+    #[test]
+    fn test_accept_negotiation() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(BodyFormat::from_accept(&headers), BodyFormat::Json);
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("text/html, application/msgpack;q=0.9"),
+        );
+        assert_eq!(BodyFormat::from_accept(&headers), BodyFormat::MsgPack);
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/json;q=0.5, application/cbor, application/msgpack;q=0.8"),
+        );
+        assert_eq!(BodyFormat::from_accept(&headers), BodyFormat::Cbor);
+        headers.insert(header::ACCEPT, HeaderValue::from_static("application/cbor;q=0, application/msgpack;q=0.1"));
+        assert_eq!(BodyFormat::from_accept(&headers), BodyFormat::MsgPack);
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json; charset=utf-8"),
+        );
+        assert_eq!(
+            BodyFormat::from_content_type(&headers),
+            Some(BodyFormat::Json)
+        );
+    }
+
+    #[test]
+    fn test_decode_failure_names_field_and_type() {
+        let mut batch = serde_json::to_value(report_batch(2)).unwrap();
+        batch[1]["coverage"] = "high".into();
+        for format in [BodyFormat::Json, BodyFormat::Cbor, BodyFormat::MsgPack] {
+            let bytes = format.encode(&batch).unwrap();
+            let e = format.decode_traced::<Vec<EnglishReport>>(&bytes).unwrap_err();
+            assert_eq!(e.field.as_deref(), Some("[1].coverage"), "{}", format.mime());
+            if format != BodyFormat::MsgPack {
+                // MessagePack reports the marker it found, not the type wanted
+                assert_eq!(e.expected.as_deref(), Some("f64"), "{}", format.mime());
+            }
+        }
+
+        batch[1].as_object_mut().unwrap().remove("english_summary");
+        batch[1]["coverage"] = 0.98.into();
+        let e = BodyFormat::Json.decode_traced::<Vec<EnglishReport>>(&serde_json::to_vec(&batch).unwrap()).unwrap_err();
+        assert_eq!(e.field.as_deref(), Some("[1].english_summary"));
+        let e = BodyFormat::Json.decode_traced::<Vec<EnglishReport>>(b"[{\"agent_id\"").unwrap_err();
+        assert_eq!(e.expected, None);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_body_answered_with_request_id() {
+        let gw = crate::testing::TestGateway::new();
+        let req = Request::post("/report")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(REQUEST_ID, "req-42")
+            .body(Body::from(r#"{"agent_id":"a","coverage":[]}"#))
+            .unwrap();
+        let resp = tower::ServiceExt::oneshot(gw.router(), req).await.unwrap();
+        assert_eq!(resp.status(), axum::http::StatusCode::BAD_REQUEST);
+        let bytes = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["code"], "invalid_request");
+        assert_eq!(body["body_error"]["request_id"], "req-42");
+        assert!(body["body_error"]["field"].is_string(), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_compressed_body_limits() {
+        use axum::{
+            http::StatusCode,
+            routing::{get, post},
+        };
+        use tower::ServiceExt;
+
+        let router = |limit| {
+            with_content_encoding(
+                Router::new()
+                    .route("/zeros", get(|| async { vec![0u8; 64 * 1024] }))
+                    .route("/len", post(|body: Bytes| async move { body.len().to_string() })),
+                limit,
+            )
+        };
+        let upload = |encoding: &'static str, body: Bytes| {
+            Request::post("/len")
+                .header(header::CONTENT_ENCODING, encoding)
+                .body(Body::from(body))
+                .unwrap()
+        };
+        // 64 KiB of zeros gzips to a few hundred bytes
+        let resp = router(1024)
+            .oneshot(Request::get("/zeros").header(header::ACCEPT_ENCODING, "gzip").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()[header::CONTENT_ENCODING], "gzip");
+        let bomb = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert!(bomb.len() < 1024, "{} compressed bytes", bomb.len());
+
+        let resp = router(1024).oneshot(upload("gzip", bomb.clone())).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE, "the limit applies after decompression");
+        let resp = router(128 * 1024).oneshot(upload("gzip", bomb)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(to_bytes(resp.into_body(), usize::MAX).await.unwrap(), "65536");
+
+        let resp = router(1024).oneshot(upload("br", Bytes::from_static(b"{}"))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+}
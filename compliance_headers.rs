@@ -0,0 +1,96 @@
+//! Compliance state on `/send` responses
+//!
+//! Every `/send` response, accepted or refused, carries the sender's
+//! standing so agents and proxies can schedule reports and back off without
+//! polling `/status`:
+//!
+//! - `X-Report-Deadline`: Unix second by which the send's protocol must be
+//!   reported on, or for a send without a protocol the earliest deadline of
+//!   the sender's protocols; a time in the past means novel sends under it
+//!   are refused as overdue. Omitted when there is no protocol to report on.
+//! - `X-Novel-Quota-Remaining`: audit events the sender's tightest agent or
+//!   tenant events quota still allows this window, each send using at least
+//!   one. Omitted when events are not limited.
+//! - `X-Violations`: violations on the sender's record
+//!
+//! `X-Policy-Version`, the policy that decided the send, is on every response
+//! already. The headers reflect the state after the send was decided.
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::{quota::Resource, AppState};
+
+const REPORT_DEADLINE: HeaderName = HeaderName::from_static("x-report-deadline");
+const NOVEL_QUOTA_REMAINING: HeaderName = HeaderName::from_static("x-novel-quota-remaining");
+const VIOLATIONS: HeaderName = HeaderName::from_static("x-violations");
+
+/// Headers describing the standing of `agent_id` after a send declaring
+/// `protocol`, if any
+pub fn for_send(state: &AppState, agent_id: &str, protocol: Option<&str>) -> HeaderMap {
+    let policy = state.policy.current();
+    let now = state.clock.now();
+    let (deadline, violations) = {
+        let st = state.inner.read().unwrap();
+        let base = st.reputation(&policy.policy, agent_id).report_interval_sec;
+        let deadline_of = |key: &str| {
+            let report_key = format!("{agent_id}::{key}");
+            let last = st.last_report_ts.get(&report_key).copied().unwrap_or(0);
+            last + st.report_interval(&policy.policy, &report_key, base, now)
+        };
+        let registered = st.protocols.get(agent_id);
+        let deadline = match protocol {
+            Some(key) => registered.is_some_and(|m| m.contains_key(key)).then(|| deadline_of(key)),
+            None => registered.and_then(|m| m.keys().map(|key| deadline_of(key)).min()),
+        };
+        (deadline, st.violations.get(agent_id).copied().unwrap_or(0))
+    };
+    let mut headers = HeaderMap::new();
+    let values = [
+        (REPORT_DEADLINE, deadline),
+        (NOVEL_QUOTA_REMAINING, state.quota.remaining(agent_id, Resource::Events)),
+        (VIOLATIONS, Some(u64::from(violations))),
+    ];
+    for (name, value) in values {
+        if let Some(value) = value {
+            headers.insert(name, HeaderValue::from(value));
+        }
+    }
+    headers
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{AgentFixture, ProtocolFixture, SendFixture, TestGateway};
+    use axum::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_send_responses_carry_standing() {
+        let gw = TestGateway::new();
+        let coord = ProtocolFixture::new("coord", "1.0").build();
+        let rogue = ProtocolFixture::new("rogue", "1.0").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(coord.clone()).reported()).await;
+
+        let sent = gw.send(&SendFixture::novel("a", "b", &coord, "SHP|eta=7f").build()).await;
+        assert_eq!(sent.status, StatusCode::OK, "{:?}", sent.body);
+        let deadline: u64 = sent.headers["x-report-deadline"].to_str().unwrap().parse().unwrap();
+        assert!(deadline > gw.state().clock.now());
+        assert_eq!(sent.headers["x-violations"], "0");
+        assert!(sent.headers.contains_key("x-policy-version"));
+        assert!(!sent.headers.contains_key("x-novel-quota-remaining"), "events are not limited");
+
+        // Novel content without a protocol is a violation
+        let mut undeclared = SendFixture::novel("a", "b", &coord, "SHP|eta=7f").build();
+        undeclared.protocol = None;
+        let refused = gw.send(&undeclared).await;
+        assert_eq!(refused.status, StatusCode::FORBIDDEN);
+        assert_eq!(refused.headers["x-violations"], "1");
+        assert_eq!(refused.headers["x-report-deadline"], deadline.to_string().as_str(), "earliest of a's protocols");
+
+        let rogue = gw.send(&SendFixture::novel("a", "b", &rogue, "SHP|eta=7f").build()).await;
+        assert!(!rogue.headers.contains_key("x-report-deadline"), "rogue is not registered");
+    }
+}
@@ -0,0 +1,358 @@
+//! Conformance suite for [`StateStore`] implementations
+//!
+//! Every store must pass [`check`] on an empty instance; durable stores must
+//! also pass [`check_reopen`], which writes through one instance and reads
+//! through a fresh one. A new store gets a test at the bottom of this file
+//! running both. The checks pin down what the gateway relies on:
+//!
+//! - registrations are keyed by agent, name, and version, and listed by agent
+//!   id then protocol key
+//! - report clocks and violation counts are absolute; a count of 0 clears it
+//! - removing an agent drops its registrations, report clocks, violations,
+//!   and reports, but keeps its audit events
+//! - audit events are idempotent by sequence number and read back ascending
+//! - reports are read back per agent in the order they were stored
+//! - outbox entries are keyed by id and stay until settled, whatever agent
+//!   they concern
+//! - policy versions are keyed by version and keep their first load time
+//! - a batch applies its records in order, all of them or none
+
+use serde_json::{json, Map};
+
+use crate::{
+    audit::AuditEvent,
+    outbox::OutboxEntry,
+    policy::{Policy, PolicySnapshot},
+    store::{Record, Registration, StateStore, StoredReport},
+    testing::ProtocolFixture,
+};
+
+fn registration(agent_id: &str, name: &str, version: &str, registered_at: u64) -> Registration {
+    Registration {
+        agent_id: agent_id.to_string(),
+        descriptor: ProtocolFixture::new(name, version).build(),
+        registered_at,
+        recertified_at: None,
+    }
+}
+
+fn event(seq: u64, name: &str, agent_id: &str) -> AuditEvent {
+    let fields: Map<_, _> = json!({"agent_id": agent_id}).as_object().cloned().unwrap();
+    AuditEvent {
+        seq,
+        ts: seq as f64,
+        level: "INFO".to_string(),
+        event: name.to_string(),
+        schema_version: crate::audit_schema::version_of(name),
+        policy_version: Some("v1".to_string()),
+        fields,
+        labels: Vec::new(),
+    }
+}
+
+fn report(agent_id: &str, accepted_at: u64, summary: &str) -> StoredReport {
+    StoredReport {
+        agent_id: agent_id.to_string(),
+        protocol: "coord:1.0".to_string(),
+        accepted_at,
+        english_summary: summary.to_string(),
+        redacted_summary: None,
+        coverage: 0.9,
+        message_ids: vec!["m1".to_string()],
+        thread_id: None,
+        quality: None,
+    }
+}
+
+fn outbox_entry(id: &str, attempts: u32) -> OutboxEntry {
+    OutboxEntry {
+        id: id.to_string(),
+        attempts,
+        ..OutboxEntry::new("park_callback", "https://hooks.example/parked", json!({"agent_id": "a"}), 100)
+    }
+}
+
+fn policy(version: &str, loaded_at: u64) -> PolicySnapshot {
+    PolicySnapshot {
+        version: version.to_string(),
+        version_id: 0,
+        loaded_at,
+        policy: Policy::default(),
+    }
+}
+
+fn versions(policies: &[PolicySnapshot]) -> Vec<(String, u64)> {
+    policies.iter().map(|p| (p.version.clone(), p.loaded_at)).collect()
+}
+
+fn keys(registrations: &[Registration]) -> Vec<(String, String, u64)> {
+    registrations
+        .iter()
+        .map(|r| {
+            let key = format!("{}:{}", r.descriptor.name, r.descriptor.version);
+            (r.agent_id.clone(), key, r.registered_at)
+        })
+        .collect()
+}
+
+fn seqs(events: &[AuditEvent]) -> Vec<u64> {
+    events.iter().map(|e| e.seq).collect()
+}
+
+/// Run every check against an empty store
+pub async fn check(store: &dyn StateStore) {
+    assert!(store.registrations().await.unwrap().is_empty(), "a new store is empty");
+    assert!(store.report_clocks().await.unwrap().is_empty());
+    assert!(store.violations().await.unwrap().is_empty());
+    assert_eq!(store.last_audit_seq().await.unwrap(), 0);
+    assert!(store.audit(0, 10).await.unwrap().is_empty());
+    assert!(store.reports("a").await.unwrap().is_empty());
+    assert!(store.outbox().await.unwrap().is_empty());
+    assert!(store.policies().await.unwrap().is_empty());
+
+    // Registrations
+    store.put_registration(&registration("b", "coord", "1.0", 10)).await.unwrap();
+    store.put_registration(&registration("a", "coord", "2.0", 11)).await.unwrap();
+    store.put_registration(&registration("a", "coord", "1.0", 12)).await.unwrap();
+    store.put_registration(&registration("a", "coord", "1.0", 13)).await.unwrap();
+    assert_eq!(
+        keys(&store.registrations().await.unwrap()),
+        [
+            ("a".into(), "coord:1.0".into(), 13),
+            ("a".into(), "coord:2.0".into(), 11),
+            ("b".into(), "coord:1.0".into(), 10),
+        ],
+        "registering again replaces; listed by agent then protocol"
+    );
+
+    // Report clocks and violations
+    store.set_report_clock("a::coord:1.0", 100).await.unwrap();
+    store.set_report_clock("a::coord:1.0", 200).await.unwrap();
+    store.set_report_clock("b::coord:1.0", 150).await.unwrap();
+    let clocks = store.report_clocks().await.unwrap();
+    assert_eq!(clocks.get("a::coord:1.0"), Some(&200), "report clocks are absolute");
+    assert_eq!(clocks.len(), 2);
+    store.set_violations("a", 2).await.unwrap();
+    store.set_violations("a", 3).await.unwrap();
+    store.set_violations("b", 1).await.unwrap();
+    store.set_violations("b", 0).await.unwrap();
+    let violations = store.violations().await.unwrap();
+    assert_eq!(violations.get("a"), Some(&3));
+    assert!(!violations.contains_key("b"), "a count of 0 clears it");
+
+    // Audit
+    for seq in 1..=3 {
+        store.append_audit(&event(seq, "msg_accepted", "a")).await.unwrap();
+    }
+    store.append_audit(&event(2, "msg_rejected", "a")).await.unwrap();
+    let page = store.audit(2, 10).await.unwrap();
+    assert_eq!(seqs(&page), [2, 3]);
+    assert_eq!(page[0].event, "msg_accepted", "a stored sequence number is not overwritten");
+    assert_eq!(page[0].fields["agent_id"], "a");
+    assert_eq!(page[0].policy_version.as_deref(), Some("v1"));
+    assert_eq!(seqs(&store.audit(1, 1).await.unwrap()), [1], "pages honour the limit");
+    assert_eq!(store.last_audit_seq().await.unwrap(), 3);
+
+    // Reports
+    store.put_report(&report("a", 100, "first")).await.unwrap();
+    store.put_report(&report("b", 150, "other")).await.unwrap();
+    store.put_report(&report("a", 200, "second")).await.unwrap();
+    let reports = store.reports("a").await.unwrap();
+    assert_eq!(reports, [report("a", 100, "first"), report("a", 200, "second")]);
+
+    // Outbox
+    store.put_outbox(&outbox_entry("o2", 0)).await.unwrap();
+    store.put_outbox(&outbox_entry("o1", 0)).await.unwrap();
+    store.put_outbox(&outbox_entry("o1", 2)).await.unwrap();
+    store.settle_outbox("o2").await.unwrap();
+    store.settle_outbox("o3").await.unwrap();
+    assert_eq!(store.outbox().await.unwrap(), [outbox_entry("o1", 2)], "storing again replaces; settling drops");
+
+    // Batches
+    let violation = |count| Record::Violations { agent_id: "b".to_string(), count };
+    store
+        .put_batch(&[violation(5), Record::Outbox(Box::new(outbox_entry("o4", 0)))])
+        .await
+        .unwrap();
+    assert_eq!(store.violations().await.unwrap().get("b"), Some(&5));
+    assert_eq!(store.outbox().await.unwrap(), [outbox_entry("o1", 2), outbox_entry("o4", 0)]);
+    store
+        .put_batch(&[violation(0), Record::OutboxSettled { id: "o4".to_string() }])
+        .await
+        .unwrap();
+    assert!(!store.violations().await.unwrap().contains_key("b"));
+    assert_eq!(store.outbox().await.unwrap(), [outbox_entry("o1", 2)]);
+
+    // Policies
+    store.put_policy(&policy("00000000000000bb", 20)).await.unwrap();
+    store.put_policy(&policy("00000000000000aa", 10)).await.unwrap();
+    store.put_policy(&policy("00000000000000bb", 30)).await.unwrap();
+    assert_eq!(
+        versions(&store.policies().await.unwrap()),
+        [("00000000000000aa".into(), 10), ("00000000000000bb".into(), 20)],
+        "a version keeps its first load time; listed by version"
+    );
+
+    // Removing an agent
+    store.remove_agent("a").await.unwrap();
+    assert_eq!(keys(&store.registrations().await.unwrap()), [("b".into(), "coord:1.0".into(), 10)]);
+    assert_eq!(store.report_clocks().await.unwrap().keys().collect::<Vec<_>>(), ["b::coord:1.0"]);
+    assert!(store.violations().await.unwrap().is_empty());
+    assert!(store.reports("a").await.unwrap().is_empty());
+    assert_eq!(store.reports("b").await.unwrap().len(), 1);
+    assert_eq!(store.last_audit_seq().await.unwrap(), 3, "audit events outlive their agent");
+    assert_eq!(store.outbox().await.unwrap().len(), 1, "deliveries outlive their agent");
+}
+
+/// Write through one instance and read through another; `open` must return
+/// instances backed by the same storage
+pub async fn check_reopen<S: StateStore>(open: impl Fn() -> S) {
+    let store = open();
+    store.put_registration(&registration("a", "coord", "1.0", 10)).await.unwrap();
+    store.set_report_clock("a::coord:1.0", 100).await.unwrap();
+    store.set_violations("a", 1).await.unwrap();
+    store.append_audit(&event(7, "report_accepted", "a")).await.unwrap();
+    store.put_report(&report("a", 100, "kept")).await.unwrap();
+    store.put_outbox(&outbox_entry("o1", 0)).await.unwrap();
+    store.put_outbox(&outbox_entry("o2", 0)).await.unwrap();
+    store.settle_outbox("o1").await.unwrap();
+    store.put_policy(&policy("00000000000000aa", 10)).await.unwrap();
+    store
+        .put_batch(&[
+            Record::Violations { agent_id: "b".to_string(), count: 2 },
+            Record::Outbox(Box::new(outbox_entry("o3", 0))),
+        ])
+        .await
+        .unwrap();
+    drop(store);
+
+    let store = open();
+    assert_eq!(keys(&store.registrations().await.unwrap()), [("a".into(), "coord:1.0".into(), 10)]);
+    assert_eq!(store.report_clocks().await.unwrap().get("a::coord:1.0"), Some(&100));
+    assert_eq!(store.violations().await.unwrap().get("a"), Some(&1));
+    assert_eq!(store.violations().await.unwrap().get("b"), Some(&2));
+    assert_eq!(store.last_audit_seq().await.unwrap(), 7);
+    assert_eq!(store.reports("a").await.unwrap(), [report("a", 100, "kept")]);
+    assert_eq!(store.outbox().await.unwrap(), [outbox_entry("o2", 0), outbox_entry("o3", 0)]);
+    assert_eq!(versions(&store.policies().await.unwrap()), [("00000000000000aa".into(), 10)]);
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{FileStore, MemoryStore, RemoteStore};
+    use axum::{extract::State, routing::post, Json, Router};
+    use std::{
+        io::Write,
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    };
+
+    fn temp_path(name: &str) -> PathBuf {
+        let suffix = crate::signing::random_hex::<8>();
+        std::env::temp_dir().join(format!("gateway-{name}-{suffix}.jsonl"))
+    }
+
+    /// Reference server for [`RemoteStore`]: an append-only log of records
+    async fn serve_records() -> String {
+        type Log = Arc<Mutex<Vec<Record>>>;
+        async fn append(State(log): State<Log>, Json(record): Json<Record>) {
+            log.lock().unwrap().push(record);
+        }
+        async fn list(State(log): State<Log>) -> Json<Vec<Record>> {
+            Json(log.lock().unwrap().clone())
+        }
+        let app = Router::new()
+            .route("/records", post(append).get(list))
+            .with_state(Log::default());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        check(&MemoryStore::default()).await;
+    }
+
+    #[tokio::test]
+    async fn test_file_store() {
+        let path = temp_path("conformance");
+        check(&FileStore::open(path.to_str().unwrap()).unwrap()).await;
+        std::fs::remove_file(&path).unwrap();
+
+        let path = temp_path("reopen");
+        check_reopen(|| FileStore::open(path.to_str().unwrap()).unwrap()).await;
+        // A torn last line is dropped, and the next record starts cleanly
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(br#"{"op":"violations","agent_id":"#)
+            .unwrap();
+        let store = FileStore::open(path.to_str().unwrap()).unwrap();
+        store.set_violations("b", 4).await.unwrap();
+        let store = FileStore::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(store.violations().await.unwrap().get("b"), Some(&4));
+        // A torn batch is dropped whole
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(br#"{"op":"batch","records":[{"op":"violations","agent_id":"b","count":9},{"op":"outbox"#)
+            .unwrap();
+        let store = FileStore::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(store.violations().await.unwrap().get("b"), Some(&4));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_store_migrates_legacy_ids() {
+        let path = temp_path("legacy");
+        let mut legacy = serde_json::to_value(Record::Registration(Box::new(registration("a", "coord", "1.0", 10)))).unwrap();
+        legacy["agent_id"] = json!("ops agent");
+        legacy["descriptor"]["name"] = json!("coord v2");
+        let lines = [
+            legacy,
+            json!({"op": "report_clock", "report_key": "ops agent::coord v2:1.0", "ts": 20}),
+            json!({"op": "violations", "agent_id": "ops agent", "count": 2}),
+        ];
+        let text: String = lines.iter().map(|l| format!("{l}\n")).collect();
+        std::fs::write(&path, text).unwrap();
+
+        let store = FileStore::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(keys(&store.registrations().await.unwrap()), [("ops-agent".into(), "coord-v2:1.0".into(), 10)]);
+        assert_eq!(store.report_clocks().await.unwrap().keys().collect::<Vec<_>>(), ["ops-agent::coord-v2:1.0"]);
+        assert_eq!(store.violations().await.unwrap().get("ops-agent"), Some(&2));
+        // Removing the migrated id drops the legacy records on every replay
+        store.remove_agent("ops-agent").await.unwrap();
+        let store = FileStore::open(path.to_str().unwrap()).unwrap();
+        assert!(store.registrations().await.unwrap().is_empty());
+        assert!(store.report_clocks().await.unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+
+        // Two legacy ids that migrate to one are refused, not merged
+        let path = temp_path("collision");
+        let lines = [
+            json!({"op": "violations", "agent_id": "ops agent", "count": 2}),
+            json!({"op": "violations", "agent_id": "ops:agent", "count": 1}),
+        ];
+        let text: String = lines.iter().map(|l| format!("{l}\n")).collect();
+        std::fs::write(&path, text).unwrap();
+        let error = FileStore::open(path.to_str().unwrap()).unwrap_err();
+        assert!(error.contains(r#"["ops agent", "ops:agent"] all load as "ops-agent""#), "{error}");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_remote_store() {
+        check(&RemoteStore::new(&serve_records().await, None)).await;
+        let url = serve_records().await;
+        check_reopen(|| RemoteStore::new(&url, None)).await;
+    }
+}
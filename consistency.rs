@@ -0,0 +1,321 @@
+//! Summary-vs-traffic consistency scoring
+//!
+//! The gateway keeps lightweight features of every accepted novel-language
+//! message (acceptance time and tokens). When a report arrives, its claims are
+//! checked against that traffic:
+//!
+//! - **count**: reported message count vs. messages observed in the window
+//! - **timing**: share of traffic since the previous report that the window covers
+//! - **codebook**: share of codebook glosses for the observed tokens that the
+//!   summary mentions (only when the protocol registered a codebook)
+//! - **sampled**: share of a random sample of the report's `message_ids`
+//!   whose decoded content the summary mentions (only when the protocol
+//!   registered a codebook and the sends carried those ids as `message_id`)
+//!
+//! The consistency score is the mean of the available components. Reports
+//! scoring below the policy's `min_consistency` go to the review queue instead
+//! of being accepted automatically.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+use crate::{signing, translation::content_words};
+
+/// Features of one accepted novel-language message
+#[derive(Debug, Clone)]
+pub struct TrafficSample {
+    /// Unix timestamp at which the gateway accepted the message
+    pub ts: f64,
+    pub tokens: Vec<String>,
+    /// Conversation the message belonged to
+    pub thread_id: Option<String>,
+    /// Sender's own id for the message, as listed in its reports
+    pub message_id: Option<String>,
+}
+
+impl TrafficSample {
+    pub fn new(content: &str, ts: f64) -> Self {
+        Self {
+            ts,
+            tokens: content
+                .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .filter(|t| !t.is_empty())
+                .map(str::to_string)
+                .collect(),
+            thread_id: None,
+            message_id: None,
+        }
+    }
+
+    pub fn in_thread(mut self, thread_id: Option<&str>) -> Self {
+        self.thread_id = thread_id.map(str::to_string);
+        self
+    }
+
+    pub fn identified(mut self, message_id: Option<&str>) -> Self {
+        self.message_id = message_id.map(str::to_string);
+        self
+    }
+}
+
+/// What a report claims about the traffic it covers
+#[derive(Debug, Clone, Copy)]
+pub struct ReportClaim<'a> {
+    pub summary: &'a str,
+    pub message_count: usize,
+    pub window_start: f64,
+    pub window_end: f64,
+    /// When the previous report was accepted (0 if none)
+    pub since: f64,
+}
+
+/// Consistency score and its components, each 0.0-1.0
+#[derive(Debug, Clone, Serialize)]
+pub struct Consistency {
+    pub score: f64,
+    pub count: f64,
+    pub timing: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codebook: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampled: Option<f64>,
+    /// Messages observed inside the report window
+    pub observed: usize,
+}
+
+impl Consistency {
+    fn mean(&self) -> f64 {
+        let components = [Some(self.count), Some(self.timing), self.codebook, self.sampled];
+        let available: Vec<f64> = components.into_iter().flatten().collect();
+        available.iter().sum::<f64>() / available.len() as f64
+    }
+
+    /// Fold the outcome of sampled-message checks into the score
+    pub fn with_samples(mut self, checks: &[SampleCheck]) -> Self {
+        if !checks.is_empty() {
+            let passed = checks.iter().filter(|c| c.passed()).count();
+            self.sampled = Some(passed as f64 / checks.len() as f64);
+            self.score = self.mean();
+        }
+        self
+    }
+}
+
+/// Share of a decoded message's gloss words the summary must mention for the
+/// summary to count as covering it
+pub const MIN_SAMPLE_OVERLAP: f64 = 0.3;
+
+/// Outcome of decoding one sampled message
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SampleCheck {
+    pub message_id: String,
+    /// Share of the message's decoded gloss words the summary mentions
+    pub overlap: f64,
+}
+
+impl SampleCheck {
+    pub fn passed(&self) -> bool {
+        self.overlap >= MIN_SAMPLE_OVERLAP
+    }
+}
+
+/// Random seed for [`verify_sample`]
+pub fn random_seed() -> u64 {
+    u64::from_le_bytes(signing::random_bytes())
+}
+
+/// Decode up to `size` of the reported `message_ids`, picked at random from
+/// `seed`, and check that `summary` mentions what each one says
+///
+/// Only listed messages the gateway saw with that `message_id` and with at
+/// least one token the codebook glosses can be sampled.
+pub fn verify_sample<'a>(
+    summary: &str,
+    message_ids: &[String],
+    traffic: impl IntoIterator<Item = &'a TrafficSample>,
+    codebook: &BTreeMap<String, String>,
+    size: usize,
+    seed: u64,
+) -> Vec<SampleCheck> {
+    if size == 0 || codebook.is_empty() {
+        return Vec::new();
+    }
+    let mut listed: HashSet<&str> = message_ids.iter().map(String::as_str).collect();
+    let mut eligible: Vec<(&str, HashSet<String>)> = traffic
+        .into_iter()
+        .filter_map(|s| {
+            let id = s.message_id.as_deref()?;
+            // A reused id is checked against its first message only
+            if !listed.remove(id) {
+                return None;
+            }
+            let decoded: HashSet<String> = s
+                .tokens
+                .iter()
+                .filter_map(|t| codebook.get(t))
+                .flat_map(|gloss| content_words(gloss).into_keys())
+                .collect();
+            (!decoded.is_empty()).then_some((id, decoded))
+        })
+        .collect();
+
+    // Partial Fisher-Yates shuffle over an xorshift sequence
+    let take = size.min(eligible.len());
+    let mut x = seed | 1;
+    for i in 0..take {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        let j = i + (x % (eligible.len() - i) as u64) as usize;
+        eligible.swap(i, j);
+    }
+
+    let summary = content_words(summary);
+    eligible
+        .into_iter()
+        .take(take)
+        .map(|(id, decoded)| SampleCheck {
+            message_id: id.to_string(),
+            overlap: decoded.iter().filter(|w| summary.contains_key(*w)).count() as f64 / decoded.len() as f64,
+        })
+        .collect()
+}
+
+/// Score `claim` against the sender's observed `traffic`
+pub fn score<'a>(
+    claim: &ReportClaim<'_>,
+    traffic: impl IntoIterator<Item = &'a TrafficSample>,
+    codebook: &BTreeMap<String, String>,
+) -> Consistency {
+    let in_window = |s: &TrafficSample| s.ts >= claim.window_start && s.ts <= claim.window_end;
+    let recent: Vec<&TrafficSample> = traffic.into_iter().filter(|s| s.ts >= claim.since).collect();
+    let observed: Vec<&TrafficSample> = recent.iter().copied().filter(|s| in_window(s)).collect();
+
+    let count = match (observed.len(), claim.message_count) {
+        (0, 0) => 1.0,
+        (a, b) => a.min(b) as f64 / a.max(b) as f64,
+    };
+    let timing = if recent.is_empty() {
+        1.0
+    } else {
+        observed.len() as f64 / recent.len() as f64
+    };
+
+    let glossed: HashSet<String> = observed
+        .iter()
+        .flat_map(|s| s.tokens.iter())
+        .filter_map(|t| codebook.get(t))
+        .flat_map(|gloss| content_words(gloss).into_keys())
+        .collect();
+    let codebook = (!glossed.is_empty()).then(|| {
+        let summary = content_words(claim.summary);
+        glossed.iter().filter(|w| summary.contains_key(*w)).count() as f64 / glossed.len() as f64
+    });
+
+    let mut consistency = Consistency {
+        score: 0.0,
+        count,
+        timing,
+        codebook,
+        sampled: None,
+        observed: observed.len(),
+    };
+    consistency.score = consistency.mean();
+    consistency
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consistent_and_unrelated_summaries() {
+        let traffic = [
+            TrafficSample::new("SHP|eta=FRI", 5.0),
+            TrafficSample::new("SHP|eta=MON", 15.0),
+            TrafficSample::new("ACK", 25.0),
+        ];
+        let codebook = BTreeMap::from([
+            ("SHP".to_string(), "shipment status".to_string()),
+            ("FRI".to_string(), "Friday".to_string()),
+            ("MON".to_string(), "Monday".to_string()),
+        ]);
+        let claim = ReportClaim {
+            summary: "Two shipment status updates: arriving Friday, then moved to Monday",
+            message_count: 2,
+            window_start: 0.0,
+            window_end: 20.0,
+            since: 0.0,
+        };
+
+        let good = score(&claim, &traffic, &codebook);
+        assert_eq!(good.observed, 2);
+        assert_eq!(good.count, 1.0);
+        assert!((good.timing - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(good.codebook, Some(1.0));
+
+        let bad = score(
+            &ReportClaim {
+                summary: "Exchanged pleasantries about the weather with a colleague",
+                message_count: 40,
+                ..claim
+            },
+            &traffic,
+            &codebook,
+        );
+        assert_eq!(bad.codebook, Some(0.0));
+        assert!(bad.score < 0.4 && good.score > 0.8);
+
+        // Without a codebook only count and timing contribute
+        let plain = score(&claim, &traffic, &BTreeMap::new());
+        assert!(plain.codebook.is_none());
+        assert!((plain.score - (1.0 + 2.0 / 3.0) / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sampled_messages_decoded_against_summary() {
+        let traffic = [
+            TrafficSample::new("SHP|eta=FRI", 5.0).identified(Some("m1")),
+            TrafficSample::new("SHP|eta=MON", 15.0).identified(Some("m2")),
+            TrafficSample::new("ACK", 16.0).identified(Some("m3")),
+            TrafficSample::new("SHP|eta=MON", 17.0),
+        ];
+        let codebook = BTreeMap::from([
+            ("SHP".to_string(), "shipment status".to_string()),
+            ("FRI".to_string(), "Friday".to_string()),
+            ("MON".to_string(), "Monday".to_string()),
+        ]);
+        let ids: Vec<String> = ["m1", "m2", "m3", "m9"].map(String::from).into();
+        let summary = "Shipment arriving Friday";
+
+        // m3 decodes to nothing and m9 was never seen, so only m1 and m2 qualify
+        let all = verify_sample(summary, &ids, &traffic, &codebook, 5, 42);
+        let mut sampled: Vec<_> = all.iter().map(|c| c.message_id.as_str()).collect();
+        sampled.sort_unstable();
+        assert_eq!(sampled, ["m1", "m2"]);
+        let m2 = all.iter().find(|c| c.message_id == "m2").unwrap();
+        assert!((m2.overlap - 1.0 / 3.0).abs() < 1e-9, "shipment of shipment/status/monday");
+
+        let one = verify_sample(summary, &ids, &traffic, &codebook, 1, 7);
+        assert_eq!(one.len(), 1);
+        assert!(verify_sample(summary, &ids, &traffic, &BTreeMap::new(), 5, 7).is_empty());
+
+        let failing = [SampleCheck { message_id: "m1".into(), overlap: 0.0 }];
+        let claim = ReportClaim {
+            summary,
+            message_count: 2,
+            window_start: 0.0,
+            window_end: 20.0,
+            since: 0.0,
+        };
+        let base = score(&claim, &traffic, &codebook);
+        let lowered = base.clone().with_samples(&failing);
+        assert_eq!(lowered.sampled, Some(0.0));
+        assert!(lowered.score < base.score);
+        assert_eq!(base.clone().with_samples(&[]).score, base.score);
+    }
+}
@@ -0,0 +1,67 @@
+//! Embedded operator dashboard (feature `dashboard`)
+//!
+//! For a quick look at a gateway without standing up Grafana, `GET /ui`
+//! serves a page compiled into the binary. It polls the existing read
+//! endpoints with a token entered on the page:
+//!
+//! - `GET /agents` for live agents and their violation counts
+//! - `GET /audit/export?cursor=` for recent send decisions (`msg_accepted`,
+//!   `msg_rejected`, `msg_quarantined`) and overdue reports
+//!   (`report_deadline_passed`, until the next `report_accepted`)
+//!
+//! The page and its assets need no token; the data behind them takes an
+//! admin or auditor token. Production builds leave the feature off: no `/ui`
+//! route is compiled in.
+
+use axum::{
+    extract::Path,
+    http::header,
+    response::{Html, IntoResponse, Response},
+};
+
+use crate::error::GatewayError;
+
+const INDEX_HTML: &str = include_str!("ui/index.html");
+
+/// Assets under `/ui/`: name, content type, and body
+const ASSETS: [(&str, &str, &str); 2] = [
+    ("dashboard.js", "text/javascript; charset=utf-8", include_str!("ui/dashboard.js")),
+    ("dashboard.css", "text/css; charset=utf-8", include_str!("ui/dashboard.css")),
+];
+
+/// The dashboard page
+pub async fn index() -> Html<&'static str> {
+    Html(INDEX_HTML)
+}
+
+/// A script or stylesheet of the page
+pub async fn asset(Path(name): Path<String>) -> Result<Response, GatewayError> {
+    let (_, content_type, body) = ASSETS
+        .iter()
+        .find(|(asset, _, _)| *asset == name)
+        .ok_or(GatewayError::NotFound("No such dashboard asset"))?;
+    Ok(([(header::CONTENT_TYPE, *content_type)], *body).into_response())
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestGateway;
+    use axum::http::{header, StatusCode};
+
+    #[tokio::test]
+    async fn test_dashboard_served() {
+        let gw = TestGateway::new();
+        let page = gw.get("/ui").await;
+        assert_eq!(page.status, StatusCode::OK);
+        assert!(page.headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+
+        let script = gw.get("/ui/dashboard.js").await;
+        assert_eq!(script.status, StatusCode::OK);
+        assert!(script.headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/javascript"));
+        assert_eq!(gw.get("/ui/secrets.json").await.status, StatusCode::NOT_FOUND);
+    }
+}
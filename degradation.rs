@@ -0,0 +1,148 @@
+//! Degradation policy for internal errors
+//!
+//! A send or report the gateway cannot decide because of an internal error,
+//! such as shared state poisoned by a panic or a panicking handler in a
+//! build that unwinds, is answered as `DEGRADATION_POLICY` says:
+//!
+//! - **deny** (fail closed, the default): refused with 503 and
+//!   `code: internal_error`
+//! - **allow** (fail open): accepted with `degraded: true`, and audited as
+//!   `msg_accepted` or `report_accepted` with `degraded = true`; a degraded
+//!   report does not reset the report clock
+//!
+//! Rules select by route group and, optionally, the agent's tenant and the
+//! protocol's risk tier, e.g.
+//! `[{"route": "send", "tenant": "ops", "risk_tier": "low", "on_error": "allow"}]`.
+//! The first matching rule applies. Only the `send` and `reports` groups
+//! can be degraded; everything else fails closed.
+//!
+//! Every internal error is audited as `internal_error` with the fallback
+//! taken. The release profile aborts on panic, so there only errors the
+//! gateway detects (a poisoned state lock) degrade; build with
+//! `panic = "unwind"` for handler panics to degrade too.
+
+use futures::FutureExt;
+use serde::Deserialize;
+use std::{any::Any, env, future::Future, panic::AssertUnwindSafe};
+use tracing::warn;
+
+use crate::{error::GatewayError, maintenance::RouteGroup, webhooks::FailureMode};
+
+/// Route groups whose internal errors a rule may fail open
+const DEGRADABLE: [RouteGroup; 2] = [RouteGroup::Send, RouteGroup::Reports];
+
+/// One `DEGRADATION_POLICY` entry
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DegradationRule {
+    pub route: RouteGroup,
+    /// Owning team of the agent; any tenant when unset
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Effective risk tier of the protocol; any tier, or none, when unset
+    #[serde(default)]
+    pub risk_tier: Option<String>,
+    pub on_error: FailureMode,
+}
+
+impl DegradationRule {
+    fn matches(&self, route: RouteGroup, tenant: &str, risk_tier: Option<&str>) -> bool {
+        self.route == route
+            && self.tenant.as_deref().is_none_or(|t| t == tenant)
+            && self
+                .risk_tier
+                .as_deref()
+                .is_none_or(|t| risk_tier.is_some_and(|r| t.eq_ignore_ascii_case(r)))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DegradationPolicy {
+    rules: Vec<DegradationRule>,
+}
+
+impl DegradationPolicy {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let rules: Vec<DegradationRule> = serde_json::from_str(raw).map_err(|e| e.to_string())?;
+        if let Some(rule) = rules.iter().find(|r| !DEGRADABLE.contains(&r.route)) {
+            return Err(format!("route {} cannot be degraded; use send or reports", rule.route.as_str()));
+        }
+        Ok(Self { rules })
+    }
+
+    /// Load `DEGRADATION_POLICY`; an invalid policy fails closed everywhere
+    pub fn from_env() -> Self {
+        match env::var("DEGRADATION_POLICY") {
+            Ok(raw) if !raw.trim().is_empty() => Self::parse(&raw).unwrap_or_else(|e| {
+                warn!(event = "config_invalid", error = %e, "Invalid DEGRADATION_POLICY, failing closed");
+                Self::default()
+            }),
+            _ => Self::default(),
+        }
+    }
+
+    pub fn rules(&self) -> &[DegradationRule] {
+        &self.rules
+    }
+
+    /// What an internal error on `route` for an agent of `tenant`, under a
+    /// protocol of `risk_tier`, gets
+    pub fn on_error(&self, route: RouteGroup, tenant: &str, risk_tier: Option<&str>) -> FailureMode {
+        self.rules
+            .iter()
+            .find(|r| r.matches(route, tenant, risk_tier))
+            .map_or(FailureMode::Deny, |r| r.on_error)
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    let detail = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause");
+    format!("handler panicked: {detail}")
+}
+
+/// Run `decision`, turning a panic into [`GatewayError::Internal`]
+pub async fn catch_panics<T>(decision: impl Future<Output = Result<T, GatewayError>>) -> Result<T, GatewayError> {
+    AssertUnwindSafe(decision)
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|payload| Err(GatewayError::Internal(panic_message(payload.as_ref()))))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_policy_and_panics() {
+        let policy = DegradationPolicy::parse(
+            r#"[{"route": "send", "tenant": "ops", "risk_tier": "high", "on_error": "deny"},
+                {"route": "send", "tenant": "ops", "on_error": "allow"}]"#,
+        )
+        .unwrap();
+        assert_eq!(policy.on_error(RouteGroup::Send, "ops", Some("HIGH")), FailureMode::Deny);
+        assert_eq!(policy.on_error(RouteGroup::Send, "ops", Some("low")), FailureMode::Allow);
+        assert_eq!(policy.on_error(RouteGroup::Send, "ops", None), FailureMode::Allow);
+        assert_eq!(policy.on_error(RouteGroup::Reports, "ops", None), FailureMode::Deny);
+        assert_eq!(policy.on_error(RouteGroup::Send, "red", None), FailureMode::Deny);
+        assert!(DegradationPolicy::parse(r#"[{"route": "reviews", "on_error": "allow"}]"#).is_err());
+
+        let decided = catch_panics(async { Ok::<_, GatewayError>(1) }).await;
+        assert_eq!(decided, Ok(1));
+        let panicked = catch_panics(async {
+            if decided.is_ok() {
+                panic!("state exploded");
+            }
+            Ok::<u32, _>(0)
+        })
+        .await;
+        assert_eq!(panicked, Err(GatewayError::Internal("handler panicked: state exploded".to_string())));
+    }
+}
@@ -0,0 +1,366 @@
+//! Receiver-side delivery receipts
+//!
+//! An allowed send says only that the gateway approved the message, not that
+//! it arrived. Each send allowed to at least one recipient is answered with a
+//! `message_id`, and the recipient, or the relay that delivered for it,
+//! confirms arrival with `POST /delivered/{message_id}`. Recipients still
+//! unconfirmed `DELIVERY_TIMEOUT_SEC` after the send are flagged undelivered
+//! and logged as `delivery_overdue`.
+//!
+//! For novel-language sends the outcome feeds the protocol's coverage
+//! accounting: its stats separate messages sent and delivered from messages
+//! sent but undelivered. A receipt that arrives after its recipient was
+//! flagged still counts, moving it from undelivered to delivered, as long as
+//! the message is remembered: for another `DELIVERY_TIMEOUT_SEC` after the
+//! timeout, or after the last recipient confirmed.
+//!
+//! Confirming is idempotent. Message ids are random and appear in audit
+//! events, so anyone who can read the sender's audit trail can confirm its
+//! messages. Like parked sends, tracked messages live only in memory and are
+//! not replicated.
+
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{env_parse, error::GatewayError, signing};
+
+#[derive(Debug, Clone)]
+pub struct DeliveryConfig {
+    /// How long recipients have to confirm; 0 disables receipt tracking
+    pub timeout_sec: u64,
+}
+
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        Self { timeout_sec: 300 }
+    }
+}
+
+impl DeliveryConfig {
+    /// Load settings from `DELIVERY_*` environment variables
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            timeout_sec: env_parse("DELIVERY_TIMEOUT_SEC").unwrap_or(defaults.timeout_sec),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryState {
+    Pending,
+    Delivered,
+    /// Not confirmed within the timeout
+    Undelivered,
+}
+
+/// One recipient's delivery
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecipientDelivery {
+    pub state: DeliveryState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivered_at: Option<u64>,
+}
+
+/// A tracked message as answered by `POST /delivered/{message_id}`
+#[derive(Debug, Clone, Serialize)]
+pub struct DeliveryStatus {
+    pub message_id: String,
+    pub from: String,
+    /// Resolved protocol key of a novel-language send
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    pub sent_at: u64,
+    pub deliver_by: u64,
+    pub recipients: BTreeMap<String, RecipientDelivery>,
+}
+
+impl DeliveryStatus {
+    pub fn is_settled(&self) -> bool {
+        self.recipients.values().all(|r| r.state == DeliveryState::Delivered)
+    }
+}
+
+/// What a receipt changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirmed {
+    /// Confirmed within the timeout
+    OnTime,
+    /// Confirmed after the recipient was flagged undelivered
+    Late,
+    /// Already confirmed
+    Repeat,
+}
+
+#[derive(Debug, Default)]
+pub struct DeliveryCounters {
+    pub delivered: AtomicU64,
+    /// Confirmed after being counted undelivered
+    pub late: AtomicU64,
+    pub undelivered: AtomicU64,
+}
+
+/// Messages awaiting or recently given delivery receipts
+#[derive(Debug, Default)]
+pub struct Deliveries {
+    config: DeliveryConfig,
+    messages: Mutex<HashMap<String, DeliveryStatus>>,
+    pub counters: DeliveryCounters,
+}
+
+impl Deliveries {
+    pub fn new(config: DeliveryConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn config(&self) -> &DeliveryConfig {
+        &self.config
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.timeout_sec > 0
+    }
+
+    /// Track a send allowed to `recipients`; returns its message id and
+    /// confirmation deadline
+    pub fn track(&self, from: &str, protocol: Option<&str>, recipients: &[&str], now: u64) -> (String, u64) {
+        let message_id = signing::random_hex::<16>();
+        let deliver_by = now + self.config.timeout_sec;
+        let pending = RecipientDelivery {
+            state: DeliveryState::Pending,
+            delivered_at: None,
+        };
+        let status = DeliveryStatus {
+            message_id: message_id.clone(),
+            from: from.to_string(),
+            protocol: protocol.map(str::to_string),
+            sent_at: now,
+            deliver_by,
+            recipients: recipients.iter().map(|r| (r.to_string(), pending.clone())).collect(),
+        };
+        self.messages.lock().unwrap().insert(message_id.clone(), status);
+        (message_id, deliver_by)
+    }
+
+    /// Confirm delivery of `message_id` to `recipient`, or to every recipient
+    /// not yet confirmed when `None`
+    ///
+    /// Returns the message and what the receipt changed for each recipient
+    /// it named.
+    pub fn confirm(
+        &self,
+        message_id: &str,
+        recipient: Option<&str>,
+        now: u64,
+    ) -> Result<(DeliveryStatus, Vec<(String, Confirmed)>), GatewayError> {
+        let mut messages = self.messages.lock().unwrap();
+        let status = messages
+            .get_mut(message_id)
+            .ok_or(GatewayError::NotFound("Unknown or forgotten message"))?;
+        let names: Vec<String> = match recipient {
+            Some(r) if status.recipients.contains_key(r) => vec![r.to_string()],
+            Some(_) => return Err(GatewayError::NotFound("Not a recipient of this message")),
+            None => status.recipients.keys().cloned().collect(),
+        };
+        let mut changes = Vec::with_capacity(names.len());
+        for name in names {
+            let delivery = status.recipients.get_mut(&name).expect("recipient listed");
+            let confirmed = match delivery.state {
+                DeliveryState::Delivered if recipient.is_none() => continue,
+                DeliveryState::Delivered => Confirmed::Repeat,
+                DeliveryState::Pending => Confirmed::OnTime,
+                DeliveryState::Undelivered => Confirmed::Late,
+            };
+            if confirmed != Confirmed::Repeat {
+                delivery.state = DeliveryState::Delivered;
+                delivery.delivered_at = Some(now);
+                let counter = match confirmed {
+                    Confirmed::Late => &self.counters.late,
+                    _ => &self.counters.delivered,
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+            changes.push((name, confirmed));
+        }
+        Ok((status.clone(), changes))
+    }
+
+    /// Flag the recipients of `message_id` still unconfirmed; returns the
+    /// message when any were
+    pub fn time_out(&self, message_id: &str) -> Option<(DeliveryStatus, Vec<String>)> {
+        let mut messages = self.messages.lock().unwrap();
+        let status = messages.get_mut(message_id)?;
+        let mut flagged = Vec::new();
+        for (name, delivery) in &mut status.recipients {
+            if delivery.state == DeliveryState::Pending {
+                delivery.state = DeliveryState::Undelivered;
+                flagged.push(name.clone());
+            }
+        }
+        if flagged.is_empty() {
+            return None;
+        }
+        self.counters.undelivered.fetch_add(flagged.len() as u64, Ordering::Relaxed);
+        Some((status.clone(), flagged))
+    }
+
+    /// Drop a message no longer awaiting receipts
+    pub fn forget(&self, message_id: &str) {
+        let mut messages = self.messages.lock().unwrap();
+        let awaiting = messages
+            .get(message_id)
+            .is_some_and(|s| s.recipients.values().any(|r| r.state == DeliveryState::Pending));
+        if !awaiting {
+            messages.remove(message_id);
+        }
+    }
+
+    /// Recipients awaiting a receipt within the timeout
+    pub fn pending(&self) -> usize {
+        self.messages
+            .lock()
+            .unwrap()
+            .values()
+            .flat_map(|s| s.recipients.values())
+            .filter(|r| r.state == DeliveryState::Pending)
+            .count()
+    }
+}
+
+/// Per-recipient delivery outcomes of a protocol's tracked sends
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeliveryCounts {
+    pub tracked: u64,
+    pub delivered: u64,
+    pub undelivered: u64,
+}
+
+impl DeliveryCounts {
+    /// Account for a receipt to one recipient
+    pub fn confirmed(&mut self, confirmed: Confirmed) {
+        match confirmed {
+            Confirmed::OnTime => self.delivered += 1,
+            Confirmed::Late => {
+                self.delivered += 1;
+                self.undelivered = self.undelivered.saturating_sub(1);
+            }
+            Confirmed::Repeat => {}
+        }
+    }
+}
+
+/// Delivery accounting in protocol analytics
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeliveryStats {
+    /// Sent and confirmed delivered
+    pub delivered: u64,
+    /// Sent but not confirmed within the timeout
+    pub undelivered: u64,
+    /// Sent and still within the timeout
+    pub awaiting: u64,
+    /// Delivered share of the sends whose outcome is known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivery_rate: Option<f64>,
+}
+
+impl DeliveryStats {
+    /// `None` until a send was tracked
+    pub fn new(counts: &DeliveryCounts) -> Option<Self> {
+        let settled = counts.delivered + counts.undelivered;
+        (counts.tracked > 0).then(|| Self {
+            delivered: counts.delivered,
+            undelivered: counts.undelivered,
+            awaiting: counts.tracked.saturating_sub(settled),
+            delivery_rate: (settled > 0).then(|| counts.delivered as f64 / settled as f64),
+        })
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{AgentFixture, ProtocolFixture, SendFixture, TestGateway};
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    #[test]
+    fn test_confirm_and_time_out() {
+        let deliveries = Deliveries::default();
+        let (id, deliver_by) = deliveries.track("a", Some("coord:1.0"), &["b", "c"], 100);
+        assert_eq!((id.len(), deliver_by), (32, 400));
+        assert_eq!(deliveries.pending(), 2);
+
+        let (status, changes) = deliveries.confirm(&id, Some("b"), 110).unwrap();
+        assert_eq!(changes, [("b".to_string(), Confirmed::OnTime)]);
+        assert!(!status.is_settled());
+        let (_, again) = deliveries.confirm(&id, Some("b"), 111).unwrap();
+        assert_eq!(again, [("b".to_string(), Confirmed::Repeat)]);
+        assert_eq!(deliveries.confirm(&id, Some("z"), 111).unwrap_err().code(), "not_found");
+        assert_eq!(deliveries.confirm("nope", None, 111).unwrap_err().code(), "not_found");
+
+        let (_, flagged) = deliveries.time_out(&id).unwrap();
+        assert_eq!(flagged, ["c"]);
+        assert!(deliveries.time_out(&id).is_none());
+        assert_eq!(deliveries.pending(), 0);
+
+        // Unnamed receipts confirm every recipient not yet confirmed
+        let (status, late) = deliveries.confirm(&id, None, 500).unwrap();
+        assert_eq!(late, [("c".to_string(), Confirmed::Late)]);
+        assert!(status.is_settled());
+        assert_eq!(deliveries.counters.delivered.load(Ordering::Relaxed), 1);
+        assert_eq!(deliveries.counters.late.load(Ordering::Relaxed), 1);
+
+        deliveries.forget(&id);
+        assert!(deliveries.confirm(&id, None, 501).is_err());
+    }
+
+    #[test]
+    fn test_delivery_counts() {
+        let mut counts = DeliveryCounts::default();
+        assert!(DeliveryStats::new(&counts).is_none());
+        counts.tracked = 4;
+        counts.confirmed(Confirmed::OnTime);
+        counts.undelivered = 2;
+        counts.confirmed(Confirmed::Late);
+        counts.confirmed(Confirmed::Repeat);
+        let stats = DeliveryStats::new(&counts).unwrap();
+        assert_eq!((stats.delivered, stats.undelivered, stats.awaiting), (2, 1, 1));
+        assert_eq!(stats.delivery_rate, Some(2.0 / 3.0));
+    }
+
+    #[tokio::test]
+    async fn test_receipts_feed_protocol_stats() {
+        let gw = TestGateway::new();
+        let coord = ProtocolFixture::new("coord", "1.0").gloss("SHP", "shipment").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(coord.clone()).reported()).await;
+        let send = SendFixture::novel("a", "b", &coord, "SHP|eta=7f;q=0x3e;z=9").to_many(&["b", "c"]);
+        let sent = gw.send(&send.build()).await;
+        let id = sent.body["message_id"].as_str().unwrap().to_string();
+
+        let resp = gw.post(&format!("/delivered/{id}"), &json!({"recipient": "b"})).await;
+        assert_eq!(resp.status, StatusCode::OK);
+        assert_eq!(resp.body["recipients"]["b"]["state"], "delivered");
+        assert_eq!(resp.body["recipients"]["c"]["state"], "pending");
+        assert_eq!(gw.post("/delivered/unknown", &json!({})).await.status, StatusCode::NOT_FOUND);
+
+        let stats = gw.get("/protocols/a/coord/1.0/stats").await;
+        assert_eq!(
+            stats.body["delivery"],
+            json!({"delivered": 1, "undelivered": 0, "awaiting": 1, "delivery_rate": 1.0})
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Demo mode with seeded synthetic agents
+//!
+//! `--demo` starts the gateway with a small synthetic organisation and a
+//! background traffic generator, so stats, graph, SLO, and metrics endpoints
+//! have something to show from the first minute. Six agents in two teams
+//! register a `coord` protocol and file their first reports, then send a
+//! steady mix of traffic through the full router, exactly as real clients
+//! would:
+//!
+//! | Agent | Behaviour |
+//! |-------|-----------|
+//! | `demo-planner`, `demo-router`, `demo-analyst` | Compliant: English and novel messages, regular reports |
+//! | `demo-courier` | Never reports, so its novel messages are refused as overdue |
+//! | `demo-scribe` | Reports with low coverage, which are refused |
+//! | `demo-scout` | Novel messages without or under an unregistered protocol, and encrypted blobs |
+//!
+//! Demo traffic is ordinary traffic: it is audited, counted, and subject to
+//! the configured policy. Without `ADMIN_TOKEN` a random one is generated and
+//! logged, since seeding the directory and reading reviews need it.
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use serde_json::{json, Value};
+use std::{env, time::Duration};
+use tower::ServiceExt;
+use tracing::{info, warn};
+
+/// Pause between generated requests
+const TICK: Duration = Duration::from_millis(250);
+
+/// Novel messages a reporting agent sends between reports
+const REPORT_EVERY: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Behaviour {
+    Compliant,
+    /// Never reports
+    Laggard,
+    /// Files reports below the coverage minimum
+    Sloppy,
+    /// Skips or invents protocols and sends opaque content
+    Rogue,
+}
+
+/// Synthetic agents: id, team, behaviour
+const AGENTS: [(&str, &str, Behaviour); 6] = [
+    ("demo-planner", "logistics", Behaviour::Compliant),
+    ("demo-router", "logistics", Behaviour::Compliant),
+    ("demo-courier", "logistics", Behaviour::Laggard),
+    ("demo-analyst", "research", Behaviour::Compliant),
+    ("demo-scribe", "research", Behaviour::Sloppy),
+    ("demo-scout", "research", Behaviour::Rogue),
+];
+
+const ENGLISH: [&str; 5] = [
+    "Please confirm the shipment arrives on Friday",
+    "Inventory count for bay 4 is complete",
+    "Can you send the updated route plan before noon?",
+    "The analysis of last week's delays is ready for review",
+    "Acknowledged, rescheduling the pickup for tomorrow",
+];
+
+/// Whether `--demo` was passed on the command line
+pub fn requested() -> bool {
+    env::args().skip(1).any(|a| a == "--demo")
+}
+
+/// Random admin token for a demo started without `ADMIN_TOKEN`
+pub fn generate_token() -> String {
+    crate::signing::random_hex::<16>()
+}
+
+/// Seed the demo agents, then generate traffic until shutdown
+pub async fn run(router: Router, admin_token: String) {
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(1, |d| d.as_nanos() as u64);
+    let mut demo = Demo::new(router, admin_token, seed);
+    demo.seed().await;
+    info!(event = "demo_seeded", agents = AGENTS.len(), "Demo agents seeded, generating traffic");
+    let mut ticks = tokio::time::interval(TICK);
+    loop {
+        ticks.tick().await;
+        demo.step().await;
+    }
+}
+
+/// Traffic generator state
+struct Demo {
+    router: Router,
+    admin_token: String,
+    rng: u64,
+    /// Novel messages per agent since its last report
+    unreported: [u32; AGENTS.len()],
+}
+
+impl Demo {
+    fn new(router: Router, admin_token: String, seed: u64) -> Self {
+        Self {
+            router,
+            admin_token,
+            rng: seed | 1,
+            unreported: [0; AGENTS.len()],
+        }
+    }
+
+    /// xorshift64; demo traffic needs variety, not quality
+    fn next(&mut self, bound: u64) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng % bound
+    }
+
+    async fn call(&self, method: Method, path: &str, body: Option<Value>) -> StatusCode {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(header::CONTENT_TYPE, "application/json");
+        if path.starts_with("/agents") {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {}", self.admin_token));
+        }
+        let body = body.map_or_else(Body::empty, |b| Body::from(b.to_string()));
+        match self.router.clone().oneshot(req.body(body).unwrap()).await {
+            Ok(response) => response.status(),
+            Err(never) => match never {},
+        }
+    }
+
+    /// Register every agent's protocol, assign owners, and file first reports
+    async fn seed(&mut self) {
+        for (i, (agent, team, behaviour)) in AGENTS.into_iter().enumerate() {
+            let steps = [
+                ("register", self.call(Method::POST, "/register_protocol_for_agent", Some(registration(agent))).await),
+                (
+                    "owner",
+                    self.call(Method::PUT, &format!("/agents/{agent}/owner"), Some(json!({ "team": team })))
+                        .await,
+                ),
+            ];
+            for (step, status) in steps {
+                if !status.is_success() {
+                    warn!(event = "demo_seed_failed", agent, step, status = status.as_u16(), "Demo seeding step failed");
+                }
+            }
+            if behaviour != Behaviour::Laggard {
+                self.report(i, 1.0).await;
+            }
+        }
+    }
+
+    /// Generate one request from a random agent
+    async fn step(&mut self) -> StatusCode {
+        let i = self.next(AGENTS.len() as u64) as usize;
+        let (from, _, behaviour) = AGENTS[i];
+        let to = AGENTS[(i + 1 + self.next(AGENTS.len() as u64 - 1) as usize) % AGENTS.len()].0;
+        let roll = self.next(100);
+
+        if roll < 40 {
+            let content = ENGLISH[self.next(ENGLISH.len() as u64) as usize];
+            return self.send(json!({ "from": from, "to": to, "content": content })).await;
+        }
+        let content = format!(
+            "SHP|eta={:x};q=0x{:x};z={}",
+            self.next(0x100),
+            self.next(0x100),
+            self.next(10)
+        );
+        match behaviour {
+            Behaviour::Rogue if roll < 65 => {
+                let protocol = json!({ "name": "shadow", "version": "0.1" });
+                self.send(json!({ "from": from, "to": to, "content": content, "protocol": protocol }))
+                    .await
+            }
+            Behaviour::Rogue if roll < 85 => self.send(json!({ "from": from, "to": to, "content": content })).await,
+            Behaviour::Rogue => {
+                let blob: String = (0..48).map(|_| format!("{:x}", self.next(16))).collect();
+                self.send(json!({ "from": from, "to": to, "content": blob })).await
+            }
+            Behaviour::Compliant | Behaviour::Sloppy if self.unreported[i] >= REPORT_EVERY => {
+                let coverage = if behaviour == Behaviour::Sloppy { 0.6 } else { 1.0 };
+                self.report(i, coverage).await
+            }
+            _ => {
+                let protocol = json!({ "name": "coord", "version": "1.0" });
+                let status = self
+                    .send(json!({ "from": from, "to": to, "content": content, "protocol": protocol }))
+                    .await;
+                self.unreported[i] += 1;
+                status
+            }
+        }
+    }
+
+    async fn send(&self, body: Value) -> StatusCode {
+        self.call(Method::POST, "/send", Some(body)).await
+    }
+
+    async fn report(&mut self, i: usize, coverage: f64) -> StatusCode {
+        let (agent, ..) = AGENTS[i];
+        let count = self.unreported[i];
+        let body = json!({
+            "agent_id": agent,
+            "protocol_name": "coord",
+            "protocol_version": "1.0",
+            "window_start_ts": 0.0,
+            "window_end_ts": f64::from(u32::MAX),
+            "message_ids": (1..=count).map(|n| format!("{agent}-{n}")).collect::<Vec<_>>(),
+            "english_summary": format!("Sent {count} shipment status updates with arrival estimates and quantities"),
+            "coverage": coverage,
+            "self_confidence": 0.9,
+        });
+        self.unreported[i] = 0;
+        self.call(Method::POST, "/report", Some(body)).await
+    }
+}
+
+fn registration(agent: &str) -> Value {
+    json!({
+        "agent_id": agent,
+        "protocol": {
+            "name": "coord",
+            "version": "1.0",
+            "purpose": "Shipment coordination between demo agents",
+            "scope": "Shipment status, arrival estimates, quantities",
+            "risk_tier": "low",
+            "translation_method": "dictionary",
+            "codebook": {"SHP": "shipment", "eta": "arrival estimate", "q": "quantity", "z": "zone"},
+        },
+    })
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestGateway, ADMIN_TOKEN};
+
+    #[tokio::test]
+    async fn test_demo_produces_compliant_and_violating_traffic() {
+        let gw = TestGateway::new();
+        let mut demo = Demo::new(gw.router(), ADMIN_TOKEN.to_string(), 42);
+        demo.seed().await;
+
+        let mut statuses = Vec::new();
+        for _ in 0..300 {
+            statuses.push(demo.step().await);
+        }
+        assert!(statuses.contains(&StatusCode::OK));
+        assert!(statuses.contains(&StatusCode::TOO_MANY_REQUESTS), "laggard never overdue");
+        assert!(statuses.contains(&StatusCode::FORBIDDEN), "rogue never refused");
+        assert!(statuses.contains(&StatusCode::BAD_REQUEST), "sloppy reports never refused");
+
+        let agents = gw.admin(Method::GET, "/agents", None::<&()>).await;
+        assert_eq!(agents.status, StatusCode::OK);
+        let stats = gw.get("/protocols/demo-planner/coord/1.0/stats").await;
+        assert_eq!(stats.status, StatusCode::OK);
+        assert!(stats.body["messages_sent"].as_u64().unwrap() > 0, "{:?}", stats.body);
+    }
+}
@@ -0,0 +1,603 @@
+//! Language detection with an optional external classifier
+//!
+//! The gateway always has the local `looks_like_english` heuristic available.
+//! When `DETECTOR_URL` is set, content is first sent to an external HTTP
+//! classifier. Calls to that classifier are guarded by a circuit breaker so a
+//! slow or failing classifier cannot stall every send:
+//!
+//! - Each call is bounded by `DETECTOR_TIMEOUT_MS`
+//! - The breaker trips once the error rate over the last `DETECTOR_WINDOW`
+//!   calls reaches `DETECTOR_FAILURE_RATE`
+//! - While open, calls are short-circuited to the configured fallback
+//! - After `DETECTOR_OPEN_SEC`, a single trial call is let through (half-open)
+//!
+//! With `DETECTOR_ENSEMBLE` set, the classifier is one voter among several
+//! local detectors instead of the sole judge (see [`ensemble`](crate::ensemble)).
+//!
+//! # Classifier protocol
+//! `POST {DETECTOR_URL}` with `{"content": "..."}`, expecting
+//! `{"is_english": true|false}` in response, optionally with a `confidence`
+//! (0.5-1.0) in that verdict that the ensemble uses as its vote.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    env, fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+use crate::{
+    env_parse,
+    ensemble::{Ballot, EnsembleConfig, EnsembleCounters, Voter},
+    looks_like_english,
+};
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+/// What to do when the external classifier cannot be consulted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DetectorFallback {
+    /// Use the local `looks_like_english` heuristic
+    Heuristic,
+    /// Treat content as English and let it through
+    FailOpen,
+    /// Refuse the message until the classifier recovers
+    FailClosed,
+}
+
+impl DetectorFallback {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "heuristic" => Some(Self::Heuristic),
+            "fail_open" | "fail-open" | "open" => Some(Self::FailOpen),
+            "fail_closed" | "fail-closed" | "closed" => Some(Self::FailClosed),
+            _ => None,
+        }
+    }
+}
+
+/// External classifier and circuit breaker settings
+#[derive(Debug, Clone)]
+pub struct DetectorConfig {
+    /// Classifier endpoint; `None` means heuristic only
+    pub url: Option<String>,
+    /// Per-call timeout
+    pub timeout: Duration,
+    /// Number of recent calls considered when computing the error rate
+    pub window: usize,
+    /// Error rate (0.0-1.0) at which the breaker trips
+    pub failure_rate: f64,
+    /// How long the breaker stays open before a trial call
+    pub open_duration: Duration,
+    /// Behaviour while the classifier is unavailable
+    pub fallback: DetectorFallback,
+    /// Weighted voting across detectors; `None` lets the classifier or
+    /// heuristic decide alone
+    pub ensemble: Option<EnsembleConfig>,
+}
+
+impl Default for DetectorConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            timeout: Duration::from_millis(250),
+            window: 20,
+            failure_rate: 0.5,
+            open_duration: Duration::from_secs(30),
+            fallback: DetectorFallback::Heuristic,
+            ensemble: None,
+        }
+    }
+}
+
+impl DetectorConfig {
+    /// Load settings from `DETECTOR_*` environment variables
+    pub fn from_env() -> Self {
+        let mut cfg = Self {
+            url: env::var("DETECTOR_URL").ok().filter(|u| !u.trim().is_empty()),
+            ensemble: EnsembleConfig::from_env(),
+            ..Self::default()
+        };
+        if let Some(ms) = env_parse::<u64>("DETECTOR_TIMEOUT_MS") {
+            cfg.timeout = Duration::from_millis(ms);
+        }
+        if let Some(n) = env_parse::<usize>("DETECTOR_WINDOW") {
+            cfg.window = n.max(1);
+        }
+        if let Some(r) = env_parse::<f64>("DETECTOR_FAILURE_RATE") {
+            cfg.failure_rate = r.clamp(0.0, 1.0);
+        }
+        if let Some(s) = env_parse::<u64>("DETECTOR_OPEN_SEC") {
+            cfg.open_duration = Duration::from_secs(s);
+        }
+        if let Ok(v) = env::var("DETECTOR_FALLBACK") {
+            match DetectorFallback::parse(&v) {
+                Some(f) => cfg.fallback = f,
+                None => warn!(value = %v, event = "config_invalid", "Unknown DETECTOR_FALLBACK, using heuristic"),
+            }
+        }
+        cfg
+    }
+}
+
+// =============================================================================
+// Circuit Breaker
+// =============================================================================
+
+/// Breaker state as exposed in metrics and readiness checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl BreakerState {
+    /// Numeric encoding for the `detector_breaker_state` gauge
+    pub fn as_gauge(self) -> u64 {
+        match self {
+            Self::Closed => 0,
+            Self::Open => 1,
+            Self::HalfOpen => 2,
+        }
+    }
+}
+
+impl fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half_open",
+        })
+    }
+}
+
+/// Error-rate circuit breaker over a fixed-size window of recent calls
+#[derive(Debug)]
+struct CircuitBreaker {
+    state: BreakerState,
+    /// Recent call outcomes, `true` for success
+    outcomes: VecDeque<bool>,
+    opened_at: Option<Instant>,
+    trial_in_flight: bool,
+    window: usize,
+    failure_rate: f64,
+    open_duration: Duration,
+}
+
+impl CircuitBreaker {
+    fn new(cfg: &DetectorConfig) -> Self {
+        Self {
+            state: BreakerState::Closed,
+            outcomes: VecDeque::with_capacity(cfg.window),
+            opened_at: None,
+            trial_in_flight: false,
+            window: cfg.window,
+            failure_rate: cfg.failure_rate,
+            open_duration: cfg.open_duration,
+        }
+    }
+
+    /// Whether a call may proceed; moves Open -> HalfOpen once the cool-down elapses
+    fn allow(&mut self, now: Instant) -> bool {
+        match self.state {
+            BreakerState::Closed => true,
+            BreakerState::Open => {
+                let elapsed = self.opened_at.map(|t| now.duration_since(t)).unwrap_or_default();
+                if elapsed >= self.open_duration {
+                    self.state = BreakerState::HalfOpen;
+                    self.trial_in_flight = true;
+                    true
+                } else {
+                    false
+                }
+            }
+            BreakerState::HalfOpen => {
+                if self.trial_in_flight {
+                    false
+                } else {
+                    self.trial_in_flight = true;
+                    true
+                }
+            }
+        }
+    }
+
+    /// Give back a half-open trial that ended without an outcome, so the
+    /// next call can try again
+    fn abandon(&mut self) {
+        self.trial_in_flight = false;
+    }
+
+    /// Record a call outcome; returns `true` if this outcome tripped the breaker
+    fn record(&mut self, success: bool, now: Instant) -> bool {
+        if self.state == BreakerState::HalfOpen {
+            self.trial_in_flight = false;
+            if success {
+                self.state = BreakerState::Closed;
+                self.outcomes.clear();
+                self.opened_at = None;
+                return false;
+            }
+            self.state = BreakerState::Open;
+            self.opened_at = Some(now);
+            return true;
+        }
+
+        if self.outcomes.len() == self.window {
+            self.outcomes.pop_front();
+        }
+        self.outcomes.push_back(success);
+
+        if self.state == BreakerState::Closed && self.outcomes.len() == self.window {
+            let failures = self.outcomes.iter().filter(|ok| !**ok).count();
+            if (failures as f64) / (self.window as f64) >= self.failure_rate {
+                self.state = BreakerState::Open;
+                self.opened_at = Some(now);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// A call let through the breaker. Dropped without an outcome, as when the
+/// send awaiting it is cancelled, it gives back the half-open trial.
+struct Admitted<'a> {
+    breaker: &'a Mutex<CircuitBreaker>,
+    recorded: bool,
+}
+
+impl Admitted<'_> {
+    /// Record the outcome; returns `true` if it tripped the breaker
+    fn record(mut self, success: bool) -> bool {
+        self.recorded = true;
+        self.breaker.lock().unwrap().record(success, Instant::now())
+    }
+}
+
+impl Drop for Admitted<'_> {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.breaker.lock().unwrap().abandon();
+        }
+    }
+}
+
+// =============================================================================
+// Detector
+// =============================================================================
+
+/// Where a language verdict came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerdictSource {
+    Classifier,
+    Heuristic,
+    FailOpen,
+    FailClosed,
+    /// Encrypted or opaque-encoded content, never classified as English
+    Encoding,
+    /// Benign machine output matched by the content allowlist
+    Allowlist,
+    /// Weighted vote of the configured detectors
+    Ensemble,
+    /// Weighted vote with the classifier abstaining because it failed
+    EnsembleDegraded,
+    /// Writing-system check against a tenant's language of record
+    Script,
+}
+
+impl fmt::Display for VerdictSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Classifier => "classifier",
+            Self::Heuristic => "heuristic",
+            Self::FailOpen => "fail_open",
+            Self::FailClosed => "fail_closed",
+            Self::Encoding => "encoding",
+            Self::Allowlist => "allowlist",
+            Self::Ensemble => "ensemble",
+            Self::EnsembleDegraded => "ensemble_degraded",
+            Self::Script => "script",
+        })
+    }
+}
+
+/// Result of language detection
+#[derive(Debug, Clone)]
+pub struct Verdict {
+    /// `None` when no verdict could be reached (fail-closed fallback)
+    pub is_english: Option<bool>,
+    pub source: VerdictSource,
+    /// Per-detector votes when the ensemble decided
+    pub ballot: Option<Ballot>,
+}
+
+impl Verdict {
+    pub fn new(is_english: Option<bool>, source: VerdictSource) -> Self {
+        Self {
+            is_english,
+            source,
+            ballot: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ClassifyRequest<'a> {
+    content: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ClassifyResponse {
+    is_english: bool,
+    /// Confidence in `is_english`
+    #[serde(default)]
+    confidence: Option<f64>,
+}
+
+impl ClassifyResponse {
+    /// Probability that the content is English
+    fn english_probability(&self) -> f64 {
+        let confidence = self.confidence.map_or(1.0, |c| c.clamp(0.0, 1.0));
+        if self.is_english {
+            confidence
+        } else {
+            1.0 - confidence
+        }
+    }
+}
+
+/// Counters exported via `/metrics`
+#[derive(Debug, Default)]
+pub struct DetectorCounters {
+    pub calls_ok: AtomicU64,
+    pub calls_error: AtomicU64,
+    pub calls_timeout: AtomicU64,
+    pub short_circuited: AtomicU64,
+    pub breaker_trips: AtomicU64,
+    pub ensemble: EnsembleCounters,
+}
+
+/// Language detector: external classifier behind a circuit breaker, plus fallback
+pub struct Detector {
+    config: DetectorConfig,
+    client: reqwest::Client,
+    breaker: Mutex<CircuitBreaker>,
+    pub counters: DetectorCounters,
+}
+
+impl Default for Detector {
+    fn default() -> Self {
+        Self::new(DetectorConfig::default()).expect("classifier client")
+    }
+}
+
+impl Detector {
+    /// Fails when the HTTP client for the classifier cannot be built
+    pub fn new(config: DetectorConfig) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder().timeout(config.timeout).build()?;
+        Ok(Self {
+            breaker: Mutex::new(CircuitBreaker::new(&config)),
+            client,
+            config,
+            counters: DetectorCounters::default(),
+        })
+    }
+
+    pub fn config(&self) -> &DetectorConfig {
+        &self.config
+    }
+
+    /// Current breaker state (always `Closed` when no classifier is configured)
+    pub fn breaker_state(&self) -> BreakerState {
+        self.breaker.lock().unwrap().state
+    }
+
+    /// Whether sends can currently be classified without refusing them
+    pub fn is_ready(&self) -> bool {
+        self.config.fallback != DetectorFallback::FailClosed
+            || self.breaker_state() != BreakerState::Open
+    }
+
+    /// Whether a verdict came from normal operation rather than a fallback,
+    /// and so may be reused for identical content
+    pub fn is_authoritative(&self, source: VerdictSource) -> bool {
+        match source {
+            VerdictSource::Classifier
+            | VerdictSource::Encoding
+            | VerdictSource::Ensemble
+            | VerdictSource::Script => true,
+            VerdictSource::Heuristic => self.config.url.is_none(),
+            // Not cached so every allowlisted send is counted against its pattern
+            VerdictSource::FailOpen
+            | VerdictSource::FailClosed
+            | VerdictSource::Allowlist
+            | VerdictSource::EnsembleDegraded => false,
+        }
+    }
+
+    /// Classify `content`, consulting the external classifier when configured
+    pub async fn classify(&self, content: &str) -> Verdict {
+        if let Some(ensemble) = &self.config.ensemble {
+            return self.classify_ensemble(ensemble, content).await;
+        }
+        if self.config.url.is_none() {
+            return Verdict::new(Some(looks_like_english(content)), VerdictSource::Heuristic);
+        }
+        match self.call_classifier(content).await {
+            Some(body) => Verdict::new(Some(body.is_english), VerdictSource::Classifier),
+            None => self.fallback(content),
+        }
+    }
+
+    /// Let the configured detectors vote; an unreachable classifier abstains
+    /// unless the fallback fails open or closed
+    async fn classify_ensemble(&self, ensemble: &EnsembleConfig, content: &str) -> Verdict {
+        let mut degraded = false;
+        let classifier = if ensemble.includes(Voter::Classifier) && self.config.url.is_some() {
+            match self.call_classifier(content).await {
+                Some(body) => Some(body.english_probability()),
+                None if self.config.fallback == DetectorFallback::Heuristic => {
+                    degraded = true;
+                    None
+                }
+                None => return self.fallback(content),
+            }
+        } else {
+            None
+        };
+        let ballot = ensemble.vote(content, classifier, &self.counters.ensemble);
+        Verdict {
+            is_english: Some(ballot.is_english),
+            source: if degraded {
+                VerdictSource::EnsembleDegraded
+            } else {
+                VerdictSource::Ensemble
+            },
+            ballot: Some(ballot),
+        }
+    }
+
+    /// Call the external classifier through the breaker; `None` when it is
+    /// unset, short-circuited, or failed
+    async fn call_classifier(&self, content: &str) -> Option<ClassifyResponse> {
+        let url = self.config.url.as_deref()?;
+
+        if !self.breaker.lock().unwrap().allow(Instant::now()) {
+            self.counters.short_circuited.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        let admitted = Admitted {
+            breaker: &self.breaker,
+            recorded: false,
+        };
+
+        let result = self
+            .client
+            .post(url)
+            .json(&ClassifyRequest { content })
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        let result = match result {
+            Ok(resp) => resp.json::<ClassifyResponse>().await,
+            Err(e) => Err(e),
+        };
+
+        if admitted.record(result.is_ok()) {
+            self.counters.breaker_trips.fetch_add(1, Ordering::Relaxed);
+            warn!(event = "detector_breaker_open", "Classifier circuit breaker opened");
+        }
+
+        match result {
+            Ok(body) => {
+                self.counters.calls_ok.fetch_add(1, Ordering::Relaxed);
+                Some(body)
+            }
+            Err(e) => {
+                if e.is_timeout() {
+                    self.counters.calls_timeout.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    self.counters.calls_error.fetch_add(1, Ordering::Relaxed);
+                }
+                warn!(
+                    event = "detector_call_failed",
+                    timeout = e.is_timeout(),
+                    error = %e,
+                    "Classifier call failed, using fallback"
+                );
+                None
+            }
+        }
+    }
+
+    fn fallback(&self, content: &str) -> Verdict {
+        match self.config.fallback {
+            DetectorFallback::Heuristic => {
+                Verdict::new(Some(looks_like_english(content)), VerdictSource::Heuristic)
+            }
+            DetectorFallback::FailOpen => Verdict::new(Some(true), VerdictSource::FailOpen),
+            DetectorFallback::FailClosed => Verdict::new(None, VerdictSource::FailClosed),
+        }
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(window: usize) -> CircuitBreaker {
+        CircuitBreaker::new(&DetectorConfig {
+            window,
+            failure_rate: 0.5,
+            open_duration: Duration::from_secs(10),
+            ..DetectorConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_breaker_trips_and_recovers() {
+        let t0 = Instant::now();
+        let mut b = breaker(4);
+
+        assert!(!b.record(true, t0));
+        assert!(!b.record(false, t0));
+        assert!(!b.record(true, t0));
+        assert!(b.record(false, t0));
+        assert_eq!(b.state, BreakerState::Open);
+        assert!(!b.allow(t0 + Duration::from_secs(5)));
+
+        // Cool-down elapsed: exactly one trial call is allowed
+        assert!(b.allow(t0 + Duration::from_secs(10)));
+        assert_eq!(b.state, BreakerState::HalfOpen);
+        assert!(!b.allow(t0 + Duration::from_secs(10)));
+
+        b.record(true, t0 + Duration::from_secs(11));
+        assert_eq!(b.state, BreakerState::Closed);
+        assert!(b.allow(t0 + Duration::from_secs(11)));
+    }
+
+    #[test]
+    fn test_failed_trial_reopens_breaker() {
+        let t0 = Instant::now();
+        let mut b = breaker(2);
+        b.record(false, t0);
+        b.record(false, t0);
+        assert!(b.allow(t0 + Duration::from_secs(10)));
+        assert!(b.record(false, t0 + Duration::from_secs(10)));
+        assert_eq!(b.state, BreakerState::Open);
+        assert!(!b.allow(t0 + Duration::from_secs(15)));
+    }
+
+    #[test]
+    fn test_cancelled_trial_is_given_back() {
+        let t0 = Instant::now();
+        let mut b = breaker(1);
+        b.opened_at = Some(t0 - Duration::from_secs(10));
+        b.state = BreakerState::Open;
+        let b = Mutex::new(b);
+
+        assert!(b.lock().unwrap().allow(t0));
+        drop(Admitted {
+            breaker: &b,
+            recorded: false,
+        });
+        assert!(b.lock().unwrap().allow(t0), "the next call gets the trial");
+        assert_eq!(b.lock().unwrap().state, BreakerState::HalfOpen);
+    }
+}
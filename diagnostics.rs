@@ -0,0 +1,147 @@
+//! Tokio runtime diagnostics (feature `runtime-diagnostics`)
+//!
+//! For finding stuck tasks when the gateway stalls under load:
+//!
+//! - `GET /debug/runtime` (admin) reports the runtime's worker count, live
+//!   tasks, and global queue depth. Builds with `--cfg tokio_unstable` add
+//!   tasks spawned, blocking-pool usage, and per-worker queue depth, polls,
+//!   parks, steals, and busy time.
+//! - With `TOKIO_CONSOLE=true`, a `console-subscriber` layer serves
+//!   `tokio-console` on `TOKIO_CONSOLE_BIND` (default `127.0.0.1:6669`).
+//!   Task instrumentation also needs `--cfg tokio_unstable`.
+//!
+//! Production builds leave the feature off: neither the endpoint nor the
+//! console layer is compiled in.
+
+use axum::Json;
+use console_subscriber::ConsoleLayer;
+use serde::Serialize;
+use std::env;
+use tokio::runtime::{Handle, RuntimeMetrics};
+use tracing::Subscriber;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+use crate::{error::GatewayError, identity::AuthedAdmin};
+
+/// Whether `TOKIO_CONSOLE` asks for the console layer
+fn console_requested() -> bool {
+    env::var("TOKIO_CONSOLE").is_ok_and(|v| matches!(v.trim(), "1" | "true"))
+}
+
+/// `tokio-console` layer, spawning its server, when `TOKIO_CONSOLE` is set
+pub fn console_layer<S>() -> Option<impl Layer<S>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    console_requested().then(|| ConsoleLayer::builder().with_default_env().spawn())
+}
+
+/// Counters of one runtime worker thread, since startup
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerStats {
+    pub worker: usize,
+    pub local_queue_depth: usize,
+    pub polls: u64,
+    pub parks: u64,
+    pub steals: u64,
+    pub busy_ms: u128,
+}
+
+/// Body of `GET /debug/runtime`
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeReport {
+    pub workers: usize,
+    /// Tasks spawned and not yet completed
+    pub alive_tasks: usize,
+    /// Tasks waiting in the shared injection queue
+    pub global_queue_depth: usize,
+    pub console: bool,
+    /// Whether the fields below were available to this build
+    pub tokio_unstable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spawned_tasks: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocking_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_blocking_threads: Option<usize>,
+    /// `spawn_blocking` calls waiting for a thread
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocking_queue_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub worker_stats: Vec<WorkerStats>,
+}
+
+impl RuntimeReport {
+    pub fn capture(handle: &Handle) -> Self {
+        let metrics = handle.metrics();
+        let mut report = Self {
+            workers: metrics.num_workers(),
+            alive_tasks: metrics.num_alive_tasks(),
+            global_queue_depth: metrics.global_queue_depth(),
+            console: console_requested(),
+            tokio_unstable: cfg!(tokio_unstable),
+            spawned_tasks: None,
+            blocking_threads: None,
+            idle_blocking_threads: None,
+            blocking_queue_depth: None,
+            worker_stats: Vec::new(),
+        };
+        report.add_unstable(&metrics);
+        report
+    }
+
+    #[cfg(tokio_unstable)]
+    fn add_unstable(&mut self, metrics: &RuntimeMetrics) {
+        self.spawned_tasks = Some(metrics.spawned_tasks_count());
+        self.blocking_threads = Some(metrics.num_blocking_threads());
+        self.idle_blocking_threads = Some(metrics.num_idle_blocking_threads());
+        self.blocking_queue_depth = Some(metrics.blocking_queue_depth());
+        self.worker_stats = (0..self.workers)
+            .map(|worker| WorkerStats {
+                worker,
+                local_queue_depth: metrics.worker_local_queue_depth(worker),
+                polls: metrics.worker_poll_count(worker),
+                parks: metrics.worker_park_count(worker),
+                steals: metrics.worker_steal_count(worker),
+                busy_ms: metrics.worker_total_busy_duration(worker).as_millis(),
+            })
+            .collect();
+    }
+
+    #[cfg(not(tokio_unstable))]
+    fn add_unstable(&mut self, _metrics: &RuntimeMetrics) {}
+}
+
+/// Tokio runtime metrics
+pub async fn runtime(_: AuthedAdmin) -> Result<Json<RuntimeReport>, GatewayError> {
+    Ok(Json(RuntimeReport::capture(&Handle::current())))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestGateway;
+    use axum::http::{Method, StatusCode};
+
+    #[tokio::test]
+    async fn test_runtime_report() {
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let waiting = tokio::spawn(rx);
+        let report = RuntimeReport::capture(&Handle::current());
+        assert_eq!(report.workers, 1);
+        assert!(report.alive_tasks >= 1);
+        assert_eq!(report.tokio_unstable, cfg!(tokio_unstable));
+        tx.send(()).unwrap();
+        waiting.await.unwrap().unwrap();
+
+        let gw = TestGateway::new();
+        assert_eq!(gw.get("/debug/runtime").await.status, StatusCode::UNAUTHORIZED);
+        let resp = gw.admin(Method::GET, "/debug/runtime", None::<&()>).await;
+        assert_eq!(resp.status, StatusCode::OK);
+        assert_eq!(resp.body["workers"], 1);
+    }
+}
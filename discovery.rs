@@ -0,0 +1,513 @@
+//! Agent discovery from a service registry
+//!
+//! With `DISCOVERY_SOURCE` set, a background task periodically imports agent
+//! identities from Consul or Kubernetes into the agent directory, so agents no
+//! longer have to be synced by hand:
+//!
+//! - **consul**: instances of `DISCOVERY_SERVICE` in the Consul catalog. The
+//!   agent id is the `agent_id` service meta key, falling back to the service
+//!   id; `team`, `public_key`, and `recipient_class` are read from service meta.
+//! - **kubernetes**: pods in `DISCOVERY_NAMESPACE` matching the label selector
+//!   `DISCOVERY_SELECTOR`. The agent id is the `agent-gateway/agent-id` label
+//!   or annotation, falling back to the pod name; `agent-gateway/team`,
+//!   `agent-gateway/public-key`, and `agent-gateway/recipient-class` are read
+//!   the same way.
+//!
+//! Each sync reconciles the directory with the registry. A newly seen agent is
+//! added, and a registered team becomes its owner. An agent that has left the
+//! registry is retired: it is soft-deleted like `DELETE /agents/{id}` and
+//! purged once the retention period elapses. If it returns before then, it
+//! is restored. Agents deleted by an admin are never restored by discovery.
+//! Retirements and restorations are replicated to a standby like their
+//! admin counterparts.
+//!
+//! A failed fetch changes nothing, so a registry outage cannot retire agents.
+//! Public keys are imported only with `DISCOVERY_IMPORT_KEYS=true` and are
+//! listed in the agent directory.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tracing::warn;
+
+use crate::{
+    env_parse,
+    replication::{Mutation, Replication},
+    InnerState,
+};
+
+/// Service account files mounted into every Kubernetes pod
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// Label and annotation prefix read from Kubernetes pods
+const K8S_PREFIX: &str = "agent-gateway/";
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+/// Registry agents are imported from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscoverySource {
+    Consul,
+    Kubernetes,
+}
+
+impl DiscoverySource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Consul => "consul",
+            Self::Kubernetes => "kubernetes",
+        }
+    }
+}
+
+/// Discovery settings
+#[derive(Debug, Clone)]
+pub struct DiscoveryConfig {
+    /// Registry to sync from; `None` disables discovery
+    pub source: Option<DiscoverySource>,
+    /// Consul HTTP address or Kubernetes API server
+    pub url: String,
+    /// Consul service whose instances are agents
+    pub service: String,
+    /// Kubernetes namespace searched for agent pods
+    pub namespace: String,
+    /// Kubernetes label selector for agent pods; all pods when unset
+    pub selector: Option<String>,
+    /// Consul ACL token or Kubernetes bearer token
+    pub token: Option<String>,
+    /// Time between syncs
+    pub interval: Duration,
+    /// Per-fetch timeout
+    pub timeout: Duration,
+    /// Import public keys into the directory
+    pub import_keys: bool,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            source: None,
+            url: String::new(),
+            service: "agent".to_string(),
+            namespace: "default".to_string(),
+            selector: None,
+            token: None,
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(5),
+            import_keys: false,
+        }
+    }
+}
+
+impl DiscoveryConfig {
+    /// Load settings from `DISCOVERY_*` environment variables
+    ///
+    /// Under Kubernetes the namespace and token default to the pod's service
+    /// account.
+    pub fn from_env() -> Self {
+        let source = match env::var("DISCOVERY_SOURCE").unwrap_or_default().trim() {
+            "" => None,
+            "consul" => Some(DiscoverySource::Consul),
+            "kubernetes" | "k8s" => Some(DiscoverySource::Kubernetes),
+            other => {
+                warn!(event = "config_invalid", source = %other, "Unknown DISCOVERY_SOURCE, discovery disabled");
+                None
+            }
+        };
+        let mut cfg = Self {
+            source,
+            token: env_parse("DISCOVERY_TOKEN"),
+            selector: env_parse("DISCOVERY_SELECTOR"),
+            import_keys: env_parse::<String>("DISCOVERY_IMPORT_KEYS").is_some_and(|v| v == "true" || v == "1"),
+            ..Self::default()
+        };
+        match source {
+            Some(DiscoverySource::Consul) => {
+                cfg.url = env_parse("DISCOVERY_URL").unwrap_or_else(|| "http://127.0.0.1:8500".to_string());
+            }
+            Some(DiscoverySource::Kubernetes) => {
+                cfg.url = env_parse("DISCOVERY_URL").unwrap_or_else(|| "https://kubernetes.default.svc".to_string());
+                cfg.token = cfg.token.or_else(|| service_account_file("token"));
+            }
+            None => {}
+        }
+        if let Some(service) = env_parse("DISCOVERY_SERVICE") {
+            cfg.service = service;
+        }
+        if let Some(namespace) = env_parse("DISCOVERY_NAMESPACE").or_else(|| service_account_file("namespace")) {
+            cfg.namespace = namespace;
+        }
+        if let Some(sec) = env_parse::<u64>("DISCOVERY_INTERVAL_SEC") {
+            cfg.interval = Duration::from_secs(sec.max(1));
+        }
+        if let Some(ms) = env_parse::<u64>("DISCOVERY_TIMEOUT_MS") {
+            cfg.timeout = Duration::from_millis(ms);
+        }
+        cfg
+    }
+}
+
+fn service_account_file(name: &str) -> Option<String> {
+    fs::read_to_string(format!("{SERVICE_ACCOUNT_DIR}/{name}"))
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+// =============================================================================
+// Registry responses
+// =============================================================================
+
+/// An agent as listed by the registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub agent_id: String,
+    pub team: Option<String>,
+    pub public_key: Option<String>,
+    /// Recipient class selecting the policy pipeline for sends to the agent
+    pub recipient_class: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ConsulService {
+    #[serde(rename = "ServiceID")]
+    service_id: String,
+    #[serde(default)]
+    service_meta: HashMap<String, String>,
+}
+
+fn consul_identities(body: &str) -> Result<Vec<Identity>, String> {
+    let services: Vec<ConsulService> = serde_json::from_str(body).map_err(|e| e.to_string())?;
+    Ok(services
+        .into_iter()
+        .map(|mut s| Identity {
+            agent_id: s
+                .service_meta
+                .remove("agent_id")
+                .filter(|id| !id.is_empty())
+                .unwrap_or(s.service_id),
+            team: s.service_meta.remove("team").filter(|t| !t.is_empty()),
+            public_key: s.service_meta.remove("public_key").filter(|k| !k.is_empty()),
+            recipient_class: s.service_meta.remove("recipient_class").filter(|c| !c.is_empty()),
+        })
+        .collect())
+}
+
+#[derive(Deserialize)]
+struct PodList {
+    items: Vec<Pod>,
+}
+
+#[derive(Deserialize)]
+struct Pod {
+    metadata: PodMetadata,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PodMetadata {
+    name: String,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+    #[serde(default)]
+    deletion_timestamp: Option<String>,
+}
+
+impl PodMetadata {
+    fn get(&self, key: &str) -> Option<String> {
+        let key = format!("{K8S_PREFIX}{key}");
+        self.labels
+            .get(&key)
+            .or_else(|| self.annotations.get(&key))
+            .filter(|v| !v.is_empty())
+            .cloned()
+    }
+}
+
+fn kubernetes_identities(body: &str) -> Result<Vec<Identity>, String> {
+    let pods: PodList = serde_json::from_str(body).map_err(|e| e.to_string())?;
+    Ok(pods
+        .items
+        .into_iter()
+        // Terminating pods are leaving the registry
+        .filter(|p| p.metadata.deletion_timestamp.is_none())
+        .map(|p| Identity {
+            agent_id: p.metadata.get("agent-id").unwrap_or_else(|| p.metadata.name.clone()),
+            team: p.metadata.get("team"),
+            public_key: p.metadata.get("public-key"),
+            recipient_class: p.metadata.get("recipient-class"),
+        })
+        .collect())
+}
+
+// =============================================================================
+// Sync
+// =============================================================================
+
+/// Discovery record kept in the agent directory
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscoveredAgent {
+    pub source: DiscoverySource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recipient_class: Option<String>,
+    /// Unix timestamp of the last sync that listed the agent
+    pub seen_at: u64,
+    /// Unix timestamp at which discovery soft-deleted the agent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retired_at: Option<u64>,
+}
+
+/// Directory changes made by one sync
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SyncChanges {
+    pub added: Vec<String>,
+    pub retired: Vec<String>,
+    pub restored: Vec<String>,
+    /// Agents whose owning team changed: (agent_id, team)
+    pub reassigned: Vec<(String, String)>,
+}
+
+/// Reconcile the directory with the identities the registry listed at `now`,
+/// recording deletions and restorations for `replication`; call while
+/// holding the state write lock
+pub fn apply(
+    st: &mut InnerState,
+    replication: &Replication,
+    source: DiscoverySource,
+    found: Vec<Identity>,
+    now: u64,
+) -> SyncChanges {
+    let mut changes = SyncChanges::default();
+    let mut seen = HashSet::new();
+    for identity in found {
+        if !seen.insert(identity.agent_id.clone()) {
+            continue;
+        }
+        let agent_id = identity.agent_id;
+        let record = st.discovered.entry(agent_id.clone()).or_insert_with(|| {
+            changes.added.push(agent_id.clone());
+            DiscoveredAgent {
+                source,
+                public_key: None,
+                recipient_class: None,
+                seen_at: now,
+                retired_at: None,
+            }
+        });
+        record.seen_at = now;
+        record.public_key = identity.public_key;
+        record.recipient_class = identity.recipient_class;
+        if record.retired_at.take().is_some() && st.deleted_agents.remove(&agent_id).is_some() {
+            replication.record(Mutation::AgentRestored { agent_id: agent_id.clone() });
+            changes.restored.push(agent_id.clone());
+        }
+        if let Some(team) = identity.team {
+            if st.owners.get(&agent_id) != Some(&team) {
+                st.owners.insert(agent_id.clone(), team.clone());
+                changes.reassigned.push((agent_id, team));
+            }
+        }
+    }
+
+    let gone: Vec<String> = st
+        .discovered
+        .iter()
+        .filter(|(id, record)| record.retired_at.is_none() && !seen.contains(*id))
+        .map(|(id, _)| id.clone())
+        .collect();
+    for agent_id in gone {
+        if st.is_deleted(&agent_id) {
+            // Deleted by an admin; discovery no longer manages it
+            st.discovered.remove(&agent_id);
+            continue;
+        }
+        st.deleted_agents.insert(agent_id.clone(), now);
+        replication.record(Mutation::AgentDeleted {
+            agent_id: agent_id.clone(),
+            ts: now,
+        });
+        if let Some(record) = st.discovered.get_mut(&agent_id) {
+            record.retired_at = Some(now);
+        }
+        changes.retired.push(agent_id);
+    }
+    changes
+}
+
+/// Counters exported via `/metrics`
+#[derive(Debug, Default)]
+pub struct DiscoveryCounters {
+    pub syncs_ok: AtomicU64,
+    pub syncs_error: AtomicU64,
+    /// Agents listed by the last successful sync
+    pub agents: AtomicU64,
+}
+
+/// Registry client
+pub struct Discovery {
+    config: DiscoveryConfig,
+    client: reqwest::Client,
+    pub counters: DiscoveryCounters,
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Self::new(DiscoveryConfig::default())
+    }
+}
+
+impl Discovery {
+    pub fn new(config: DiscoveryConfig) -> Self {
+        let mut builder = reqwest::Client::builder().timeout(config.timeout);
+        if config.source == Some(DiscoverySource::Kubernetes) {
+            let ca = fs::read(format!("{SERVICE_ACCOUNT_DIR}/ca.crt")).ok();
+            if let Some(cert) = ca.and_then(|pem| reqwest::Certificate::from_pem(&pem).ok()) {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        Self {
+            client: builder.build().unwrap_or_default(),
+            config,
+            counters: DiscoveryCounters::default(),
+        }
+    }
+
+    pub fn config(&self) -> &DiscoveryConfig {
+        &self.config
+    }
+
+    /// List the agents currently in the registry
+    pub async fn fetch(&self) -> Result<Vec<Identity>, String> {
+        let Some(source) = self.config.source else {
+            return Ok(Vec::new());
+        };
+        let result = self.fetch_from(source).await;
+        match &result {
+            Ok(found) => {
+                self.counters.syncs_ok.fetch_add(1, Ordering::Relaxed);
+                self.counters.agents.store(found.len() as u64, Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.counters.syncs_error.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        result
+    }
+
+    async fn fetch_from(&self, source: DiscoverySource) -> Result<Vec<Identity>, String> {
+        let base = self.config.url.trim_end_matches('/');
+        let request = match source {
+            DiscoverySource::Consul => {
+                let call = self.client.get(format!("{base}/v1/catalog/service/{}", self.config.service));
+                match &self.config.token {
+                    Some(token) => call.header("X-Consul-Token", token),
+                    None => call,
+                }
+            }
+            DiscoverySource::Kubernetes => {
+                let mut call = self
+                    .client
+                    .get(format!("{base}/api/v1/namespaces/{}/pods", self.config.namespace));
+                if let Some(selector) = &self.config.selector {
+                    call = call.query(&[("labelSelector", selector)]);
+                }
+                match &self.config.token {
+                    Some(token) => call.bearer_auth(token),
+                    None => call,
+                }
+            }
+        };
+        let body = request
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?
+            .text()
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut found = match source {
+            DiscoverySource::Consul => consul_identities(&body)?,
+            DiscoverySource::Kubernetes => kubernetes_identities(&body)?,
+        };
+        if !self.config.import_keys {
+            for identity in &mut found {
+                identity.public_key = None;
+            }
+        }
+        Ok(found)
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_parsing_and_reconcile() {
+        let consul = consul_identities(
+            r#"[
+                {"ServiceID": "agent-1", "ServiceMeta": {"team": "red", "public_key": "MCowBQ"}},
+                {"ServiceID": "web-7", "ServiceMeta": {"agent_id": "b", "recipient_class": "human"}}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(consul[0].agent_id, "agent-1");
+        assert_eq!(consul[0].public_key.as_deref(), Some("MCowBQ"));
+        assert_eq!((consul[1].agent_id.as_str(), consul[1].team.as_deref()), ("b", None));
+        assert_eq!(consul[1].recipient_class.as_deref(), Some("human"));
+
+        let pods = kubernetes_identities(
+            r#"{"items": [
+                {"metadata": {"name": "pod-a", "labels": {"agent-gateway/team": "blue"},
+                              "annotations": {"agent-gateway/agent-id": "a"}}},
+                {"metadata": {"name": "pod-b", "deletionTimestamp": "2026-01-01T00:00:00Z"}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(pods.len(), 1);
+        assert_eq!((pods[0].agent_id.as_str(), pods[0].team.as_deref()), ("a", Some("blue")));
+
+        let mut st = InnerState::default();
+        let repl = Replication::default();
+        let changes = apply(&mut st, &repl, DiscoverySource::Consul, consul.clone(), 100);
+        assert_eq!(changes.added.len(), 2);
+        assert_eq!(changes.reassigned, vec![("agent-1".to_string(), "red".to_string())]);
+        assert_eq!(st.owners["agent-1"], "red");
+        assert_eq!(st.discovered["b"].recipient_class.as_deref(), Some("human"));
+
+        // Leaving the registry retires; returning before the purge restores
+        let changes = apply(&mut st, &repl, DiscoverySource::Consul, consul[..1].to_vec(), 200);
+        assert_eq!(changes.retired, vec!["b".to_string()]);
+        assert_eq!(st.deleted_agents["b"], 200);
+        let changes = apply(&mut st, &repl, DiscoverySource::Consul, consul.clone(), 300);
+        assert_eq!(changes.restored, vec!["b".to_string()]);
+        assert!(changes.added.is_empty() && !st.is_deleted("b"));
+        // Both are replicated
+        let replicated = repl.log.since(&repl.log.run(), 0).unwrap();
+        assert!(matches!(&replicated[0].1, Mutation::AgentDeleted { agent_id, ts: 200 } if agent_id == "b"));
+        assert!(matches!(&replicated[1].1, Mutation::AgentRestored { agent_id } if agent_id == "b"));
+
+        // An agent deleted by an admin stays deleted and is no longer tracked
+        st.deleted_agents.insert("agent-1".into(), 350);
+        apply(&mut st, &repl, DiscoverySource::Consul, consul[1..].to_vec(), 400);
+        assert!(!st.discovered.contains_key("agent-1"));
+        let changes = apply(&mut st, &repl, DiscoverySource::Consul, consul, 500);
+        assert!(changes.restored.is_empty() && st.is_deleted("agent-1"));
+    }
+}
@@ -0,0 +1,130 @@
+//! Documentation artifacts attached to protocol registrations
+//!
+//! `translation_method` only names how reports are produced. Registrations
+//! may also attach the protocol's actual documentation: a markdown spec, a
+//! grammar, worked examples. Artifacts are stored inside the descriptor, so
+//! they replicate with it, and are served by
+//! `GET /protocols/{agent}/{name}/{version}/docs`.
+//!
+//! The gateway stamps each artifact with the SHA-256 of its content at
+//! registration; the `protocol_registered` audit event lists those hashes, so
+//! the documentation an agent registered under can be proven later.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::signing;
+
+/// Most artifacts one protocol may carry
+pub const MAX_DOC_ARTIFACTS: usize = 16;
+
+/// Largest combined size of one protocol's artifacts
+pub const MAX_DOC_BYTES: usize = 256 * 1024;
+
+/// Longest artifact name
+const MAX_NAME_LEN: usize = 128;
+
+/// What an artifact documents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocKind {
+    /// Prose specification, in markdown
+    Markdown,
+    /// Formal grammar, e.g. BNF
+    Grammar,
+    /// Sample messages with their English meaning
+    Example,
+}
+
+/// One documentation artifact
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocArtifact {
+    /// Unique within the protocol, e.g. `spec.md`
+    pub name: String,
+    pub kind: DocKind,
+    pub content: String,
+    /// SHA-256 of `content`, hex; set by the gateway
+    #[serde(default)]
+    pub sha256: String,
+}
+
+/// An artifact as recorded in the audit trail
+#[derive(Debug, Clone, Serialize)]
+pub struct DocDigest<'a> {
+    pub name: &'a str,
+    pub kind: DocKind,
+    pub bytes: usize,
+    pub sha256: &'a str,
+}
+
+impl DocArtifact {
+    pub fn digest(&self) -> DocDigest<'_> {
+        DocDigest {
+            name: &self.name,
+            kind: self.kind,
+            bytes: self.content.len(),
+            sha256: &self.sha256,
+        }
+    }
+}
+
+/// Check names and size limits, then stamp each artifact's hash
+pub fn seal(docs: &mut [DocArtifact]) -> Result<(), String> {
+    if docs.len() > MAX_DOC_ARTIFACTS {
+        return Err(format!("docs: at most {MAX_DOC_ARTIFACTS} artifacts are allowed"));
+    }
+    let mut names = HashSet::new();
+    for doc in docs.iter() {
+        let name = doc.name.trim();
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(format!("docs: artifact names must be 1-{MAX_NAME_LEN} characters"));
+        }
+        if !names.insert(name) {
+            return Err(format!("docs: duplicate artifact name {name:?}"));
+        }
+        if doc.content.trim().is_empty() {
+            return Err(format!("docs: artifact {name:?} is empty"));
+        }
+    }
+    let total: usize = docs.iter().map(|d| d.content.len()).sum();
+    if total > MAX_DOC_BYTES {
+        return Err(format!("docs: {total} bytes exceeds the {MAX_DOC_BYTES}-byte limit"));
+    }
+    for doc in docs {
+        doc.sha256 = signing::content_digest(&doc.content);
+    }
+    Ok(())
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(name: &str, content: &str) -> DocArtifact {
+        DocArtifact {
+            name: name.to_string(),
+            kind: DocKind::Markdown,
+            content: content.to_string(),
+            sha256: "forged".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_seal_checks_and_hashes() {
+        let mut docs = vec![doc("spec.md", "# coord"), doc("grammar.bnf", "<msg> ::= <op> '|' <args>")];
+        seal(&mut docs).unwrap();
+        assert_eq!(docs[0].sha256, signing::content_digest("# coord"));
+        assert_eq!(docs[0].digest().bytes, 7);
+
+        assert!(seal(&mut [doc("a", "x"), doc("a", "y")]).unwrap_err().contains("duplicate"));
+        assert!(seal(&mut [doc(" ", "x")]).is_err());
+        assert!(seal(&mut [doc("a", "  ")]).is_err());
+        assert!(seal(&mut [doc("a", &"x".repeat(MAX_DOC_BYTES + 1))]).is_err());
+        let mut many: Vec<_> = (0..=MAX_DOC_ARTIFACTS).map(|i| doc(&i.to_string(), "x")).collect();
+        assert!(seal(&mut many).is_err());
+    }
+}
@@ -0,0 +1,456 @@
+//! Offboarding of dormant agents
+//!
+//! With `DORMANT_AFTER_DAYS` set, an agent none of whose protocols has been
+//! registered, used, or reported on for that many days is flagged as dormant
+//! (`agent_dormant_flagged`) and its owning team is notified through the alert
+//! channels (`agent_dormant`). The owner then has `DORMANT_CONFIRM_SEC` to
+//! answer:
+//!
+//! - `POST /agents/{agent_id}/dormancy/keep` keeps the agent, logged as
+//!   `agent_dormancy_dismissed`; it is not flagged again until it has been
+//!   silent for another `DORMANT_AFTER_DAYS`
+//! - `POST /agents/{agent_id}/dormancy/confirm` offboards it at once
+//!
+//! Traffic from the agent clears the flag as well (`agent_dormancy_cleared`).
+//! Once the window closes unanswered, or on confirmation, the agent is
+//! offboarded: its protocols and state are written as a JSON export bundle to
+//! `DORMANT_ARCHIVE_DIR` (`agent_archived`, with the bundle's path and
+//! SHA-256), then it is soft-deleted (`agent_offboarded`) and purged after the
+//! usual retention. An agent under investigation hold is never flagged, and
+//! a bundle that cannot be written leaves the agent in place until the next
+//! sweep (`agent_archive_failed`).
+//!
+//! Flags live in memory: a restart flags dormant agents again and restarts
+//! their confirmation window. A standby sweeps nothing.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    path::PathBuf,
+    sync::Mutex,
+    time::Duration,
+};
+use tracing::{info, warn};
+
+use crate::{
+    alerts::AlertKind,
+    env_parse,
+    error::GatewayError,
+    identity::{AuthedAdmin, AuthedCaller},
+    ownership::Caller,
+    raise_alert,
+    reputation::TrackRecord,
+    signing::content_digest,
+    soft_delete_agent, AlertSubject, ApiResponse, AppState, InnerState, ProtocolDescriptor,
+};
+
+/// Default seconds the owner has to answer a flag
+pub const DEFAULT_CONFIRM_SEC: u64 = 7 * 86_400;
+
+/// Seconds between sweeps for dormant agents
+const SWEEP_INTERVAL_SEC: u64 = 3_600;
+
+#[derive(Debug, Clone)]
+pub struct DormancyConfig {
+    /// Seconds of silence before an agent is flagged; 0 disables offboarding
+    pub after_sec: u64,
+    pub confirm_sec: u64,
+    /// Directory export bundles are written to
+    pub archive_dir: PathBuf,
+}
+
+impl Default for DormancyConfig {
+    fn default() -> Self {
+        Self {
+            after_sec: 0,
+            confirm_sec: DEFAULT_CONFIRM_SEC,
+            archive_dir: PathBuf::from("archives"),
+        }
+    }
+}
+
+impl DormancyConfig {
+    /// Load `DORMANT_AFTER_DAYS`, `DORMANT_CONFIRM_SEC`, and
+    /// `DORMANT_ARCHIVE_DIR`
+    pub fn from_env() -> Self {
+        let d = Self::default();
+        let var = |key: &str| env_parse::<u64>(key);
+        Self {
+            after_sec: var("DORMANT_AFTER_DAYS").map_or(d.after_sec, |days| days.saturating_mul(86_400)),
+            confirm_sec: var("DORMANT_CONFIRM_SEC").unwrap_or(d.confirm_sec),
+            archive_dir: env::var("DORMANT_ARCHIVE_DIR").map(PathBuf::from).unwrap_or(d.archive_dir),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.after_sec > 0
+    }
+}
+
+/// An agent flagged as dormant, awaiting its owner
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DormantAgent {
+    pub agent_id: String,
+    /// Last registration, send, or report on any of its protocols
+    pub last_active: u64,
+    pub flagged_at: u64,
+    /// When the agent is offboarded unless its owner keeps it
+    pub offboard_at: u64,
+    /// Who confirmed the offboarding, if anyone did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmed_by: Option<String>,
+}
+
+/// Flagged agents, and when owners last kept theirs
+#[derive(Debug, Default)]
+pub struct Dormancy {
+    config: DormancyConfig,
+    flagged: Mutex<BTreeMap<String, DormantAgent>>,
+    kept: Mutex<HashMap<String, u64>>,
+}
+
+impl Dormancy {
+    pub fn new(config: DormancyConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn config(&self) -> &DormancyConfig {
+        &self.config
+    }
+
+    pub fn flagged(&self) -> Vec<DormantAgent> {
+        self.flagged.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Last activity of `agent_id`, counting its owner keeping it as activity
+    fn last_active(&self, st: &InnerState, agent_id: &str) -> u64 {
+        let kept = self.kept.lock().unwrap().get(agent_id).copied().unwrap_or(0);
+        last_active(st, agent_id).max(kept)
+    }
+}
+
+/// Last registration, send, or report on any protocol of `agent_id`
+fn last_active(st: &InnerState, agent_id: &str) -> u64 {
+    let Some(protocols) = st.protocols.get(agent_id) else {
+        return 0;
+    };
+    protocols
+        .keys()
+        .map(|key| {
+            let report_key = format!("{agent_id}::{key}");
+            let stats = st.protocol_stats.get(&report_key);
+            let registered = stats.map_or(0, |s| s.certified_at());
+            let used = stats.and_then(|s| s.last_used_ts).unwrap_or(0);
+            let reported = st.last_report_ts.get(&report_key).copied().unwrap_or(0);
+            registered.max(used).max(reported)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// One protocol of an archived agent
+#[derive(Debug, Serialize)]
+struct ArchivedProtocol {
+    descriptor: ProtocolDescriptor,
+    registered_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recertified_at: Option<u64>,
+    messages_sent: u64,
+    reports_filed: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_used_ts: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_report_ts: Option<u64>,
+}
+
+/// Export bundle written before an agent is offboarded
+#[derive(Debug, Serialize)]
+struct ArchiveBundle {
+    agent_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    team: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    org: Option<String>,
+    archived_at: u64,
+    last_active: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confirmed_by: Option<String>,
+    protocols: Vec<ArchivedProtocol>,
+    violations: u32,
+    track_record: TrackRecord,
+}
+
+impl ArchiveBundle {
+    fn collect(st: &InnerState, flag: &DormantAgent, now: u64) -> Self {
+        let agent_id = &flag.agent_id;
+        let team = st.owners.get(agent_id).cloned();
+        let org = team.as_ref().and_then(|t| st.team_orgs.get(t)).cloned();
+        let mut protocols: Vec<ArchivedProtocol> = st
+            .protocols
+            .get(agent_id)
+            .into_iter()
+            .flatten()
+            .map(|(key, descriptor)| {
+                let report_key = format!("{agent_id}::{key}");
+                let stats = st.protocol_stats.get(&report_key);
+                ArchivedProtocol {
+                    descriptor: descriptor.clone(),
+                    registered_at: stats.map_or(0, |s| s.registered_at),
+                    recertified_at: stats.and_then(|s| s.recertified_at),
+                    messages_sent: stats.map_or(0, |s| s.messages_sent),
+                    reports_filed: stats.map_or(0, |s| s.reports_filed),
+                    last_used_ts: stats.and_then(|s| s.last_used_ts),
+                    last_report_ts: st.last_report_ts.get(&report_key).copied(),
+                }
+            })
+            .collect();
+        protocols.sort_by(|a, b| (&a.descriptor.name, &a.descriptor.version).cmp(&(&b.descriptor.name, &b.descriptor.version)));
+        Self {
+            agent_id: agent_id.clone(),
+            team,
+            org,
+            archived_at: now,
+            last_active: flag.last_active,
+            confirmed_by: flag.confirmed_by.clone(),
+            protocols,
+            violations: st.violations.get(agent_id).copied().unwrap_or(0),
+            track_record: st.track_records.get(agent_id).cloned().unwrap_or_default(),
+        }
+    }
+}
+
+/// Write the export bundle of a flagged agent; returns its path and SHA-256
+fn archive(state: &AppState, config: &DormancyConfig, flag: &DormantAgent, now: u64) -> Result<(PathBuf, String), String> {
+    let bundle = ArchiveBundle::collect(&state.inner.read().unwrap(), flag, now);
+    let json = serde_json::to_string_pretty(&bundle).map_err(|e| e.to_string())?;
+    fs::create_dir_all(&config.archive_dir).map_err(|e| e.to_string())?;
+    let path = config.archive_dir.join(format!("{}-{now}.json", flag.agent_id));
+    fs::write(&path, &json).map_err(|e| e.to_string())?;
+    Ok((path, content_digest(&json)))
+}
+
+/// Archive and soft-delete a flagged agent; a failed archive keeps it flagged
+async fn offboard(state: &AppState, config: &DormancyConfig, flag: DormantAgent, now: u64) -> Result<(), GatewayError> {
+    let agent_id = flag.agent_id.clone();
+    let (path, sha256) = match archive(state, config, &flag, now) {
+        Ok(archived) => archived,
+        Err(error) => {
+            warn!(agent_id = %agent_id, error = %error, event = "agent_archive_failed", "Dormant agent could not be archived");
+            return Err(GatewayError::Internal(format!("archive failed: {error}")));
+        }
+    };
+    info!(
+        agent_id = %agent_id,
+        path = %path.display(),
+        sha256 = %sha256,
+        event = "agent_archived",
+        "Dormant agent archived"
+    );
+    state.dormancy.flagged.lock().unwrap().remove(&agent_id);
+    state.dormancy.kept.lock().unwrap().remove(&agent_id);
+    let _ = soft_delete_agent(state, &agent_id)?;
+    info!(
+        agent_id = %agent_id,
+        last_active = flag.last_active,
+        confirmed_by = flag.confirmed_by.as_deref(),
+        event = "agent_offboarded",
+        "Dormant agent offboarded"
+    );
+    let detail = format!("{agent_id} was offboarded after a dormancy review; its export bundle is {}", path.display());
+    raise_alert(state, AlertKind::AgentOffboarded, AlertSubject::Agent(&agent_id), &detail).await;
+    Ok(())
+}
+
+/// Flag newly dormant agents, clear active ones, and offboard those whose
+/// window closed
+pub async fn sweep(state: &AppState, config: &DormancyConfig, now: u64) {
+    if !config.enabled() {
+        return;
+    }
+    let mut newly_flagged = Vec::new();
+    let mut due = Vec::new();
+    {
+        let st = state.inner.read().unwrap();
+        let mut flagged = state.dormancy.flagged.lock().unwrap();
+        flagged.retain(|agent_id, flag| {
+            let live = st.protocols.contains_key(agent_id) && !st.is_deleted(agent_id);
+            let active = live && state.dormancy.last_active(&st, agent_id) > flag.last_active;
+            if active || !live || state.holds.active(agent_id) {
+                info!(agent_id = %agent_id, event = "agent_dormancy_cleared", "Dormancy flag cleared");
+                return false;
+            }
+            true
+        });
+        for agent_id in st.protocols.keys() {
+            if st.is_deleted(agent_id) || state.holds.active(agent_id) {
+                continue;
+            }
+            if let Some(flag) = flagged.get(agent_id) {
+                if flag.offboard_at <= now {
+                    due.push(flag.clone());
+                }
+                continue;
+            }
+            let last_active = state.dormancy.last_active(&st, agent_id);
+            if now.saturating_sub(last_active) < config.after_sec {
+                continue;
+            }
+            let flag = DormantAgent {
+                agent_id: agent_id.clone(),
+                last_active,
+                flagged_at: now,
+                offboard_at: now.saturating_add(config.confirm_sec),
+                confirmed_by: None,
+            };
+            flagged.insert(agent_id.clone(), flag.clone());
+            newly_flagged.push(flag);
+        }
+    }
+
+    for flag in newly_flagged {
+        info!(
+            agent_id = %flag.agent_id,
+            last_active = flag.last_active,
+            offboard_at = flag.offboard_at,
+            event = "agent_dormant_flagged",
+            "Agent flagged as dormant"
+        );
+        let detail = format!(
+            "{id} has been silent since {}; it is archived and offboarded at {} unless its owner keeps it with POST /agents/{id}/dormancy/keep",
+            flag.last_active,
+            flag.offboard_at,
+            id = flag.agent_id
+        );
+        raise_alert(state, AlertKind::AgentDormant, AlertSubject::Agent(&flag.agent_id), &detail).await;
+    }
+    for flag in due {
+        let _ = offboard(state, config, flag, now).await;
+    }
+}
+
+/// Sweep for dormant agents every hour while offboarding is enabled
+pub async fn run(state: AppState) {
+    if !state.dormancy.config().enabled() {
+        return;
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(SWEEP_INTERVAL_SEC));
+    loop {
+        interval.tick().await;
+        // A standby follows the primary's deletions
+        if state.replication.is_standby() {
+            continue;
+        }
+        let config = state.dormancy.config().clone();
+        sweep(&state, &config, state.clock.now()).await;
+    }
+}
+
+/// The flag on `agent_id`, if `caller` is an admin or the agent's owner
+fn owned_flag(state: &AppState, caller: &Caller, agent_id: &str) -> Result<(DormantAgent, String), GatewayError> {
+    let owner = state.inner.read().unwrap().owners.get(agent_id).cloned();
+    let by = match caller {
+        Caller::Admin => "admin".to_string(),
+        Caller::Team(team) if owner.as_ref() == Some(team) => format!("team:{team}"),
+        _ => return Err(GatewayError::OutOfScope),
+    };
+    match state.dormancy.flagged.lock().unwrap().get(agent_id) {
+        Some(flag) => Ok((flag.clone(), by)),
+        None => Err(GatewayError::NotFound("Agent not flagged as dormant")),
+    }
+}
+
+/// Keep a flagged agent; it is not flagged again until silent for another
+/// `DORMANT_AFTER_DAYS`
+pub async fn keep(
+    State(state): State<AppState>,
+    AuthedCaller(caller): AuthedCaller,
+    Path(agent_id): Path<String>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let (flag, by) = owned_flag(&state, &caller, &agent_id)?;
+    let now = state.clock.now();
+    state.dormancy.flagged.lock().unwrap().remove(&agent_id);
+    state.dormancy.kept.lock().unwrap().insert(agent_id.clone(), now);
+    info!(
+        agent_id = %agent_id,
+        by = %by,
+        flagged_at = flag.flagged_at,
+        event = "agent_dormancy_dismissed",
+        "Dormant agent kept by its owner"
+    );
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+/// Confirm the offboarding of a flagged agent, archiving and removing it now
+pub async fn confirm(
+    State(state): State<AppState>,
+    AuthedCaller(caller): AuthedCaller,
+    Path(agent_id): Path<String>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let (mut flag, by) = owned_flag(&state, &caller, &agent_id)?;
+    info!(agent_id = %agent_id, by = %by, event = "agent_offboarding_confirmed", "Dormant agent offboarding confirmed");
+    flag.confirmed_by = Some(by);
+    let config = state.dormancy.config().clone();
+    offboard(&state, &config, flag, state.clock.now()).await?;
+    Ok((StatusCode::OK, Json(ApiResponse::success_with_message("Agent archived and offboarded"))))
+}
+
+/// Agents flagged as dormant
+pub async fn list(_: AuthedAdmin, State(state): State<AppState>) -> Json<Vec<DormantAgent>> {
+    Json(state.dormancy.flagged())
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{AgentFixture, ProtocolFixture, TestGateway, ADMIN_TOKEN};
+    use axum::http::Method;
+
+    #[tokio::test]
+    async fn test_dormant_agent_flagged_then_offboarded() {
+        let gw = TestGateway::new();
+        let coord = ProtocolFixture::new("coord", "1.0").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(coord.clone()).reported()).await;
+        gw.setup_agent(AgentFixture::new("b").protocol(coord).reported()).await;
+        let suffix = crate::signing::random_hex::<8>();
+        let config = DormancyConfig {
+            after_sec: 86_400,
+            confirm_sec: 3_600,
+            archive_dir: env::temp_dir().join(format!("gateway-archive-{suffix}")),
+        };
+
+        gw.advance(Duration::from_secs(86_400));
+        sweep(gw.state(), &config, gw.now()).await;
+        let flagged = gw.state().dormancy.flagged();
+        assert_eq!(flagged.len(), 2);
+        assert_eq!(flagged[0].offboard_at, gw.now() + 3_600);
+
+        let kept = gw.call(Method::POST, "/agents/b/dormancy/keep", None::<&()>, Some(ADMIN_TOKEN)).await;
+        assert_eq!(kept.status, StatusCode::OK);
+        gw.advance(Duration::from_secs(3_600));
+        sweep(gw.state(), &config, gw.now()).await;
+        assert!(gw.state().dormancy.flagged().is_empty(), "a is offboarded and b kept");
+
+        let st = gw.state().inner.read().unwrap();
+        assert!(st.is_deleted("a"));
+        assert!(!st.is_deleted("b"));
+        drop(st);
+        let bundle = fs::read_dir(&config.archive_dir).unwrap().next().unwrap().unwrap().path();
+        let bundle: serde_json::Value = serde_json::from_slice(&fs::read(bundle).unwrap()).unwrap();
+        assert_eq!(bundle["agent_id"], "a");
+        assert_eq!(bundle["protocols"][0]["descriptor"]["name"], "coord");
+        fs::remove_dir_all(&config.archive_dir).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+//! Encrypted and opaque-encoded content detection
+//!
+//! Ciphertext wrapped in base64 or hex can pass the English heuristic (long
+//! alphanumeric runs with enough vowels), letting an agent communicate without
+//! any oversight. Every send is therefore scanned for opaque tokens before
+//! language detection:
+//!
+//! - **hex**: 32+ hex digits
+//! - **base64**: 24+ characters of the standard or URL-safe alphabet that mix
+//!   letter case and digits
+//! - **high entropy**: 32+ characters of any kind at 4.5+ bits per character
+//!
+//! Content counts as encrypted when such tokens make up at least half of its
+//! non-whitespace characters, so an English message quoting a hash still
+//! passes. What happens next depends on the policy's `encrypted_content` mode.
+
+use serde::{Deserialize, Serialize};
+
+/// Shortest hex token considered opaque (128 bits)
+const MIN_HEX_LEN: usize = 32;
+/// Shortest base64 token considered opaque
+const MIN_BASE64_LEN: usize = 24;
+/// Shortest token checked for raw entropy
+const MIN_ENTROPY_LEN: usize = 32;
+/// Bits per character above which a token looks random
+const HIGH_ENTROPY_BITS: f64 = 4.5;
+/// Share of non-whitespace content that must be opaque
+const MIN_OPAQUE_SHARE: f64 = 0.5;
+
+/// How encrypted payloads are treated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptedContentPolicy {
+    /// Refuse the message
+    #[default]
+    Deny,
+    /// Allow only under a registered protocol that declares key escrow
+    RequireProtocol,
+    /// Hold the message for admin review
+    Quarantine,
+}
+
+impl EncryptedContentPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "deny" => Some(Self::Deny),
+            "require_protocol" | "require-protocol" => Some(Self::RequireProtocol),
+            "quarantine" => Some(Self::Quarantine),
+            _ => None,
+        }
+    }
+}
+
+/// Encryption declared by a protocol, with where its keys are escrowed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptionMetadata {
+    /// Cipher, e.g. `aes-256-gcm`
+    pub algorithm: String,
+    /// Escrow holding the decryption keys, e.g. a KMS key URI
+    pub key_escrow: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+}
+
+impl EncryptionMetadata {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.algorithm.trim().is_empty() {
+            return Err("encryption.algorithm is required".to_string());
+        }
+        if self.key_escrow.trim().is_empty() {
+            return Err("encryption.key_escrow is required".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Kind of opaque token found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpaqueEncoding {
+    Hex,
+    Base64,
+    HighEntropy,
+}
+
+impl std::fmt::Display for OpaqueEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Hex => "hex",
+            Self::Base64 => "base64",
+            Self::HighEntropy => "high_entropy",
+        })
+    }
+}
+
+/// Opaque content found in a message
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct OpaqueContent {
+    /// Encoding of the longest opaque token
+    pub encoding: OpaqueEncoding,
+    /// Share of non-whitespace characters in opaque tokens
+    pub share: f64,
+}
+
+/// Shannon entropy in bits per character
+pub fn entropy_bits(s: &str) -> f64 {
+    let mut counts = [0u32; 256];
+    for b in s.bytes() {
+        counts[usize::from(b)] += 1;
+    }
+    let len = s.len() as f64;
+    counts
+        .iter()
+        .filter(|c| **c > 0)
+        .map(|c| {
+            let p = f64::from(*c) / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn classify_token(token: &str) -> Option<OpaqueEncoding> {
+    let len = token.len();
+    if len >= MIN_HEX_LEN && token.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Some(OpaqueEncoding::Hex);
+    }
+    let body = token.trim_end_matches('=');
+    let base64_alphabet = body
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'-' | b'_'));
+    let mixed = body.bytes().any(|b| b.is_ascii_uppercase())
+        && body.bytes().any(|b| b.is_ascii_lowercase())
+        && body.bytes().any(|b| b.is_ascii_digit());
+    if len >= MIN_BASE64_LEN && len - body.len() <= 2 && base64_alphabet && mixed {
+        return Some(OpaqueEncoding::Base64);
+    }
+    if len >= MIN_ENTROPY_LEN && entropy_bits(token) >= HIGH_ENTROPY_BITS {
+        return Some(OpaqueEncoding::HighEntropy);
+    }
+    None
+}
+
+/// Detect encrypted or opaque-encoded content
+pub fn detect(content: &str) -> Option<OpaqueContent> {
+    let total: usize = content.split_whitespace().map(str::len).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut opaque = 0;
+    let mut longest: Option<(usize, OpaqueEncoding)> = None;
+    for token in content.split_whitespace() {
+        // Ignore surrounding punctuation such as quotes or a trailing period
+        let token = token.trim_matches(|c: char| matches!(c, '"' | '\'' | '(' | ')' | ',' | '.' | ';' | ':'));
+        if let Some(encoding) = classify_token(token) {
+            opaque += token.len();
+            if longest.is_none_or(|(len, _)| token.len() > len) {
+                longest = Some((token.len(), encoding));
+            }
+        }
+    }
+    let share = opaque as f64 / total as f64;
+    match longest {
+        Some((_, encoding)) if share >= MIN_OPAQUE_SHARE => Some(OpaqueContent { encoding, share }),
+        _ => None,
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_opaque_content() {
+        let b64 = "U2FsdGVkX1+vupppZksvRf5pq5g5XjFRlipRkwB0K1Y96Qsv2Lm+31cmzaAILwyt";
+        assert_eq!(detect(b64).unwrap().encoding, OpaqueEncoding::Base64);
+        assert_eq!(detect(&format!("payload {b64}")).unwrap().encoding, OpaqueEncoding::Base64);
+
+        let hex = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        assert_eq!(detect(hex).unwrap().encoding, OpaqueEncoding::Hex);
+
+        let noise = "k#8Qz!v@2Lw$9Rp%4Tx^7Yb&1Nc*6Md(3Hf)0Gj";
+        assert_eq!(detect(noise).unwrap().encoding, OpaqueEncoding::HighEntropy);
+
+        // English, including English that quotes a hash, is left alone
+        assert!(detect("The shipment arrives on Friday, please confirm.").is_none());
+        assert!(detect(&format!(
+            "Please verify that the release artifact we published this morning matches checksum {hex} before deploying it anywhere"
+        ))
+        .is_none());
+        assert!(detect("X9|d=17;u=0x3f;rt=2;ack#77").is_none());
+        assert!(detect("").is_none());
+
+        assert_eq!(EncryptedContentPolicy::parse("require-protocol"), Some(EncryptedContentPolicy::RequireProtocol));
+        let escrow = EncryptionMetadata {
+            algorithm: "aes-256-gcm".into(),
+            key_escrow: String::new(),
+            key_id: None,
+        };
+        assert!(escrow.validate().is_err());
+    }
+}
@@ -0,0 +1,332 @@
+//! Scheduled enforcement windows
+//!
+//! Some deployments enforce strictly only while people are around to answer
+//! for refused sends, and only audit overnight. The policy's `enforcement`
+//! section switches the enforcement mode on a schedule:
+//!
+//! ```json
+//! {"default": [{"cron": "* 0-7,19-23 * * *", "mode": "audit_only"},
+//!              {"cron": "* * * * 0,6", "mode": "audit_only"}],
+//!  "tenants": {"red": [{"cron": "* 22-23,0-5 * * *", "mode": "audit_only"}]}}
+//! ```
+//!
+//! Each window is a five-field cron expression (`minute hour day-of-month
+//! month day-of-week`, UTC, Sunday is 0 or 7) matching the minutes the mode
+//! applies. The first matching window wins; outside every window the mode is
+//! `enforce`. A team listed under `tenants` follows only its own windows;
+//! every other agent follows `default`.
+//!
+//! In `audit_only` mode a send refused by a compliance check (unregistered or
+//! undeclared protocol, overdue report, schema or encrypted-content
+//! violation, suspension, codebook, supersession) is still logged and counted
+//! as a violation, then accepted with the refusal's code in `waived`. Reports,
+//! quotas, recipient preferences, and decision webhooks are enforced as usual.
+
+use chrono::{DateTime, Datelike, Timelike};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fmt,
+    sync::Mutex,
+};
+use tracing::warn;
+
+/// Schedule key of the windows for agents of unlisted teams
+pub const DEFAULT_SCHEDULE: &str = "default";
+
+/// How far ahead [`EnforcementSchedule::next_change`] looks, in minutes
+const LOOKAHEAD_MIN: u64 = 8 * 24 * 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnforcementMode {
+    #[default]
+    Enforce,
+    AuditOnly,
+}
+
+impl EnforcementMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Enforce => "enforce",
+            Self::AuditOnly => "audit_only",
+        }
+    }
+}
+
+/// A five-field cron expression, kept with its source text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cron {
+    expr: String,
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    /// Whether day-of-month and day-of-week are both restricted, in which
+    /// case either may match
+    either_day: bool,
+}
+
+/// Bit set of the values `field` allows within `min..=max`
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0)),
+            None => (part, Some(1)),
+        };
+        let step = step.ok_or_else(|| format!("bad step in {part:?}"))?;
+        let (lo, hi) = match range {
+            "*" => (min, max),
+            _ => {
+                let (lo, hi) = range.split_once('-').unwrap_or((range, range));
+                let lo: u32 = lo.parse().map_err(|_| format!("bad value in {part:?}"))?;
+                let hi: u32 = hi.parse().map_err(|_| format!("bad value in {part:?}"))?;
+                (lo, hi)
+            }
+        };
+        if lo < min || hi > max || lo > hi {
+            return Err(format!("{part:?} is outside {min}-{max}"));
+        }
+        for value in (lo..=hi).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("cron {expr:?} needs five fields"));
+        };
+        let invalid = |e: String| format!("cron {expr:?}: {e}");
+        let weekdays = parse_field(weekday, 0, 7).map_err(invalid)?;
+        Ok(Self {
+            expr: expr.to_string(),
+            minutes: parse_field(minute, 0, 59).map_err(invalid)?,
+            hours: parse_field(hour, 0, 23).map_err(invalid)? as u32,
+            days: parse_field(day, 1, 31).map_err(invalid)? as u32,
+            months: parse_field(month, 1, 12).map_err(invalid)? as u16,
+            // 7 is Sunday too
+            weekdays: (weekdays | (weekdays >> 7)) as u8 & 0x7f,
+            either_day: day != "*" && weekday != "*",
+        })
+    }
+
+    /// Whether the minute containing `ts` (Unix seconds, UTC) matches
+    pub fn matches(&self, ts: u64) -> bool {
+        let Some(t) = DateTime::from_timestamp(ts as i64, 0) else {
+            return false;
+        };
+        let day = self.days & (1 << t.day()) != 0;
+        let weekday = self.weekdays & (1 << t.weekday().num_days_from_sunday()) != 0;
+        self.minutes & (1 << t.minute()) != 0
+            && self.hours & (1 << t.hour()) != 0
+            && self.months & (1 << t.month()) != 0
+            && if self.either_day { day || weekday } else { day && weekday }
+    }
+}
+
+impl TryFrom<String> for Cron {
+    type Error = String;
+
+    fn try_from(expr: String) -> Result<Self, String> {
+        Self::parse(&expr)
+    }
+}
+
+impl From<Cron> for String {
+    fn from(cron: Cron) -> Self {
+        cron.expr
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expr)
+    }
+}
+
+/// Minutes in which a mode applies
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Window {
+    pub cron: Cron,
+    pub mode: EnforcementMode,
+}
+
+/// Enforcement windows, by default and per team
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EnforcementSchedule {
+    #[serde(default)]
+    pub default: Vec<Window>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tenants: BTreeMap<String, Vec<Window>>,
+}
+
+impl EnforcementSchedule {
+    /// Parse `ENFORCEMENT_SCHEDULE` (JSON); `None` when unset or invalid
+    pub fn from_env() -> Option<Self> {
+        let raw = env::var("ENFORCEMENT_SCHEDULE").ok().filter(|v| !v.trim().is_empty())?;
+        match serde_json::from_str::<Self>(&raw).map_err(|e| e.to_string()).and_then(|s| s.validate().map(|_| s)) {
+            Ok(schedule) => Some(schedule),
+            Err(e) => {
+                warn!(event = "config_invalid", error = %e, "Ignoring invalid ENFORCEMENT_SCHEDULE");
+                None
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(team) = self.tenants.keys().find(|t| t.trim().is_empty() || *t == DEFAULT_SCHEDULE) {
+            return Err(format!("enforcement.tenants may not be keyed {team:?}"));
+        }
+        Ok(())
+    }
+
+    /// Windows that apply to agents of `team`
+    fn windows(&self, team: Option<&str>) -> &[Window] {
+        team.and_then(|t| self.tenants.get(t)).unwrap_or(&self.default)
+    }
+
+    /// Schedule key `team` follows: the team when listed, otherwise `default`
+    pub fn schedule_for<'a>(&self, team: Option<&'a str>) -> &'a str {
+        match team {
+            Some(t) if self.tenants.contains_key(t) => t,
+            _ => DEFAULT_SCHEDULE,
+        }
+    }
+
+    /// Mode in force for agents of `team` at `ts`
+    pub fn mode_at(&self, team: Option<&str>, ts: u64) -> EnforcementMode {
+        self.windows(team)
+            .iter()
+            .find(|w| w.cron.matches(ts))
+            .map_or(EnforcementMode::Enforce, |w| w.mode)
+    }
+
+    /// Start of the first minute after `ts` with a different mode for
+    /// `team`, if one comes within eight days
+    pub fn next_change(&self, team: Option<&str>, ts: u64) -> Option<u64> {
+        let current = self.mode_at(team, ts);
+        let minute = ts - ts % 60;
+        (1..=LOOKAHEAD_MIN)
+            .map(|n| minute + n * 60)
+            .find(|&t| self.mode_at(team, t) != current)
+    }
+
+    /// Every schedule key: `default`, then each listed team
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(DEFAULT_SCHEDULE).chain(self.tenants.keys().map(String::as_str))
+    }
+}
+
+/// Mode of one schedule key at a point in time
+#[derive(Debug, Clone, Serialize)]
+pub struct ModeStatus {
+    pub mode: EnforcementMode,
+    /// When the mode next changes, if within eight days
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_change: Option<u64>,
+}
+
+/// A change of mode seen by [`ModeTracker::update`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub schedule: String,
+    pub from: EnforcementMode,
+    pub to: EnforcementMode,
+}
+
+/// Last mode seen per schedule key, to log each transition once
+#[derive(Debug, Default)]
+pub struct ModeTracker {
+    modes: Mutex<BTreeMap<String, EnforcementMode>>,
+}
+
+impl ModeTracker {
+    /// Modes under `schedule` at `now` that differ from the last update;
+    /// keys no longer scheduled fall back to `enforce`
+    pub fn update(&self, schedule: Option<&EnforcementSchedule>, now: u64) -> Vec<Transition> {
+        let current: BTreeMap<String, EnforcementMode> = schedule
+            .map(|s| {
+                s.keys()
+                    .map(|key| (key.to_string(), s.mode_at(Some(key), now)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut modes = self.modes.lock().unwrap();
+        let mut transitions = Vec::new();
+        for key in modes.keys().chain(current.keys()).collect::<BTreeSet<_>>() {
+            let from = modes.get(key).copied().unwrap_or_default();
+            let to = current.get(key).copied().unwrap_or_default();
+            if from != to {
+                transitions.push(Transition { schedule: key.clone(), from, to });
+            }
+        }
+        *modes = current;
+        transitions
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-07-01 00:00:00 UTC, a Monday
+    const MONDAY: u64 = 1_719_792_000;
+
+    fn window(cron: &str, mode: EnforcementMode) -> Window {
+        Window { cron: Cron::parse(cron).unwrap(), mode }
+    }
+
+    #[test]
+    fn test_cron() {
+        let nights = Cron::parse("* 0-7,19-23 * * *").unwrap();
+        assert!(nights.matches(MONDAY + 3 * 3600));
+        assert!(!nights.matches(MONDAY + 12 * 3600));
+        let weekends = Cron::parse("*/15 * * * 0,6").unwrap();
+        assert!(weekends.matches(MONDAY - 3600 + 30 * 60), "Sunday 23:30");
+        assert!(!weekends.matches(MONDAY - 3600 + 31 * 60));
+        assert!(Cron::parse("* 7 * * 7").unwrap().matches(MONDAY - 17 * 3600), "7 is Sunday");
+        assert!(Cron::parse("* 24 * * *").is_err());
+        assert!(Cron::parse("* * *").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+    }
+
+    #[test]
+    fn test_schedule_and_transitions() {
+        let schedule = EnforcementSchedule {
+            default: vec![window("* 0-7 * * *", EnforcementMode::AuditOnly)],
+            tenants: BTreeMap::from([("red".to_string(), vec![])]),
+        };
+        assert_eq!(schedule.mode_at(None, MONDAY), EnforcementMode::AuditOnly);
+        assert_eq!(schedule.mode_at(Some("blue"), MONDAY), EnforcementMode::AuditOnly);
+        assert_eq!(schedule.mode_at(Some("red"), MONDAY), EnforcementMode::Enforce);
+        assert_eq!(schedule.schedule_for(Some("blue")), DEFAULT_SCHEDULE);
+        assert_eq!(schedule.next_change(None, MONDAY + 90), Some(MONDAY + 8 * 3600));
+        assert_eq!(schedule.next_change(Some("red"), MONDAY), None);
+
+        let tracker = ModeTracker::default();
+        let started = tracker.update(Some(&schedule), MONDAY);
+        assert_eq!(
+            started,
+            [Transition {
+                schedule: DEFAULT_SCHEDULE.to_string(),
+                from: EnforcementMode::Enforce,
+                to: EnforcementMode::AuditOnly,
+            }]
+        );
+        assert!(tracker.update(Some(&schedule), MONDAY + 60).is_empty());
+        let ended = tracker.update(None, MONDAY + 120);
+        assert_eq!(ended.len(), 1);
+        assert_eq!(ended[0].to, EnforcementMode::Enforce);
+    }
+}
@@ -0,0 +1,342 @@
+//! Language-detector ensemble with weighted voting
+//!
+//! With `DETECTOR_ENSEMBLE` set, several detectors vote on every message
+//! instead of one deciding alone:
+//!
+//! - **heuristic**: the local `looks_like_english` check (1 or 0)
+//! - **ngram**: share of letter bigrams among the most common English ones
+//! - **entropy**: Shannon entropy in bits per character
+//! - **classifier**: the external classifier at `DETECTOR_URL`, using its
+//!   `confidence` when it returns one (1 or 0 otherwise)
+//!
+//! Raw scores are on different scales, so each detector's score is calibrated
+//! into a probability that the message is English with
+//! `1 / (1 + e^-((raw - midpoint) / scale))`. A negative `scale` means higher
+//! raw scores are less English, as for entropy. The ensemble score is the
+//! weight-averaged probability; at or above `threshold` the message is
+//! English. For example:
+//!
+//! `{"threshold": 0.5, "detectors": {"heuristic": {"weight": 1}, "ngram": {"weight": 2}, "classifier": {"weight": 3}}}`
+//!
+//! Only the detectors listed vote. Unset `weight`, `midpoint`, and `scale`
+//! take the detector's defaults. A classifier that cannot be reached abstains,
+//! and the remaining detectors decide unless `DETECTOR_FALLBACK` says
+//! otherwise. Every ballot is logged as a `language_ensemble` audit event with
+//! the per-detector votes, so a misclassification can be traced to the
+//! detector responsible.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tracing::warn;
+
+use crate::{encryption::entropy_bits, looks_like_english};
+
+/// Most frequent letter bigrams in English text
+const COMMON_BIGRAMS: &[&str] = &[
+    "th", "he", "in", "er", "an", "re", "on", "at", "en", "nd", "ti", "es", "or", "te", "of",
+    "ed", "is", "it", "al", "ar", "st", "to", "nt", "ng", "se", "ha", "as", "ou", "io", "le",
+    "ve", "co", "me", "de", "hi", "ri", "ro", "ic", "ne", "ea", "ra", "ce", "li", "ch", "ll",
+    "be", "ma", "si", "om", "ur",
+];
+
+/// Fewest letter bigrams the n-gram detector needs to vote
+const MIN_BIGRAMS: usize = 4;
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+/// A detector that can take part in the ensemble
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Voter {
+    Heuristic,
+    Ngram,
+    Entropy,
+    Classifier,
+}
+
+impl Voter {
+    pub const ALL: [Self; 4] = [Self::Heuristic, Self::Ngram, Self::Entropy, Self::Classifier];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Heuristic => "heuristic",
+            Self::Ngram => "ngram",
+            Self::Entropy => "entropy",
+            Self::Classifier => "classifier",
+        }
+    }
+
+    fn default_calibration(self) -> Calibration {
+        let (midpoint, scale) = match self {
+            Self::Heuristic => (0.5, 0.25),
+            // Already a probability; keep it close to what the classifier said
+            Self::Classifier => (0.5, 0.1),
+            Self::Ngram => (0.3, 0.07),
+            Self::Entropy => (4.6, -0.25),
+        };
+        Calibration {
+            weight: 1.0,
+            midpoint,
+            scale,
+        }
+    }
+}
+
+/// Weight and score normalization of one detector
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Calibration {
+    pub weight: f64,
+    /// Raw score at which the detector is undecided
+    pub midpoint: f64,
+    /// Raw-score distance that moves the probability by one logit
+    pub scale: f64,
+}
+
+impl Calibration {
+    /// Probability that content with `raw` score is English
+    pub fn normalize(&self, raw: f64) -> f64 {
+        1.0 / (1.0 + (-(raw - self.midpoint) / self.scale).exp())
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CalibrationOverride {
+    weight: Option<f64>,
+    midpoint: Option<f64>,
+    scale: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VoterOverrides {
+    heuristic: Option<CalibrationOverride>,
+    ngram: Option<CalibrationOverride>,
+    entropy: Option<CalibrationOverride>,
+    classifier: Option<CalibrationOverride>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnsembleDocument {
+    #[serde(default = "default_threshold")]
+    threshold: f64,
+    detectors: VoterOverrides,
+}
+
+fn default_threshold() -> f64 {
+    0.5
+}
+
+/// `DETECTOR_ENSEMBLE` settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnsembleConfig {
+    /// Ensemble score at or above which content is English
+    pub threshold: f64,
+    /// Voting detectors, in evaluation order
+    pub voters: Vec<(Voter, Calibration)>,
+}
+
+impl EnsembleConfig {
+    /// Parse a `DETECTOR_ENSEMBLE` document
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let doc: EnsembleDocument = serde_json::from_str(raw).map_err(|e| e.to_string())?;
+        if !(0.0..=1.0).contains(&doc.threshold) {
+            return Err("threshold must be within [0, 1]".to_string());
+        }
+        let overrides = [
+            (Voter::Heuristic, doc.detectors.heuristic),
+            (Voter::Ngram, doc.detectors.ngram),
+            (Voter::Entropy, doc.detectors.entropy),
+            (Voter::Classifier, doc.detectors.classifier),
+        ];
+        let mut voters = Vec::new();
+        for (voter, cal) in overrides {
+            let Some(cal) = cal else { continue };
+            let d = voter.default_calibration();
+            let cal = Calibration {
+                weight: cal.weight.unwrap_or(d.weight),
+                midpoint: cal.midpoint.unwrap_or(d.midpoint),
+                scale: cal.scale.unwrap_or(d.scale),
+            };
+            if cal.weight < 0.0 || !cal.weight.is_finite() || cal.scale == 0.0 || !cal.scale.is_finite() {
+                return Err(format!("{}: weight must be non-negative and scale non-zero", voter.as_str()));
+            }
+            voters.push((voter, cal));
+        }
+        if voters.iter().all(|(_, cal)| cal.weight == 0.0) {
+            return Err("at least one detector needs a positive weight".to_string());
+        }
+        Ok(Self {
+            threshold: doc.threshold,
+            voters,
+        })
+    }
+
+    /// Load from `DETECTOR_ENSEMBLE`; unset or invalid leaves the ensemble off
+    pub fn from_env() -> Option<Self> {
+        let raw = env::var("DETECTOR_ENSEMBLE").ok().filter(|v| !v.trim().is_empty())?;
+        Self::parse(&raw)
+            .inspect_err(|e| {
+                warn!(event = "config_invalid", error = %e, "Invalid DETECTOR_ENSEMBLE, ensemble disabled")
+            })
+            .ok()
+    }
+
+    pub fn includes(&self, voter: Voter) -> bool {
+        self.voters.iter().any(|(v, _)| *v == voter)
+    }
+}
+
+// =============================================================================
+// Voting
+// =============================================================================
+
+/// Share of letter bigrams found among [`COMMON_BIGRAMS`], or `None` when the
+/// content has too few letters to judge
+pub fn bigram_score(content: &str) -> Option<f64> {
+    let lower = content.to_ascii_lowercase();
+    let mut total = 0usize;
+    let mut common = 0usize;
+    for word in lower.split(|c: char| !c.is_ascii_alphabetic()) {
+        for pair in word.as_bytes().windows(2) {
+            total += 1;
+            if COMMON_BIGRAMS.iter().any(|b| b.as_bytes() == pair) {
+                common += 1;
+            }
+        }
+    }
+    (total >= MIN_BIGRAMS).then(|| common as f64 / total as f64)
+}
+
+/// One detector's vote
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Vote {
+    pub detector: Voter,
+    /// Score on the detector's own scale
+    pub raw: f64,
+    /// Calibrated probability that the content is English
+    pub confidence: f64,
+    pub weight: f64,
+}
+
+/// All votes on one message and the combined result
+#[derive(Debug, Clone, Serialize)]
+pub struct Ballot {
+    pub score: f64,
+    pub threshold: f64,
+    pub is_english: bool,
+    pub votes: Vec<Vote>,
+    /// Detectors that could not vote, e.g. an unreachable classifier
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub abstained: Vec<Voter>,
+}
+
+impl Ballot {
+    /// Voters whose own vote went against the ensemble
+    pub fn dissenters(&self) -> impl Iterator<Item = Voter> + '_ {
+        self.votes
+            .iter()
+            .filter(|v| v.weight > 0.0 && (v.confidence >= 0.5) != self.is_english)
+            .map(|v| v.detector)
+    }
+}
+
+/// Counters exported via `/metrics`
+#[derive(Debug, Default)]
+pub struct EnsembleCounters {
+    /// Votes against the ensemble's verdict, by [`Voter`]
+    pub dissents: [AtomicU64; Voter::ALL.len()],
+}
+
+impl EnsembleConfig {
+    /// Tally the local detectors plus the classifier's English probability,
+    /// `None` when the classifier abstains or is not part of the ensemble
+    pub fn vote(&self, content: &str, classifier: Option<f64>, counters: &EnsembleCounters) -> Ballot {
+        let mut votes = Vec::new();
+        let mut abstained = Vec::new();
+        for &(voter, cal) in &self.voters {
+            let raw = match voter {
+                Voter::Heuristic => Some(if looks_like_english(content) { 1.0 } else { 0.0 }),
+                Voter::Ngram => bigram_score(content),
+                Voter::Entropy => Some(entropy_bits(content)),
+                Voter::Classifier => classifier,
+            };
+            match raw {
+                Some(raw) => votes.push(Vote {
+                    detector: voter,
+                    raw,
+                    confidence: cal.normalize(raw),
+                    weight: cal.weight,
+                }),
+                None => abstained.push(voter),
+            }
+        }
+        let weight: f64 = votes.iter().map(|v| v.weight).sum();
+        // Nobody left to vote: undecided
+        let score = if weight > 0.0 {
+            votes.iter().map(|v| v.weight * v.confidence).sum::<f64>() / weight
+        } else {
+            0.5
+        };
+        let ballot = Ballot {
+            score,
+            threshold: self.threshold,
+            is_english: score >= self.threshold,
+            votes,
+            abstained,
+        };
+        for voter in ballot.dissenters() {
+            counters.dissents[voter as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        ballot
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ensemble_votes_and_calibration() {
+        let cfg = EnsembleConfig::parse(
+            r#"{"detectors": {"heuristic": {}, "ngram": {"weight": 2}, "entropy": {"weight": 0.5}, "classifier": {"weight": 3}}}"#,
+        )
+        .unwrap();
+        assert_eq!(cfg.threshold, 0.5);
+        assert_eq!(cfg.voters[1], (Voter::Ngram, Calibration { weight: 2.0, midpoint: 0.3, scale: 0.07 }));
+        assert!(EnsembleConfig::parse(r#"{"detectors": {"magic": {}}}"#).is_err());
+        assert!(EnsembleConfig::parse(r#"{"detectors": {"ngram": {"weight": 0}}}"#).is_err());
+
+        // Entropy calibrates inversely: more bits, less English
+        let entropy = Voter::Entropy.default_calibration();
+        assert!(entropy.normalize(3.8) > 0.9 && entropy.normalize(5.5) < 0.1);
+
+        let counters = EnsembleCounters::default();
+        let english = cfg.vote("The shipment should arrive at the harbor on Friday morning", None, &counters);
+        assert!(english.is_english && english.score > 0.8);
+        assert_eq!(english.abstained, vec![Voter::Classifier]);
+
+        let novel = cfg.vote("X9|f=0x3a;7f;ack#42", Some(0.0), &counters);
+        assert!(!novel.is_english);
+        assert_eq!(novel.votes.len(), 3, "too few bigrams for the n-gram detector");
+        assert!(novel.abstained.contains(&Voter::Ngram));
+
+        // The classifier and n-grams outvote a fooled heuristic and entropy,
+        // and the dissents are counted against them
+        let ballot = cfg.vote("qzx vkj wpf bnm tlk rrs dgh", Some(0.02), &counters);
+        assert!(!ballot.is_english);
+        assert_eq!(ballot.dissenters().collect::<Vec<_>>(), vec![Voter::Heuristic, Voter::Entropy]);
+        assert_eq!(counters.dissents[Voter::Heuristic as usize].load(Ordering::Relaxed), 1);
+        assert_eq!(counters.dissents[Voter::Classifier as usize].load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,496 @@
+//! Typed gateway errors
+//!
+//! Handlers and extractors fail with a [`GatewayError`]. Its [`IntoResponse`]
+//! impl is the single place an error becomes an HTTP status and an
+//! [`ApiResponse`] body. The body carries the human-readable `error` message
+//! and a stable `code`, such as `report_overdue`, that clients can match on.
+//! The library exports it as `policy_gateway::error::GatewayError`, so
+//! embedders match on the variant itself. Messages come from the
+//! [`messages`](crate::messages) catalog, phrased for the caller's locale;
+//! `Display` is always the built-in English.
+//!
+//! A refused send or report also lists every reason it was refused for in
+//! `deny_reasons`, each with its `code`, `error`, and `remediation`. A send
+//! failing several checks at once is a [`GatewayError::Denied`]; its first
+//! reason is the primary one, giving the response its status and `code`.
+//!
+//! The codes reuse the `reason` values of the gateway's structured log events,
+//! so a refused request and its log line can be correlated.
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::{fmt, time::Duration};
+
+use crate::{
+    codec::BodyError,
+    language::LanguageOfRecord,
+    maintenance::RouteGroup,
+    messages::{self, Message},
+    quota::Breach,
+    ApiResponse,
+};
+
+/// Why the gateway refused a request
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum GatewayError {
+    /// Admin API disabled because neither `ADMIN_TOKEN` nor `ADMIN_TOKENS` is set
+    AdminDisabled,
+    /// Wrong or missing admin bearer token
+    InvalidAdminToken,
+    /// An admin tried to approve their own proposed action
+    SelfApproval,
+    /// The proposed action outlived `DUAL_CONTROL_TTL_SEC` unapproved
+    ApprovalExpired,
+    /// Read endpoint called without a valid team or admin token
+    Unauthenticated,
+    /// The caller's team does not own the requested agent, team, or org
+    OutOfScope,
+    /// Write attempted with a read-only auditor token
+    ReadOnly,
+    /// The authorization policy grants the caller's role no access to the
+    /// route, e.g. `"GET /stats/slo"`
+    Forbidden(String),
+    /// The agent is soft-deleted; `action` completes "restore it ..."
+    AgentDeleted { action: &'static str },
+    /// Novel-language send or report against a protocol the agent never registered
+    NotRegistered,
+    /// Novel-language send without a protocol declaration
+    MissingProtocol,
+    /// The declared protocol version was superseded by `successor`
+    Superseded { successor: String },
+    /// In the global namespace, `protocol` is defined by another agent,
+    /// its `owner`
+    ProtocolNameTaken { protocol: String, owner: String },
+    /// The agent protocol is suspended for review after repeated held reports
+    ProtocolSuspended,
+    /// The agent protocol is suspended by federated gateway `origin`
+    SuspendedByPeer { origin: String },
+    /// The agent protocol was not recertified within its grace period
+    RecertificationLapsed,
+    /// The agent is on probation and the protocol has no codebook
+    CodebookRequired,
+    /// The message does not match the protocol's message schema; carries
+    /// the validation errors
+    SchemaViolation(String),
+    /// No report within the reporting interval; `seconds` since the last one,
+    /// `None` when the protocol was never reported on
+    ReportOverdue { seconds: Option<u64> },
+    /// Report coverage below the policy minimum
+    CoverageLow { actual: f64, required: f64 },
+    /// English summary shorter than the policy minimum
+    SummaryTooShort { required: usize },
+    /// Summary scored below the minimum quality for its protocol's risk tier
+    SummaryQualityLow { score: f64, required: f64 },
+    /// Summary not written in the agent's language of record
+    SummaryLanguage { expected: String },
+    /// Encrypted or opaque payload refused; `protocol_required` when a
+    /// registered protocol with key escrow would have allowed it
+    EncryptedContent { protocol_required: bool },
+    /// External classifier unavailable and the fallback fails closed
+    DetectorUnavailable,
+    /// A decision webhook denied the send, with the reason it gave
+    Vetoed { reason: Option<String> },
+    /// A fail-closed decision webhook could not be reached
+    DecisionUnavailable,
+    /// A storage quota refused the request
+    QuotaExceeded(Breach),
+    /// The only recipient of a send was refused
+    RecipientRefused(String),
+    /// A novel message was addressed outside its protocol's allowlist
+    ScopeViolation { recipient: String },
+    /// A send carried attachments without declaring a protocol
+    AttachmentRequiresProtocol,
+    /// An uploaded attachment is over `ATTACHMENT_MAX_BYTES`
+    AttachmentTooLarge { limit: usize },
+    /// Request failed validation
+    Invalid(String),
+    /// Request body did not decode into the route's type
+    BodyInvalid(Box<BodyError>),
+    /// Request body in an unsupported format
+    UnsupportedMediaType,
+    /// Request body could not be read, e.g. over `MAX_BODY_BYTES`
+    BodyRejected { status: StatusCode, message: String },
+    /// Unknown id or resource
+    NotFound(&'static str),
+    /// Request conflicts with the current state
+    Conflict(&'static str),
+    /// Fault injection requested without `CHAOS_ENABLED`
+    ChaosDisabled,
+    /// Test alert requested without an alert channel
+    NoAlertChannel,
+    /// Every alert channel failed; carries the dispatch summary
+    AlertDeliveryFailed(String),
+    /// Replication stream requested without `REPLICATION_TOKEN`
+    ReplicationDisabled,
+    /// Wrong or missing replication token
+    InvalidReplicationToken,
+    /// Registry snapshot requested without `REGISTRY_SYNC_TOKEN`
+    RegistrySyncDisabled,
+    /// Wrong or missing registry sync token
+    InvalidRegistrySyncToken,
+    /// Message forwarded without `FORWARD_TOKEN` configured
+    ForwardingDisabled,
+    /// Wrong or missing forwarding token
+    InvalidForwardToken,
+    /// Request tagged as mirrored without this gateway's mirror token
+    InvalidMirrorToken,
+    /// Write sent to a standby
+    Standby,
+    /// The route's group is paused for maintenance, with the reason given
+    Maintenance { group: RouteGroup, reason: Option<String> },
+    /// The request outlived its route's timeout
+    TimedOut { after: Duration },
+    /// An admin blocked the client's IP
+    IpBlocked,
+    /// The client's IP is over its per-minute throttle; `retry_after` seconds
+    /// until the next minute
+    IpThrottled { per_minute: u64, retry_after: u64 },
+    /// Response could not be encoded in the negotiated format
+    Encoding(String),
+    /// An internal error kept the gateway from deciding, and the
+    /// degradation policy fails closed
+    Internal(String),
+    /// A send refused by several checks at once, the primary one first
+    Denied(Vec<GatewayError>),
+}
+
+impl GatewayError {
+    /// Refusal for every reason in `reasons`, the primary one first; a
+    /// single reason is returned as is
+    pub fn denied(mut reasons: Vec<Self>) -> Self {
+        match reasons.len() {
+            1 => reasons.remove(0),
+            _ => Self::Denied(reasons),
+        }
+    }
+
+    /// Every reason behind the refusal, the primary one first
+    pub fn reasons(&self) -> &[Self] {
+        match self {
+            Self::Denied(reasons) => reasons,
+            _ => std::slice::from_ref(self),
+        }
+    }
+
+    /// Whether a send was refused by a compliance check, which audit-only
+    /// enforcement waives; a refusal for several reasons is waived only when
+    /// each of them is
+    pub fn is_compliance(&self) -> bool {
+        if let Self::Denied(reasons) = self {
+            return !reasons.is_empty() && reasons.iter().all(Self::is_compliance);
+        }
+        matches!(
+            self,
+            Self::NotRegistered
+                | Self::MissingProtocol
+                | Self::Superseded { .. }
+                | Self::ProtocolSuspended
+                | Self::SuspendedByPeer { .. }
+                | Self::RecertificationLapsed
+                | Self::CodebookRequired
+                | Self::SchemaViolation(_)
+                | Self::EncryptedContent { .. }
+                | Self::AttachmentRequiresProtocol
+                | Self::ReportOverdue { .. }
+        )
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Denied(reasons) => reasons.first().map_or(StatusCode::FORBIDDEN, Self::status),
+            Self::InvalidAdminToken
+            | Self::Unauthenticated
+            | Self::InvalidReplicationToken
+            | Self::InvalidRegistrySyncToken
+            | Self::InvalidForwardToken
+            | Self::InvalidMirrorToken => StatusCode::UNAUTHORIZED,
+            Self::AdminDisabled
+            | Self::OutOfScope
+            | Self::Forbidden(_)
+            | Self::ReadOnly
+            | Self::AgentDeleted { .. }
+            | Self::NotRegistered
+            | Self::MissingProtocol
+            | Self::Superseded { .. }
+            | Self::ProtocolSuspended
+            | Self::SuspendedByPeer { .. }
+            | Self::RecertificationLapsed
+            | Self::CodebookRequired
+            | Self::SchemaViolation(_)
+            | Self::EncryptedContent { .. }
+            | Self::RecipientRefused(_)
+            | Self::ScopeViolation { .. }
+            | Self::AttachmentRequiresProtocol
+            | Self::Vetoed { .. }
+            | Self::SelfApproval
+            | Self::ChaosDisabled
+            | Self::IpBlocked => StatusCode::FORBIDDEN,
+            Self::ReportOverdue { .. } | Self::IpThrottled { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::CoverageLow { .. }
+            | Self::SummaryTooShort { .. }
+            | Self::SummaryQualityLow { .. }
+            | Self::SummaryLanguage { .. }
+            | Self::Invalid(_)
+            | Self::BodyInvalid(_) => StatusCode::BAD_REQUEST,
+            Self::DetectorUnavailable
+            | Self::DecisionUnavailable
+            | Self::NoAlertChannel
+            | Self::Standby
+            | Self::Maintenance { .. }
+            | Self::Internal(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::QuotaExceeded(_) => StatusCode::INSUFFICIENT_STORAGE,
+            Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::AttachmentTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::BodyRejected { status, .. } => *status,
+            Self::NotFound(_)
+            | Self::ReplicationDisabled
+            | Self::RegistrySyncDisabled
+            | Self::ForwardingDisabled => StatusCode::NOT_FOUND,
+            Self::Conflict(_) | Self::ProtocolNameTaken { .. } => StatusCode::CONFLICT,
+            Self::ApprovalExpired => StatusCode::GONE,
+            Self::AlertDeliveryFailed(_) => StatusCode::BAD_GATEWAY,
+            Self::TimedOut { .. } => StatusCode::GATEWAY_TIMEOUT,
+            Self::Encoding(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable machine-readable code sent as the response's `code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Denied(reasons) => reasons.first().map_or("denied", Self::code),
+            Self::AdminDisabled => "admin_disabled",
+            Self::InvalidAdminToken => "invalid_admin_token",
+            Self::SelfApproval => "self_approval",
+            Self::ApprovalExpired => "approval_expired",
+            Self::Unauthenticated => "unauthenticated",
+            Self::OutOfScope => "out_of_scope",
+            Self::ReadOnly => "read_only",
+            Self::Forbidden(_) => "forbidden",
+            Self::AgentDeleted { .. } => "agent_deleted",
+            Self::NotRegistered => "protocol_not_registered",
+            Self::MissingProtocol => "missing_protocol",
+            Self::Superseded { .. } => "protocol_superseded",
+            Self::ProtocolNameTaken { .. } => "protocol_name_taken",
+            Self::ProtocolSuspended | Self::SuspendedByPeer { .. } => "protocol_suspended",
+            Self::RecertificationLapsed => "recertification_lapsed",
+            Self::CodebookRequired => "codebook_required",
+            Self::SchemaViolation(_) => "schema_violation",
+            Self::ReportOverdue { .. } => "report_overdue",
+            Self::CoverageLow { .. } => "coverage_low",
+            Self::SummaryTooShort { .. } => "summary_too_short",
+            Self::SummaryQualityLow { .. } => "summary_quality_low",
+            Self::SummaryLanguage { .. } => "summary_language",
+            Self::EncryptedContent { .. } => "encrypted_content",
+            Self::DetectorUnavailable => "detector_unavailable",
+            Self::Vetoed { .. } => "webhook_denied",
+            Self::DecisionUnavailable => "decision_webhook_unavailable",
+            Self::QuotaExceeded(_) => "quota_exceeded",
+            Self::RecipientRefused(_) => "recipient_refused",
+            Self::ScopeViolation { .. } => "scope_violation",
+            Self::AttachmentRequiresProtocol => "attachment_requires_protocol",
+            Self::AttachmentTooLarge { .. } => "attachment_too_large",
+            Self::Invalid(_) | Self::BodyInvalid(_) => "invalid_request",
+            Self::UnsupportedMediaType => "unsupported_media_type",
+            Self::BodyRejected { .. } => "body_rejected",
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::ChaosDisabled => "chaos_disabled",
+            Self::NoAlertChannel => "no_alert_channel",
+            Self::AlertDeliveryFailed(_) => "alert_delivery_failed",
+            Self::ReplicationDisabled => "replication_disabled",
+            Self::InvalidReplicationToken => "invalid_replication_token",
+            Self::RegistrySyncDisabled => "registry_sync_disabled",
+            Self::InvalidRegistrySyncToken => "invalid_registry_sync_token",
+            Self::ForwardingDisabled => "forwarding_disabled",
+            Self::InvalidForwardToken => "invalid_forward_token",
+            Self::InvalidMirrorToken => "invalid_mirror_token",
+            Self::Standby => "standby",
+            Self::Maintenance { .. } => "maintenance",
+            Self::TimedOut { .. } => "timeout",
+            Self::IpBlocked => "ip_blocked",
+            Self::IpThrottled { .. } => "ip_throttled",
+            Self::Encoding(_) => "encoding_failed",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl GatewayError {
+    /// The response message: its catalog key and variables (see
+    /// [`messages`](crate::messages))
+    pub fn message(&self) -> Message {
+        match self {
+            Self::Denied(reasons) if !reasons.is_empty() => reasons[0].message(),
+            Self::AgentDeleted { action } => Message::new("agent_deleted").arg("action", action),
+            Self::Superseded { successor } => Message::new("protocol_superseded").arg("successor", successor),
+            Self::SuspendedByPeer { origin } => Message::new("protocol_suspended.remote").arg("origin", origin),
+            Self::ProtocolNameTaken { protocol, owner } => Message::new("protocol_name_taken")
+                .arg("protocol", protocol)
+                .arg("owner", owner)
+                .arg("path", protocol.replacen(':', "/", 1)),
+            Self::SchemaViolation(errors) => Message::new("schema_violation").arg("errors", errors),
+            Self::ReportOverdue { seconds: Some(seconds) } => Message::new("report_overdue").arg("seconds", seconds),
+            Self::ReportOverdue { seconds: None } => Message::new("report_overdue.first"),
+            Self::CoverageLow { actual, required } => Message::new("coverage_low")
+                .arg("actual", format!("{actual:.2}"))
+                .arg("required", format!("{required:.2}")),
+            Self::SummaryTooShort { required } => Message::new("summary_too_short").arg("required", required),
+            Self::SummaryQualityLow { score, required } => Message::new("summary_quality_low")
+                .arg("score", format!("{score:.2}"))
+                .arg("required", format!("{required:.2}")),
+            Self::SummaryLanguage { expected } => Message::new("summary_language").arg("expected", expected),
+            Self::EncryptedContent { protocol_required: true } => {
+                Message::new("encrypted_content.protocol_required")
+            }
+            Self::Vetoed { reason: Some(reason) } => Message::new("webhook_denied").arg("reason", reason),
+            Self::Vetoed { reason: None } => Message::new("webhook_denied.no_reason"),
+            Self::QuotaExceeded(breach) => Message::new("quota_exceeded").arg("breach", breach),
+            Self::RecipientRefused(reason) | Self::Invalid(reason) => Message::new(self.code()).arg("reason", reason),
+            Self::Forbidden(route) => Message::new("forbidden").arg("route", route),
+            Self::ScopeViolation { recipient } => Message::new("scope_violation").arg("recipient", recipient),
+            Self::AttachmentTooLarge { limit } => Message::new("attachment_too_large").arg("limit", limit),
+            Self::BodyRejected { message, .. } => Message::new("body_rejected").arg("reason", message),
+            Self::BodyInvalid(e) => match &e.field {
+                Some(field) => Message::new("invalid_request.field").arg("field", field).arg("detail", &e.detail),
+                None => Message::new("invalid_request.body").arg("detail", &e.detail),
+            },
+            Self::NotFound(what) | Self::Conflict(what) => Message::new(self.code()).arg("what", what),
+            Self::AlertDeliveryFailed(summary) => Message::new("alert_delivery_failed").arg("summary", summary),
+            Self::Maintenance { group, reason: Some(reason) } => {
+                Message::new("maintenance").arg("group", group).arg("reason", reason)
+            }
+            Self::Maintenance { group, reason: None } => Message::new("maintenance.no_reason").arg("group", group),
+            Self::TimedOut { after } => Message::new("timeout").arg("ms", after.as_millis()),
+            Self::IpThrottled { per_minute, .. } => Message::new("ip_throttled").arg("per_minute", per_minute),
+            Self::Encoding(e) | Self::Internal(e) => Message::new(self.code()).arg("detail", e),
+            _ => Message::new(self.code()),
+        }
+    }
+}
+
+impl GatewayError {
+    /// How the sender can clear a refused send or report
+    pub fn remediation(&self) -> Option<Message> {
+        let key = match self {
+            Self::AgentDeleted { .. } => "agent_deleted.remediation",
+            Self::NotRegistered => "protocol_not_registered.remediation",
+            Self::MissingProtocol => "missing_protocol.remediation",
+            Self::Superseded { successor } => {
+                return Some(Message::new("protocol_superseded.remediation").arg("successor", successor))
+            }
+            Self::ProtocolSuspended => "protocol_suspended.remediation",
+            Self::SuspendedByPeer { origin } => {
+                return Some(Message::new("protocol_suspended.remote.remediation").arg("origin", origin))
+            }
+            Self::RecertificationLapsed => "recertification_lapsed.remediation",
+            Self::CodebookRequired => "codebook_required.remediation",
+            Self::SchemaViolation(_) => "schema_violation.remediation",
+            Self::ScopeViolation { .. } => "scope_violation.remediation",
+            Self::AttachmentRequiresProtocol => "attachment_requires_protocol.remediation",
+            Self::ReportOverdue { .. } => "report_overdue.remediation",
+            Self::EncryptedContent { protocol_required: true } => "encrypted_content.protocol_required.remediation",
+            Self::EncryptedContent { protocol_required: false } => "encrypted_content.remediation",
+            _ => return None,
+        };
+        Some(Message::new(key))
+    }
+}
+
+/// One reason a send or report was refused, as listed in `deny_reasons`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DenyReason {
+    pub code: &'static str,
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remediation: Option<String>,
+}
+
+impl DenyReason {
+    /// Every reason behind `err`, phrased for the request and `record`; empty
+    /// unless `err` refused a send or report
+    pub fn list(err: &GatewayError, record: Option<&LanguageOfRecord>) -> Vec<Self> {
+        let reasons = err.reasons();
+        if reasons.iter().all(|r| r.remediation().is_none()) {
+            return Vec::new();
+        }
+        reasons
+            .iter()
+            .map(|reason| Self {
+                code: reason.code(),
+                error: messages::localize(&reason.message(), record),
+                remediation: reason.remediation().map(|m| messages::localize(&m, record)),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message().english())
+    }
+}
+
+impl std::error::Error for GatewayError {}
+
+impl From<GatewayError> for ApiResponse {
+    fn from(err: GatewayError) -> Self {
+        let text = messages::localize(&err.message(), None);
+        let mut body = Self::error(&text);
+        body.code = Some(err.code());
+        body.deny_reasons = DenyReason::list(&err, None);
+        if let GatewayError::BodyInvalid(e) = err {
+            body.body_error = Some(*e);
+        }
+        body
+    }
+}
+
+impl IntoResponse for GatewayError {
+    fn into_response(self) -> Response {
+        (self.status(), Json(ApiResponse::from(self))).into_response()
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+
+    #[tokio::test]
+    async fn test_error_response_shape() {
+        let err = GatewayError::ReportOverdue { seconds: Some(75) };
+        assert_eq!(err.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["ok"], false);
+        assert_eq!(body["code"], "report_overdue");
+        assert!(body["error"].as_str().unwrap().contains("75s since last report"));
+
+        assert_eq!(body["deny_reasons"][0]["code"], "report_overdue");
+        assert!(body["deny_reasons"][0]["remediation"].as_str().unwrap().contains("POST /report"));
+
+        let both = GatewayError::denied(vec![GatewayError::AgentDeleted { action: "to resume messaging" }, GatewayError::NotRegistered]);
+        assert_eq!((both.status(), both.code()), (StatusCode::FORBIDDEN, "agent_deleted"));
+        assert!(!both.is_compliance(), "a deleted agent is not waived");
+        let reasons = DenyReason::list(&both, None);
+        assert_eq!(reasons.iter().map(|r| r.code).collect::<Vec<_>>(), ["agent_deleted", "protocol_not_registered"]);
+        assert_eq!(GatewayError::denied(vec![GatewayError::NotRegistered]), GatewayError::NotRegistered);
+        assert!(DenyReason::list(&GatewayError::Unauthenticated, None).is_empty());
+
+        let low = GatewayError::CoverageLow { actual: 0.5, required: 0.95 };
+        assert_eq!(low.to_string(), "Coverage 0.50 below minimum 0.95");
+        assert_eq!(low.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,223 @@
+//! Trace exemplars on metrics
+//!
+//! When a rejection-rate or latency panel spikes, an exemplar on the metric
+//! leads straight to a trace of one of the requests behind it. With
+//! OpenTelemetry tracing configured for the deployment
+//! (`OTEL_EXPORTER_OTLP_ENDPOINT` set) or `METRICS_EXEMPLARS=true`, the
+//! gateway reads the W3C `traceparent` header of each request and, for the
+//! requests carrying one:
+//!
+//! - tags every audit event of the request with its `trace_id`
+//! - keeps the latest traced increment of `rejected_messages_total`, and the
+//!   latest traced observation of each `send_stage_duration_seconds` bucket,
+//!   as that series' exemplar
+//!
+//! Exemplars are only part of the OpenMetrics exposition format: scrapers
+//! asking `/metrics` for `application/openmetrics-text` get them, as
+//! `# {trace_id="..."} <value> <timestamp>` after the sample, while the
+//! plain Prometheus text format stays unchanged. `METRICS_EXEMPLARS=false`
+//! turns them off even with OpenTelemetry configured.
+
+use axum::{extract::State, http::HeaderMap, middleware::Next, response::Response};
+use std::{
+    env, fmt,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::Instrument;
+
+use crate::AppState;
+
+/// Request header carrying the W3C trace context
+const TRACEPARENT: &str = "traceparent";
+
+tokio::task_local! {
+    /// Trace of the request being served
+    static TRACE: TraceId;
+}
+
+/// Whether requests are traced for exemplars
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExemplarConfig {
+    pub enabled: bool,
+}
+
+impl ExemplarConfig {
+    /// Enabled by `OTEL_EXPORTER_OTLP_ENDPOINT`, overridden either way by
+    /// `METRICS_EXEMPLARS`
+    pub fn from_env() -> Self {
+        let explicit = env::var("METRICS_EXEMPLARS").ok().and_then(|v| match v.trim() {
+            "true" | "1" => Some(true),
+            "false" | "0" => Some(false),
+            _ => None,
+        });
+        let otel = env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_ok_and(|v| !v.trim().is_empty());
+        Self {
+            enabled: explicit.unwrap_or(otel),
+        }
+    }
+}
+
+/// A W3C trace id: 16 bytes, not all zero
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceId([u8; 16]);
+
+impl TraceId {
+    /// Trace id of a `traceparent` header (`00-<trace id>-<parent id>-<flags>`)
+    pub fn from_traceparent(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let (version, trace, parent, flags) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        let hex = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+        if !hex(version, 2) || version == "ff" || !hex(parent, 16) || !hex(flags, 2) {
+            return None;
+        }
+        if version == "00" && parts.next().is_some() {
+            return None;
+        }
+        let mut id = [0u8; 16];
+        for (i, byte) in id.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(trace.get(2 * i..2 * i + 2)?, 16).ok()?;
+        }
+        (hex(trace, 32) && id != [0; 16]).then_some(Self(id))
+    }
+
+    /// Trace of the request being served, if it is traced
+    pub fn current() -> Option<Self> {
+        TRACE.try_with(|id| *id).ok()
+    }
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+/// A sample of a series tied to the trace that produced it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Exemplar {
+    pub trace_id: TraceId,
+    pub value: f64,
+    /// Unix seconds
+    pub timestamp: f64,
+}
+
+impl fmt::Display for Exemplar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "# {{trace_id=\"{}\"}} {} {:.3}", self.trace_id, self.value, self.timestamp)
+    }
+}
+
+/// The latest exemplar of one series
+#[derive(Debug, Default)]
+pub struct ExemplarSlot(Mutex<Option<Exemplar>>);
+
+impl ExemplarSlot {
+    /// Keep `value` as the exemplar when the current request is traced
+    pub fn record(&self, value: f64) {
+        if let Some(trace_id) = TraceId::current() {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+            *self.0.lock().unwrap() = Some(Exemplar {
+                trace_id,
+                value,
+                timestamp,
+            });
+        }
+    }
+
+    pub fn get(&self) -> Option<Exemplar> {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Middleware serving traced requests in their trace's context
+pub async fn trace_context(State(state): State<AppState>, req: axum::extract::Request, next: Next) -> Response {
+    let trace_id = state.exemplars.enabled.then(|| traced(req.headers())).flatten();
+    match trace_id {
+        Some(id) => {
+            let span = tracing::info_span!("trace", trace_id = %id);
+            TRACE.scope(id, next.run(req).instrument(span)).await
+        }
+        None => next.run(req).await,
+    }
+}
+
+fn traced(headers: &HeaderMap) -> Option<TraceId> {
+    TraceId::from_traceparent(headers.get(TRACEPARENT)?.to_str().ok()?)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audit::{AuditLayer, AuditLog},
+        testing::{SendFixture, TestGateway},
+    };
+    use axum::{body::Body, http::Request};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+    use tracing_subscriber::prelude::*;
+
+    const HEADER: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    async fn scrape(gw: &TestGateway, accept: &str) -> String {
+        let req = Request::get("/metrics").header("accept", accept).body(Body::empty()).unwrap();
+        let resp = gw.router().oneshot(req).await.unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_traceparent_parsed_and_scoped() {
+        let id = TraceId::from_traceparent(HEADER).unwrap();
+        assert_eq!(id.to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        for bad in [
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f35-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(TraceId::from_traceparent(bad), None, "{bad}");
+        }
+
+        let slot = ExemplarSlot::default();
+        slot.record(1.0);
+        assert_eq!(slot.get(), None, "untraced requests leave no exemplar");
+        TRACE.scope(id, async { slot.record(0.25) }).await;
+        let exemplar = slot.get().unwrap();
+        assert_eq!((exemplar.trace_id, exemplar.value), (id, 0.25));
+        assert!(exemplar.to_string().starts_with("# {trace_id=\"4bf92f3577b34da6a3ce929d0e0e4736\"} 0.25 "));
+    }
+
+    #[tokio::test]
+    async fn test_refusal_exemplar_links_to_audited_decision() {
+        let log = Arc::new(AuditLog::new(1_000));
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry().with(AuditLayer::new(log.clone())));
+        let gw = TestGateway::with_exemplars();
+        let mut undeclared = SendFixture::english("a", "b").build();
+        undeclared.content = "SHP|eta=7f".to_string();
+        let req = Request::post("/send")
+            .header("content-type", "application/json")
+            .header("traceparent", HEADER)
+            .body(Body::from(serde_json::to_vec(&undeclared).unwrap()))
+            .unwrap();
+        let resp = gw.router().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), axum::http::StatusCode::FORBIDDEN);
+
+        let open = scrape(&gw, "application/openmetrics-text; version=1.0.0").await;
+        let rejected = open.lines().find(|l| l.starts_with("rejected_messages_total ")).unwrap();
+        assert!(rejected.contains("# {trace_id=\"4bf92f3577b34da6a3ce929d0e0e4736\"} 1 "), "{rejected}");
+        assert!(open.contains("# TYPE rejected_messages counter") && open.ends_with("# EOF\n"));
+        let plain = scrape(&gw, "text/plain").await;
+        assert!(plain.contains("rejected_messages_total 1\n") && !plain.contains("trace_id"));
+
+        let events = log.read_page(0, u64::MAX, 1_000);
+        let refusal = events.iter().find(|e| e.event == "msg_rejected").unwrap();
+        assert_eq!(refusal.fields["trace_id"], "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
+}
@@ -0,0 +1,292 @@
+//! Candidate policies evaluated alongside the active one
+//!
+//! Raising a threshold such as `min_coverage` is safer once its effect on
+//! live traffic is known. `PUT /admin/policy/experiment` takes a candidate
+//! policy (the body of `PUT /admin/policy`) and evaluates it on every report
+//! next to the policy in force, without enforcing it. Each threshold rule is
+//! compared on its own:
+//!
+//! - `min_coverage`: the report's coverage
+//! - `min_summary_length`: the length of its English summary
+//! - `min_consistency`: its consistency score, for reports that get that far
+//!
+//! Protocols on trial are held to each policy's `trial` thresholds. A report
+//! that passes a rule under one policy and fails it under the other is a
+//! divergence, logged as a `policy_experiment_diverged` audit event. `GET`
+//! answers the comparison so far: per rule, the reports evaluated, those
+//! the candidate alone would refuse (`stricter`) and alone would accept
+//! (`looser`), and the most recent divergences. `DELETE` ends the experiment
+//! with its final comparison. Starting another experiment replaces the
+//! running one and its counts. Experiments are held in memory and not
+//! replicated.
+
+use axum::{extract::State, Json};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex, RwLock},
+};
+use tracing::{info, warn};
+
+use crate::{codec::Payload, error::GatewayError, identity::AuthedAdmin, policy::Policy, validate_policy, AppState};
+
+/// Divergences kept for the comparison report
+const RECENT_DIVERGENCES: usize = 100;
+
+/// A policy threshold compared between the active and candidate policies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Rule {
+    #[serde(rename = "min_coverage")]
+    Coverage,
+    #[serde(rename = "min_summary_length")]
+    SummaryLength,
+    #[serde(rename = "min_consistency")]
+    Consistency,
+}
+
+impl Rule {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Coverage => "min_coverage",
+            Self::SummaryLength => "min_summary_length",
+            Self::Consistency => "min_consistency",
+        }
+    }
+
+    /// The threshold `policy` sets, with trial terms for protocols on trial
+    fn threshold(self, policy: &Policy, on_trial: bool) -> f64 {
+        let trial = policy.trial.as_ref().filter(|_| on_trial);
+        match self {
+            Self::Coverage => trial.map_or(policy.min_coverage, |t| t.min_coverage),
+            Self::SummaryLength => trial.map_or(policy.min_summary_length, |t| t.min_summary_length) as f64,
+            Self::Consistency => policy.min_consistency,
+        }
+    }
+}
+
+/// How often the two policies agreed on one rule
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Tally {
+    pub evaluated: u64,
+    /// Passed under the active policy, failed under the candidate
+    pub stricter: u64,
+    /// Failed under the active policy, passed under the candidate
+    pub looser: u64,
+    /// Share of evaluated reports on which the policies disagreed
+    pub divergence_rate: f64,
+}
+
+/// One report the policies decided differently
+#[derive(Debug, Clone, Serialize)]
+pub struct Divergence {
+    pub rule: Rule,
+    pub agent_id: String,
+    pub protocol: String,
+    pub value: f64,
+    pub active_threshold: f64,
+    pub candidate_threshold: f64,
+    /// Whether the candidate alone refuses it
+    pub stricter: bool,
+    pub at: u64,
+}
+
+/// A running experiment
+#[derive(Debug)]
+struct Running {
+    candidate: Policy,
+    candidate_version: String,
+    started_at: u64,
+    started_by: String,
+    tallies: Mutex<(BTreeMap<Rule, Tally>, VecDeque<Divergence>)>,
+}
+
+/// Comparison of a candidate policy with the active one
+#[derive(Debug, Clone, Serialize)]
+pub struct Comparison {
+    pub candidate_version: String,
+    pub active_version: String,
+    pub started_at: u64,
+    pub started_by: String,
+    pub rules: BTreeMap<Rule, Tally>,
+    /// Most recent divergences, oldest first
+    pub recent: Vec<Divergence>,
+    pub candidate: Policy,
+}
+
+/// The candidate policy under evaluation, if any
+#[derive(Debug, Default)]
+pub struct Experiment {
+    running: RwLock<Option<Arc<Running>>>,
+}
+
+impl Experiment {
+    fn start(&self, candidate: Policy, started_by: &str, now: u64) -> Arc<Running> {
+        let running = Arc::new(Running {
+            candidate_version: format!("{:016x}", candidate.version_id()),
+            candidate,
+            started_at: now,
+            started_by: started_by.to_string(),
+            tallies: Mutex::default(),
+        });
+        *self.running.write().unwrap() = Some(running.clone());
+        running
+    }
+
+    fn stop(&self) -> Option<Arc<Running>> {
+        self.running.write().unwrap().take()
+    }
+
+    /// Evaluate `rule` on a report's `value` under both policies
+    pub fn compare(&self, state: &AppState, rule: Rule, value: f64, agent_id: &str, protocol: &str, on_trial: bool) {
+        let Some(running) = self.running.read().unwrap().clone() else {
+            return;
+        };
+        let active = state.policy.current();
+        let active_threshold = rule.threshold(&active.policy, on_trial);
+        let candidate_threshold = rule.threshold(&running.candidate, on_trial);
+        let (active_pass, candidate_pass) = (value >= active_threshold, value >= candidate_threshold);
+        let mut tallies = running.tallies.lock().unwrap();
+        let (rules, recent) = &mut *tallies;
+        let tally = rules.entry(rule).or_default();
+        tally.evaluated += 1;
+        if active_pass == candidate_pass {
+            return;
+        }
+        let stricter = active_pass;
+        if stricter {
+            tally.stricter += 1;
+        } else {
+            tally.looser += 1;
+        }
+        info!(
+            agent_id = %agent_id,
+            protocol = %protocol,
+            rule = rule.as_str(),
+            value,
+            active_threshold,
+            candidate_threshold,
+            stricter,
+            candidate_version = %running.candidate_version,
+            policy_version = %active.version,
+            event = "policy_experiment_diverged",
+            "Candidate policy decided a report differently"
+        );
+        if recent.len() == RECENT_DIVERGENCES {
+            recent.pop_front();
+        }
+        recent.push_back(Divergence {
+            rule,
+            agent_id: agent_id.to_string(),
+            protocol: protocol.to_string(),
+            value,
+            active_threshold,
+            candidate_threshold,
+            stricter,
+            at: state.clock.now(),
+        });
+    }
+}
+
+impl Running {
+    fn comparison(&self, state: &AppState) -> Comparison {
+        let (rules, recent) = &*self.tallies.lock().unwrap();
+        let rules = rules
+            .iter()
+            .map(|(rule, tally)| {
+                let diverged = (tally.stricter + tally.looser) as f64;
+                let divergence_rate = diverged / tally.evaluated.max(1) as f64;
+                (*rule, Tally { divergence_rate, ..tally.clone() })
+            })
+            .collect();
+        Comparison {
+            candidate_version: self.candidate_version.clone(),
+            active_version: state.policy.current().version.clone(),
+            started_at: self.started_at,
+            started_by: self.started_by.clone(),
+            rules,
+            recent: recent.iter().cloned().collect(),
+            candidate: self.candidate.clone(),
+        }
+    }
+}
+
+/// Start evaluating a candidate policy alongside the active one
+pub async fn start(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Payload(candidate): Payload<Policy>,
+) -> Result<Json<Comparison>, GatewayError> {
+    validate_policy(&candidate)?;
+    let running = state.experiment.start(candidate, &admin, state.clock.now());
+    warn!(
+        candidate_version = %running.candidate_version,
+        policy_version = %state.policy.current().version,
+        policy = %serde_json::to_string(&running.candidate).unwrap_or_default(),
+        admin = %admin,
+        event = "policy_experiment_started",
+        "Candidate policy evaluated alongside the active one"
+    );
+    Ok(Json(running.comparison(&state)))
+}
+
+/// Comparison of the running experiment so far
+pub async fn report(_: AuthedAdmin, State(state): State<AppState>) -> Result<Json<Comparison>, GatewayError> {
+    let running = state.experiment.running.read().unwrap().clone();
+    let running = running.ok_or(GatewayError::NotFound("No policy experiment running"))?;
+    Ok(Json(running.comparison(&state)))
+}
+
+/// End the running experiment with its final comparison
+pub async fn stop(State(state): State<AppState>, AuthedAdmin(admin): AuthedAdmin) -> Result<Json<Comparison>, GatewayError> {
+    let running = state.experiment.stop().ok_or(GatewayError::NotFound("No policy experiment running"))?;
+    let comparison = running.comparison(&state);
+    warn!(
+        candidate_version = %comparison.candidate_version,
+        rules = %serde_json::to_string(&comparison.rules).unwrap_or_default(),
+        admin = %admin,
+        event = "policy_experiment_stopped",
+        "Policy experiment ended"
+    );
+    Ok(Json(comparison))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        policy::Policy,
+        testing::{AgentFixture, ProtocolFixture, ReportFixture, TestGateway},
+    };
+    use axum::http::{Method, StatusCode};
+
+    #[tokio::test]
+    async fn test_candidate_divergence_counted_per_rule() {
+        let gw = TestGateway::new();
+        let coord = ProtocolFixture::new("coord", "1.0").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(coord.clone())).await;
+        let candidate = Policy {
+            min_coverage: 0.99,
+            ..Policy::default()
+        };
+        let started = gw.admin(Method::PUT, "/admin/policy/experiment", Some(&candidate)).await;
+        assert_eq!(started.status, StatusCode::OK, "{:?}", started.body);
+
+        // Accepted under the active policy, refused under the candidate
+        let report = ReportFixture::new("a", &coord).coverage(0.97).build();
+        assert_eq!(gw.report(&report).await.status, StatusCode::OK);
+
+        let resp = gw.admin(Method::GET, "/admin/policy/experiment", None::<&()>).await;
+        let coverage = &resp.body["rules"]["min_coverage"];
+        assert_eq!((coverage["evaluated"].as_u64(), coverage["stricter"].as_u64()), (Some(1), Some(1)));
+        assert_eq!(resp.body["rules"]["min_summary_length"]["stricter"], 0);
+        assert_eq!(resp.body["recent"][0]["rule"], "min_coverage");
+
+        let stopped = gw.admin(Method::DELETE, "/admin/policy/experiment", None::<&()>).await;
+        assert_eq!(stopped.status, StatusCode::OK);
+        let gone = gw.admin(Method::GET, "/admin/policy/experiment", None::<&()>).await;
+        assert_eq!(gone.status, StatusCode::NOT_FOUND);
+    }
+}
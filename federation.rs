@@ -0,0 +1,661 @@
+//! Suspension propagation between federated gateways
+//!
+//! A protocol suspended on one gateway, for review (see [`crate::sanctions`])
+//! or for a lapsed certification (see [`crate::recert`]), should not keep
+//! sending through another region. When a protocol is suspended or
+//! reinstated, the gateway pushes a signed notice to every registry sync peer
+//! at `POST /federation/suspensions`. The notice names the agent protocol, the
+//! origin gateway, and an expiry `FEDERATION_SUSPENSION_TTL_SEC` after it was
+//! issued. A notice a peer did not take is retried every
+//! [`RETRY_INTERVAL`] until it is, a newer notice for the protocol replaces
+//! it, or it expires. Suspensions still in force are announced again every
+//! half TTL, so a peer drops a suspension on its own only once its origin has
+//! gone quiet.
+//!
+//! Federation reuses the registry sync identity: notices are signed with
+//! `REGISTRY_SYNC_KEY`, verified against the key pinned for their origin in
+//! `REGISTRY_SYNC_PEER_KEYS`, and delivered with `REGISTRY_SYNC_TOKEN`. It is
+//! disabled when that token is unset.
+//!
+//! `FEDERATION_TRUST` decides what a peer's suspensions do here, per origin
+//! (`eu=enforce,us=flag`, with `*` for every other peer):
+//!
+//! - `enforce` (the default): sends under the protocol are refused with
+//!   `protocol_suspended` until the origin reinstates it or the notice runs out
+//! - `flag`: the suspension is listed in `GET /status` but sends are not refused
+//! - `ignore`: the notice is logged and dropped
+//!
+//! Each gateway numbers its notices with a sequence that only rises. It
+//! starts from the issue time in microseconds, so it keeps rising across
+//! restarts, and a suspension and reinstatement in the same second stay
+//! ordered. A notice numbered no higher than the last one seen from the same
+//! origin for the same protocol is a replay and is dropped. Remote
+//! suspensions and undelivered notices are held in memory and not
+//! replicated; peers announce them again within half a TTL.
+
+use axum::{extract::State, http::HeaderMap, Json};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+    time::Duration,
+};
+use tracing::{info, warn};
+
+use crate::{
+    bearer_token, codec::Payload, env_parse, error::GatewayError, mirror, policy::Policy, recert::RecertStage,
+    registry_sync::RegistrySync, sanctions::Standing, tokens_match, ApiResponse, AppState, InnerState,
+};
+
+/// Default lifetime of a suspension notice
+pub const DEFAULT_TTL_SEC: u64 = 86_400;
+
+/// Per-request timeout when pushing a notice to a peer
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause between retries of notices peers did not take
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What a peer's suspensions do on this gateway
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trust {
+    #[default]
+    Enforce,
+    Flag,
+    Ignore,
+}
+
+impl Trust {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "enforce" => Some(Self::Enforce),
+            "flag" => Some(Self::Flag),
+            "ignore" => Some(Self::Ignore),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FederationConfig {
+    /// Trust per origin gateway
+    pub trust: BTreeMap<String, Trust>,
+    /// Trust of origins not listed
+    pub default_trust: Trust,
+    /// Lifetime of the notices this gateway issues, and the longest it keeps
+    /// a peer's
+    pub ttl_sec: u64,
+}
+
+impl Default for FederationConfig {
+    fn default() -> Self {
+        Self {
+            trust: BTreeMap::new(),
+            default_trust: Trust::default(),
+            ttl_sec: DEFAULT_TTL_SEC,
+        }
+    }
+}
+
+impl FederationConfig {
+    /// Load `FEDERATION_TRUST` (`name=enforce|flag|ignore,...`, `*` for the
+    /// rest) and `FEDERATION_SUSPENSION_TTL_SEC`
+    pub fn from_env() -> Self {
+        let d = Self::default();
+        let mut config = Self {
+            ttl_sec: env_parse("FEDERATION_SUSPENSION_TTL_SEC")
+                .filter(|&s: &u64| s > 0)
+                .unwrap_or(d.ttl_sec),
+            ..d
+        };
+        let raw = env::var("FEDERATION_TRUST").unwrap_or_default();
+        for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.split_once('=').and_then(|(name, trust)| Some((name.trim(), Trust::parse(trust)?))) {
+                Some(("*", trust)) => config.default_trust = trust,
+                Some((name, trust)) => {
+                    config.trust.insert(name.to_string(), trust);
+                }
+                None => warn!(
+                    event = "config_invalid",
+                    entry = %entry,
+                    "Ignoring FEDERATION_TRUST entry: expected name=enforce|flag|ignore"
+                ),
+            }
+        }
+        config
+    }
+
+    pub fn trust(&self, origin: &str) -> Trust {
+        self.trust.get(origin).copied().unwrap_or(self.default_trust)
+    }
+}
+
+/// A suspension or reinstatement of an agent protocol, as announced by its
+/// origin gateway
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SuspensionNotice {
+    pub origin: String,
+    pub agent_id: String,
+    /// Protocol key (`name:version`)
+    pub protocol: String,
+    /// `false` announces a reinstatement
+    pub suspended: bool,
+    #[serde(default)]
+    pub reason: String,
+    /// Rises with every notice the origin issues
+    pub seq: u64,
+    pub issued_at: u64,
+    pub expires_at: u64,
+}
+
+/// Body of `POST /federation/suspensions`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedNotice {
+    /// The [`SuspensionNotice`] as JSON, signed byte for byte
+    pub notice: String,
+    /// base64url Ed25519 signature over `notice`
+    pub signature: String,
+}
+
+/// A peer's suspension of an agent protocol in force here
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RemoteSuspension {
+    pub origin: String,
+    pub reason: String,
+    pub issued_at: u64,
+    pub expires_at: u64,
+    pub trust: Trust,
+}
+
+#[derive(Debug, Default)]
+struct Book {
+    /// "agent_id::protocol_key" -> origin -> suspension
+    suspensions: HashMap<String, BTreeMap<String, RemoteSuspension>>,
+    /// (origin, "agent_id::protocol_key") -> sequence of the latest notice applied
+    latest: HashMap<(String, String), u64>,
+}
+
+/// A notice a peer has yet to take
+#[derive(Debug, Clone)]
+struct Undelivered {
+    seq: u64,
+    expires_at: u64,
+    signed: SignedNotice,
+}
+
+/// Suspensions exchanged with federated peers
+#[derive(Debug)]
+pub struct Federation {
+    config: FederationConfig,
+    client: reqwest::Client,
+    book: RwLock<Book>,
+    /// Sequence of the last notice issued
+    seq: AtomicU64,
+    /// (peer, "agent_id::protocol_key") -> latest notice not yet delivered
+    undelivered: Mutex<BTreeMap<(String, String), Undelivered>>,
+}
+
+impl Default for Federation {
+    fn default() -> Self {
+        Self::new(FederationConfig::default())
+    }
+}
+
+impl Federation {
+    pub fn new(config: FederationConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::builder()
+                .timeout(PUSH_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            book: RwLock::default(),
+            seq: AtomicU64::new(0),
+            undelivered: Mutex::default(),
+        }
+    }
+
+    /// Sequence of a notice issued at `now`: above every earlier one, and
+    /// no lower than `now` in microseconds
+    fn next_seq(&self, now: u64) -> u64 {
+        let floor = now.saturating_mul(1_000_000);
+        let next = |seq: u64| (seq + 1).max(floor);
+        next(self.seq.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |s| Some(next(s))).unwrap())
+    }
+
+    /// Hold `signed` for `peer` until delivered, replacing older notices for
+    /// the same protocol
+    fn queue(&self, peer: &str, report_key: &str, notice: &SuspensionNotice, signed: &SignedNotice) {
+        let mut undelivered = self.undelivered.lock().unwrap();
+        let key = (peer.to_string(), report_key.to_string());
+        if undelivered.get(&key).is_some_and(|u| u.seq >= notice.seq) {
+            return;
+        }
+        let held = Undelivered {
+            seq: notice.seq,
+            expires_at: notice.expires_at,
+            signed: signed.clone(),
+        };
+        undelivered.insert(key, held);
+    }
+
+    /// Notices still owed to peers as of `now`, dropping expired ones
+    fn owed(&self, now: u64) -> Vec<((String, String), Undelivered)> {
+        let mut undelivered = self.undelivered.lock().unwrap();
+        undelivered.retain(|_, u| u.expires_at > now);
+        undelivered.iter().map(|(k, u)| (k.clone(), u.clone())).collect()
+    }
+
+    /// Record that `peer` took notice `seq` for `report_key`
+    fn delivered(&self, peer: &str, report_key: &str, seq: u64) {
+        let mut undelivered = self.undelivered.lock().unwrap();
+        let key = (peer.to_string(), report_key.to_string());
+        if undelivered.get(&key).is_some_and(|u| u.seq == seq) {
+            undelivered.remove(&key);
+        }
+    }
+
+    /// Peer suspensions of `report_key` in force as of `now`, enforced or not
+    pub fn suspensions(&self, report_key: &str, now: u64) -> Vec<RemoteSuspension> {
+        let book = self.book.read().unwrap();
+        let by_origin = book.suspensions.get(report_key);
+        by_origin.into_iter().flat_map(|m| m.values()).filter(|s| s.expires_at > now).cloned().collect()
+    }
+
+    /// The first peer suspension of `report_key` this gateway enforces
+    pub fn enforced(&self, report_key: &str, now: u64) -> Option<RemoteSuspension> {
+        self.suspensions(report_key, now).into_iter().find(|s| s.trust == Trust::Enforce)
+    }
+
+    /// Every peer suspension in force as of `now`, by report key
+    pub fn all(&self, now: u64) -> Vec<(String, RemoteSuspension)> {
+        let book = self.book.read().unwrap();
+        let mut all: Vec<_> = book
+            .suspensions
+            .iter()
+            .flat_map(|(key, m)| m.values().filter(|s| s.expires_at > now).map(move |s| (key.clone(), s.clone())))
+            .collect();
+        all.sort_by(|a, b| (&a.0, &a.1.origin).cmp(&(&b.0, &b.1.origin)));
+        all
+    }
+
+    /// Check a notice came from a pinned peer and is still live
+    fn verify(&self, sync: &RegistrySync, signed: &SignedNotice, now: u64) -> Result<SuspensionNotice, String> {
+        let notice: SuspensionNotice =
+            serde_json::from_str(&signed.notice).map_err(|e| format!("malformed notice: {e}"))?;
+        let peer = sync.peer(&notice.origin).ok_or_else(|| format!("unknown origin {}", notice.origin))?;
+        RegistrySync::verify_bytes(peer, signed.notice.as_bytes(), &signed.signature)?;
+        if notice.expires_at <= now {
+            return Err("notice expired".to_string());
+        }
+        Ok(notice)
+    }
+
+    /// Apply a verified notice; false when it is a replay
+    fn apply(&self, notice: &SuspensionNotice, trust: Trust, now: u64) -> bool {
+        let report_key = format!("{}::{}", notice.agent_id, notice.protocol);
+        let mut book = self.book.write().unwrap();
+        let latest = book.latest.entry((notice.origin.clone(), report_key.clone())).or_insert(0);
+        if notice.seq <= *latest {
+            return false;
+        }
+        *latest = notice.seq;
+        let by_origin = book.suspensions.entry(report_key.clone()).or_default();
+        if notice.suspended && trust != Trust::Ignore {
+            let suspension = RemoteSuspension {
+                origin: notice.origin.clone(),
+                reason: notice.reason.clone(),
+                issued_at: notice.issued_at,
+                expires_at: notice.expires_at.min(now + self.config.ttl_sec),
+                trust,
+            };
+            by_origin.insert(notice.origin.clone(), suspension);
+        } else {
+            by_origin.remove(&notice.origin);
+            if by_origin.is_empty() {
+                book.suspensions.remove(&report_key);
+            }
+        }
+        true
+    }
+}
+
+/// Why a local suspension is in force, as announced and listed in `/status`
+pub fn reason(standing: &Standing) -> String {
+    format!("{} consecutive reports held for low consistency", standing.strikes)
+}
+
+/// Why sends under `report_key` are suspended on this gateway as of `now`,
+/// if they are
+pub fn local_suspension(st: &InnerState, policy: &Policy, report_key: &str, now: u64) -> Option<String> {
+    let stats = st.protocol_stats.get(report_key)?;
+    if stats.standing.is_suspended() {
+        return Some(reason(&stats.standing));
+    }
+    (st.recert_stage(policy, report_key, now) == Some(RecertStage::Suspended))
+        .then(|| "certification lapsed".to_string())
+}
+
+/// Announce whether `agent_id`'s `protocol` is suspended here, for whatever
+/// reason, to every peer; `lifted` says why when it no longer is
+pub fn announce_standing(state: &AppState, agent_id: &str, protocol: &str, lifted: &str) {
+    let policy = state.policy.current();
+    let suspension = {
+        let st = state.inner.read().unwrap();
+        local_suspension(&st, &policy.policy, &format!("{agent_id}::{protocol}"), state.clock.now())
+    };
+    match suspension {
+        Some(reason) => announce(state, agent_id, protocol, true, &reason),
+        None => announce(state, agent_id, protocol, false, lifted),
+    }
+}
+
+/// Announce a local suspension (or, with `suspended` false, reinstatement)
+/// of `agent_id`'s `protocol` to every peer
+pub fn announce(state: &AppState, agent_id: &str, protocol: &str, suspended: bool, reason: &str) {
+    let sync = &state.registry_sync;
+    if sync.config().token.is_none() || sync.config().peers.is_empty() || state.replication.is_standby() {
+        return;
+    }
+    let now = state.clock.now();
+    let notice = SuspensionNotice {
+        origin: sync.config().name.clone(),
+        agent_id: agent_id.to_string(),
+        protocol: protocol.to_string(),
+        suspended,
+        reason: reason.to_string(),
+        seq: state.federation.next_seq(now),
+        issued_at: now,
+        expires_at: now + state.federation.config.ttl_sec,
+    };
+    let json = serde_json::to_string(&notice).unwrap_or_default();
+    let signed = SignedNotice {
+        signature: sync.sign_bytes(json.as_bytes()),
+        notice: json,
+    };
+    let report_key = format!("{agent_id}::{protocol}");
+    for peer in &sync.config().peers {
+        state.federation.queue(&peer.name, &report_key, &notice, &signed);
+    }
+    let state = state.clone();
+    tokio::spawn(mirror::carry(async move { deliver(&state).await }));
+}
+
+/// Push every notice still owed to a peer
+async fn deliver(state: &AppState) {
+    let sync = &state.registry_sync;
+    let token = sync.config().token.clone().unwrap_or_default();
+    for ((peer, report_key), undelivered) in state.federation.owed(state.clock.now()) {
+        let Some(url) = sync.peer(&peer).map(|p| format!("{}/federation/suspensions", p.url)) else {
+            continue;
+        };
+        let result = state
+            .federation
+            .client
+            .post(url)
+            .bearer_auth(&token)
+            .json(&undelivered.signed)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        let (agent_id, protocol) = report_key.split_once("::").unwrap_or((&report_key, ""));
+        match result {
+            Ok(_) => {
+                state.federation.delivered(&peer, &report_key, undelivered.seq);
+                info!(
+                    agent_id = %agent_id,
+                    protocol = %protocol,
+                    peer = %peer,
+                    seq = undelivered.seq,
+                    event = "suspension_propagated",
+                    "Suspension notice delivered to peer"
+                );
+            }
+            Err(e) => warn!(
+                agent_id = %agent_id,
+                protocol = %protocol,
+                peer = %peer,
+                seq = undelivered.seq,
+                error = %e,
+                retry_in_sec = RETRY_INTERVAL.as_secs(),
+                event = "suspension_propagation_failed",
+                "Suspension notice not delivered to peer, retrying"
+            ),
+        }
+    }
+}
+
+/// Take a peer's suspension or reinstatement notice
+pub async fn receive(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Payload(signed): Payload<SignedNotice>,
+) -> Result<Json<ApiResponse>, GatewayError> {
+    let sync = &state.registry_sync;
+    let Some(expected) = sync.config().token.as_deref() else {
+        return Err(GatewayError::RegistrySyncDisabled);
+    };
+    if !bearer_token(&headers).is_some_and(|t| tokens_match(t, expected)) {
+        return Err(GatewayError::InvalidRegistrySyncToken);
+    }
+    let now = state.clock.now();
+    let notice = state.federation.verify(sync, &signed, now).map_err(|e| {
+        warn!(error = %e, event = "remote_suspension_rejected", "Suspension notice rejected");
+        GatewayError::Invalid(format!("Suspension notice rejected: {e}"))
+    })?;
+    let trust = state.federation.config.trust(&notice.origin);
+    if !state.federation.apply(&notice, trust, now) {
+        return Ok(Json(ApiResponse::success_with_message("Notice already applied")));
+    }
+    state.decision_cache.invalidate_agent(&notice.agent_id);
+    let event = match (notice.suspended, trust) {
+        (_, Trust::Ignore) => "remote_suspension_ignored",
+        (true, _) => "remote_suspension_applied",
+        (false, _) => "remote_suspension_lifted",
+    };
+    warn!(
+        agent_id = %notice.agent_id,
+        protocol = %notice.protocol,
+        origin = %notice.origin,
+        trust = ?trust,
+        reason = %notice.reason,
+        expires_at = notice.expires_at,
+        event = event,
+        "Suspension notice from peer applied"
+    );
+    Ok(Json(ApiResponse::success()))
+}
+
+/// Retry undelivered notices every [`RETRY_INTERVAL`], and announce local
+/// suspensions still in force every half TTL, so peers keep them past their
+/// notices' expiry
+pub async fn run(state: AppState) {
+    if state.registry_sync.config().token.is_none() || state.registry_sync.config().peers.is_empty() {
+        return;
+    }
+    let mut reannounce = tokio::time::interval(Duration::from_secs((state.federation.config.ttl_sec / 2).max(1)));
+    reannounce.tick().await;
+    let mut retry = tokio::time::interval(RETRY_INTERVAL);
+    retry.tick().await;
+    loop {
+        tokio::select! {
+            _ = retry.tick() => {
+                if !state.replication.is_standby() {
+                    deliver(&state).await;
+                }
+                continue;
+            }
+            _ = reannounce.tick() => {}
+        }
+        let policy = state.policy.current();
+        let now = state.clock.now();
+        let suspended: Vec<(String, String, String)> = {
+            let st = state.inner.read().unwrap();
+            st.protocol_stats
+                .keys()
+                .filter_map(|key| {
+                    let reason = local_suspension(&st, &policy.policy, key, now)?;
+                    let (agent_id, protocol) = key.split_once("::")?;
+                    Some((agent_id.to_string(), protocol.to_string(), reason))
+                })
+                .collect()
+        };
+        for (agent_id, protocol, reason) in suspended {
+            announce(&state, &agent_id, &protocol, true, &reason);
+        }
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        registry_sync::{Peer, RegistrySyncConfig},
+        testing::{AgentFixture, ProtocolFixture, SendFixture, TestGateway},
+    };
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use ed25519_dalek::SigningKey;
+
+    fn notice(origin: &str, suspended: bool, issued_at: u64) -> SuspensionNotice {
+        SuspensionNotice {
+            origin: origin.to_string(),
+            agent_id: "a".to_string(),
+            protocol: "coord:1.0".to_string(),
+            suspended,
+            reason: "held reports".to_string(),
+            seq: issued_at * 1_000_000,
+            issued_at,
+            expires_at: issued_at + 1_000,
+        }
+    }
+
+    #[test]
+    fn test_trust_and_replays() {
+        let federation = Federation::new(FederationConfig {
+            trust: BTreeMap::from([("us".to_string(), Trust::Flag), ("ap".to_string(), Trust::Ignore)]),
+            ttl_sec: 500,
+            ..FederationConfig::default()
+        });
+        assert!(federation.apply(&notice("eu", true, 100), Trust::Enforce, 100));
+        assert!(federation.apply(&notice("us", true, 100), federation.config.trust("us"), 100));
+        assert!(federation.apply(&notice("ap", true, 100), federation.config.trust("ap"), 100));
+        let held = federation.suspensions("a::coord:1.0", 100);
+        assert_eq!(held.iter().map(|s| s.origin.as_str()).collect::<Vec<_>>(), ["eu", "us"]);
+        assert_eq!(held[0].expires_at, 600, "capped at the local TTL");
+        assert_eq!(federation.enforced("a::coord:1.0", 100).unwrap().origin, "eu");
+
+        // A replayed suspension does not undo a later reinstatement
+        assert!(federation.apply(&notice("eu", false, 200), Trust::Enforce, 200));
+        assert!(!federation.apply(&notice("eu", true, 100), Trust::Enforce, 200));
+        assert_eq!(federation.enforced("a::coord:1.0", 200), None);
+        assert_eq!(federation.all(200).len(), 1, "us still flags it");
+        assert!(federation.all(601).is_empty(), "expired");
+
+        // Suspended and reinstated within one second, told apart by sequence
+        let seq = federation.next_seq(700);
+        assert!(seq >= 700_000_000 && federation.next_seq(700) > seq && federation.next_seq(0) > seq);
+        let suspended = SuspensionNotice { seq, ..notice("eu", true, 700) };
+        let lifted = SuspensionNotice { seq: seq + 1, ..notice("eu", false, 700) };
+        assert!(federation.apply(&suspended, Trust::Enforce, 700));
+        assert!(federation.apply(&lifted, Trust::Enforce, 700));
+        assert_eq!(federation.enforced("a::coord:1.0", 700), None);
+    }
+
+    #[test]
+    fn test_undelivered_notices_are_kept_until_taken() {
+        let federation = Federation::default();
+        let signed = |n: &SuspensionNotice| SignedNotice {
+            notice: serde_json::to_string(n).unwrap(),
+            signature: String::new(),
+        };
+        let (older, newer) = (notice("eu", true, 100), notice("eu", false, 200));
+        federation.queue("us", "a::coord:1.0", &newer, &signed(&newer));
+        federation.queue("us", "a::coord:1.0", &older, &signed(&older));
+        federation.queue("ap", "a::coord:1.0", &older, &signed(&older));
+        let owed = federation.owed(300);
+        assert_eq!(owed.len(), 2);
+        assert!(owed.iter().all(|((peer, _), u)| u.seq == if peer == "us" { newer.seq } else { older.seq }));
+
+        // Taking a superseded notice does not clear the newer one
+        federation.delivered("us", "a::coord:1.0", older.seq);
+        federation.delivered("ap", "a::coord:1.0", older.seq);
+        assert_eq!(federation.owed(300).len(), 1);
+        assert!(federation.owed(1_200).is_empty(), "expired");
+    }
+
+    #[tokio::test]
+    async fn test_signed_peer_suspension_enforced_and_listed() {
+        let eu = RegistrySync::new(RegistrySyncConfig {
+            name: "eu".to_string(),
+            seed: Some([1; 32]),
+            ..RegistrySyncConfig::default()
+        });
+        let gw = TestGateway::with_registry_sync(RegistrySyncConfig {
+            name: "us".to_string(),
+            token: Some("sync".to_string()),
+            peers: vec![Peer {
+                name: "eu".to_string(),
+                url: String::new(),
+                key: SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            }],
+            ..RegistrySyncConfig::default()
+        });
+        let coord = ProtocolFixture::new("coord", "1.0").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(coord.clone()).reported()).await;
+        let push = |notice: &SuspensionNotice, signer: &RegistrySync| {
+            let notice = serde_json::to_string(notice).unwrap();
+            let signed = SignedNotice {
+                signature: signer.sign_bytes(notice.as_bytes()),
+                notice,
+            };
+            Request::post("/federation/suspensions")
+                .header("content-type", "application/json")
+                .header("authorization", "Bearer sync")
+                .body(Body::from(serde_json::to_vec(&signed).unwrap()))
+                .unwrap()
+        };
+        let now = gw.now();
+        let suspended = SuspensionNotice {
+            issued_at: now,
+            expires_at: now + 3_600,
+            ..notice("eu", true, now)
+        };
+
+        // Signed by a key not pinned for eu
+        let impostor = RegistrySync::new(RegistrySyncConfig {
+            name: "eu".to_string(),
+            seed: Some([2; 32]),
+            ..RegistrySyncConfig::default()
+        });
+        assert_eq!(gw.request(push(&suspended, &impostor)).await.status, StatusCode::BAD_REQUEST);
+        assert_eq!(gw.request(push(&suspended, &eu)).await.status, StatusCode::OK);
+
+        let refused = gw.send(&SendFixture::novel("a", "b", &coord, "SHP|eta=7f").build()).await;
+        assert_eq!(refused.status, StatusCode::FORBIDDEN);
+        assert_eq!(refused.body["code"], "protocol_suspended");
+        assert!(refused.body.to_string().contains("gateway eu"), "{:?}", refused.body);
+        let status = gw.get("/status").await;
+        let listed = &status.body["suspensions"][0];
+        assert_eq!(listed["origin"], "eu");
+        assert_eq!(listed["protocol"], "coord:1.0");
+        assert_eq!(listed["enforced"], true);
+
+        let lifted = notice("eu", false, now + 1);
+        assert_eq!(gw.request(push(&lifted, &eu)).await.status, StatusCode::OK);
+        let sent = gw.send(&SendFixture::novel("a", "b", &coord, "SHP|eta=7f").build()).await;
+        assert_eq!(sent.status, StatusCode::OK, "{:?}", sent.body);
+        assert_eq!(gw.get("/status").await.body["suspensions"], serde_json::json!([]));
+    }
+}
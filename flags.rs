@@ -0,0 +1,256 @@
+//! Runtime feature flags with targeting and percentage rollout
+//!
+//! New enforcement behaviours ship behind a named flag so they can be turned
+//! on for a few agents or teams, then a growing share of agents, before
+//! everyone. A flag is on for an agent when any of these holds, checked in
+//! order:
+//!
+//! 1. the flag is not `enabled` (kill switch): off for everyone
+//! 2. the agent is in `exclude_agents`: off
+//! 3. the agent is in `agents`, or its team in `tenants`: on
+//! 4. the agent's rollout bucket is below `rollout_percent`: on
+//!
+//! Buckets come from FNV-1a over the flag name and agent id, so an agent stays
+//! in or out of a rollout across restarts and replicas, and raising the
+//! percentage only ever adds agents. Flags start from `FEATURE_FLAGS` (JSON)
+//! and are replaced at runtime via `PUT /admin/flags`.
+//!
+//! Enforcement behaviours named below ([`MESSAGE_SCHEMA`], [`SUMMARY_QUALITY`])
+//! check their flag with [`FeatureFlags::gates`]: they apply to every agent
+//! until a flag of their name is configured, and from then on only to the
+//! agents it is on for. Replacing the flags drops every cached decision.
+//!
+//! Sends and reports run inside a span carrying the flags active for the
+//! agent, so every decision event in the audit trail records them as `flags`.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    sync::RwLock,
+};
+use tracing::warn;
+
+/// Rollout buckets per percent
+const BUCKETS_PER_PERCENT: u64 = 100;
+
+/// Validation of novel-language sends against their protocol's message schema
+pub const MESSAGE_SCHEMA: &str = "message_schema";
+
+/// The report summary quality floor of the policy's `summary_quality`
+pub const SUMMARY_QUALITY: &str = "summary_quality";
+
+/// Targeting of one flag
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlagRule {
+    /// Kill switch; a disabled flag is off for everyone
+    pub enabled: bool,
+    /// Share of agents (0-100) the flag is on for
+    pub rollout_percent: f64,
+    /// Agents the flag is always on for
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub agents: BTreeSet<String>,
+    /// Teams whose agents the flag is always on for
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub tenants: BTreeSet<String>,
+    /// Agents the flag is always off for
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub exclude_agents: BTreeSet<String>,
+}
+
+impl Default for FlagRule {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rollout_percent: 0.0,
+            agents: BTreeSet::new(),
+            tenants: BTreeSet::new(),
+            exclude_agents: BTreeSet::new(),
+        }
+    }
+}
+
+impl FlagRule {
+    fn validate(&self) -> Result<(), String> {
+        if !(0.0..=100.0).contains(&self.rollout_percent) {
+            return Err("rollout_percent must be within [0, 100]".to_string());
+        }
+        Ok(())
+    }
+
+    fn applies(&self, name: &str, agent_id: &str, tenant: Option<&str>) -> bool {
+        if !self.enabled || self.exclude_agents.contains(agent_id) {
+            return false;
+        }
+        if self.agents.contains(agent_id) || tenant.is_some_and(|t| self.tenants.contains(t)) {
+            return true;
+        }
+        let threshold = (self.rollout_percent * BUCKETS_PER_PERCENT as f64) as u64;
+        bucket(name, agent_id) < threshold
+    }
+}
+
+/// Stable rollout bucket of `agent_id` for flag `name`, in [0, 10000)
+fn bucket(name: &str, agent_id: &str) -> u64 {
+    let hash = name
+        .bytes()
+        .chain([0])
+        .chain(agent_id.bytes())
+        .fold(0xcbf2_9ce4_8422_2325u64, |h, b| (h ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3));
+    hash % (100 * BUCKETS_PER_PERCENT)
+}
+
+/// Every flag, keyed by name
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FlagConfig {
+    pub flags: BTreeMap<String, FlagRule>,
+}
+
+impl FlagConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, rule) in &self.flags {
+            if name.trim().is_empty() || name.contains(',') {
+                return Err(format!("invalid flag name {name:?}"));
+            }
+            rule.validate().map_err(|e| format!("{name}: {e}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Runtime-controllable feature flags
+#[derive(Debug, Default)]
+pub struct FeatureFlags {
+    config: RwLock<FlagConfig>,
+}
+
+impl FeatureFlags {
+    pub fn new(config: FlagConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+        }
+    }
+
+    /// Load the initial flags from `FEATURE_FLAGS`
+    pub fn from_env() -> Self {
+        let config = match env::var("FEATURE_FLAGS") {
+            Ok(json) => match serde_json::from_str::<FlagConfig>(&json)
+                .map_err(|e| e.to_string())
+                .and_then(|c| c.validate().map(|_| c))
+            {
+                Ok(config) => config,
+                Err(e) => {
+                    warn!(event = "config_invalid", error = %e, "Invalid FEATURE_FLAGS, starting without flags");
+                    FlagConfig::default()
+                }
+            },
+            Err(_) => FlagConfig::default(),
+        };
+        Self::new(config)
+    }
+
+    pub fn config(&self) -> FlagConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    pub fn set_config(&self, config: FlagConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Whether flag `name` is on for `agent_id` in team `tenant`
+    pub fn enabled(&self, name: &str, agent_id: &str, tenant: Option<&str>) -> bool {
+        self.config
+            .read()
+            .unwrap()
+            .flags
+            .get(name)
+            .is_some_and(|rule| rule.applies(name, agent_id, tenant))
+    }
+
+    /// Whether the enforcement behaviour behind flag `name` applies to
+    /// `agent_id` in team `tenant`: always while no such flag is configured,
+    /// otherwise when the flag is on
+    pub fn gates(&self, name: &str, agent_id: &str, tenant: Option<&str>) -> bool {
+        !self.config.read().unwrap().flags.contains_key(name) || self.enabled(name, agent_id, tenant)
+    }
+
+    /// Names of the flags on for `agent_id`, comma-separated; empty when none are
+    pub fn active(&self, agent_id: &str, tenant: Option<&str>) -> String {
+        let config = self.config.read().unwrap();
+        let mut active = String::new();
+        for (name, rule) in &config.flags {
+            if rule.applies(name, agent_id, tenant) {
+                if !active.is_empty() {
+                    active.push(',');
+                }
+                active.push_str(name);
+            }
+        }
+        active
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targeting_and_kill_switch() {
+        let rule = FlagRule {
+            agents: BTreeSet::from(["a".to_string()]),
+            tenants: BTreeSet::from(["red".to_string()]),
+            exclude_agents: BTreeSet::from(["x".to_string()]),
+            ..FlagRule::default()
+        };
+        let flags = FeatureFlags::new(FlagConfig {
+            flags: BTreeMap::from([("strict_vocab".to_string(), rule.clone())]),
+        });
+        assert!(flags.enabled("strict_vocab", "a", None));
+        assert!(flags.enabled("strict_vocab", "b", Some("red")));
+        assert!(!flags.enabled("strict_vocab", "x", Some("red")));
+        assert!(!flags.enabled("strict_vocab", "b", Some("blue")));
+        assert!(!flags.enabled("other", "a", None));
+        assert_eq!(flags.active("a", None), "strict_vocab");
+        assert_eq!(flags.active("b", None), "");
+
+        let off = FlagRule { enabled: false, ..rule };
+        flags.set_config(FlagConfig { flags: BTreeMap::from([("strict_vocab".to_string(), off)]) });
+        assert!(!flags.enabled("strict_vocab", "a", None));
+    }
+
+    #[test]
+    fn test_gates_apply_until_configured() {
+        let flags = FeatureFlags::default();
+        assert!(flags.gates(MESSAGE_SCHEMA, "a", None), "ungated without a flag");
+
+        let rule = FlagRule {
+            agents: BTreeSet::from(["a".to_string()]),
+            ..FlagRule::default()
+        };
+        flags.set_config(FlagConfig { flags: BTreeMap::from([(MESSAGE_SCHEMA.to_string(), rule)]) });
+        assert!(flags.gates(MESSAGE_SCHEMA, "a", None));
+        assert!(!flags.gates(MESSAGE_SCHEMA, "b", None));
+        assert!(flags.gates(SUMMARY_QUALITY, "b", None));
+    }
+
+    #[test]
+    fn test_percentage_rollout() {
+        let at = |percent| FlagRule { rollout_percent: percent, ..FlagRule::default() };
+        let agents: Vec<String> = (0..10_000).map(|i| format!("agent-{i}")).collect();
+        let on = |percent| agents.iter().filter(|a| at(percent).applies("f", a, None)).count();
+        assert_eq!(on(0.0), 0);
+        assert_eq!(on(100.0), agents.len());
+        assert!((2_000..3_000).contains(&on(25.0)), "{} agents at 25%", on(25.0));
+
+        // Raising the percentage only adds agents
+        assert!(agents.iter().all(|a| !at(10.0).applies("f", a, None) || at(50.0).applies("f", a, None)));
+
+        assert!(FlagConfig { flags: BTreeMap::from([("f".into(), at(101.0))]) }.validate().is_err());
+        assert!(FlagConfig { flags: BTreeMap::from([("a,b".into(), at(1.0))]) }.validate().is_err());
+    }
+}
@@ -0,0 +1,379 @@
+//! Message forwarding between gateways
+//!
+//! Agents in different clusters sit behind different gateways. A recipient
+//! matching a route in `FORWARD_ROUTES` is served by the peer gateway the
+//! route names: once a send has passed every local check, the gateway relays
+//! it to that peer's `POST /forward`, and the peer re-evaluates its own
+//! receiver-side policy (deleted recipients, routing classes) for each
+//! recipient. The peer's decisions replace the local ones in the send
+//! response, so a recipient is allowed only when both gateways allow it. A
+//! peer that cannot be reached refuses its recipients.
+//!
+//! Every relay carries a random correlation id. The sending gateway logs
+//! `msg_forwarded` (or `msg_forward_failed`) and the receiving gateway logs
+//! `msg_forward_received` with the same id, linking the two audit trails.
+//!
+//! Gateways authenticate each other with the shared `FORWARD_TOKEN`;
+//! forwarding is disabled in both directions when it is unset. A forwarded
+//! message is never forwarded again: recipients the receiving gateway would
+//! itself route elsewhere are refused.
+//!
+//! A shadow gateway does not forward mirrored sends; their recipients keep
+//! the local decision.
+
+use axum::{extract::State, http::HeaderMap, Json};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env, time::Duration};
+use tracing::{info, warn};
+
+use crate::{
+    bearer_token, codec::Payload, error::GatewayError, mirror, recipient_decision, signing, tokens_match, AppState,
+    RecipientDecision, SendMessageRequest,
+};
+
+/// Per-request timeout when relaying to a peer
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(10);
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+/// A gateway recipients can be forwarded to
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForwardPeer {
+    pub name: String,
+    pub url: String,
+}
+
+/// Recipients served by a peer: an exact agent id, or a prefix ending in `*`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub pattern: String,
+    pub peer: String,
+}
+
+impl Route {
+    pub fn matches(&self, agent_id: &str) -> bool {
+        match self.pattern.strip_suffix('*') {
+            Some(prefix) => agent_id.starts_with(prefix),
+            None => self.pattern == agent_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ForwardingConfig {
+    /// Name of this gateway in forwarded messages
+    pub name: String,
+    /// Shared secret between gateways; forwarding is disabled when unset
+    pub token: Option<String>,
+    pub peers: Vec<ForwardPeer>,
+    /// Checked in order; the first matching route wins
+    pub routes: Vec<Route>,
+}
+
+impl Default for ForwardingConfig {
+    fn default() -> Self {
+        Self {
+            name: "gateway".to_string(),
+            token: None,
+            peers: Vec::new(),
+            routes: Vec::new(),
+        }
+    }
+}
+
+impl ForwardingConfig {
+    /// Load `FORWARD_TOKEN`, `FORWARD_PEERS` (`name=url,...`), `FORWARD_ROUTES`
+    /// (`pattern=peer,...`), and `FORWARD_NAME`, which defaults to
+    /// `REGISTRY_SYNC_NAME`
+    pub fn from_env() -> Self {
+        let d = Self::default();
+        let list = |name: &str| {
+            env::var(name)
+                .unwrap_or_default()
+                .split(',')
+                .map(|e| e.trim().to_string())
+                .filter(|e| !e.is_empty())
+                .collect::<Vec<_>>()
+        };
+        let mut peers = Vec::new();
+        for entry in list("FORWARD_PEERS") {
+            match entry.split_once('=') {
+                Some((name, url)) => peers.push(ForwardPeer {
+                    name: name.trim().to_string(),
+                    url: url.trim().trim_end_matches('/').to_string(),
+                }),
+                None => warn!(event = "config_invalid", entry = %entry, "Ignoring FORWARD_PEERS entry: expected name=url"),
+            }
+        }
+        let mut routes = Vec::new();
+        for entry in list("FORWARD_ROUTES") {
+            let Some((pattern, peer)) = entry.split_once('=') else {
+                warn!(event = "config_invalid", entry = %entry, "Ignoring FORWARD_ROUTES entry: expected pattern=peer");
+                continue;
+            };
+            let peer = peer.trim().to_string();
+            if peers.iter().any(|p| p.name == peer) {
+                routes.push(Route {
+                    pattern: pattern.trim().to_string(),
+                    peer,
+                });
+            } else {
+                warn!(event = "config_invalid", peer = %peer, "Forwarding route names a peer missing from FORWARD_PEERS, ignoring it");
+            }
+        }
+        let name = ["FORWARD_NAME", "REGISTRY_SYNC_NAME"]
+            .into_iter()
+            .find_map(|key| env::var(key).ok().map(|n| n.trim().to_string()).filter(|n| !n.is_empty()))
+            .unwrap_or(d.name);
+        Self {
+            name,
+            token: env::var("FORWARD_TOKEN").ok().filter(|t| !t.is_empty()),
+            peers,
+            routes,
+        }
+    }
+}
+
+// =============================================================================
+// Forwarding
+// =============================================================================
+
+/// Body of `POST /forward`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardEnvelope {
+    /// Shared by the audit events of both gateways
+    pub correlation_id: String,
+    /// Name of the forwarding gateway
+    pub origin: String,
+    pub from: String,
+    pub to: Vec<String>,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Sender's protocol key for novel-language sends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+}
+
+/// Response of `POST /forward`: the receiving gateway's decision per recipient
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardReply {
+    pub correlation_id: String,
+    pub decisions: BTreeMap<String, RecipientDecision>,
+}
+
+#[derive(Debug)]
+pub struct Forwarding {
+    config: ForwardingConfig,
+    client: reqwest::Client,
+}
+
+impl Default for Forwarding {
+    fn default() -> Self {
+        Self::new(ForwardingConfig::default())
+    }
+}
+
+impl Forwarding {
+    pub fn new(config: ForwardingConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::builder()
+                .timeout(FORWARD_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn config(&self) -> &ForwardingConfig {
+        &self.config
+    }
+
+    /// Peer serving `agent_id`, if it is not served here
+    pub fn route(&self, agent_id: &str) -> Option<&ForwardPeer> {
+        self.config.token.as_ref()?;
+        let route = self.config.routes.iter().find(|r| r.matches(agent_id))?;
+        self.config.peers.iter().find(|p| p.name == route.peer)
+    }
+
+    async fn relay(&self, peer: &ForwardPeer, token: &str, envelope: &ForwardEnvelope) -> Result<ForwardReply, String> {
+        self.client
+            .post(format!("{}/forward", peer.url))
+            .bearer_auth(token)
+            .json(envelope)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Relay recipients allowed here but served by a peer, replacing their
+/// decisions with the peer's
+pub async fn forward(
+    state: &AppState,
+    req: &SendMessageRequest,
+    protocol: Option<&str>,
+    decisions: &mut BTreeMap<String, RecipientDecision>,
+) {
+    let forwarding = &state.forwarding;
+    let Some(token) = forwarding.config.token.as_deref() else {
+        return;
+    };
+    if mirror::is_mirrored() {
+        return;
+    }
+    let mut by_peer: BTreeMap<&str, (&ForwardPeer, Vec<String>)> = BTreeMap::new();
+    for (to, _) in decisions.iter().filter(|(_, d)| d.allowed) {
+        if let Some(peer) = forwarding.route(to) {
+            by_peer.entry(&peer.name).or_insert_with(|| (peer, Vec::new())).1.push(to.clone());
+        }
+    }
+
+    for (peer, to) in by_peer.into_values() {
+        let envelope = ForwardEnvelope {
+            correlation_id: signing::random_hex::<16>(),
+            origin: forwarding.config.name.clone(),
+            from: req.from.to_string(),
+            to,
+            content: req.content.clone(),
+            content_type: req.content_type.clone(),
+            protocol: protocol.map(str::to_string),
+            thread_id: req.thread_id.clone(),
+            message_id: req.message_id.clone(),
+        };
+        match forwarding.relay(peer, token, &envelope).await {
+            Ok(mut reply) => {
+                for to in &envelope.to {
+                    let decision = reply
+                        .decisions
+                        .remove(to)
+                        .unwrap_or_else(|| RecipientDecision::deny(&format!("Gateway {} gave no decision", peer.name)));
+                    info!(
+                        from = %req.from,
+                        to = %to,
+                        peer = %peer.name,
+                        correlation_id = %envelope.correlation_id,
+                        allowed = decision.allowed,
+                        event = "msg_forwarded",
+                        "Message forwarded to peer gateway"
+                    );
+                    decisions.insert(to.clone(), decision);
+                }
+            }
+            Err(error) => {
+                warn!(
+                    from = %req.from,
+                    to = ?envelope.to,
+                    peer = %peer.name,
+                    correlation_id = %envelope.correlation_id,
+                    error = %error,
+                    event = "msg_forward_failed",
+                    "Forwarding to peer gateway failed"
+                );
+                let refused = RecipientDecision::deny(&format!("Gateway {} unreachable", peer.name));
+                for to in &envelope.to {
+                    decisions.insert(to.clone(), refused.clone());
+                }
+            }
+        }
+    }
+}
+
+// =============================================================================
+// Handler
+// =============================================================================
+
+/// Re-evaluate receiver-side policy for a message forwarded by a peer
+pub(crate) async fn receive(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Payload(envelope): Payload<ForwardEnvelope>,
+) -> Result<Json<ForwardReply>, GatewayError> {
+    let Some(expected) = state.forwarding.config.token.as_deref() else {
+        return Err(GatewayError::ForwardingDisabled);
+    };
+    if !bearer_token(&headers).is_some_and(|t| tokens_match(t, expected)) {
+        return Err(GatewayError::InvalidForwardToken);
+    }
+    if envelope.to.is_empty() {
+        return Err(GatewayError::Invalid("to must list at least one recipient".to_string()));
+    }
+
+    let policy = state.policy.current();
+    let decisions: BTreeMap<_, _> = {
+        let st = state.inner.read().unwrap();
+        envelope
+            .to
+            .iter()
+            .map(|to| {
+                let decision = match state.forwarding.route(to) {
+                    Some(_) => RecipientDecision::deny("Recipient is not served by this gateway"),
+                    None => recipient_decision(&st, &policy.policy, &envelope.from, to, envelope.protocol.as_deref()),
+                };
+                (to.clone(), decision)
+            })
+            .collect()
+    };
+    for (to, decision) in &decisions {
+        info!(
+            from = %envelope.from,
+            to = %to,
+            origin = %envelope.origin,
+            correlation_id = %envelope.correlation_id,
+            protocol = envelope.protocol.as_deref(),
+            thread_id = envelope.thread_id.as_deref(),
+            allowed = decision.allowed,
+            reason = decision.reason.as_deref(),
+            event = "msg_forward_received",
+            "Forwarded message re-evaluated"
+        );
+    }
+    Ok(Json(ForwardReply {
+        correlation_id: envelope.correlation_id,
+        decisions,
+    }))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routes() {
+        let peer = |name: &str| ForwardPeer {
+            name: name.to_string(),
+            url: format!("https://{name}.example"),
+        };
+        let route = |pattern: &str, peer: &str| Route {
+            pattern: pattern.to_string(),
+            peer: peer.to_string(),
+        };
+        let config = ForwardingConfig {
+            token: Some("secret".into()),
+            peers: vec![peer("eu"), peer("us")],
+            routes: vec![route("eu-billing", "us"), route("eu-*", "eu")],
+            ..ForwardingConfig::default()
+        };
+        let forwarding = Forwarding::new(config.clone());
+        assert_eq!(forwarding.route("eu-billing").map(|p| p.name.as_str()), Some("us"), "first match wins");
+        assert_eq!(forwarding.route("eu-ledger").map(|p| p.name.as_str()), Some("eu"));
+        assert_eq!(forwarding.route("local"), None);
+
+        let disabled = Forwarding::new(ForwardingConfig { token: None, ..config });
+        assert_eq!(disabled.route("eu-ledger"), None, "no routing without a token");
+    }
+}
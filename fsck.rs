@@ -0,0 +1,317 @@
+//! State consistency check and repair
+//!
+//! Crashes, partial writes, or a replica catching up out of order can leave
+//! the state store referring to things that no longer exist. `POST /admin/fsck`
+//! scans it for:
+//!
+//! - **malformed keys**: report clocks, stats, or traffic not keyed
+//!   `agent_id::protocol_key`
+//! - **dangling report clocks**, **orphan stats**, and **orphan traffic**:
+//!   entries for a protocol the agent has not registered
+//! - **orphan violations**: violation counts of an agent the gateway knows
+//!   nothing else about (no protocol, owner, discovery record, track record,
+//!   or deletion)
+//! - **dangling reviews**: held reports for a protocol no longer registered
+//! - **future timestamps**: report clocks, registration and last-use times,
+//!   and deletion times more than [`FUTURE_TOLERANCE_SEC`] ahead of the clock
+//!
+//! Each finding carries the repair that resolves it, if any: dropping the
+//! entry, or clamping the timestamp to now. Dangling reviews are left for a
+//! reviewer to reject. With `{"repair": true}` the repairs are applied,
+//! replicated to standbys, and each logged as a `state_repaired` audit event.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::InnerState;
+
+/// How far ahead of the clock a timestamp may be before it is impossible
+pub const FUTURE_TOLERANCE_SEC: u64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingKind {
+    MalformedKey,
+    DanglingReportClock,
+    OrphanStats,
+    OrphanTraffic,
+    OrphanViolations,
+    DanglingReview,
+    FutureTimestamp,
+}
+
+impl FindingKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::MalformedKey => "malformed_key",
+            Self::DanglingReportClock => "dangling_report_clock",
+            Self::OrphanStats => "orphan_stats",
+            Self::OrphanTraffic => "orphan_traffic",
+            Self::OrphanViolations => "orphan_violations",
+            Self::DanglingReview => "dangling_review",
+            Self::FutureTimestamp => "future_timestamp",
+        }
+    }
+}
+
+/// A change that resolves a finding
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Repair {
+    DropReportClock { report_key: String },
+    DropStats { report_key: String },
+    DropTraffic { report_key: String },
+    DropViolations { agent_id: String },
+    ClampReportClock { report_key: String, ts: u64 },
+    /// Clamp a protocol's registration and last-use times
+    ClampStats { report_key: String, ts: u64 },
+    ClampDeletion { agent_id: String, ts: u64 },
+}
+
+impl Repair {
+    /// Agent whose cached send decisions the repair may change
+    pub fn agent_id(&self) -> &str {
+        match self {
+            Self::DropReportClock { report_key }
+            | Self::DropStats { report_key }
+            | Self::DropTraffic { report_key }
+            | Self::ClampReportClock { report_key, .. }
+            | Self::ClampStats { report_key, .. } => {
+                report_key.split_once("::").map_or(report_key, |(agent_id, _)| agent_id)
+            }
+            Self::DropViolations { agent_id } | Self::ClampDeletion { agent_id, .. } => agent_id,
+        }
+    }
+
+    pub fn apply(&self, st: &mut InnerState) {
+        match self {
+            Self::DropReportClock { report_key } => {
+                st.last_report_ts.remove(report_key);
+            }
+            Self::DropStats { report_key } => {
+                st.protocol_stats.remove(report_key);
+            }
+            Self::DropTraffic { report_key } => {
+                st.traffic.remove(report_key);
+            }
+            Self::DropViolations { agent_id } => {
+                st.violations.remove(agent_id);
+            }
+            Self::ClampReportClock { report_key, ts } => {
+                if let Some(last) = st.last_report_ts.get_mut(report_key) {
+                    *last = (*last).min(*ts);
+                }
+            }
+            Self::ClampStats { report_key, ts } => {
+                if let Some(stats) = st.protocol_stats.get_mut(report_key) {
+                    stats.registered_at = stats.registered_at.min(*ts);
+                    stats.last_used_ts = stats.last_used_ts.map(|t| t.min(*ts));
+                }
+            }
+            Self::ClampDeletion { agent_id, ts } => {
+                if let Some(deleted_at) = st.deleted_agents.get_mut(agent_id) {
+                    *deleted_at = (*deleted_at).min(*ts);
+                }
+            }
+        }
+    }
+}
+
+/// One inconsistency in the state store
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    pub kind: FindingKind,
+    /// Key or id of the offending entry
+    pub key: String,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repair: Option<Repair>,
+}
+
+/// Body of `POST /admin/fsck`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FsckRequest {
+    /// Apply the repairs of the findings
+    #[serde(default)]
+    pub repair: bool,
+}
+
+/// Response of `POST /admin/fsck`
+#[derive(Debug, Clone, Serialize)]
+pub struct FsckReport {
+    pub scanned_at: u64,
+    pub findings: Vec<Finding>,
+    /// Repairs applied; 0 unless repair was requested
+    pub repaired: usize,
+}
+
+/// Whether `agent_id` has registered `protocol`
+fn registered(st: &InnerState, report_key: &str) -> Option<bool> {
+    let (agent_id, protocol) = report_key.split_once("::")?;
+    Some(st.protocols.get(agent_id).is_some_and(|m| m.contains_key(protocol)))
+}
+
+/// Check one `agent_id::protocol_key` map for malformed and unregistered keys
+fn check_keys<'a>(
+    st: &InnerState,
+    keys: impl Iterator<Item = &'a String>,
+    kind: FindingKind,
+    what: &str,
+    drop: impl Fn(String) -> Repair,
+    findings: &mut Vec<Finding>,
+) {
+    for key in keys {
+        match registered(st, key) {
+            None => findings.push(Finding {
+                kind: FindingKind::MalformedKey,
+                key: key.clone(),
+                detail: format!("{what} key is not agent_id::protocol_key"),
+                repair: Some(drop(key.clone())),
+            }),
+            Some(false) => findings.push(Finding {
+                kind,
+                key: key.clone(),
+                detail: format!("{what} for a protocol the agent has not registered"),
+                repair: Some(drop(key.clone())),
+            }),
+            Some(true) => {}
+        }
+    }
+}
+
+/// Every inconsistency in `st` as of `now`, in a stable order
+pub fn scan(st: &InnerState, now: u64) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let future = |ts: u64| ts > now + FUTURE_TOLERANCE_SEC;
+
+    check_keys(
+        st,
+        st.last_report_ts.keys(),
+        FindingKind::DanglingReportClock,
+        "Report clock",
+        |report_key| Repair::DropReportClock { report_key },
+        &mut findings,
+    );
+    check_keys(
+        st,
+        st.protocol_stats.keys(),
+        FindingKind::OrphanStats,
+        "Usage stats",
+        |report_key| Repair::DropStats { report_key },
+        &mut findings,
+    );
+    check_keys(
+        st,
+        st.traffic.keys(),
+        FindingKind::OrphanTraffic,
+        "Traffic samples",
+        |report_key| Repair::DropTraffic { report_key },
+        &mut findings,
+    );
+
+    let known: HashSet<&str> = st
+        .protocols
+        .keys()
+        .chain(st.owners.keys())
+        .chain(st.discovered.keys())
+        .chain(st.track_records.keys())
+        .chain(st.deleted_agents.keys())
+        .map(String::as_str)
+        .collect();
+    for agent_id in st.violations.keys().filter(|a| !known.contains(a.as_str())) {
+        findings.push(Finding {
+            kind: FindingKind::OrphanViolations,
+            key: agent_id.clone(),
+            detail: "Violations of an agent unknown to the gateway".to_string(),
+            repair: Some(Repair::DropViolations { agent_id: agent_id.clone() }),
+        });
+    }
+
+    for (id, review) in &st.reviews {
+        let report_key = format!("{}::{}", review.report.agent_id, review.protocol);
+        if registered(st, &report_key) == Some(false) {
+            findings.push(Finding {
+                kind: FindingKind::DanglingReview,
+                key: id.to_string(),
+                detail: format!("Held report for {report_key}, which is not registered; reject it"),
+                repair: None,
+            });
+        }
+    }
+
+    for (report_key, &last) in st.last_report_ts.iter().filter(|(_, &ts)| future(ts)) {
+        findings.push(Finding {
+            kind: FindingKind::FutureTimestamp,
+            key: report_key.clone(),
+            detail: format!("Report clock {last} is ahead of now ({now})"),
+            repair: Some(Repair::ClampReportClock { report_key: report_key.clone(), ts: now }),
+        });
+    }
+    for (report_key, stats) in &st.protocol_stats {
+        let latest = stats.registered_at.max(stats.last_used_ts.unwrap_or(0));
+        if future(latest) {
+            findings.push(Finding {
+                kind: FindingKind::FutureTimestamp,
+                key: report_key.clone(),
+                detail: format!("Registration or last use {latest} is ahead of now ({now})"),
+                repair: Some(Repair::ClampStats { report_key: report_key.clone(), ts: now }),
+            });
+        }
+    }
+    for (agent_id, &deleted_at) in st.deleted_agents.iter().filter(|(_, &ts)| future(ts)) {
+        findings.push(Finding {
+            kind: FindingKind::FutureTimestamp,
+            key: agent_id.clone(),
+            detail: format!("Deletion time {deleted_at} is ahead of now ({now})"),
+            repair: Some(Repair::ClampDeletion { agent_id: agent_id.clone(), ts: now }),
+        });
+    }
+
+    findings.sort_by(|a, b| (a.kind.as_str(), &a.key).cmp(&(b.kind.as_str(), &b.key)));
+    findings
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::ProtocolFixture, ProtocolStats};
+
+    #[test]
+    fn test_scan_and_repair() {
+        let now = 1_000_000;
+        let mut st = InnerState::default();
+        let coord = ProtocolFixture::new("coord", "1.0").build();
+        st.protocols.entry("a".into()).or_default().insert("coord:1.0".into(), coord);
+        st.last_report_ts.insert("a::coord:1.0".into(), now + 10_000);
+        st.last_report_ts.insert("a::gone:1.0".into(), now);
+        st.last_report_ts.insert("nonsense".into(), now);
+        st.protocol_stats.insert("b::coord:1.0".into(), ProtocolStats::default());
+        st.violations.insert("a".into(), 1);
+        st.violations.insert("ghost".into(), 3);
+        st.deleted_agents.insert("c".into(), now + 60);
+
+        let findings = scan(&st, now);
+        let kinds: Vec<_> = findings.iter().map(|f| (f.kind.as_str(), f.key.as_str())).collect();
+        assert_eq!(
+            kinds,
+            [
+                ("dangling_report_clock", "a::gone:1.0"),
+                ("future_timestamp", "a::coord:1.0"),
+                ("malformed_key", "nonsense"),
+                ("orphan_stats", "b::coord:1.0"),
+                ("orphan_violations", "ghost"),
+            ]
+        );
+
+        for repair in findings.iter().filter_map(|f| f.repair.as_ref()) {
+            repair.apply(&mut st);
+        }
+        assert!(scan(&st, now).is_empty());
+        assert_eq!(st.last_report_ts["a::coord:1.0"], now);
+        assert_eq!(st.violations.keys().collect::<Vec<_>>(), ["a"]);
+    }
+}
@@ -0,0 +1,246 @@
+//! Communication graph: who sends novel-language traffic to whom
+//!
+//! Every accepted novel-language message adds to the edge from its sender to
+//! each recipient it was delivered to, counted per protocol in hourly buckets.
+//! Edges are indexed by sender and by recipient, so
+//! `GET /graph/edges?agent=X&window=7d` answers "who did X talk to" and "who
+//! talked to X" without scanning the audit log. Buckets older than
+//! [`GRAPH_RETENTION_SEC`] are dropped.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::intern::entry_mut;
+
+/// Width of a time bucket
+pub const BUCKET_SEC: u64 = 60 * 60;
+
+/// Age after which a bucket is dropped
+pub const GRAPH_RETENTION_SEC: u64 = 30 * 24 * 60 * 60;
+
+/// Window used when a query does not give one
+pub const DEFAULT_WINDOW_SEC: u64 = 7 * 24 * 60 * 60;
+
+/// Parse a window such as `3600`, `90m`, `24h`, or `7d` into seconds
+pub fn parse_window(window: &str) -> Result<u64, String> {
+    let window = window.trim();
+    let (digits, unit) = match window.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&window[..i], c),
+        _ => (window, 's'),
+    };
+    let scale = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => 0,
+    };
+    match digits.parse::<u64>() {
+        Ok(n) if n > 0 && scale > 0 => Ok(n.saturating_mul(scale)),
+        _ => Err(format!("window must be a positive duration such as 3600, 90m, 24h, or 7d, got {window:?}")),
+    }
+}
+
+/// Which edges of an agent a query returns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Messages the agent sent
+    Out,
+    /// Messages the agent received
+    In,
+    #[default]
+    Both,
+}
+
+/// Message counts of one sender -> recipient pair: bucket start -> protocol -> count
+#[derive(Debug, Default)]
+struct Edge {
+    buckets: BTreeMap<u64, HashMap<String, u64>>,
+}
+
+/// An edge summarised over a query window
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EdgeSummary {
+    pub from: String,
+    pub to: String,
+    pub messages: u64,
+    /// Messages per protocol
+    pub protocols: BTreeMap<String, u64>,
+    /// Start of the earliest and latest hourly bucket with traffic
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+/// Sender and recipient indices over accepted novel-language messages
+#[derive(Debug, Default)]
+pub struct CommGraph {
+    /// sender -> recipient -> edge
+    outgoing: HashMap<String, HashMap<String, Edge>>,
+    /// recipient -> senders with an edge to it
+    incoming: HashMap<String, HashSet<String>>,
+    /// Newest bucket that has been pruned against
+    pruned_at: u64,
+}
+
+impl CommGraph {
+    /// Count a message from `from` to `to` on `protocol` at `now`
+    pub fn record(&mut self, from: &str, to: &str, protocol: &str, now: u64) {
+        let bucket = now - now % BUCKET_SEC;
+        if bucket > self.pruned_at {
+            self.pruned_at = bucket;
+            self.prune(now.saturating_sub(GRAPH_RETENTION_SEC));
+        }
+        let edge = entry_mut(entry_mut(&mut self.outgoing, from), to);
+        *entry_mut(edge.buckets.entry(bucket).or_default(), protocol) += 1;
+        let senders = entry_mut(&mut self.incoming, to);
+        if !senders.contains(from) {
+            senders.insert(from.to_string());
+        }
+    }
+
+    /// Drop buckets that ended before `cutoff` and edges left empty
+    fn prune(&mut self, cutoff: u64) {
+        let incoming = &mut self.incoming;
+        self.outgoing.retain(|from, edges| {
+            edges.retain(|to, edge| {
+                edge.buckets.retain(|start, _| start + BUCKET_SEC > cutoff);
+                let keep = !edge.buckets.is_empty();
+                if !keep {
+                    if let Some(senders) = incoming.get_mut(to) {
+                        senders.remove(from);
+                    }
+                }
+                keep
+            });
+            !edges.is_empty()
+        });
+        incoming.retain(|_, senders| !senders.is_empty());
+    }
+
+    /// Remove every edge to or from `agent_id`
+    pub fn forget_agent(&mut self, agent_id: &str) {
+        if let Some(edges) = self.outgoing.remove(agent_id) {
+            for to in edges.keys() {
+                if let Some(senders) = self.incoming.get_mut(to) {
+                    senders.remove(agent_id);
+                }
+            }
+        }
+        if let Some(senders) = self.incoming.remove(agent_id) {
+            for from in senders {
+                if let Some(edges) = self.outgoing.get_mut(&from) {
+                    edges.remove(agent_id);
+                }
+            }
+        }
+        self.outgoing.retain(|_, edges| !edges.is_empty());
+        self.incoming.retain(|_, senders| !senders.is_empty());
+    }
+
+    /// Edges with traffic since `since`, for one agent or the whole graph
+    pub fn edges(&self, agent: Option<&str>, direction: Direction, since: u64) -> Vec<EdgeSummary> {
+        let mut pairs: Vec<(&str, &str)> = Vec::new();
+        match agent {
+            None => {
+                for (from, edges) in &self.outgoing {
+                    pairs.extend(edges.keys().map(|to| (from.as_str(), to.as_str())));
+                }
+            }
+            Some(agent) => {
+                if direction != Direction::In {
+                    if let Some(edges) = self.outgoing.get(agent) {
+                        pairs.extend(edges.keys().map(|to| (agent, to.as_str())));
+                    }
+                }
+                if direction != Direction::Out {
+                    if let Some(senders) = self.incoming.get(agent) {
+                        // A self-send is already listed as outgoing
+                        pairs.extend(
+                            senders
+                                .iter()
+                                .filter(|from| direction == Direction::In || from.as_str() != agent)
+                                .map(|from| (from.as_str(), agent)),
+                        );
+                    }
+                }
+            }
+        }
+        let mut summaries: Vec<EdgeSummary> = pairs
+            .into_iter()
+            .filter_map(|(from, to)| self.summarise(from, to, since))
+            .collect();
+        summaries.sort_by(|a, b| b.messages.cmp(&a.messages).then_with(|| (&a.from, &a.to).cmp(&(&b.from, &b.to))));
+        summaries
+    }
+
+    fn summarise(&self, from: &str, to: &str, since: u64) -> Option<EdgeSummary> {
+        let edge = self.outgoing.get(from)?.get(to)?;
+        let first_bucket = since - since % BUCKET_SEC;
+        let mut summary = EdgeSummary {
+            from: from.to_string(),
+            to: to.to_string(),
+            messages: 0,
+            protocols: BTreeMap::new(),
+            first_seen: 0,
+            last_seen: 0,
+        };
+        for (start, counts) in edge.buckets.range(first_bucket..) {
+            if summary.messages == 0 {
+                summary.first_seen = *start;
+            }
+            summary.last_seen = *start;
+            for (protocol, n) in counts {
+                summary.messages += n;
+                *summary.protocols.entry(protocol.clone()).or_default() += n;
+            }
+        }
+        (summary.messages > 0).then_some(summary)
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edge_indices_windows_and_retention() {
+        let day = 24 * BUCKET_SEC;
+        let t0 = 1_700_000_000 - 1_700_000_000 % BUCKET_SEC;
+        let mut graph = CommGraph::default();
+        graph.record("a", "x", "coord:1.0", t0);
+        graph.record("a", "x", "coord:2.0", t0 + 10 * day);
+        graph.record("b", "x", "coord:1.0", t0 + 10 * day);
+        graph.record("x", "c", "coord:1.0", t0 + 10 * day + 5);
+
+        let now = t0 + 10 * day;
+        let into_x = graph.edges(Some("x"), Direction::In, now - 7 * day);
+        assert_eq!(into_x.len(), 2);
+        let from_a = into_x.iter().find(|e| e.from == "a").unwrap();
+        assert_eq!((from_a.messages, from_a.first_seen), (1, now));
+        assert_eq!(from_a.protocols.keys().collect::<Vec<_>>(), vec!["coord:2.0"]);
+        // A wider window reaches the older bucket
+        let wide = graph.edges(Some("x"), Direction::In, t0);
+        assert_eq!(wide.iter().find(|e| e.from == "a").unwrap().messages, 2);
+        assert_eq!(graph.edges(Some("x"), Direction::Out, t0).len(), 1);
+        assert_eq!(graph.edges(Some("x"), Direction::Both, t0).len(), 3);
+        assert_eq!(graph.edges(None, Direction::Both, t0).len(), 3);
+
+        // Buckets past retention are dropped on the next new bucket
+        graph.record("b", "x", "coord:1.0", t0 + 31 * day);
+        assert_eq!(graph.edges(Some("a"), Direction::Out, 0)[0].messages, 1);
+
+        graph.forget_agent("x");
+        assert!(graph.edges(None, Direction::Both, 0).is_empty());
+        assert!(graph.incoming.is_empty() && graph.outgoing.is_empty());
+
+        assert_eq!(parse_window("7d"), Ok(7 * day));
+        assert_eq!(parse_window("90m"), Ok(5_400));
+        assert_eq!(parse_window("3600"), Ok(3_600));
+        assert!(parse_window("0h").is_err() && parse_window("1w").is_err() && parse_window("").is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! Investigation holds on agents
+//!
+//! An agent under investigation needs its traffic kept in full, beyond the
+//! rules that normally minimize what the gateway stores. An admin places a
+//! hold with `PUT /admin/holds/{agent_id}`, giving a reason and a
+//! `duration_sec` (at most [`MAX_HOLD_SEC`]). While it is active:
+//!
+//! - `msg_accepted` events of sends from or to the agent carry the message
+//!   `content` in the audit trail
+//! - a `drop_content` storage quota no longer replaces the agent's content,
+//!   held report summaries, or audit event fields with digests, and a
+//!   `reject` quota no longer suppresses its audit events; usage is still
+//!   counted
+//! - a soft-deleted agent is not purged
+//!
+//! A hold ends when it runs out or is lifted with `DELETE
+//! /admin/holds/{agent_id}`; placing it again replaces the window. Every
+//! change is an audit event (`investigation_hold_set`,
+//! `investigation_hold_released`, `investigation_hold_expired`). Holds live in
+//! memory, like IP rules and content logging, and must be placed again after
+//! a restart.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+};
+
+use crate::clock::Clock;
+
+/// Default length of a hold
+pub const DEFAULT_HOLD_SEC: u64 = 30 * 86_400;
+
+/// Longest hold
+pub const MAX_HOLD_SEC: u64 = 180 * 86_400;
+
+/// Body of `PUT /admin/holds/{agent_id}`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HoldRequest {
+    /// Why the agent is held, e.g. a case reference; required
+    pub reason: String,
+    /// How long the hold lasts, in seconds
+    #[serde(default)]
+    pub duration_sec: Option<u64>,
+}
+
+/// An active hold
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InvestigationHold {
+    pub agent_id: String,
+    pub reason: String,
+    /// Admin who placed the hold
+    pub set_by: String,
+    pub since: u64,
+    /// When the hold runs out, Unix seconds
+    pub until: u64,
+}
+
+/// Active holds by agent
+#[derive(Debug, Default)]
+pub struct Holds {
+    clock: Arc<Clock>,
+    holds: RwLock<HashMap<String, InvestigationHold>>,
+}
+
+impl Holds {
+    pub fn new(clock: Arc<Clock>) -> Self {
+        Self {
+            clock,
+            holds: RwLock::default(),
+        }
+    }
+
+    /// Place or replace the hold on `agent_id`
+    pub fn set(&self, agent_id: &str, req: HoldRequest, admin: &str) -> Result<InvestigationHold, String> {
+        if req.reason.trim().is_empty() {
+            return Err("reason is required to place an investigation hold".to_string());
+        }
+        let duration = req.duration_sec.unwrap_or(DEFAULT_HOLD_SEC);
+        if duration == 0 || duration > MAX_HOLD_SEC {
+            return Err(format!("duration_sec must be within 1-{MAX_HOLD_SEC}"));
+        }
+        let now = self.clock.now();
+        let hold = InvestigationHold {
+            agent_id: agent_id.to_string(),
+            reason: req.reason,
+            set_by: admin.to_string(),
+            since: now,
+            until: now + duration,
+        };
+        self.holds.write().unwrap().insert(agent_id.to_string(), hold.clone());
+        Ok(hold)
+    }
+
+    /// Lift the hold on `agent_id`
+    pub fn release(&self, agent_id: &str) -> Option<InvestigationHold> {
+        self.holds.write().unwrap().remove(agent_id)
+    }
+
+    /// Remove the hold on `agent_id` if it has run out
+    pub fn expire(&self, agent_id: &str) -> Option<InvestigationHold> {
+        let now = self.clock.now();
+        let mut holds = self.holds.write().unwrap();
+        holds.get(agent_id).filter(|h| h.until <= now)?;
+        holds.remove(agent_id)
+    }
+
+    /// Whether `agent_id` is held right now
+    pub fn active(&self, agent_id: &str) -> bool {
+        let holds = self.holds.read().unwrap();
+        holds.get(agent_id).is_some_and(|h| self.clock.now() < h.until)
+    }
+
+    /// Holds still in force, by agent
+    pub fn list(&self) -> BTreeMap<String, InvestigationHold> {
+        let now = self.clock.now();
+        let holds = self.holds.read().unwrap();
+        holds
+            .iter()
+            .filter(|(_, h)| now < h.until)
+            .map(|(id, h)| (id.clone(), h.clone()))
+            .collect()
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{AgentFixture, ProtocolFixture, SendFixture, TestGateway};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn test_hold_is_time_boxed() {
+        let clock = Arc::new(Clock::manual(1_000));
+        let holds = Holds::new(clock.clone());
+        let req = |reason: &str, duration_sec| HoldRequest {
+            reason: reason.to_string(),
+            duration_sec,
+        };
+        assert!(holds.set("a", req(" ", None), "root").is_err());
+        assert!(holds.set("a", req("case-7", Some(MAX_HOLD_SEC + 1)), "root").is_err());
+
+        let hold = holds.set("a", req("case-7", Some(60)), "root").unwrap();
+        assert_eq!(hold.until, 1_060);
+        assert!(holds.active("a"));
+        assert!(holds.expire("a").is_none(), "not yet run out");
+
+        clock.advance(Duration::from_secs(60));
+        assert!(!holds.active("a"));
+        assert!(holds.list().is_empty());
+        assert_eq!(holds.expire("a").map(|h| h.reason), Some("case-7".to_string()));
+        assert!(holds.release("a").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_hold_defers_purge() {
+        let gw = TestGateway::new();
+        let protocol = ProtocolFixture::new("coord", "1.0").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(protocol.clone()).reported()).await;
+        let resp = gw
+            .admin(Method::PUT, "/admin/holds/a", Some(&json!({"reason": "case-7", "duration_sec": 3_600})))
+            .await;
+        assert_eq!(resp.status, StatusCode::OK, "{:?}", resp.body);
+        assert_eq!(resp.body["set_by"], "admin");
+        assert!(gw.state().holds.active("a"));
+
+        let resp = gw.send(&SendFixture::novel("a", "b", &protocol, "SHP|eta=7f").build()).await;
+        assert_eq!(resp.status, StatusCode::OK);
+
+        {
+            let mut st = gw.state().inner.write().unwrap();
+            st.deleted_agents.insert("a".to_string(), 0);
+            let holds = &gw.state().holds;
+            assert!(st.purge_expired(gw.now(), 0, |id| holds.active(id)).is_empty());
+        }
+
+        let resp = gw.admin(Method::DELETE, "/admin/holds/a", None::<&()>).await;
+        assert_eq!(resp.status, StatusCode::OK);
+        let resp = gw.admin(Method::DELETE, "/admin/holds/a", None::<&()>).await;
+        assert_eq!(resp.status, StatusCode::NOT_FOUND);
+        let holds = &gw.state().holds;
+        let mut st = gw.state().inner.write().unwrap();
+        assert_eq!(st.purge_expired(gw.now(), 0, |id| holds.active(id)), vec!["a".to_string()]);
+    }
+}
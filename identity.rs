@@ -0,0 +1,120 @@
+//! Extractors for the authenticated caller
+//!
+//! Handlers take the caller as a parameter instead of resolving bearer tokens
+//! themselves, so every endpoint answers a missing or wrong token the same
+//! way:
+//!
+//! - [`AuthedAdmin`]: an admin token; 403 `admin_disabled` while no admin
+//!   token is configured, 401 `invalid_admin_token` otherwise
+//! - [`AuthedAuditor`]: an admin or auditor token, for read-only admin
+//!   endpoints
+//! - [`AuthedCaller`]: any caller of a read endpoint, as resolved by
+//!   [`read_access`](crate::read_access); 401 `unauthenticated` when a token is
+//!   required
+//! - [`AuthedAgent`]: a read caller allowed to see the `agent_id` path
+//!   segment; 403 `out_of_scope` for a team token of another team
+//!
+//! Extraction runs before the body is read, so refused callers never cost a
+//! decode.
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path},
+    http::request::Parts,
+};
+use std::collections::HashMap;
+
+use crate::{admin_caller, error::GatewayError, ownership::Caller, read_access, require_audit_read, AppState};
+
+/// An admin, by name
+#[derive(Debug, Clone)]
+pub struct AuthedAdmin(pub String);
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthedAdmin {
+    type Rejection = GatewayError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        admin_caller(state, &parts.headers).map(Self)
+    }
+}
+
+/// An admin or an auditor
+#[derive(Debug, Clone)]
+pub struct AuthedAuditor;
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthedAuditor {
+    type Rejection = GatewayError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        require_audit_read(state, &parts.headers).map(|_| Self)
+    }
+}
+
+/// The caller of a read endpoint
+#[derive(Debug, Clone)]
+pub struct AuthedCaller(pub Caller);
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthedCaller {
+    type Rejection = GatewayError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        read_access(state, &parts.headers).map(Self)
+    }
+}
+
+/// A read caller scoped to the agent named by the `agent_id` path segment
+#[derive(Debug, Clone)]
+pub struct AuthedAgent {
+    pub agent_id: String,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthedAgent {
+    type Rejection = GatewayError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let caller = read_access(state, &parts.headers)?;
+        let Path(params) = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| GatewayError::Invalid(e.body_text()))?;
+        let agent_id = params
+            .get("agent_id")
+            .cloned()
+            .ok_or_else(|| GatewayError::Invalid("Route has no agent_id".to_string()))?;
+        if !caller.may_read_agent(&state.inner.read().unwrap(), &agent_id) {
+            return Err(GatewayError::OutOfScope);
+        }
+        Ok(Self { agent_id })
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{TestGateway, ADMIN_TOKEN};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_callers_refused_before_body() {
+        let gw = TestGateway::new();
+        let bogus = json!({"flags": "not a map"});
+
+        let missing = gw.call(Method::PUT, "/admin/flags", Some(&bogus), None).await;
+        assert_eq!(missing.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(missing.body["code"], "invalid_admin_token");
+        let wrong = gw.call(Method::PUT, "/admin/flags", Some(&bogus), Some("nope")).await;
+        assert_eq!(wrong.body["code"], "invalid_admin_token");
+        let admin = gw.call(Method::PUT, "/admin/flags", Some(&bogus), Some(ADMIN_TOKEN)).await;
+        assert_eq!(admin.status, StatusCode::BAD_REQUEST, "an admin gets as far as the body");
+
+        let unknown = gw.get("/agents/ghost/reputation").await;
+        assert_eq!(unknown.status, StatusCode::NOT_FOUND, "open reads resolve the agent");
+    }
+}
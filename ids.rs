@@ -0,0 +1,391 @@
+//! Validated agent ids and protocol names and versions
+//!
+//! State is keyed by composite strings: `{name}:{version}` for a protocol and
+//! `{agent_id}::{protocol_key}` for an agent's protocol. An id containing `:`
+//! or whitespace makes those keys ambiguous, so requests carry [`AgentId`],
+//! [`ProtocolName`], and [`ProtocolVersion`], which only deserialize from
+//! DNS-safe text:
+//!
+//! - ASCII letters, digits, `-`, `_`, and `.`
+//! - starting and ending with a letter or digit
+//! - at most [`MAX_AGENT_ID_LEN`] bytes for an agent id and
+//!   [`MAX_PROTOCOL_PART_LEN`] for a protocol name or version
+//!
+//! Records written before ids were checked may hold keys that fail these
+//! rules. The state store rewrites them with [`migrate`] as it reads them
+//! back (see `store`), so a legacy `ops agent` loads as `ops-agent`. Audit
+//! events keep the ids they were recorded with.
+//!
+//! Two stored ids can migrate to the same one: `ops agent` and `ops:agent`
+//! both load as `ops-agent`, as would an `ops-agent` stored alongside them.
+//! Loading those would merge two agents' state, so a store read under
+//! [`refuse_collisions`] fails instead, naming them. An id written in its
+//! migrated form after every legacy record of it is taken to be the same
+//! agent, as the gateway writes it that way once it has migrated.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    borrow::Borrow,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    ops::Deref,
+};
+use tracing::warn;
+
+/// Longest agent id, as for a DNS name
+pub const MAX_AGENT_ID_LEN: usize = 253;
+
+/// Longest protocol name or version, as for a DNS label
+pub const MAX_PROTOCOL_PART_LEN: usize = 63;
+
+fn allowed(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')
+}
+
+/// Refuse `value` unless it is a DNS-safe `what` of at most `max` bytes
+pub fn validate(what: &str, value: &str, max: usize) -> Result<(), String> {
+    let edges_ok = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+    if value.len() > max
+        || !value.chars().all(allowed)
+        || !edges_ok(value.chars().next())
+        || !edges_ok(value.chars().last())
+    {
+        return Err(format!(
+            "{what} {value:?} must be 1-{max} letters, digits, '-', '_', or '.', \
+             starting and ending with a letter or digit"
+        ));
+    }
+    Ok(())
+}
+
+/// DNS-safe form of a legacy `value`: each run of disallowed characters
+/// becomes one `-`, the ends are trimmed to a letter or digit, and the result
+/// cut to `max` bytes; `None` when nothing usable is left
+pub fn migrate(value: &str, max: usize) -> Option<String> {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if allowed(c) {
+            out.push(c);
+        } else if !out.ends_with('-') {
+            out.push('-');
+        }
+    }
+    out.truncate(max);
+    let out = out.trim_matches(|c: char| !c.is_ascii_alphanumeric());
+    (!out.is_empty()).then(|| out.to_string())
+}
+
+/// Rewrite a stored `name:version` protocol key with [`migrate`]; keys that
+/// do not split are returned unchanged
+pub fn migrate_protocol_key(key: &str) -> String {
+    let Some((name, version)) = key.rsplit_once(':') else {
+        return key.to_string();
+    };
+    match (migrate(name, MAX_PROTOCOL_PART_LEN), migrate(version, MAX_PROTOCOL_PART_LEN)) {
+        (Some(name), Some(version)) => format!("{name}:{version}"),
+        _ => key.to_string(),
+    }
+}
+
+/// Rewrite a stored `agent_id::name:version` report key with [`migrate`];
+/// keys that do not split are returned unchanged for fsck to report
+///
+/// The key is split at its last `::`, as legacy agent ids may contain `::`.
+/// A legacy protocol name containing `::` then leaves the clock under an
+/// agent that has no such protocol, which fsck reports as a dangling clock.
+pub fn migrate_report_key(key: &str) -> String {
+    let Some((agent_id, protocol)) = key.rsplit_once("::") else {
+        return key.to_string();
+    };
+    match migrate(agent_id, MAX_AGENT_ID_LEN) {
+        Some(agent_id) => format!("{agent_id}::{}", migrate_protocol_key(protocol)),
+        None => key.to_string(),
+    }
+}
+
+/// Deserialize a stored agent id, rewriting a legacy one with [`migrate`]
+pub fn legacy_agent_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let raw = String::deserialize(deserializer)?;
+    if validate("agent_id", &raw, MAX_AGENT_ID_LEN).is_ok() {
+        note_loaded("agent_id", &raw, &raw);
+        return Ok(raw);
+    }
+    let migrated = migrate(&raw, MAX_AGENT_ID_LEN)
+        .ok_or_else(|| serde::de::Error::custom(format!("agent_id {raw:?} has no usable characters")))?;
+    warn!(from = %raw, to = %migrated, event = "legacy_id_migrated", "Stored agent id rewritten");
+    note_loaded("agent_id", &raw, &migrated);
+    Ok(migrated)
+}
+
+/// Deserialize a stored `agent_id::name:version` report key, rewriting a
+/// legacy one with [`migrate_report_key`]
+pub fn legacy_report_key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    legacy_key(deserializer, migrate_report_key)
+}
+
+/// Deserialize a stored `name:version` protocol key, rewriting a legacy one
+/// with [`migrate_protocol_key`]
+pub fn legacy_protocol_key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    legacy_key(deserializer, migrate_protocol_key)
+}
+
+fn legacy_key<'de, D: Deserializer<'de>>(deserializer: D, migrate: fn(&str) -> String) -> Result<String, D::Error> {
+    let raw = String::deserialize(deserializer)?;
+    let migrated = migrate(&raw);
+    if migrated != raw {
+        warn!(from = %raw, to = %migrated, event = "legacy_id_migrated", "Stored key rewritten");
+    }
+    Ok(migrated)
+}
+
+// =============================================================================
+// Collisions
+// =============================================================================
+
+/// How the stored forms of one loaded id appeared, in read order
+#[derive(Default)]
+struct Forms {
+    /// Legacy forms migrated to the id
+    legacy: BTreeSet<String>,
+    /// Position of the last record holding a legacy form
+    last_legacy: u64,
+    /// Position of the first record holding the id as is
+    first_valid: Option<u64>,
+}
+
+impl Forms {
+    /// The stored forms of `id` that name different agents, if more than one
+    fn colliding(self, id: &str) -> Option<Vec<String>> {
+        let mut stored: Vec<String> = self.legacy.into_iter().collect();
+        if self.first_valid.is_some_and(|at| at < self.last_legacy) {
+            stored.push(id.to_string());
+        }
+        (stored.len() > 1).then_some(stored)
+    }
+}
+
+#[derive(Default)]
+struct Ledger {
+    read: u64,
+    ids: BTreeMap<(&'static str, String), Forms>,
+}
+
+thread_local! {
+    /// Ids read back by the [`refuse_collisions`] call running on this thread
+    static LEDGER: RefCell<Option<Ledger>> = const { RefCell::new(None) };
+}
+
+/// Note that a stored `what` read back as `raw` loads as `loaded`
+pub fn note_loaded(what: &'static str, raw: &str, loaded: &str) {
+    LEDGER.with_borrow_mut(|ledger| {
+        let Some(ledger) = ledger else {
+            return;
+        };
+        ledger.read += 1;
+        let read = ledger.read;
+        let forms = ledger.ids.entry((what, loaded.to_string())).or_default();
+        if raw == loaded {
+            forms.first_valid.get_or_insert(read);
+        } else {
+            forms.legacy.insert(raw.to_string());
+            forms.last_legacy = read;
+        }
+    });
+}
+
+/// Run `read`, which deserializes stored records in the order they were
+/// written, and fail it when stored ids collide once migrated
+pub fn refuse_collisions<T>(read: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    LEDGER.set(Some(Ledger::default()));
+    let result = read();
+    let ledger = LEDGER.take().unwrap_or_default();
+    let collisions: Vec<String> = ledger
+        .ids
+        .into_iter()
+        .filter_map(|((what, id), forms)| {
+            let stored = forms.colliding(&id)?;
+            Some(format!("{what} {stored:?} all load as {id:?}"))
+        })
+        .collect();
+    if !collisions.is_empty() {
+        return Err(format!(
+            "stored ids collide once made DNS-safe, rename them in the store: {}",
+            collisions.join("; ")
+        ));
+    }
+    result
+}
+
+macro_rules! validated_id {
+    ($(#[$doc:meta])* $name:ident, $what:literal, $max:expr) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+        #[serde(try_from = "String", into = "String")]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+
+            /// Rewrite a legacy value with [`migrate`]
+            pub fn migrate(raw: &str) -> Option<Self> {
+                migrate(raw, $max).map(Self)
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = String;
+
+            fn try_from(value: String) -> Result<Self, String> {
+                validate($what, &value, $max)?;
+                Ok(Self(value))
+            }
+        }
+
+        impl TryFrom<&str> for $name {
+            type Error = String;
+
+            fn try_from(value: &str) -> Result<Self, String> {
+                Self::try_from(value.to_string())
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+validated_id!(
+    /// An agent id
+    AgentId,
+    "agent_id",
+    MAX_AGENT_ID_LEN
+);
+
+validated_id!(
+    /// A protocol name
+    ProtocolName,
+    "protocol name",
+    MAX_PROTOCOL_PART_LEN
+);
+
+validated_id!(
+    /// A protocol version
+    ProtocolVersion,
+    "protocol version",
+    MAX_PROTOCOL_PART_LEN
+);
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        store::Record,
+        testing::{ProtocolFixture, TestGateway},
+    };
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_ids_are_dns_safe() {
+        for ok in ["a", "agent-001", "compressed_coord", "1.0", "1.x"] {
+            assert!(serde_json::from_value::<AgentId>(serde_json::json!(ok)).is_ok(), "{ok}");
+        }
+        for bad in ["", "a::b", "coord:1", "ops agent", "-a", "a.", "é", &"a".repeat(64)] {
+            assert!(serde_json::from_value::<ProtocolName>(serde_json::json!(bad)).is_err(), "{bad:?}");
+        }
+        let id = AgentId::try_from("agent-001").unwrap();
+        assert_eq!(serde_json::to_value(&id).unwrap(), "agent-001");
+        assert_eq!(id, "agent-001");
+
+        let gw = TestGateway::new();
+        let protocol = ProtocolFixture::new("coord", "1.0").build();
+        let mut req = serde_json::json!({"agent_id": "a::b", "protocol": protocol});
+        let resp = gw.post("/register_protocol_for_agent", &req).await;
+        assert_eq!(resp.status, StatusCode::BAD_REQUEST, "{:?}", resp.body);
+        req["agent_id"] = serde_json::json!("a");
+        req["protocol"]["version"] = serde_json::json!("1.0 beta");
+        let resp = gw.post("/register_protocol_for_agent", &req).await;
+        assert_eq!(resp.status, StatusCode::BAD_REQUEST);
+        let resp = gw.post("/send", &serde_json::json!({"from": "a", "to": ["b", "c d"], "content": "hi"})).await;
+        assert_eq!(resp.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_legacy_keys_migrate() {
+        assert_eq!(migrate("ops agent", MAX_AGENT_ID_LEN).as_deref(), Some("ops-agent"));
+        assert_eq!(migrate(" a::b ", MAX_AGENT_ID_LEN).as_deref(), Some("a-b"));
+        assert_eq!(migrate("::", MAX_AGENT_ID_LEN), None);
+        assert_eq!(ProtocolVersion::migrate("1.0 beta").unwrap(), "1.0-beta");
+        assert_eq!(migrate_report_key("ops agent::coord v2:1.0"), "ops-agent::coord-v2:1.0");
+        assert_eq!(migrate_report_key("a::coord:1.0"), "a::coord:1.0");
+        assert_eq!(migrate_report_key("ops::agent::coord:1.0"), "ops-agent::coord:1.0", "split at the last ::");
+        assert_eq!(migrate_report_key("garbage"), "garbage");
+        assert_eq!(migrate_protocol_key("coord:1.0 rc"), "coord:1.0-rc");
+    }
+
+    #[test]
+    fn test_migration_collisions_are_refused() {
+        let read = |ids: &[&str]| {
+            refuse_collisions(|| {
+                for id in ids {
+                    serde_json::from_value::<Record>(json!({"op": "agent_removed", "agent_id": id})).unwrap();
+                }
+                Ok(())
+            })
+        };
+        assert!(read(&["ops agent", "ops agent", "ops-agent"]).is_ok(), "written as migrated afterwards");
+        let error = read(&["ops agent", "ops:agent"]).unwrap_err();
+        assert!(error.contains(r#"agent_id ["ops agent", "ops:agent"] all load as "ops-agent""#), "{error}");
+        let error = read(&["ops-agent", "ops agent"]).unwrap_err();
+        assert!(error.contains(r#"["ops agent", "ops-agent"]"#), "stored as is before the legacy form: {error}");
+        assert!(read(&["a", "b c"]).is_ok());
+    }
+}
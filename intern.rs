@@ -0,0 +1,126 @@
+//! Interned agent ids and protocol keys for the send hot path
+//!
+//! The same few agent ids and protocol keys appear on every send. Interning
+//! them as shared `Arc<str>`s lets decision cache keys and cached decisions
+//! be built and cloned without copying the strings. The table holds at most
+//! [`MAX_INTERNED`] entries. When it is full, the least recently used eighth
+//! is evicted, so a flood of unknown senders, or agents long gone, cannot
+//! crowd out the ids in use.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+/// Entries kept in the intern table
+pub const MAX_INTERNED: usize = 65_536;
+
+/// Table of shared agent ids and protocol keys
+#[derive(Debug)]
+pub struct Interner {
+    /// Shared name -> tick of its last use
+    names: RwLock<HashMap<Arc<str>, AtomicU64>>,
+    tick: AtomicU64,
+    capacity: usize,
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Self::new(MAX_INTERNED)
+    }
+}
+
+impl Interner {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            names: RwLock::new(HashMap::new()),
+            tick: AtomicU64::new(0),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Shared copy of `name`, allocated on first sight only
+    pub fn intern(&self, name: &str) -> Arc<str> {
+        let tick = self.tick.fetch_add(1, Ordering::Relaxed);
+        if let Some((shared, used)) = self.names.read().unwrap().get_key_value(name) {
+            used.store(tick, Ordering::Relaxed);
+            return shared.clone();
+        }
+        let mut names = self.names.write().unwrap();
+        if let Some((shared, used)) = names.get_key_value(name) {
+            used.store(tick, Ordering::Relaxed);
+            return shared.clone();
+        }
+        if names.len() >= self.capacity {
+            self.evict(&mut names);
+        }
+        let shared: Arc<str> = Arc::from(name);
+        names.insert(shared.clone(), AtomicU64::new(tick));
+        shared
+    }
+
+    /// Drop the least recently used eighth of the table; one scan pays for
+    /// the next eighth of inserts
+    fn evict(&self, names: &mut HashMap<Arc<str>, AtomicU64>) {
+        let mut ticks: Vec<u64> = names.values().map(|used| used.load(Ordering::Relaxed)).collect();
+        let oldest = (self.capacity / 8).max(1).min(ticks.len());
+        let (_, cutoff, _) = ticks.select_nth_unstable(oldest - 1);
+        let cutoff = *cutoff;
+        names.retain(|_, used| used.load(Ordering::Relaxed) > cutoff);
+    }
+}
+
+/// Entry for `key` in a string-keyed map, copying the key only on first insert
+///
+/// `HashMap::entry` takes an owned key, which costs an allocation per call
+/// even when the entry already exists.
+pub fn entry_mut<'a, V: Default>(map: &'a mut HashMap<String, V>, key: &str) -> &'a mut V {
+    if !map.contains_key(key) {
+        map.insert(key.to_string(), V::default());
+    }
+    map.get_mut(key).expect("entry inserted above")
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern_shares_names() {
+        let interner = Interner::default();
+        let a = interner.intern("agent-a");
+        assert!(Arc::ptr_eq(&a, &interner.intern("agent-a")));
+        assert!(!Arc::ptr_eq(&a, &interner.intern("agent-b")));
+        assert_eq!(interner.names.read().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_intern_evicts_least_recently_used() {
+        let interner = Interner::new(8);
+        let kept = interner.intern("kept");
+        for i in 0..100 {
+            interner.intern(&format!("flood-{i}"));
+            // In use throughout, so never the oldest
+            assert!(Arc::ptr_eq(&kept, &interner.intern("kept")));
+        }
+        let names = interner.names.read().unwrap();
+        assert!(names.len() <= 8);
+        assert!(names.contains_key("flood-99") && !names.contains_key("flood-0"));
+    }
+
+    #[test]
+    fn test_entry_mut() {
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        *entry_mut(&mut counts, "a") += 1;
+        *entry_mut(&mut counts, "a") += 1;
+        assert_eq!(counts.get("a"), Some(&2));
+        assert_eq!(counts.len(), 1);
+    }
+}
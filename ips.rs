@@ -0,0 +1,417 @@
+//! Per-client-IP request accounting, throttling, and blocking
+//!
+//! Agent ids are claimed by the caller, so abuse from one network location
+//! can hide behind many of them. Every request is counted against the IP it
+//! came from: totals, rejections (any 4xx or 5xx answer), and the requests
+//! of the current minute. `GET /stats/ips` lists the busiest.
+//!
+//! Behind a load balancer the peer address is the proxy's. Requests from an
+//! address in `TRUSTED_PROXIES` are attributed to the right-most
+//! `X-Forwarded-For` entry that is not itself a trusted proxy; the header is
+//! ignored from anyone else, so clients cannot pick their own IP.
+//!
+//! Admins block an IP (403, `code: ip_blocked`) or throttle it to a number of
+//! requests per minute (429, `code: ip_throttled`, with `Retry-After`) via
+//! `PUT /admin/ips/{ip}`, optionally until a given time. `/health*`,
+//! `/metrics`, and `/admin/*` are counted but never refused, so an admin
+//! cannot lock themselves out.
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+};
+use tracing::{info, warn};
+
+use crate::{env_parse, error::GatewayError, AppState};
+
+/// Length of the window `Throttle` limits, in seconds
+pub const WINDOW_SEC: u64 = 60;
+
+/// Default bound on the IPs tracked at once
+const DEFAULT_MAX_TRACKED: usize = 10_000;
+
+/// Trusted proxies and tracking bounds
+#[derive(Debug, Clone, PartialEq)]
+pub struct IpConfig {
+    /// Peers whose `X-Forwarded-For` is believed
+    pub trusted_proxies: Vec<IpAddr>,
+    /// IPs tracked at once; the least recently seen is forgotten past it
+    pub max_tracked: usize,
+}
+
+impl Default for IpConfig {
+    fn default() -> Self {
+        Self {
+            trusted_proxies: Vec::new(),
+            max_tracked: DEFAULT_MAX_TRACKED,
+        }
+    }
+}
+
+impl IpConfig {
+    /// Read `TRUSTED_PROXIES` (comma-separated IPs) and `IP_TRACKING_MAX`
+    pub fn from_env() -> Self {
+        let mut trusted_proxies = Vec::new();
+        for entry in env::var("TRUSTED_PROXIES").unwrap_or_default().split(',') {
+            let entry = entry.trim();
+            if entry.is_empty() {
+                continue;
+            }
+            match entry.parse() {
+                Ok(ip) => trusted_proxies.push(ip),
+                Err(_) => warn!(event = "config_invalid", entry, "Ignoring invalid TRUSTED_PROXIES entry"),
+            }
+        }
+        let max_tracked = env_parse("IP_TRACKING_MAX")
+            .filter(|&n| n > 0)
+            .unwrap_or(DEFAULT_MAX_TRACKED);
+        Self {
+            trusted_proxies,
+            max_tracked,
+        }
+    }
+
+    fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.contains(ip)
+    }
+
+    /// The client behind `peer`, honoring `X-Forwarded-For` from trusted proxies
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(&peer) {
+            return peer;
+        }
+        let forwarded: Vec<IpAddr> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|hop| hop.trim().parse().ok())
+            .collect();
+        forwarded
+            .iter()
+            .rev()
+            .find(|ip| !self.is_trusted(ip))
+            .or(forwarded.first())
+            .copied()
+            .unwrap_or(peer)
+    }
+}
+
+/// What an admin rule does to an IP's requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum IpAction {
+    /// Refuse every request
+    Block,
+    /// Refuse requests past `per_minute` in each minute
+    Throttle { per_minute: u64 },
+}
+
+impl IpAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Block => "block",
+            Self::Throttle { .. } => "throttle",
+        }
+    }
+}
+
+/// An admin's block or throttle of one IP
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IpRule {
+    #[serde(flatten)]
+    pub action: IpAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// When the rule lapses, Unix seconds; indefinite when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+    /// When the rule was set; set by the gateway
+    #[serde(default)]
+    pub since: u64,
+    /// Admin who set the rule; set by the gateway
+    #[serde(default)]
+    pub set_by: String,
+}
+
+impl IpRule {
+    fn lapsed(&self, now: u64) -> bool {
+        self.until.is_some_and(|until| until <= now)
+    }
+}
+
+/// Counts for one IP
+#[derive(Debug, Clone, Default)]
+struct Tally {
+    requests: u64,
+    rejected: u64,
+    refused: u64,
+    first_seen: u64,
+    last_seen: u64,
+    /// Start of the current window and requests within it
+    window_start: u64,
+    window_requests: u64,
+}
+
+/// One IP in `GET /stats/ips`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IpUsage {
+    pub ip: IpAddr,
+    pub requests: u64,
+    /// Requests answered 4xx or 5xx, including those this module refused
+    pub rejected: u64,
+    pub rejection_ratio: f64,
+    /// Requests refused by a block or throttle
+    pub refused: u64,
+    /// Requests in the current minute
+    pub requests_this_minute: u64,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<IpRule>,
+}
+
+/// Refusals by rule action, for `/metrics`
+#[derive(Debug, Default)]
+pub struct IpCounters {
+    pub blocked: AtomicU64,
+    pub throttled: AtomicU64,
+}
+
+/// Per-IP request counts and admin rules
+#[derive(Debug, Default)]
+pub struct IpTracker {
+    config: IpConfig,
+    tallies: Mutex<HashMap<IpAddr, Tally>>,
+    rules: RwLock<BTreeMap<IpAddr, IpRule>>,
+    pub counters: IpCounters,
+}
+
+impl IpTracker {
+    pub fn new(config: IpConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn config(&self) -> &IpConfig {
+        &self.config
+    }
+
+    /// Count a request from `ip` and decide whether its rule refuses it
+    pub fn admit(&self, ip: IpAddr, now: u64, enforce: bool) -> Result<(), GatewayError> {
+        let rule = self.rule(ip, now).filter(|_| enforce);
+        let mut tallies = self.tallies.lock().unwrap();
+        if !tallies.contains_key(&ip) && tallies.len() >= self.config.max_tracked {
+            if let Some(stalest) = tallies.iter().min_by_key(|(_, t)| t.last_seen).map(|(ip, _)| *ip) {
+                tallies.remove(&stalest);
+            }
+        }
+        let tally = tallies.entry(ip).or_insert_with(|| Tally {
+            first_seen: now,
+            ..Tally::default()
+        });
+        let window_start = now - now % WINDOW_SEC;
+        if tally.window_start != window_start {
+            tally.window_start = window_start;
+            tally.window_requests = 0;
+        }
+        tally.requests += 1;
+        tally.window_requests += 1;
+        tally.last_seen = now;
+
+        let refusal = match rule.map(|r| r.action) {
+            Some(IpAction::Block) => {
+                self.counters.blocked.fetch_add(1, Ordering::Relaxed);
+                Some(GatewayError::IpBlocked)
+            }
+            Some(IpAction::Throttle { per_minute }) if tally.window_requests > per_minute => {
+                self.counters.throttled.fetch_add(1, Ordering::Relaxed);
+                Some(GatewayError::IpThrottled {
+                    per_minute,
+                    retry_after: window_start + WINDOW_SEC - now,
+                })
+            }
+            _ => None,
+        };
+        match refusal {
+            Some(err) => {
+                tally.refused += 1;
+                tally.rejected += 1;
+                Err(err)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Count an admitted request that was answered 4xx or 5xx
+    pub fn record_rejection(&self, ip: IpAddr) {
+        if let Some(tally) = self.tallies.lock().unwrap().get_mut(&ip) {
+            tally.rejected += 1;
+        }
+    }
+
+    /// The rule in force for `ip`, if any
+    pub fn rule(&self, ip: IpAddr, now: u64) -> Option<IpRule> {
+        self.rules.read().unwrap().get(&ip).filter(|r| !r.lapsed(now)).cloned()
+    }
+
+    /// Rules in force, lapsed ones dropped
+    pub fn rules(&self, now: u64) -> BTreeMap<IpAddr, IpRule> {
+        let mut rules = self.rules.write().unwrap();
+        rules.retain(|_, r| !r.lapsed(now));
+        rules.clone()
+    }
+
+    pub fn set_rule(&self, ip: IpAddr, rule: IpRule) {
+        self.rules.write().unwrap().insert(ip, rule);
+    }
+
+    pub fn clear_rule(&self, ip: IpAddr) -> Option<IpRule> {
+        self.rules.write().unwrap().remove(&ip)
+    }
+
+    pub fn tracked(&self) -> usize {
+        self.tallies.lock().unwrap().len()
+    }
+
+    /// Tracked IPs, most requests first
+    pub fn usage(&self, now: u64, limit: usize) -> Vec<IpUsage> {
+        let rules = self.rules(now);
+        let window_start = now - now % WINDOW_SEC;
+        let mut usage: Vec<IpUsage> = self
+            .tallies
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&ip, t)| IpUsage {
+                ip,
+                requests: t.requests,
+                rejected: t.rejected,
+                rejection_ratio: t.rejected as f64 / t.requests.max(1) as f64,
+                refused: t.refused,
+                requests_this_minute: if t.window_start == window_start { t.window_requests } else { 0 },
+                first_seen: t.first_seen,
+                last_seen: t.last_seen,
+                rule: rules.get(&ip).cloned(),
+            })
+            .collect();
+        usage.sort_by(|a, b| b.requests.cmp(&a.requests).then(a.ip.cmp(&b.ip)));
+        usage.truncate(limit);
+        usage
+    }
+}
+
+/// Routes rules never refuse
+fn exempt(path: &str) -> bool {
+    path.starts_with("/health") || path == "/metrics" || path.starts_with("/admin/")
+}
+
+/// Middleware counting each request against its client IP and enforcing rules
+///
+/// Requests without a peer address, such as in-process calls, are not counted.
+pub(crate) async fn account_requests(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(peer) = req.extensions().get::<ConnectInfo<SocketAddr>>().map(|c| c.0.ip()) else {
+        return next.run(req).await;
+    };
+    let ips = &state.ips;
+    let ip = ips.config().client_ip(peer, req.headers());
+    let path = req.uri().path().to_string();
+    if let Err(err) = ips.admit(ip, state.clock.now(), !exempt(&path)) {
+        info!(event = "ip_refused", ip = %ip, path = %path, reason = err.code(), "Request refused for client IP");
+        let retry_after = match &err {
+            GatewayError::IpThrottled { retry_after, .. } => Some(*retry_after),
+            _ => None,
+        };
+        let mut response = err.into_response();
+        if let Some(wait) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(wait.max(1)));
+        }
+        return response;
+    }
+    let response = next.run(req).await;
+    if response.status().is_client_error() || response.status().is_server_error() {
+        ips.record_rejection(ip);
+    }
+    response
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_client_ip_honors_trusted_proxies_only() {
+        let config = IpConfig {
+            trusted_proxies: vec![ip("10.0.0.1"), ip("10.0.0.2")],
+            ..IpConfig::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("6.6.6.6, 1.2.3.4, 10.0.0.2"));
+        assert_eq!(config.client_ip(ip("10.0.0.1"), &headers), ip("1.2.3.4"));
+        assert_eq!(config.client_ip(ip("5.5.5.5"), &headers), ip("5.5.5.5"));
+        assert_eq!(config.client_ip(ip("10.0.0.1"), &HeaderMap::new()), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_throttle_block_and_stats() {
+        let ips = IpTracker::new(IpConfig {
+            max_tracked: 2,
+            ..IpConfig::default()
+        });
+        let (a, b) = (ip("1.1.1.1"), ip("2.2.2.2"));
+        let rule = |action| IpRule {
+            action,
+            reason: None,
+            until: Some(200),
+            since: 0,
+            set_by: "admin".into(),
+        };
+        ips.set_rule(a, rule(IpAction::Throttle { per_minute: 2 }));
+        assert!(ips.admit(a, 60, true).is_ok());
+        assert!(ips.admit(a, 61, true).is_ok());
+        assert_eq!(
+            ips.admit(a, 62, true),
+            Err(GatewayError::IpThrottled { per_minute: 2, retry_after: 58 })
+        );
+        assert!(ips.admit(a, 62, false).is_ok(), "exempt routes are never refused");
+        assert!(ips.admit(a, 120, true).is_ok(), "a new minute resets the window");
+
+        ips.set_rule(b, rule(IpAction::Block));
+        assert_eq!(ips.admit(b, 120, true), Err(GatewayError::IpBlocked));
+        assert!(ips.admit(b, 200, true).is_ok(), "the block lapsed");
+        ips.record_rejection(b);
+
+        let usage = ips.usage(200, 10);
+        assert_eq!(usage[0].ip, a);
+        assert_eq!((usage[0].requests, usage[0].refused, usage[0].requests_this_minute), (5, 1, 0));
+        assert_eq!((usage[1].rejected, usage[1].rejection_ratio), (2, 1.0));
+
+        ips.admit(ip("3.3.3.3"), 300, true).unwrap();
+        assert_eq!(ips.tracked(), 2);
+        assert!(ips.usage(300, 10).iter().all(|u| u.ip != a), "the stalest IP is forgotten");
+    }
+}
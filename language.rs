@@ -0,0 +1,348 @@
+//! Language of record per tenant
+//!
+//! English is the default language of record: messages written in it pass
+//! sender-side checks freely, and reports summarise novel-language traffic in
+//! it. A fleet governed in another language sets `LANGUAGE_OF_RECORD`:
+//!
+//! ```json
+//! {"default": {"languages": ["en"]},
+//!  "tenants": {"tokyo": {"languages": ["ja"],
+//!                        "messages": {"report_overdue": "報告が期限切れです。{error}"}}}}
+//! ```
+//!
+//! A tenant is an agent's owning team; agents of unlisted teams follow
+//! `default`. Each entry sets:
+//!
+//! - `languages`: codes (`en`, `ja`, `zh`, `ko`, `de`, ...) accepted as the
+//!   language of record. A send in one of them is treated like English is by
+//!   default, and report summaries must be written in one of them.
+//! - `detectors`: how text is judged. `gateway` is the gateway's own detector
+//!   (classifier, ensemble, or heuristic), which judges English only;
+//!   `script` is a local check of the writing system and of machine syntax.
+//!   Text is in the language of record when any detector accepts it. Defaults
+//!   to `gateway` when the only language is English, `script` otherwise.
+//! - `messages`: the tenant's own error messages, keyed by message key or
+//!   error `code` (see [`messages`](crate::messages)), with `{error}`
+//!   standing for the catalog's message. Catalog messages name the tenant's
+//!   languages where they would say English.
+//!
+//! Without `LANGUAGE_OF_RECORD`, or for agents its entries do not cover,
+//! messages are judged by the gateway detector and report summaries are only
+//! checked for length.
+
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+};
+use tracing::warn;
+
+use crate::{
+    detector::{Detector, Verdict, VerdictSource},
+    looks_like_english,
+    messages,
+};
+
+/// Writing systems the `script` detector recognises
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    /// Kana and kanji
+    Japanese,
+    Han,
+    Hangul,
+    Cyrillic,
+    Greek,
+    Arabic,
+    Hebrew,
+    Devanagari,
+}
+
+impl Script {
+    fn contains(self, c: char) -> bool {
+        let c = c as u32;
+        match self {
+            Self::Latin => c <= 0x024F,
+            Self::Japanese => matches!(c, 0x3040..=0x30FF | 0x31F0..=0x31FF | 0xFF66..=0xFF9F) || is_han(c),
+            Self::Han => is_han(c),
+            Self::Hangul => matches!(c, 0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF),
+            Self::Cyrillic => matches!(c, 0x0400..=0x052F),
+            Self::Greek => matches!(c, 0x0370..=0x03FF | 0x1F00..=0x1FFF),
+            Self::Arabic => matches!(c, 0x0600..=0x06FF | 0x0750..=0x077F),
+            Self::Hebrew => matches!(c, 0x0590..=0x05FF),
+            Self::Devanagari => matches!(c, 0x0900..=0x097F),
+        }
+    }
+}
+
+/// CJK unified and compatibility ideographs
+fn is_han(c: u32) -> bool {
+    matches!(c, 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF)
+}
+
+/// Supported languages of record: code, name, and script
+const LANGUAGES: &[(&str, &str, Script)] = &[
+    ("en", "English", Script::Latin),
+    ("de", "German", Script::Latin),
+    ("es", "Spanish", Script::Latin),
+    ("fr", "French", Script::Latin),
+    ("it", "Italian", Script::Latin),
+    ("nl", "Dutch", Script::Latin),
+    ("pt", "Portuguese", Script::Latin),
+    ("ja", "Japanese", Script::Japanese),
+    ("zh", "Chinese", Script::Han),
+    ("ko", "Korean", Script::Hangul),
+    ("ru", "Russian", Script::Cyrillic),
+    ("uk", "Ukrainian", Script::Cyrillic),
+    ("el", "Greek", Script::Greek),
+    ("ar", "Arabic", Script::Arabic),
+    ("he", "Hebrew", Script::Hebrew),
+    ("hi", "Hindi", Script::Devanagari),
+];
+
+/// Share of letters that must belong to the language's script
+const MIN_SCRIPT_SHARE: f64 = 0.6;
+
+/// Punctuation that does not count as machine syntax
+fn is_prose_punctuation(c: char) -> bool {
+    matches!(c, '.' | ',' | '!' | '?' | '\'' | '"' | '-' | ':' | '(' | ')')
+        || matches!(c, '«' | '»' | '¡' | '¿' | '„' | '“' | '”' | '‘' | '’' | '…' | '·' | '・' | 'ー' | '〜')
+        // CJK punctuation and fullwidth forms
+        || matches!(c as u32, 0x3000..=0x303F | 0xFF01..=0xFF0F | 0xFF1A..=0xFF1F | 0xFF5B..=0xFF65)
+}
+
+/// A language of record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Language {
+    pub code: &'static str,
+    pub name: &'static str,
+    script: Script,
+}
+
+impl Language {
+    pub const ENGLISH: Self = Self {
+        code: "en",
+        name: "English",
+        script: Script::Latin,
+    };
+
+    pub fn parse(code: &str) -> Option<Self> {
+        let code = code.trim().to_ascii_lowercase();
+        LANGUAGES
+            .iter()
+            .find(|(c, _, _)| *c == code)
+            .map(|&(code, name, script)| Self { code, name, script })
+    }
+
+    /// Local check that `text` is written in this language: English uses
+    /// the `looks_like_english` heuristic, other languages their script
+    pub fn matches(&self, text: &str) -> bool {
+        if *self == Self::ENGLISH {
+            return looks_like_english(text);
+        }
+        let s = text.trim();
+        if s.is_empty() {
+            return true;
+        }
+        let chars = s.chars().count();
+        let symbols = s
+            .chars()
+            .filter(|c| !c.is_alphanumeric() && !c.is_whitespace() && !is_prose_punctuation(*c))
+            .count();
+        if symbols * 10 > chars {
+            return false;
+        }
+        let letters: Vec<char> = s.chars().filter(|c| c.is_alphabetic()).collect();
+        if letters.is_empty() {
+            return true;
+        }
+        let native = letters.iter().filter(|c| self.script.contains(**c)).count();
+        if (native as f64) < MIN_SCRIPT_SHARE * letters.len() as f64 {
+            return false;
+        }
+        if self.script != Script::Latin {
+            return true;
+        }
+
+        // Latin-script languages get the English word and vowel checks
+        let words = s.split(|c: char| !c.is_alphabetic()).filter(|w| w.chars().count() >= 2).count();
+        if chars > 40 && words < 3 {
+            return false;
+        }
+        let vowels = letters
+            .iter()
+            .flat_map(|c| c.to_lowercase())
+            .filter(|c| "aeiouyàáâãäåæèéêëìíîïòóôõöøùúûüýÿœ".contains(*c))
+            .count();
+        !(chars > 30 && (vowels as f64) < 0.20 * letters.len() as f64)
+    }
+}
+
+/// How text is judged to be in the language of record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LanguageDetector {
+    /// The gateway's configured detector; English only
+    Gateway,
+    /// Local writing-system check
+    Script,
+}
+
+#[derive(Deserialize)]
+struct RawRecord {
+    languages: Vec<String>,
+    #[serde(default)]
+    detectors: Vec<LanguageDetector>,
+    #[serde(default)]
+    messages: BTreeMap<String, String>,
+}
+
+/// One tenant's language of record
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "RawRecord")]
+pub struct LanguageOfRecord {
+    pub languages: Vec<Language>,
+    pub detectors: Vec<LanguageDetector>,
+    /// Error message templates by error code
+    pub messages: BTreeMap<String, String>,
+}
+
+impl TryFrom<RawRecord> for LanguageOfRecord {
+    type Error = String;
+
+    fn try_from(raw: RawRecord) -> Result<Self, String> {
+        let languages = raw
+            .languages
+            .iter()
+            .map(|code| Language::parse(code).ok_or_else(|| format!("unknown language {code:?}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        if languages.is_empty() {
+            return Err("languages must not be empty".to_string());
+        }
+        let english = languages.contains(&Language::ENGLISH);
+        let detectors = match raw.detectors {
+            d if !d.is_empty() => d,
+            _ if english && languages.len() == 1 => vec![LanguageDetector::Gateway],
+            _ => vec![LanguageDetector::Script],
+        };
+        if !english && detectors == [LanguageDetector::Gateway] {
+            return Err("the gateway detector judges English only".to_string());
+        }
+        if let Some(key) = raw.messages.keys().find(|k| !messages::is_known(k)) {
+            return Err(format!("unknown message key {key:?}"));
+        }
+        Ok(Self {
+            languages,
+            detectors,
+            messages: raw.messages,
+        })
+    }
+}
+
+impl LanguageOfRecord {
+    /// Language names joined for messages, e.g. "Japanese or English"
+    pub fn names(&self) -> String {
+        let names: Vec<&str> = self.languages.iter().map(|l| l.name).collect();
+        names.join(" or ")
+    }
+
+    /// Judge whether `content` is in the language of record
+    ///
+    /// `is_english` of the verdict means "in the language of record". A
+    /// detector failing closed only decides when no other detector accepts.
+    pub async fn classify(&self, detector: &Detector, content: &str) -> Verdict {
+        let mut refused = None;
+        let mut unavailable = None;
+        for kind in &self.detectors {
+            let verdict = match kind {
+                LanguageDetector::Gateway if !self.languages.contains(&Language::ENGLISH) => continue,
+                LanguageDetector::Gateway => detector.classify(content).await,
+                LanguageDetector::Script => {
+                    let matched = self.languages.iter().any(|l| l.matches(content));
+                    Verdict::new(Some(matched), VerdictSource::Script)
+                }
+            };
+            match verdict.is_english {
+                Some(true) => return verdict,
+                Some(false) => _ = refused.get_or_insert(verdict),
+                None => _ = unavailable.get_or_insert(verdict),
+            }
+        }
+        unavailable
+            .or(refused)
+            .unwrap_or_else(|| Verdict::new(Some(false), VerdictSource::Script))
+    }
+}
+
+/// `LANGUAGE_OF_RECORD`: a default and per-tenant entries
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct LanguageConfig {
+    #[serde(default)]
+    pub default: Option<LanguageOfRecord>,
+    #[serde(default)]
+    pub tenants: HashMap<String, LanguageOfRecord>,
+}
+
+impl LanguageConfig {
+    pub fn from_env() -> Self {
+        match env::var("LANGUAGE_OF_RECORD") {
+            Ok(raw) if !raw.trim().is_empty() => serde_json::from_str(&raw).unwrap_or_else(|e| {
+                warn!(event = "config_invalid", error = %e, "Invalid LANGUAGE_OF_RECORD, using English");
+                Self::default()
+            }),
+            _ => Self::default(),
+        }
+    }
+
+    /// Whether any agent has a language of record
+    pub fn is_configured(&self) -> bool {
+        self.default.is_some() || !self.tenants.is_empty()
+    }
+
+    /// The language of record of an agent in team `tenant`, `None` when not
+    /// configured for it
+    pub fn record(&self, tenant: Option<&str>) -> Option<&LanguageOfRecord> {
+        tenant
+            .and_then(|t| self.tenants.get(t))
+            .or(self.default.as_ref())
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn judged(record: &LanguageOfRecord, text: &str) -> Option<bool> {
+        record.classify(&Detector::default(), text).await.is_english
+    }
+
+    #[tokio::test]
+    async fn test_language_of_record() {
+        let config: LanguageConfig = serde_json::from_str(
+            r#"{"tenants": {"tokyo": {"languages": ["ja"],
+                "messages": {"report_overdue": "報告が期限切れです。{error}"}},
+                "berlin": {"languages": ["de", "en"], "detectors": ["script"]}}}"#,
+        )
+        .unwrap();
+        assert!(config.record(Some("red")).is_none(), "unlisted teams without a default keep English");
+        let tokyo = config.record(Some("tokyo")).unwrap();
+        assert_eq!(tokyo.detectors, [LanguageDetector::Script]);
+
+        assert_eq!(judged(tokyo, "出荷状況を更新しました。エージェントBに送信済みです。").await, Some(true));
+        assert_eq!(judged(tokyo, "The shipment status was updated and sent to agent b.").await, Some(false));
+        assert_eq!(judged(tokyo, "SHP|eta=7f;q=0x3e;z=9").await, Some(false), "machine syntax");
+        let berlin = config.record(Some("berlin")).unwrap();
+        assert_eq!(judged(berlin, "Die Sendung wurde aktualisiert und an Agent B übermittelt.").await, Some(true));
+        assert_eq!(judged(berlin, "xkcd|qrst=0x7f;zzgv=9;prt=0x3e;wvx=12").await, Some(false));
+
+        let invalid = r#"{"default": {"languages": ["ja"], "detectors": ["gateway"]}}"#;
+        assert!(serde_json::from_str::<LanguageConfig>(invalid).is_err());
+        assert!(serde_json::from_str::<LanguageConfig>(r#"{"default": {"languages": ["xx"]}}"#).is_err());
+        let typo = r#"{"default": {"languages": ["en"], "messages": {"report_overdo": "x"}}}"#;
+        assert!(serde_json::from_str::<LanguageConfig>(typo).is_err());
+    }
+}
@@ -0,0 +1,300 @@
+//! Per-stage latency of the send pipeline
+//!
+//! Agents gate real-time loops on `/send`, so a refusal is only useful if it
+//! comes back quickly. Each send is timed through the stages of the pipeline:
+//!
+//! - **deserialize**: reading and decoding the request body
+//! - **detection**: encoding checks, the allowlist, and language detection
+//! - **policy**: decision cache, registration, version, report-cadence, quota,
+//!   and per-recipient checks
+//! - **webhook**: the decision webhook call, if a rule matched
+//! - **storage**: recording usage, traffic, and thread entries
+//! - **audit**: emitting the per-recipient outcome events
+//!
+//! `total` is the end-to-end time of every send and `rejected` that of refused
+//! sends only. Stages a send never reached are not recorded for it. The
+//! timings are recorded on the request's `send_pipeline` span as `{stage}_us`
+//! fields, in the `send_stage_duration_seconds` histogram, and in a window of
+//! recent samples from which `/stats/latency` computes percentiles.
+
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{field::Empty, Span};
+
+use crate::metrics::Histogram;
+
+/// Recent samples kept per stage for percentiles
+pub const LATENCY_WINDOW: usize = 4_096;
+
+/// A timed part of the send pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Deserialize,
+    Detection,
+    Policy,
+    Webhook,
+    Storage,
+    Audit,
+    /// End to end, every send
+    Total,
+    /// End to end, refused sends only
+    Rejected,
+}
+
+impl Stage {
+    pub const ALL: [Self; 8] = [
+        Self::Deserialize,
+        Self::Detection,
+        Self::Policy,
+        Self::Webhook,
+        Self::Storage,
+        Self::Audit,
+        Self::Total,
+        Self::Rejected,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Deserialize => "deserialize",
+            Self::Detection => "detection",
+            Self::Policy => "policy",
+            Self::Webhook => "webhook",
+            Self::Storage => "storage",
+            Self::Audit => "audit",
+            Self::Total => "total",
+            Self::Rejected => "rejected",
+        }
+    }
+
+    /// Field of the `send_pipeline` span holding this stage's microseconds
+    fn span_field(self) -> Option<&'static str> {
+        match self {
+            Self::Deserialize => Some("deserialize_us"),
+            Self::Detection => Some("detection_us"),
+            Self::Policy => Some("policy_us"),
+            Self::Webhook => Some("webhook_us"),
+            Self::Storage => Some("storage_us"),
+            Self::Audit => Some("audit_us"),
+            Self::Total => Some("total_us"),
+            Self::Rejected => None,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Span timing one send; stage fields are filled in when it completes
+pub fn pipeline_span() -> Span {
+    tracing::info_span!(
+        "send_pipeline",
+        deserialize_us = Empty,
+        detection_us = Empty,
+        policy_us = Empty,
+        webhook_us = Empty,
+        storage_us = Empty,
+        audit_us = Empty,
+        total_us = Empty,
+        rejected = Empty,
+    )
+}
+
+// =============================================================================
+// Per-send timing
+// =============================================================================
+
+/// A point in a send's pipeline, for timing the work after it
+#[derive(Debug, Clone, Copy)]
+pub struct Mark {
+    at: Instant,
+    attributed: Duration,
+}
+
+/// Stage timings of one send, accumulated as it moves through the pipeline
+#[derive(Debug)]
+pub struct PipelineTiming {
+    started: Instant,
+    stages: [Option<Duration>; Stage::ALL.len()],
+    /// Time attributed to any stage so far
+    attributed: Duration,
+}
+
+impl PipelineTiming {
+    /// Start timing a send whose body took `deserialize` to decode
+    pub fn start(deserialize: Duration) -> Self {
+        let mut timing = Self {
+            started: Instant::now(),
+            stages: Default::default(),
+            attributed: Duration::ZERO,
+        };
+        timing.add(Stage::Deserialize, deserialize);
+        timing
+    }
+
+    pub fn mark(&self) -> Mark {
+        Mark {
+            at: Instant::now(),
+            attributed: self.attributed,
+        }
+    }
+
+    pub fn add(&mut self, stage: Stage, elapsed: Duration) {
+        let total = self.stages[stage.index()].get_or_insert(Duration::ZERO);
+        *total += elapsed;
+        self.attributed += elapsed;
+    }
+
+    /// Attribute the time since `mark` to `stage`, less any time already
+    /// attributed to stages nested inside it
+    pub fn add_since(&mut self, stage: Stage, mark: Mark) {
+        let nested = self.attributed - mark.attributed;
+        self.add(stage, mark.at.elapsed().saturating_sub(nested));
+    }
+
+    /// Run `f` as part of `stage`
+    pub fn time<T>(&mut self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let mark = self.mark();
+        let out = f();
+        self.add_since(stage, mark);
+        out
+    }
+
+    fn total(&self) -> Duration {
+        self.stages[Stage::Deserialize.index()].unwrap_or_default() + self.started.elapsed()
+    }
+}
+
+// =============================================================================
+// Tracker
+// =============================================================================
+
+#[derive(Debug, Default)]
+struct StageStats {
+    histogram: Histogram,
+    /// Recent samples in microseconds, oldest first
+    recent: Mutex<VecDeque<u64>>,
+}
+
+impl StageStats {
+    fn observe(&self, elapsed: Duration) {
+        self.histogram.observe(elapsed.as_secs_f64());
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == LATENCY_WINDOW {
+            recent.pop_front();
+        }
+        recent.push_back(elapsed.as_micros() as u64);
+    }
+}
+
+/// Percentiles of one stage over the recent window, in milliseconds
+#[derive(Debug, Clone, Serialize)]
+pub struct StageLatency {
+    pub stage: &'static str,
+    /// Sends timed since startup
+    pub count: u64,
+    /// Samples the percentiles are computed from
+    pub window: usize,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Latency of every send pipeline stage
+#[derive(Debug, Default)]
+pub struct LatencyTracker {
+    stages: [StageStats; Stage::ALL.len()],
+}
+
+impl LatencyTracker {
+    /// Record a completed send on the tracker and on its pipeline `span`
+    pub fn finish(&self, timing: PipelineTiming, rejected: bool, span: &Span) {
+        let total = timing.total();
+        let mut stages = timing.stages;
+        stages[Stage::Total.index()] = Some(total);
+        if rejected {
+            stages[Stage::Rejected.index()] = Some(total);
+        }
+        for stage in Stage::ALL {
+            let Some(elapsed) = stages[stage.index()] else {
+                continue;
+            };
+            self.stages[stage.index()].observe(elapsed);
+            if let Some(field) = stage.span_field() {
+                span.record(field, elapsed.as_micros() as u64);
+            }
+        }
+        span.record("rejected", rejected);
+    }
+
+    pub fn histogram(&self, stage: Stage) -> &Histogram {
+        &self.stages[stage.index()].histogram
+    }
+
+    /// Per-stage percentiles over the recent window
+    pub fn summary(&self) -> Vec<StageLatency> {
+        Stage::ALL
+            .into_iter()
+            .map(|stage| {
+                let stats = &self.stages[stage.index()];
+                let mut samples: Vec<u64> = stats.recent.lock().unwrap().iter().copied().collect();
+                samples.sort_unstable();
+                let pct = |p: f64| {
+                    let rank = ((p * samples.len() as f64).ceil() as usize).clamp(1, samples.len().max(1));
+                    samples.get(rank - 1).map_or(0.0, |us| *us as f64 / 1_000.0)
+                };
+                StageLatency {
+                    stage: stage.as_str(),
+                    count: stats.histogram.count(),
+                    window: samples.len(),
+                    p50_ms: pct(0.5),
+                    p90_ms: pct(0.9),
+                    p99_ms: pct(0.99),
+                    max_ms: samples.last().map_or(0.0, |us| *us as f64 / 1_000.0),
+                }
+            })
+            .collect()
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_stages_and_percentiles() {
+        let mut timing = PipelineTiming::start(Duration::from_millis(2));
+        // Detection nested inside policy is not counted twice
+        let mark = timing.mark();
+        timing.add(Stage::Detection, Duration::from_millis(5));
+        timing.add_since(Stage::Policy, mark);
+        assert_eq!(timing.stages[Stage::Detection.index()], Some(Duration::from_millis(5)));
+        assert!(timing.stages[Stage::Policy.index()].unwrap() < Duration::from_millis(5));
+        assert_eq!(timing.time(Stage::Storage, || 7), 7);
+        assert!(timing.stages[Stage::Webhook.index()].is_none());
+
+        let tracker = LatencyTracker::default();
+        tracker.finish(timing, true, &Span::none());
+        for ms in 1..=99 {
+            let mut timing = PipelineTiming::start(Duration::from_millis(ms));
+            timing.add(Stage::Webhook, Duration::from_millis(ms));
+            tracker.finish(timing, false, &Span::none());
+        }
+        let summary = tracker.summary();
+        let webhook = summary.iter().find(|s| s.stage == "webhook").unwrap();
+        assert_eq!((webhook.count, webhook.p50_ms, webhook.p99_ms), (99, 50.0, 99.0));
+        let rejected = summary.iter().find(|s| s.stage == "rejected").unwrap();
+        assert_eq!(rejected.count, 1);
+        assert!(rejected.p99_ms >= 2.0);
+        assert_eq!(tracker.histogram(Stage::Total).count(), 100);
+    }
+}
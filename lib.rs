@@ -0,0 +1,7224 @@
+//! Policy Gateway for Agent Governance
+//!
+//! This service enforces novel-language governance rules:
+//! - Protocol registration required before novel-language use
+//! - Periodic English reports required for continued use
+//! - All messages logged for audit trail
+//!
+//! # Endpoints
+//! - `POST /register_protocol_for_agent` - Register a protocol
+//! - `POST /report` - Submit an English translation report
+//! - `POST /report_batch` - Submit many reports at once, with per-report results
+//! - `POST /send` - Send a message (gated by compliance)
+//! - `GET /send/stream` - WebSocket stream of sends and their decisions
+//! - `POST /send/reserve`, `POST /send/commit`, `POST /send/abort` - Two-phase send
+//! - `POST /attachments/{agent_id}` - Upload an attachment for a later send
+//! - `GET /parked/{id}` - Outcome of a message parked on an overdue report
+//! - `POST /delivered/{message_id}` - Confirm a message reached its recipient
+//! - `GET /threads/{id}` - A conversation with its reports and audit events
+//! - `GET /graph/edges` - Who sent novel-language traffic to whom
+//! - `GET /protocols/{agent}/{name}/{version}/stats` - Protocol usage analytics
+//! - `GET /protocols/{agent}/{name}/{version}/docs` - Documentation attached at registration
+//! - `POST /protocols/{owner}/{name}/{version}/adopt` - Register another agent's descriptor for an agent
+//! - `GET /protocols/conflicts` - Protocol names defined differently by several agents (requires `ADMIN_TOKEN`)
+//! - `POST /protocols/{agent}/{name}/{version}/reinstate` - Lift a protocol suspension (requires `ADMIN_TOKEN`)
+//! - `POST /protocols/{agent}/{name}/{version}/risk/reset` - Clear a raised risk tier (requires `ADMIN_TOKEN`)
+//! - `POST /protocols/{agent}/{name}/{version}/recertify` - Recertify a protocol (requires `ADMIN_TOKEN`)
+//! - `POST /protocols/{agent}/{name}/{version}/scope` - Add recipients to a protocol's allowlist (requires `ADMIN_TOKEN`)
+//! - `GET /reviews` - Reports held for review (requires `ADMIN_TOKEN`)
+//! - `POST /reviews/{id}/approve|reject` - Resolve a held report (requires `ADMIN_TOKEN`)
+//! - `GET /reviews/findings` - Possible undeclared protocols in English traffic
+//! - `POST /reviews/findings/{id}/confirm|dismiss` - Resolve a finding (requires `ADMIN_TOKEN`)
+//! - `GET /agents` - Agent directory (scoped to the caller's team)
+//! - `GET /agents/{id}/reputation` - Reputation score and the policy terms it earns
+//! - `GET /agents/{id}/summary-quality` - Report summary quality scores and their trend
+//! - `PUT /agents/{id}/owner`, `PUT /teams/{team}` - Maintain ownership (requires `ADMIN_TOKEN`)
+//! - `GET /teams/{team}/stats`, `GET /orgs/{org}/stats` - Compliance rollups
+//! - `GET /stats/latency` - Per-stage send latency percentiles
+//! - `GET /stats/ips` - Request and rejection counts per client IP
+//! - `GET /billing/usage` - Metered usage per tenant and agent for chargeback, as JSON or CSV
+//! - `DELETE /agents/{id}` - Soft-delete an agent (requires `ADMIN_TOKEN`)
+//! - `POST /agents/{id}/restore` - Restore a soft-deleted agent (requires `ADMIN_TOKEN`)
+//! - `POST /agents/{id}/dormancy/keep` - Keep an agent flagged as dormant (owning team or `ADMIN_TOKEN`)
+//! - `POST /agents/{id}/dormancy/confirm` - Archive and offboard an agent flagged as dormant (owning team or `ADMIN_TOKEN`)
+//! - `GET /policies/{version}` - Policy thresholds for a version (`current` for the one in force)
+//! - `PUT /admin/policy` - Put new policy thresholds in force (admin)
+//! - `GET|PUT|DELETE /admin/policy/experiment` - Evaluate a candidate policy alongside the active one and compare decisions (admin)
+//! - `GET|PUT /admin/chaos` - Fault-injection rules (requires `ADMIN_TOKEN` and `CHAOS_ENABLED`)
+//! - `GET|PUT /admin/maintenance` - Pause route groups for maintenance (requires `ADMIN_TOKEN`)
+//! - `GET|PUT /admin/flags` - Feature flags and rollout targeting (requires `ADMIN_TOKEN`)
+//! - `GET|PUT /admin/logging` - Verbose content logging for incident response (requires `ADMIN_TOKEN`)
+//! - `GET /admin/ips`, `PUT|DELETE /admin/ips/{ip}` - Block or throttle client IPs (requires `ADMIN_TOKEN`)
+//! - `GET /admin/holds`, `PUT|DELETE /admin/holds/{agent_id}` - Investigation holds retaining an agent's content in full (requires `ADMIN_TOKEN`)
+//! - `GET /admin/registry-sync` - Registry sync peers, lag, and divergence (requires `ADMIN_TOKEN`)
+//! - `GET /registry/snapshot` - Signed protocol registry snapshot for peer gateways (requires `REGISTRY_SYNC_TOKEN`)
+//! - `POST /federation/suspensions` - Signed suspension or reinstatement notice from a peer gateway (requires `REGISTRY_SYNC_TOKEN`)
+//! - `POST /forward` - Re-evaluate receiver-side policy for a message forwarded by a peer gateway (requires `FORWARD_TOKEN`)
+//! - `GET /audit/schema` - JSON Schemas of the registered audit event kinds (`?event=` for one)
+//! - `GET /audit/export` - Stream the audit trail as NDJSON (requires `ADMIN_TOKEN`)
+//! - `POST /audit/search` - Search the audit trail with a query (requires `ADMIN_TOKEN`)
+//! - `GET|POST /audit/annotations`, `DELETE /audit/annotations/{id}` - Label sets of audit events (requires `ADMIN_TOKEN`)
+//! - `POST /admin/backfill` - Import historical messages and reports into the audit trail (requires `ADMIN_TOKEN`)
+//! - `POST /admin/alerts/test` - Send a test alert (requires `ADMIN_TOKEN`)
+//! - `GET /admin/outbox` - Parked-send callbacks awaiting delivery, with failed ones listed (requires `ADMIN_TOKEN`)
+//! - `GET /admin/dormant` - Agents flagged as dormant, awaiting offboarding (requires `ADMIN_TOKEN`)
+//! - `GET /admin/approvals` - Admin actions awaiting a second admin (requires `ADMIN_TOKEN`)
+//! - `POST /admin/fsck` - Check the state store for inconsistencies and repair them (requires `ADMIN_TOKEN`)
+//! - `POST /admin/approvals/{id}/approve|reject` - Resolve a proposed admin action (requires `ADMIN_TOKEN`)
+//! - `GET /debug/runtime` - Tokio runtime metrics (requires `ADMIN_TOKEN` and the `runtime-diagnostics` feature)
+//! - `GET /ui` - Operator dashboard (requires the `dashboard` feature)
+//! - `GET /health` - Health check
+//! - `GET /health/ready` - Readiness check (language detector availability)
+//! - `GET /status` - Policy version and enforcement mode per schedule
+//! - `GET /metrics` - Prometheus metrics
+//!
+//! Request and response bodies may be JSON, CBOR (`application/cbor`), or
+//! MessagePack (`application/msgpack`); see [`codec`].
+
+mod alerts;
+mod allowlist;
+mod annotations;
+mod approvals;
+mod attachments;
+mod audit;
+mod audit_schema;
+mod audit_search;
+mod authz;
+mod backfill;
+mod batch;
+#[cfg(any(test, feature = "test-harness"))]
+pub mod bench;
+mod cache;
+mod chaos;
+mod clock;
+mod codec;
+mod compliance_headers;
+#[cfg(any(test, feature = "test-harness"))]
+pub mod conformance;
+mod consistency;
+mod delivery;
+mod demo;
+mod degradation;
+#[cfg(feature = "dashboard")]
+mod dashboard;
+mod detector;
+mod dormancy;
+#[cfg(feature = "runtime-diagnostics")]
+mod diagnostics;
+mod discovery;
+mod docs;
+mod encryption;
+mod enforcement;
+mod ensemble;
+mod exemplars;
+mod experiment;
+mod federation;
+mod flags;
+mod forwarding;
+mod fsck;
+mod graph;
+mod holds;
+mod identity;
+mod ids;
+mod intern;
+mod ips;
+mod language;
+mod latency;
+pub mod error;
+mod maintenance;
+mod messages;
+mod metering;
+mod metrics;
+mod mirror;
+mod namespace;
+mod outbox;
+mod ownership;
+mod parking;
+mod policy;
+mod quarantine;
+mod quota;
+mod recert;
+mod registry_sync;
+mod replication;
+mod revision;
+mod readability;
+mod redaction;
+mod reputation;
+mod risk;
+mod reservations;
+mod retry;
+mod routing;
+mod sampling;
+mod sanctions;
+#[cfg(test)]
+mod scenario;
+mod schema;
+mod scrub;
+mod security;
+mod signing;
+mod slo;
+mod soft_limits;
+mod store;
+mod stream;
+mod structure;
+#[cfg(any(test, feature = "test-harness"))]
+pub mod testing;
+mod threads;
+mod timeouts;
+mod timers;
+mod tls;
+mod translation;
+mod trial;
+mod undeclared;
+mod versioning;
+mod webhooks;
+
+use alerts::{Alert, AlertKind, Alerter, Dispatch};
+use allowlist::{ContentAllowlist, PatternStats};
+use annotations::{AnnotateRequest, Annotation, Annotations};
+use approvals::{AdminAction, ApprovalQueue, Approvals, Proposal, Refusal};
+use attachments::{AttachmentConfig, Attachments};
+use audit_search::SearchConfig;
+use audit::{AuditEvent, AuditLayer, AuditLog};
+use authz::{AuthzPolicy, Routes};
+use backfill::{BackfillRequest, BackfillSummary};
+use batch::BatchConfig;
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use cache::{DecisionCache, MissCache, SendKind, SenderDecision};
+use chaos::{ChaosConfig, FaultInjector};
+use clock::{Clock, SkewConfig, SkewMonitor};
+use codec::{negotiate_response, with_content_encoding, BodyError, OptionalPayload, Payload, Timed};
+use consistency::{Consistency, ReportClaim, TrafficSample};
+use degradation::DegradationPolicy;
+use detector::{BreakerState, Detector, DetectorConfig, DetectorFallback, Verdict, VerdictSource};
+use dormancy::{Dormancy, DormancyConfig};
+use discovery::{DiscoveredAgent, Discovery, DiscoveryConfig};
+use docs::{DocArtifact, DocDigest};
+use encryption::{EncryptedContentPolicy, EncryptionMetadata, OpaqueContent};
+use enforcement::{EnforcementMode, ModeStatus, ModeTracker};
+use ensemble::Voter;
+use exemplars::ExemplarConfig;
+use experiment::{Experiment, Rule};
+use flags::{FeatureFlags, FlagConfig};
+use forwarding::{Forwarding, ForwardingConfig};
+use fsck::{FsckReport, FsckRequest, Repair};
+use graph::{CommGraph, Direction, EdgeSummary};
+use holds::{HoldRequest, Holds, InvestigationHold};
+use identity::{AuthedAdmin, AuthedAgent, AuthedAuditor, AuthedCaller};
+use ids::{AgentId, ProtocolName, ProtocolVersion};
+use intern::{entry_mut, Interner};
+use ips::{IpAction, IpConfig, IpRule, IpTracker, IpUsage};
+use language::{LanguageConfig, LanguageOfRecord};
+use latency::{LatencyTracker, PipelineTiming, Stage, StageLatency};
+use error::{DenyReason, GatewayError};
+use maintenance::{Maintenance, MaintenanceConfig, Pause, RouteGroup};
+use messages::Catalog;
+use metering::{Meter, MeterConfig};
+use metrics::{Histogram, Metrics, PromWriter};
+use mirror::Mirror;
+use namespace::{Conflict, Namespace};
+use ownership::{AdminTokens, AgentEntry, AuditorTokens, Caller, OrgRollup, TeamRollup, TeamTokens};
+use delivery::{Confirmed, Deliveries, DeliveryConfig, DeliveryCounts, DeliveryStats, DeliveryStatus};
+use outbox::{Outbox, OutboxConfig, OutboxEntry};
+use parking::{ParkConfig, ParkLot, ParkState, ParkStatus, ParkTicket};
+use policy::{Policy, PolicyRegistry, PolicySnapshot};
+use quarantine::{Quarantine, QuarantineConfig, QuarantineState, QuarantineStatus, Quarantined, QuarantinedMessage};
+use quota::{QuotaAction, QuotaConfig, QuotaReport, QuotaTracker, Resource};
+use registry_sync::{RegistrySync, RegistrySyncConfig, RegistrySyncStatus};
+use replication::{Mutation, Replication, ReplicationConfig, ReplicationStatus};
+use revision::{ChangeControl, FieldChange};
+use recert::{RecertStage, RecertStatus};
+use readability::{Quality, Trend};
+use reputation::{Reputation, TrackRecord};
+use risk::{RiskStanding, RiskTier, Trigger, Volume};
+use reservations::{ReservationConfig, ReservationState, ReservationStatus, ReservationTicket, Reservations};
+use retry::{Retries, RetryConfig, RetryTicket};
+use routing::Pipeline;
+use sampling::{Sampler, SamplingConfig, SamplingLayer};
+use sanctions::{ProtocolStatus, Standing};
+use schema::{MessageSchema, SchemaStats};
+use scrub::{ContentLogging, LogScrubber, ScrubbedJson};
+use security::SecurityConfig;
+use signing::{Jwks, KeyInfo, KeyRing, SigningConfig, VerifiedReceipt};
+use slo::{SloConfig, SloTracker, TenantSlo};
+use soft_limits::{Advisory, Notices};
+use store::{Persistence, Record, StateStore, StoredReport};
+use stream::SendStreams;
+use structure::{Families, FamilySummary};
+use threads::{ThreadEntry, ThreadView, Threads};
+use timeouts::RequestTimeouts;
+use timers::{TimerKind, Timers};
+use tls::{TlsConfig, TlsReloads};
+use translation::{TranslationConfig, Translator};
+use trial::{Graduation, Trial};
+use undeclared::{Undeclared, UndeclaredConfig};
+use versioning::{HistoryPolicy, LegacySendPolicy, Resolution};
+use webhooks::{Decision, DecisionHooks, DecisionRequest, FailureMode};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    fmt as stdfmt,
+    net::{IpAddr, SocketAddr},
+    sync::{atomic::Ordering, Arc, PoisonError, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tower::ServiceBuilder;
+use tracing::{error, info, warn, Instrument, Level, Span};
+use futures::StreamExt;
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+// Policy defaults; the thresholds in force are loaded into [`policy::Policy`]
+
+/// Maximum seconds allowed between reports for novel-language use
+const REPORT_INTERVAL_SEC: u64 = 60;
+
+/// Minimum coverage fraction required in reports
+const MIN_COVERAGE: f64 = 0.95;
+
+/// Minimum English summary length in characters
+const MIN_SUMMARY_LENGTH: usize = 30;
+
+/// Consistency score below which a report is held for review
+const MIN_CONSISTENCY: f64 = 0.5;
+
+/// Consecutive held reports after which an agent protocol is suspended for review
+const REPORT_STRIKE_LIMIT: u32 = 3;
+
+/// Reported messages decoded against the codebook to check each report
+const REPORT_SAMPLE_SIZE: usize = 3;
+
+/// Seconds without use after which a registered protocol is flagged for cleanup
+const UNUSED_PROTOCOL_SEC: u64 = 7 * 24 * 60 * 60;
+
+/// Seconds a soft-deleted agent is retained before its state is purged
+const DELETED_AGENT_RETENTION_SEC: u64 = 30 * 24 * 60 * 60;
+
+/// Seconds between sweeps for soft-deleted agents past retention
+const PURGE_SWEEP_INTERVAL_SEC: u64 = 60;
+
+/// How often SLO burn rates are checked for alerting
+const SLO_SWEEP_INTERVAL_SEC: u64 = 30;
+
+/// How often the wall clock is compared with the gateway's timeline
+const CLOCK_CHECK_INTERVAL_SEC: u64 = 5;
+
+/// How often enforcement schedules are checked for mode transitions
+const ENFORCEMENT_CHECK_INTERVAL_SEC: u64 = 60;
+
+/// How often quota breaches, and writes the state store shed, are turned
+/// into alerts
+const QUOTA_ALERT_INTERVAL_SEC: u64 = 30;
+
+/// Resolution of the timer wheel that tracks deadlines
+const TIMER_TICK_SEC: u64 = 1;
+
+/// How often the signing key's age is checked against its rotation schedule
+const KEY_ROTATION_CHECK_SEC: u64 = 60;
+
+/// `iss` claim of every receipt
+const RECEIPT_ISSUER: &str = "policy-gateway";
+
+/// Accepted-message features retained per agent protocol for consistency scoring
+const MAX_TRAFFIC_SAMPLES: usize = 1_000;
+
+/// Audit events read from the log per export chunk
+const AUDIT_EXPORT_PAGE: usize = 1_000;
+
+/// Name of the admin holding `ADMIN_TOKEN`
+const PRIMARY_ADMIN: &str = "admin";
+
+// =============================================================================
+// State
+// =============================================================================
+
+/// Shared application state
+#[derive(Clone, Default)]
+struct AppState {
+    inner: Arc<RwLock<InnerState>>,
+    detector: Arc<Detector>,
+    allowlist: Arc<ContentAllowlist>,
+    metrics: Arc<Metrics>,
+    decision_cache: Arc<DecisionCache>,
+    /// Recent (agent, protocol) registration misses
+    registration_misses: Arc<MissCache>,
+    alerter: Arc<Alerter>,
+    audit: Arc<AuditLog>,
+    /// Labels attached to sets of audit events
+    annotations: Arc<Annotations>,
+    policy: Arc<PolicyRegistry>,
+    /// Last enforcement mode seen per schedule, for logging transitions
+    enforcement: Arc<ModeTracker>,
+    translator: Arc<Translator>,
+    chaos: Arc<FaultInjector>,
+    maintenance: Arc<Maintenance>,
+    flags: Arc<FeatureFlags>,
+    /// Whether diagnostic logs keep message content
+    scrubber: Arc<LogScrubber>,
+    /// Share of each sampled event kind kept in logs and the audit trail
+    sampler: Arc<Sampler>,
+    clock: Arc<Clock>,
+    clock_skew: Arc<SkewMonitor>,
+    signer: Arc<KeyRing>,
+    quota: Arc<QuotaTracker>,
+    /// Agents whose content is retained in full for an investigation
+    holds: Arc<Holds>,
+    /// Usage per agent and billing period, for chargeback
+    meter: Arc<Meter>,
+    parking: Arc<ParkLot>,
+    quarantine: Arc<Quarantine>,
+    /// Parked-send callbacks awaiting delivery
+    outbox: Arc<Outbox>,
+    reservations: Arc<Reservations>,
+    deliveries: Arc<Deliveries>,
+    /// Request counts and admin blocks per client IP
+    ips: Arc<IpTracker>,
+    timers: Arc<Timers>,
+    webhooks: Arc<DecisionHooks>,
+    /// Answer to sends and reports an internal error kept undecided
+    degradation: Arc<DegradationPolicy>,
+    discovery: Arc<Discovery>,
+    latency: Arc<LatencyTracker>,
+    streams: Arc<SendStreams>,
+    timeouts: Arc<RequestTimeouts>,
+    slo: Arc<SloTracker>,
+    replication: Arc<Replication>,
+    /// Signed protocol registry exchange with peer gateways
+    registry_sync: Arc<RegistrySync>,
+    /// Suspensions exchanged with federated peers
+    federation: Arc<federation::Federation>,
+    /// Relay of sends to recipients behind peer gateways
+    forwarding: Arc<Forwarding>,
+    /// Copies of sampled requests to a shadow gateway
+    mirror: Arc<Mirror>,
+    /// Per-agent or gateway-wide protocol names
+    namespace: Arc<Namespace>,
+    /// Retry tokens of sends refused for a barely overdue report
+    retries: Arc<Retries>,
+    /// Agents flagged as dormant, awaiting offboarding
+    dormancy: Arc<Dormancy>,
+    /// Fingerprints of English traffic, clustered for undeclared protocols
+    undeclared: Arc<Undeclared>,
+    /// Limits of `POST /report_batch`
+    report_batches: Arc<BatchConfig>,
+    /// Uploaded attachments awaiting their sends
+    attachments: Arc<Attachments>,
+    /// Candidate policy evaluated alongside the active one
+    experiment: Arc<Experiment>,
+    /// Whether requests are traced for metric exemplars
+    exemplars: ExemplarConfig,
+    /// Budget of `POST /audit/search` queries
+    audit_search: Arc<SearchConfig>,
+    /// Roles admitted per route
+    authz: Arc<AuthzPolicy>,
+    /// Language of record per tenant
+    languages: Arc<LanguageConfig>,
+    /// Response message templates beyond built-in English
+    catalog: Arc<Catalog>,
+    /// Certificate reload outcomes, when serving TLS
+    tls_reloads: Arc<TlsReloads>,
+    /// Writes to the durable state store, when one is configured
+    store: Arc<Persistence>,
+    /// When agents were last notified of nearing each soft limit
+    soft_limit_notices: Arc<Notices>,
+    /// Shared agent ids and protocol keys for the send path
+    interner: Arc<Interner>,
+    /// Bearer token for `/admin/*`; admin endpoints are disabled when unset
+    admin_token: Option<Arc<str>>,
+    /// Named admin bearer tokens, beside `admin_token`
+    admin_tokens: Arc<AdminTokens>,
+    /// Admin actions awaiting a second admin's approval
+    approvals: Arc<Approvals>,
+    /// Descriptor fields whose revision waits for an admin
+    change_control: Arc<ChangeControl>,
+    /// Team-scoped bearer tokens for read endpoints
+    team_tokens: Arc<TeamTokens>,
+    /// Read-only bearer tokens for external auditors
+    auditor_tokens: Arc<AuditorTokens>,
+}
+
+/// Internal mutable state
+#[derive(Default)]
+struct InnerState {
+    /// Protocol registry: agent_id -> (protocol_key -> descriptor)
+    protocols: HashMap<String, HashMap<String, ProtocolDescriptor>>,
+    
+    /// Last report timestamp: "agent_id::protocol_key" -> unix_timestamp
+    last_report_ts: HashMap<String, u64>,
+    
+    /// Violation counts: agent_id -> count
+    violations: HashMap<String, u32>,
+
+    /// Reporting and review history: agent_id -> record
+    track_records: HashMap<String, TrackRecord>,
+
+    /// Usage analytics: "agent_id::protocol_key" -> stats
+    protocol_stats: HashMap<String, ProtocolStats>,
+
+    /// Soft-deleted agents: agent_id -> deletion timestamp
+    deleted_agents: HashMap<String, u64>,
+
+    /// Recent novel-message features: "agent_id::protocol_key" -> samples, oldest first
+    traffic: HashMap<String, VecDeque<TrafficSample>>,
+
+    /// Reports held for review: review id -> report
+    reviews: BTreeMap<u64, PendingReview>,
+    next_review_id: u64,
+
+    /// Agent directory: agent_id -> owning team
+    owners: HashMap<String, String>,
+
+    /// team -> owning org
+    team_orgs: HashMap<String, String>,
+
+    /// Agents imported from the service registry: agent_id -> record
+    discovered: HashMap<String, DiscoveredAgent>,
+
+    /// Alerts raised: agent_id -> count
+    alerts: HashMap<String, u64>,
+
+    /// Delivered messages and accepted reports by conversation
+    threads: Threads,
+
+    /// Sender and recipient indices over accepted novel-language messages
+    graph: CommGraph,
+
+    /// Summary quality of recent accepted reports: agent_id -> samples, oldest first
+    summary_quality: HashMap<String, VecDeque<readability::Sample>>,
+}
+
+impl InnerState {
+    fn is_deleted(&self, agent_id: &str) -> bool {
+        self.deleted_agents.contains_key(agent_id)
+    }
+
+    /// Remove every trace of `agent_id` from live state
+    fn purge_agent(&mut self, agent_id: &str) {
+        let prefix = format!("{agent_id}::");
+        self.protocols.remove(agent_id);
+        self.last_report_ts.retain(|k, _| !k.starts_with(&prefix));
+        self.violations.remove(agent_id);
+        self.track_records.remove(agent_id);
+        self.protocol_stats.retain(|k, _| !k.starts_with(&prefix));
+        self.traffic.retain(|k, _| !k.starts_with(&prefix));
+        self.reviews.retain(|_, r| r.report.agent_id != agent_id);
+        self.owners.remove(agent_id);
+        self.discovered.remove(agent_id);
+        self.alerts.remove(agent_id);
+        self.threads.forget_agent(agent_id);
+        self.graph.forget_agent(agent_id);
+        self.summary_quality.remove(agent_id);
+        self.deleted_agents.remove(agent_id);
+    }
+
+    /// Count a compliance violation against `agent_id`; returns the new total
+    fn add_violation(&mut self, agent_id: &str) -> u32 {
+        let count = self.violations.entry(agent_id.to_string()).or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    /// Reputation of `agent_id` under `policy`
+    fn reputation(&self, policy: &Policy, agent_id: &str) -> Reputation {
+        self.track_records.get(agent_id).cloned().unwrap_or_default().reputation(
+            self.violations.get(agent_id).copied().unwrap_or(0),
+            policy.probation.as_ref(),
+            policy.report_interval_sec,
+        )
+    }
+
+    /// Report interval on `report_key` for an agent whose own is `interval`,
+    /// tightened by the protocol's effective risk tier and, as of `now`, by a
+    /// lapsed certification
+    fn report_interval(&self, policy: &Policy, report_key: &str, interval: u64, now: u64) -> u64 {
+        let interval = match &policy.risk {
+            Some(risk) => self
+                .risk_tier(report_key)
+                .and_then(|tier| risk.interval(tier))
+                .map_or(interval, |tiered| tiered.min(interval)),
+            None => interval,
+        };
+        match (&policy.recertification, self.recert_stage(policy, report_key, now)) {
+            (Some(recert), Some(stage)) if stage >= RecertStage::Restricted => {
+                recert.restricted_report_interval_sec.min(interval)
+            }
+            _ => interval,
+        }
+    }
+
+    /// Recertification stage of the protocol behind `report_key` as of
+    /// `now`; none when the policy has no recertification
+    fn recert_stage(&self, policy: &Policy, report_key: &str, now: u64) -> Option<RecertStage> {
+        let recert = policy.recertification.as_ref()?;
+        let stats = self.protocol_stats.get(report_key)?;
+        Some(recert.stage(stats.certified_at(), now))
+    }
+
+    /// Effective risk tier of the protocol behind `report_key`
+    fn risk_tier(&self, report_key: &str) -> Option<RiskTier> {
+        let (agent_id, key) = report_key.split_once("::")?;
+        let registered = self.protocols.get(agent_id)?.get(key)?;
+        let standing = self.protocol_stats.get(report_key).map(|s| &s.risk);
+        standing.map_or_else(|| RiskTier::parse(&registered.risk_tier), |s| s.tier(&registered.risk_tier))
+    }
+
+    /// Update the track record of `agent_id`, returning it to replicate
+    fn update_track_record(&mut self, agent_id: &str, f: impl FnOnce(&mut TrackRecord)) -> Mutation {
+        let record = entry_mut(&mut self.track_records, agent_id);
+        f(record);
+        Mutation::TrackRecord {
+            agent_id: agent_id.to_string(),
+            record: record.clone(),
+        }
+    }
+
+    /// Purge soft-deleted agents whose retention has elapsed; returns their ids
+    fn purge_expired(&mut self, now: u64, retention_sec: u64, held: impl Fn(&str) -> bool) -> Vec<String> {
+        let expired: Vec<String> = self
+            .deleted_agents
+            .iter()
+            .filter(|(id, deleted_at)| now.saturating_sub(**deleted_at) >= retention_sec && !held(id))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            self.purge_agent(id);
+        }
+        expired
+    }
+}
+
+/// Per-protocol usage counters
+#[derive(Debug, Default)]
+struct ProtocolStats {
+    registered_at: u64,
+    messages_sent: u64,
+    recipients: HashSet<String>,
+    reports_filed: u64,
+    coverage_sum: f64,
+    /// Reports scored against machine glosses, and the sum of their scores
+    honesty_scored: u64,
+    honesty_sum: f64,
+    last_used_ts: Option<u64>,
+    /// Structural families of the accepted traffic
+    structure: Families,
+    /// Messages that passed and failed the protocol's message schema
+    schema_passed: u64,
+    schema_failed: u64,
+    /// Strikes and suspension for inconsistent reports
+    standing: Standing,
+    /// Risk tier raised by reassessment triggers
+    risk: RiskStanding,
+    /// Sends per window, for volume triggers
+    volume: Volume,
+    /// Receipts for the sends tracked under `DELIVERY_TIMEOUT_SEC`
+    deliveries: DeliveryCounts,
+    /// Relaxed report thresholds until the protocol graduates
+    trial: Option<Trial>,
+    /// Last recertification, if any since registration
+    recertified_at: Option<u64>,
+}
+
+impl ProtocolStats {
+    /// Start of the current certification: the last recertification, or
+    /// registration
+    fn certified_at(&self) -> u64 {
+        self.recertified_at.unwrap_or(self.registered_at)
+    }
+}
+
+// =============================================================================
+// Data Types
+// =============================================================================
+
+/// Protocol metadata required for registration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProtocolDescriptor {
+    name: ProtocolName,
+    version: ProtocolVersion,
+    purpose: String,
+    scope: String,
+    risk_tier: String,
+    translation_method: String,
+    /// Earlier versions this one supersedes, e.g. `"1.x"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compatible_with: Option<String>,
+    /// Inherit or reset the report clock of compatible versions
+    #[serde(default)]
+    history: HistoryPolicy,
+    /// Treatment of sends that still declare a compatible older version
+    #[serde(default)]
+    legacy_sends: LegacySendPolicy,
+    /// Message token -> English gloss, used to check report consistency
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    codebook: BTreeMap<String, String>,
+    /// Encryption used by this protocol and where its keys are escrowed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encryption: Option<EncryptionMetadata>,
+    /// Specs, grammars, and examples documenting the protocol
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    docs: Vec<DocArtifact>,
+    /// JSON Schema every novel-language message under the protocol must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message_schema: Option<MessageSchema>,
+    /// Recipients novel messages may be addressed to: agent ids,
+    /// `team:<team>`, or `org:<org>`; empty leaves the protocol unrestricted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allowed_recipients: Vec<String>,
+}
+
+impl ProtocolDescriptor {
+    /// Whether `to` falls inside the declared recipient allowlist
+    fn in_scope(&self, st: &InnerState, to: &str) -> bool {
+        if self.allowed_recipients.is_empty() {
+            return true;
+        }
+        let team = st.owners.get(to);
+        let org = team.and_then(|t| st.team_orgs.get(t));
+        self.allowed_recipients.iter().any(|entry| match entry.split_once(':') {
+            Some(("team", t)) => team.is_some_and(|team| team == t),
+            Some(("org", o)) => org.is_some_and(|org| org == o),
+            _ => entry == to,
+        })
+    }
+}
+
+/// Check allowlist entries: agent ids, `team:<team>`, or `org:<org>`
+fn validate_scope(entries: &[String]) -> Result<(), String> {
+    for entry in entries {
+        match entry.split_once(':') {
+            Some(("team" | "org", name)) if !name.trim().is_empty() => {}
+            Some(_) => return Err(format!("Allowed recipient {entry:?} must be an agent id, team:<team>, or org:<org>")),
+            None => ids::validate("Allowed recipient", entry, ids::MAX_AGENT_ID_LEN)?,
+        }
+    }
+    Ok(())
+}
+
+/// Request to register a protocol for an agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisterProtocolRequest {
+    agent_id: AgentId,
+    protocol: ProtocolDescriptor,
+    /// Start the protocol on trial under the policy's relaxed thresholds
+    #[serde(default)]
+    trial: bool,
+}
+
+/// English translation report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnglishReport {
+    agent_id: AgentId,
+    protocol_name: ProtocolName,
+    protocol_version: ProtocolVersion,
+    window_start_ts: f64,
+    window_end_ts: f64,
+    message_ids: Vec<String>,
+    english_summary: String,
+    coverage: f64,
+    self_confidence: f64,
+    notes: Option<String>,
+    /// Conversation the report covers; scores it against that thread only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    thread_id: Option<String>,
+}
+
+/// A report held for review because it scored low on consistency
+#[derive(Debug, Clone, Serialize)]
+struct PendingReview {
+    id: u64,
+    /// Resolved protocol key the report applies to
+    protocol: String,
+    submitted_at: u64,
+    consistency: Consistency,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    honesty: Option<f64>,
+    report: EnglishReport,
+    /// Summary with its quoted protocol tokens redacted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redacted_summary: Option<String>,
+}
+
+/// Protocol reference in messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolRef {
+    name: ProtocolName,
+    version: ProtocolVersion,
+}
+
+/// Message recipient(s): a single agent id or a broadcast list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Recipients {
+    One(AgentId),
+    Many(Vec<AgentId>),
+}
+
+impl Recipients {
+    /// Whether a broadcast list names nobody
+    fn is_empty(&self) -> bool {
+        matches!(self, Self::Many(list) if list.is_empty())
+    }
+
+    /// Distinct recipients in request order
+    fn list(&self) -> Vec<&str> {
+        match self {
+            Self::One(to) => vec![to.as_str()],
+            Self::Many(list) => {
+                let mut seen = HashSet::new();
+                list.iter()
+                    .map(AgentId::as_str)
+                    .filter(|to| seen.insert(*to))
+                    .collect()
+            }
+        }
+    }
+}
+
+impl stdfmt::Display for Recipients {
+    fn fmt(&self, f: &mut stdfmt::Formatter<'_>) -> stdfmt::Result {
+        match self {
+            Self::One(to) => f.write_str(to),
+            Self::Many(list) => f.write_str(&list.join(",")),
+        }
+    }
+}
+
+/// Request to send a message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendMessageRequest {
+    from: AgentId,
+    to: Recipients,
+    content: String,
+    /// Sender's hint for machine output, e.g. `application/json`; only
+    /// consulted by allowlist patterns restricted to content types
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    protocol: Option<ProtocolRef>,
+    ts: Option<f64>,
+    /// Hold the message if the sender's report is overdue, instead of refusing it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    park: bool,
+    /// Where to POST the outcome of a parked message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    callback_url: Option<String>,
+    /// Conversation the message belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    thread_id: Option<String>,
+    /// Sender's own id for the message, as it lists it in its report's
+    /// `message_ids`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message_id: Option<String>,
+    /// Report to file before the send is evaluated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    report: Option<EnglishReport>,
+    /// Token from an earlier refusal for a barely overdue report, resending
+    /// that message as its retry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_token: Option<String>,
+    /// Attempt this send retries, once its `retry_token` was redeemed
+    #[serde(skip)]
+    retry_of: Option<String>,
+    /// Quarantine entry this send was released from after review
+    #[serde(skip)]
+    released_from: Option<u64>,
+    /// Ids of attachments uploaded with `POST /attachments/{agent_id}`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<String>,
+}
+
+/// Query parameters for `/audit/export`
+#[derive(Debug, Clone, Default, Deserialize)]
+struct AuditExportQuery {
+    /// First sequence number to export (inclusive)
+    cursor: Option<u64>,
+    /// Maximum number of events to export
+    limit: Option<usize>,
+    /// Export only events carrying this annotation label
+    label: Option<String>,
+}
+
+/// Query parameters for `GET /billing/usage`
+#[derive(Debug, Deserialize)]
+struct BillingQuery {
+    /// Billing period label; the current period when unset
+    period: Option<String>,
+    /// `csv` for a CSV export, JSON otherwise
+    format: Option<String>,
+}
+
+/// Query parameters for `GET /audit/annotations`
+#[derive(Debug, Deserialize)]
+struct AnnotationQuery {
+    label: Option<String>,
+}
+
+/// Query parameters for `GET /graph/edges`
+#[derive(Debug, Deserialize)]
+struct GraphQuery {
+    /// Limit to edges of this agent; the whole graph when unset
+    agent: Option<String>,
+    /// Lookback such as `24h` or `7d`
+    window: Option<String>,
+    #[serde(default)]
+    direction: Direction,
+}
+
+/// Query parameters for `GET /stats/ips`
+#[derive(Debug, Deserialize)]
+struct IpStatsQuery {
+    /// Busiest IPs to list; 100 when unset
+    limit: Option<usize>,
+}
+
+/// Edges returned by `GET /graph/edges`
+#[derive(Debug, Serialize)]
+struct GraphResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    agent: Option<String>,
+    window_sec: u64,
+    since: u64,
+    edges: Vec<EdgeSummary>,
+}
+
+/// Directory update assigning an agent to a team
+#[derive(Debug, Clone, Deserialize)]
+struct SetOwnerRequest {
+    team: String,
+}
+
+/// Directory update assigning a team to an org
+#[derive(Debug, Clone, Deserialize)]
+struct SetTeamRequest {
+    org: String,
+}
+
+/// Admin test-alert request
+#[derive(Debug, Clone, Default, Deserialize)]
+struct TestAlertRequest {
+    kind: Option<AlertKind>,
+    agent_id: Option<String>,
+}
+
+/// Generic API response
+#[derive(Debug, Default, Serialize)]
+pub struct ApiResponse {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Machine-readable error code (see [`GatewayError::code`])
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    /// Every reason a send or report was refused for, the primary one first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    deny_reasons: Vec<DenyReason>,
+    /// Per-recipient outcome of a broadcast send
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decisions: Option<BTreeMap<String, RecipientDecision>>,
+    /// Signed receipt (compact JWS) for an accepted send or report
+    #[serde(skip_serializing_if = "Option::is_none")]
+    receipt: Option<String>,
+    /// Ticket for a send parked until the sender's next report
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parked: Option<ParkTicket>,
+    /// Reservation to commit or abort for a two-phase send
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reservation: Option<ReservationTicket>,
+    /// Id recipients confirm delivery of an allowed send with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_id: Option<String>,
+    /// Signed receipt for a report filed with the send
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report_receipt: Option<String>,
+    /// Hard limits the agent is nearing
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    advisories: Vec<Advisory>,
+    /// Code of the refusal waived because the sender's team was audit-only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    waived: Option<&'static str>,
+    /// Token to retry a send refused for a barely overdue report
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry: Option<RetryTicket>,
+    /// Accepted undecided because of an internal error (see [`degradation`])
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    degraded: bool,
+    /// Where and why a request body did not decode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body_error: Option<BodyError>,
+    /// Fields a re-registration revised, or would revise once approved
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    changes: Vec<FieldChange>,
+    /// Proposal awaiting an admin (see `GET /admin/approvals`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    approval_id: Option<u64>,
+}
+
+impl ApiResponse {
+    fn success() -> Self {
+        Self { ok: true, ..Self::default() }
+    }
+    
+    fn success_with_message(msg: &str) -> Self {
+        Self { ok: true, message: Some(msg.to_string()), ..Self::default() }
+    }
+    
+    fn error(msg: &str) -> Self {
+        Self { ok: false, error: Some(msg.to_string()), ..Self::default() }
+    }
+}
+
+/// Claims signed into a receipt
+#[derive(Debug, Serialize)]
+struct ReceiptClaims<'a> {
+    iss: &'static str,
+    /// `message` or `report`
+    kind: &'static str,
+    /// Sending or reporting agent
+    sub: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    protocol: Option<&'a str>,
+    /// Recipients the message was accepted for
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    recipients: Vec<&'a str>,
+    /// SHA-256 of the message content, hex-encoded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_sha256: Option<String>,
+    /// SHA-256 of each attachment, in the send's order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments_sha256: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_count: Option<usize>,
+    policy_version: &'a str,
+    iat: u64,
+}
+
+/// Body of `POST /receipts/verify`
+#[derive(Debug, Clone, Deserialize)]
+struct VerifyReceiptRequest {
+    receipt: String,
+}
+
+/// Outcome of a send for one recipient
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecipientDecision {
+    allowed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    /// Error code of a refusal more specific than `recipient_refused`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+}
+
+impl RecipientDecision {
+    fn allow() -> Self {
+        Self { allowed: true, reason: None, code: None }
+    }
+
+    fn deny(reason: &str) -> Self {
+        Self { allowed: false, reason: Some(reason.to_string()), code: None }
+    }
+
+    fn out_of_scope(protocol: &str) -> Self {
+        Self {
+            allowed: false,
+            reason: Some(format!("Recipient outside the declared scope of {protocol}")),
+            code: Some("scope_violation".to_string()),
+        }
+    }
+}
+
+/// Usage analytics for a registered protocol
+#[derive(Debug, Serialize)]
+struct ProtocolStatsResponse {
+    agent_id: String,
+    protocol: String,
+    registered_at: u64,
+    messages_sent: u64,
+    unique_recipients: usize,
+    reports_filed: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    average_coverage: Option<f64>,
+    /// Mean similarity of reports to machine glosses, when glossing is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    average_honesty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_used_ts: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recommendation: Option<String>,
+    /// Structural families of the accepted traffic, largest first
+    structural_families: Vec<FamilySummary>,
+    /// Traffic splits into more than one structural family
+    mismatch_suspected: bool,
+    /// Message schema validation, once a message was checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schema_validation: Option<SchemaStats>,
+    status: ProtocolStatus,
+    /// Consecutive reports held for low consistency
+    strikes: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suspended_at: Option<u64>,
+    /// Effective risk tier: the registered one unless reassessment raised it
+    risk_tier: String,
+    #[serde(skip_serializing_if = "RiskStanding::is_default")]
+    risk_reassessment: RiskStanding,
+    /// Delivery receipts, once a send was tracked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delivery: Option<DeliveryStats>,
+    /// Trial the protocol is on, until it graduates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trial: Option<Trial>,
+    /// Certification, while the policy has recertification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recertification: Option<RecertStatus>,
+}
+
+impl ProtocolStatsResponse {
+    fn new(
+        agent_id: &str,
+        key: &str,
+        risk_tier: &str,
+        stats: &ProtocolStats,
+        policy: &Policy,
+        now: u64,
+    ) -> Self {
+        let last_activity = stats.last_used_ts.unwrap_or(stats.registered_at);
+        let unused_after = policy.unused_protocol_sec;
+        let recommendation = (now.saturating_sub(last_activity) > unused_after).then(|| {
+            format!(
+                "Protocol unused for over {} days; consider deregistering it",
+                unused_after / 86_400
+            )
+        });
+        Self {
+            agent_id: agent_id.to_string(),
+            protocol: key.to_string(),
+            registered_at: stats.registered_at,
+            messages_sent: stats.messages_sent,
+            unique_recipients: stats.recipients.len(),
+            reports_filed: stats.reports_filed,
+            average_coverage: (stats.reports_filed > 0)
+                .then(|| stats.coverage_sum / stats.reports_filed as f64),
+            average_honesty: (stats.honesty_scored > 0)
+                .then(|| stats.honesty_sum / stats.honesty_scored as f64),
+            last_used_ts: stats.last_used_ts,
+            recommendation,
+            structural_families: stats.structure.summary(),
+            mismatch_suspected: stats.structure.is_mixed(),
+            schema_validation: SchemaStats::new(stats.schema_passed, stats.schema_failed),
+            status: stats.standing.status(),
+            strikes: stats.standing.strikes,
+            suspended_at: stats.standing.suspended_at,
+            risk_tier: stats.risk.effective(risk_tier),
+            risk_reassessment: stats.risk.clone(),
+            delivery: DeliveryStats::new(&stats.deliveries),
+            trial: stats.trial,
+            recertification: policy
+                .recertification
+                .as_ref()
+                .map(|recert| recert.status(stats.certified_at(), now)),
+        }
+    }
+}
+
+/// Body of `GET /protocols/{agent}/{name}/{version}/docs`
+#[derive(Debug, Serialize)]
+struct ProtocolDocsResponse {
+    agent_id: String,
+    protocol: String,
+    translation_method: String,
+    docs: Vec<DocArtifact>,
+}
+
+/// Health check response
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    ok: bool,
+    message: &'static str,
+    /// Mode of the default enforcement schedule, when one is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enforcement: Option<EnforcementMode>,
+}
+
+/// Body of `GET /status`
+#[derive(Debug, Serialize)]
+struct StatusResponse {
+    policy_version: String,
+    /// Mode per enforcement schedule: `default` and each scheduled team the
+    /// caller may read; empty while no schedule is set
+    enforcement: BTreeMap<String, ModeStatus>,
+    /// Suspended agent protocols the caller may read, here and by peers
+    suspensions: Vec<SuspensionStatus>,
+}
+
+/// A protocol suspension as listed by `GET /status`
+#[derive(Debug, Serialize)]
+struct SuspensionStatus {
+    agent_id: String,
+    protocol: String,
+    /// Gateway that suspended the protocol
+    origin: String,
+    /// Unix second the protocol was suspended
+    since: u64,
+    reason: String,
+    /// When a peer's suspension lapses unless announced again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    /// Whether sends under the protocol are refused here
+    enforced: bool,
+}
+
+/// Readiness response including language detector status
+#[derive(Debug, Serialize)]
+struct ReadinessResponse {
+    ok: bool,
+    detector: DetectorStatus,
+    /// Route groups paused for maintenance
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    maintenance: BTreeMap<RouteGroup, Pause>,
+}
+
+/// Language detector status as reported by `/health/ready`
+#[derive(Debug, Serialize)]
+struct DetectorStatus {
+    classifier_configured: bool,
+    breaker: BreakerState,
+    fallback: DetectorFallback,
+}
+
+// =============================================================================
+// Utility Functions
+// =============================================================================
+
+/// Parse the environment variable `key`, if set, not blank, and well-formed
+fn env_parse<T: std::str::FromStr>(key: &str) -> Option<T> {
+    let value = std::env::var(key).ok()?;
+    Some(value.trim()).filter(|v| !v.is_empty())?.parse().ok()
+}
+
+/// Get current Unix timestamp in seconds
+fn now_unix_sec() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+/// Create protocol key from name and version
+fn protocol_key(name: &str, version: &str) -> String {
+    format!("{name}:{version}")
+}
+
+/// Compare tokens in constant time so they cannot be guessed byte by byte
+fn tokens_match(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+/// Check `Authorization: Bearer <ADMIN_TOKEN>` on an admin endpoint
+fn require_admin(state: &AppState, headers: &HeaderMap) -> Result<(), GatewayError> {
+    admin_caller(state, headers).map(|_| ())
+}
+
+/// Name of the admin calling an admin endpoint: `admin` for `ADMIN_TOKEN`,
+/// otherwise the name `ADMIN_TOKENS` gives the token
+fn admin_caller(state: &AppState, headers: &HeaderMap) -> Result<String, GatewayError> {
+    if state.admin_token.is_none() && state.admin_tokens.is_empty() {
+        return Err(GatewayError::AdminDisabled);
+    }
+    let presented = bearer_token(headers).unwrap_or("");
+    if state.admin_token.as_deref().is_some_and(|expected| tokens_match(presented, expected)) {
+        return Ok(PRIMARY_ADMIN.to_string());
+    }
+    state
+        .admin_tokens
+        .admin_for(presented)
+        .map(str::to_string)
+        .ok_or(GatewayError::InvalidAdminToken)
+}
+
+/// Identify the caller of a read endpoint
+///
+/// Without team tokens configured, unauthenticated reads stay open.
+fn read_access(state: &AppState, headers: &HeaderMap) -> Result<Caller, GatewayError> {
+    let presented = bearer_token(headers);
+    if presented.is_some() && admin_caller(state, headers).is_ok() {
+        return Ok(Caller::Admin);
+    }
+    if let Some(auditor) = presented.and_then(|t| state.auditor_tokens.auditor_for(t)) {
+        return Ok(Caller::Auditor(auditor.to_string()));
+    }
+    if let Some(team) = presented.and_then(|t| state.team_tokens.team_for(t)) {
+        return Ok(Caller::Team(team.to_string()));
+    }
+    if presented.is_none() && state.team_tokens.is_empty() {
+        return Ok(Caller::Open);
+    }
+    Err(GatewayError::Unauthenticated)
+}
+
+/// Check for the admin or an auditor token on a read-only admin endpoint
+fn require_audit_read(state: &AppState, headers: &HeaderMap) -> Result<Caller, GatewayError> {
+    if let Some(auditor) = bearer_token(headers).and_then(|t| state.auditor_tokens.auditor_for(t)) {
+        return Ok(Caller::Auditor(auditor.to_string()));
+    }
+    require_admin(state, headers).map(|()| Caller::Admin)
+}
+
+/// What an alert is about
+#[derive(Debug, Clone, Copy)]
+enum AlertSubject<'a> {
+    Agent(&'a str),
+    /// A tenant (owning team); `slo::UNASSIGNED_TENANT` has no team
+    Tenant(&'a str),
+    Gateway,
+}
+
+/// Raise an alert, attributed to the team and org of its subject when known
+async fn raise_alert(state: &AppState, kind: AlertKind, subject: AlertSubject<'_>, detail: &str) -> Dispatch {
+    let (agent_id, team, org) = {
+        let mut st = state.inner.write().unwrap();
+        let (agent_id, team) = match subject {
+            AlertSubject::Agent(id) => {
+                *st.alerts.entry(id.to_string()).or_insert(0) += 1;
+                (Some(id), st.owners.get(id).cloned())
+            }
+            AlertSubject::Tenant(tenant) => (None, (tenant != slo::UNASSIGNED_TENANT).then(|| tenant.to_string())),
+            AlertSubject::Gateway => (None, None),
+        };
+        let org = team.as_ref().and_then(|t| st.team_orgs.get(t)).cloned();
+        (agent_id, team, org)
+    };
+    let alert = Alert::new(kind, agent_id, detail).with_owner(team, org);
+    state.alerter.notify(&alert).await
+}
+
+/// Receiver-side checks for one recipient of a send that passed sender-side checks
+///
+/// `protocol` is the sender's protocol key for novel-language sends. The
+/// recipient's class under `policy.routing` selects any further checks.
+fn recipient_decision(
+    st: &InnerState,
+    policy: &Policy,
+    from: &str,
+    to: &str,
+    protocol: Option<&str>,
+) -> RecipientDecision {
+    if to.trim().is_empty() {
+        return RecipientDecision::deny("Invalid recipient id");
+    }
+    if st.is_deleted(to) {
+        return RecipientDecision::deny("Recipient agent deleted");
+    }
+    if let Some(key) = protocol {
+        let descriptor = st.protocols.get(from).and_then(|m| m.get(key));
+        if descriptor.is_some_and(|p| !p.in_scope(st, to)) {
+            return RecipientDecision::out_of_scope(key);
+        }
+    }
+    let Some(routing) = &policy.routing else {
+        return RecipientDecision::allow();
+    };
+    let listed = st.discovered.get(to).and_then(|d| d.recipient_class.as_deref());
+    let Some(class) = routing.classify(to, listed, st.owners.get(to).map(String::as_str)) else {
+        return RecipientDecision::allow();
+    };
+    match (routing.pipeline(class), protocol) {
+        (Pipeline::EnglishOnly, Some(_)) => {
+            RecipientDecision::deny(&format!("Recipient class {class} accepts English only"))
+        }
+        (Pipeline::CodebookRequired, Some(key)) => {
+            let glossed = st
+                .protocols
+                .get(from)
+                .and_then(|m| m.get(key))
+                .is_some_and(|p| !p.codebook.is_empty());
+            if glossed {
+                RecipientDecision::allow()
+            } else {
+                RecipientDecision::deny(&format!("Recipient class {class} requires a protocol codebook"))
+            }
+        }
+        _ => RecipientDecision::allow(),
+    }
+}
+
+/// Collapse per-recipient decisions into a send response
+///
+/// A single-recipient send keeps the plain success/error shape. Broadcasts
+/// always carry the decision map: 200 when every recipient was allowed,
+/// 207 when only some were, 403 when none were.
+fn send_outcome(
+    recipients: &Recipients,
+    decisions: BTreeMap<String, RecipientDecision>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let allowed = decisions.values().filter(|d| d.allowed).count();
+    if let Recipients::One(_) = recipients {
+        return match decisions.into_iter().next() {
+            Some((to, d)) if !d.allowed && d.code.as_deref() == Some("scope_violation") => {
+                Err(GatewayError::ScopeViolation { recipient: to })
+            }
+            Some((_, d)) if !d.allowed => Err(GatewayError::RecipientRefused(
+                d.reason.unwrap_or_else(|| "Recipient refused".to_string()),
+            )),
+            _ => Ok((StatusCode::OK, Json(ApiResponse::success()))),
+        };
+    }
+
+    let (code, mut body) = if allowed == decisions.len() {
+        (StatusCode::OK, ApiResponse::success())
+    } else if allowed > 0 {
+        (StatusCode::MULTI_STATUS, ApiResponse::error("Some recipients were refused"))
+    } else {
+        (StatusCode::FORBIDDEN, ApiResponse::error("All recipients were refused"))
+    };
+    body.decisions = Some(decisions);
+    Ok((code, Json(body)))
+}
+
+/// Heuristic check if text appears to be English
+///
+/// Returns `true` if the text is plausibly English.
+/// Conservative: flags anything suspicious as non-English.
+///
+/// For production, consider:
+/// - Language model classifier
+/// - Entropy-based detection
+/// - Compression ratio analysis
+fn looks_like_english(s: &str) -> bool {
+    let s = s.trim();
+    if s.is_empty() {
+        return true;
+    }
+
+    // Reject if contains non-ASCII characters
+    if s.chars().any(|c| c as u32 > 0x7F) {
+        return false;
+    }
+
+    // Reject structured machine syntax (key=value;..., pipes, hashes). The
+    // word and vowel checks below only look at longer strings, so without
+    // this short codes such as `CMD|seq=0;state=0x00` read as English.
+    let symbols = s
+        .chars()
+        .filter(|c| !c.is_alphanumeric() && !c.is_whitespace())
+        .filter(|c| !matches!(c, '.' | ',' | '!' | '?' | '\'' | '"' | '-' | ':' | '(' | ')'))
+        .count();
+    if symbols * 10 > s.len() {
+        return false;
+    }
+
+    // Count recognizable word-like tokens
+    let word_count = s
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|w| w.len() >= 2)
+        .count();
+
+    // Reject if very few words in a long string
+    if s.len() > 40 && word_count < 3 {
+        return false;
+    }
+
+    // Check vowel ratio (English typically ~30-40%)
+    let letters: usize = s.chars().filter(|c| c.is_ascii_alphabetic()).count();
+    if letters > 0 {
+        let vowels: usize = s
+            .chars()
+            .filter(|c| matches!(c.to_ascii_lowercase(), 'a' | 'e' | 'i' | 'o' | 'u'))
+            .count();
+        let ratio = (vowels as f64) / (letters as f64);
+        if s.len() > 30 && ratio < 0.20 {
+            return false;
+        }
+    }
+
+    true
+}
+
+// =============================================================================
+// Handlers
+// =============================================================================
+
+/// Health check endpoint
+async fn health(State(state): State<AppState>) -> (StatusCode, Json<HealthResponse>) {
+    let enforcement = state
+        .policy
+        .current()
+        .policy
+        .enforcement
+        .as_ref()
+        .map(|schedule| schedule.mode_at(None, state.clock.now()));
+    (
+        StatusCode::OK,
+        Json(HealthResponse {
+            ok: true,
+            message: "Gateway operational",
+            enforcement,
+        }),
+    )
+}
+
+/// Policy version, the enforcement mode of each schedule the caller may
+/// read, and the suspensions of agents it may read
+async fn gateway_status(
+    State(state): State<AppState>,
+    AuthedCaller(caller): AuthedCaller,
+) -> Json<StatusResponse> {
+    let policy = state.policy.current();
+    let now = state.clock.now();
+    let enforcement = policy
+        .policy
+        .enforcement
+        .as_ref()
+        .map(|schedule| {
+            schedule
+                .keys()
+                .filter(|key| *key == enforcement::DEFAULT_SCHEDULE || caller.may_read_team(key))
+                .map(|key| {
+                    let status = ModeStatus {
+                        mode: schedule.mode_at(Some(key), now),
+                        next_change: schedule.next_change(Some(key), now),
+                    };
+                    (key.to_string(), status)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut suspensions = Vec::new();
+    {
+        let st = state.inner.read().unwrap();
+        let origin = &state.registry_sync.config().name;
+        for (report_key, stats) in &st.protocol_stats {
+            let (Some(since), Some((agent_id, protocol))) = (stats.standing.suspended_at, report_key.split_once("::"))
+            else {
+                continue;
+            };
+            if caller.may_read_agent(&st, agent_id) {
+                suspensions.push(SuspensionStatus {
+                    agent_id: agent_id.to_string(),
+                    protocol: protocol.to_string(),
+                    origin: origin.clone(),
+                    since,
+                    reason: federation::reason(&stats.standing),
+                    expires_at: None,
+                    enforced: true,
+                });
+            }
+        }
+        for (report_key, remote) in state.federation.all(now) {
+            let Some((agent_id, protocol)) = report_key.split_once("::") else {
+                continue;
+            };
+            if caller.may_read_agent(&st, agent_id) {
+                suspensions.push(SuspensionStatus {
+                    agent_id: agent_id.to_string(),
+                    protocol: protocol.to_string(),
+                    origin: remote.origin,
+                    since: remote.issued_at,
+                    reason: remote.reason,
+                    expires_at: Some(remote.expires_at),
+                    enforced: remote.trust == federation::Trust::Enforce,
+                });
+            }
+        }
+    }
+    suspensions.sort_by(|a, b| (&a.agent_id, &a.protocol, &a.origin).cmp(&(&b.agent_id, &b.protocol, &b.origin)));
+    Json(StatusResponse {
+        policy_version: policy.version.clone(),
+        enforcement,
+        suspensions,
+    })
+}
+
+/// Readiness check: not ready while the classifier breaker is open and the
+/// fallback is fail-closed, since every novel-looking send would be refused
+///
+/// Paused route groups are listed but do not fail readiness; the other
+/// groups are still served.
+async fn health_ready(State(state): State<AppState>) -> (StatusCode, Json<ReadinessResponse>) {
+    let detector = &state.detector;
+    let ready = detector.is_ready();
+    let status = DetectorStatus {
+        classifier_configured: detector.config().url.is_some(),
+        breaker: detector.breaker_state(),
+        fallback: detector.config().fallback,
+    };
+    let code = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (
+        code,
+        Json(ReadinessResponse {
+            ok: ready,
+            detector: status,
+            maintenance: state.maintenance.config().paused,
+        }),
+    )
+}
+
+/// Prometheus metrics endpoint
+async fn metrics_endpoint(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> ([(header::HeaderName, &'static str); 1], String) {
+    // Exemplars exist only in OpenMetrics, so it is served to scrapers asking for it
+    let openmetrics = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/openmetrics-text"));
+    let m = &state.metrics;
+    let d = &state.detector.counters;
+    let c = &state.decision_cache;
+    let t = &state.translator.counters;
+    let park = &state.parking.counters;
+    let mirrored = &state.mirror.counters;
+    let delivery = &state.deliveries.counters;
+    let hooks = &state.webhooks.counters;
+    let disc = &state.discovery.counters;
+    let patterns = state.allowlist.stats();
+    let pattern_labels: Vec<_> = patterns.iter().map(|p| [("pattern", p.name.as_str())]).collect();
+    let pattern_matches: Vec<(&[(&str, &str)], f64)> = pattern_labels
+        .iter()
+        .zip(&patterns)
+        .map(|(labels, p)| (&labels[..], p.matches as f64))
+        .collect();
+    let request_counts = state.timeouts.counts();
+    let request_labels: Vec<_> = request_counts.iter().map(|(group, ..)| [("group", *group)]).collect();
+    let slow_requests: Vec<(&[(&str, &str)], f64)> = request_labels
+        .iter()
+        .zip(&request_counts)
+        .map(|(labels, (_, slow, _))| (&labels[..], *slow as f64))
+        .collect();
+    let request_timeouts: Vec<(&[(&str, &str)], f64)> = request_labels
+        .iter()
+        .zip(&request_counts)
+        .map(|(labels, (_, _, timed_out))| (&labels[..], *timed_out as f64))
+        .collect();
+    let schema_violations = state.audit.schema_violations();
+    let schema_labels: Vec<_> = schema_violations.keys().map(|event| [("event", *event)]).collect();
+    let schema_violations: Vec<(&[(&str, &str)], f64)> = schema_labels
+        .iter()
+        .zip(schema_violations.values())
+        .map(|(labels, n)| (&labels[..], *n as f64))
+        .collect();
+    let sync_peers = state.registry_sync.peers(state.clock.now());
+    let sync_labels: Vec<_> = sync_peers.keys().map(|peer| [("peer", peer.as_str())]).collect();
+    let mut sync_lag: Vec<(&[(&str, &str)], f64)> = Vec::new();
+    let (mut sync_divergent, mut sync_failures, mut sync_imported) = (Vec::new(), Vec::new(), Vec::new());
+    for (labels, peer) in sync_labels.iter().zip(sync_peers.values()) {
+        if let Some(lag) = peer.lag_sec {
+            sync_lag.push((&labels[..], lag as f64));
+        }
+        sync_divergent.push((&labels[..], peer.divergent as f64));
+        sync_failures.push((&labels[..], peer.failures as f64));
+        sync_imported.push((&labels[..], peer.imported as f64));
+    }
+    let voter_labels = Voter::ALL.map(|voter| [("detector", voter.as_str())]);
+    let dissents: Vec<(&[(&str, &str)], f64)> = voter_labels
+        .iter()
+        .zip(&d.ensemble.dissents)
+        .map(|(labels, n)| (&labels[..], n.load(Ordering::Relaxed) as f64))
+        .collect();
+    let timer_labels = TimerKind::ALL.map(|kind| [("kind", kind.as_str())]);
+    let timers_pending: Vec<(&[(&str, &str)], f64)> = timer_labels
+        .iter()
+        .zip(TimerKind::ALL)
+        .map(|(labels, kind)| (&labels[..], state.timers.pending(kind) as f64))
+        .collect();
+    let timers_fired: Vec<(&[(&str, &str)], f64)> = timer_labels
+        .iter()
+        .zip(TimerKind::ALL)
+        .map(|(labels, kind)| (&labels[..], state.timers.fired(kind) as f64))
+        .collect();
+    let sampled: Vec<(&str, u64, u64)> = state.sampler.counts().collect();
+    let sampled_labels: Vec<[[(&str, &str); 2]; 2]> = sampled
+        .iter()
+        .map(|(event, ..)| [[("event", *event), ("outcome", "kept")], [("event", *event), ("outcome", "sampled_out")]])
+        .collect();
+    let log_events: Vec<(&[(&str, &str)], f64)> = sampled_labels
+        .iter()
+        .zip(&sampled)
+        .flat_map(|([kept_labels, out_labels], (_, kept, out))| {
+            [(&kept_labels[..], *kept as f64), (&out_labels[..], *out as f64)]
+        })
+        .collect();
+    let stage_labels = Stage::ALL.map(|stage| [("stage", stage.as_str())]);
+    let stage_latency: Vec<(&[(&str, &str)], &Histogram)> = stage_labels
+        .iter()
+        .zip(Stage::ALL)
+        .map(|(labels, stage)| (&labels[..], state.latency.histogram(stage)))
+        .collect();
+    let mut w = if openmetrics { PromWriter::openmetrics() } else { PromWriter::new() };
+    w.counter("english_messages_total", "English messages accepted", m.english_messages.load(Ordering::Relaxed))
+        .counter("novel_messages_total", "Novel-language messages accepted", m.novel_messages.load(Ordering::Relaxed))
+        .traced_counter(
+            "rejected_messages_total",
+            "Messages rejected by policy",
+            m.rejected_messages.load(Ordering::Relaxed),
+            m.rejected_exemplar.get(),
+        )
+        .counter("reports_submitted_total", "English reports accepted", m.reports_submitted.load(Ordering::Relaxed))
+        .counter("reports_held_for_review_total", "Reports held for review on low consistency", m.reports_held.load(Ordering::Relaxed))
+        .counter("protocols_suspended_total", "Agent protocols suspended after repeated held reports", m.protocols_suspended.load(Ordering::Relaxed))
+        .counter("quarantined_messages_total", "Encrypted messages quarantined for review", m.quarantined_messages.load(Ordering::Relaxed))
+        .counter("compliance_violations_total", "Compliance violations recorded", m.violations.load(Ordering::Relaxed))
+        .counter("slo_burn_alerts_total", "SLO burn-rate alerts raised", m.slo_burn_alerts.load(Ordering::Relaxed))
+        .counter("sends_waived_total", "Sends accepted despite a compliance refusal in audit-only mode", m.sends_waived.load(Ordering::Relaxed))
+        .counter("retries_offered_total", "Retry tokens issued for sends refused for a barely overdue report", m.retries_offered.load(Ordering::Relaxed))
+        .counter("retries_redeemed_total", "Retry tokens redeemed by resubmitted sends", m.retries_redeemed.load(Ordering::Relaxed))
+        .counter("internal_errors_total", "Sends and reports the gateway failed to decide", m.internal_errors.load(Ordering::Relaxed))
+        .counter("degraded_accepts_total", "Sends and reports accepted undecided by a fail-open degradation rule", m.degraded_accepts.load(Ordering::Relaxed))
+        .counter("quota_rejections_total", "Requests refused for exceeding a storage quota", state.quota.rejections.load(Ordering::Relaxed))
+        .counter("audit_events_suppressed_total", "Audit events not stored because of a quota", state.quota.suppressed_events.load(Ordering::Relaxed))
+        .labelled(
+            "audit_schema_violations_total",
+            "Audit events that did not conform to their kind's schema, by kind",
+            "counter",
+            &schema_violations,
+        )
+        .counter("signing_key_rotations_total", "Receipt signing key rotations", state.signer.rotations.load(Ordering::Relaxed))
+        .counter("tls_reloads_total", "TLS certificates reloaded from disk", state.tls_reloads.reloads.load(Ordering::Relaxed))
+        .counter("tls_reload_failures_total", "TLS certificate reloads that failed", state.tls_reloads.failures.load(Ordering::Relaxed))
+        .labelled(
+            "mirrored_requests_total",
+            "Requests copied to the shadow gateway by outcome",
+            "counter",
+            &[
+                (&[("outcome", "sent")], mirrored.sent.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "failed")], mirrored.failed.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "dropped")], mirrored.dropped.load(Ordering::Relaxed) as f64),
+            ],
+        )
+        .gauge("parked_sends", "Messages parked until their sender's next report", state.parking.pending() as f64)
+        .labelled(
+            "parked_sends_resolved_total",
+            "Parked messages by outcome",
+            "counter",
+            &[
+                (&[("outcome", "delivered")], park.delivered.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "refused")], park.refused.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "expired")], park.expired.load(Ordering::Relaxed) as f64),
+            ],
+        )
+        .counter("park_callbacks_failed_total", "Parked-message callback attempts that failed", park.callbacks_failed.load(Ordering::Relaxed))
+        .gauge("outbox_pending", "Callbacks in the outbox awaiting delivery", state.outbox.pending() as f64)
+        .counter("outbox_delivered_total", "Outbox deliveries made", state.outbox.delivered.load(Ordering::Relaxed))
+        .counter("outbox_abandoned_total", "Outbox deliveries given up after their last attempt", state.outbox.abandoned.load(Ordering::Relaxed))
+        .gauge("state_store_queue_depth", "Records queued for the state store", state.store.depth() as f64)
+        .counter("state_store_writes_shed_total", "Records dropped because the state store queue was full", state.store.shed.load(Ordering::Relaxed))
+        .gauge("send_reservations_pending", "Two-phase sends awaiting commit or abort", state.reservations.pending() as f64)
+        .labelled(
+            "send_reservations_resolved_total",
+            "Two-phase sends by outcome",
+            "counter",
+            &[
+                (&[("outcome", "committed")], state.reservations.committed.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "aborted")], state.reservations.aborted.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "expired")], state.reservations.expired.load(Ordering::Relaxed) as f64),
+            ],
+        )
+        .gauge("deliveries_pending", "Recipients of allowed sends yet to confirm delivery", state.deliveries.pending() as f64)
+        .labelled(
+            "delivery_receipts_total",
+            "Recipients of allowed sends by delivery outcome; late receipts were first counted undelivered",
+            "counter",
+            &[
+                (&[("outcome", "delivered")], delivery.delivered.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "late")], delivery.late.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "undelivered")], delivery.undelivered.load(Ordering::Relaxed) as f64),
+            ],
+        )
+        .labelled(
+            "decision_webhook_calls_total",
+            "Decision webhook calls by outcome",
+            "counter",
+            &[
+                (&[("outcome", "allow")], hooks.allowed.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "deny")], hooks.denied.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "failed_open")], hooks.failed_open.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "failed_closed")], hooks.failed_closed.load(Ordering::Relaxed) as f64),
+            ],
+        )
+        .labelled(
+            "discovery_syncs_total",
+            "Service registry syncs by outcome",
+            "counter",
+            &[
+                (&[("outcome", "ok")], disc.syncs_ok.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "error")], disc.syncs_error.load(Ordering::Relaxed) as f64),
+            ],
+        )
+        .gauge("discovered_agents", "Agents listed by the last registry sync", disc.agents.load(Ordering::Relaxed) as f64)
+        .histogram("send_stage_duration_seconds", "Send pipeline latency by stage", &stage_latency)
+        .labelled("timers_pending", "Deadlines waiting on the timer wheel by kind", "gauge", &timers_pending)
+        .labelled("timers_fired_total", "Deadlines reached by kind", "counter", &timers_fired)
+        .labelled("log_events_total", "Events of each LOG_SAMPLING kind kept and sampled out", "counter", &log_events)
+        .counter("clock_skew_events_total", "Wall-clock jumps detected", state.clock_skew.events.load(Ordering::Relaxed))
+        .gauge("clock_skew_seconds", "Wall clock minus the gateway's monotonic timeline", state.clock_skew.offset_sec())
+        .labelled(
+            "detector_ensemble_dissents_total",
+            "Ensemble votes against the final language verdict by detector",
+            "counter",
+            &dissents,
+        )
+        .gauge(
+            "detector_breaker_state",
+            "Classifier circuit breaker state (0=closed, 1=open, 2=half_open)",
+            state.detector.breaker_state().as_gauge() as f64,
+        )
+        .labelled(
+            "detector_calls_total",
+            "Classifier calls by outcome",
+            "counter",
+            &[
+                (&[("outcome", "ok")], d.calls_ok.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "error")], d.calls_error.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "timeout")], d.calls_timeout.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "short_circuited")], d.short_circuited.load(Ordering::Relaxed) as f64),
+            ],
+        )
+        .counter("detector_breaker_trips_total", "Times the classifier breaker opened", d.breaker_trips.load(Ordering::Relaxed))
+        .labelled(
+            "benign_pattern_matches_total",
+            "Messages classified as benign machine output, by allowlist pattern",
+            "counter",
+            &pattern_matches,
+        )
+        .counter("decision_cache_hits_total", "Sender-side decisions served from cache", c.hits.load(Ordering::Relaxed))
+        .counter("decision_cache_misses_total", "Sender-side decisions evaluated in full", c.misses.load(Ordering::Relaxed))
+        .gauge("decision_cache_entries", "Decisions currently cached", c.len() as f64)
+        .gauge("decision_cache_capacity", "Maximum cached decisions", c.capacity() as f64)
+        .counter(
+            "negative_cache_hits_total",
+            "Unregistered-protocol sends refused from the negative cache",
+            state.registration_misses.hits.load(Ordering::Relaxed),
+        )
+        .gauge(
+            "negative_cache_entries",
+            "Registration misses currently cached",
+            state.registration_misses.len() as f64,
+        )
+        .gauge("send_streams_open", "Open WebSocket send streams", state.streams.open() as f64)
+        .labelled(
+            "registry_sync_lag_seconds",
+            "Age of the last registry snapshot applied from each peer",
+            "gauge",
+            &sync_lag,
+        )
+        .labelled(
+            "registry_sync_divergent_entries",
+            "Registry entries that differed from each peer at the last sync",
+            "gauge",
+            &sync_divergent,
+        )
+        .labelled("registry_sync_failures_total", "Failed registry pulls by peer", "counter", &sync_failures)
+        .labelled(
+            "registry_sync_imported_total",
+            "Protocol registrations imported by peer",
+            "counter",
+            &sync_imported,
+        )
+        .gauge(
+            "verbose_content_logging",
+            "Whether diagnostic logs include message content (1) or scrub it (0)",
+            f64::from(u8::from(state.scrubber.verbose(now_unix_sec()))),
+        )
+        .labelled(
+            "slow_requests_total",
+            "Requests slower than SLOW_REQUEST_MS, by route group",
+            "counter",
+            &slow_requests,
+        )
+        .labelled(
+            "request_timeouts_total",
+            "Requests answered 504 after their route's timeout, by route group",
+            "counter",
+            &request_timeouts,
+        )
+        .labelled(
+            "ip_requests_refused_total",
+            "Requests refused by an admin rule on the client IP, by action",
+            "counter",
+            &[
+                (&[("action", "block")], state.ips.counters.blocked.load(Ordering::Relaxed) as f64),
+                (&[("action", "throttle")], state.ips.counters.throttled.load(Ordering::Relaxed) as f64),
+            ],
+        )
+        .gauge("client_ips_tracked", "Client IPs with request counts held", state.ips.tracked() as f64)
+        .labelled(
+            "translation_calls_total",
+            "Translation service calls by outcome",
+            "counter",
+            &[
+                (&[("outcome", "ok")], t.calls_ok.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "error")], t.calls_error.load(Ordering::Relaxed) as f64),
+                (&[("outcome", "dropped")], t.dropped.load(Ordering::Relaxed) as f64),
+            ],
+        );
+    let content_type = if openmetrics {
+        "application/openmetrics-text; version=1.0.0; charset=utf-8"
+    } else {
+        "text/plain; version=0.0.4"
+    };
+    ([(header::CONTENT_TYPE, content_type)], w.finish())
+}
+
+/// Register a protocol for an agent
+async fn register_protocol_for_agent(
+    State(state): State<AppState>,
+    Payload(req): Payload<RegisterProtocolRequest>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    register_protocol(&state, req, false)
+}
+
+/// Register or revise a protocol; `approved` revisions skip the admin
+/// approval guarded fields otherwise wait for
+fn register_protocol(
+    state: &AppState,
+    mut req: RegisterProtocolRequest,
+    approved: bool,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let key = protocol_key(&req.protocol.name, &req.protocol.version);
+    let report_key = format!("{}::{}", req.agent_id, key);
+
+    check_quota(state, &req.agent_id, Resource::Events, 1)?;
+    if let Some(Err(e)) = req.protocol.encryption.as_ref().map(EncryptionMetadata::validate) {
+        warn!(
+            agent_id = %req.agent_id,
+            protocol = %key,
+            event = "registration_rejected",
+            reason = "invalid_encryption_metadata",
+            error = %e,
+            "Registration rejected: invalid encryption metadata"
+        );
+        return Err(GatewayError::Invalid(e));
+    }
+    if let Err(e) = docs::seal(&mut req.protocol.docs) {
+        warn!(
+            agent_id = %req.agent_id,
+            protocol = %key,
+            event = "registration_rejected",
+            reason = "invalid_docs",
+            error = %e,
+            "Registration rejected: invalid documentation artifacts"
+        );
+        return Err(GatewayError::Invalid(e));
+    }
+    if let Err(e) = validate_scope(&req.protocol.allowed_recipients) {
+        warn!(
+            agent_id = %req.agent_id,
+            protocol = %key,
+            event = "registration_rejected",
+            reason = "invalid_scope",
+            error = %e,
+            "Registration rejected: invalid recipient allowlist"
+        );
+        return Err(GatewayError::Invalid(e));
+    }
+    let digests: Vec<DocDigest> = req.protocol.docs.iter().map(DocArtifact::digest).collect();
+    let docs = serde_json::to_string(&digests).unwrap_or_default();
+
+    let mut st = state.inner.write().unwrap();
+
+    if st.is_deleted(&req.agent_id) {
+        warn!(
+            agent_id = %req.agent_id,
+            protocol = %key,
+            event = "registration_rejected",
+            reason = "agent_deleted",
+            "Registration rejected: agent deleted"
+        );
+        return Err(GatewayError::AgentDeleted { action: "before registering protocols" });
+    }
+
+    if let Err(owner) = state.namespace.check(&st, &req.agent_id, &key, &req.protocol) {
+        let owner = owner.to_string();
+        state.namespace.refuse(&key, &req.agent_id);
+        warn!(
+            agent_id = %req.agent_id,
+            protocol = %key,
+            owner = %owner,
+            event = "registration_rejected",
+            reason = "protocol_name_taken",
+            "Registration rejected: protocol defined by another agent"
+        );
+        return Err(GatewayError::ProtocolNameTaken { protocol: key, owner });
+    }
+
+    let policy = state.policy.current();
+    if req.trial && policy.policy.trial.is_none() {
+        warn!(
+            agent_id = %req.agent_id,
+            protocol = %key,
+            event = "registration_rejected",
+            reason = "trials_disabled",
+            "Registration rejected: trial requested but the policy has no trials"
+        );
+        return Err(GatewayError::Invalid("Protocol trials are not enabled by the policy".to_string()));
+    }
+
+    let reputation = st.reputation(&policy.policy, &req.agent_id);
+    if reputation.codebook_required && req.protocol.codebook.is_empty() {
+        warn!(
+            agent_id = %req.agent_id,
+            protocol = %key,
+            event = "registration_rejected",
+            reason = "codebook_required",
+            tier = ?reputation.tier,
+            "Registration rejected: agent on probation registered no codebook"
+        );
+        return Err(GatewayError::CodebookRequired);
+    }
+
+    // Diff a re-registration against the descriptor in force
+    let changes = st
+        .protocols
+        .get(req.agent_id.as_str())
+        .and_then(|protocols| protocols.get(&key))
+        .map(|previous| revision::diff(previous, &req.protocol))
+        .unwrap_or_default();
+    let guarded = state.change_control.guarded(&changes);
+    if !approved && !guarded.is_empty() {
+        let action = AdminAction::ChangeProtocolDescriptor {
+            agent_id: req.agent_id.clone(),
+            descriptor: Box::new(req.protocol),
+            changes: changes.clone().into(),
+        };
+        let proposed_by = format!("agent:{}", req.agent_id);
+        let Some(proposal) = state.approvals.propose(action, &proposed_by, state.clock.now()) else {
+            return Err(GatewayError::Conflict("The same descriptor change is already awaiting approval"));
+        };
+        info!(
+            agent_id = %req.agent_id,
+            protocol = %key,
+            approval_id = proposal.id,
+            fields = %guarded.join(","),
+            changes = %serde_json::to_string(&changes).unwrap_or_default(),
+            expires_at = proposal.expires_at,
+            event = "descriptor_change_proposed",
+            "Descriptor change awaiting admin approval"
+        );
+        let body = ApiResponse {
+            changes,
+            approval_id: Some(proposal.id),
+            ..ApiResponse::success_with_message("Descriptor change awaiting admin approval")
+        };
+        return Ok((StatusCode::ACCEPTED, Json(body)));
+    }
+
+    // Carry the report clock over from compatible earlier versions
+    if let (Some(requirement), HistoryPolicy::Inherit) =
+        (req.protocol.compatible_with.as_deref(), req.protocol.history)
+    {
+        let inherited = versioning::inherited_report_ts(
+            &st,
+            &req.agent_id,
+            &req.protocol.name,
+            &req.protocol.version,
+            requirement,
+        );
+        if let Some(ts) = inherited {
+            let clock = st.last_report_ts.entry(report_key.clone()).or_insert(0);
+            *clock = (*clock).max(ts);
+            let clock = *clock;
+            schedule_report_deadline(state, &st, &report_key, clock);
+            info!(
+                agent_id = %req.agent_id,
+                protocol = %key,
+                compatible_with = %requirement,
+                event = "report_clock_inherited",
+                "Report clock inherited from compatible version"
+            );
+        }
+    }
+
+    let first = !st.protocol_stats.contains_key(&report_key);
+    let (registered_at, recertified_at) = st
+        .protocol_stats
+        .get(&report_key)
+        .map_or((state.clock.now(), None), |s| (s.registered_at, s.recertified_at));
+    let mutation = Mutation::ProtocolRegistered {
+        agent_id: req.agent_id.to_string(),
+        descriptor: Box::new(req.protocol),
+        registered_at,
+        report_clock: st.last_report_ts.get(&report_key).copied(),
+        recertified_at,
+    };
+    mutation.clone().apply(&mut st);
+    state.replication.record(mutation);
+    schedule_recertification(state, &st, &report_key);
+    if let (true, true, Some(trial_policy)) = (req.trial, first, &policy.policy.trial) {
+        let mutation = Mutation::ProtocolTrial {
+            report_key: report_key.clone(),
+            trial: Some(Trial { started_at: registered_at }),
+        };
+        mutation.clone().apply(&mut st);
+        state.replication.record(mutation);
+        let ends_at = trial_policy.ends_at(registered_at);
+        if let Some(at) = ends_at {
+            state.timers.schedule(TimerKind::TrialEnd, &report_key, at);
+        }
+        info!(
+            agent_id = %req.agent_id,
+            protocol = %key,
+            ends_at,
+            max_messages = trial_policy.max_messages,
+            min_coverage = trial_policy.min_coverage,
+            min_summary_length = trial_policy.min_summary_length,
+            trial = true,
+            event = "protocol_trial_started",
+            "Protocol registered on trial"
+        );
+    }
+    state.registry_sync.touch(&report_key, state.clock.now());
+
+    drop(st);
+    state.decision_cache.invalidate_agent(&req.agent_id);
+    state.registration_misses.invalidate(&report_key);
+
+    info!(
+        agent_id = %req.agent_id,
+        protocol = %key,
+        docs = %docs,
+        event = "protocol_registered",
+        "Protocol registered"
+    );
+    if !changes.is_empty() {
+        info!(
+            agent_id = %req.agent_id,
+            protocol = %key,
+            fields = %changes.iter().map(|c| c.field.as_str()).collect::<Vec<_>>().join(","),
+            changes = %serde_json::to_string(&changes).unwrap_or_default(),
+            approved,
+            event = "protocol_descriptor_changed",
+            "Protocol descriptor revised"
+        );
+    }
+
+    Ok((StatusCode::OK, Json(ApiResponse { changes, ..ApiResponse::success() })))
+}
+
+/// Body of `POST /protocols/{owner}/{name}/{version}/adopt`
+#[derive(Debug, Deserialize)]
+struct AdoptProtocolRequest {
+    agent_id: AgentId,
+    #[serde(default)]
+    trial: bool,
+}
+
+/// Register the descriptor `owner` registered for `name:version` for
+/// another agent
+///
+/// In the global namespace `owner` must own the protocol (see [`namespace`]).
+async fn adopt_protocol(
+    State(state): State<AppState>,
+    Path((owner, name, version)): Path<(String, String, String)>,
+    Payload(req): Payload<AdoptProtocolRequest>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let key = protocol_key(&name, &version);
+    let descriptor = {
+        let st = state.inner.read().unwrap();
+        let descriptor = st
+            .protocols
+            .get(&owner)
+            .and_then(|protocols| protocols.get(&key))
+            .cloned()
+            .ok_or(GatewayError::NotFound("Protocol not registered by that agent"))?;
+        if state.namespace.is_global() {
+            if let Some((canonical_owner, _)) = namespace::owner(&st, &key).filter(|(o, _)| *o != owner) {
+                return Err(GatewayError::Invalid(format!(
+                    "{key} is owned by {canonical_owner}: adopt it from its owner"
+                )));
+            }
+        }
+        descriptor
+    };
+    let agent_id = req.agent_id.clone();
+    let registered = register_protocol_for_agent(
+        State(state),
+        Payload(RegisterProtocolRequest {
+            agent_id: req.agent_id,
+            protocol: descriptor,
+            trial: req.trial,
+        }),
+    )
+    .await?;
+    info!(
+        agent_id = %agent_id,
+        protocol = %key,
+        owner = %owner,
+        event = "protocol_adopted",
+        "Protocol descriptor adopted from its owner"
+    );
+    Ok(registered)
+}
+
+/// Protocol names registered with differing descriptors or refused a
+/// redefinition
+async fn protocol_conflicts(
+    State(state): State<AppState>,
+    _: AuthedAuditor,
+) -> Result<Json<Vec<Conflict>>, GatewayError> {
+    let st = state.inner.read().unwrap();
+    Ok(Json(state.namespace.conflicts(&st)))
+}
+
+/// Submit an English translation report
+async fn submit_report(
+    State(state): State<AppState>,
+    Payload(report): Payload<EnglishReport>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let agent_id = report.agent_id.clone();
+    let outcome = submit_report_outcome(state.clone(), report).await;
+    phrase_refusal(&state, &agent_id, outcome)
+}
+
+/// File a report as `POST /report` does, before phrasing any refusal
+async fn submit_report_outcome(
+    state: AppState,
+    report: EnglishReport,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    if let Some(thread_id) = &report.thread_id {
+        threads::validate_id(thread_id).map_err(GatewayError::Invalid)?;
+    }
+    let span = thread_span(report.thread_id.as_deref());
+    let flags = flags_span(&state, &report.agent_id);
+    let trial = trial_span(&state, &report.agent_id, &protocol_key(&report.protocol_name, &report.protocol_version));
+    let agent_id = report.agent_id.clone();
+    let protocol = protocol_key(&report.protocol_name, &report.protocol_version);
+    let filed = file_report(state.clone(), report).instrument(span).instrument(flags).instrument(trial);
+    match degradation::catch_panics(filed).await {
+        Err(GatewayError::Internal(detail)) => degrade(&state, RouteGroup::Reports, &agent_id, Some(&protocol), &[], detail),
+        outcome => outcome,
+    }
+}
+
+/// Validate, score, and accept or hold a report
+async fn file_report(
+    state: AppState,
+    mut report: EnglishReport,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    check_state(&state)?;
+    let key = protocol_key(&report.protocol_name, &report.protocol_version);
+
+    check_quota(&state, &report.agent_id, Resource::Events, 1)?;
+    let drop_content = check_quota(&state, &report.agent_id, Resource::Reports, 1)?;
+
+    // Validate protocol registration and resolve version compatibility
+    let key = {
+        let st = state.inner.read().unwrap();
+        if st.is_deleted(&report.agent_id) {
+            warn!(
+                agent_id = %report.agent_id,
+                protocol = %key,
+                event = "report_rejected",
+                reason = "agent_deleted",
+                "Report rejected: agent deleted"
+            );
+            return Err(GatewayError::AgentDeleted { action: "before submitting reports" });
+        }
+
+        let registered = st
+            .protocols
+            .get(report.agent_id.as_str())
+            .and_then(|m| m.get(&key))
+            .is_some();
+
+        if !registered {
+            warn!(
+                agent_id = %report.agent_id,
+                protocol = %key,
+                event = "report_rejected",
+                reason = "protocol_not_registered",
+                "Report rejected: protocol not registered"
+            );
+            return Err(GatewayError::NotRegistered);
+        }
+
+        match versioning::resolve(&st, &report.agent_id, &report.protocol_name, &report.protocol_version) {
+            Resolution::Use { key, .. } => key,
+            Resolution::Superseded { successor } => {
+                warn!(
+                    agent_id = %report.agent_id,
+                    protocol = %key,
+                    successor = %successor,
+                    event = "report_rejected",
+                    reason = "protocol_superseded",
+                    "Report rejected: protocol version superseded"
+                );
+                return Err(GatewayError::Superseded { successor });
+            }
+        }
+    };
+    let report_key = format!("{}::{}", report.agent_id, key);
+    let snapshot = state.policy.current();
+    let policy = &snapshot.policy;
+    let trial = on_trial(&state, &report.agent_id, &key);
+    let (min_coverage, min_summary_length) = match (trial, &policy.trial) {
+        (true, Some(trial)) => (trial.min_coverage, trial.min_summary_length),
+        _ => (policy.min_coverage, policy.min_summary_length),
+    };
+
+    // Evaluate any candidate policy on the same report, without enforcing it
+    let summary_length = report.english_summary.trim().chars().count();
+    let experiment = &state.experiment;
+    experiment.compare(&state, Rule::Coverage, report.coverage, &report.agent_id, &key, trial);
+    experiment.compare(&state, Rule::SummaryLength, summary_length as f64, &report.agent_id, &key, trial);
+
+    // Validate coverage threshold
+    if report.coverage < min_coverage {
+        warn!(
+            agent_id = %report.agent_id,
+            protocol = %key,
+            event = "report_rejected",
+            reason = "coverage_low",
+            coverage = report.coverage,
+            "Report rejected: coverage below minimum"
+        );
+        return Err(GatewayError::CoverageLow {
+            actual: report.coverage,
+            required: min_coverage,
+        });
+    }
+
+    // Validate summary length
+    if summary_length < min_summary_length {
+        warn!(
+            agent_id = %report.agent_id,
+            protocol = %key,
+            event = "report_rejected",
+            reason = "summary_too_short",
+            "Report rejected: English summary too short"
+        );
+        return Err(GatewayError::SummaryTooShort {
+            required: min_summary_length,
+        });
+    }
+
+    // Validate summary quality against the minimum for the protocol's tier
+    let quality = Quality::of(&report.english_summary);
+    let min_quality = match &policy.summary_quality {
+        Some(q) if !trial => {
+            let st = state.inner.read().unwrap();
+            let team = st.owners.get(report.agent_id.as_str()).map(String::as_str);
+            match state.flags.gates(flags::SUMMARY_QUALITY, &report.agent_id, team) {
+                true => q.minimum(st.risk_tier(&report_key).unwrap_or(RiskTier::Low)),
+                false => None,
+            }
+        }
+        _ => None,
+    };
+    if let Some(required) = min_quality.filter(|&required| quality.score < required) {
+        warn!(
+            agent_id = %report.agent_id,
+            protocol = %key,
+            event = "report_rejected",
+            reason = "summary_quality_low",
+            score = quality.score,
+            required,
+            type_token_ratio = quality.type_token_ratio,
+            stopword_ratio = quality.stopword_ratio,
+            mean_sentence_words = quality.mean_sentence_words,
+            "Report rejected: English summary quality below minimum"
+        );
+        return Err(GatewayError::SummaryQualityLow {
+            score: quality.score,
+            required,
+        });
+    }
+
+    // Validate the summary is written in the language of record
+    if let Some(record) = language_of_record(&state, &report.agent_id) {
+        let verdict = record.classify(&state.detector, &report.english_summary).await;
+        match verdict.is_english {
+            Some(true) => {}
+            Some(false) => {
+                warn!(
+                    agent_id = %report.agent_id,
+                    protocol = %key,
+                    event = "report_rejected",
+                    reason = "summary_language",
+                    expected = %record.names(),
+                    source = %verdict.source,
+                    "Report rejected: summary not in the language of record"
+                );
+                return Err(GatewayError::SummaryLanguage { expected: record.names() });
+            }
+            None => {
+                warn!(
+                    agent_id = %report.agent_id,
+                    protocol = %key,
+                    event = "report_rejected",
+                    reason = "detector_unavailable",
+                    "Language detector unavailable"
+                );
+                return Err(GatewayError::DetectorUnavailable);
+            }
+        }
+    }
+
+    // Score the summary against machine glosses of the reported window
+    let glosses = state
+        .translator
+        .glosses_between(&report_key, report.window_start_ts, report.window_end_ts);
+    let honesty = translation::honesty_score(
+        &report.english_summary,
+        glosses.iter().map(|g| g.gloss.as_str()),
+    );
+    if let Some(score) = honesty {
+        info!(
+            agent_id = %report.agent_id,
+            protocol = %key,
+            event = "report_honesty_scored",
+            score,
+            glosses = glosses.len(),
+            "Report compared with machine glosses"
+        );
+    }
+
+    // Check the summary against the traffic it claims to cover, decoding a
+    // sample of the messages it lists, and gloss or redact the protocol
+    // tokens it quotes
+    let (consistency, checks, redaction) = {
+        let st = state.inner.read().unwrap();
+        let claim = ReportClaim {
+            summary: &report.english_summary,
+            message_count: report.message_ids.len(),
+            window_start: report.window_start_ts,
+            window_end: report.window_end_ts,
+            since: st.last_report_ts.get(&report_key).copied().unwrap_or(0) as f64,
+        };
+        let empty = BTreeMap::new();
+        let codebook = st
+            .protocols
+            .get(report.agent_id.as_str())
+            .and_then(|m| m.get(&key))
+            .map_or(&empty, |p| &p.codebook);
+        let traffic: Vec<&TrafficSample> = st
+            .traffic
+            .get(&report_key)
+            .into_iter()
+            .flatten()
+            .filter(|s| report.thread_id.is_none() || s.thread_id == report.thread_id)
+            .collect();
+        let checks = consistency::verify_sample(
+            &report.english_summary,
+            &report.message_ids,
+            traffic.iter().copied(),
+            codebook,
+            policy.report_sample_size,
+            consistency::random_seed(),
+        );
+        let tokens = st.traffic.get(&report_key).into_iter().flatten().flat_map(|s| &s.tokens);
+        let redaction = redaction::redact(&report.english_summary, codebook, tokens.map(String::as_str));
+        (consistency::score(&claim, traffic.iter().copied(), codebook), checks, redaction)
+    };
+    if let Some(redaction) = &redaction {
+        info!(
+            agent_id = %report.agent_id,
+            protocol = %key,
+            event = "report_redacted",
+            glossed = redaction.glossed,
+            redacted = redaction.redacted,
+            "Protocol tokens quoted in the summary glossed or redacted"
+        );
+    }
+    let redacted_summary = redaction.map(|r| r.summary);
+    if !checks.is_empty() {
+        let failed: Vec<&str> = checks
+            .iter()
+            .filter(|c| !c.passed())
+            .map(|c| c.message_id.as_str())
+            .collect();
+        info!(
+            agent_id = %report.agent_id,
+            protocol = %key,
+            event = "report_sample_checked",
+            sampled = checks.len(),
+            failed = %failed.join(","),
+            "Sampled reported messages decoded and compared with the summary"
+        );
+    }
+    let consistency = consistency.with_samples(&checks);
+    info!(
+        agent_id = %report.agent_id,
+        protocol = %key,
+        event = "report_consistency_scored",
+        score = consistency.score,
+        count = consistency.count,
+        timing = consistency.timing,
+        codebook = ?consistency.codebook,
+        sampled = ?consistency.sampled,
+        "Report compared with observed traffic"
+    );
+
+    experiment.compare(&state, Rule::Consistency, consistency.score, &report.agent_id, &key, trial);
+
+    // Low-consistency reports wait for a reviewer instead of being accepted
+    if consistency.score < policy.min_consistency {
+        let score = consistency.score;
+        let agent_id = report.agent_id.clone();
+        let mut redacted_summary = redacted_summary;
+        if drop_content {
+            report.english_summary = dropped_content(&report.english_summary);
+            report.notes = report.notes.as_deref().map(dropped_content);
+            redacted_summary = redacted_summary.as_deref().map(dropped_content);
+        }
+        state.quota.record(&report.agent_id, Resource::Reports, 1);
+        let (id, standing, suspended) = {
+            let mut st = state.inner.write().unwrap();
+            let standing = &mut st.protocol_stats.entry(report_key.clone()).or_default().standing;
+            let suspended = standing.strike(policy.report_strike_limit, state.clock.now());
+            let standing = standing.clone();
+            state.replication.record(Mutation::ProtocolStanding {
+                report_key: report_key.clone(),
+                standing: standing.clone(),
+            });
+            st.next_review_id += 1;
+            let id = st.next_review_id;
+            st.reviews.insert(
+                id,
+                PendingReview {
+                    id,
+                    protocol: key.clone(),
+                    submitted_at: state.clock.now(),
+                    consistency,
+                    honesty,
+                    report,
+                    redacted_summary,
+                },
+            );
+            (id, standing, suspended)
+        };
+        Metrics::inc(&state.metrics.reports_held);
+        warn!(
+            agent_id = %agent_id,
+            protocol = %key,
+            review_id = id,
+            event = "report_strike_recorded",
+            strikes = standing.strikes,
+            limit = policy.report_strike_limit,
+            "Held report counted as a strike against the protocol"
+        );
+        if suspended {
+            suspend_protocol(&state, &agent_id, &key, &standing);
+        }
+        let mut body = ApiResponse::success_with_message(&format!(
+            "Report held for review (id {id}): consistency {score:.2} below minimum {:.2}",
+            policy.min_consistency
+        ));
+        body.advisories = soft_limit_advisories(&state, policy, &agent_id, Some(&key));
+        return Ok((StatusCode::ACCEPTED, Json(body)));
+    }
+
+    accept_report(&state, &report, redacted_summary, &key, honesty, state.clock.now());
+    state.quota.record(&report.agent_id, Resource::Reports, 1);
+    let mut body = ApiResponse::success();
+    body.receipt = Some(state.signer.sign(&ReceiptClaims {
+        iss: RECEIPT_ISSUER,
+        kind: "report",
+        sub: &report.agent_id,
+        protocol: Some(&key),
+        recipients: Vec::new(),
+        content_sha256: Some(signing::content_digest(&report.english_summary)),
+        attachments_sha256: Vec::new(),
+        message_count: Some(report.message_ids.len()),
+        policy_version: &snapshot.version,
+        iat: state.clock.now(),
+    }));
+    body.advisories = soft_limit_advisories(&state, policy, &report.agent_id, Some(&key));
+    Ok((StatusCode::OK, Json(body)))
+}
+
+/// Record an accepted report: reset the report clock and update stats
+///
+/// `redacted_summary` is the summary with its quoted protocol tokens
+/// redacted, if it quotes any. `filed_at` is when the report was submitted,
+/// which decides whether it counts as on time even if a reviewer accepted it
+/// later.
+fn accept_report(
+    state: &AppState,
+    report: &EnglishReport,
+    redacted_summary: Option<String>,
+    key: &str,
+    honesty: Option<f64>,
+    filed_at: u64,
+) {
+    let report_key = format!("{}::{}", report.agent_id, key);
+    let quality = Quality::of(&report.english_summary);
+    {
+        let mut st = state.inner.write().unwrap();
+        let policy = state.policy.current();
+        let interval = st.reputation(&policy.policy, &report.agent_id).report_interval_sec;
+        let interval = st.report_interval(&policy.policy, &report_key, interval, state.clock.now());
+        let previous = st.last_report_ts.insert(report_key.clone(), state.clock.now());
+        let late = previous.is_some_and(|last| last > 0 && filed_at.saturating_sub(last) > interval);
+        let record = st.update_track_record(&report.agent_id, |r| {
+            if late {
+                r.reports_late += 1;
+            } else {
+                r.reports_on_time += 1;
+            }
+        });
+        state.replication.record(record);
+        schedule_report_deadline(state, &st, &report_key, state.clock.now());
+        state.slo.record_report(&report_key, state.clock.now());
+        state.replication.record(Mutation::ReportAccepted {
+            report_key: report_key.clone(),
+            ts: state.clock.now(),
+        });
+        let stats = st.protocol_stats.entry(report_key.clone()).or_default();
+        if stats.standing.clear_strikes() {
+            state.replication.record(Mutation::ProtocolStanding {
+                report_key: report_key.clone(),
+                standing: stats.standing.clone(),
+            });
+        }
+        stats.reports_filed += 1;
+        stats.coverage_sum += report.coverage;
+        if let Some(score) = honesty {
+            stats.honesty_scored += 1;
+            stats.honesty_sum += score;
+        }
+        readability::record(
+            entry_mut(&mut st.summary_quality, &report.agent_id),
+            readability::Sample {
+                protocol: key.to_string(),
+                accepted_at: state.clock.now(),
+                quality,
+            },
+        );
+        if let Some(thread_id) = &report.thread_id {
+            st.threads.record(
+                thread_id,
+                ThreadEntry::Report {
+                    ts: state.clock.now_f64(),
+                    agent_id: report.agent_id.to_string(),
+                    protocol: key.to_string(),
+                    english_summary: report.english_summary.clone(),
+                    redacted_summary: redacted_summary.clone(),
+                    coverage: report.coverage,
+                },
+            );
+        }
+    }
+    state.store.write(Record::Report(StoredReport {
+        agent_id: report.agent_id.to_string(),
+        protocol: key.to_string(),
+        accepted_at: state.clock.now(),
+        english_summary: report.english_summary.clone(),
+        redacted_summary,
+        coverage: report.coverage,
+        message_ids: report.message_ids.clone(),
+        thread_id: report.thread_id.clone(),
+        quality: Some(quality),
+    }));
+    state.decision_cache.invalidate_agent(&report.agent_id);
+    Metrics::inc(&state.metrics.reports_submitted);
+
+    info!(
+        agent_id = %report.agent_id,
+        protocol = %key,
+        event = "report_accepted",
+        thread_id = report.thread_id.as_deref(),
+        message_count = report.message_ids.len(),
+        coverage = report.coverage,
+        "Report accepted"
+    );
+
+    if state.parking.has_pending(&report_key) {
+        tokio::spawn(mirror::carry(release_parked(state.clone(), report_key)));
+    }
+}
+
+/// Send a message (gated by compliance checks)
+///
+/// With `park` set, a message refused only for an overdue report is held
+/// until the next accepted report instead (see [`parking`]). With `report`
+/// set, the report is filed first and the send evaluated only once it is
+/// accepted. With `retry_token` set, the send retries a message refused for a
+/// barely overdue report (see [`retry`]).
+async fn send_message(
+    State(state): State<AppState>,
+    Timed(Payload(req), decoded): Timed<Payload<SendMessageRequest>>,
+) -> Response {
+    let from = req.from.clone();
+    let protocol = req.protocol.as_ref().map(|p| protocol_key(&p.name, &p.version));
+    let outcome = decide_send(&state, req, decoded).await;
+    let mut response = phrase_refusal(&state, &from, outcome).into_response();
+    let standing = compliance_headers::for_send(&state, &from, protocol.as_deref());
+    response.headers_mut().extend(standing);
+    response
+}
+
+/// Run one send through the full pipeline, for `/send` and `/send/stream`
+///
+/// `decoded` is how long the request took to read and decode. A send an
+/// internal error kept undecided is answered by the degradation policy.
+async fn decide_send(
+    state: &AppState,
+    req: SendMessageRequest,
+    decoded: Duration,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let from = req.from.clone();
+    let protocol = req.protocol.as_ref().map(|p| protocol_key(&p.name, &p.version));
+    let to: Vec<String> = req.to.list().into_iter().map(str::to_string).collect();
+    match degradation::catch_panics(run_send(state, req, decoded)).await {
+        Err(GatewayError::Internal(detail)) => degrade(state, RouteGroup::Send, &from, protocol.as_deref(), &to, detail),
+        outcome => outcome,
+    }
+}
+
+/// The send pipeline behind [`decide_send`]
+async fn run_send(
+    state: &AppState,
+    mut req: SendMessageRequest,
+    decoded: Duration,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    check_state(state)?;
+    if req.to.is_empty() {
+        return Err(GatewayError::Invalid("`to` must name at least one recipient".to_string()));
+    }
+    let report_receipt = match req.report.take() {
+        Some(report) => match file_inline_report(state, &req.from, report).await? {
+            Ok(receipt) => receipt,
+            Err(held) => return Ok(held),
+        },
+        None => None,
+    };
+    if let Some(token) = req.retry_token.take() {
+        req.retry_of = Some(redeem_retry(state, &req, &token)?);
+    }
+    let from = req.from.clone();
+    let mut timing = PipelineTiming::start(decoded);
+    let pipeline = latency::pipeline_span();
+    let mut retry = None;
+    let outcome = async {
+        if let Some(url) = &req.callback_url {
+            state.parking.check_callback(url).map_err(GatewayError::Invalid)?;
+        }
+        if let Some(thread_id) = &req.thread_id {
+            threads::validate_id(thread_id).map_err(GatewayError::Invalid)?;
+        }
+        let span = thread_span(req.thread_id.as_deref());
+        let flags = flags_span(state, &req.from);
+        let trial = match &req.protocol {
+            Some(p) => trial_span(state, &req.from, &protocol_key(&p.name, &p.version)),
+            None => Span::none(),
+        };
+        let retried = match &req.retry_of {
+            Some(attempt_id) => tracing::info_span!("retry", retry_of = %attempt_id),
+            None => Span::none(),
+        };
+        async {
+            match deliver_send(state, &req, &mut timing).await {
+                Err(overdue @ GatewayError::ReportOverdue { .. }) if req.park && state.parking.enabled() => {
+                    park_send(state, req, overdue)
+                }
+                Err(overdue @ GatewayError::ReportOverdue { .. }) if req.retry_of.is_none() => {
+                    retry = offer_retry(state, &req);
+                    Err(overdue)
+                }
+                outcome => outcome,
+            }
+        }
+        .instrument(span)
+        .instrument(flags)
+        .instrument(trial)
+        .instrument(retried)
+        .await
+    }
+    .instrument(pipeline.clone())
+    .await;
+    state.latency.finish(timing, outcome.is_err(), &pipeline);
+    let outcome = match outcome {
+        Err(refusal) => audit_only_waiver(state, &from, &refusal).ok_or(refusal),
+        outcome => outcome,
+    };
+    let outcome = match (outcome, retry) {
+        (Err(overdue @ GatewayError::ReportOverdue { .. }), Some(ticket)) => Ok(refuse_with_retry(state, &from, overdue, ticket)),
+        (outcome, _) => outcome,
+    };
+    outcome.map(|(code, Json(mut body))| {
+        body.report_receipt = report_receipt;
+        (code, Json(body))
+    })
+}
+
+/// Refuse to decide on state a panic left inconsistent
+fn check_state(state: &AppState) -> Result<(), GatewayError> {
+    if state.inner.is_poisoned() {
+        return Err(GatewayError::Internal("gateway state lock poisoned".to_string()));
+    }
+    Ok(())
+}
+
+/// Answer a send to `to`, or a report, that an internal error kept
+/// undecided, as `DEGRADATION_POLICY` says for the agent's tenant and the
+/// protocol's effective risk tier
+fn degrade(
+    state: &AppState,
+    route: RouteGroup,
+    agent_id: &str,
+    protocol: Option<&str>,
+    to: &[String],
+    detail: String,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let (tenant, risk_tier) = {
+        // Best effort: the lock may be the very thing that failed
+        let st = state.inner.read().unwrap_or_else(PoisonError::into_inner);
+        let risk_tier = protocol.and_then(|p| {
+            let declared = st.protocols.get(agent_id)?.get(p)?;
+            let risk = st.protocol_stats.get(&format!("{agent_id}::{p}")).map(|s| &s.risk);
+            Some(risk.map_or_else(|| declared.risk_tier.clone(), |r| r.effective(&declared.risk_tier)))
+        });
+        (st.owners.get(agent_id).cloned(), risk_tier)
+    };
+    let tenant = tenant.as_deref().unwrap_or(slo::UNASSIGNED_TENANT);
+    let on_error = state.degradation.on_error(route, tenant, risk_tier.as_deref());
+    Metrics::inc(&state.metrics.internal_errors);
+    error!(
+        agent_id = %agent_id,
+        route = route.as_str(),
+        protocol = protocol,
+        error = %detail,
+        fallback = if on_error == FailureMode::Allow { "allow" } else { "deny" },
+        event = "internal_error",
+        "Internal error while deciding a request"
+    );
+    if on_error == FailureMode::Deny {
+        return Err(GatewayError::Internal(detail));
+    }
+    Metrics::inc(&state.metrics.degraded_accepts);
+    match route {
+        RouteGroup::Reports => warn!(
+            agent_id = %agent_id,
+            protocol = protocol,
+            degraded = true,
+            event = "report_accepted",
+            "Report accepted undecided by the degradation policy"
+        ),
+        _ => {
+            for to in to {
+                warn!(
+                    from = %agent_id,
+                    to = %to,
+                    kind = "degraded",
+                    protocol = protocol,
+                    degraded = true,
+                    event = "msg_accepted",
+                    "Message accepted undecided by the degradation policy"
+                );
+            }
+        }
+    }
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse {
+            degraded: true,
+            ..ApiResponse::success_with_message(&format!("Accepted without a policy decision: {detail}"))
+        }),
+    ))
+}
+
+/// Accept a send refused by a compliance check while its sender's team is
+/// audit-only; `None` when the refusal stands
+fn audit_only_waiver(
+    state: &AppState,
+    from: &str,
+    refusal: &GatewayError,
+) -> Option<(StatusCode, Json<ApiResponse>)> {
+    if !refusal.is_compliance() {
+        return None;
+    }
+    let policy = state.policy.current();
+    let schedule = policy.policy.enforcement.as_ref()?;
+    let team = state.inner.read().unwrap().owners.get(from).cloned();
+    if schedule.mode_at(team.as_deref(), state.clock.now()) != EnforcementMode::AuditOnly {
+        return None;
+    }
+    warn!(
+        from = %from,
+        schedule = schedule.schedule_for(team.as_deref()),
+        waived = refusal.code(),
+        event = "enforcement_waived",
+        "Send accepted in audit-only mode"
+    );
+    Metrics::inc(&state.metrics.sends_waived);
+    Some((
+        StatusCode::OK,
+        Json(ApiResponse {
+            waived: Some(refusal.code()),
+            ..ApiResponse::success_with_message(&format!("Accepted in audit-only mode: {refusal}"))
+        }),
+    ))
+}
+
+/// File the `report` of a send from `from` before the send is evaluated
+///
+/// Returns the report's receipt once it is accepted, so the send sees the
+/// refreshed report clock, or the response to answer instead of sending
+/// when the report was held for review.
+async fn file_inline_report(
+    state: &AppState,
+    from: &str,
+    report: EnglishReport,
+) -> Result<Result<Option<String>, (StatusCode, Json<ApiResponse>)>, GatewayError> {
+    if report.agent_id != from {
+        return Err(GatewayError::Invalid("report.agent_id must match from".to_string()));
+    }
+    // The send path is open, but report ingestion may be paused
+    if let Some(pause) = state.maintenance.pause_of(RouteGroup::Reports) {
+        return Err(GatewayError::Maintenance {
+            group: RouteGroup::Reports,
+            reason: pause.reason,
+        });
+    }
+    if let Some(thread_id) = &report.thread_id {
+        threads::validate_id(thread_id).map_err(GatewayError::Invalid)?;
+    }
+    let span = thread_span(report.thread_id.as_deref());
+    let flags = flags_span(state, from);
+    let trial = trial_span(state, from, &protocol_key(&report.protocol_name, &report.protocol_version));
+    let (code, Json(mut body)) = file_report(state.clone(), report)
+        .instrument(span)
+        .instrument(flags)
+        .instrument(trial)
+        .await?;
+    if code != StatusCode::OK {
+        body.message = body.message.map(|m| format!("{m}; message not sent"));
+        return Ok(Err((code, Json(body))));
+    }
+    Ok(Ok(body.receipt))
+}
+
+/// Span tagging the audit events of a request with its `thread_id`
+fn thread_span(thread_id: Option<&str>) -> Span {
+    match thread_id {
+        Some(thread_id) => tracing::info_span!("thread", thread_id),
+        None => Span::none(),
+    }
+}
+
+/// Span tagging the audit events of a decision with the feature flags active
+/// for `agent_id`; no span when none are
+fn flags_span(state: &AppState, agent_id: &str) -> Span {
+    let active = {
+        let st = state.inner.read().unwrap();
+        state.flags.active(agent_id, st.owners.get(agent_id).map(String::as_str))
+    };
+    if active.is_empty() {
+        Span::none()
+    } else {
+        tracing::info_span!("flags", flags = %active)
+    }
+}
+
+/// Span tagging the audit events of a decision with `trial` when the
+/// protocol behind `agent_id::protocol` is on trial; no span otherwise
+fn trial_span(state: &AppState, agent_id: &str, protocol: &str) -> Span {
+    let st = state.inner.read().unwrap();
+    let report_key = format!("{agent_id}::{protocol}");
+    if st.protocol_stats.get(&report_key).is_some_and(|s| s.trial.is_some()) {
+        tracing::info_span!("trial", trial = true)
+    } else {
+        Span::none()
+    }
+}
+
+/// The language of record of `agent_id`'s team, when one is configured
+fn language_of_record<'a>(state: &'a AppState, agent_id: &str) -> Option<&'a LanguageOfRecord> {
+    if !state.languages.is_configured() {
+        return None;
+    }
+    let team = state.inner.read().unwrap().owners.get(agent_id).cloned();
+    state.languages.record(team.as_deref())
+}
+
+/// Classify `content` against the sender's language of record, or English
+async fn classify_content(state: &AppState, agent_id: &str, content: &str) -> Verdict {
+    match language_of_record(state, agent_id) {
+        Some(record) => record.classify(&state.detector, content).await,
+        None => state.detector.classify(content).await,
+    }
+}
+
+/// Body of a refused request, phrased for the agent's language of record
+fn refusal_body(state: &AppState, agent_id: &str, err: GatewayError) -> ApiResponse {
+    let record = language_of_record(state, agent_id);
+    let phrased = record.map(|record| messages::localize(&err.message(), Some(record)));
+    let reasons = record.map(|record| DenyReason::list(&err, Some(record)));
+    let mut body = ApiResponse::from(err);
+    if let Some(text) = phrased {
+        body.error = Some(text);
+    }
+    if let Some(reasons) = reasons {
+        body.deny_reasons = reasons;
+    }
+    body
+}
+
+/// Answer a refused send or report in the phrasing of the agent's language
+/// of record; other agents get the error as is
+fn phrase_refusal(
+    state: &AppState,
+    agent_id: &str,
+    outcome: Result<(StatusCode, Json<ApiResponse>), GatewayError>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    match outcome {
+        Err(err) if language_of_record(state, agent_id).is_some() => {
+            Ok((err.status(), Json(refusal_body(state, agent_id, err))))
+        }
+        outcome => outcome,
+    }
+}
+
+/// Whether the protocol is on trial, graduating it first if its trial is over
+fn on_trial(state: &AppState, agent_id: &str, protocol: &str) -> bool {
+    let graduation = {
+        let st = state.inner.read().unwrap();
+        let Some(stats) = st.protocol_stats.get(&format!("{agent_id}::{protocol}")) else {
+            return false;
+        };
+        let Some(trial) = stats.trial else {
+            return false;
+        };
+        let policy = state.policy.current();
+        trial.graduation(policy.policy.trial.as_ref(), stats.messages_sent, state.clock.now())
+    };
+    match graduation {
+        Some(graduation) => {
+            graduate_trial(state, agent_id, protocol, graduation);
+            false
+        }
+        None => true,
+    }
+}
+
+/// Move a protocol from its trial to full enforcement, and alert its owner
+fn graduate_trial(state: &AppState, agent_id: &str, protocol: &str, graduation: Graduation) {
+    let report_key = format!("{agent_id}::{protocol}");
+    let Some(trial) = ({
+        let mut st = state.inner.write().unwrap();
+        st.protocol_stats.get_mut(&report_key).and_then(|s| s.trial.take())
+    }) else {
+        return;
+    };
+    state.replication.record(Mutation::ProtocolTrial {
+        report_key: report_key.clone(),
+        trial: None,
+    });
+    state.timers.cancel(TimerKind::TrialEnd, &report_key);
+    info!(
+        agent_id = %agent_id,
+        protocol = %protocol,
+        started_at = trial.started_at,
+        reason = graduation.as_str(),
+        event = "protocol_graduated",
+        "Protocol graduated from trial to full enforcement"
+    );
+    let detail = format!(
+        "{protocol} graduated from its trial ({}); reports on it must now meet the full coverage and summary thresholds",
+        graduation.as_str()
+    );
+    let state = state.clone();
+    let agent_id = agent_id.to_string();
+    tokio::spawn(mirror::carry(async move {
+        raise_alert(&state, AlertKind::TrialGraduated, AlertSubject::Agent(&agent_id), &detail).await;
+    }));
+}
+
+/// Evaluate a send and, when allowed, record it as delivered
+async fn deliver_send(
+    state: &AppState,
+    req: &SendMessageRequest,
+    timing: &mut PipelineTiming,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    // A retry was counted with the attempt it retries
+    if req.retry_of.is_none() {
+        timing.time(Stage::Policy, || check_quota(state, &req.from, Resource::Events, 1))?;
+    }
+    let attached = timing.time(Stage::Policy, || state.attachments.claim(state, req))?;
+    let mark = timing.mark();
+    let policy = state.policy.current();
+    let declared = req.protocol.as_ref().map(|p| (p.name.as_str(), p.version.as_str()));
+    let cache_key = state
+        .decision_cache
+        .key(
+            state.interner.intern(&req.from),
+            declared,
+            &req.content,
+            req.content_type.as_deref(),
+            policy.version_id,
+        );
+
+    // Sender-side checks, served from the decision cache when possible; a
+    // send released from quarantine skipped a check, so is never cached
+    let cacheable = req.released_from.is_none();
+    let evaluated = match state.decision_cache.get(&cache_key).filter(|_| cacheable) {
+        Some(decision) => Ok(Ok((decision, true))),
+        None => evaluate_sender(state, req, &policy.policy, timing).await.map(|evaluated| {
+            evaluated.map(|(decision, valid_for)| {
+                if cacheable && state.detector.is_authoritative(decision.source) {
+                    state.decision_cache.insert(cache_key, decision.clone(), valid_for);
+                }
+                (decision, false)
+            })
+        }),
+    };
+    timing.add_since(Stage::Policy, mark);
+    let (decision, cached) = match evaluated? {
+        Ok(evaluated) => evaluated,
+        Err(held) => return Ok(held.response(language_of_record(state, &req.from))),
+    };
+
+    // Novel content is stored for consistency scoring, threaded content for replay
+    let drop_content = timing.time(Stage::Policy, || match &decision.kind {
+        SendKind::English if req.thread_id.is_none() => Ok(false),
+        _ => check_quota(state, &req.from, Resource::MessageBytes, req.content.len() as u64),
+    })?;
+
+    // Receiver-side checks, per recipient
+    let protocol = match &decision.kind {
+        SendKind::Novel { key, .. } => Some(&**key),
+        SendKind::English => None,
+    };
+    let mut decisions = timing.time(Stage::Policy, || {
+        let st = state.inner.read().unwrap();
+        decide_recipients(&st, &policy.policy, req, protocol)
+    });
+    if let Some(key) = protocol {
+        let mark = timing.mark();
+        let decided = consult_webhook(state, req, key, &decisions).await;
+        timing.add_since(Stage::Webhook, mark);
+        decided?;
+    }
+    // Recipients behind a peer gateway are re-evaluated there
+    let mark = timing.mark();
+    forwarding::forward(state, req, protocol, &mut decisions).await;
+    timing.add_since(Stage::Policy, mark);
+
+    let mark = timing.mark();
+    let tracked = if state.deliveries.enabled() && decisions.values().any(|d| d.allowed) {
+        let mut to = Vec::with_capacity(decisions.len());
+        to.extend(decisions.iter().filter(|(_, d)| d.allowed).map(|(to, _)| to.as_str()));
+        let (message_id, deliver_by) = state.deliveries.track(&req.from, protocol, &to, state.clock.now());
+        state.timers.schedule(TimerKind::DeliveryTimeout, &message_id, deliver_by);
+        Some(message_id)
+    } else {
+        None
+    };
+    if let SendKind::Novel { key, .. } = &decision.kind {
+        let now = state.clock.now();
+        let report_key = format!("{}::{}", req.from, key);
+        let mut st = state.inner.write().unwrap();
+        let allowed = decisions.values().filter(|d| d.allowed).count() as u64;
+        entry_mut(&mut st.track_records, &req.from).messages_accepted += allowed;
+        let descriptor = st.protocols.get(req.from.as_str()).and_then(|m| m.get(&**key));
+        let schema = descriptor.is_some_and(|d| d.message_schema.is_some());
+        let registered_tier = descriptor.map(|d| d.risk_tier.clone()).unwrap_or_default();
+        let classes = match (&policy.policy.risk, &policy.policy.routing) {
+            (Some(_), Some(routing)) => recipient_classes(&st, routing, &decisions),
+            _ => BTreeSet::new(),
+        };
+        let stats = entry_mut(&mut st.protocol_stats, &report_key);
+        if schema && allowed > 0 {
+            stats.schema_passed += 1;
+        }
+        if tracked.is_some() {
+            stats.deliveries.tracked += allowed;
+        }
+        let known = stats.recipients.len();
+        for (to, _) in decisions.iter().filter(|(_, d)| d.allowed) {
+            stats.messages_sent += 1;
+            if !stats.recipients.contains(to) {
+                stats.recipients.insert(to.clone());
+            }
+            stats.last_used_ts = Some(now);
+        }
+        let raised = policy.policy.risk.as_ref().filter(|_| allowed > 0).and_then(|risk| {
+            let added = (stats.recipients.len() > known).then_some(stats.recipients.len());
+            let fired = risk.on_send(&mut stats.volume, allowed, added, &classes, now)?;
+            stats.risk.raise(&registered_tier, fired, now).then(|| (fired.clone(), stats.risk.clone()))
+        });
+        let graduated = stats
+            .trial
+            .and_then(|t| t.graduation(policy.policy.trial.as_ref(), stats.messages_sent, now));
+        let mismatch = decisions
+            .values()
+            .any(|d| d.allowed)
+            .then(|| stats.structure.observe(&req.content))
+            .flatten();
+        for (to, _) in decisions.iter().filter(|(_, d)| d.allowed) {
+            st.graph.record(&req.from, to, key, now);
+        }
+        if decisions.values().any(|d| d.allowed) {
+            let samples = entry_mut(&mut st.traffic, &report_key);
+            if samples.len() == MAX_TRAFFIC_SAMPLES {
+                samples.pop_front();
+            }
+            // Digests of dropped content do not count as stored content
+            let sample = if drop_content {
+                TrafficSample::new(&dropped_content(&req.content), state.clock.now_f64())
+            } else {
+                state.quota.record(&req.from, Resource::MessageBytes, req.content.len() as u64);
+                TrafficSample::new(&req.content, state.clock.now_f64())
+            };
+            samples.push_back(
+                sample
+                    .in_thread(req.thread_id.as_deref())
+                    .identified(req.message_id.as_deref()),
+            );
+            let tenant = st.owners.get(req.from.as_str()).map_or(slo::UNASSIGNED_TENANT, String::as_str);
+            state.slo.record_send(tenant, &report_key, now);
+        }
+        drop(st);
+
+        if let Some((trigger, standing)) = raised {
+            raise_risk_tier(state, &req.from, key, &registered_tier, &trigger, &standing);
+        }
+        if let Some(graduation) = graduated {
+            graduate_trial(state, &req.from, key, graduation);
+        }
+        if let Some(families) = mismatch {
+            suspect_protocol_mismatch(state, &req.from, key, families);
+        }
+
+        // Gloss in the background; the send never waits on the translator
+        if decisions.values().any(|d| d.allowed) && state.translator.enabled_for(key) {
+            state.translator.spawn_gloss(report_key, key.to_string(), req.content.clone());
+        }
+    }
+
+    // Allowlisted machine output is benign by definition
+    if matches!(decision.kind, SendKind::English)
+        && decision.source != VerdictSource::Allowlist
+        && state.undeclared.enabled()
+        && decisions.values().any(|d| d.allowed)
+    {
+        state.undeclared.record(&req.from, &req.content);
+    }
+
+    if let Some(thread_id) = &req.thread_id {
+        let mut to = Vec::with_capacity(decisions.len());
+        to.extend(decisions.iter().filter(|(_, d)| d.allowed).map(|(to, _)| to.clone()));
+        if !to.is_empty() {
+            let content = if drop_content {
+                dropped_content(&req.content)
+            } else {
+                // Novel content was already counted when stored as traffic
+                if protocol.is_none() {
+                    state.quota.record(&req.from, Resource::MessageBytes, req.content.len() as u64);
+                }
+                req.content.clone()
+            };
+            state.inner.write().unwrap().threads.record(
+                thread_id,
+                ThreadEntry::Message {
+                    ts: state.clock.now_f64(),
+                    from: req.from.to_string(),
+                    to,
+                    protocol: protocol.map(str::to_string),
+                    content,
+                },
+            );
+        }
+    }
+    timing.add_since(Stage::Storage, mark);
+
+    let mark = timing.mark();
+    let sender_held = state.holds.active(&req.from);
+    for (to, d) in &decisions {
+        if !d.allowed {
+            log_recipient_rejected(state, &req.from, to, d);
+            continue;
+        }
+        // An investigation hold keeps the content of the agent's traffic
+        let held = (sender_held || state.holds.active(to)).then_some(req.content.as_str());
+        match &decision.kind {
+            SendKind::English => {
+                info!(
+                    from = %req.from,
+                    to = %to,
+                    event = "msg_accepted",
+                    kind = "english",
+                    message_id = tracked.as_deref(),
+                    detector = %decision.source,
+                    cached,
+                    content = held,
+                    "English message accepted"
+                );
+                Metrics::inc(&state.metrics.english_messages);
+            }
+            SendKind::Novel { key, upgraded_from } => {
+                info!(
+                    from = %req.from,
+                    to = %to,
+                    event = "msg_accepted",
+                    kind = "novel",
+                    protocol = %key,
+                    bytes = req.content.len(),
+                    upgraded_from = upgraded_from.as_deref(),
+                    message_id = tracked.as_deref(),
+                    detector = %decision.source,
+                    cached,
+                    content = held,
+                    "Novel message accepted"
+                );
+                Metrics::inc(&state.metrics.novel_messages);
+            }
+        }
+    }
+    timing.add_since(Stage::Audit, mark);
+
+    let mut recipients = Vec::with_capacity(decisions.len());
+    recipients.extend(decisions.iter().filter(|(_, d)| d.allowed).map(|(to, _)| to.as_str()));
+    if !recipients.is_empty() && !attached.is_empty() {
+        let parent = tracked.as_deref().or(req.message_id.as_deref());
+        state.attachments.release(req, &attached, parent);
+    }
+    let receipt = (!recipients.is_empty()).then(|| {
+        state.signer.sign(&ReceiptClaims {
+            iss: RECEIPT_ISSUER,
+            kind: "message",
+            sub: &req.from,
+            protocol,
+            recipients,
+            content_sha256: Some(signing::content_digest(&req.content)),
+            attachments_sha256: attached.iter().map(|a| a.sha256.clone()).collect(),
+            message_count: None,
+            policy_version: &policy.version,
+            iat: state.clock.now(),
+        })
+    });
+    let (code, Json(mut body)) = send_outcome(&req.to, decisions)?;
+    body.receipt = receipt;
+    body.message_id = tracked;
+    body.advisories = soft_limit_advisories(state, &policy.policy, &req.from, protocol);
+    Ok((code, Json(body)))
+}
+
+/// Refuse further sends under a protocol whose reports keep failing review
+fn suspend_protocol(state: &AppState, agent_id: &str, protocol: &str, standing: &Standing) {
+    state.decision_cache.invalidate_agent(agent_id);
+    Metrics::inc(&state.metrics.protocols_suspended);
+    warn!(
+        agent_id = %agent_id,
+        protocol = %protocol,
+        event = "protocol_suspended",
+        strikes = standing.strikes,
+        "Protocol suspended for review after repeated inconsistent reports"
+    );
+    let detail = format!(
+        "{protocol} suspended for review after {} consecutive reports were held for low consistency; sends under it are refused until a held report is approved or an admin reinstates it",
+        standing.strikes
+    );
+    reassess_on_anomaly(state, agent_id, protocol);
+    federation::announce(state, agent_id, protocol, true, &federation::reason(standing));
+    let state = state.clone();
+    let agent_id = agent_id.to_string();
+    tokio::spawn(mirror::carry(async move {
+        raise_alert(&state, AlertKind::AgentSuspended, AlertSubject::Agent(&agent_id), &detail).await;
+    }));
+}
+
+/// Hard limits `agent_id` is nearing under `policy.soft_limits`, the
+/// suspension of `protocol` included
+///
+/// Each advisory is logged and alerted on once per notice interval; the
+/// response carries it for as long as it applies.
+fn soft_limit_advisories(state: &AppState, policy: &Policy, agent_id: &str, protocol: Option<&str>) -> Vec<Advisory> {
+    let limits = &policy.soft_limits;
+    let mut advisories = limits.quota(&state.quota.nearing(agent_id, limits.ratio));
+    {
+        let st = state.inner.read().unwrap();
+        let reputation = st.reputation(policy, agent_id);
+        advisories.extend(limits.violations(reputation.violations, reputation.violation_ceiling));
+        if let Some(key) = protocol {
+            if let Some(stats) = st.protocol_stats.get(&format!("{agent_id}::{key}")) {
+                advisories.extend(limits.strikes(key, &stats.standing, policy.report_strike_limit));
+            }
+        }
+    }
+
+    let now = state.clock.now();
+    for advisory in &advisories {
+        if !state.soft_limit_notices.due(agent_id, advisory, now) {
+            continue;
+        }
+        warn!(
+            agent_id = %agent_id,
+            kind = %advisory.kind,
+            subject = %advisory.subject,
+            used = advisory.used,
+            limit = advisory.limit,
+            event = "soft_limit_approached",
+            "Agent approaching a hard limit"
+        );
+        let state = state.clone();
+        let agent_id = agent_id.to_string();
+        let detail = advisory.message.clone();
+        tokio::spawn(mirror::carry(async move {
+            raise_alert(&state, AlertKind::SoftLimitApproached, AlertSubject::Agent(&agent_id), &detail).await;
+        }));
+    }
+    advisories
+}
+
+/// Lift a protocol suspension; returns whether the protocol was suspended
+fn lift_suspension(state: &AppState, agent_id: &str, protocol: &str, via: &str) -> bool {
+    let report_key = format!("{agent_id}::{protocol}");
+    let (reinstated, strikes) = {
+        let mut st = state.inner.write().unwrap();
+        let Some(stats) = st.protocol_stats.get_mut(&report_key) else {
+            return false;
+        };
+        let strikes = stats.standing.strikes;
+        let reinstated = stats.standing.reinstate();
+        if strikes > 0 {
+            state.replication.record(Mutation::ProtocolStanding {
+                report_key,
+                standing: Standing::default(),
+            });
+        }
+        (reinstated, strikes)
+    };
+    if reinstated {
+        state.decision_cache.invalidate_agent(agent_id);
+        info!(
+            agent_id = %agent_id,
+            protocol = %protocol,
+            event = "protocol_reinstated",
+            via = via,
+            strikes,
+            "Protocol suspension lifted"
+        );
+        // Still suspended if its certification lapsed
+        federation::announce_standing(state, agent_id, protocol, &format!("reinstated by {via}"));
+    }
+    reinstated
+}
+
+/// Alert that traffic under one registration looks like several protocols
+fn suspect_protocol_mismatch(state: &AppState, from: &str, protocol: &str, families: Vec<FamilySummary>) {
+    let shapes: Vec<String> = families
+        .iter()
+        .map(|f| format!("{:?} ({:.0}%)", f.shape, f.share * 100.0))
+        .collect();
+    warn!(
+        agent_id = %from,
+        protocol = %protocol,
+        families = %serde_json::to_string(&families).unwrap_or_default(),
+        event = "protocol_mismatch_suspected",
+        "Traffic under one protocol registration splits into distinct structures"
+    );
+    let detail = format!(
+        "Messages on {protocol} fall into {} structural families: {}",
+        families.len(),
+        shapes.join(", ")
+    );
+    reassess_on_anomaly(state, from, protocol);
+    let state = state.clone();
+    let from = from.to_string();
+    tokio::spawn(mirror::carry(async move {
+        raise_alert(&state, AlertKind::ProtocolMismatchSuspected, AlertSubject::Agent(&from), &detail).await;
+    }));
+}
+
+/// Classes of the allowed recipients of a send under `routing`
+fn recipient_classes(
+    st: &InnerState,
+    routing: &routing::Routing,
+    decisions: &BTreeMap<String, RecipientDecision>,
+) -> BTreeSet<String> {
+    decisions
+        .iter()
+        .filter(|(_, d)| d.allowed)
+        .filter_map(|(to, _)| {
+            let listed = st.discovered.get(to).and_then(|d| d.recipient_class.as_deref());
+            routing.classify(to, listed, st.owners.get(to).map(String::as_str))
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Raise a protocol's risk tier on an anomaly alert, if the policy says to
+fn reassess_on_anomaly(state: &AppState, agent_id: &str, protocol: &str) {
+    let policy = state.policy.current();
+    let Some(trigger) = policy.policy.risk.as_ref().and_then(|r| r.on_anomaly()) else {
+        return;
+    };
+    let raised = {
+        let mut st = state.inner.write().unwrap();
+        let Some(registered) = st.protocols.get(agent_id).and_then(|m| m.get(protocol)).map(|d| d.risk_tier.clone())
+        else {
+            return;
+        };
+        let stats = entry_mut(&mut st.protocol_stats, &format!("{agent_id}::{protocol}"));
+        stats.risk.raise(&registered, trigger, state.clock.now()).then(|| (registered, stats.risk.clone()))
+    };
+    if let Some((registered, standing)) = raised {
+        raise_risk_tier(state, agent_id, protocol, &registered, trigger, &standing);
+    }
+}
+
+/// Tighten reporting on a protocol whose effective risk tier rose, and alert
+/// its owner
+fn raise_risk_tier(
+    state: &AppState,
+    agent_id: &str,
+    protocol: &str,
+    registered: &str,
+    trigger: &Trigger,
+    standing: &RiskStanding,
+) {
+    let report_key = format!("{agent_id}::{protocol}");
+    state.replication.record(Mutation::ProtocolRisk {
+        report_key: report_key.clone(),
+        risk: standing.clone(),
+    });
+    state.decision_cache.invalidate_agent(agent_id);
+    let interval = {
+        let st = state.inner.read().unwrap();
+        if let Some(&last) = st.last_report_ts.get(&report_key) {
+            schedule_report_deadline(state, &st, &report_key, last);
+        }
+        let policy = state.policy.current();
+        let interval = st.reputation(&policy.policy, agent_id).report_interval_sec;
+        st.report_interval(&policy.policy, &report_key, interval, state.clock.now())
+    };
+    let tier = standing.effective(registered);
+    warn!(
+        agent_id = %agent_id,
+        protocol = %protocol,
+        registered_tier = %registered,
+        risk_tier = %tier,
+        trigger = %trigger.reason(),
+        report_interval_sec = interval,
+        event = "risk_tier_raised",
+        "Protocol risk tier raised"
+    );
+    let detail = format!(
+        "{protocol} raised from {registered} to {tier} risk ({}); reports on it are due every {interval} seconds",
+        trigger.reason()
+    );
+    let state = state.clone();
+    let agent_id = agent_id.to_string();
+    tokio::spawn(mirror::carry(async move {
+        raise_alert(&state, AlertKind::RiskTierRaised, AlertSubject::Agent(&agent_id), &detail).await;
+    }));
+}
+
+/// Hold a send refused for an overdue report until the next accepted report
+///
+/// Falls back to the original refusal when the sender's queue is full.
+fn park_send(
+    state: &AppState,
+    mut req: SendMessageRequest,
+    overdue: GatewayError,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    // The shadow of a send never calls the production sender back
+    if mirror::is_mirrored() {
+        req.callback_url = None;
+    }
+    let key = {
+        let st = state.inner.read().unwrap();
+        match req.protocol.as_ref().map(|p| versioning::resolve(&st, &req.from, &p.name, &p.version)) {
+            Some(Resolution::Use { key, .. }) => key,
+            _ => return Err(overdue),
+        }
+    };
+    let report_key = format!("{}::{}", req.from, key);
+    let from = req.from.clone();
+    let ticket = match state.parking.park(req, &report_key, &key, state.clock.now()) {
+        Ok(ticket) => ticket,
+        Err(e) => {
+            warn!(
+                from = %from,
+                protocol = %key,
+                event = "park_refused",
+                error = %e,
+                "Parking queue full"
+            );
+            return Err(overdue);
+        }
+    };
+    state
+        .timers
+        .schedule(TimerKind::ParkExpiry, &ticket.id.to_string(), ticket.expires_at);
+    info!(
+        from = %from,
+        protocol = %key,
+        park_id = ticket.id,
+        expires_at = ticket.expires_at,
+        event = "send_parked",
+        "Message parked until the next report"
+    );
+    let mut body = ApiResponse::success_with_message(&format!(
+        "Report overdue: message parked (id {}) until a report is accepted",
+        ticket.id
+    ));
+    body.code = Some("parked");
+    body.parked = Some(ticket);
+    Ok((StatusCode::ACCEPTED, Json(body)))
+}
+
+/// A retry token for a send refused for a report overdue by at most
+/// `RETRY_GRACE_SEC`
+fn offer_retry(state: &AppState, req: &SendMessageRequest) -> Option<RetryTicket> {
+    let protocol = req.protocol.as_ref()?;
+    let policy = state.policy.current();
+    let now = state.clock.now();
+    let st = state.inner.read().unwrap();
+    let Resolution::Use { key, .. } = versioning::resolve(&st, &req.from, &protocol.name, &protocol.version) else {
+        return None;
+    };
+    let report_key = format!("{}::{}", req.from, key);
+    // A protocol never reported on is not briefly overdue
+    let last = st.last_report_ts.get(&report_key).copied().filter(|&ts| ts > 0)?;
+    let interval = st.reputation(&policy.policy, &req.from).report_interval_sec;
+    let interval = st.report_interval(&policy.policy, &report_key, interval, now);
+    drop(st);
+    let overdue_by = now.saturating_sub(last).saturating_sub(interval);
+    state
+        .retries
+        .eligible(overdue_by)
+        .then(|| state.retries.issue(&req.from, &report_key, &req.to.list(), &req.content, now))
+}
+
+/// Answer a refusal for an overdue report with its retry token
+fn refuse_with_retry(
+    state: &AppState,
+    from: &str,
+    overdue: GatewayError,
+    ticket: RetryTicket,
+) -> (StatusCode, Json<ApiResponse>) {
+    info!(
+        from = %from,
+        attempt_id = %ticket.attempt_id,
+        window_sec = ticket.window_sec,
+        event = "retry_offered",
+        "Retry token issued for a send refused for a barely overdue report"
+    );
+    Metrics::inc(&state.metrics.retries_offered);
+    let status = overdue.status();
+    let mut body = refusal_body(state, from, overdue);
+    body.retry = Some(ticket);
+    (status, Json(body))
+}
+
+/// Redeem the retry token of a resubmitted send; returns the id of the
+/// attempt it retries
+fn redeem_retry(state: &AppState, req: &SendMessageRequest, token: &str) -> Result<String, GatewayError> {
+    let redeemed = {
+        let st = state.inner.read().unwrap();
+        state.retries.redeem(
+            token,
+            &req.from,
+            &req.to.list(),
+            &req.content,
+            |report_key| st.last_report_ts.get(report_key).copied(),
+            state.clock.now(),
+        )
+    }
+    .map_err(GatewayError::Invalid)?;
+    info!(
+        from = %req.from,
+        retry_of = %redeemed.attempt_id,
+        report_key = %redeemed.report_key,
+        event = "retry_redeemed",
+        "Retry token redeemed"
+    );
+    Metrics::inc(&state.metrics.retries_redeemed);
+    Ok(redeemed.attempt_id)
+}
+
+/// Re-evaluate the sends parked on `report_key` after a report was accepted
+async fn release_parked(state: AppState, report_key: String) {
+    for (id, req) in state.parking.take(&report_key) {
+        state.timers.cancel(TimerKind::ParkExpiry, &id.to_string());
+        let span = thread_span(req.thread_id.as_deref());
+        // Released sends are off the sender's request path, so their timing is dropped
+        let mut timing = PipelineTiming::start(Duration::ZERO);
+        let (outcome, code, body) = match deliver_send(&state, &req, &mut timing).instrument(span).await {
+            Ok((code, Json(body))) => (ParkState::Delivered, code, body),
+            Err(e) => (ParkState::Refused, e.status(), ApiResponse::from(e)),
+        };
+        info!(
+            from = %req.from,
+            park_id = id,
+            outcome = outcome.as_str(),
+            status = code.as_u16(),
+            event = "parked_send_released",
+            "Parked message re-evaluated"
+        );
+        let body = serde_json::to_value(&body).unwrap_or_default();
+        let resolved = state.parking.resolve(id, outcome, code.as_u16(), body, state.clock.now());
+        if let Some((status, callback_url)) = resolved {
+            retain_park_outcome(&state, &status);
+            if let Some(url) = callback_url {
+                queue_park_callback(&state, &url, &status);
+            }
+        }
+    }
+}
+
+/// Give the decision webhook for the send's protocol, if any, the final say
+async fn consult_webhook(
+    state: &AppState,
+    req: &SendMessageRequest,
+    key: &str,
+    decisions: &BTreeMap<String, RecipientDecision>,
+) -> Result<(), GatewayError> {
+    let to: Vec<&str> = decisions
+        .iter()
+        .filter(|(_, d)| d.allowed)
+        .map(|(to, _)| to.as_str())
+        .collect();
+    if to.is_empty() {
+        return Ok(());
+    }
+    // Reassessment may have raised the protocol past its registered tier
+    let risk_tier = {
+        let st = state.inner.read().unwrap();
+        let risk = st.protocol_stats.get(&format!("{}::{key}", req.from)).map(|s| &s.risk);
+        st.protocols
+            .get(req.from.as_str())
+            .and_then(|protocols| protocols.get(key))
+            .map(|d| risk.map_or_else(|| d.risk_tier.clone(), |r| r.effective(&d.risk_tier)))
+            .unwrap_or_default()
+    };
+    let decision = state
+        .webhooks
+        .decide(&DecisionRequest {
+            from: &req.from,
+            to,
+            protocol: key,
+            risk_tier: &risk_tier,
+            content: &req.content,
+            content_type: req.content_type.as_deref(),
+        })
+        .await;
+    match decision {
+        Decision::Unchecked => Ok(()),
+        Decision::Skipped => {
+            info!(
+                from = %req.from,
+                protocol = %key,
+                event = "decision_webhook_skipped",
+                "Decision webhook skipped for a mirrored send"
+            );
+            Ok(())
+        }
+        Decision::Allowed => {
+            info!(
+                from = %req.from,
+                protocol = %key,
+                event = "decision_webhook_allowed",
+                "Decision webhook allowed the send"
+            );
+            Ok(())
+        }
+        Decision::FailedOpen { error } => {
+            warn!(
+                from = %req.from,
+                protocol = %key,
+                event = "decision_webhook_failed",
+                fallback = "allow",
+                error = %error,
+                "Decision webhook failed, allowing the send"
+            );
+            Ok(())
+        }
+        Decision::Denied { reason } => {
+            warn!(
+                from = %req.from,
+                protocol = %key,
+                event = "msg_rejected",
+                reason = "webhook_denied",
+                webhook_reason = reason.as_deref().unwrap_or(""),
+                "Message denied by decision webhook"
+            );
+            state.metrics.reject();
+            Err(GatewayError::Vetoed { reason })
+        }
+        Decision::FailedClosed { error } => {
+            warn!(
+                from = %req.from,
+                protocol = %key,
+                event = "msg_rejected",
+                reason = "decision_webhook_unavailable",
+                error = %error,
+                "Decision webhook failed, refusing the send"
+            );
+            state.metrics.reject();
+            Err(GatewayError::DecisionUnavailable)
+        }
+    }
+}
+
+/// Sender-side checks: language verdict, protocol registration, version
+/// policy, protocol standing, and report freshness
+///
+/// On success also returns how long the decision remains valid, i.e. the
+/// time left before the sender's next report is due, or the send's
+/// quarantine id when it was held for review instead.
+async fn evaluate_sender(
+    state: &AppState,
+    req: &SendMessageRequest,
+    policy: &Policy,
+    timing: &mut PipelineTiming,
+) -> Result<Result<(SenderDecision, Option<Duration>), Quarantined>, GatewayError> {
+    // Every check that applies runs, so the sender learns all its refusals
+    // at once; the first is the primary one
+    let mut denials = Vec::new();
+    if state.inner.read().unwrap().is_deleted(&req.from) {
+        warn!(
+            from = %req.from,
+            event = "msg_rejected",
+            reason = "agent_deleted",
+            "Sender agent deleted"
+        );
+        denials.push(GatewayError::AgentDeleted { action: "to resume messaging" });
+    }
+
+    // Encrypted payloads never reach language detection; one released from
+    // quarantine was cleared by its review
+    let opaque = timing.time(Stage::Detection, || encryption::detect(&req.content));
+    if let Some(found) = opaque.filter(|_| req.released_from.is_none()) {
+        match check_encrypted(state, req, policy.encrypted_content, found) {
+            Err(refused @ GatewayError::EncryptedContent { .. }) => denials.push(refused),
+            Ok(Some(held)) => return Ok(Err(held)),
+            checked => {
+                checked?;
+            }
+        }
+    }
+    let mark = timing.mark();
+    let verdict = match opaque {
+        Some(_) => Verdict::new(Some(false), VerdictSource::Encoding),
+        None => match state.allowlist.matches(&req.content, req.content_type.as_deref()) {
+            Some(benign) => {
+                // JSON strings and URL paths can carry text of any language
+                let carried = match benign.text.is_empty() {
+                    true => None,
+                    false => Some(classify_content(state, &req.from, &benign.text).await),
+                };
+                match carried.filter(|verdict| verdict.is_english != Some(true)) {
+                    Some(verdict) => {
+                        info!(
+                            from = %req.from,
+                            pattern = %benign.pattern,
+                            event = "content_allowlist_text_refused",
+                            "Machine output carries non-English text"
+                        );
+                        verdict
+                    }
+                    None => {
+                        info!(
+                            from = %req.from,
+                            pattern = %benign.pattern,
+                            event = "content_allowlisted",
+                            "Benign machine output"
+                        );
+                        Verdict::new(Some(true), VerdictSource::Allowlist)
+                    }
+                }
+            }
+            None => classify_content(state, &req.from, &req.content).await,
+        },
+    };
+    timing.add_since(Stage::Detection, mark);
+    if let Some(ballot) = &verdict.ballot {
+        info!(
+            from = %req.from,
+            event = "language_ensemble",
+            is_english = ballot.is_english,
+            score = ballot.score,
+            threshold = ballot.threshold,
+            votes = %serde_json::to_string(&ballot.votes).unwrap_or_default(),
+            abstained = ?ballot.abstained.iter().map(|v| v.as_str()).collect::<Vec<_>>(),
+            dissenters = ?ballot.dissenters().map(Voter::as_str).collect::<Vec<_>>(),
+            "Language decided by detector ensemble"
+        );
+    }
+
+    // Classifier unavailable and configured to fail closed
+    let Some(is_english) = verdict.is_english else {
+        // Refusals found before detection stand
+        deny(state, denials)?;
+        warn!(
+            from = %req.from,
+            event = "msg_rejected",
+            reason = "detector_unavailable",
+            "Language detector unavailable"
+        );
+        state.metrics.reject();
+        return Err(GatewayError::DetectorUnavailable);
+    };
+
+    // Messages in the language of record (English by default) pass
+    // sender-side checks freely
+    if is_english {
+        deny(state, denials)?;
+        let decision = SenderDecision {
+            kind: SendKind::English,
+            source: verdict.source,
+        };
+        return Ok(Ok((decision, None)));
+    }
+
+    // Novel language: require protocol declaration
+    let pref = match &req.protocol {
+        Some(p) => p,
+        None => {
+            warn!(
+                from = %req.from,
+                event = "msg_rejected",
+                reason = "missing_protocol",
+                violation = true,
+                "Novel language without protocol declaration"
+            );
+
+            // Record violation
+            {
+                let mut st = state.inner.write().unwrap();
+                let count = st.add_violation(&req.from);
+                state.replication.record(Mutation::Violations { agent_id: req.from.to_string(), count });
+            }
+            state.decision_cache.invalidate_agent(&req.from);
+            Metrics::inc(&state.metrics.violations);
+            return Err(refuse(state, denials, GatewayError::MissingProtocol));
+        }
+    };
+
+    let key = protocol_key(&pref.name, &pref.version);
+    let miss_key = format!("{}::{}", req.from, key);
+
+    // Repeats of a recent registration miss are refused without a lock or log line
+    if state.registration_misses.contains(&miss_key) {
+        return Err(refuse(state, denials, GatewayError::NotRegistered));
+    }
+
+    let st = state.inner.read().unwrap();
+
+    // Check protocol registration
+    let registered = st
+        .protocols
+        .get(req.from.as_str())
+        .and_then(|m| m.get(&key))
+        .is_some();
+
+    if !registered {
+        drop(st);
+        let repeats = state.registration_misses.record(&miss_key);
+        warn!(
+            from = %req.from,
+            protocol = %key,
+            event = "msg_rejected",
+            reason = "protocol_not_registered",
+            repeats,
+            "Protocol not registered"
+        );
+        return Err(refuse(state, denials, GatewayError::NotRegistered));
+    }
+
+    // Apply version compatibility policy
+    let (key, upgraded_from) = match versioning::resolve(&st, &req.from, &pref.name, &pref.version) {
+        Resolution::Use { key, upgraded_from } => (key, upgraded_from),
+        Resolution::Superseded { successor } => {
+            warn!(
+                from = %req.from,
+                protocol = %key,
+                successor = %successor,
+                event = "msg_rejected",
+                reason = "protocol_superseded",
+                "Protocol version superseded"
+            );
+            return Err(refuse(state, denials, GatewayError::Superseded { successor }));
+        }
+    };
+    let report_key = format!("{}::{}", req.from, key);
+    let reputation = st.reputation(policy, &req.from);
+
+    // Agents on probation must gloss what they send
+    let glossed = st
+        .protocols
+        .get(req.from.as_str())
+        .and_then(|m| m.get(&key))
+        .is_some_and(|d| !d.codebook.is_empty());
+    if reputation.codebook_required && !glossed {
+        warn!(
+            from = %req.from,
+            protocol = %key,
+            event = "msg_rejected",
+            reason = "codebook_required",
+            tier = ?reputation.tier,
+            "Agent on probation sent under a protocol without a codebook"
+        );
+        denials.push(GatewayError::CodebookRequired);
+    }
+
+    // Structured protocols may only send what their schema accepts
+    let breach = st
+        .protocols
+        .get(req.from.as_str())
+        .and_then(|m| m.get(&key))
+        .and_then(|d| d.message_schema.as_ref())
+        .filter(|_| {
+            let team = st.owners.get(req.from.as_str()).map(String::as_str);
+            state.flags.gates(flags::MESSAGE_SCHEMA, &req.from, team)
+        })
+        .and_then(|schema| schema.check(&req.content).err());
+    let st = if let Some(errors) = breach {
+        drop(st);
+        let errors = errors.join("; ");
+        warn!(
+            from = %req.from,
+            protocol = %key,
+            event = "msg_rejected",
+            reason = "schema_violation",
+            violation = true,
+            errors = %errors,
+            "Message does not match the protocol's schema"
+        );
+        {
+            let mut st = state.inner.write().unwrap();
+            let count = st.add_violation(&req.from);
+            state.replication.record(Mutation::Violations { agent_id: req.from.to_string(), count });
+            entry_mut(&mut st.protocol_stats, &report_key).schema_failed += 1;
+        }
+        state.decision_cache.invalidate_agent(&req.from);
+        Metrics::inc(&state.metrics.violations);
+        denials.push(GatewayError::SchemaViolation(errors));
+        state.inner.read().unwrap()
+    } else {
+        st
+    };
+
+    // Refuse protocols suspended for repeated inconsistent reports
+    if st.protocol_stats.get(&report_key).is_some_and(|s| s.standing.is_suspended()) {
+        warn!(
+            from = %req.from,
+            protocol = %key,
+            event = "msg_rejected",
+            reason = "protocol_suspended",
+            "Protocol suspended for review"
+        );
+        denials.push(GatewayError::ProtocolSuspended);
+    } else if let Some(remote) = state.federation.enforced(&report_key, state.clock.now()) {
+        warn!(
+            from = %req.from,
+            protocol = %key,
+            origin = %remote.origin,
+            event = "msg_rejected",
+            reason = "protocol_suspended",
+            "Protocol suspended for review by a federated peer"
+        );
+        denials.push(GatewayError::SuspendedByPeer { origin: remote.origin });
+    }
+
+    // Refuse protocols whose certification lapsed past its grace
+    let now = state.clock.now();
+    if st.recert_stage(policy, &report_key, now) == Some(RecertStage::Suspended) {
+        warn!(
+            from = %req.from,
+            protocol = %key,
+            event = "msg_rejected",
+            reason = "recertification_lapsed",
+            "Protocol certification lapsed"
+        );
+        denials.push(GatewayError::RecertificationLapsed);
+    }
+
+    // Check report freshness
+    let last = st.last_report_ts.get(&report_key).copied().unwrap_or(0);
+
+    let interval = st.report_interval(policy, &report_key, reputation.report_interval_sec, now);
+    let overdue = now.saturating_sub(last) > interval;
+    if overdue && state.clock_skew.in_grace(now) {
+        warn!(
+            from = %req.from,
+            protocol = %key,
+            event = "clock_skew_grace",
+            seconds_since_report = now - last,
+            "Report overdue, allowed during clock-skew grace period"
+        );
+    } else if overdue {
+        warn!(
+            from = %req.from,
+            protocol = %key,
+            event = "msg_rejected",
+            reason = "report_overdue",
+            seconds_since_report = now - last,
+            "Report overdue"
+        );
+        denials.push(GatewayError::ReportOverdue {
+            seconds: (last > 0).then(|| now.saturating_sub(last)),
+        });
+    }
+    deny(state, denials)?;
+
+    let decision = SenderDecision {
+        kind: SendKind::Novel {
+            key: state.interner.intern(&key),
+            upgraded_from,
+        },
+        source: verdict.source,
+    };
+    // Cached until the report falls due or the certification changes stage
+    let recert_change = policy
+        .recertification
+        .as_ref()
+        .zip(st.protocol_stats.get(&report_key))
+        .and_then(|(recert, stats)| recert.next_change(stats.certified_at(), now));
+    let expires = recert_change.map_or(last + interval, |at| at.min(last + interval));
+    let valid_for = Duration::from_secs(expires.saturating_sub(now));
+    Ok(Ok((decision, Some(valid_for))))
+}
+
+/// Refuse a send for its `denials`, if any, counting it as rejected once
+fn deny(state: &AppState, denials: Vec<GatewayError>) -> Result<(), GatewayError> {
+    if denials.is_empty() {
+        return Ok(());
+    }
+    state.metrics.reject();
+    Err(GatewayError::denied(denials))
+}
+
+/// Refuse a send for its `denials` and the `reason` that ends its evaluation
+fn refuse(state: &AppState, mut denials: Vec<GatewayError>, reason: GatewayError) -> GatewayError {
+    denials.push(reason);
+    state.metrics.reject();
+    GatewayError::denied(denials)
+}
+
+/// Check a storage quota before ingesting `amount` of `resource`
+///
+/// Returns whether content should be stored as a digest only. Under the
+/// `reject` action an exceeded quota refuses the request with 507.
+fn check_quota(
+    state: &AppState,
+    agent_id: &str,
+    resource: Resource,
+    amount: u64,
+) -> Result<bool, GatewayError> {
+    let Some(breach) = state.quota.check(agent_id, resource, amount) else {
+        return Ok(false);
+    };
+    match state.quota.action() {
+        QuotaAction::Reject => {
+            warn!(
+                agent_id = %agent_id,
+                event = "ingestion_rejected",
+                reason = "quota_exceeded",
+                scope = %breach.scope,
+                subject = %breach.subject,
+                resource = %breach.resource,
+                used = breach.used,
+                limit = breach.limit,
+                "Storage quota exceeded"
+            );
+            state.quota.rejections.fetch_add(1, Ordering::Relaxed);
+            Err(GatewayError::QuotaExceeded(breach))
+        }
+        // An investigation hold keeps the agent's content in full
+        QuotaAction::DropContent => Ok(!state.holds.active(agent_id)),
+        QuotaAction::Alert => Ok(false),
+    }
+}
+
+/// Digest stored in place of dropped content
+fn dropped_content(content: &str) -> String {
+    format!("sha256:{}", signing::content_digest(content))
+}
+
+/// Apply the encrypted-content policy to a send carrying opaque content
+///
+/// Under `require_protocol` the send continues through the normal novel-language
+/// checks when it declares a registered protocol with key escrow. Under
+/// `quarantine` it is held, and the hold is returned for the sender's answer.
+fn check_encrypted(
+    state: &AppState,
+    req: &SendMessageRequest,
+    mode: EncryptedContentPolicy,
+    found: OpaqueContent,
+) -> Result<Option<Quarantined>, GatewayError> {
+    if mode == EncryptedContentPolicy::Quarantine {
+        let mut message = req.clone();
+        // The shadow of a send never calls the production sender back
+        if mirror::is_mirrored() {
+            message.callback_url = None;
+        }
+        let drop_content = check_quota(state, &req.from, Resource::MessageBytes, req.content.len() as u64)?;
+        if drop_content {
+            message.content = dropped_content(&req.content);
+        }
+        let id = match state.quarantine.hold(message, found, state.clock.now()) {
+            Ok(id) => id,
+            Err(e) => {
+                warn!(
+                    from = %req.from,
+                    event = "msg_rejected",
+                    reason = "quarantine_full",
+                    encoding = %found.encoding,
+                    error = %e,
+                    "Quarantine full for the sender: encrypted message refused"
+                );
+                return Err(GatewayError::EncryptedContent { protocol_required: false });
+            }
+        };
+        if !drop_content {
+            state.quota.record(&req.from, Resource::MessageBytes, req.content.len() as u64);
+        }
+        warn!(
+            from = %req.from,
+            to = %req.to,
+            event = "msg_quarantined",
+            reason = "encrypted_content",
+            encoding = %found.encoding,
+            quarantine_id = id,
+            "Encrypted message quarantined"
+        );
+        Metrics::inc(&state.metrics.quarantined_messages);
+        return Ok(Some(Quarantined { id }));
+    }
+
+    if mode == EncryptedContentPolicy::RequireProtocol {
+        let escrowed = req.protocol.as_ref().is_some_and(|p| {
+            let st = state.inner.read().unwrap();
+            st.protocols
+                .get(req.from.as_str())
+                .and_then(|m| m.get(&protocol_key(&p.name, &p.version)))
+                .is_some_and(|d| d.encryption.is_some())
+        });
+        if escrowed {
+            return Ok(None);
+        }
+    }
+
+    warn!(
+        from = %req.from,
+        event = "msg_rejected",
+        reason = "encrypted_content",
+        violation = true,
+        encoding = %found.encoding,
+        policy = ?mode,
+        "Encrypted content refused"
+    );
+    {
+        let mut st = state.inner.write().unwrap();
+        let count = st.add_violation(&req.from);
+        state.replication.record(Mutation::Violations { agent_id: req.from.to_string(), count });
+    }
+    state.decision_cache.invalidate_agent(&req.from);
+    Metrics::inc(&state.metrics.violations);
+    Err(GatewayError::EncryptedContent {
+        protocol_required: mode == EncryptedContentPolicy::RequireProtocol,
+    })
+}
+
+/// Evaluate receiver-side checks for every distinct recipient
+fn decide_recipients(
+    st: &InnerState,
+    policy: &Policy,
+    req: &SendMessageRequest,
+    protocol: Option<&str>,
+) -> BTreeMap<String, RecipientDecision> {
+    req.to
+        .list()
+        .into_iter()
+        .map(|to| (to.to_string(), recipient_decision(st, policy, &req.from, to, protocol)))
+        .collect()
+}
+
+fn log_recipient_rejected(state: &AppState, from: &str, to: &str, decision: &RecipientDecision) {
+    warn!(
+        from = %from,
+        to = %to,
+        event = "msg_rejected",
+        reason = decision.code.as_deref().unwrap_or("recipient_refused"),
+        detail = decision.reason.as_deref(),
+        "Recipient refused"
+    );
+    state.metrics.reject();
+}
+
+/// Send a test alert through every configured alert channel
+///
+/// The optional body selects which alert kind's template to exercise; a
+/// body that does not decode is refused with 400.
+async fn admin_test_alert(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+    OptionalPayload(body): OptionalPayload<TestAlertRequest>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    if !state.alerter.has_channels() {
+        return Err(GatewayError::NoAlertChannel);
+    }
+
+    let req = body.unwrap_or_default();
+    let dispatch = raise_alert(
+        &state,
+        req.kind.unwrap_or(AlertKind::Test),
+        req.agent_id.as_deref().map_or(AlertSubject::Gateway, AlertSubject::Agent),
+        "Test alert from the policy gateway",
+    )
+    .await;
+    let summary = format!(
+        "delivered={} rate_limited={} failed={}",
+        dispatch.delivered.len(),
+        dispatch.rate_limited.len(),
+        dispatch.failed.len()
+    );
+    if dispatch.delivered.is_empty() {
+        Err(GatewayError::AlertDeliveryFailed(summary))
+    } else {
+        Ok((StatusCode::OK, Json(ApiResponse::success_with_message(&summary))))
+    }
+}
+
+/// Reports held for review, oldest first
+async fn list_reviews(
+    State(state): State<AppState>,
+    _: AuthedAuditor,
+) -> Result<Json<Vec<PendingReview>>, GatewayError> {
+    let st = state.inner.read().unwrap();
+    Ok(Json(st.reviews.values().cloned().collect()))
+}
+
+/// Accept a held report as if it had passed the consistency check
+async fn approve_review(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+    Path(id): Path<u64>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let Some(review) = state.inner.write().unwrap().reviews.remove(&id) else {
+        return Err(GatewayError::NotFound("Unknown review id"));
+    };
+
+    info!(
+        agent_id = %review.report.agent_id,
+        protocol = %review.protocol,
+        review_id = id,
+        event = "review_approved",
+        "Held report approved"
+    );
+    {
+        let mut st = state.inner.write().unwrap();
+        let record = st.update_track_record(&review.report.agent_id, |r| r.reviews_approved += 1);
+        state.replication.record(record);
+    }
+    lift_suspension(&state, &review.report.agent_id, &review.protocol, "review");
+    accept_report(
+        &state,
+        &review.report,
+        review.redacted_summary.clone(),
+        &review.protocol,
+        review.honesty,
+        review.submitted_at,
+    );
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+/// Reject a held report; counts as a compliance violation
+async fn reject_review(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+    Path(id): Path<u64>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let review = {
+        let mut st = state.inner.write().unwrap();
+        let Some(review) = st.reviews.remove(&id) else {
+            return Err(GatewayError::NotFound("Unknown review id"));
+        };
+        let count = st.add_violation(&review.report.agent_id);
+        state.replication.record(Mutation::Violations { agent_id: review.report.agent_id.to_string(), count });
+        let record = st.update_track_record(&review.report.agent_id, |r| r.reviews_rejected += 1);
+        state.replication.record(record);
+        review
+    };
+    state.decision_cache.invalidate_agent(&review.report.agent_id);
+    Metrics::inc(&state.metrics.violations);
+
+    warn!(
+        agent_id = %review.report.agent_id,
+        protocol = %review.protocol,
+        review_id = id,
+        event = "report_rejected",
+        reason = "review_rejected",
+        violation = true,
+        consistency = review.consistency.score,
+        "Held report rejected on review"
+    );
+    let detail = format!(
+        "Report {id} on {} rejected on review (consistency {:.2}); recorded as a compliance violation",
+        review.protocol, review.consistency.score
+    );
+    let agent_id = review.report.agent_id.to_string();
+    tokio::spawn(mirror::carry(async move {
+        raise_alert(&state, AlertKind::ReportFraudDetected, AlertSubject::Agent(&agent_id), &detail).await;
+    }));
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+/// Encrypted messages held for review, oldest first
+async fn list_quarantine(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+) -> Result<Json<Vec<QuarantinedMessage>>, GatewayError> {
+    Ok(Json(state.quarantine.held()))
+}
+
+/// A quarantined message and the outcome of its review
+async fn quarantine_status(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+    Path(id): Path<u64>,
+) -> Result<Json<QuarantineStatus>, GatewayError> {
+    state
+        .quarantine
+        .status(id)
+        .map(Json)
+        .ok_or(GatewayError::NotFound("Unknown quarantine id"))
+}
+
+/// Release a quarantined message once its content has been reviewed
+async fn release_quarantined(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Path(id): Path<u64>,
+) -> Result<Response, GatewayError> {
+    admin_action(&state, &admin, AdminAction::ReleaseQuarantined { id })
+}
+
+/// Re-evaluate a reviewed message in the background; answers 202 with its
+/// status, pollable at `GET /quarantine/{id}`
+fn release_quarantined_message(state: &AppState, id: u64) -> Result<(StatusCode, Json<QuarantineStatus>), GatewayError> {
+    let (status, mut req) = state
+        .quarantine
+        .release(id)
+        .ok_or(GatewayError::NotFound("Unknown quarantine id"))?;
+    info!(
+        from = %req.from,
+        to = %req.to,
+        quarantine_id = id,
+        event = "quarantine_released",
+        "Quarantined message released"
+    );
+    req.released_from = Some(id);
+    let state = state.clone();
+    tokio::spawn(reevaluate_released(state, id, req).in_current_span());
+    Ok((StatusCode::ACCEPTED, Json(status)))
+}
+
+/// Deliver or refuse a message released from quarantine, as parked sends are
+/// once their report is in
+async fn reevaluate_released(state: AppState, id: u64, req: SendMessageRequest) {
+    let span = thread_span(req.thread_id.as_deref());
+    // Released sends are off the sender's request path, so their timing is dropped
+    let mut timing = PipelineTiming::start(Duration::ZERO);
+    let (outcome, code, body) = match deliver_send(&state, &req, &mut timing).instrument(span).await {
+        Ok((code, Json(body))) => (QuarantineState::Delivered, code, body),
+        Err(e) => (QuarantineState::Refused, e.status(), ApiResponse::from(e)),
+    };
+    info!(
+        from = %req.from,
+        quarantine_id = id,
+        outcome = outcome.as_str(),
+        status = code.as_u16(),
+        event = "quarantined_send_released",
+        "Quarantined message re-evaluated"
+    );
+    let body = serde_json::to_value(&body).unwrap_or_default();
+    if let Some((status, callback_url)) = state.quarantine.resolve(id, outcome, code.as_u16(), body, state.clock.now()) {
+        settle_quarantined(&state, &status, callback_url, Vec::new());
+    }
+}
+
+/// Keep a resolved quarantined message pollable for
+/// `QUARANTINE_RETENTION_SEC` and queue the callback it is owed, stored with
+/// `with`, the writes of the state change that resolved it
+fn settle_quarantined(state: &AppState, status: &QuarantineStatus, callback_url: Option<String>, with: Vec<Record>) {
+    let resolved_at = status.resolved_at.unwrap_or(status.quarantined_at);
+    state.timers.schedule(
+        TimerKind::QuarantineRetention,
+        &status.id.to_string(),
+        resolved_at + state.quarantine.config().retention_sec,
+    );
+    let Some(url) = callback_url else {
+        for record in with {
+            state.store.write(record);
+        }
+        return;
+    };
+    let payload = serde_json::to_value(status).unwrap_or_default();
+    let entry = OutboxEntry::new(outbox::QUARANTINE_CALLBACK, &url, payload, state.clock.now());
+    info!(
+        agent_id = %status.agent_id,
+        quarantine_id = status.id,
+        outbox_id = %entry.id,
+        event = "quarantine_callback_queued",
+        "Quarantined-send callback queued"
+    );
+    outbox::enqueue(state, entry, with);
+}
+
+/// Discard a quarantined message; counts as a compliance violation
+async fn discard_quarantined(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Path(id): Path<u64>,
+) -> Result<Response, GatewayError> {
+    admin_action(&state, &admin, AdminAction::DiscardQuarantined { id })
+}
+
+fn discard_quarantined_message(state: &AppState, id: u64) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let Some((status, held, callback_url)) = state.quarantine.discard(id, state.clock.now()) else {
+        return Err(GatewayError::NotFound("Unknown quarantine id"));
+    };
+    // The violation is stored with the callback it triggers
+    let violation = {
+        let mut st = state.inner.write().unwrap();
+        let count = st.add_violation(&held.from);
+        state.replication.record_unstored(Mutation::Violations { agent_id: held.from.to_string(), count })
+    };
+    state.decision_cache.invalidate_agent(&held.from);
+    Metrics::inc(&state.metrics.violations);
+
+    warn!(
+        from = %held.from,
+        to = %held.to,
+        quarantine_id = id,
+        event = "msg_rejected",
+        reason = "encrypted_content",
+        violation = true,
+        encoding = %status.detected.encoding,
+        "Quarantined message discarded"
+    );
+    settle_quarantined(state, &status, callback_url, violation);
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+/// Soft-delete an agent
+///
+/// Its sends, reports, and registrations are refused and it can no longer
+/// receive messages, but its protocols, report history, and violations are
+/// kept until the retention period elapses or it is restored.
+async fn delete_agent(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Path(agent_id): Path<String>,
+) -> Result<Response, GatewayError> {
+    admin_action(&state, &admin, AdminAction::DeleteAgent { agent_id })
+}
+
+fn soft_delete_agent(state: &AppState, agent_id: &str) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    {
+        let mut st = state.inner.write().unwrap();
+        if !st.protocols.contains_key(agent_id) {
+            return Err(GatewayError::NotFound("Unknown agent"));
+        }
+        if st.is_deleted(agent_id) {
+            return Err(GatewayError::Conflict("Agent already deleted"));
+        }
+        st.deleted_agents.insert(agent_id.to_string(), state.clock.now());
+        state.replication.record(Mutation::AgentDeleted {
+            agent_id: agent_id.to_string(),
+            ts: state.clock.now(),
+        });
+    }
+    state.decision_cache.invalidate_agent(agent_id);
+
+    let retention = state.policy.current().policy.deleted_agent_retention_sec;
+    info!(
+        agent_id = %agent_id,
+        retention_sec = retention,
+        event = "agent_deleted",
+        "Agent soft-deleted"
+    );
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message(&format!(
+            "Agent deleted; state is purged after {retention} seconds unless restored"
+        ))),
+    ))
+}
+
+/// Restore a soft-deleted agent with its retained state
+async fn restore_agent(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+    Path(agent_id): Path<String>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let deleted_at = {
+        let mut st = state.inner.write().unwrap();
+        let deleted_at = st.deleted_agents.remove(&agent_id);
+        if deleted_at.is_some() {
+            state.replication.record(Mutation::AgentRestored { agent_id: agent_id.clone() });
+        }
+        deleted_at
+    };
+    let Some(deleted_at) = deleted_at else {
+        return Err(GatewayError::NotFound("No deleted agent with this id"));
+    };
+    state.decision_cache.invalidate_agent(&agent_id);
+    reschedule_report_deadlines(&state);
+
+    info!(
+        agent_id = %agent_id,
+        deleted_at,
+        event = "agent_restored",
+        "Agent restored"
+    );
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+/// Periodically purge soft-deleted agents past the retention period
+async fn purge_deleted_agents(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(PURGE_SWEEP_INTERVAL_SEC));
+    loop {
+        interval.tick().await;
+        // A standby purges when the primary's purge is replicated
+        if state.replication.is_standby() {
+            continue;
+        }
+        let retention = state.policy.current().policy.deleted_agent_retention_sec;
+        let now = state.clock.now();
+        let purged = {
+            let mut st = state.inner.write().unwrap();
+            // An agent under investigation stays until its hold ends
+            let purged = st.purge_expired(now, retention, |id| state.holds.active(id));
+            for agent_id in &purged {
+                state.replication.record(Mutation::AgentPurged { agent_id: agent_id.clone() });
+                state.registry_sync.tombstone(agent_id, now);
+            }
+            purged
+        };
+        for agent_id in purged {
+            state.decision_cache.invalidate_agent(&agent_id);
+            state.translator.forget_agent(&agent_id);
+            state.quarantine.forget_agent(&agent_id);
+            state.slo.forget_agent(&agent_id);
+            state.quota.forget_agent(&agent_id);
+            state.soft_limit_notices.forget_agent(&agent_id);
+            info!(agent_id = %agent_id, event = "agent_purged", "Deleted agent purged");
+        }
+    }
+}
+
+/// Periodically reconcile the agent directory with the service registry
+async fn sync_discovery(state: AppState) {
+    let Some(source) = state.discovery.config().source else {
+        return;
+    };
+    let mut interval = tokio::time::interval(state.discovery.config().interval);
+    loop {
+        interval.tick().await;
+        // A standby follows the primary's directory
+        if state.replication.is_standby() {
+            continue;
+        }
+        let found = match state.discovery.fetch().await {
+            Ok(found) => found,
+            Err(error) => {
+                warn!(source = source.as_str(), error = %error, event = "discovery_failed", "Agent discovery sync failed");
+                continue;
+            }
+        };
+        let now = state.clock.now();
+        let changes = {
+            let mut st = state.inner.write().unwrap();
+            discovery::apply(&mut st, &state.replication, source, found, now)
+        };
+        for agent_id in &changes.added {
+            info!(agent_id = %agent_id, source = source.as_str(), event = "agent_discovered", "Agent imported from registry");
+        }
+        for (agent_id, team) in &changes.reassigned {
+            state.quota.set_owner(agent_id, team);
+            info!(
+                agent_id = %agent_id,
+                team = %team,
+                source = source.as_str(),
+                event = "agent_owner_set",
+                "Agent ownership updated from registry"
+            );
+        }
+        for agent_id in &changes.retired {
+            state.decision_cache.invalidate_agent(agent_id);
+            info!(agent_id = %agent_id, source = source.as_str(), event = "agent_retired", "Agent left the registry and was soft-deleted");
+        }
+        for agent_id in &changes.restored {
+            state.decision_cache.invalidate_agent(agent_id);
+            info!(agent_id = %agent_id, source = source.as_str(), event = "agent_restored", "Agent returned to the registry");
+        }
+        if !changes.restored.is_empty() {
+            reschedule_report_deadlines(&state);
+        }
+    }
+}
+
+/// Periodically raise alerts for new quota breaches and state store writes shed
+async fn quota_alerts(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(QUOTA_ALERT_INTERVAL_SEC));
+    loop {
+        interval.tick().await;
+        for breach in state.quota.take_breaches() {
+            // `subject` rather than `agent_id`, so the event never counts against the quota
+            warn!(
+                scope = %breach.scope,
+                subject = %breach.subject,
+                resource = %breach.resource,
+                used = breach.used,
+                limit = breach.limit,
+                action = ?state.quota.action(),
+                event = "quota_exceeded",
+                "Storage quota exceeded"
+            );
+            let subject = match breach.scope {
+                quota::Scope::Agent => AlertSubject::Agent(&breach.subject),
+                quota::Scope::Tenant => AlertSubject::Tenant(&breach.subject),
+            };
+            raise_alert(&state, AlertKind::QuotaExceeded, subject, &breach.to_string()).await;
+        }
+        let shed = state.store.take_shed();
+        if shed > 0 {
+            error!(
+                shed,
+                queued = state.store.depth(),
+                event = "store_writes_shed",
+                "State store queue full, records dropped"
+            );
+            let detail = format!("{shed} records were dropped because the state store queue was full; they will not survive a restart");
+            raise_alert(&state, AlertKind::StoreWritesShed, AlertSubject::Gateway, &detail).await;
+        }
+    }
+}
+
+/// Keep a resolved parked send pollable for `PARK_TIMEOUT_SEC`
+fn retain_park_outcome(state: &AppState, status: &ParkStatus) {
+    let resolved_at = status.resolved_at.unwrap_or(status.parked_at);
+    state.timers.schedule(
+        TimerKind::ParkRetention,
+        &status.id.to_string(),
+        resolved_at + state.parking.config().timeout_sec,
+    );
+}
+
+/// Queue the callback owed for a resolved parked send in the outbox
+fn queue_park_callback(state: &AppState, url: &str, status: &ParkStatus) {
+    let payload = serde_json::to_value(status).unwrap_or_default();
+    let entry = OutboxEntry::new(outbox::PARK_CALLBACK, url, payload, state.clock.now());
+    info!(
+        agent_id = %status.agent_id,
+        park_id = status.id,
+        outbox_id = %entry.id,
+        event = "park_callback_queued",
+        "Parked-send callback queued"
+    );
+    outbox::enqueue(state, entry, Vec::new());
+}
+
+/// Keep a resolved reservation's outcome for `RESERVATION_TTL_SEC`, so a
+/// retried commit or abort still gets its answer
+fn retain_reservation(state: &AppState, status: &ReservationStatus) {
+    let resolved_at = status.resolved_at.unwrap_or(status.reserved_at);
+    state.timers.schedule(
+        TimerKind::ReservationRetention,
+        &status.id.to_string(),
+        resolved_at + state.reservations.config().ttl_sec,
+    );
+}
+
+/// Keep a tracked message for `DELIVERY_TIMEOUT_SEC` from `from`, so late
+/// or repeated receipts still find it
+fn retain_delivery(state: &AppState, message_id: &str, from: u64) {
+    state.timers.schedule(
+        TimerKind::DeliveryRetention,
+        message_id,
+        from + state.deliveries.config().timeout_sec,
+    );
+}
+
+/// Count recipients of a message that never confirmed delivery against its
+/// protocol's coverage
+fn delivery_overdue(state: &AppState, status: &DeliveryStatus, flagged: &[String]) {
+    if let Some(key) = &status.protocol {
+        let report_key = format!("{}::{}", status.from, key);
+        if let Some(stats) = state.inner.write().unwrap().protocol_stats.get_mut(&report_key) {
+            stats.deliveries.undelivered += flagged.len() as u64;
+        }
+    }
+    warn!(
+        from = %status.from,
+        message_id = %status.message_id,
+        protocol = status.protocol.as_deref(),
+        recipients = ?flagged,
+        sent_at = status.sent_at,
+        event = "delivery_overdue",
+        "Allowed message not confirmed delivered in time"
+    );
+}
+
+/// Set the timer for the next report on `report_key`, last reported at `last`
+///
+/// Protocols never reported on are overdue from registration and get no timer.
+fn schedule_report_deadline(state: &AppState, st: &InnerState, report_key: &str, last: u64) {
+    if last > 0 {
+        let agent_id = report_key.split_once("::").map_or(report_key, |(agent_id, _)| agent_id);
+        let policy = state.policy.current();
+        let interval = st.reputation(&policy.policy, agent_id).report_interval_sec;
+        let interval = st.report_interval(&policy.policy, report_key, interval, state.clock.now());
+        state.timers.schedule(TimerKind::ReportDeadline, report_key, last + interval);
+    }
+}
+
+/// Reset every report deadline from the report clocks, and every
+/// recertification timer, e.g. after the policy changed or this gateway took
+/// over as primary
+fn reschedule_report_deadlines(state: &AppState) {
+    let st = state.inner.read().unwrap();
+    let deleted = |report_key: &str| {
+        report_key
+            .split_once("::")
+            .is_some_and(|(agent_id, _)| st.is_deleted(agent_id))
+    };
+    for (report_key, last) in &st.last_report_ts {
+        if deleted(report_key) {
+            state.timers.cancel(TimerKind::ReportDeadline, report_key);
+        } else {
+            schedule_report_deadline(state, &st, report_key, *last);
+        }
+    }
+    for report_key in st.protocol_stats.keys() {
+        if deleted(report_key) {
+            state.timers.cancel(TimerKind::Recertification, report_key);
+        } else {
+            schedule_recertification(state, &st, report_key);
+        }
+    }
+}
+
+/// Set the timer for the next recertification stage of `report_key`, or
+/// clear it when there is none
+fn schedule_recertification(state: &AppState, st: &InnerState, report_key: &str) {
+    let policy = state.policy.current();
+    let next = policy
+        .policy
+        .recertification
+        .as_ref()
+        .zip(st.protocol_stats.get(report_key))
+        .and_then(|(recert, stats)| recert.next_change(stats.certified_at(), state.clock.now()));
+    match next {
+        Some(at) => state.timers.schedule(TimerKind::Recertification, report_key, at),
+        None => {
+            state.timers.cancel(TimerKind::Recertification, report_key);
+        }
+    }
+}
+
+/// Alert the owner of the protocol behind `report_key` to the
+/// recertification stage it reached, and set the timer for the next one
+fn recertification_stage_reached(state: &AppState, report_key: &str, now: u64) {
+    let Some((agent_id, protocol)) = report_key.split_once("::") else {
+        return;
+    };
+    let policy = state.policy.current();
+    let Some(recert) = &policy.policy.recertification else {
+        return;
+    };
+    let (status, interval) = {
+        let st = state.inner.read().unwrap();
+        let registered = st.protocols.get(agent_id).is_some_and(|m| m.contains_key(protocol));
+        let Some(stats) = st.protocol_stats.get(report_key).filter(|_| registered && !st.is_deleted(agent_id)) else {
+            return;
+        };
+        let status = recert.status(stats.certified_at(), now);
+        schedule_recertification(state, &st, report_key);
+        if let Some(&last) = st.last_report_ts.get(report_key) {
+            schedule_report_deadline(state, &st, report_key, last);
+        }
+        let interval = st.reputation(&policy.policy, agent_id).report_interval_sec;
+        (status, st.report_interval(&policy.policy, report_key, interval, now))
+    };
+    state.decision_cache.invalidate_agent(agent_id);
+    let suspend_at = status.due_at.saturating_add(recert.grace_sec);
+    let (kind, detail) = match status.stage {
+        RecertStage::Certified => return,
+        RecertStage::Due => {
+            info!(
+                agent_id = %agent_id,
+                protocol = %protocol,
+                due_at = status.due_at,
+                event = "recertification_due",
+                "Protocol due for recertification"
+            );
+            let detail = format!(
+                "{protocol} must be recertified by {}; reports on it are then due every {} seconds at most until it is",
+                status.due_at, recert.restricted_report_interval_sec
+            );
+            (AlertKind::RecertificationDue, detail)
+        }
+        RecertStage::Restricted => {
+            warn!(
+                agent_id = %agent_id,
+                protocol = %protocol,
+                due_at = status.due_at,
+                suspend_at,
+                report_interval_sec = interval,
+                event = "recertification_restricted",
+                "Protocol restricted for a lapsed certification"
+            );
+            let detail = format!(
+                "{protocol} was not recertified by {}; reports on it are due every {interval} seconds, and sends on it are refused from {suspend_at}",
+                status.due_at
+            );
+            (AlertKind::RecertificationLapsed, detail)
+        }
+        RecertStage::Suspended => {
+            warn!(
+                agent_id = %agent_id,
+                protocol = %protocol,
+                due_at = status.due_at,
+                event = "recertification_suspended",
+                "Protocol suspended for a lapsed certification"
+            );
+            let detail = format!("{protocol} was not recertified by {suspend_at}; sends on it are refused until an admin recertifies it");
+            federation::announce_standing(state, agent_id, protocol, "");
+            (AlertKind::RecertificationLapsed, detail)
+        }
+    };
+    let state = state.clone();
+    let agent_id = agent_id.to_string();
+    tokio::spawn(mirror::carry(async move {
+        raise_alert(&state, kind, AlertSubject::Agent(&agent_id), &detail).await;
+    }));
+}
+
+/// Advance the timer wheel and act on the deadlines it reaches
+async fn run_timers(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(TIMER_TICK_SEC));
+    loop {
+        interval.tick().await;
+        let now = state.clock.now();
+        for timer in state.timers.advance(now) {
+            match timer.kind {
+                TimerKind::ReportDeadline => report_deadline_passed(&state, &timer.key, now),
+                TimerKind::ParkExpiry => {
+                    let Ok(id) = timer.key.parse() else { continue };
+                    let Some((status, callback_url)) = state.parking.expire(id, now) else {
+                        continue;
+                    };
+                    info!(
+                        from = %status.agent_id,
+                        protocol = %status.protocol,
+                        park_id = status.id,
+                        event = "parked_send_expired",
+                        "Parked message expired without a report"
+                    );
+                    retain_park_outcome(&state, &status);
+                    if let Some(url) = callback_url {
+                        queue_park_callback(&state, &url, &status);
+                    }
+                }
+                TimerKind::ParkRetention => {
+                    if let Ok(id) = timer.key.parse() {
+                        state.parking.forget(id);
+                    }
+                }
+                TimerKind::QuarantineRetention => {
+                    if let Ok(id) = timer.key.parse() {
+                        state.quarantine.forget(id);
+                    }
+                }
+                TimerKind::ReservationExpiry => {
+                    let Ok(id) = timer.key.parse() else { continue };
+                    let Some(status) = state.reservations.expire(id, now) else {
+                        continue;
+                    };
+                    warn!(
+                        from = %status.agent_id,
+                        reservation = status.id,
+                        recipients = ?status.recipients,
+                        reserved_at = status.reserved_at,
+                        event = "reservation_expired",
+                        "Reserved send neither committed nor aborted in time"
+                    );
+                    retain_reservation(&state, &status);
+                }
+                TimerKind::ReservationRetention => {
+                    if let Ok(id) = timer.key.parse() {
+                        state.reservations.forget(id);
+                    }
+                }
+                TimerKind::DeliveryTimeout => {
+                    if let Some((status, flagged)) = state.deliveries.time_out(&timer.key) {
+                        delivery_overdue(&state, &status, &flagged);
+                    }
+                    retain_delivery(&state, &timer.key, now);
+                }
+                TimerKind::DeliveryRetention => state.deliveries.forget(&timer.key),
+                // A standby graduates when the primary's graduation is replicated
+                TimerKind::TrialEnd if state.replication.is_standby() => {}
+                TimerKind::TrialEnd => {
+                    if let Some((agent_id, protocol)) = timer.key.split_once("::") {
+                        on_trial(&state, agent_id, protocol);
+                    }
+                }
+                // A standby leaves the alerts to the primary
+                TimerKind::Recertification if state.replication.is_standby() => {}
+                TimerKind::Recertification => recertification_stage_reached(&state, &timer.key, now),
+                TimerKind::HoldExpiry => {
+                    if let Some(hold) = state.holds.expire(&timer.key) {
+                        warn!(
+                            agent_id = %hold.agent_id,
+                            reason = %hold.reason,
+                            since = hold.since,
+                            event = "investigation_hold_expired",
+                            "Investigation hold ran out"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Log a report deadline that passed, unless a report or a longer interval,
+/// e.g. after the agent came off probation, moved it since the timer was set
+fn report_deadline_passed(state: &AppState, report_key: &str, now: u64) {
+    let Some((agent_id, protocol)) = report_key.split_once("::") else {
+        return;
+    };
+    let (last, interval) = {
+        let st = state.inner.read().unwrap();
+        if st.is_deleted(agent_id) {
+            return;
+        }
+        match st.last_report_ts.get(report_key) {
+            Some(&last) => {
+                let policy = state.policy.current();
+                let interval = st.reputation(&policy.policy, agent_id).report_interval_sec;
+                (last, st.report_interval(&policy.policy, report_key, interval, now))
+            }
+            None => return,
+        }
+    };
+    let due = last + interval;
+    if due > now {
+        state.timers.schedule(TimerKind::ReportDeadline, report_key, due);
+        return;
+    }
+    warn!(
+        agent_id = %agent_id,
+        protocol = %protocol,
+        seconds_since_report = now - last,
+        event = "report_deadline_passed",
+        "Report deadline passed; novel-language sends are refused until the next report"
+    );
+}
+
+/// Rotate the signing key once it outlives `SIGNING_ROTATE_SEC`
+async fn rotate_signing_keys(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(KEY_ROTATION_CHECK_SEC));
+    loop {
+        interval.tick().await;
+        let now = state.clock.now();
+        if state.signer.rotation_due(now) {
+            if let Some(kid) = state.signer.rotate(now) {
+                info!(kid = %kid, event = "signing_key_rotated", trigger = "schedule", "Signing key rotated");
+            }
+        }
+    }
+}
+
+/// Periodically compare the wall clock with the gateway's timeline
+async fn watch_clock(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(CLOCK_CHECK_INTERVAL_SEC));
+    loop {
+        interval.tick().await;
+        if let Some(skew) = state.clock_skew.check(&state.clock) {
+            warn!(
+                offset_sec = skew.offset_sec,
+                jump_sec = skew.jump_sec,
+                grace_until = ?skew.grace_until,
+                event = "clock_skew",
+                "Wall clock jumped away from the gateway's monotonic timeline"
+            );
+        }
+    }
+}
+
+/// Log each change of enforcement mode as schedules cross window boundaries
+async fn watch_enforcement(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(ENFORCEMENT_CHECK_INTERVAL_SEC));
+    loop {
+        interval.tick().await;
+        let policy = state.policy.current();
+        for transition in state.enforcement.update(policy.policy.enforcement.as_ref(), state.clock.now()) {
+            warn!(
+                schedule = %transition.schedule,
+                from = transition.from.as_str(),
+                to = transition.to.as_str(),
+                policy_version = %policy.version,
+                event = "enforcement_mode_changed",
+                "Enforcement mode changed"
+            );
+        }
+    }
+}
+
+/// Periodically raise alerts for tenants burning their SLO error budget too fast
+async fn slo_burn_alerts(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(SLO_SWEEP_INTERVAL_SEC));
+    loop {
+        interval.tick().await;
+        for tenant in state.slo.due_alerts(state.clock.now()) {
+            let detail = format!(
+                "Tenant {} is burning its report-coverage error budget at {:.1}x over {}s and {:.1}x over {}s (objective {}, reports within {}s)",
+                tenant.tenant,
+                tenant.long.burn_rate,
+                tenant.long.window_sec,
+                tenant.short.burn_rate,
+                tenant.short.window_sec,
+                tenant.objective,
+                tenant.report_within_sec,
+            );
+            raise_alert(&state, AlertKind::SloBurnRate, AlertSubject::Tenant(&tenant.tenant), &detail).await;
+            Metrics::inc(&state.metrics.slo_burn_alerts);
+        }
+    }
+}
+
+/// Put new policy thresholds in force; earlier versions stay retrievable
+async fn admin_load_policy(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Payload(policy): Payload<Policy>,
+) -> Result<Response, GatewayError> {
+    validate_policy(&policy)?;
+    admin_action(&state, &admin, AdminAction::LoadPolicy { policy: Box::new(policy) })
+}
+
+fn validate_policy(policy: &Policy) -> Result<(), GatewayError> {
+    if !(0.0..=1.0).contains(&policy.min_coverage) || policy.report_interval_sec == 0 {
+        return Err(GatewayError::Invalid(
+            "min_coverage must be within [0, 1] and report_interval_sec positive".to_string(),
+        ));
+    }
+    if let Some(probation) = &policy.probation {
+        probation.validate(policy.report_interval_sec).map_err(GatewayError::Invalid)?;
+    }
+    if let Some(routing) = &policy.routing {
+        routing.validate().map_err(GatewayError::Invalid)?;
+    }
+    if let Some(risk) = &policy.risk {
+        risk.validate().map_err(GatewayError::Invalid)?;
+    }
+    if let Some(trial) = &policy.trial {
+        trial
+            .validate(policy.min_coverage, policy.min_summary_length)
+            .map_err(GatewayError::Invalid)?;
+    }
+    if let Some(recert) = &policy.recertification {
+        recert.validate().map_err(GatewayError::Invalid)?;
+    }
+    if let Some(enforcement) = &policy.enforcement {
+        enforcement.validate().map_err(GatewayError::Invalid)?;
+    }
+    if let Some(quality) = &policy.summary_quality {
+        quality.validate().map_err(GatewayError::Invalid)?;
+    }
+    policy.soft_limits.validate().map_err(GatewayError::Invalid)
+}
+
+fn load_policy(state: &AppState, policy: Policy) -> Json<PolicySnapshot> {
+    let previous = state.policy.current().version.clone();
+    let (snapshot, new) = state.policy.load(policy);
+    if new {
+        state.store.write(Record::Policy(Box::new((*snapshot).clone())));
+    }
+    state.audit.set_policy_version(&snapshot.version);
+    reschedule_report_deadlines(state);
+    info!(
+        policy_version = %snapshot.version,
+        previous_version = %previous,
+        policy = %serde_json::to_string(&snapshot.policy).unwrap_or_default(),
+        event = "policy_loaded",
+        "Policy loaded"
+    );
+    Json((*snapshot).clone())
+}
+
+fn require_chaos(state: &AppState) -> Result<(), GatewayError> {
+    if state.chaos.enabled() {
+        Ok(())
+    } else {
+        Err(GatewayError::ChaosDisabled)
+    }
+}
+
+/// Public keys for verifying receipts
+async fn jwks(State(state): State<AppState>) -> Json<Jwks> {
+    Json(state.signer.jwks())
+}
+
+/// Verify a receipt against the published keys
+async fn verify_receipt(
+    State(state): State<AppState>,
+    Payload(req): Payload<VerifyReceiptRequest>,
+) -> Result<Json<VerifiedReceipt>, GatewayError> {
+    state
+        .signer
+        .verify(&req.receipt)
+        .map(Json)
+        .map_err(|e| GatewayError::Invalid(format!("Invalid receipt: {e}")))
+}
+
+/// Signing keys and their rotation state
+async fn admin_list_keys(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+) -> Result<Json<Vec<KeyInfo>>, GatewayError> {
+    Ok(Json(state.signer.list()))
+}
+
+/// Rotate the signing key now
+async fn admin_rotate_keys(State(state): State<AppState>, AuthedAdmin(admin): AuthedAdmin) -> Result<Response, GatewayError> {
+    admin_action(&state, &admin, AdminAction::RotateKeys)
+}
+
+fn rotate_signing_key(state: &AppState) -> Result<Json<Vec<KeyInfo>>, GatewayError> {
+    let kid = state
+        .signer
+        .rotate(state.clock.now())
+        .ok_or(GatewayError::Conflict("signing keys come from SIGNING_KEYS; rotate by changing it"))?;
+    info!(kid = %kid, event = "signing_key_rotated", trigger = "admin", "Signing key rotated");
+    Ok(Json(state.signer.list()))
+}
+
+/// Scan the state store for inconsistencies, repairing them when asked
+async fn admin_fsck(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Payload(req): Payload<FsckRequest>,
+) -> Result<Response, GatewayError> {
+    if req.repair {
+        return admin_action(&state, &admin, AdminAction::RepairState);
+    }
+    let now = state.clock.now();
+    let findings = fsck::scan(&state.inner.read().unwrap(), now);
+    info!(admin = %admin, findings = findings.len(), event = "state_checked", "State consistency checked");
+    Ok(Json(FsckReport {
+        scanned_at: now,
+        findings,
+        repaired: 0,
+    })
+    .into_response())
+}
+
+/// Apply every repair a fresh scan finds, replicating and logging each
+fn repair_state(state: &AppState) -> Json<FsckReport> {
+    let now = state.clock.now();
+    let (findings, repairs) = {
+        let mut st = state.inner.write().unwrap();
+        let findings = fsck::scan(&st, now);
+        let repairs: Vec<Repair> = findings.iter().filter_map(|f| f.repair.clone()).collect();
+        for repair in &repairs {
+            repair.apply(&mut st);
+        }
+        if !repairs.is_empty() {
+            state.replication.record(Mutation::StateRepaired { repairs: repairs.clone() });
+        }
+        (findings, repairs)
+    };
+    for finding in &findings {
+        let Some(repair) = &finding.repair else { continue };
+        if let Repair::DropReportClock { report_key } = repair {
+            state.timers.cancel(TimerKind::ReportDeadline, report_key);
+        }
+        state.decision_cache.invalidate_agent(repair.agent_id());
+        warn!(
+            kind = finding.kind.as_str(),
+            key = %finding.key,
+            detail = %finding.detail,
+            repair = %serde_json::to_string(repair).unwrap_or_default(),
+            event = "state_repaired",
+            "State inconsistency repaired"
+        );
+    }
+    if repairs.iter().any(|r| matches!(r, Repair::ClampReportClock { .. })) {
+        reschedule_report_deadlines(state);
+    }
+    Json(FsckReport {
+        scanned_at: now,
+        findings,
+        repaired: repairs.len(),
+    })
+}
+
+/// Storage usage and limits per tenant and agent
+async fn admin_quotas(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+) -> Result<Json<QuotaReport>, GatewayError> {
+    Ok(Json(state.quota.report()))
+}
+
+/// Benign-content allowlist patterns with their match counts
+async fn admin_patterns(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+) -> Result<Json<Vec<PatternStats>>, GatewayError> {
+    Ok(Json(state.allowlist.stats()))
+}
+
+/// Fault-injection rules currently in force
+async fn admin_get_chaos(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+) -> Result<Json<ChaosConfig>, GatewayError> {
+    require_chaos(&state)?;
+    Ok(Json(state.chaos.config()))
+}
+
+/// Replace the fault-injection rules; an empty rule set stops all faults
+async fn admin_set_chaos(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+    Payload(config): Payload<ChaosConfig>,
+) -> Result<Json<ChaosConfig>, GatewayError> {
+    require_chaos(&state)?;
+    config
+        .validate()
+        .map_err(GatewayError::Invalid)?;
+
+    warn!(
+        event = "chaos_configured",
+        rules = %serde_json::to_string(&config.rules).unwrap_or_default(),
+        "Fault-injection rules replaced"
+    );
+    state.chaos.set_config(config.clone());
+    Ok(Json(config))
+}
+
+/// Import historical messages and reports into the audit trail
+async fn admin_backfill(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+    Payload(req): Payload<BackfillRequest>,
+) -> Result<Json<BackfillSummary>, GatewayError> {
+    let summary = backfill::import(&state.audit, req, state.clock.now()).map_err(GatewayError::Invalid)?;
+    warn!(
+        event = "backfill_imported",
+        source = %summary.source,
+        messages = summary.messages,
+        reports = summary.reports,
+        "Historical records imported"
+    );
+    Ok(Json(summary))
+}
+
+/// Feature flags and their targeting
+async fn admin_get_flags(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+) -> Result<Json<FlagConfig>, GatewayError> {
+    Ok(Json(state.flags.config()))
+}
+
+/// Replace every feature flag; flags left out are off, and the behaviours
+/// they gated apply to everyone again
+async fn admin_set_flags(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+    Payload(config): Payload<FlagConfig>,
+) -> Result<Json<FlagConfig>, GatewayError> {
+    config.validate().map_err(GatewayError::Invalid)?;
+
+    warn!(
+        event = "flags_configured",
+        flags = %serde_json::to_string(&config.flags).unwrap_or_default(),
+        "Feature flags replaced"
+    );
+    state.flags.set_config(config.clone());
+    // Cached decisions were made under the old flags
+    state.decision_cache.clear();
+    Ok(Json(config))
+}
+
+/// Whether diagnostic logs currently include message content
+async fn admin_get_logging(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+) -> Result<Json<ContentLogging>, GatewayError> {
+    Ok(Json(state.scrubber.config(now_unix_sec())))
+}
+
+/// Switch verbose content logging on for a bounded window, or back off
+async fn admin_set_logging(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+    Payload(config): Payload<ContentLogging>,
+) -> Result<Json<ContentLogging>, GatewayError> {
+    let config = state
+        .scrubber
+        .set_config(config, now_unix_sec())
+        .map_err(GatewayError::Invalid)?;
+    warn!(
+        event = "content_logging_configured",
+        verbose_content = config.verbose_content,
+        reason = config.reason.as_deref(),
+        until = config.until,
+        "Verbose content logging changed"
+    );
+    Ok(Json(config))
+}
+
+/// Route groups currently paused for maintenance
+async fn admin_get_maintenance(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+) -> Result<Json<MaintenanceConfig>, GatewayError> {
+    Ok(Json(state.maintenance.config()))
+}
+
+/// Replace the paused route groups; `{"paused": {}}` ends maintenance
+async fn admin_set_maintenance(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+    Payload(config): Payload<MaintenanceConfig>,
+) -> Result<Json<MaintenanceConfig>, GatewayError> {
+    let config = state.maintenance.set_config(config, state.clock.now());
+    warn!(
+        event = "maintenance_configured",
+        paused = ?config.paused.keys().map(|g| g.as_str()).collect::<Vec<_>>(),
+        "Maintenance mode changed"
+    );
+    Ok(Json(config))
+}
+
+/// Client IPs currently blocked or throttled
+async fn admin_list_ip_rules(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+) -> Result<Json<BTreeMap<IpAddr, IpRule>>, GatewayError> {
+    Ok(Json(state.ips.rules(state.clock.now())))
+}
+
+fn parse_ip(ip: &str) -> Result<IpAddr, GatewayError> {
+    ip.parse()
+        .map_err(|_| GatewayError::Invalid(format!("{ip:?} is not an IP address")))
+}
+
+/// Block or throttle a client IP, replacing any rule it had
+async fn admin_set_ip_rule(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Path(ip): Path<String>,
+    Payload(mut rule): Payload<IpRule>,
+) -> Result<Json<IpRule>, GatewayError> {
+    let ip = parse_ip(&ip)?;
+    if rule.action == (IpAction::Throttle { per_minute: 0 }) {
+        return Err(GatewayError::Invalid("per_minute must be positive; block the IP instead".to_string()));
+    }
+    rule.since = state.clock.now();
+    rule.set_by = admin;
+    state.ips.set_rule(ip, rule.clone());
+    warn!(
+        event = "ip_rule_set",
+        ip = %ip,
+        action = rule.action.as_str(),
+        reason = rule.reason.as_deref(),
+        until = rule.until,
+        admin = %rule.set_by,
+        "Client IP rule set"
+    );
+    Ok(Json(rule))
+}
+
+/// Agents under an investigation hold
+async fn admin_list_holds(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+) -> Result<Json<BTreeMap<String, InvestigationHold>>, GatewayError> {
+    Ok(Json(state.holds.list()))
+}
+
+/// Place or replace an investigation hold on an agent
+async fn admin_set_hold(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Path(agent_id): Path<String>,
+    Payload(req): Payload<HoldRequest>,
+) -> Result<Json<InvestigationHold>, GatewayError> {
+    let hold = state.holds.set(&agent_id, req, &admin).map_err(GatewayError::Invalid)?;
+    state.timers.schedule(TimerKind::HoldExpiry, &agent_id, hold.until);
+    warn!(
+        agent_id = %agent_id,
+        reason = %hold.reason,
+        until = hold.until,
+        admin = %admin,
+        event = "investigation_hold_set",
+        "Investigation hold placed"
+    );
+    Ok(Json(hold))
+}
+
+/// Lift an investigation hold before it runs out
+async fn admin_release_hold(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Path(agent_id): Path<String>,
+) -> Result<Json<InvestigationHold>, GatewayError> {
+    let hold = state
+        .holds
+        .release(&agent_id)
+        .ok_or(GatewayError::NotFound("No hold on that agent"))?;
+    state.timers.cancel(TimerKind::HoldExpiry, &agent_id);
+    warn!(
+        agent_id = %agent_id,
+        reason = %hold.reason,
+        admin = %admin,
+        event = "investigation_hold_released",
+        "Investigation hold lifted"
+    );
+    Ok(Json(hold))
+}
+
+/// Lift the block or throttle of a client IP
+async fn admin_clear_ip_rule(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Path(ip): Path<String>,
+) -> Result<Json<IpRule>, GatewayError> {
+    let ip = parse_ip(&ip)?;
+    let rule = state.ips.clear_rule(ip).ok_or(GatewayError::NotFound("No rule for that IP"))?;
+    warn!(event = "ip_rule_cleared", ip = %ip, action = rule.action.as_str(), admin = %admin, "Client IP rule cleared");
+    Ok(Json(rule))
+}
+
+#[derive(Debug, Deserialize)]
+struct AuditSchemaQuery {
+    event: Option<String>,
+}
+
+/// JSON Schemas of the registered audit event kinds, or of one kind
+async fn audit_schema(Query(query): Query<AuditSchemaQuery>) -> Result<Json<serde_json::Value>, GatewayError> {
+    match query.event {
+        Some(event) => audit_schema::schema_of(&event)
+            .map(|schema| Json(schema.json_schema()))
+            .ok_or(GatewayError::NotFound("Unknown audit event kind")),
+        None => Ok(Json(audit_schema::document())),
+    }
+}
+
+/// Stream audit events as NDJSON, oldest first
+///
+/// The export is bounded by the log's high-water mark when the request
+/// arrives (`X-Audit-Cursor-End`); resume an interrupted export with
+/// `?cursor=<last seq + 1>`. `X-Audit-First-Seq` is the oldest retained
+/// event, so a cursor below it means events were lost to retention.
+async fn audit_export(
+    State(state): State<AppState>,
+    _: AuthedAuditor,
+    Query(query): Query<AuditExportQuery>,
+) -> Response {
+    let log = state.audit.clone();
+    let end = log.next_seq();
+    let first = log.first_seq().unwrap_or(end);
+    let from = query.cursor.unwrap_or(first);
+    let limit = query.limit.unwrap_or(usize::MAX);
+    let label = query.label;
+
+    // Each chunk is read from the log only when the client polls for it
+    let chunks = futures::stream::unfold((from, limit), move |(cursor, remaining)| {
+        let log = log.clone();
+        let label = label.clone();
+        async move {
+            if remaining == 0 || cursor >= end {
+                return None;
+            }
+            // A label filter may skip most of a page, so read whole pages
+            let size = if label.is_some() { AUDIT_EXPORT_PAGE } else { remaining.min(AUDIT_EXPORT_PAGE) };
+            let page = log.read_page(cursor, end, size);
+            let last = page.last()?.seq;
+            let mut chunk = Vec::with_capacity(page.len() * 256);
+            let mut written = 0;
+            let labelled = page.iter().filter(|e| label.as_ref().is_none_or(|l| e.labels.contains(l)));
+            for event in labelled.take(remaining) {
+                if serde_json::to_writer(&mut chunk, event).is_ok() {
+                    chunk.push(b'\n');
+                }
+                written += 1;
+            }
+            let next = (last + 1, remaining - written);
+            Some((Ok::<_, std::io::Error>(Bytes::from(chunk)), next))
+        }
+    })
+    // Compression encoders may poll again after the end of the stream
+    .fuse();
+
+    info!(event = "audit_export", cursor = from, cursor_end = end, "Audit export started");
+    (
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+            (header::HeaderName::from_static("x-audit-cursor-end"), end.to_string()),
+            (header::HeaderName::from_static("x-audit-first-seq"), first.to_string()),
+        ],
+        Body::from_stream(chunks),
+    )
+        .into_response()
+}
+
+/// Label every retained audit event matching a filter
+async fn annotate_audit(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Payload(mut req): Payload<AnnotateRequest>,
+) -> Result<Json<Annotation>, GatewayError> {
+    req.validate().map_err(GatewayError::Invalid)?;
+    let seqs = state.audit.annotate(&req.filter, &req.label);
+    let annotation = state.annotations.add(Annotation {
+        id: 0,
+        label: req.label,
+        note: req.note,
+        filter: req.filter,
+        matched: seqs.len(),
+        created_by: admin,
+        created_at: state.clock.now(),
+        seqs,
+    });
+    info!(
+        annotation_id = annotation.id,
+        label = %annotation.label,
+        matched = annotation.matched,
+        filter = %serde_json::to_string(&annotation.filter).unwrap_or_default(),
+        admin = %annotation.created_by,
+        event = "audit_annotated",
+        "Audit events annotated"
+    );
+    Ok(Json(annotation))
+}
+
+/// Annotations made so far, optionally with one label
+async fn list_annotations(
+    State(state): State<AppState>,
+    _: AuthedAuditor,
+    Query(query): Query<AnnotationQuery>,
+) -> Result<Json<Vec<Annotation>>, GatewayError> {
+    Ok(Json(state.annotations.list(query.label.as_deref())))
+}
+
+/// Delete an annotation and the label it put on events
+async fn delete_annotation(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Path(id): Path<u64>,
+) -> Result<Json<Annotation>, GatewayError> {
+    let (annotation, orphaned) = state
+        .annotations
+        .remove(id)
+        .ok_or(GatewayError::NotFound("Unknown annotation id"))?;
+    state.audit.unlabel(&orphaned, &annotation.label);
+    info!(
+        annotation_id = id,
+        label = %annotation.label,
+        unlabelled = orphaned.len(),
+        admin = %admin,
+        event = "audit_annotation_deleted",
+        "Audit annotation deleted"
+    );
+    Ok(Json(annotation))
+}
+
+/// Replication role, sequence, and follower status
+async fn admin_replication_status(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+) -> Result<Json<ReplicationStatus>, GatewayError> {
+    Ok(Json(state.replication.status()))
+}
+
+/// Registry sync peers with their lag and divergence
+async fn admin_registry_sync_status(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+) -> Result<Json<RegistrySyncStatus>, GatewayError> {
+    Ok(Json(state.registry_sync.status(state.clock.now())))
+}
+
+/// Promote a standby to primary: stop following and start accepting writes
+async fn admin_promote(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    if !state.replication.promote() {
+        return Err(GatewayError::Conflict("Already primary"));
+    }
+    reschedule_report_deadlines(&state);
+    warn!(
+        seq = state.replication.log.last_seq(),
+        event = "replication_promoted",
+        "Standby promoted to primary"
+    );
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+/// Thresholds for a policy version loaded by this gateway
+async fn get_policy(
+    State(state): State<AppState>,
+    Path(version): Path<String>,
+) -> Result<Json<PolicySnapshot>, GatewayError> {
+    let snapshot = if version == "current" {
+        Some(state.policy.current())
+    } else {
+        state.policy.get(&version)
+    };
+    match snapshot {
+        Some(snapshot) => Ok(Json((*snapshot).clone())),
+        None => Err(GatewayError::NotFound("Unknown policy version")),
+    }
+}
+
+/// Stamp every response with the policy version in force
+async fn stamp_policy_version(
+    State(state): State<AppState>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let pinned = state.policy.latest();
+    let (mut response, version) = policy::pin(pinned, async {
+        let response = next.run(req).await;
+        (response, state.policy.current().version.clone())
+    })
+    .await;
+    if let Ok(value) = header::HeaderValue::from_str(&version) {
+        response
+            .headers_mut()
+            .insert(header::HeaderName::from_static("x-policy-version"), value);
+    }
+    response
+}
+
+/// Agent directory, limited to the caller's team for team tokens
+async fn list_agents(
+    State(state): State<AppState>,
+    AuthedCaller(caller): AuthedCaller,
+) -> Result<Json<Vec<AgentEntry>>, GatewayError> {
+    let st = state.inner.read().unwrap();
+    let ids: BTreeSet<&String> = st
+        .protocols
+        .keys()
+        .chain(st.owners.keys())
+        .chain(st.discovered.keys())
+        .collect();
+    let entries = ids
+        .into_iter()
+        .filter(|id| caller.may_read_agent(&st, id))
+        .map(|id| AgentEntry::new(&st, id))
+        .collect();
+    Ok(Json(entries))
+}
+
+/// Assign an agent to its owning team
+async fn set_agent_owner(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+    Path(agent_id): Path<String>,
+    Payload(req): Payload<SetOwnerRequest>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    if req.team.trim().is_empty() {
+        return Err(GatewayError::Invalid("Team must not be empty".to_string()));
+    }
+    let previous = state
+        .inner
+        .write()
+        .unwrap()
+        .owners
+        .insert(agent_id.clone(), req.team.clone());
+    state.quota.set_owner(&agent_id, &req.team);
+    // The team decides the agent's language of record
+    state.decision_cache.invalidate_agent(&agent_id);
+    info!(
+        agent_id = %agent_id,
+        team = %req.team,
+        previous_team = previous.as_deref(),
+        event = "agent_owner_set",
+        "Agent ownership updated"
+    );
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+/// Assign a team to its owning org
+async fn set_team_org(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+    Path(team): Path<String>,
+    Payload(req): Payload<SetTeamRequest>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    if req.org.trim().is_empty() {
+        return Err(GatewayError::Invalid("Org must not be empty".to_string()));
+    }
+    state
+        .inner
+        .write()
+        .unwrap()
+        .team_orgs
+        .insert(team.clone(), req.org.clone());
+    info!(team = %team, org = %req.org, event = "team_org_set", "Team ownership updated");
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+/// Compliance totals for a team's agents
+async fn team_stats(
+    State(state): State<AppState>,
+    AuthedCaller(caller): AuthedCaller,
+    Path(team): Path<String>,
+) -> Result<Json<TeamRollup>, GatewayError> {
+    if !caller.may_read_team(&team) {
+        return Err(GatewayError::OutOfScope);
+    }
+    let st = state.inner.read().unwrap();
+    Ok(Json(ownership::team_rollup(&st, &team)))
+}
+
+/// Report-coverage SLO status per tenant, limited to the caller's team
+async fn slo_stats(
+    State(state): State<AppState>,
+    AuthedCaller(caller): AuthedCaller,
+) -> Result<Json<Vec<TenantSlo>>, GatewayError> {
+    let mut status = state.slo.status(state.clock.now());
+    status.retain(|t| caller.is_unscoped() || caller.may_read_team(&t.tenant));
+    Ok(Json(status))
+}
+
+/// Metered usage in a billing period, limited to the caller's team
+async fn billing_usage(
+    State(state): State<AppState>,
+    AuthedCaller(caller): AuthedCaller,
+    Query(query): Query<BillingQuery>,
+) -> Result<Response, GatewayError> {
+    let period = query
+        .period
+        .unwrap_or_else(|| state.meter.config().period.label(state.clock.now_f64()));
+    let report = state.meter.report(&period, |tenant| caller.may_read_team(tenant));
+    match query.format.as_deref() {
+        Some("csv") => {
+            let disposition = format!("attachment; filename=\"usage-{period}.csv\"");
+            Ok((
+                [
+                    (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                    (header::CONTENT_DISPOSITION, disposition),
+                ],
+                report.to_csv(),
+            )
+                .into_response())
+        }
+        None | Some("json") => Ok(Json(report).into_response()),
+        Some(other) => Err(GatewayError::Invalid(format!("Unknown format {other:?}; expected json or csv"))),
+    }
+}
+
+/// Who sent novel-language traffic to whom over a recent window
+///
+/// Team tokens see only edges with an endpoint owned by their team.
+async fn graph_edges(
+    State(state): State<AppState>,
+    AuthedCaller(caller): AuthedCaller,
+    Query(query): Query<GraphQuery>,
+) -> Result<Json<GraphResponse>, GatewayError> {
+    let window_sec = match &query.window {
+        Some(window) => graph::parse_window(window).map_err(GatewayError::Invalid)?,
+        None => graph::DEFAULT_WINDOW_SEC,
+    };
+    let since = state.clock.now().saturating_sub(window_sec);
+    let st = state.inner.read().unwrap();
+    let mut edges = st.graph.edges(query.agent.as_deref(), query.direction, since);
+    edges.retain(|e| caller.may_read_agent(&st, &e.from) || caller.may_read_agent(&st, &e.to));
+    Ok(Json(GraphResponse {
+        agent: query.agent,
+        window_sec,
+        since,
+        edges,
+    }))
+}
+
+/// Per-stage send latency percentiles over recent sends
+async fn latency_stats(
+    State(state): State<AppState>,
+    _: AuthedCaller,
+) -> Result<Json<Vec<StageLatency>>, GatewayError> {
+    Ok(Json(state.latency.summary()))
+}
+
+/// Request and rejection counts of the busiest client IPs
+///
+/// IPs span teams, so team tokens are refused.
+async fn ip_stats(
+    State(state): State<AppState>,
+    AuthedCaller(caller): AuthedCaller,
+    Query(query): Query<IpStatsQuery>,
+) -> Result<Json<Vec<IpUsage>>, GatewayError> {
+    if !caller.is_unscoped() {
+        return Err(GatewayError::OutOfScope);
+    }
+    Ok(Json(state.ips.usage(state.clock.now(), query.limit.unwrap_or(100))))
+}
+
+/// Compliance totals for an org, broken down by team
+async fn org_stats(
+    State(state): State<AppState>,
+    AuthedCaller(caller): AuthedCaller,
+    Path(org): Path<String>,
+) -> Result<Json<OrgRollup>, GatewayError> {
+    if !caller.is_unscoped() {
+        return Err(GatewayError::OutOfScope);
+    }
+    let st = state.inner.read().unwrap();
+    Ok(Json(ownership::org_rollup(&st, &org)))
+}
+
+/// A conversation's messages, reports, and audit events, limited to the
+/// agents the caller may read
+async fn get_thread(
+    State(state): State<AppState>,
+    AuthedCaller(caller): AuthedCaller,
+    Path(thread_id): Path<String>,
+) -> Result<Json<ThreadView>, GatewayError> {
+    let events = state.audit.thread_events(&thread_id);
+    let st = state.inner.read().unwrap();
+    threads::reconstruct(&st, &caller, &thread_id, events)
+        .map(Json)
+        .ok_or(GatewayError::NotFound("Unknown thread"))
+}
+
+/// Decide a send and hold its delivery open until committed or aborted
+///
+/// The decision is that of `/send`; when any recipient is allowed, the
+/// response also carries a reservation to resolve after delivery.
+async fn reserve_send(
+    State(state): State<AppState>,
+    Timed(Payload(req), decoded): Timed<Payload<SendMessageRequest>>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    if req.park {
+        return Err(GatewayError::Invalid("park cannot be combined with a reservation".to_string()));
+    }
+    state.reservations.check_capacity(&req.from)?;
+    let from = req.from.clone();
+    let to: Vec<String> = req.to.list().into_iter().map(str::to_string).collect();
+    let outcome = decide_send(&state, req, decoded).await;
+    let (code, Json(mut body)) = phrase_refusal(&state, &from, outcome)?;
+    // A broadcast refused for every recipient is still answered with its decisions
+    if !matches!(code, StatusCode::OK | StatusCode::MULTI_STATUS) {
+        return Ok((code, Json(body)));
+    }
+    let recipients: Vec<String> = match &body.decisions {
+        Some(decisions) => to.into_iter().filter(|r| decisions.get(r).is_some_and(|d| d.allowed)).collect(),
+        None => to,
+    };
+    if recipients.is_empty() {
+        return Ok((code, Json(body)));
+    }
+    let ticket = state.reservations.reserve(&from, recipients.clone(), state.clock.now());
+    state
+        .timers
+        .schedule(TimerKind::ReservationExpiry, &ticket.id.to_string(), ticket.expires_at);
+    info!(
+        from = %from,
+        reservation = ticket.id,
+        recipients = ?recipients,
+        expires_at = ticket.expires_at,
+        event = "send_reserved",
+        "Send reserved until committed or aborted"
+    );
+    body.reservation = Some(ticket);
+    Ok((code, Json(body)))
+}
+
+/// Body of `/send/commit` and `/send/abort`
+#[derive(Debug, Deserialize)]
+struct ResolveReservationRequest {
+    token: String,
+    /// Why delivery was abandoned; recorded with an abort
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Confirm delivery of a reserved send
+async fn commit_send(
+    State(state): State<AppState>,
+    Payload(req): Payload<ResolveReservationRequest>,
+) -> Result<Json<ReservationStatus>, GatewayError> {
+    resolve_reservation(&state, req, ReservationState::Committed)
+}
+
+/// Record that a reserved send was not delivered
+async fn abort_send(
+    State(state): State<AppState>,
+    Payload(req): Payload<ResolveReservationRequest>,
+) -> Result<Json<ReservationStatus>, GatewayError> {
+    resolve_reservation(&state, req, ReservationState::Aborted)
+}
+
+fn resolve_reservation(
+    state: &AppState,
+    req: ResolveReservationRequest,
+    outcome: ReservationState,
+) -> Result<Json<ReservationStatus>, GatewayError> {
+    let reason = match outcome {
+        ReservationState::Aborted => req.reason,
+        _ => None,
+    };
+    let (status, resolved) = state
+        .reservations
+        .resolve(&req.token, outcome, reason, state.clock.now())?;
+    if resolved {
+        state.timers.cancel(TimerKind::ReservationExpiry, &status.id.to_string());
+        retain_reservation(state, &status);
+        let event = match outcome {
+            ReservationState::Committed => "send_committed",
+            _ => "send_aborted",
+        };
+        info!(
+            from = %status.agent_id,
+            reservation = status.id,
+            recipients = ?status.recipients,
+            reason = status.reason.as_deref(),
+            event,
+            "Reserved send {}",
+            outcome.as_str()
+        );
+    }
+    Ok(Json(status))
+}
+
+/// Body of `POST /delivered/{message_id}`
+#[derive(Debug, Deserialize)]
+struct DeliveredRequest {
+    /// Recipient the message reached; every unconfirmed recipient when unset
+    #[serde(default)]
+    recipient: Option<String>,
+}
+
+/// Confirm that an allowed send reached its recipient
+async fn confirm_delivery(
+    State(state): State<AppState>,
+    Path(message_id): Path<String>,
+    Payload(req): Payload<DeliveredRequest>,
+) -> Result<Json<DeliveryStatus>, GatewayError> {
+    let now = state.clock.now();
+    let (status, changes) = state.deliveries.confirm(&message_id, req.recipient.as_deref(), now)?;
+    let changes: Vec<_> = changes.into_iter().filter(|(_, c)| *c != Confirmed::Repeat).collect();
+    if changes.is_empty() {
+        return Ok(Json(status));
+    }
+    if let Some(key) = &status.protocol {
+        let report_key = format!("{}::{}", status.from, key);
+        if let Some(stats) = state.inner.write().unwrap().protocol_stats.get_mut(&report_key) {
+            for (_, confirmed) in &changes {
+                stats.deliveries.confirmed(*confirmed);
+            }
+        }
+    }
+    for (to, confirmed) in &changes {
+        info!(
+            from = %status.from,
+            to = %to,
+            message_id = %status.message_id,
+            protocol = status.protocol.as_deref(),
+            late = *confirmed == Confirmed::Late,
+            event = "msg_delivered",
+            "Message delivery confirmed"
+        );
+    }
+    if status.is_settled() {
+        state.timers.cancel(TimerKind::DeliveryTimeout, &message_id);
+        retain_delivery(&state, &message_id, now);
+    }
+    Ok(Json(status))
+}
+
+/// Outcome of a parked send, scoped like other agent reads
+async fn parked_status(
+    State(state): State<AppState>,
+    AuthedCaller(caller): AuthedCaller,
+    Path(id): Path<u64>,
+) -> Result<Json<ParkStatus>, GatewayError> {
+    let status = state
+        .parking
+        .status(id)
+        .ok_or(GatewayError::NotFound("Unknown parked message"))?;
+    if !caller.may_read_agent(&state.inner.read().unwrap(), &status.agent_id) {
+        return Err(GatewayError::OutOfScope);
+    }
+    Ok(Json(status))
+}
+
+/// Usage analytics for one registered protocol
+async fn protocol_stats(
+    State(state): State<AppState>,
+    AuthedAgent { agent_id, .. }: AuthedAgent,
+    Path((_, name, version)): Path<(String, String, String)>,
+) -> Result<Json<ProtocolStatsResponse>, GatewayError> {
+    let key = protocol_key(&name, &version);
+    let st = state.inner.read().unwrap();
+
+    let registered = st.protocols.get(&agent_id).and_then(|m| m.get(&key));
+    let stats = st.protocol_stats.get(&format!("{agent_id}::{key}"));
+
+    match registered.zip(stats) {
+        Some((descriptor, stats)) => Ok(Json(ProtocolStatsResponse::new(
+            &agent_id,
+            &key,
+            &descriptor.risk_tier,
+            stats,
+            &state.policy.current().policy,
+            state.clock.now(),
+        ))),
+        None => Err(GatewayError::NotFound("Protocol not registered")),
+    }
+}
+
+/// Reputation of an agent and the report interval and codebook terms it earns
+async fn agent_reputation(
+    State(state): State<AppState>,
+    AuthedAgent { agent_id, .. }: AuthedAgent,
+) -> Result<Json<Reputation>, GatewayError> {
+    let st = state.inner.read().unwrap();
+    if !st.protocols.contains_key(&agent_id) && !st.track_records.contains_key(&agent_id) {
+        return Err(GatewayError::NotFound("Unknown agent"));
+    }
+    Ok(Json(st.reputation(&state.policy.current().policy, &agent_id)))
+}
+
+/// Summary quality of an agent's recent accepted reports and its trend
+async fn agent_summary_quality(
+    State(state): State<AppState>,
+    AuthedAgent { agent_id, .. }: AuthedAgent,
+) -> Result<Json<Trend>, GatewayError> {
+    let st = state.inner.read().unwrap();
+    if !st.protocols.contains_key(&agent_id) && !st.summary_quality.contains_key(&agent_id) {
+        return Err(GatewayError::NotFound("Unknown agent"));
+    }
+    Ok(Json(Trend::of(&agent_id, st.summary_quality.get(&agent_id))))
+}
+
+/// Documentation artifacts attached to a registered protocol
+async fn protocol_docs(
+    State(state): State<AppState>,
+    AuthedAgent { agent_id, .. }: AuthedAgent,
+    Path((_, name, version)): Path<(String, String, String)>,
+) -> Result<Json<ProtocolDocsResponse>, GatewayError> {
+    let key = protocol_key(&name, &version);
+    let st = state.inner.read().unwrap();
+    match st.protocols.get(&agent_id).and_then(|m| m.get(&key)) {
+        Some(descriptor) => Ok(Json(ProtocolDocsResponse {
+            agent_id,
+            protocol: key,
+            translation_method: descriptor.translation_method.clone(),
+            docs: descriptor.docs.clone(),
+        })),
+        None => Err(GatewayError::NotFound("Protocol not registered")),
+    }
+}
+
+/// Lift the suspension of a protocol whose reports kept failing review
+async fn reinstate_protocol(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Path((agent_id, name, version)): Path<(String, String, String)>,
+) -> Result<Response, GatewayError> {
+    admin_action(&state, &admin, AdminAction::ReinstateProtocol { agent_id, name, version })
+}
+
+fn reinstate_suspended_protocol(
+    state: &AppState,
+    agent_id: &str,
+    key: &str,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let registered = state
+        .inner
+        .read()
+        .unwrap()
+        .protocols
+        .get(agent_id)
+        .is_some_and(|m| m.contains_key(key));
+    if !registered {
+        return Err(GatewayError::NotFound("Protocol not registered"));
+    }
+    if !lift_suspension(state, agent_id, key, "admin") {
+        return Err(GatewayError::Conflict("Protocol is not suspended"));
+    }
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+/// Return a protocol raised by reassessment to its registered risk tier
+async fn reset_protocol_risk(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+    Path((agent_id, name, version)): Path<(String, String, String)>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let key = protocol_key(&name, &version);
+    let report_key = format!("{agent_id}::{key}");
+    let previous = {
+        let mut st = state.inner.write().unwrap();
+        if !st.protocols.get(&agent_id).is_some_and(|m| m.contains_key(&key)) {
+            return Err(GatewayError::NotFound("Protocol not registered"));
+        }
+        let Some(stats) = st.protocol_stats.get_mut(&report_key).filter(|s| !s.risk.is_default()) else {
+            return Err(GatewayError::Conflict("Protocol risk tier was not raised"));
+        };
+        let previous = std::mem::take(&mut stats.risk);
+        state.replication.record(Mutation::ProtocolRisk {
+            report_key: report_key.clone(),
+            risk: RiskStanding::default(),
+        });
+        if let Some(&last) = st.last_report_ts.get(&report_key) {
+            schedule_report_deadline(&state, &st, &report_key, last);
+        }
+        previous
+    };
+    state.decision_cache.invalidate_agent(&agent_id);
+    info!(
+        agent_id = %agent_id,
+        protocol = %key,
+        raised_to = previous.raised_to.map_or("", RiskTier::as_str),
+        event = "risk_tier_reset",
+        "Protocol risk tier reset"
+    );
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+/// Recertify a protocol, starting a new certification cycle from now
+async fn recertify_protocol(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+    Path((agent_id, name, version)): Path<(String, String, String)>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let key = protocol_key(&name, &version);
+    let report_key = format!("{agent_id}::{key}");
+    let now = state.clock.now();
+    let policy = state.policy.current();
+    let (previous, lapsed) = {
+        let mut st = state.inner.write().unwrap();
+        let descriptor = st.protocols.get(&agent_id).and_then(|m| m.get(&key)).cloned();
+        let Some((descriptor, stats)) = descriptor.zip(st.protocol_stats.get(&report_key)) else {
+            return Err(GatewayError::NotFound("Protocol not registered"));
+        };
+        let previous = stats.certified_at();
+        let lapsed = st.recert_stage(&policy.policy, &report_key, now) == Some(RecertStage::Suspended);
+        let mutation = Mutation::ProtocolRegistered {
+            agent_id: agent_id.clone(),
+            descriptor: Box::new(descriptor),
+            registered_at: stats.registered_at,
+            report_clock: None,
+            recertified_at: Some(now),
+        };
+        mutation.clone().apply(&mut st);
+        state.replication.record(mutation);
+        schedule_recertification(&state, &st, &report_key);
+        if let Some(&last) = st.last_report_ts.get(&report_key) {
+            schedule_report_deadline(&state, &st, &report_key, last);
+        }
+        (previous, lapsed)
+    };
+    state.decision_cache.invalidate_agent(&agent_id);
+    if lapsed {
+        federation::announce_standing(&state, &agent_id, &key, "recertified");
+    }
+    info!(
+        agent_id = %agent_id,
+        protocol = %key,
+        previously_certified_at = previous,
+        event = "protocol_recertified",
+        "Protocol recertified"
+    );
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+/// Body of `POST /protocols/{agent}/{name}/{version}/scope`
+#[derive(Debug, Deserialize)]
+struct ExpandScopeRequest {
+    add: Vec<String>,
+}
+
+/// Add recipients to the allowlist of a registered protocol
+async fn expand_protocol_scope(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Path((agent_id, name, version)): Path<(String, String, String)>,
+    Payload(req): Payload<ExpandScopeRequest>,
+) -> Result<Response, GatewayError> {
+    if req.add.is_empty() {
+        return Err(GatewayError::Invalid("Name at least one recipient to add".to_string()));
+    }
+    validate_scope(&req.add).map_err(GatewayError::Invalid)?;
+    let protocol = protocol_key(&name, &version);
+    admin_action(&state, &admin, AdminAction::ExpandProtocolScope { agent_id, protocol, add: req.add.into() })
+}
+
+fn widen_protocol_scope(
+    state: &AppState,
+    agent_id: &str,
+    key: &str,
+    add: Box<[String]>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let report_key = format!("{agent_id}::{key}");
+    let added = {
+        let mut st = state.inner.write().unwrap();
+        let descriptor = st.protocols.get(agent_id).and_then(|m| m.get(key)).cloned();
+        let Some((mut descriptor, stats)) = descriptor.zip(st.protocol_stats.get(&report_key)) else {
+            return Err(GatewayError::NotFound("Protocol not registered"));
+        };
+        if descriptor.allowed_recipients.is_empty() {
+            return Err(GatewayError::Conflict("Protocol scope is unrestricted"));
+        }
+        let added: BTreeSet<String> =
+            add.into_vec().into_iter().filter(|r| !descriptor.allowed_recipients.contains(r)).collect();
+        descriptor.allowed_recipients.extend(added.iter().cloned());
+        let mutation = Mutation::ProtocolRegistered {
+            agent_id: agent_id.to_string(),
+            descriptor: Box::new(descriptor),
+            registered_at: stats.registered_at,
+            report_clock: None,
+            recertified_at: stats.recertified_at,
+        };
+        mutation.clone().apply(&mut st);
+        state.replication.record(mutation);
+        added
+    };
+    state.decision_cache.invalidate_agent(agent_id);
+    info!(
+        agent_id = %agent_id,
+        protocol = %key,
+        added = ?added,
+        event = "protocol_scope_expanded",
+        "Protocol scope expanded"
+    );
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+// =============================================================================
+// Dual Control
+// =============================================================================
+
+/// Take an admin action as `admin`, or propose it when it needs a second admin
+fn admin_action(state: &AppState, admin: &str, action: AdminAction) -> Result<Response, GatewayError> {
+    if !state.approvals.requires(action.kind()) {
+        return perform_admin_action(state, action, admin, None);
+    }
+    let Some(proposal) = state.approvals.propose(action, admin, state.clock.now()) else {
+        return Err(GatewayError::Conflict("The same action is already awaiting approval"));
+    };
+    info!(
+        approval_id = proposal.id,
+        action = %proposal.action.kind(),
+        target = %proposal.action.target(),
+        proposed_by = %admin,
+        expires_at = proposal.expires_at,
+        event = "admin_action_proposed",
+        "Admin action awaiting approval"
+    );
+    Ok((StatusCode::ACCEPTED, Json(proposal)).into_response())
+}
+
+/// Run an admin action; events it logs carry the admins behind it
+fn perform_admin_action(
+    state: &AppState,
+    action: AdminAction,
+    admin: &str,
+    approved_by: Option<&str>,
+) -> Result<Response, GatewayError> {
+    let span = match approved_by {
+        Some(approver) => tracing::info_span!("admin", admin, approved_by = approver),
+        None => tracing::info_span!("admin", admin),
+    };
+    let _admin = span.entered();
+    let (kind, target) = (action.kind(), action.target());
+    let response = match action {
+        AdminAction::DeleteAgent { agent_id } => soft_delete_agent(state, &agent_id)?.into_response(),
+        AdminAction::DiscardQuarantined { id } => discard_quarantined_message(state, id)?.into_response(),
+        AdminAction::ReleaseQuarantined { id } => release_quarantined_message(state, id)?.into_response(),
+        AdminAction::ReinstateProtocol { agent_id, name, version } => {
+            reinstate_suspended_protocol(state, &agent_id, &protocol_key(&name, &version))?.into_response()
+        }
+        AdminAction::ExpandProtocolScope { agent_id, protocol, add } => {
+            widen_protocol_scope(state, &agent_id, &protocol, add)?.into_response()
+        }
+        AdminAction::LoadPolicy { policy } => load_policy(state, *policy).into_response(),
+        AdminAction::RotateKeys => rotate_signing_key(state).into_response(),
+        AdminAction::RepairState => repair_state(state).into_response(),
+        AdminAction::ChangeProtocolDescriptor { agent_id, descriptor, .. } => {
+            let req = RegisterProtocolRequest { agent_id, protocol: *descriptor, trial: false };
+            register_protocol(state, req, true)?.into_response()
+        }
+    };
+    info!(action = %kind, target = %target, event = "admin_action_performed", "Admin action performed");
+    Ok(response)
+}
+
+/// Drop and log proposals past their TTL
+fn expire_proposals(state: &AppState) {
+    for proposal in state.approvals.expire(state.clock.now()) {
+        log_expired(&proposal);
+    }
+}
+
+fn log_expired(proposal: &Proposal) {
+    info!(
+        approval_id = proposal.id,
+        action = %proposal.action.kind(),
+        target = %proposal.action.target(),
+        proposed_by = %proposal.proposed_by,
+        event = "admin_action_expired",
+        "Admin action expired unapproved"
+    );
+}
+
+/// Admin actions awaiting a second admin
+async fn admin_list_approvals(
+    State(state): State<AppState>,
+    _: AuthedAdmin,
+) -> Result<Json<ApprovalQueue>, GatewayError> {
+    expire_proposals(&state);
+    Ok(Json(state.approvals.queue()))
+}
+
+/// Approve and run another admin's proposed action
+async fn admin_approve_action(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Path(id): Path<u64>,
+) -> Result<Response, GatewayError> {
+    let proposal = match state.approvals.approve(id, &admin, state.clock.now()) {
+        Ok(proposal) => proposal,
+        Err(Refusal::Unknown) => return Err(GatewayError::NotFound("Unknown approval id")),
+        Err(Refusal::SelfApproval) => return Err(GatewayError::SelfApproval),
+        Err(Refusal::Expired(proposal)) => {
+            log_expired(&proposal);
+            return Err(GatewayError::ApprovalExpired);
+        }
+    };
+    info!(
+        approval_id = id,
+        action = %proposal.action.kind(),
+        target = %proposal.action.target(),
+        proposed_by = %proposal.proposed_by,
+        approved_by = %admin,
+        event = "admin_action_approved",
+        "Admin action approved"
+    );
+    perform_admin_action(&state, proposal.action, &proposal.proposed_by, Some(&admin))
+}
+
+/// Reject or withdraw a proposed action
+async fn admin_reject_action(
+    State(state): State<AppState>,
+    AuthedAdmin(admin): AuthedAdmin,
+    Path(id): Path<u64>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let Some(proposal) = state.approvals.reject(id) else {
+        return Err(GatewayError::NotFound("Unknown approval id"));
+    };
+    info!(
+        approval_id = id,
+        action = %proposal.action.kind(),
+        target = %proposal.action.target(),
+        proposed_by = %proposal.proposed_by,
+        rejected_by = %admin,
+        event = "admin_action_rejected",
+        "Admin action rejected"
+    );
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+// =============================================================================
+// Main
+// =============================================================================
+
+/// Every route of the gateway, without middleware
+fn routes() -> Routes {
+    let routes = Routes::new()
+        .route("/health", get(health))
+        .route("/health/ready", get(health_ready))
+        .route("/status", get(gateway_status))
+        .route("/metrics", get(metrics_endpoint))
+        .route("/register_protocol_for_agent", post(register_protocol_for_agent))
+        .route("/report", post(submit_report))
+        .route("/report_batch", post(batch::submit))
+        .route("/send", post(send_message))
+        .route("/send/stream", get(stream::send_stream))
+        .route("/send/reserve", post(reserve_send))
+        .route("/send/commit", post(commit_send))
+        .route("/send/abort", post(abort_send))
+        .route("/attachments/:agent_id", post(attachments::upload))
+        .route("/parked/:id", get(parked_status))
+        .route("/delivered/:message_id", post(confirm_delivery))
+        .route("/threads/:id", get(get_thread))
+        .route("/graph/edges", get(graph_edges))
+        .route("/protocols/:agent_id/:name/:version/stats", get(protocol_stats))
+        .route("/protocols/:agent_id/:name/:version/docs", get(protocol_docs))
+        .route("/protocols/:agent_id/:name/:version/adopt", post(adopt_protocol))
+        .route("/protocols/conflicts", get(protocol_conflicts))
+        .route("/protocols/:agent_id/:name/:version/reinstate", post(reinstate_protocol))
+        .route("/protocols/:agent_id/:name/:version/risk/reset", post(reset_protocol_risk))
+        .route("/protocols/:agent_id/:name/:version/recertify", post(recertify_protocol))
+        .route("/protocols/:agent_id/:name/:version/scope", post(expand_protocol_scope))
+        .route("/reviews", get(list_reviews))
+        .route("/reviews/:id/approve", post(approve_review))
+        .route("/reviews/:id/reject", post(reject_review))
+        .route("/reviews/findings", get(undeclared::list))
+        .route("/reviews/findings/:id/confirm", post(undeclared::confirm))
+        .route("/reviews/findings/:id/dismiss", post(undeclared::dismiss))
+        .route("/quarantine", get(list_quarantine))
+        .route("/quarantine/:id", get(quarantine_status))
+        .route("/quarantine/:id/release", post(release_quarantined))
+        .route("/quarantine/:id/discard", post(discard_quarantined))
+        .route("/agents", get(list_agents))
+        .route("/agents/:agent_id", delete(delete_agent))
+        .route("/agents/:agent_id/restore", post(restore_agent))
+        .route("/agents/:agent_id/dormancy/keep", post(dormancy::keep))
+        .route("/agents/:agent_id/dormancy/confirm", post(dormancy::confirm))
+        .route("/agents/:agent_id/owner", put(set_agent_owner))
+        .route("/agents/:agent_id/reputation", get(agent_reputation))
+        .route("/agents/:agent_id/summary-quality", get(agent_summary_quality))
+        .route("/teams/:team", put(set_team_org))
+        .route("/teams/:team/stats", get(team_stats))
+        .route("/orgs/:org/stats", get(org_stats))
+        .route("/stats/slo", get(slo_stats))
+        .route("/stats/latency", get(latency_stats))
+        .route("/stats/ips", get(ip_stats))
+        .route("/billing/usage", get(billing_usage))
+        .route("/policies/:version", get(get_policy))
+        .route("/audit/schema", get(audit_schema))
+        .route("/audit/export", get(audit_export))
+        .route("/audit/search", post(audit_search::handle))
+        .route("/audit/annotations", get(list_annotations).post(annotate_audit))
+        .route("/audit/annotations/:id", delete(delete_annotation))
+        .route("/admin/backfill", post(admin_backfill))
+        .route("/admin/outbox", get(outbox::list))
+        .route("/admin/dormant", get(dormancy::list))
+        .route("/admin/alerts/test", post(admin_test_alert))
+        .route("/admin/policy", put(admin_load_policy))
+        .route(
+            "/admin/policy/experiment",
+            get(experiment::report).put(experiment::start).delete(experiment::stop),
+        )
+        .route("/admin/patterns", get(admin_patterns))
+        .route("/admin/quotas", get(admin_quotas))
+        .route("/admin/keys", get(admin_list_keys))
+        .route("/admin/keys/rotate", post(admin_rotate_keys))
+        .route("/admin/fsck", post(admin_fsck))
+        .route("/admin/approvals", get(admin_list_approvals))
+        .route("/admin/approvals/:id/approve", post(admin_approve_action))
+        .route("/admin/approvals/:id/reject", post(admin_reject_action))
+        .route("/.well-known/jwks.json", get(jwks))
+        .route("/receipts/verify", post(verify_receipt))
+        .route("/admin/chaos", get(admin_get_chaos).put(admin_set_chaos))
+        .route("/admin/maintenance", get(admin_get_maintenance).put(admin_set_maintenance))
+        .route("/admin/flags", get(admin_get_flags).put(admin_set_flags))
+        .route("/admin/logging", get(admin_get_logging).put(admin_set_logging))
+        .route("/admin/ips", get(admin_list_ip_rules))
+        .route("/admin/ips/:ip", put(admin_set_ip_rule).delete(admin_clear_ip_rule))
+        .route("/admin/holds", get(admin_list_holds))
+        .route("/admin/holds/:agent_id", put(admin_set_hold).delete(admin_release_hold))
+        .route("/admin/replication", get(admin_replication_status))
+        .route("/admin/replication/promote", post(admin_promote))
+        .route("/replication/stream", get(replication::stream))
+        .route("/admin/registry-sync", get(admin_registry_sync_status))
+        .route("/registry/snapshot", get(registry_sync::snapshot))
+        .route("/federation/suspensions", post(federation::receive))
+        .route("/forward", post(forwarding::receive));
+    #[cfg(feature = "runtime-diagnostics")]
+    let routes = routes.route("/debug/runtime", get(diagnostics::runtime));
+    #[cfg(feature = "dashboard")]
+    let routes = routes.route("/ui", get(dashboard::index)).route("/ui/:asset", get(dashboard::asset));
+    routes
+}
+
+/// Build the gateway router with every route and middleware layer
+fn router(state: AppState, security: &SecurityConfig, max_body_bytes: usize) -> Router {
+    let app = routes().into_router();
+    // Each group is boxed once; layering middleware one by one would have
+    // every request clone the boxed stack beneath each of them
+    let app = app.layer(
+        ServiceBuilder::new()
+            .layer(axum::middleware::from_fn_with_state(state.clone(), ownership::auditor_access))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), authz::authorize))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), maintenance::pause_routes))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), replication::refuse_writes_on_standby))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), stamp_policy_version))
+            .layer(axum::middleware::from_fn(negotiate_response)),
+    );
+    let app = if state.chaos.enabled() {
+        warn!(event = "chaos_enabled", "Fault injection enabled; do not run in production");
+        app.layer(axum::middleware::from_fn_with_state(state.clone(), chaos::inject_faults))
+    } else {
+        app
+    };
+    let app = app.layer(
+        ServiceBuilder::new()
+            // Outermost, so every refusal is phrased in the caller's locale
+            .layer(axum::middleware::from_fn_with_state(state.clone(), messages::negotiate_locale))
+            // Next, so the audit events and metrics of every refusal carry the trace
+            .layer(axum::middleware::from_fn_with_state(state.clone(), exemplars::trace_context))
+            // Outside the rest, so blocked IPs cost no further work
+            .layer(axum::middleware::from_fn_with_state(state.clone(), ips::account_requests))
+            // Outside the timeout, so a copy is taken before the request can time out
+            .layer(axum::middleware::from_fn_with_state(state.clone(), mirror::mirror_requests))
+            // Outside fault injection, so injected delays surface as timeouts
+            .layer(axum::middleware::from_fn_with_state(state.clone(), timeouts::bound_requests)),
+    );
+    let app = with_content_encoding(app, max_body_bytes);
+    security.apply(app).with_state(state)
+}
+
+/// Meter the live events stored up to `last_seq`; returns how many were read
+async fn restore_usage(meter: &Meter, store: &dyn StateStore, last_seq: u64) -> Result<usize, String> {
+    let (mut cursor, mut read) = (1, 0);
+    while cursor <= last_seq {
+        let page = store.audit(cursor, AUDIT_EXPORT_PAGE).await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        cursor = last.seq + 1;
+        read += page.len();
+        let backfilled = |e: &&AuditEvent| e.fields.get("backfilled").and_then(|v| v.as_bool()) == Some(true);
+        let live = page.iter().filter(|e| e.seq <= last_seq && !backfilled(e));
+        for event in live {
+            meter.observe(event);
+        }
+    }
+    Ok(read)
+}
+
+/// Configure the gateway from the environment and serve it until Ctrl+C
+///
+/// This is the `policy_gateway` binary; embedders call it from their own
+/// runtime.
+pub async fn run() {
+    // Initialize logging; the audit layer sees every event regardless of RUST_LOG
+    let audit = Arc::new(AuditLog::from_env());
+    let scrubber = Arc::new(LogScrubber::default());
+    let (format, fields) = ScrubbedJson::new(scrubber.clone());
+    let sampling = SamplingConfig::from_env();
+    let sampler = Arc::new(Sampler::new(sampling.clone().unwrap_or_default()));
+    let subscriber = tracing_subscriber::registry()
+        .with(SamplingLayer::new(sampler.clone()))
+        .with(
+            fmt::layer()
+                .event_format(format)
+                .fmt_fields(fields)
+                .with_filter(EnvFilter::from_default_env().add_directive(Level::INFO.into())),
+        )
+        .with(AuditLayer::new(audit.clone()));
+    #[cfg(feature = "runtime-diagnostics")]
+    let subscriber = subscriber.with(diagnostics::console_layer());
+    subscriber.init();
+
+    let meter = Arc::new(Meter::new(MeterConfig::from_env()));
+
+    // Open the state store before anything worth keeping is audited
+    let state_store = store::open_from_env().unwrap_or_else(|e| panic!("Cannot open the state store: {e}"));
+    let persistence = match &state_store {
+        Some(store) => {
+            let last_seq = store
+                .last_audit_seq()
+                .await
+                .unwrap_or_else(|e| panic!("Cannot read the state store: {e}"));
+            audit.resume_after(last_seq);
+            let restored = restore_usage(&meter, store.as_ref(), last_seq)
+                .await
+                .unwrap_or_else(|e| panic!("Cannot read the state store: {e}"));
+            info!(events = restored, event = "billing_usage_restored", "Billing usage rebuilt from the stored audit trail");
+            let capacity = env_parse("STATE_STORE_QUEUE_CAPACITY").unwrap_or(store::DEFAULT_QUEUE_CAPACITY);
+            let reserve = env_parse("STATE_STORE_QUEUE_RESERVE").unwrap_or(store::DEFAULT_QUEUE_RESERVE);
+            let batching = store::Batching {
+                max_records: env_parse("STATE_STORE_BATCH_MAX").unwrap_or(store::DEFAULT_BATCH_MAX),
+                interval: env_parse("STATE_STORE_FLUSH_INTERVAL_MS")
+                    .map(Duration::from_millis)
+                    .unwrap_or_default(),
+            };
+            let persistence = Arc::new(Persistence::start(store.clone(), capacity, reserve, batching));
+            audit.set_store(persistence.clone());
+            persistence
+        }
+        None => Arc::new(Persistence::default()),
+    };
+    audit.set_meter(meter.clone());
+
+    match sampling {
+        Ok(_) if !sampler.is_empty() => info!(
+            rates = ?sampler.rates().collect::<BTreeMap<_, _>>(),
+            event = "log_sampling_configured",
+            "Log and audit event sampling configured"
+        ),
+        Ok(_) => {}
+        Err(e) => warn!(event = "config_invalid", error = %e, "Ignoring invalid LOG_SAMPLING"),
+    }
+
+    let detector_config = DetectorConfig::from_env();
+    info!(
+        classifier = detector_config.url.is_some(),
+        fallback = ?detector_config.fallback,
+        ensemble = ?detector_config
+            .ensemble
+            .as_ref()
+            .map(|e| e.voters.iter().map(|(v, _)| v.as_str()).collect::<Vec<_>>()),
+        event = "detector_configured",
+        "Language detector configured"
+    );
+    let detector =
+        Detector::new(detector_config).unwrap_or_else(|e| panic!("Cannot build the classifier client: {e}"));
+    let allowlist = ContentAllowlist::from_env();
+    info!(
+        patterns = ?allowlist.names(),
+        event = "allowlist_configured",
+        "Benign content allowlist configured"
+    );
+    let translation_config = TranslationConfig::from_env();
+    info!(
+        enabled = translation_config.url.is_some(),
+        protocols = ?translation_config.protocols,
+        event = "translation_configured",
+        "Translation hook configured"
+    );
+    let slo = SloTracker::new(SloConfig::from_env());
+    info!(
+        objective = slo.config().objective,
+        report_within_sec = slo.config().report_within_sec,
+        burn_rate_alert = slo.config().burn_rate_alert,
+        event = "slo_configured",
+        "Compliance SLO configured"
+    );
+    let clock_skew = SkewMonitor::new(SkewConfig::from_env());
+    info!(
+        threshold_sec = clock_skew.config().threshold_sec,
+        grace_sec = clock_skew.config().grace_sec,
+        mode = ?clock_skew.config().mode,
+        event = "clock_skew_configured",
+        "Clock skew detection configured"
+    );
+    let clock = Arc::new(Clock::default());
+    let signer = KeyRing::new(SigningConfig::from_env(), clock.now());
+    info!(
+        kid = %signer.active_kid(),
+        configured_keys = signer.config().keys.len(),
+        rotate_sec = signer.config().rotate_sec,
+        retain_sec = signer.config().retain_sec,
+        event = "signing_configured",
+        "Receipt signing configured"
+    );
+    let quota = Arc::new(QuotaTracker::new(QuotaConfig::from_env(), clock.clone()));
+    audit.set_quota(quota.clone());
+    let holds = Arc::new(Holds::new(clock.clone()));
+    audit.set_holds(holds.clone());
+    info!(
+        action = ?quota.action(),
+        event = "quota_configured",
+        "Storage quotas configured"
+    );
+    let parking = ParkLot::new(ParkConfig::from_env());
+    info!(
+        timeout_sec = parking.config().timeout_sec,
+        callbacks = !parking.config().callback_prefixes.is_empty(),
+        event = "parking_configured",
+        "Overdue-send parking configured"
+    );
+    let reservations = Reservations::new(ReservationConfig::from_env());
+    info!(
+        ttl_sec = reservations.config().ttl_sec,
+        event = "reservations_configured",
+        "Two-phase send reservations configured"
+    );
+    let deliveries = Deliveries::new(DeliveryConfig::from_env());
+    info!(
+        timeout_sec = deliveries.config().timeout_sec,
+        event = "delivery_receipts_configured",
+        "Delivery receipt tracking configured"
+    );
+    let webhooks =
+        DecisionHooks::new(webhooks::rules_from_env().unwrap_or_else(|e| panic!("Invalid DECISION_WEBHOOKS: {e}")));
+    if !webhooks.rules().is_empty() {
+        info!(
+            rules = webhooks.rules().len(),
+            event = "decision_webhooks_configured",
+            "Decision webhooks configured"
+        );
+    }
+    let namespace = Namespace::from_env();
+    if namespace.is_global() {
+        info!(event = "protocol_namespace_configured", mode = "global", "Protocol names are unique gateway-wide");
+    }
+    let degradation = DegradationPolicy::from_env();
+    if !degradation.rules().is_empty() {
+        info!(
+            rules = degradation.rules().len(),
+            event = "degradation_policy_configured",
+            "Degradation policy for internal errors configured"
+        );
+    }
+    let discovery = Discovery::new(DiscoveryConfig::from_env());
+    if let Some(source) = discovery.config().source {
+        info!(
+            source = source.as_str(),
+            url = %discovery.config().url,
+            interval_sec = discovery.config().interval.as_secs(),
+            import_keys = discovery.config().import_keys,
+            event = "discovery_configured",
+            "Agent discovery configured"
+        );
+    }
+    let maintenance = Maintenance::from_env(clock.now());
+    let paused = maintenance.config().paused;
+    if !paused.is_empty() {
+        warn!(
+            paused = ?paused.keys().map(|g| g.as_str()).collect::<Vec<_>>(),
+            event = "maintenance_configured",
+            "Starting with route groups paused for maintenance"
+        );
+    }
+    let registry_sync = RegistrySync::new(RegistrySyncConfig::from_env());
+    if registry_sync.config().token.is_some() {
+        info!(
+            name = %registry_sync.config().name,
+            public_key = %registry_sync.public_key(),
+            peers = ?registry_sync.config().peers.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(),
+            interval_sec = registry_sync.config().interval.as_secs(),
+            event = "registry_sync_configured",
+            "Protocol registry sync configured"
+        );
+    }
+    let forwarding = Forwarding::new(ForwardingConfig::from_env());
+    if forwarding.config().token.is_some() {
+        info!(
+            name = %forwarding.config().name,
+            peers = ?forwarding.config().peers.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(),
+            routes = forwarding.config().routes.len(),
+            event = "forwarding_configured",
+            "Gateway forwarding configured"
+        );
+    }
+    let languages = LanguageConfig::from_env();
+    if languages.is_configured() {
+        info!(
+            default = ?languages.default.as_ref().map(|r| r.languages.iter().map(|l| l.code).collect::<Vec<_>>()),
+            tenants = ?languages.tenants.keys().collect::<Vec<_>>(),
+            event = "language_of_record_configured",
+            "Language of record configured"
+        );
+    }
+    let catalog = Catalog::from_env();
+    if !catalog.locales().is_empty() {
+        info!(
+            locales = ?catalog.locales(),
+            event = "message_catalog_loaded",
+            "Response message catalog loaded"
+        );
+    }
+    let ips = IpTracker::new(IpConfig::from_env());
+    if !ips.config().trusted_proxies.is_empty() {
+        info!(
+            trusted_proxies = ?ips.config().trusted_proxies,
+            event = "trusted_proxies_configured",
+            "Honoring X-Forwarded-For from trusted proxies"
+        );
+    }
+    let auditor_tokens = AuditorTokens::from_env();
+    if !auditor_tokens.is_empty() {
+        info!(
+            auditors = auditor_tokens.len(),
+            event = "auditors_configured",
+            "Read-only auditor tokens configured"
+        );
+    }
+    let admin_tokens = AdminTokens::from_env();
+    if !admin_tokens.is_empty() {
+        info!(admins = admin_tokens.len(), event = "admin_tokens_configured", "Named admin tokens configured");
+    }
+    let approvals = Approvals::from_env();
+    if approvals.actions().next().is_some() {
+        info!(
+            actions = ?approvals.actions().map(|a| a.to_string()).collect::<Vec<_>>(),
+            ttl_sec = approvals.queue().ttl_sec,
+            event = "dual_control_configured",
+            "Dual control configured for admin actions"
+        );
+    }
+    let policy = PolicyRegistry::new(Policy::from_env());
+    let current = policy.current();
+    audit.set_policy_version(&current.version);
+    info!(
+        policy_version = %current.version,
+        policy = %serde_json::to_string(&current.policy).unwrap_or_default(),
+        event = "policy_loaded",
+        "Policy loaded"
+    );
+
+    let max_body_bytes = codec::max_body_bytes_from_env();
+    let mirror = Mirror::from_env(max_body_bytes);
+    if mirror.enabled() {
+        info!(
+            url = ?mirror.config().url,
+            rate = mirror.config().rate,
+            routes = ?mirror.config().routes.iter().map(|g| g.as_str()).collect::<Vec<_>>(),
+            event = "mirroring_configured",
+            "Traffic mirroring to a shadow gateway configured"
+        );
+    } else if mirror.config().token.is_some() {
+        info!(event = "mirroring_configured", shadow = true, "Serving mirrored traffic as a shadow gateway");
+    }
+    let authz = AuthzPolicy::from_env().unwrap_or_else(|e| panic!("Invalid AUTHZ_POLICY: {e}"));
+    if let Err(e) = authz.check_routes(routes().paths()) {
+        panic!("Incomplete authorization policy: {e}");
+    }
+    let demo = demo::requested();
+    let admin_token = match std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()) {
+        Some(token) => Some(token),
+        None if demo => {
+            let token = demo::generate_token();
+            // No `event` field, so the token stays out of the audit trail
+            warn!(admin_token = %token, "No ADMIN_TOKEN set, generated one for the demo");
+            Some(token)
+        }
+        None => None,
+    };
+    let state = AppState {
+        detector: Arc::new(detector),
+        allowlist: Arc::new(allowlist),
+        decision_cache: Arc::new(DecisionCache::from_env()),
+        registration_misses: Arc::new(MissCache::from_env()),
+        alerter: Arc::new(Alerter::from_env()),
+        audit,
+        policy: Arc::new(policy),
+        translator: Arc::new(Translator::new(translation_config)),
+        chaos: Arc::new(FaultInjector::from_env()),
+        maintenance: Arc::new(maintenance),
+        flags: Arc::new(FeatureFlags::from_env()),
+        scrubber,
+        sampler,
+        clock_skew: Arc::new(clock_skew),
+        signer: Arc::new(signer),
+        quota,
+        holds,
+        meter,
+        parking: Arc::new(parking),
+        quarantine: Arc::new(Quarantine::new(QuarantineConfig::from_env())),
+        outbox: Arc::new(Outbox::new(OutboxConfig::from_env())),
+        reservations: Arc::new(reservations),
+        deliveries: Arc::new(deliveries),
+        ips: Arc::new(ips),
+        timers: Arc::new(Timers::new(clock.now())),
+        webhooks: Arc::new(webhooks),
+        degradation: Arc::new(degradation),
+        discovery: Arc::new(discovery),
+        clock,
+        slo: Arc::new(slo),
+        streams: Arc::new(SendStreams::from_env(max_body_bytes)),
+        timeouts: Arc::new(RequestTimeouts::from_env()),
+        replication: Arc::new(Replication::new(ReplicationConfig::from_env())),
+        registry_sync: Arc::new(registry_sync),
+        federation: Arc::new(federation::Federation::new(federation::FederationConfig::from_env())),
+        forwarding: Arc::new(forwarding),
+        mirror: Arc::new(mirror),
+        namespace: Arc::new(namespace),
+        retries: Arc::new(Retries::new(RetryConfig::from_env())),
+        report_batches: Arc::new(BatchConfig::from_env()),
+        attachments: Arc::new(Attachments::new(AttachmentConfig::from_env())),
+        audit_search: Arc::new(SearchConfig::from_env()),
+        exemplars: ExemplarConfig::from_env(),
+        dormancy: Arc::new(Dormancy::new(DormancyConfig::from_env())),
+        undeclared: Arc::new(Undeclared::new(UndeclaredConfig::from_env())),
+        authz: Arc::new(authz),
+        languages: Arc::new(languages),
+        catalog: Arc::new(catalog),
+        store: persistence.clone(),
+        admin_token: admin_token.map(Arc::from),
+        admin_tokens: Arc::new(admin_tokens),
+        approvals: Arc::new(approvals),
+        change_control: Arc::new(ChangeControl::from_env()),
+        team_tokens: Arc::new(TeamTokens::from_env()),
+        auditor_tokens: Arc::new(auditor_tokens),
+        ..AppState::default()
+    };
+
+    if let Some(store) = &state_store {
+        store::load(&state, &**store)
+            .await
+            .unwrap_or_else(|e| panic!("Cannot load from the state store: {e}"));
+        state.replication.set_store(persistence.clone());
+    }
+
+    tokio::spawn(purge_deleted_agents(state.clone()));
+    tokio::spawn(slo_burn_alerts(state.clone()));
+    tokio::spawn(watch_clock(state.clone()));
+    tokio::spawn(watch_enforcement(state.clone()));
+    tokio::spawn(rotate_signing_keys(state.clone()));
+    tokio::spawn(quota_alerts(state.clone()));
+    tokio::spawn(run_timers(state.clone()));
+    tokio::spawn(outbox::run(state.clone()));
+    tokio::spawn(dormancy::run(state.clone()));
+    tokio::spawn(undeclared::run(state.clone()));
+    tokio::spawn(sync_discovery(state.clone()));
+    tokio::spawn(replication::follow(state.clone()));
+    tokio::spawn(registry_sync::run(state.clone()));
+    tokio::spawn(federation::run(state.clone()));
+    info!(
+        role = state.replication.status().role,
+        event = "replication_configured",
+        "Replication role"
+    );
+
+    let security = SecurityConfig::from_env_and_args().unwrap_or_else(|e| panic!("Invalid CORS settings: {e}"));
+    if security.dev {
+        warn!(event = "dev_mode", "Running with permissive --dev CORS and security headers");
+    }
+
+    let tls = TlsConfig::from_env().unwrap_or_else(|e| panic!("Invalid TLS configuration: {e}"));
+    let rustls = match &tls {
+        Some(tls) => {
+            let rustls = tls::load(tls)
+                .await
+                .unwrap_or_else(|e| panic!("Cannot load the TLS certificate: {e}"));
+            tls::watch(tls.clone(), rustls.clone(), state.tls_reloads.clone())
+                .unwrap_or_else(|e| panic!("Cannot watch the TLS certificate: {e}"));
+            Some(rustls)
+        }
+        None => None,
+    };
+
+    let demo_token = state.admin_token.as_deref().map(str::to_string);
+    let app = router(state, &security, max_body_bytes);
+    if let (true, Some(token)) = (demo, demo_token) {
+        warn!(event = "demo_mode", "Demo mode: generating synthetic agent traffic");
+        tokio::spawn(demo::run(app.clone(), token));
+    }
+
+    let addr: SocketAddr = std::env::var("LISTEN_ADDR")
+        .ok()
+        .and_then(|a| a.parse().ok())
+        .unwrap_or_else(|| "0.0.0.0:8080".parse().unwrap());
+    
+    info!(
+        address = %addr,
+        tls = rustls.is_some(),
+        event = "gateway_started",
+        "Policy Gateway listening"
+    );
+
+    // Graceful shutdown on Ctrl+C
+    match rustls {
+        Some(rustls) => tls::serve(addr, rustls, app, shutdown_signal()).await,
+        None => {
+            let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(shutdown_signal())
+                .await
+                .unwrap();
+        }
+    }
+    persistence.flush().await;
+}
+
+async fn shutdown_signal() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to install Ctrl+C handler");
+    info!(event = "shutdown", "Shutting down gracefully");
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+    use testing::{AgentFixture, ProtocolFixture, SendFixture, TestGateway};
+
+    #[test]
+    fn test_looks_like_english() {
+        assert!(looks_like_english("Hello, how are you today?"));
+        assert!(looks_like_english("The quick brown fox jumps over the lazy dog."));
+        assert!(looks_like_english(""));
+        assert!(looks_like_english("   "));
+        
+        // Should flag as non-English
+        assert!(!looks_like_english("X9|d=17;u=0x3f;rt=2;ack#77"));
+        assert!(!looks_like_english("CMD|seq=0;state=0x00"));
+        assert!(!looks_like_english("αβγδ")); // Non-ASCII
+        assert!(!looks_like_english("xyzxyzxyzxyzxyzxyzxyzxyzxyzxyzxyzxyzxyz")); // No vowels
+    }
+
+    #[test]
+    fn test_unused_protocol_recommendation() {
+        let now = 10 * 86_400 + 1_000_000;
+        let mut stats = ProtocolStats {
+            registered_at: 1_000_000,
+            ..ProtocolStats::default()
+        };
+        let resp = ProtocolStatsResponse::new("a", "p:1", "low", &stats, &Policy::default(), now);
+        assert!(resp.recommendation.is_some());
+        assert!(resp.average_coverage.is_none());
+
+        stats.last_used_ts = Some(now - 60);
+        stats.reports_filed = 2;
+        stats.coverage_sum = 1.9;
+        let resp = ProtocolStatsResponse::new("a", "p:1", "low", &stats, &Policy::default(), now);
+        assert!(resp.recommendation.is_none());
+        assert_eq!(resp.average_coverage, Some(0.95));
+    }
+
+    #[test]
+    fn test_broadcast_outcome() {
+        assert!(serde_json::from_str::<Recipients>(r#"["b", " "]"#).is_err());
+        let to: Recipients = serde_json::from_str(r#"["b", "c", "b", "d"]"#).unwrap();
+        assert_eq!(to.list(), vec!["b", "c", "d"]);
+
+        let mut st = InnerState::default();
+        st.deleted_agents.insert("d".to_string(), 0);
+        let decisions: BTreeMap<_, _> = to
+            .list()
+            .into_iter()
+            .map(|r| (r.to_string(), recipient_decision(&st, &Policy::default(), "a", r, None)))
+            .collect();
+        let (code, Json(body)) = send_outcome(&to, decisions).unwrap();
+        assert_eq!(code, StatusCode::MULTI_STATUS);
+        let decisions = body.decisions.unwrap();
+        assert!(decisions["b"].allowed && decisions["c"].allowed);
+        assert!(!decisions["d"].allowed);
+
+        let one: Recipients = serde_json::from_str(r#""b""#).unwrap();
+        let (code, Json(body)) = send_outcome(&one, BTreeMap::from([("b".into(), RecipientDecision::allow())])).unwrap();
+        assert_eq!(code, StatusCode::OK);
+        assert!(body.decisions.is_none());
+        let refused = send_outcome(&one, BTreeMap::from([("b".into(), RecipientDecision::deny("Recipient agent deleted"))]));
+        assert_eq!(refused.unwrap_err(), GatewayError::RecipientRefused("Recipient agent deleted".into()));
+    }
+
+    #[test]
+    fn test_soft_delete_and_purge() {
+        let mut st = InnerState::default();
+        for agent in ["a", "ab"] {
+            st.protocols.entry(agent.into()).or_default();
+            st.last_report_ts.insert(format!("{agent}::p:1"), 5);
+            st.protocol_stats.insert(format!("{agent}::p:1"), ProtocolStats::default());
+        }
+        st.violations.insert("a".into(), 2);
+        st.deleted_agents.insert("a".into(), 1_000);
+
+        assert!(!recipient_decision(&st, &Policy::default(), "ab", "a", None).allowed);
+        assert!(recipient_decision(&st, &Policy::default(), "a", "ab", None).allowed);
+
+        // Retained until the retention period has fully elapsed
+        assert!(st.purge_expired(1_099, 100, |_| false).is_empty());
+        assert_eq!(st.violations.get("a"), Some(&2));
+
+        assert_eq!(st.purge_expired(1_100, 100, |_| false), vec!["a".to_string()]);
+        assert!(!st.protocols.contains_key("a") && !st.is_deleted("a"));
+        assert!(st.violations.is_empty());
+        assert_eq!(st.last_report_ts.keys().collect::<Vec<_>>(), vec!["ab::p:1"]);
+        assert_eq!(st.protocol_stats.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_lists_every_deny_reason() {
+        let gw = TestGateway::new();
+        let coord = ProtocolFixture::new("coord", "1.0").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(coord)).await;
+        assert_eq!(gw.admin(Method::DELETE, "/agents/a", None::<&()>).await.status, StatusCode::OK);
+
+        let unregistered = ProtocolFixture::new("other", "1.0").build();
+        let resp = gw.send(&SendFixture::novel("a", "b", &unregistered, "SHP|eta=7f").build()).await;
+        assert_eq!(resp.status, StatusCode::FORBIDDEN);
+        assert_eq!(resp.body["code"], "agent_deleted", "the first reason is the primary one");
+        let reasons = resp.body["deny_reasons"].as_array().unwrap();
+        let codes: Vec<_> = reasons.iter().map(|r| r["code"].as_str().unwrap()).collect();
+        assert_eq!(codes, ["agent_deleted", "protocol_not_registered"]);
+        assert!(reasons[1]["remediation"].as_str().unwrap().contains("/register_protocol_for_agent"));
+    }
+
+    #[tokio::test]
+    async fn test_send_outside_protocol_scope() {
+        let gw = TestGateway::new();
+        let coord = ProtocolFixture::new("coord", "1.0").allow_recipient("b").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(coord.clone()).reported()).await;
+
+        assert_eq!(gw.send(&SendFixture::novel("a", "b", &coord, "SHP|eta=7f").build()).await.status, StatusCode::OK);
+        let resp = gw.send(&SendFixture::novel("a", "c", &coord, "SHP|eta=7f").build()).await;
+        assert_eq!(resp.status, StatusCode::FORBIDDEN);
+        assert_eq!(resp.body["code"], "scope_violation");
+
+        let body = serde_json::json!({"add": ["c"]});
+        let resp = gw.admin(Method::POST, "/protocols/a/coord/1.0/scope", Some(&body)).await;
+        assert_eq!(resp.status, StatusCode::OK);
+        assert_eq!(gw.send(&SendFixture::novel("a", "c", &coord, "SHP|eta=7f").build()).await.status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_schema_enforcement_follows_its_flag() {
+        let gw = TestGateway::with_decision_cache(DecisionCache::new(100, Duration::from_secs(60)));
+        let ship = ProtocolFixture::new("ship", "1.0")
+            .message_schema(serde_json::json!({"type": "object", "required": ["op"]}))
+            .build();
+        gw.setup_agent(AgentFixture::new("a").protocol(ship.clone()).reported()).await;
+        let send = SendFixture::novel("a", "b", &ship, "SHP|eta=7f").build();
+
+        let flags = serde_json::json!({"flags": {"message_schema": {"agents": ["c"]}}});
+        gw.admin(Method::PUT, "/admin/flags", Some(&flags)).await;
+        assert_eq!(gw.send(&send).await.status, StatusCode::OK, "not rolled out to a");
+
+        let flags = serde_json::json!({"flags": {"message_schema": {"rollout_percent": 100}}});
+        gw.admin(Method::PUT, "/admin/flags", Some(&flags)).await;
+        let resp = gw.send(&send).await;
+        assert_eq!(resp.body["code"], "schema_violation", "the cached decision was dropped");
+    }
+
+    #[tokio::test]
+    async fn test_send_to_nobody_refused() {
+        let gw = TestGateway::new();
+        gw.setup_agent(AgentFixture::new("a")).await;
+        let resp = gw.send(&SendFixture::english("a", "b").to_many(&[]).build()).await;
+        assert_eq!(resp.status, StatusCode::BAD_REQUEST);
+        assert_eq!(resp.body["code"], "invalid_request");
+    }
+
+    #[tokio::test]
+    async fn test_allowlisted_json_carrying_other_language() {
+        let gw = TestGateway::with_allowlist("json");
+        gw.setup_agent(AgentFixture::new("a")).await;
+        let mut req = SendFixture::english("a", "b").build();
+        req.content = r#"{"status": "ok", "count": 3}"#.into();
+        assert_eq!(gw.send(&req).await.status, StatusCode::OK);
+        req.content = r#"{"status": "ok", "note": "Привет, как у тебя дела сегодня"}"#.into();
+        let resp = gw.send(&req).await;
+        assert_eq!(resp.status, StatusCode::FORBIDDEN, "{}", resp.body);
+    }
+
+    #[test]
+    fn test_protocol_key() {
+        assert_eq!(protocol_key("test", "1.0"), "test:1.0");
+        assert_eq!(protocol_key("my_protocol", "2.3.4"), "my_protocol:2.3.4");
+    }
+}
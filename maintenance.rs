@@ -0,0 +1,274 @@
+//! Scoped maintenance mode
+//!
+//! Route groups can be paused one at a time, e.g. report ingestion during a
+//! storage migration, while the rest of the gateway stays live: `/send` keeps
+//! deciding from the decision cache and in-memory state. Paused routes answer
+//! 503 with `code: maintenance` and the reason given when pausing, plus
+//! `Retry-After` when an expected end was given.
+//!
+//! Groups start paused from `MAINTENANCE_PAUSED` and are switched at runtime
+//! via `PUT /admin/maintenance`; `/health/ready` lists the paused groups.
+//! `/health*`, `/metrics`, `/admin/*`, replication, and receipt verification
+//! belong to no group and are never paused.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env, fmt, sync::RwLock};
+use tracing::{info, warn};
+
+use crate::{error::GatewayError, AppState};
+
+/// Routes paused together
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteGroup {
+    /// `/send`, `/send/*`, and `/parked/{id}`
+    Send,
+    /// `/report`
+    Reports,
+    /// `/register_protocol_for_agent` and protocol adoption
+    Registration,
+    /// `/reviews*`, `/quarantine*`, and protocol reinstatement and
+    /// recertification
+    Reviews,
+    /// Agent deletion, restore, and ownership
+    Directory,
+    /// Stats, graph, thread, policy, and audit reads
+    Reads,
+}
+
+impl RouteGroup {
+    pub const ALL: [Self; 6] = [
+        Self::Send,
+        Self::Reports,
+        Self::Registration,
+        Self::Reviews,
+        Self::Directory,
+        Self::Reads,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Send => "send",
+            Self::Reports => "reports",
+            Self::Registration => "registration",
+            Self::Reviews => "reviews",
+            Self::Directory => "directory",
+            Self::Reads => "reads",
+        }
+    }
+
+    /// Group of a request path; `None` for routes that are never paused
+    pub fn of(path: &str) -> Option<Self> {
+        const READS: [&str; 8] = [
+            "/stats/", "/graph/", "/threads/", "/protocols/", "/teams/", "/orgs/", "/policies/", "/audit/",
+        ];
+        let group = match path {
+            "/send" => Self::Send,
+            "/report" | "/report_batch" => Self::Reports,
+            "/register_protocol_for_agent" => Self::Registration,
+            _ if path.starts_with("/send/")
+                || path.starts_with("/parked/")
+                || path.starts_with("/delivered/")
+                || path.starts_with("/attachments/") =>
+            {
+                Self::Send
+            }
+            _ if path.starts_with("/reviews") || path.starts_with("/quarantine") => Self::Reviews,
+            _ if path.starts_with("/protocols/")
+                && (path.ends_with("/reinstate") || path.ends_with("/risk/reset") || path.ends_with("/recertify")) =>
+            {
+                Self::Reviews
+            }
+            _ if path.starts_with("/protocols/") && (path.ends_with("/adopt") || path.ends_with("/scope")) => {
+                Self::Registration
+            }
+            _ if path.starts_with("/agents") => Self::Directory,
+            _ if path.starts_with("/teams/") && !path.ends_with("/stats") => Self::Directory,
+            _ if READS.iter().any(|prefix| path.starts_with(prefix)) => Self::Reads,
+            _ => return None,
+        };
+        Some(group)
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Send => "Message sending",
+            Self::Reports => "Report ingestion",
+            Self::Registration => "Protocol registration",
+            Self::Reviews => "Review and quarantine actions",
+            Self::Directory => "Agent directory changes",
+            Self::Reads => "Read endpoints",
+        }
+    }
+}
+
+impl fmt::Display for RouteGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.describe())
+    }
+}
+
+/// Why and how long a group is paused
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Pause {
+    /// Shown to callers of the paused routes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Expected end of the pause, Unix seconds; sets `Retry-After`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+    /// When the group was paused; set by the gateway
+    pub since: u64,
+}
+
+/// Paused route groups
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceConfig {
+    #[serde(default)]
+    pub paused: BTreeMap<RouteGroup, Pause>,
+}
+
+/// Runtime-switchable maintenance mode
+#[derive(Debug, Default)]
+pub struct Maintenance {
+    config: RwLock<MaintenanceConfig>,
+}
+
+impl Maintenance {
+    /// Pause the comma-separated groups in `MAINTENANCE_PAUSED`
+    pub fn from_env(now: u64) -> Self {
+        let mut config = MaintenanceConfig::default();
+        for name in env::var("MAINTENANCE_PAUSED").unwrap_or_default().split(',') {
+            let name = name.trim();
+            if name.is_empty() {
+                continue;
+            }
+            match RouteGroup::ALL.into_iter().find(|g| g.as_str() == name) {
+                Some(group) => {
+                    let pause = Pause {
+                        reason: Some("Paused at startup".to_string()),
+                        until: None,
+                        since: now,
+                    };
+                    config.paused.insert(group, pause);
+                }
+                None => warn!(event = "config_invalid", group = %name, "Unknown route group in MAINTENANCE_PAUSED"),
+            }
+        }
+        Self {
+            config: RwLock::new(config),
+        }
+    }
+
+    pub fn config(&self) -> MaintenanceConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Replace the paused groups; groups already paused keep their `since`
+    pub fn set_config(&self, mut config: MaintenanceConfig, now: u64) -> MaintenanceConfig {
+        let mut current = self.config.write().unwrap();
+        for (group, pause) in &mut config.paused {
+            pause.since = current.paused.get(group).map_or(now, |p| p.since);
+        }
+        *current = config.clone();
+        config
+    }
+
+    /// The pause in force for `group`, if any
+    pub fn pause_of(&self, group: RouteGroup) -> Option<Pause> {
+        self.config.read().unwrap().paused.get(&group).cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.config.read().unwrap().paused.is_empty()
+    }
+}
+
+/// Middleware answering 503 on routes of paused groups
+pub(crate) async fn pause_routes(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    if state.maintenance.is_empty() {
+        return next.run(req).await;
+    }
+    let Some(group) = RouteGroup::of(req.uri().path()) else {
+        return next.run(req).await;
+    };
+    let Some(pause) = state.maintenance.pause_of(group) else {
+        return next.run(req).await;
+    };
+    info!(
+        event = "maintenance_refused",
+        group = group.as_str(),
+        path = %req.uri().path(),
+        "Route paused for maintenance"
+    );
+    let now = state.clock.now();
+    let mut response = GatewayError::Maintenance {
+        group,
+        reason: pause.reason,
+    }
+    .into_response();
+    if let Some(wait) = pause.until.filter(|until| *until > now).map(|until| until - now) {
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(wait));
+    }
+    response
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_groups() {
+        assert_eq!(RouteGroup::of("/send"), Some(RouteGroup::Send));
+        assert_eq!(RouteGroup::of("/send/stream"), Some(RouteGroup::Send));
+        assert_eq!(RouteGroup::of("/parked/7"), Some(RouteGroup::Send));
+        assert_eq!(RouteGroup::of("/report"), Some(RouteGroup::Reports));
+        assert_eq!(RouteGroup::of("/protocols/a/p/1/reinstate"), Some(RouteGroup::Reviews));
+        assert_eq!(RouteGroup::of("/protocols/a/p/1/stats"), Some(RouteGroup::Reads));
+        assert_eq!(RouteGroup::of("/teams/red"), Some(RouteGroup::Directory));
+        assert_eq!(RouteGroup::of("/teams/red/stats"), Some(RouteGroup::Reads));
+        for path in ["/health/ready", "/metrics", "/admin/maintenance", "/receipts/verify"] {
+            assert_eq!(RouteGroup::of(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_set_config_keeps_since() {
+        let maintenance = Maintenance::default();
+        let pause = |reason: &str| Pause {
+            reason: Some(reason.to_string()),
+            ..Pause::default()
+        };
+        let config = MaintenanceConfig {
+            paused: BTreeMap::from([(RouteGroup::Reports, pause("storage migration"))]),
+        };
+        assert_eq!(maintenance.set_config(config, 100).paused[&RouteGroup::Reports].since, 100);
+
+        let config = MaintenanceConfig {
+            paused: BTreeMap::from([
+                (RouteGroup::Reports, pause("still migrating")),
+                (RouteGroup::Registration, pause("storage migration")),
+            ]),
+        };
+        let config = maintenance.set_config(config, 160);
+        assert_eq!(config.paused[&RouteGroup::Reports].since, 100);
+        assert_eq!(config.paused[&RouteGroup::Registration].since, 160);
+        assert!(maintenance.pause_of(RouteGroup::Send).is_none());
+
+        maintenance.set_config(MaintenanceConfig::default(), 200);
+        assert!(maintenance.is_empty());
+    }
+}
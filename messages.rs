@@ -0,0 +1,451 @@
+//! Response message catalog
+//!
+//! Every error message the gateway answers with is a template in this
+//! catalog, looked up by message key: the error's `code`, with a suffix where
+//! one code has several phrasings (`report_overdue.first`). Variables in
+//! braces, such as `{seconds}` or `{required}`, are filled in from the error;
+//! `{language}` names the agent's language of record, English by default.
+//!
+//! English is built in. `MESSAGE_CATALOG` names a JSON file of further
+//! locales, each a partial set of templates; keys a locale leaves out fall
+//! back to English:
+//!
+//! ```json
+//! {"ja": {"report_overdue": "報告が期限切れです（前回の報告から{seconds}秒）。{language}で報告してください"},
+//!  "de": {"coverage_low": "Abdeckung {actual} unter dem Minimum {required}"}}
+//! ```
+//!
+//! Each response is phrased in the first locale of the request's
+//! `Accept-Language` the catalog has, by exact tag and then by primary
+//! subtag (`de-AT` uses `de`). Without a match, refused sends and reports are
+//! phrased in the agent's language of record when the catalog has it, and
+//! everything else in English. A tenant's `messages` in `LANGUAGE_OF_RECORD`
+//! override the catalog for that tenant's sends and reports; there `{error}`
+//! stands for the catalog's message.
+//!
+//! Only `error`, `message`, and the text of `deny_reasons` are phrased: the
+//! `code` of a response never changes with locale or tenant. The remediation
+//! of a refusal is keyed `{code}.remediation`.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use std::{collections::HashMap, env, fs, future::Future, sync::Arc};
+use tracing::warn;
+
+use crate::{language::LanguageOfRecord, AppState};
+
+/// Built-in English templates by message key
+const ENGLISH: &[(&str, &str)] = &[
+    ("admin_disabled", "Admin API disabled: set ADMIN_TOKEN"),
+    ("invalid_admin_token", "Invalid admin token"),
+    ("self_approval", "A different admin must approve this action"),
+    ("approval_expired", "Proposed action expired unapproved: propose it again"),
+    ("unauthenticated", "Missing or invalid bearer token"),
+    ("out_of_scope", "Outside your team's scope"),
+    ("read_only", "Auditor tokens are read-only"),
+    ("forbidden", "The authorization policy does not let your token call {route}"),
+    ("agent_deleted", "Agent deleted: restore it {action}"),
+    ("protocol_not_registered", "Protocol not registered"),
+    ("missing_protocol", "Novel language requires protocol declaration"),
+    ("protocol_superseded", "Protocol version superseded: use {successor}"),
+    (
+        "protocol_name_taken",
+        "{protocol} is owned by {owner}: adopt its descriptor with POST /protocols/{owner}/{path}/adopt",
+    ),
+    (
+        "protocol_suspended",
+        "Protocol suspended for review after repeated inconsistent reports: awaiting reinstatement",
+    ),
+    (
+        "protocol_suspended.remote",
+        "Protocol suspended for review by gateway {origin}: awaiting reinstatement there",
+    ),
+    (
+        "recertification_lapsed",
+        "Protocol certification lapsed: awaiting recertification by an admin",
+    ),
+    (
+        "codebook_required",
+        "Agent on probation: protocols must carry a codebook glossing their tokens",
+    ),
+    ("schema_violation", "Message does not match the protocol's schema: {errors}"),
+    (
+        "report_overdue",
+        "Report overdue ({seconds}s since last report): submit {language} report to continue novel-language messaging",
+    ),
+    (
+        "report_overdue.first",
+        "Report overdue (no report yet): submit {language} report to continue novel-language messaging",
+    ),
+    ("coverage_low", "Coverage {actual} below minimum {required}"),
+    ("summary_too_short", "{language} summary must be at least {required} characters"),
+    (
+        "summary_quality_low",
+        "{language} summary quality {score} below minimum {required}: write varied sentences, not padding",
+    ),
+    ("summary_language", "Summary must be written in {expected}"),
+    (
+        "encrypted_content.protocol_required",
+        "Encrypted content requires a registered protocol with key-escrow metadata",
+    ),
+    ("encrypted_content", "Encrypted content is not allowed"),
+    ("quarantined", "Encrypted content quarantined for review (id {id})"),
+    ("detector_unavailable", "Language detector unavailable, retry later"),
+    ("webhook_denied", "Denied by decision webhook: {reason}"),
+    ("webhook_denied.no_reason", "Denied by decision webhook"),
+    ("decision_webhook_unavailable", "Decision webhook unavailable, retry later"),
+    ("quota_exceeded", "Quota exceeded: {breach}"),
+    ("recipient_refused", "{reason}"),
+    ("scope_violation", "Recipient {recipient} is outside the protocol's declared scope"),
+    ("attachment_requires_protocol", "Attachments are novel content and require a protocol declaration"),
+    ("attachment_too_large", "Attachment over the {limit}-byte limit"),
+    ("invalid_request", "{reason}"),
+    ("invalid_request.field", "Invalid request body at {field}: {detail}"),
+    ("invalid_request.body", "Invalid request body: {detail}"),
+    (
+        "unsupported_media_type",
+        "Content-Type must be application/json, application/cbor, or application/msgpack",
+    ),
+    ("body_rejected", "{reason}"),
+    ("not_found", "{what}"),
+    ("conflict", "{what}"),
+    ("chaos_disabled", "Fault injection disabled: set CHAOS_ENABLED=true"),
+    ("no_alert_channel", "No alert channel configured"),
+    ("alert_delivery_failed", "{summary}"),
+    ("replication_disabled", "Replication disabled"),
+    ("invalid_replication_token", "Missing or invalid replication token"),
+    ("registry_sync_disabled", "Registry sync disabled"),
+    ("invalid_registry_sync_token", "Missing or invalid registry sync token"),
+    ("forwarding_disabled", "Forwarding disabled"),
+    ("invalid_forward_token", "Missing or invalid forwarding token"),
+    ("invalid_mirror_token", "Invalid mirror token"),
+    ("standby", "Standby gateway: send writes to the primary"),
+    ("maintenance", "{group} paused for maintenance: {reason}"),
+    ("maintenance.no_reason", "{group} paused for maintenance, retry later"),
+    ("timeout", "Request timed out after {ms} ms, retry later"),
+    ("ip_blocked", "Requests from your IP are blocked"),
+    (
+        "ip_throttled",
+        "Requests from your IP are limited to {per_minute} per minute, retry later",
+    ),
+    ("encoding_failed", "Failed to encode response: {detail}"),
+    ("internal_error", "The gateway could not decide this request ({detail}), retry later"),
+    ("denied", "Refused"),
+    // Remediation of refused sends and reports, listed in `deny_reasons`
+    ("agent_deleted.remediation", "Ask an admin to restore the agent with POST /agents/{agent_id}/restore"),
+    (
+        "protocol_not_registered.remediation",
+        "Register the protocol with POST /register_protocol_for_agent",
+    ),
+    (
+        "missing_protocol.remediation",
+        "Declare a registered protocol in the send's protocol field, or write in {language}",
+    ),
+    ("protocol_superseded.remediation", "Send under {successor}"),
+    (
+        "protocol_suspended.remediation",
+        "Ask an admin to approve a held report or reinstate the protocol with POST /protocols/{agent_id}/{name}/{version}/reinstate",
+    ),
+    (
+        "protocol_suspended.remote.remediation",
+        "Ask an admin of gateway {origin} to approve a held report or reinstate the protocol",
+    ),
+    (
+        "recertification_lapsed.remediation",
+        "Ask an admin to recertify the protocol with POST /protocols/{agent_id}/{name}/{version}/recertify",
+    ),
+    (
+        "codebook_required.remediation",
+        "Register the protocol again with a codebook glossing its tokens",
+    ),
+    ("schema_violation.remediation", "Send content matching the protocol's message schema"),
+    (
+        "scope_violation.remediation",
+        "Register the protocol again with the recipient allowed, or ask an admin to expand its scope with POST /protocols/{agent_id}/{name}/{version}/scope",
+    ),
+    (
+        "attachment_requires_protocol.remediation",
+        "Declare a protocol registered for the sender in the send's protocol field",
+    ),
+    ("report_overdue.remediation", "Submit a {language} report with POST /report"),
+    (
+        "encrypted_content.protocol_required.remediation",
+        "Register the protocol with key-escrow metadata and declare it on the send",
+    ),
+    ("encrypted_content.remediation", "Send the content unencrypted"),
+];
+
+/// A message key and the values of its variables
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub key: &'static str,
+    pub args: Vec<(&'static str, String)>,
+}
+
+impl Message {
+    pub fn new(key: &'static str) -> Self {
+        Self { key, args: Vec::new() }
+    }
+
+    pub fn arg(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.args.push((name, value.to_string()));
+        self
+    }
+
+    /// The message in built-in English
+    pub fn english(&self) -> String {
+        fill(english(self.key), &self.args, "English")
+    }
+}
+
+/// Whether `key` is a message key or the code of one
+pub fn is_known(key: &str) -> bool {
+    ENGLISH.iter().any(|(k, _)| *k == key || k.split('.').next() == Some(key))
+}
+
+fn english(key: &str) -> &'static str {
+    ENGLISH
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map_or("{reason}", |(_, template)| template)
+}
+
+/// Replace each `{name}` in `template` with its value; unknown names are
+/// kept as written
+fn fill(template: &str, args: &[(&str, String)], language: &str) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let Some(close) = after.find('}') else {
+            rest = &rest[open..];
+            break;
+        };
+        let name = &after[..close];
+        match args.iter().find(|(n, _)| *n == name) {
+            Some((_, value)) => out.push_str(value),
+            None if name == "language" => out.push_str(language),
+            None => out.push_str(&rest[open..open + close + 2]),
+        }
+        rest = &after[close + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Locales loaded from `MESSAGE_CATALOG`
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    /// Lowercased locale tag -> message key -> template
+    locales: HashMap<String, HashMap<String, String>>,
+}
+
+impl Catalog {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let locales: HashMap<String, HashMap<String, String>> =
+            serde_json::from_str(raw).map_err(|e| e.to_string())?;
+        for (locale, templates) in &locales {
+            if let Some(key) = templates.keys().find(|k| !ENGLISH.iter().any(|(e, _)| e == k)) {
+                return Err(format!("unknown message key {key:?} in locale {locale:?}"));
+            }
+        }
+        Ok(Self {
+            locales: locales
+                .into_iter()
+                .map(|(locale, templates)| (locale.to_ascii_lowercase(), templates))
+                .collect(),
+        })
+    }
+
+    /// Load the file named by `MESSAGE_CATALOG`; English only when unset
+    pub fn from_env() -> Self {
+        let Ok(path) = env::var("MESSAGE_CATALOG") else {
+            return Self::default();
+        };
+        match fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|raw| Self::parse(&raw)) {
+            Ok(catalog) => catalog,
+            Err(e) => {
+                warn!(event = "config_invalid", path = %path, error = %e, "Invalid MESSAGE_CATALOG, using English");
+                Self::default()
+            }
+        }
+    }
+
+    /// Locale tags with templates, sorted
+    pub fn locales(&self) -> Vec<&str> {
+        let mut locales: Vec<&str> = self.locales.keys().map(String::as_str).collect();
+        locales.sort();
+        locales
+    }
+
+    /// The catalog locale serving `tag`, by exact tag then primary subtag
+    fn serving(&self, tag: &str) -> Option<&str> {
+        let tag = tag.to_ascii_lowercase();
+        let primary = tag.split(['-', '_']).next().unwrap_or_default();
+        let found = [tag.as_str(), primary]
+            .into_iter()
+            .find_map(|t| self.locales.get_key_value(t).map(|(k, _)| k.as_str()));
+        found
+    }
+
+    /// Pick the locale to answer in; `None` is built-in English
+    pub fn negotiate(&self, accepted: &[String], record: Option<&LanguageOfRecord>) -> Option<&str> {
+        for tag in accepted {
+            if tag.eq_ignore_ascii_case("en") || tag.to_ascii_lowercase().starts_with("en-") {
+                return self.serving(tag);
+            }
+            if let Some(locale) = self.serving(tag) {
+                return Some(locale);
+            }
+        }
+        record?.languages.iter().find_map(|l| self.serving(l.code))
+    }
+
+    /// Phrase `message` in `locale`, with the tenant's overrides when given
+    pub fn render(&self, message: &Message, locale: Option<&str>, record: Option<&LanguageOfRecord>) -> String {
+        let template = locale
+            .and_then(|l| self.locales.get(l))
+            .and_then(|templates| templates.get(message.key))
+            .map_or_else(|| english(message.key), String::as_str);
+        let language = record.map_or_else(|| "English".to_string(), LanguageOfRecord::names);
+        let text = fill(template, &message.args, &language);
+        let code = message.key.split('.').next().unwrap_or_default();
+        let Some(records) = record.map(|r| &r.messages) else {
+            return text;
+        };
+        match records.get(message.key).or_else(|| records.get(code)) {
+            Some(template) => {
+                let mut args = message.args.clone();
+                args.push(("error", text));
+                fill(template, &args, &language)
+            }
+            None => text,
+        }
+    }
+}
+
+/// `Accept-Language` tags in order of preference, dropping `*` and `q=0`
+pub fn accepted_languages(headers: &HeaderMap) -> Vec<String> {
+    let Some(raw) = headers.get(header::ACCEPT_LANGUAGE).and_then(|v| v.to_str().ok()) else {
+        return Vec::new();
+    };
+    let mut tags: Vec<(f64, usize, String)> = raw
+        .split(',')
+        .enumerate()
+        .filter_map(|(i, item)| {
+            let mut parts = item.split(';');
+            let tag = parts.next()?.trim();
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f64>().ok())?;
+            (!tag.is_empty() && tag != "*" && q > 0.0).then(|| (q, i, tag.to_string()))
+        })
+        .collect();
+    tags.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    tags.into_iter().map(|(_, _, tag)| tag).collect()
+}
+
+/// The catalog and accepted locales of the request being served
+#[derive(Debug, Clone)]
+pub struct Negotiated {
+    catalog: Arc<Catalog>,
+    accepted: Vec<String>,
+}
+
+tokio::task_local! {
+    static NEGOTIATED: Negotiated;
+}
+
+/// Middleware making the request's `Accept-Language` available to every
+/// error rendered while it is served
+pub async fn negotiate_locale(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let negotiated = Negotiated {
+        catalog: state.catalog.clone(),
+        accepted: accepted_languages(req.headers()),
+    };
+    NEGOTIATED.scope(negotiated, next.run(req)).await
+}
+
+/// The negotiation of the request being served, to carry into spawned tasks
+pub fn current() -> Option<Negotiated> {
+    NEGOTIATED.try_with(Clone::clone).ok()
+}
+
+/// Run `fut` under a negotiation carried over from a request
+pub async fn within<F: Future>(negotiated: Option<Negotiated>, fut: F) -> F::Output {
+    match negotiated {
+        Some(negotiated) => NEGOTIATED.scope(negotiated, fut).await,
+        None => fut.await,
+    }
+}
+
+/// Phrase `message` for the request being served, in English outside one
+pub fn localize(message: &Message, record: Option<&LanguageOfRecord>) -> String {
+    NEGOTIATED
+        .try_with(|n| {
+            let locale = n.catalog.negotiate(&n.accepted, record);
+            n.catalog.render(message, locale, record)
+        })
+        .unwrap_or_else(|_| Catalog::default().render(message, None, record))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::GatewayError, language::LanguageConfig};
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_catalog_negotiation() {
+        let catalog = Catalog::parse(
+            r#"{"ja": {"report_overdue": "報告が期限切れです（前回の報告から{seconds}秒）"},
+                "de": {"coverage_low": "Abdeckung {actual} unter dem Minimum {required}"}}"#,
+        )
+        .unwrap();
+        assert!(Catalog::parse(r#"{"de": {"no_such_key": "x"}}"#).is_err());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static("fr;q=0.9, de-AT, *;q=0.5, ja;q=0"));
+        let accepted = accepted_languages(&headers);
+        assert_eq!(accepted, ["de-AT", "fr"]);
+        assert_eq!(catalog.negotiate(&accepted, None), Some("de"), "primary subtag");
+        assert_eq!(catalog.negotiate(&["en-GB".to_string(), "de".to_string()], None), None);
+
+        let low = GatewayError::CoverageLow { actual: 0.5, required: 0.95 }.message();
+        assert_eq!(catalog.render(&low, Some("de"), None), "Abdeckung 0.50 unter dem Minimum 0.95");
+        assert_eq!(catalog.render(&low, Some("ja"), None), "Coverage 0.50 below minimum 0.95", "English fallback");
+
+        // Tenants: their language of record picks the locale, and their
+        // overrides wrap the catalog message
+        let languages: LanguageConfig = serde_json::from_str(
+            r#"{"tenants": {"tokyo": {"languages": ["ja"], "messages": {"report_overdue": "[tokyo] {error}"}},
+                "berlin": {"languages": ["de", "en"], "detectors": ["script"]}}}"#,
+        )
+        .unwrap();
+        let tokyo = languages.record(Some("tokyo"));
+        let overdue = GatewayError::ReportOverdue { seconds: Some(75) }.message();
+        let locale = catalog.negotiate(&[], tokyo);
+        assert_eq!(locale, Some("ja"));
+        assert_eq!(catalog.render(&overdue, locale, tokyo), "[tokyo] 報告が期限切れです（前回の報告から75秒）");
+        let first = GatewayError::ReportOverdue { seconds: None }.message();
+        assert_eq!(
+            catalog.render(&first, None, tokyo),
+            "[tokyo] Report overdue (no report yet): submit Japanese report to continue novel-language messaging",
+            "overrides keyed by code cover every phrasing of it"
+        );
+        let short = GatewayError::SummaryTooShort { required: 20 }.message();
+        assert_eq!(
+            catalog.render(&short, None, languages.record(Some("berlin"))),
+            "German or English summary must be at least 20 characters"
+        );
+    }
+}
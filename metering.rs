@@ -0,0 +1,394 @@
+//! Usage metering for chargeback
+//!
+//! The [`Meter`] is fed every live audit event (see [`audit`](crate::audit))
+//! and adds up, per agent and billing period, what the agent cost:
+//!
+//! - **requests**: decisions on the agent's requests: `msg_accepted` and
+//!   `msg_rejected` (one per recipient decided), `report_accepted`,
+//!   `report_rejected`, `protocol_registered`, and `registration_rejected`
+//! - **novel_bytes**: content of accepted novel-language messages, once per
+//!   recipient it is delivered to
+//! - **audit_bytes**: audit events of the agent's own requests (see
+//!   [`AGENT_EVENTS`](crate::audit::AGENT_EVENTS)) as stored and exported
+//! - **webhook_deliveries**: decision webhook calls made for the agent's sends
+//!
+//! Usage is billed to the tenant owning the agent when the event was
+//! recorded (`unassigned` otherwise), as learned from `agent_owner_set`
+//! events; an agent moved between teams mid-period shows up under both.
+//!
+//! Periods are UTC calendar months by default, or days or ISO weeks with
+//! `BILLING_PERIOD`. The latest `BILLING_RETAIN_PERIODS` are kept. Usage
+//! lives in memory; with a state store, it is rebuilt at startup from the
+//! stored audit trail. Backfilled history is not metered, nor are events
+//! dropped by `LOG_SAMPLING` or suppressed by a storage quota.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, io,
+    sync::Mutex,
+};
+use tracing::warn;
+
+use crate::{audit::AuditEvent, env_parse, slo::UNASSIGNED_TENANT};
+
+/// Default number of billing periods kept
+const DEFAULT_RETAIN_PERIODS: usize = 12;
+
+/// Events counted as requests
+const REQUEST_EVENTS: [&str; 6] = [
+    "msg_accepted",
+    "msg_rejected",
+    "report_accepted",
+    "report_rejected",
+    "protocol_registered",
+    "registration_rejected",
+];
+
+/// `msg_rejected` reasons that follow a decision webhook call
+const WEBHOOK_REJECTIONS: [&str; 2] = ["webhook_denied", "decision_webhook_unavailable"];
+
+/// Length of a billing period
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BillingPeriod {
+    Day,
+    Week,
+    #[default]
+    Month,
+}
+
+impl BillingPeriod {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "day" | "daily" => Some(Self::Day),
+            "week" | "weekly" => Some(Self::Week),
+            "month" | "monthly" => Some(Self::Month),
+            _ => None,
+        }
+    }
+
+    /// Label of the period containing `ts`: `2024-03-07`, `2024-W10`, or
+    /// `2024-03`; labels of one length sort chronologically
+    pub fn label(&self, ts: f64) -> String {
+        use chrono::{DateTime, Datelike};
+        let Some(t) = DateTime::from_timestamp(ts as i64, 0) else {
+            return "unknown".to_string();
+        };
+        match self {
+            Self::Day => t.format("%Y-%m-%d").to_string(),
+            Self::Week => {
+                let week = t.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            }
+            Self::Month => t.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MeterConfig {
+    pub period: BillingPeriod,
+    pub retain_periods: usize,
+}
+
+impl Default for MeterConfig {
+    fn default() -> Self {
+        Self {
+            period: BillingPeriod::Month,
+            retain_periods: DEFAULT_RETAIN_PERIODS,
+        }
+    }
+}
+
+impl MeterConfig {
+    /// Load `BILLING_PERIOD` and `BILLING_RETAIN_PERIODS`
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let period = match env::var("BILLING_PERIOD") {
+            Ok(raw) => BillingPeriod::parse(&raw).unwrap_or_else(|| {
+                warn!(event = "config_invalid", period = %raw, "Unknown BILLING_PERIOD, using month");
+                defaults.period
+            }),
+            Err(_) => defaults.period,
+        };
+        Self {
+            period,
+            retain_periods: env_parse("BILLING_RETAIN_PERIODS")
+                .unwrap_or(defaults.retain_periods)
+                .max(1),
+        }
+    }
+}
+
+/// Metered usage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub requests: u64,
+    pub novel_bytes: u64,
+    pub audit_bytes: u64,
+    pub webhook_deliveries: u64,
+}
+
+impl Usage {
+    fn add(&mut self, other: &Usage) {
+        self.requests += other.requests;
+        self.novel_bytes += other.novel_bytes;
+        self.audit_bytes += other.audit_bytes;
+        self.webhook_deliveries += other.webhook_deliveries;
+    }
+}
+
+/// Counts bytes written, to size events without buffering them
+struct ByteCount(u64);
+
+impl io::Write for ByteCount {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What one audit event costs
+fn cost(event: &AuditEvent) -> Usage {
+    let field = |name: &str| event.fields.get(name).and_then(Value::as_str);
+    let mut size = ByteCount(0);
+    let _ = serde_json::to_writer(&mut size, event);
+    let novel = event.event == "msg_accepted" && field("kind") == Some("novel");
+    let webhook = match event.event.as_str() {
+        "decision_webhook_allowed" | "decision_webhook_failed" => true,
+        "msg_rejected" => field("reason").is_some_and(|r| WEBHOOK_REJECTIONS.contains(&r)),
+        _ => false,
+    };
+    Usage {
+        requests: REQUEST_EVENTS.contains(&event.event.as_str()) as u64,
+        novel_bytes: if novel { event.fields.get("bytes").and_then(Value::as_u64).unwrap_or(0) } else { 0 },
+        audit_bytes: size.0,
+        webhook_deliveries: webhook as u64,
+    }
+}
+
+/// One agent's usage in a period
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentUsage {
+    pub agent_id: String,
+    pub tenant: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+/// One tenant's usage in a period
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantUsage {
+    pub tenant: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+/// Body of `GET /billing/usage`
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    pub period: String,
+    pub period_kind: BillingPeriod,
+    /// Every retained period, oldest first
+    pub periods: Vec<String>,
+    pub tenants: Vec<TenantUsage>,
+    pub agents: Vec<AgentUsage>,
+}
+
+impl UsageReport {
+    /// One row per agent, with a header
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("period,tenant,agent_id,requests,novel_bytes,audit_bytes,webhook_deliveries\n");
+        for row in &self.agents {
+            let u = &row.usage;
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{}\n",
+                csv_field(&self.period),
+                csv_field(&row.tenant),
+                csv_field(&row.agent_id),
+                u.requests,
+                u.novel_bytes,
+                u.audit_bytes,
+                u.webhook_deliveries
+            ));
+        }
+        csv
+    }
+}
+
+/// Quote a CSV field when it needs it
+///
+/// Agent ids are chosen by agents, so a leading formula character is
+/// escaped before the sheet the export lands in evaluates it.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{value}")
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// period -> (tenant, agent) -> usage
+    periods: BTreeMap<String, BTreeMap<(String, String), Usage>>,
+    /// Agent -> owning team, from `agent_owner_set`
+    owners: HashMap<String, String>,
+}
+
+/// Per-agent usage over billing periods
+#[derive(Debug, Default)]
+pub struct Meter {
+    config: MeterConfig,
+    inner: Mutex<Inner>,
+}
+
+impl Meter {
+    pub fn new(config: MeterConfig) -> Self {
+        Self {
+            config,
+            inner: Mutex::default(),
+        }
+    }
+
+    pub fn config(&self) -> &MeterConfig {
+        &self.config
+    }
+
+    /// Add the cost of `event` to the agent it is attributed to
+    pub fn observe(&self, event: &AuditEvent) {
+        let field = |name: &str| event.fields.get(name).and_then(Value::as_str);
+        let mut inner = self.inner.lock().unwrap();
+        if event.event == "agent_owner_set" {
+            if let (Some(agent), Some(team)) = (field("agent_id"), field("team")) {
+                inner.owners.insert(agent.to_string(), team.to_string());
+            }
+        }
+        // Governance acting on an agent is not the agent's usage
+        if !crate::audit::is_agent_traffic(&event.event, &event.fields) {
+            return;
+        }
+        // Quota-digested events keep only `agent`
+        let Some(agent) = ["from", "agent_id", "agent"].into_iter().find_map(field) else {
+            return;
+        };
+        let tenant = inner.owners.get(agent).map_or(UNASSIGNED_TENANT, String::as_str).to_string();
+        let period = self.config.period.label(event.ts);
+        inner
+            .periods
+            .entry(period)
+            .or_default()
+            .entry((tenant, agent.to_string()))
+            .or_default()
+            .add(&cost(event));
+        while inner.periods.len() > self.config.retain_periods {
+            inner.periods.pop_first();
+        }
+    }
+
+    /// Usage in `period`, limited to tenants `visible` accepts
+    pub fn report(&self, period: &str, visible: impl Fn(&str) -> bool) -> UsageReport {
+        let inner = self.inner.lock().unwrap();
+        let mut tenants: BTreeMap<&str, Usage> = BTreeMap::new();
+        let mut agents = Vec::new();
+        for ((tenant, agent), usage) in inner.periods.get(period).into_iter().flatten() {
+            if !visible(tenant) {
+                continue;
+            }
+            tenants.entry(tenant).or_default().add(usage);
+            agents.push(AgentUsage {
+                agent_id: agent.clone(),
+                tenant: tenant.clone(),
+                usage: *usage,
+            });
+        }
+        UsageReport {
+            period: period.to_string(),
+            period_kind: self.config.period,
+            periods: inner.periods.keys().cloned().collect(),
+            tenants: tenants
+                .into_iter()
+                .map(|(tenant, usage)| TenantUsage {
+                    tenant: tenant.to_string(),
+                    usage,
+                })
+                .collect(),
+            agents,
+        }
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Map};
+
+    fn event(seq: u64, ts: f64, kind: &str, fields: Value) -> AuditEvent {
+        let Value::Object(fields) = fields else {
+            panic!("fields must be an object");
+        };
+        AuditEvent {
+            seq,
+            ts,
+            level: "INFO".to_string(),
+            event: kind.to_string(),
+            schema_version: crate::audit_schema::version_of(kind),
+            policy_version: Some("1".to_string()),
+            fields,
+            labels: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_usage_by_period_and_tenant() {
+        let meter = Meter::new(MeterConfig {
+            period: BillingPeriod::Month,
+            retain_periods: 2,
+        });
+        // 2024-03-15 and 2024-04-15
+        let (march, april) = (1_710_460_800.0, 1_713_139_200.0);
+        let accepted = json!({"from": "a", "to": "b", "kind": "novel", "bytes": 40});
+        meter.observe(&event(1, march, "msg_accepted", accepted.clone()));
+        meter.observe(&event(2, march, "agent_owner_set", json!({"agent_id": "a", "team": "red"})));
+        meter.observe(&event(3, march, "msg_accepted", accepted.clone()));
+        meter.observe(&event(4, march, "decision_webhook_allowed", json!({"from": "a"})));
+        meter.observe(&event(5, march, "msg_rejected", json!({"from": "a", "reason": "webhook_denied"})));
+        meter.observe(&event(6, march, "gateway_started", Map::new().into()));
+        meter.observe(&event(7, april, "report_accepted", json!({"agent_id": "b"})));
+
+        let report = meter.report("2024-03", |_| true);
+        assert_eq!(report.periods, vec!["2024-03", "2024-04"]);
+        let tenants: Vec<_> = report.tenants.iter().map(|t| t.tenant.as_str()).collect();
+        assert_eq!(tenants, vec!["red", "unassigned"], "the first send predates the owner");
+        let red = &report.tenants[0].usage;
+        assert_eq!((red.requests, red.novel_bytes, red.webhook_deliveries), (2, 40, 2));
+        assert!(red.audit_bytes > 0);
+        assert_eq!(report.tenants[1].usage.novel_bytes, 40);
+
+        let scoped = meter.report("2024-03", |t| t == "red");
+        assert_eq!(scoped.agents.len(), 1);
+        let csv = scoped.to_csv();
+        assert!(csv.starts_with("period,tenant,agent_id,"));
+        assert!(csv.lines().nth(1).unwrap().starts_with("2024-03,red,a,2,40,"));
+
+        // A third period evicts the oldest
+        meter.observe(&event(8, april + 31.0 * 86_400.0, "report_accepted", json!({"agent_id": "b"})));
+        assert_eq!(meter.report("2024-03", |_| true).periods, vec!["2024-04", "2024-05"]);
+        assert!(meter.report("2024-03", |_| true).agents.is_empty());
+    }
+}
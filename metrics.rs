@@ -0,0 +1,199 @@
+//! Prometheus-style metrics
+//!
+//! Counters are plain atomics updated from the handlers; `/metrics` renders
+//! them in the Prometheus text exposition format, or in OpenMetrics with trace
+//! exemplars (see [`exemplars`](crate::exemplars)). Latencies are kept in
+//! fixed-bucket [`Histogram`]s.
+
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::exemplars::{Exemplar, ExemplarSlot};
+
+/// Gateway-wide counters
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub english_messages: AtomicU64,
+    pub novel_messages: AtomicU64,
+    pub rejected_messages: AtomicU64,
+    pub rejected_exemplar: ExemplarSlot,
+    pub reports_submitted: AtomicU64,
+    pub reports_held: AtomicU64,
+    pub protocols_suspended: AtomicU64,
+    pub quarantined_messages: AtomicU64,
+    pub violations: AtomicU64,
+    pub slo_burn_alerts: AtomicU64,
+    /// Sends accepted despite a compliance refusal in audit-only mode
+    pub sends_waived: AtomicU64,
+    /// Retry tokens issued for sends refused for a barely overdue report
+    pub retries_offered: AtomicU64,
+    pub retries_redeemed: AtomicU64,
+    /// Sends and reports the gateway failed to decide, and those of them
+    /// accepted by a fail-open degradation rule
+    pub internal_errors: AtomicU64,
+    pub degraded_accepts: AtomicU64,
+}
+
+impl Metrics {
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a rejected message, with the request's trace as exemplar
+    pub fn reject(&self) {
+        Self::inc(&self.rejected_messages);
+        self.rejected_exemplar.record(1.0);
+    }
+}
+
+/// Upper bounds in seconds of the latency histogram buckets
+pub const LATENCY_BUCKETS: [f64; 14] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// Latency histogram over [`LATENCY_BUCKETS`]
+#[derive(Debug, Default)]
+pub struct Histogram {
+    /// Per-bucket (not cumulative) counts, with `+Inf` last
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+    /// Latest traced observation of each bucket
+    exemplars: [ExemplarSlot; LATENCY_BUCKETS.len() + 1],
+}
+
+impl Histogram {
+    pub fn observe(&self, seconds: f64) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|le| seconds <= *le)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.exemplars[bucket].record(seconds);
+        self.sum_micros
+            .fetch_add((seconds * 1e6) as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+/// Builder for the Prometheus text exposition format
+#[derive(Debug, Default)]
+pub struct PromWriter {
+    out: String,
+    /// Whether to write OpenMetrics, exemplars included
+    openmetrics: bool,
+}
+
+impl PromWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writer of the OpenMetrics text format
+    pub fn openmetrics() -> Self {
+        Self {
+            openmetrics: true,
+            ..Self::default()
+        }
+    }
+
+    /// Write a single unlabelled counter
+    pub fn counter(&mut self, name: &str, help: &str, value: u64) -> &mut Self {
+        self.traced_counter(name, help, value, None)
+    }
+
+    /// Write a single unlabelled counter with its exemplar, if any
+    pub fn traced_counter(&mut self, name: &str, help: &str, value: u64, exemplar: Option<Exemplar>) -> &mut Self {
+        self.header(name, help, "counter");
+        let _ = write!(self.out, "{name} {value}");
+        self.exemplar(exemplar);
+        self
+    }
+
+    /// Write a single unlabelled gauge
+    pub fn gauge(&mut self, name: &str, help: &str, value: f64) -> &mut Self {
+        self.header(name, help, "gauge");
+        let _ = writeln!(self.out, "{name} {value}");
+        self
+    }
+
+    /// Write a metric family with one sample per label set
+    pub fn labelled(
+        &mut self,
+        name: &str,
+        help: &str,
+        kind: &str,
+        samples: &[(&[(&str, &str)], f64)],
+    ) -> &mut Self {
+        self.header(name, help, kind);
+        for (labels, value) in samples {
+            let labels = labels
+                .iter()
+                .map(|(k, v)| format!("{k}=\"{}\"", escape_label(v)))
+                .collect::<Vec<_>>()
+                .join(",");
+            let _ = writeln!(self.out, "{name}{{{labels}}} {value}");
+        }
+        self
+    }
+
+    /// Write a histogram family with one series per label set
+    pub fn histogram(&mut self, name: &str, help: &str, series: &[(&[(&str, &str)], &Histogram)]) -> &mut Self {
+        self.header(name, help, "histogram");
+        for (labels, histogram) in series {
+            let labels: String = labels
+                .iter()
+                .map(|(k, v)| format!("{k}=\"{}\",", escape_label(v)))
+                .collect();
+            let mut cumulative = 0;
+            for (i, bucket) in histogram.buckets.iter().enumerate() {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let le = LATENCY_BUCKETS.get(i).map_or("+Inf".to_string(), |le| le.to_string());
+                let _ = write!(self.out, "{name}_bucket{{{labels}le=\"{le}\"}} {cumulative}");
+                self.exemplar(histogram.exemplars[i].get());
+            }
+            let labels = labels.trim_end_matches(',');
+            let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+            let _ = writeln!(self.out, "{name}_sum{{{labels}}} {sum}");
+            let _ = writeln!(self.out, "{name}_count{{{labels}}} {}", histogram.count());
+        }
+        self
+    }
+
+    pub fn finish(mut self) -> String {
+        if self.openmetrics {
+            self.out.push_str("# EOF\n");
+        }
+        self.out
+    }
+
+    fn header(&mut self, name: &str, help: &str, kind: &str) {
+        // OpenMetrics names a counter family without its `_total` suffix
+        let name = match kind {
+            "counter" if self.openmetrics => name.strip_suffix("_total").unwrap_or(name),
+            _ => name,
+        };
+        let _ = writeln!(self.out, "# HELP {name} {help}");
+        let _ = writeln!(self.out, "# TYPE {name} {kind}");
+    }
+
+    /// End a sample line, with its exemplar in OpenMetrics
+    fn exemplar(&mut self, exemplar: Option<Exemplar>) {
+        match exemplar {
+            Some(exemplar) if self.openmetrics => {
+                let _ = writeln!(self.out, " {exemplar}");
+            }
+            _ => self.out.push('\n'),
+        }
+    }
+}
+
+fn escape_label(v: &str) -> String {
+    v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
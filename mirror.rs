@@ -0,0 +1,379 @@
+//! Traffic mirroring to a shadow gateway
+//!
+//! Before an upgrade, production traffic can be replayed against the new
+//! version. With `MIRROR_URL` set, the gateway copies a sample of the requests
+//! on the route groups in `MIRROR_ROUTES` (`send,reports,registration` by
+//! default) to the shadow gateway at that URL. `MIRROR_SAMPLE_RATE` sets the
+//! share copied. Sampling is deterministic like log sampling: at rate `r`, the
+//! first request is copied and then one in every `1 / r`. Copies are sent in
+//! the background with the request's method, path, headers, and body. The
+//! shadow's response is ignored and never delays or changes the production
+//! answer. At most `MIRROR_MAX_IN_FLIGHT` copies are outstanding; past that
+//! they are dropped.
+//!
+//! Copies carry the shared `MIRROR_TOKEN` in `X-Mirror-Token`, and mirroring
+//! is disabled without it. A shadow started with the same token serves them
+//! as usual, except that it emits no webhooks:
+//!
+//! - decision webhooks are not called, and the send is decided by the
+//!   gateway's own checks (`decision_webhook_skipped`)
+//! - alerts are audited but not dispatched
+//! - parked sends never call the sender back
+//! - recipients routed to a peer gateway are not forwarded, so they keep the
+//!   shadow's own decision
+//!
+//! Every audit event of a mirrored request carries `mirrored = true`, so the
+//! shadow's decisions can be compared with production's. A request with a
+//! wrong or unexpected token is refused with 401 and
+//! `code: invalid_mirror_token`; the tag cannot be used to skip a decision
+//! webhook. A shadow never mirrors further.
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, Method, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    env,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+use tracing::{info_span, warn, Instrument};
+
+use crate::{env_parse, error::GatewayError, maintenance::RouteGroup, tokens_match, AppState};
+
+/// Header carrying `MIRROR_TOKEN` on mirrored requests
+pub const MIRROR_HEADER: HeaderName = HeaderName::from_static("x-mirror-token");
+
+/// Per-request timeout when copying to the shadow
+const MIRROR_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outstanding copies when `MIRROR_MAX_IN_FLIGHT` is unset
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 64;
+
+/// Request headers not copied: the shadow's client sets its own framing
+const HOP_HEADERS: [HeaderName; 5] = [
+    header::HOST,
+    header::CONTENT_LENGTH,
+    header::CONTENT_ENCODING,
+    header::TRANSFER_ENCODING,
+    header::CONNECTION,
+];
+
+tokio::task_local! {
+    /// Set while serving a mirrored request
+    static MIRRORED: ();
+}
+
+/// Whether the request being served is a mirrored copy
+pub fn is_mirrored() -> bool {
+    MIRRORED.try_with(|_| ()).is_ok()
+}
+
+/// `fut` marked mirrored when spawned while serving a mirrored request
+pub fn carry<F: Future>(fut: F) -> impl Future<Output = F::Output> {
+    let mirrored = is_mirrored();
+    async move {
+        match mirrored {
+            true => MIRRORED.scope((), fut).await,
+            false => fut.await,
+        }
+    }
+}
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirrorConfig {
+    /// Base URL of the shadow gateway; nothing is mirrored when unset
+    pub url: Option<String>,
+    /// Shared secret tagging mirrored requests, on both gateways
+    pub token: Option<String>,
+    /// Share of eligible requests copied, within [0, 1]
+    pub rate: f64,
+    pub routes: Vec<RouteGroup>,
+    pub max_in_flight: usize,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            token: None,
+            rate: 1.0,
+            routes: vec![RouteGroup::Send, RouteGroup::Reports, RouteGroup::Registration],
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+        }
+    }
+}
+
+impl MirrorConfig {
+    /// Load `MIRROR_URL`, `MIRROR_TOKEN`, `MIRROR_SAMPLE_RATE`,
+    /// `MIRROR_ROUTES`, and `MIRROR_MAX_IN_FLIGHT`
+    pub fn from_env() -> Self {
+        let d = Self::default();
+        let url = env::var("MIRROR_URL")
+            .ok()
+            .map(|u| u.trim().trim_end_matches('/').to_string())
+            .filter(|u| !u.is_empty());
+        let token = env::var("MIRROR_TOKEN").ok().filter(|t| !t.is_empty());
+        if url.is_some() && token.is_none() {
+            warn!(event = "config_invalid", "MIRROR_URL set without MIRROR_TOKEN, mirroring disabled");
+        }
+        let rate = match env::var("MIRROR_SAMPLE_RATE") {
+            Ok(raw) => match raw.trim().parse::<f64>().ok().filter(|r| (0.0..=1.0).contains(r)) {
+                Some(rate) => rate,
+                None => {
+                    warn!(event = "config_invalid", rate = %raw, "MIRROR_SAMPLE_RATE must be within [0, 1], using 1");
+                    d.rate
+                }
+            },
+            Err(_) => d.rate,
+        };
+        let routes = match env::var("MIRROR_ROUTES") {
+            Ok(raw) => raw
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .filter_map(|name| {
+                    let group = RouteGroup::ALL.into_iter().find(|g| g.as_str() == name);
+                    if group.is_none() {
+                        warn!(event = "config_invalid", group = %name, "Unknown route group in MIRROR_ROUTES");
+                    }
+                    group
+                })
+                .collect(),
+            Err(_) => d.routes,
+        };
+        let max_in_flight = env_parse("MIRROR_MAX_IN_FLIGHT").unwrap_or(d.max_in_flight);
+        Self {
+            url,
+            token,
+            rate,
+            routes,
+            max_in_flight,
+        }
+    }
+}
+
+// =============================================================================
+// Mirroring
+// =============================================================================
+
+/// Copies by outcome, exported via `/metrics`
+#[derive(Debug, Default)]
+pub struct MirrorCounters {
+    pub sent: AtomicU64,
+    pub failed: AtomicU64,
+    /// Skipped because `max_in_flight` copies were outstanding
+    pub dropped: AtomicU64,
+}
+
+/// A request as it is copied to the shadow
+struct Copied {
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+#[derive(Debug)]
+pub struct Mirror {
+    config: MirrorConfig,
+    client: reqwest::Client,
+    /// Largest body buffered for a copy, matching the request body limit
+    max_body_bytes: usize,
+    /// Eligible requests seen, for deterministic sampling
+    seen: AtomicU64,
+    in_flight: Arc<Semaphore>,
+    pub counters: MirrorCounters,
+}
+
+impl Default for Mirror {
+    fn default() -> Self {
+        Self::new(MirrorConfig::default(), crate::codec::DEFAULT_MAX_BODY_BYTES)
+    }
+}
+
+impl Mirror {
+    pub fn new(config: MirrorConfig, max_body_bytes: usize) -> Self {
+        Self {
+            in_flight: Arc::new(Semaphore::new(config.max_in_flight.max(1))),
+            config,
+            client: reqwest::Client::builder()
+                .timeout(MIRROR_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            max_body_bytes,
+            seen: AtomicU64::new(0),
+            counters: MirrorCounters::default(),
+        }
+    }
+
+    /// Configuration from the environment, with bodies capped at `max_body_bytes`
+    pub fn from_env(max_body_bytes: usize) -> Self {
+        Self::new(MirrorConfig::from_env(), max_body_bytes)
+    }
+
+    pub fn config(&self) -> &MirrorConfig {
+        &self.config
+    }
+
+    /// Whether this gateway copies requests to a shadow
+    pub fn enabled(&self) -> bool {
+        self.config.url.is_some() && self.config.token.is_some()
+    }
+
+    /// Whether to copy the next request to `path`
+    fn sample(&self, path: &str) -> bool {
+        if !self.enabled() || !RouteGroup::of(path).is_some_and(|g| self.config.routes.contains(&g)) {
+            return false;
+        }
+        let n = self.seen.fetch_add(1, Ordering::Relaxed) + 1;
+        (n as f64 * self.config.rate).ceil() > ((n - 1) as f64 * self.config.rate).ceil()
+    }
+
+    /// Whether `presented` is this gateway's mirror token
+    fn trusts(&self, presented: &[u8]) -> bool {
+        let presented = String::from_utf8_lossy(presented);
+        self.config.token.as_deref().is_some_and(|expected| tokens_match(&presented, expected))
+    }
+
+    async fn send(&self, copy: Copied) {
+        let (Some(url), Some(token)) = (&self.config.url, &self.config.token) else {
+            return;
+        };
+        let target = match copy.uri.path_and_query() {
+            Some(path) => format!("{url}{path}"),
+            None => url.clone(),
+        };
+        // reqwest is on its own `http` version, so method and headers go over as text
+        let method = reqwest::Method::from_bytes(copy.method.as_str().as_bytes()).unwrap_or_default();
+        let mut request = self.client.request(method, &target);
+        for (name, value) in copy.headers.iter().filter(|(name, _)| !HOP_HEADERS.contains(name)) {
+            request = request.header(name.as_str(), value.as_bytes());
+        }
+        let result = request
+            .header(MIRROR_HEADER.as_str(), token)
+            .body(copy.body)
+            .send()
+            .await;
+        let counter = match result {
+            Ok(_) => &self.counters.sent,
+            Err(e) => {
+                warn!(event = "mirror_failed", path = %copy.uri.path(), error = %e, "Copy to shadow gateway failed");
+                &self.counters.failed
+            }
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Serve mirrored requests without webhooks, and copy sampled ones to the shadow
+pub async fn mirror_requests(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let mirror = state.mirror.clone();
+    if let Some(presented) = req.headers().get(MIRROR_HEADER) {
+        if !mirror.trusts(presented.as_bytes()) {
+            return GatewayError::InvalidMirrorToken.into_response();
+        }
+        let span = info_span!("mirrored", mirrored = true);
+        return MIRRORED.scope((), next.run(req).instrument(span)).await;
+    }
+    if !mirror.sample(req.uri().path()) {
+        return next.run(req).await;
+    }
+    let Ok(permit) = mirror.in_flight.clone().try_acquire_owned() else {
+        mirror.counters.dropped.fetch_add(1, Ordering::Relaxed);
+        return next.run(req).await;
+    };
+
+    let (parts, body) = req.into_parts();
+    let body = match to_bytes(body, mirror.max_body_bytes).await {
+        Ok(body) => body,
+        Err(e) => {
+            return GatewayError::BodyRejected {
+                status: StatusCode::PAYLOAD_TOO_LARGE,
+                message: format!("Failed to buffer the request body: {e}"),
+            }
+            .into_response()
+        }
+    };
+    let copy = Copied {
+        method: parts.method.clone(),
+        uri: parts.uri.clone(),
+        headers: parts.headers.clone(),
+        body: body.clone(),
+    };
+    tokio::spawn(async move {
+        mirror.send(copy).await;
+        drop(permit);
+    });
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{AgentFixture, ProtocolFixture, SendFixture, TestGateway};
+    use crate::webhooks::WebhookRule;
+
+    #[test]
+    fn test_sampling() {
+        let mirror = Mirror::new(
+            MirrorConfig {
+                url: Some("http://shadow:8080".to_string()),
+                token: Some("s3cret".to_string()),
+                rate: 0.25,
+                ..MirrorConfig::default()
+            },
+            1024,
+        );
+        let copied: Vec<_> = (0..8).map(|_| mirror.sample("/send")).collect();
+        assert_eq!(copied, [true, false, false, false, true, false, false, false]);
+        assert!(!mirror.sample("/stats/slo"), "reads are not mirrored by default");
+        assert!(!Mirror::default().sample("/send"));
+    }
+
+    #[tokio::test]
+    async fn test_shadow_skips_decision_webhooks() {
+        // Nothing listens on port 1, so a consulted webhook fails closed
+        let rule: WebhookRule = serde_json::from_str(r#"{"url": "http://127.0.0.1:1/decide"}"#).unwrap();
+        let gw = TestGateway::with_shadow("s3cret", vec![rule]);
+        let protocol = ProtocolFixture::new("coord", "1.0").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(protocol.clone()).reported()).await;
+        gw.setup_agent(AgentFixture::new("b")).await;
+        let send = SendFixture::novel("a", "b", &protocol, "SHP|eta=7f").build();
+
+        assert_eq!(gw.send(&send).await.status, StatusCode::SERVICE_UNAVAILABLE);
+        let mirrored = |token: &str| {
+            Request::builder()
+                .method(Method::POST)
+                .uri("/send")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(MIRROR_HEADER, token)
+                .body(Body::from(serde_json::to_vec(&send).unwrap()))
+                .unwrap()
+        };
+        let forged = gw.request(mirrored("guess")).await;
+        assert_eq!(forged.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(forged.body["code"], "invalid_mirror_token");
+        let shadowed = gw.request(mirrored("s3cret")).await;
+        assert_eq!(shadowed.status, StatusCode::OK, "{:?}", shadowed.body);
+
+        let failed_closed = &gw.state().webhooks.counters.failed_closed;
+        assert_eq!(failed_closed.load(Ordering::Relaxed), 1, "the shadow never called the webhook");
+    }
+}
@@ -0,0 +1,244 @@
+//! Global protocol namespace
+//!
+//! By default protocol names are scoped to the agent registering them, so two
+//! agents may register `coord:1.0` with unrelated semantics. With
+//! `PROTOCOL_NAMESPACE=global`, a `name:version` means one thing gateway-wide:
+//! the first agent to register it owns the canonical descriptor, and other
+//! agents must adopt that descriptor (`POST
+//! /protocols/{owner}/{name}/{version}/adopt`) instead of defining their own.
+//! Registering a descriptor identical to the canonical one counts as
+//! adopting it; a different one is refused with 409 and
+//! `code: protocol_name_taken`.
+//!
+//! Ownership goes to the earliest registration by an agent that is not
+//! soft-deleted, so a deleted owner hands the name to the next registrant.
+//!
+//! `GET /protocols/conflicts` lists every `name:version` registered by
+//! several agents whose descriptors differ, with the refused attempts to
+//! redefine it. Registrations from before the mode was switched on, or
+//! replicated from a peer, can still diverge; the report is how auditors
+//! find them.
+
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    sync::Mutex,
+};
+use tracing::warn;
+
+use crate::{signing::content_digest, InnerState, ProtocolDescriptor};
+
+/// Whether protocol names are per agent or gateway-wide
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NamespaceMode {
+    #[default]
+    Agent,
+    Global,
+}
+
+impl NamespaceMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "agent" => Some(Self::Agent),
+            "global" => Some(Self::Global),
+            _ => None,
+        }
+    }
+}
+
+/// SHA-256 of a descriptor's canonical JSON, to tell definitions apart
+pub fn descriptor_digest(descriptor: &ProtocolDescriptor) -> String {
+    content_digest(&serde_json::to_string(descriptor).unwrap_or_default())
+}
+
+/// Owner of `key` (`name:version`): the earliest registrant that is not
+/// soft-deleted, ties broken by agent id
+pub fn owner<'a>(st: &'a InnerState, key: &str) -> Option<(&'a str, &'a ProtocolDescriptor)> {
+    st.protocols
+        .iter()
+        .filter(|(agent_id, _)| !st.is_deleted(agent_id))
+        .filter_map(|(agent_id, protocols)| {
+            let descriptor = protocols.get(key)?;
+            let registered_at = st.protocol_stats.get(&format!("{agent_id}::{key}")).map_or(0, |s| s.registered_at);
+            Some((registered_at, agent_id.as_str(), descriptor))
+        })
+        .min_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)))
+        .map(|(_, agent_id, descriptor)| (agent_id, descriptor))
+}
+
+/// A registration whose descriptor differs from the canonical one
+#[derive(Debug, Clone, Serialize)]
+pub struct Divergent {
+    pub agent_id: String,
+    pub descriptor_sha256: String,
+    pub registered_at: u64,
+}
+
+/// One entry of `GET /protocols/conflicts`
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    pub protocol: String,
+    pub owner: String,
+    pub descriptor_sha256: String,
+    /// Agents registered with the canonical descriptor
+    pub adopters: Vec<String>,
+    pub divergent: Vec<Divergent>,
+    /// Refused attempts to redefine the protocol: agent_id -> count
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub refused: BTreeMap<String, u64>,
+}
+
+/// Namespace mode and refused redefinitions
+#[derive(Debug, Default)]
+pub struct Namespace {
+    mode: NamespaceMode,
+    /// protocol key -> agent_id -> refused registrations
+    refused: Mutex<HashMap<String, BTreeMap<String, u64>>>,
+}
+
+impl Namespace {
+    pub fn new(mode: NamespaceMode) -> Self {
+        Self {
+            mode,
+            refused: Mutex::default(),
+        }
+    }
+
+    /// Load `PROTOCOL_NAMESPACE`
+    pub fn from_env() -> Self {
+        let mode = match env::var("PROTOCOL_NAMESPACE") {
+            Ok(raw) => NamespaceMode::parse(&raw).unwrap_or_else(|| {
+                warn!(event = "config_invalid", mode = %raw, "Unknown PROTOCOL_NAMESPACE, using agent");
+                NamespaceMode::Agent
+            }),
+            Err(_) => NamespaceMode::Agent,
+        };
+        Self::new(mode)
+    }
+
+    pub fn is_global(&self) -> bool {
+        self.mode == NamespaceMode::Global
+    }
+
+    /// Owner of `key` when `agent_id` may not register `descriptor` under it
+    pub fn check<'a>(
+        &self,
+        st: &'a InnerState,
+        agent_id: &str,
+        key: &str,
+        descriptor: &ProtocolDescriptor,
+    ) -> Result<(), &'a str> {
+        if !self.is_global() {
+            return Ok(());
+        }
+        match owner(st, key) {
+            Some((owner, canonical)) if owner != agent_id && descriptor_digest(canonical) != descriptor_digest(descriptor) => {
+                Err(owner)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a refused redefinition of `key` by `agent_id`
+    pub fn refuse(&self, key: &str, agent_id: &str) {
+        *self
+            .refused
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .entry(agent_id.to_string())
+            .or_insert(0) += 1;
+    }
+
+    /// Protocols registered with differing descriptors, or refused a
+    /// redefinition, by key
+    pub fn conflicts(&self, st: &InnerState) -> Vec<Conflict> {
+        let refused = self.refused.lock().unwrap();
+        let mut keys: Vec<&String> = st.protocols.values().flat_map(|p| p.keys()).collect();
+        keys.extend(refused.keys());
+        keys.sort();
+        keys.dedup();
+
+        let mut conflicts = Vec::new();
+        for key in keys {
+            let Some((owner, canonical)) = owner(st, key) else {
+                continue;
+            };
+            let digest = descriptor_digest(canonical);
+            let mut adopters = Vec::new();
+            let mut divergent = Vec::new();
+            for (agent_id, protocols) in &st.protocols {
+                let Some(descriptor) = protocols.get(key.as_str()).filter(|_| agent_id != owner) else {
+                    continue;
+                };
+                let theirs = descriptor_digest(descriptor);
+                if theirs == digest {
+                    adopters.push(agent_id.clone());
+                } else {
+                    divergent.push(Divergent {
+                        agent_id: agent_id.clone(),
+                        descriptor_sha256: theirs,
+                        registered_at: st
+                            .protocol_stats
+                            .get(&format!("{agent_id}::{key}"))
+                            .map_or(0, |s| s.registered_at),
+                    });
+                }
+            }
+            let refused = refused.get(key.as_str()).cloned().unwrap_or_default();
+            if divergent.is_empty() && refused.is_empty() {
+                continue;
+            }
+            adopters.sort();
+            divergent.sort_by(|a, b| a.agent_id.cmp(&b.agent_id));
+            conflicts.push(Conflict {
+                protocol: key.clone(),
+                owner: owner.to_string(),
+                descriptor_sha256: digest,
+                adopters,
+                divergent,
+                refused,
+            });
+        }
+        conflicts
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{ProtocolFixture, TestGateway};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_global_namespace() {
+        let gw = TestGateway::with_global_namespace();
+        let canonical = ProtocolFixture::new("coord", "1.0").gloss("SHP", "shipment").build();
+        let redefined = ProtocolFixture::new("coord", "1.0").gloss("SHP", "shutdown").build();
+        assert_eq!(gw.register("a", &canonical).await.status, StatusCode::OK);
+
+        let taken = gw.register("b", &redefined).await;
+        assert_eq!(taken.status, StatusCode::CONFLICT);
+        assert_eq!(taken.body["code"], "protocol_name_taken");
+        assert_eq!(gw.register("a", &redefined).await.status, StatusCode::OK, "the owner may revise it");
+
+        let adopted = gw.post("/protocols/a/coord/1.0/adopt", &json!({"agent_id": "b"})).await;
+        assert_eq!(adopted.status, StatusCode::OK, "{:?}", adopted.body);
+        let adopted = gw.post("/protocols/b/coord/1.0/adopt", &json!({"agent_id": "c"})).await;
+        assert_eq!(adopted.status, StatusCode::BAD_REQUEST, "only the owner's descriptor is canonical");
+
+        let conflicts = gw.admin(Method::GET, "/protocols/conflicts", None::<&()>).await;
+        assert_eq!(conflicts.status, StatusCode::OK);
+        assert_eq!(conflicts.body[0]["protocol"], "coord:1.0");
+        assert_eq!(conflicts.body[0]["owner"], "a");
+        assert_eq!(conflicts.body[0]["adopters"], json!(["b"]));
+        assert_eq!(conflicts.body[0]["refused"]["b"], 1);
+    }
+}
@@ -0,0 +1,404 @@
+//! Durable outbox for parked-send callbacks
+//!
+//! A report that releases a parked send, or a parked send expiring, owes the
+//! sender a callback. The callback is not POSTed where the outcome is
+//! decided: it is queued here as an [`OutboxEntry`] and written to the state
+//! store in one batch with the stored state change behind it, such as the
+//! violation counted for a discarded quarantined send, so the store holds
+//! both or neither. The batch is never shed from the store's write queue.
+//! Outcomes the store does not keep, such as a parked send's release, leave
+//! the entry alone in its batch; a released quarantined send is delivered as
+//! a new send, whose own writes are queued ahead of the entry but not with
+//! it. Entries still queued are loaded back at startup and delivered then.
+//!
+//! A delivery worker drains the outbox. Each attempt POSTs the entry's
+//! payload with its id in an `Idempotency-Key` header; the id is random and
+//! kept across attempts and restarts, so a receiver that saw an attempt whose
+//! answer was lost can drop the repeat. A failed attempt is retried with
+//! exponential backoff from `OUTBOX_RETRY_BASE_SEC` up to
+//! `OUTBOX_RETRY_MAX_SEC`. After `OUTBOX_MAX_ATTEMPTS` failed attempts the
+//! entry is abandoned, logged as `outbox_delivery_abandoned`, and removed
+//! from the store; it stays listed until the gateway restarts. A standby
+//! delivers nothing.
+//!
+//! `GET /admin/outbox` lists the entries that failed at least once.
+
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::Notify;
+use tracing::{error, warn};
+
+use crate::{identity::AuthedAdmin, signing, store::Record, AppState};
+
+/// Header carrying an entry's id on every attempt
+pub const IDEMPOTENCY_HEADER: &str = "idempotency-key";
+
+/// Kind of the callbacks of parked sends
+pub const PARK_CALLBACK: &str = "park_callback";
+
+/// Kind of the callbacks of quarantined sends
+pub const QUARANTINE_CALLBACK: &str = "quarantine_callback";
+
+/// Seconds between checks for retries coming due
+const TICK_SEC: u64 = 1;
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+#[derive(Debug, Clone)]
+pub struct OutboxConfig {
+    /// Attempts before an entry is abandoned
+    pub max_attempts: u32,
+    /// Wait after the first failed attempt, doubled after each further one
+    pub retry_base_sec: u64,
+    /// Longest wait between attempts
+    pub retry_max_sec: u64,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            retry_base_sec: 1,
+            retry_max_sec: 300,
+        }
+    }
+}
+
+impl OutboxConfig {
+    /// Read `OUTBOX_MAX_ATTEMPTS`, `OUTBOX_RETRY_BASE_SEC`, and
+    /// `OUTBOX_RETRY_MAX_SEC`
+    pub fn from_env() -> Self {
+        use crate::env_parse as var;
+        let d = Self::default();
+        Self {
+            max_attempts: var("OUTBOX_MAX_ATTEMPTS").unwrap_or(d.max_attempts).max(1),
+            retry_base_sec: var("OUTBOX_RETRY_BASE_SEC").unwrap_or(d.retry_base_sec).max(1),
+            retry_max_sec: var("OUTBOX_RETRY_MAX_SEC").unwrap_or(d.retry_max_sec),
+        }
+    }
+
+    /// Wait before the next attempt after `attempts` failed ones
+    fn backoff(&self, attempts: u32) -> u64 {
+        let doublings = attempts.saturating_sub(1).min(32);
+        self.retry_base_sec
+            .saturating_mul(1 << doublings)
+            .min(self.retry_max_sec.max(self.retry_base_sec))
+    }
+}
+
+// =============================================================================
+// Entries
+// =============================================================================
+
+/// A delivery waiting in the outbox
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    /// Dedup key, sent as `Idempotency-Key`
+    pub id: String,
+    /// What is delivered, e.g. `park_callback`
+    pub kind: String,
+    pub url: String,
+    pub payload: Value,
+    pub created_at: u64,
+    /// Failed attempts since the gateway started
+    #[serde(default)]
+    pub attempts: u32,
+    /// When the next attempt is due
+    #[serde(default)]
+    pub next_attempt_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// No further attempts are made
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub abandoned: bool,
+}
+
+impl OutboxEntry {
+    /// A delivery of `payload` to `url`, due at once, under a fresh id
+    pub fn new(kind: &str, url: &str, payload: Value, now: u64) -> Self {
+        Self {
+            id: format!("{kind}-{}", signing::random_hex::<16>()),
+            kind: kind.to_string(),
+            url: url.to_string(),
+            payload,
+            created_at: now,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            abandoned: false,
+        }
+    }
+}
+
+/// How an attempt ended for its entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Settlement {
+    Delivered,
+    /// Failed; retried at the given time
+    Retry(u64),
+    /// Failed for the last time
+    Abandoned,
+}
+
+// =============================================================================
+// Outbox
+// =============================================================================
+
+/// Deliveries not yet made, keyed by id
+pub struct Outbox {
+    config: OutboxConfig,
+    entries: Mutex<BTreeMap<String, OutboxEntry>>,
+    wake: Notify,
+    client: reqwest::Client,
+    pub delivered: AtomicU64,
+    pub attempts_failed: AtomicU64,
+    pub abandoned: AtomicU64,
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self::new(OutboxConfig::default())
+    }
+}
+
+impl Outbox {
+    pub fn new(config: OutboxConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(BTreeMap::new()),
+            wake: Notify::new(),
+            // A callback host could otherwise redirect past the prefix check
+            client: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .expect("outbox client"),
+            delivered: AtomicU64::new(0),
+            attempts_failed: AtomicU64::new(0),
+            abandoned: AtomicU64::new(0),
+        }
+    }
+
+    /// Take `entry` in and wake the worker; false when its id is already in
+    pub fn insert(&self, entry: OutboxEntry) -> bool {
+        let added = {
+            let mut entries = self.entries.lock().unwrap();
+            if entries.contains_key(&entry.id) {
+                false
+            } else {
+                entries.insert(entry.id.clone(), entry);
+                true
+            }
+        };
+        if added {
+            self.wake.notify_one();
+        }
+        added
+    }
+
+    /// Entries due for an attempt at `now`
+    pub fn due(&self, now: u64) -> Vec<OutboxEntry> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .values()
+            .filter(|e| !e.abandoned && e.next_attempt_at <= now)
+            .cloned()
+            .collect()
+    }
+
+    /// POST `entry` to its URL, giving up after `timeout`
+    async fn attempt(&self, entry: &OutboxEntry, timeout: Duration) -> Result<(), String> {
+        let resp = self
+            .client
+            .post(&entry.url)
+            .timeout(timeout)
+            .header(IDEMPOTENCY_HEADER, &entry.id)
+            .json(&entry.payload)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("{} returned {}", entry.kind, resp.status()))
+        }
+    }
+
+    /// Record the outcome of an attempt on entry `id` at `now`
+    pub fn settle(&self, id: &str, result: Result<(), String>, now: u64) -> Option<Settlement> {
+        let mut entries = self.entries.lock().unwrap();
+        let Err(error) = result else {
+            entries.remove(id)?;
+            self.delivered.fetch_add(1, Ordering::Relaxed);
+            return Some(Settlement::Delivered);
+        };
+        let entry = entries.get_mut(id)?;
+        self.attempts_failed.fetch_add(1, Ordering::Relaxed);
+        entry.attempts += 1;
+        entry.last_error = Some(error);
+        if entry.attempts >= self.config.max_attempts {
+            entry.abandoned = true;
+            self.abandoned.fetch_add(1, Ordering::Relaxed);
+            return Some(Settlement::Abandoned);
+        }
+        entry.next_attempt_at = now + self.config.backoff(entry.attempts);
+        Some(Settlement::Retry(entry.next_attempt_at))
+    }
+
+    /// Entries that failed at least once, oldest first
+    pub fn stuck(&self) -> Vec<OutboxEntry> {
+        let entries = self.entries.lock().unwrap();
+        let mut stuck: Vec<_> = entries.values().filter(|e| e.attempts > 0).cloned().collect();
+        stuck.sort_by_key(|e| e.created_at);
+        stuck
+    }
+
+    /// Entries not yet delivered or abandoned
+    pub fn pending(&self) -> usize {
+        self.entries.lock().unwrap().values().filter(|e| !e.abandoned).count()
+    }
+}
+
+/// Queue a delivery: stored in one batch with `with`, the writes of the
+/// state change it follows from, behind the writes already queued, then
+/// handed to the worker
+pub fn enqueue(state: &AppState, entry: OutboxEntry, with: Vec<Record>) {
+    let mut records = with;
+    records.push(Record::Outbox(Box::new(entry.clone())));
+    state.store.commit(Record::Batch { records });
+    state.outbox.insert(entry);
+}
+
+/// Attempt every entry due at `now`
+pub async fn deliver_due(state: &AppState, now: u64) {
+    // Both kinds are callbacks to URLs under `PARK_CALLBACK_PREFIXES`
+    let timeout = state.parking.config().callback_timeout;
+    for entry in state.outbox.due(now) {
+        let result = state.outbox.attempt(&entry, timeout).await;
+        if result.is_err() && entry.kind == PARK_CALLBACK {
+            state.parking.counters.callbacks_failed.fetch_add(1, Ordering::Relaxed);
+        }
+        let error = result.as_ref().err().cloned().unwrap_or_default();
+        match state.outbox.settle(&entry.id, result, now) {
+            Some(Settlement::Delivered) => state.store.commit(Record::OutboxSettled { id: entry.id }),
+            Some(Settlement::Retry(at)) => warn!(
+                id = %entry.id,
+                kind = %entry.kind,
+                attempts = entry.attempts + 1,
+                retry_at = at,
+                error = %error,
+                event = "outbox_delivery_failed",
+                "Outbox delivery failed, retrying"
+            ),
+            Some(Settlement::Abandoned) => {
+                error!(
+                    id = %entry.id,
+                    kind = %entry.kind,
+                    attempts = entry.attempts + 1,
+                    error = %error,
+                    event = "outbox_delivery_abandoned",
+                    "Outbox delivery abandoned"
+                );
+                state.store.commit(Record::OutboxSettled { id: entry.id });
+            }
+            None => {}
+        }
+    }
+}
+
+/// Deliver queued entries as they come in and retries as they come due
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(TICK_SEC));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = state.outbox.wake.notified() => {}
+        }
+        if state.replication.is_standby() {
+            continue;
+        }
+        deliver_due(&state, state.clock.now()).await;
+    }
+}
+
+/// Listing of the outbox
+#[derive(Debug, Serialize)]
+pub struct OutboxView {
+    pub pending: usize,
+    /// Entries that failed at least once, oldest first
+    pub stuck: Vec<OutboxEntry>,
+}
+
+/// The outbox's stuck deliveries
+pub async fn list(_: AuthedAdmin, State(state): State<AppState>) -> Json<OutboxView> {
+    Json(OutboxView {
+        pending: state.outbox.pending(),
+        stuck: state.outbox.stuck(),
+    })
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestGateway, ADMIN_TOKEN};
+    use axum::http::{Method, StatusCode};
+
+    #[tokio::test]
+    async fn test_outbox_retries_then_abandons() {
+        let outbox = Outbox::new(OutboxConfig {
+            max_attempts: 3,
+            retry_base_sec: 10,
+            retry_max_sec: 15,
+        });
+        let entry = OutboxEntry::new(PARK_CALLBACK, "http://127.0.0.1:9/", Value::Null, 100);
+        assert!(outbox.insert(entry.clone()));
+        assert!(!outbox.insert(entry.clone()), "an id is taken in once");
+        assert_eq!(outbox.due(100).len(), 1);
+
+        let timeout = Duration::from_secs(1);
+        assert!(outbox.attempt(&entry, timeout).await.is_err(), "nothing listens on port 9");
+        assert_eq!(outbox.settle(&entry.id, Err("refused".into()), 100), Some(Settlement::Retry(110)));
+        assert!(outbox.due(109).is_empty());
+        assert_eq!(outbox.settle(&entry.id, Err("refused".into()), 110), Some(Settlement::Retry(125)));
+        assert_eq!(outbox.settle(&entry.id, Err("refused".into()), 125), Some(Settlement::Abandoned));
+        assert!(outbox.due(u64::MAX).is_empty());
+        let stuck = outbox.stuck();
+        assert_eq!((stuck[0].attempts, stuck[0].abandoned), (3, true));
+
+        let other = OutboxEntry::new(PARK_CALLBACK, "http://x/", Value::Null, 100);
+        assert_ne!(other.id, entry.id);
+        outbox.insert(other.clone());
+        assert_eq!(outbox.settle(&other.id, Ok(()), 100), Some(Settlement::Delivered));
+        assert_eq!(outbox.pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_callback_listed_as_stuck() {
+        let gw = TestGateway::new();
+        let entry = OutboxEntry::new(PARK_CALLBACK, "http://127.0.0.1:9/", Value::Null, gw.now());
+        enqueue(gw.state(), entry.clone(), Vec::new());
+        deliver_due(gw.state(), gw.now()).await;
+
+        assert_eq!(gw.get("/admin/outbox").await.status, StatusCode::UNAUTHORIZED);
+        let resp = gw.call(Method::GET, "/admin/outbox", None::<&()>, Some(ADMIN_TOKEN)).await;
+        assert_eq!(resp.status, StatusCode::OK);
+        assert_eq!(resp.body["pending"], 1);
+        assert_eq!(resp.body["stuck"][0]["id"], entry.id.as_str());
+        assert_eq!(resp.body["stuck"][0]["attempts"], 1);
+        assert_eq!(gw.state().parking.counters.callbacks_failed.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,375 @@
+//! Agent ownership (agent -> team -> org) and team-scoped read access
+//!
+//! The agent directory records which team owns each agent and which org each
+//! team belongs to. Compliance stats and alert counts roll up along that chain.
+//!
+//! Read endpoints accept four kinds of caller:
+//! - an admin token (`ADMIN_TOKEN` or one of `ADMIN_TOKENS`), which sees
+//!   everything
+//! - an auditor token from `AUDITOR_TOKENS` (`token:auditor,...`), which sees
+//!   everything but may only read
+//! - a team token from `TEAM_TOKENS` (`token:team,...`), which only sees
+//!   agents owned by its team
+//! - no token, which is allowed only while no team tokens are configured, so
+//!   existing deployments keep their open read access
+//!
+//! Auditors may also read the audit export and the report review queue. Any
+//! write with an auditor token is refused by [`auditor_access`], which records
+//! every auditor request in the audit log.
+
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::{collections::BTreeMap, env};
+use tracing::{info, warn};
+
+use crate::{bearer_token, error::GatewayError, tokens_match, AppState, InnerState};
+
+/// POST routes an auditor token may call, as they change nothing
+const READ_ONLY_POSTS: &[&str] = &["/receipts/verify", "/audit/search"];
+
+/// Parse `token:name,...` pairs from `var`
+fn named_tokens(var: &str) -> Vec<(String, String)> {
+    env::var(var)
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| entry.trim().split_once(':'))
+        .filter(|(token, name)| !token.is_empty() && !name.is_empty())
+        .map(|(token, name)| (token.to_string(), name.to_string()))
+        .collect()
+}
+
+/// Bearer tokens scoped to a single team
+#[derive(Debug, Clone, Default)]
+pub struct TeamTokens {
+    tokens: Vec<(String, String)>,
+}
+
+impl TeamTokens {
+    /// Parse `TEAM_TOKENS` (`token:team,...`)
+    pub fn from_env() -> Self {
+        Self {
+            tokens: named_tokens("TEAM_TOKENS"),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Team owning `presented`, compared in constant time
+    pub fn team_for(&self, presented: &str) -> Option<&str> {
+        self.tokens
+            .iter()
+            .find(|(token, _)| tokens_match(presented, token))
+            .map(|(_, team)| team.as_str())
+    }
+}
+
+/// Read-only bearer tokens for external auditors
+#[derive(Debug, Clone, Default)]
+pub struct AuditorTokens {
+    tokens: Vec<(String, String)>,
+}
+
+impl AuditorTokens {
+    /// Parse `AUDITOR_TOKENS` (`token:auditor,...`)
+    pub fn from_env() -> Self {
+        Self {
+            tokens: named_tokens("AUDITOR_TOKENS"),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Auditor holding `presented`, compared in constant time
+    pub fn auditor_for(&self, presented: &str) -> Option<&str> {
+        self.tokens
+            .iter()
+            .find(|(token, _)| tokens_match(presented, token))
+            .map(|(_, auditor)| auditor.as_str())
+    }
+}
+
+/// Named admin bearer tokens, beside `ADMIN_TOKEN`
+#[derive(Debug, Clone, Default)]
+pub struct AdminTokens {
+    tokens: Vec<(String, String)>,
+}
+
+impl AdminTokens {
+    /// Parse `ADMIN_TOKENS` (`token:admin,...`)
+    pub fn from_env() -> Self {
+        Self {
+            tokens: named_tokens("ADMIN_TOKENS"),
+        }
+    }
+
+    #[cfg(any(test, feature = "test-harness"))]
+    pub fn new(tokens: &[(&str, &str)]) -> Self {
+        Self {
+            tokens: tokens.iter().map(|(t, n)| (t.to_string(), n.to_string())).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Admin holding `presented`, compared in constant time
+    pub fn admin_for(&self, presented: &str) -> Option<&str> {
+        self.tokens
+            .iter()
+            .find(|(token, _)| tokens_match(presented, token))
+            .map(|(_, admin)| admin.as_str())
+    }
+}
+
+/// Middleware limiting auditor tokens to reads and auditing their use
+pub(crate) async fn auditor_access(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(auditor) = bearer_token(req.headers()).and_then(|t| state.auditor_tokens.auditor_for(t)) else {
+        return next.run(req).await;
+    };
+    let auditor = auditor.to_string();
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let query = req.uri().query().unwrap_or("").to_string();
+    if !matches!(method, Method::GET | Method::HEAD) && !READ_ONLY_POSTS.contains(&path.as_str()) {
+        warn!(
+            auditor = %auditor,
+            method = %method,
+            path = %path,
+            event = "auditor_access",
+            status = 403,
+            "Auditor write refused"
+        );
+        return GatewayError::ReadOnly.into_response();
+    }
+    let response = next.run(req).await;
+    info!(
+        auditor = %auditor,
+        method = %method,
+        path = %path,
+        query = %query,
+        event = "auditor_access",
+        status = response.status().as_u16(),
+        "Auditor read"
+    );
+    response
+}
+
+/// Who is calling a read endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Caller {
+    Admin,
+    /// Read-only auditor, by name
+    Auditor(String),
+    Team(String),
+    /// No token while no team tokens are configured
+    Open,
+}
+
+impl Caller {
+    /// Whether this caller may see everything
+    pub fn is_unscoped(&self) -> bool {
+        matches!(self, Self::Admin | Self::Auditor(_) | Self::Open)
+    }
+
+    /// Whether this caller sees report summaries as filed rather than with
+    /// their quoted protocol tokens redacted
+    pub fn may_read_raw_summaries(&self) -> bool {
+        matches!(self, Self::Admin | Self::Auditor(_))
+    }
+
+    pub fn may_read_team(&self, team: &str) -> bool {
+        match self {
+            Self::Team(own) => own == team,
+            _ => true,
+        }
+    }
+
+    pub fn may_read_agent(&self, st: &InnerState, agent_id: &str) -> bool {
+        match self {
+            Self::Team(own) => st.owners.get(agent_id) == Some(own),
+            _ => true,
+        }
+    }
+}
+
+/// Directory entry for one agent
+#[derive(Debug, Serialize)]
+pub struct AgentEntry {
+    pub agent_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+    pub deleted: bool,
+    pub protocols: usize,
+    pub violations: u32,
+    pub alerts: u64,
+    /// Registry the agent was imported from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discovered_from: Option<&'static str>,
+    /// Public key imported from the registry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    /// Recipient class listed by the registry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recipient_class: Option<String>,
+}
+
+impl AgentEntry {
+    pub fn new(st: &InnerState, agent_id: &str) -> Self {
+        let team = st.owners.get(agent_id).cloned();
+        let org = team.as_ref().and_then(|t| st.team_orgs.get(t)).cloned();
+        let discovered = st.discovered.get(agent_id);
+        Self {
+            agent_id: agent_id.to_string(),
+            org,
+            team,
+            deleted: st.deleted_agents.contains_key(agent_id),
+            protocols: st.protocols.get(agent_id).map_or(0, |m| m.len()),
+            violations: st.violations.get(agent_id).copied().unwrap_or(0),
+            alerts: st.alerts.get(agent_id).copied().unwrap_or(0),
+            discovered_from: discovered.map(|d| d.source.as_str()),
+            public_key: discovered.and_then(|d| d.public_key.clone()),
+            recipient_class: discovered.and_then(|d| d.recipient_class.clone()),
+        }
+    }
+}
+
+/// Compliance totals over a set of agents
+#[derive(Debug, Default, Serialize)]
+pub struct Rollup {
+    pub agents: usize,
+    pub deleted_agents: usize,
+    pub protocols: usize,
+    pub violations: u64,
+    pub alerts: u64,
+    pub messages_sent: u64,
+    pub reports_filed: u64,
+}
+
+impl Rollup {
+    fn add(&mut self, st: &InnerState, agent_id: &str) {
+        let entry = AgentEntry::new(st, agent_id);
+        self.agents += 1;
+        self.deleted_agents += usize::from(entry.deleted);
+        self.protocols += entry.protocols;
+        self.violations += u64::from(entry.violations);
+        self.alerts += entry.alerts;
+
+        let prefix = format!("{agent_id}::");
+        for (_, stats) in st.protocol_stats.iter().filter(|(k, _)| k.starts_with(&prefix)) {
+            self.messages_sent += stats.messages_sent;
+            self.reports_filed += stats.reports_filed;
+        }
+    }
+}
+
+/// Rollup for one team
+#[derive(Debug, Serialize)]
+pub struct TeamRollup {
+    pub team: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+    #[serde(flatten)]
+    pub totals: Rollup,
+}
+
+/// Rollup for one org, with its teams
+#[derive(Debug, Serialize)]
+pub struct OrgRollup {
+    pub org: String,
+    #[serde(flatten)]
+    pub totals: Rollup,
+    pub teams: BTreeMap<String, Rollup>,
+}
+
+pub fn team_rollup(st: &InnerState, team: &str) -> TeamRollup {
+    let mut totals = Rollup::default();
+    for (agent_id, _) in st.owners.iter().filter(|(_, t)| *t == team) {
+        totals.add(st, agent_id);
+    }
+    TeamRollup {
+        team: team.to_string(),
+        org: st.team_orgs.get(team).cloned(),
+        totals,
+    }
+}
+
+pub fn org_rollup(st: &InnerState, org: &str) -> OrgRollup {
+    let mut totals = Rollup::default();
+    let mut teams: BTreeMap<String, Rollup> = BTreeMap::new();
+    for (agent_id, team) in &st.owners {
+        if st.team_orgs.get(team).map(String::as_str) != Some(org) {
+            continue;
+        }
+        totals.add(st, agent_id);
+        teams.entry(team.clone()).or_default().add(st, agent_id);
+    }
+    OrgRollup {
+        org: org.to_string(),
+        totals,
+        teams,
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rollups_and_scoping() {
+        let mut st = InnerState::default();
+        for (agent, team) in [("a1", "red"), ("a2", "red"), ("b1", "blue")] {
+            st.owners.insert(agent.into(), team.into());
+        }
+        st.team_orgs.insert("red".into(), "acme".into());
+        st.team_orgs.insert("blue".into(), "acme".into());
+        st.violations.insert("a1".into(), 2);
+        st.violations.insert("b1".into(), 1);
+        st.alerts.insert("a2".into(), 3);
+
+        let red = team_rollup(&st, "red");
+        assert_eq!((red.totals.agents, red.totals.violations, red.totals.alerts), (2, 2, 3));
+        assert_eq!(red.org.as_deref(), Some("acme"));
+
+        let acme = org_rollup(&st, "acme");
+        assert_eq!((acme.totals.agents, acme.totals.violations), (3, 3));
+        assert_eq!(acme.teams["blue"].violations, 1);
+
+        let caller = Caller::Team("red".into());
+        assert!(caller.may_read_agent(&st, "a1"));
+        assert!(!caller.may_read_agent(&st, "b1"));
+        assert!(!caller.may_read_team("blue"));
+        assert!(Caller::Admin.may_read_agent(&st, "b1"));
+        let auditor = Caller::Auditor("kpmg".into());
+        assert!(auditor.may_read_agent(&st, "b1") && auditor.may_read_team("blue"));
+
+        let auditors = AuditorTokens {
+            tokens: vec![("s3cret".into(), "kpmg".into())],
+        };
+        assert_eq!(auditors.auditor_for("s3cret"), Some("kpmg"));
+        assert_eq!(auditors.auditor_for("s3cre"), None);
+    }
+}
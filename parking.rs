@@ -0,0 +1,440 @@
+//! Parking of sends refused for an overdue report
+//!
+//! A send with `"park": true` that is refused only because the sender's report
+//! is overdue is held instead of bounced. When a report for that protocol is
+//! accepted within `PARK_TIMEOUT_SEC`, the parked sends are re-evaluated in
+//! arrival order and delivered, or refused if they no longer pass. Sends
+//! still parked at the timeout expire.
+//!
+//! A parked send may name a `callback_url` to receive its outcome as a JSON
+//! POST, delivered through the outbox (see `outbox`). The URL must fall under
+//! one of `PARK_CALLBACK_PREFIXES`, so a sender cannot point the gateway at
+//! arbitrary hosts: it must have the prefix's scheme, host and port, no
+//! credentials, and a path at or below the prefix's path, compared segment by
+//! segment after normalization. Outcomes can also be polled at
+//! `GET /parked/{id}` for `PARK_TIMEOUT_SEC` after they resolve.
+//!
+//! The lot does not watch the clock itself. The caller schedules each send's
+//! expiry and each outcome's retention on the gateway's timer wheel and calls
+//! [`ParkLot::expire`] and [`ParkLot::forget`] when those timers fire.
+//!
+//! Parked sends live only in memory and are not replicated. A restart or
+//! failover drops them, and polling them then returns 404.
+
+use reqwest::Url;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+use tracing::warn;
+
+use crate::{env_parse, SendMessageRequest};
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+#[derive(Debug, Clone)]
+pub struct ParkConfig {
+    /// How long a send stays parked; 0 disables parking
+    pub timeout_sec: u64,
+    /// Sends parked at once per agent
+    pub max_per_agent: usize,
+    /// URL prefixes callbacks may target; empty disables callbacks
+    pub callback_prefixes: Vec<Url>,
+    /// Per-call callback timeout
+    pub callback_timeout: Duration,
+}
+
+impl Default for ParkConfig {
+    fn default() -> Self {
+        Self {
+            timeout_sec: 30,
+            max_per_agent: 100,
+            callback_prefixes: Vec::new(),
+            callback_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl ParkConfig {
+    /// Load settings from `PARK_*` environment variables
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let parse = |name: &str| env_parse::<u64>(name);
+        Self {
+            timeout_sec: parse("PARK_TIMEOUT_SEC").unwrap_or(defaults.timeout_sec),
+            max_per_agent: parse("PARK_MAX_PER_AGENT").map_or(defaults.max_per_agent, |n| n as usize),
+            callback_prefixes: env::var("PARK_CALLBACK_PREFIXES")
+                .map(|list| {
+                    list.split(',')
+                        .map(str::trim)
+                        .filter(|p| !p.is_empty())
+                        .filter_map(|p| match Url::parse(p) {
+                            Ok(url) if url.has_host() => Some(url),
+                            _ => {
+                                warn!(event = "config_invalid", prefix = %p, "Ignoring PARK_CALLBACK_PREFIXES entry: not an absolute URL");
+                                None
+                            }
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            callback_timeout: parse("PARK_CALLBACK_TIMEOUT_MS")
+                .map_or(defaults.callback_timeout, Duration::from_millis),
+        }
+    }
+}
+
+// =============================================================================
+// Parked Sends
+// =============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParkState {
+    Pending,
+    /// Re-evaluated after a report and accepted
+    Delivered,
+    /// Re-evaluated after a report and refused
+    Refused,
+    /// No acceptable report arrived in time
+    Expired,
+}
+
+impl ParkState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Delivered => "delivered",
+            Self::Refused => "refused",
+            Self::Expired => "expired",
+        }
+    }
+}
+
+/// Returned to the sender in place of the overdue refusal
+#[derive(Debug, Clone, Serialize)]
+pub struct ParkTicket {
+    pub id: u64,
+    pub expires_at: u64,
+}
+
+/// A parked send as seen by `GET /parked/{id}` and callbacks
+#[derive(Debug, Clone, Serialize)]
+pub struct ParkStatus {
+    pub id: u64,
+    pub agent_id: String,
+    /// Resolved protocol the awaited report must cover
+    pub protocol: String,
+    pub state: ParkState,
+    pub parked_at: u64,
+    pub expires_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_at: Option<u64>,
+    /// HTTP status the send was answered with on re-evaluation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Response body of the re-evaluated send
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+}
+
+struct Entry {
+    status: ParkStatus,
+    /// "agent_id::protocol_key" of the awaited report
+    report_key: String,
+    /// The send itself, until it is taken for re-evaluation or expires
+    req: Option<SendMessageRequest>,
+    callback_url: Option<String>,
+}
+
+#[derive(Default)]
+struct Lot {
+    next_id: u64,
+    entries: BTreeMap<u64, Entry>,
+}
+
+#[derive(Debug, Default)]
+pub struct ParkCounters {
+    pub delivered: AtomicU64,
+    pub refused: AtomicU64,
+    pub expired: AtomicU64,
+    pub callbacks_failed: AtomicU64,
+}
+
+/// Sends waiting for their sender's next report
+pub struct ParkLot {
+    config: ParkConfig,
+    lot: Mutex<Lot>,
+    pub counters: ParkCounters,
+}
+
+impl Default for ParkLot {
+    fn default() -> Self {
+        Self::new(ParkConfig::default())
+    }
+}
+
+impl ParkLot {
+    pub fn new(config: ParkConfig) -> Self {
+        Self {
+            config,
+            lot: Mutex::new(Lot::default()),
+            counters: ParkCounters::default(),
+        }
+    }
+
+    pub fn config(&self) -> &ParkConfig {
+        &self.config
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.timeout_sec > 0
+    }
+
+    /// Refuse callback URLs outside `PARK_CALLBACK_PREFIXES`
+    pub fn check_callback(&self, url: &str) -> Result<(), String> {
+        if self.config.callback_prefixes.is_empty() {
+            return Err("Park callbacks disabled: set PARK_CALLBACK_PREFIXES".to_string());
+        }
+        let url = Url::parse(url).map_err(|e| format!("callback_url is not a valid URL: {e}"))?;
+        if self.config.callback_prefixes.iter().any(|p| under_prefix(p, &url)) {
+            Ok(())
+        } else {
+            Err("callback_url not allowed by PARK_CALLBACK_PREFIXES".to_string())
+        }
+    }
+
+    /// Hold `req` until a report on `report_key` is accepted
+    ///
+    /// Fails when the sender already has `PARK_MAX_PER_AGENT` sends parked.
+    pub fn park(&self, req: SendMessageRequest, report_key: &str, protocol: &str, now: u64) -> Result<ParkTicket, String> {
+        let mut lot = self.lot.lock().unwrap();
+        let parked = lot
+            .entries
+            .values()
+            .filter(|e| e.req.is_some() && req.from == *e.status.agent_id)
+            .count();
+        if parked >= self.config.max_per_agent {
+            return Err(format!("{parked} sends already parked"));
+        }
+        lot.next_id += 1;
+        let id = lot.next_id;
+        let expires_at = now + self.config.timeout_sec;
+        let status = ParkStatus {
+            id,
+            agent_id: req.from.to_string(),
+            protocol: protocol.to_string(),
+            state: ParkState::Pending,
+            parked_at: now,
+            expires_at,
+            resolved_at: None,
+            status: None,
+            response: None,
+        };
+        let callback_url = req.callback_url.clone();
+        lot.entries.insert(
+            id,
+            Entry {
+                status,
+                report_key: report_key.to_string(),
+                req: Some(req),
+                callback_url,
+            },
+        );
+        Ok(ParkTicket { id, expires_at })
+    }
+
+    /// Whether any send is waiting on a report for `report_key`
+    pub fn has_pending(&self, report_key: &str) -> bool {
+        self.lot
+            .lock()
+            .unwrap()
+            .entries
+            .values()
+            .any(|e| e.req.is_some() && e.report_key == report_key)
+    }
+
+    /// Take the sends waiting on `report_key` for re-evaluation, oldest first
+    pub fn take(&self, report_key: &str) -> Vec<(u64, SendMessageRequest)> {
+        let mut lot = self.lot.lock().unwrap();
+        lot.entries
+            .iter_mut()
+            .filter(|(_, e)| e.report_key == report_key)
+            .filter_map(|(id, e)| Some((*id, e.req.take()?)))
+            .collect()
+    }
+
+    /// Record the outcome of a re-evaluated send, returning its status and
+    /// callback URL
+    pub fn resolve(&self, id: u64, state: ParkState, status: u16, response: Value, now: u64) -> Option<(ParkStatus, Option<String>)> {
+        let mut lot = self.lot.lock().unwrap();
+        let entry = lot.entries.get_mut(&id)?;
+        entry.status.state = state;
+        entry.status.resolved_at = Some(now);
+        entry.status.status = Some(status);
+        entry.status.response = Some(response);
+        match state {
+            ParkState::Delivered => self.counters.delivered.fetch_add(1, Ordering::Relaxed),
+            _ => self.counters.refused.fetch_add(1, Ordering::Relaxed),
+        };
+        Some((entry.status.clone(), entry.callback_url.clone()))
+    }
+
+    /// Expire a send still parked at its deadline, returning its status and
+    /// callback URL
+    pub fn expire(&self, id: u64, now: u64) -> Option<(ParkStatus, Option<String>)> {
+        let mut lot = self.lot.lock().unwrap();
+        let entry = lot.entries.get_mut(&id)?;
+        entry.req.take()?;
+        entry.status.state = ParkState::Expired;
+        entry.status.resolved_at = Some(now);
+        self.counters.expired.fetch_add(1, Ordering::Relaxed);
+        Some((entry.status.clone(), entry.callback_url.clone()))
+    }
+
+    /// Drop a resolved send's outcome once it is no longer kept for polling
+    pub fn forget(&self, id: u64) {
+        let mut lot = self.lot.lock().unwrap();
+        if lot.entries.get(&id).is_some_and(|e| e.req.is_none()) {
+            lot.entries.remove(&id);
+        }
+    }
+
+    pub fn status(&self, id: u64) -> Option<ParkStatus> {
+        self.lot.lock().unwrap().entries.get(&id).map(|e| e.status.clone())
+    }
+
+    /// Sends currently parked
+    pub fn pending(&self) -> usize {
+        self.lot
+            .lock()
+            .unwrap()
+            .entries
+            .values()
+            .filter(|e| e.status.state == ParkState::Pending)
+            .count()
+    }
+}
+
+/// Whether `url` has the origin of `prefix` and a path at or below its path
+fn under_prefix(prefix: &Url, url: &Url) -> bool {
+    let base = prefix.path().trim_end_matches('/');
+    let path = url.path();
+    prefix.scheme() == url.scheme()
+        && prefix.host() == url.host()
+        && prefix.port_or_known_default() == url.port_or_known_default()
+        && url.username().is_empty()
+        && url.password().is_none()
+        && (path == base || path.strip_prefix(base).is_some_and(|rest| rest.starts_with('/')))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ProtocolFixture, SendFixture};
+
+    fn lot() -> ParkLot {
+        ParkLot::new(ParkConfig {
+            timeout_sec: 30,
+            max_per_agent: 2,
+            callback_prefixes: vec![
+                Url::parse("https://hooks.example").unwrap(),
+                Url::parse("https://api.example/cb").unwrap(),
+            ],
+            ..ParkConfig::default()
+        })
+    }
+
+    fn send() -> SendMessageRequest {
+        let coord = ProtocolFixture::new("coord", "1.0").build();
+        SendFixture::novel("a", "b", &coord, "SHP|eta=7f").build()
+    }
+
+    #[test]
+    fn test_callbacks_must_match_a_prefix() {
+        let lot = lot();
+        assert!(lot.check_callback("https://hooks.example/a").is_ok());
+        assert!(lot.check_callback("https://HOOKS.example:443/a?x=1").is_ok());
+        assert!(lot.check_callback("https://api.example/cb/done").is_ok());
+        assert!(lot.check_callback("http://169.254.169.254/").is_err());
+        // Same leading characters, other host, port, scheme, or path
+        for url in [
+            "https://hooks.example.attacker.net/a",
+            "https://hooks.example@169.254.169.254/a",
+            "https://hooks.example:8443/a",
+            "http://hooks.example/a",
+            "https://api.example/cbx",
+            "https://api.example/cb/../admin",
+            "not a url",
+        ] {
+            assert!(lot.check_callback(url).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_parking_is_limited_per_agent() {
+        let lot = lot();
+        let first = lot.park(send(), "a::coord:1.0", "coord:1.0", 100).unwrap();
+        lot.park(send(), "a::coord:1.0", "coord:1.0", 110).unwrap();
+        assert_eq!(first.expires_at, 130);
+        assert!(lot.park(send(), "a::coord:1.0", "coord:1.0", 110).is_err());
+        assert!(lot.has_pending("a::coord:1.0"));
+        assert!(!lot.has_pending("a::other:1.0"));
+    }
+
+    #[test]
+    fn test_parked_send_expires_once() {
+        let lot = lot();
+        let parked = lot.park(send(), "a::coord:1.0", "coord:1.0", 100).unwrap();
+        let (expired, _) = lot.expire(parked.id, 130).unwrap();
+        assert_eq!(expired.state, ParkState::Expired);
+        assert!(lot.expire(parked.id, 131).is_none());
+        assert!(lot.take("a::coord:1.0").is_empty());
+    }
+
+    #[test]
+    fn test_report_releases_parked_sends_once() {
+        let lot = lot();
+        let parked = lot.park(send(), "a::coord:1.0", "coord:1.0", 100).unwrap();
+        let taken = lot.take("a::coord:1.0");
+        assert_eq!(taken.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![parked.id]);
+        assert!(lot.take("a::coord:1.0").is_empty());
+        let (status, _) = lot
+            .resolve(parked.id, ParkState::Delivered, 200, Value::Null, 105)
+            .unwrap();
+        assert_eq!(status.state, ParkState::Delivered);
+        assert_eq!(lot.pending(), 0);
+    }
+
+    #[test]
+    fn test_release_racing_expiry_wins() {
+        let lot = lot();
+        let parked = lot.park(send(), "a::coord:1.0", "coord:1.0", 100).unwrap();
+        lot.take("a::coord:1.0");
+        assert!(lot.expire(parked.id, 130).is_none());
+    }
+
+    #[test]
+    fn test_outcomes_are_pollable_until_forgotten() {
+        let lot = lot();
+        let parked = lot.park(send(), "a::coord:1.0", "coord:1.0", 100).unwrap();
+        lot.take("a::coord:1.0");
+        lot.resolve(parked.id, ParkState::Delivered, 200, Value::Null, 105);
+        assert_eq!(lot.status(parked.id).unwrap().status, Some(200));
+        lot.forget(parked.id);
+        assert!(lot.status(parked.id).is_none());
+    }
+}
@@ -0,0 +1,370 @@
+//! Versioned policy thresholds
+//!
+//! Every policy the gateway loads is identified by a version derived from its
+//! content (FNV-1a over the canonical JSON), so the same thresholds always map
+//! to the same version across restarts. Audit events and responses are stamped
+//! with the version that produced them, and `GET /policies/{version}` returns
+//! the exact thresholds that were in force.
+//!
+//! A request is decided under one policy from start to finish: the snapshot
+//! in force when it arrives is pinned for it (see [`pin`]), so a policy
+//! loaded meanwhile applies from the next request on. With a state store
+//! configured, every version is stored when first loaded and the versions of
+//! earlier runs stay retrievable after a restart.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    env,
+    future::Future,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    encryption::EncryptedContentPolicy, enforcement::EnforcementSchedule, recert::RecertPolicy, readability::QualityPolicy, reputation::Probation, risk::RiskPolicy, routing::Routing, soft_limits::SoftLimits, trial::TrialPolicy, now_unix_sec, DELETED_AGENT_RETENTION_SEC, MIN_CONSISTENCY, MIN_COVERAGE, MIN_SUMMARY_LENGTH,
+    REPORT_INTERVAL_SEC, REPORT_SAMPLE_SIZE, REPORT_STRIKE_LIMIT, UNUSED_PROTOCOL_SEC,
+};
+
+/// Policy thresholds applied to sends and reports
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Policy {
+    /// Maximum seconds allowed between reports for novel-language use
+    pub report_interval_sec: u64,
+    /// Minimum coverage fraction required in reports
+    pub min_coverage: f64,
+    /// Minimum English summary length in characters
+    pub min_summary_length: usize,
+    /// Consistency score below which a report is held for review
+    #[serde(default = "default_min_consistency")]
+    pub min_consistency: f64,
+    /// Consecutive held reports after which an agent protocol is suspended
+    /// for review; 0 disables suspension
+    #[serde(default = "default_report_strike_limit")]
+    pub report_strike_limit: u32,
+    /// Seconds without use after which a protocol is flagged for cleanup
+    pub unused_protocol_sec: u64,
+    /// Seconds a soft-deleted agent is kept before it is purged
+    #[serde(default = "default_deleted_agent_retention_sec")]
+    pub deleted_agent_retention_sec: u64,
+    /// Treatment of encrypted or opaque-encoded payloads
+    #[serde(default)]
+    pub encrypted_content: EncryptedContentPolicy,
+    /// Stricter terms for new and low-reputation agents; none when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probation: Option<Probation>,
+    /// Recipient classes and their pipelines; every recipient gets the
+    /// standard pipeline when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing: Option<Routing>,
+    /// Triggers that raise a protocol's effective risk tier, and the report
+    /// interval of each tier; registered tiers stand when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub risk: Option<RiskPolicy>,
+    /// Where agents are warned short of a hard limit
+    #[serde(default, skip_serializing_if = "SoftLimits::is_default")]
+    pub soft_limits: SoftLimits,
+    /// Relaxed report thresholds for protocols registered on trial; trials
+    /// are refused when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trial: Option<TrialPolicy>,
+    /// Recertification cycle of registered protocols; certifications never
+    /// lapse when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recertification: Option<RecertPolicy>,
+    /// Windows in which sends are only audited; strict enforcement always
+    /// when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enforcement: Option<EnforcementSchedule>,
+    /// Reported messages decoded against the codebook per report; 0 disables
+    /// sampling
+    #[serde(default = "default_report_sample_size", skip_serializing_if = "is_default_report_sample_size")]
+    pub report_sample_size: usize,
+    /// Minimum report summary quality by risk tier; summaries are scored but
+    /// not held to a minimum when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary_quality: Option<QualityPolicy>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            report_interval_sec: REPORT_INTERVAL_SEC,
+            min_coverage: MIN_COVERAGE,
+            min_summary_length: MIN_SUMMARY_LENGTH,
+            min_consistency: MIN_CONSISTENCY,
+            report_strike_limit: REPORT_STRIKE_LIMIT,
+            unused_protocol_sec: UNUSED_PROTOCOL_SEC,
+            deleted_agent_retention_sec: DELETED_AGENT_RETENTION_SEC,
+            encrypted_content: EncryptedContentPolicy::default(),
+            probation: None,
+            routing: None,
+            risk: None,
+            soft_limits: SoftLimits::default(),
+            trial: None,
+            recertification: None,
+            enforcement: None,
+            report_sample_size: REPORT_SAMPLE_SIZE,
+            summary_quality: None,
+        }
+    }
+}
+
+impl Policy {
+    /// Defaults overridden by `REPORT_INTERVAL_SEC`, `MIN_COVERAGE`,
+    /// `MIN_SUMMARY_LENGTH`, `MIN_CONSISTENCY`, `REPORT_STRIKE_LIMIT`,
+    /// `UNUSED_PROTOCOL_SEC`, `DELETED_AGENT_RETENTION_SEC`,
+    /// `ENCRYPTED_CONTENT_POLICY`, `RECIPIENT_ROUTING`, `PROTOCOL_RISK`,
+    /// `ENFORCEMENT_SCHEDULE`, `REPORT_SAMPLE_SIZE`, `SUMMARY_QUALITY`, and
+    /// the `PROBATION_*`, `SOFT_LIMIT_*`, `TRIAL_*`, and `RECERT_*` variables
+    pub fn from_env() -> Self {
+        use crate::env_parse as var;
+        let d = Self::default();
+        let report_interval_sec = var("REPORT_INTERVAL_SEC").unwrap_or(d.report_interval_sec);
+        Self {
+            report_interval_sec,
+            min_coverage: var("MIN_COVERAGE").unwrap_or(d.min_coverage),
+            min_summary_length: var("MIN_SUMMARY_LENGTH").unwrap_or(d.min_summary_length),
+            min_consistency: var("MIN_CONSISTENCY").unwrap_or(d.min_consistency),
+            report_strike_limit: var("REPORT_STRIKE_LIMIT").unwrap_or(d.report_strike_limit),
+            unused_protocol_sec: var("UNUSED_PROTOCOL_SEC").unwrap_or(d.unused_protocol_sec),
+            deleted_agent_retention_sec: var("DELETED_AGENT_RETENTION_SEC")
+                .unwrap_or(d.deleted_agent_retention_sec),
+            encrypted_content: env::var("ENCRYPTED_CONTENT_POLICY")
+                .ok()
+                .and_then(|v| EncryptedContentPolicy::parse(&v))
+                .unwrap_or(d.encrypted_content),
+            probation: Probation::from_env(),
+            routing: Routing::from_env(),
+            risk: RiskPolicy::from_env(),
+            soft_limits: SoftLimits::from_env(),
+            trial: TrialPolicy::from_env(),
+            recertification: RecertPolicy::from_env(report_interval_sec),
+            enforcement: EnforcementSchedule::from_env(),
+            report_sample_size: var("REPORT_SAMPLE_SIZE").unwrap_or(d.report_sample_size),
+            summary_quality: QualityPolicy::from_env(),
+        }
+    }
+
+    /// Content-derived version identifier
+    pub fn version_id(&self) -> u64 {
+        let canonical = serde_json::to_vec(self).unwrap_or_default();
+        canonical.iter().fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+            (h ^ u64::from(*b)).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    }
+}
+
+fn default_min_consistency() -> f64 {
+    MIN_CONSISTENCY
+}
+
+fn default_report_strike_limit() -> u32 {
+    REPORT_STRIKE_LIMIT
+}
+
+fn default_deleted_agent_retention_sec() -> u64 {
+    DELETED_AGENT_RETENTION_SEC
+}
+
+fn default_report_sample_size() -> usize {
+    REPORT_SAMPLE_SIZE
+}
+
+fn is_default_report_sample_size(size: &usize) -> bool {
+    *size == REPORT_SAMPLE_SIZE
+}
+
+/// A loaded policy and its version
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicySnapshot {
+    pub version: String,
+    #[serde(skip)]
+    pub version_id: u64,
+    /// When this version was first loaded
+    pub loaded_at: u64,
+    pub policy: Policy,
+}
+
+impl PolicySnapshot {
+    fn new(policy: Policy) -> Self {
+        let version_id = policy.version_id();
+        Self {
+            version: format!("{version_id:016x}"),
+            version_id,
+            loaded_at: now_unix_sec(),
+            policy,
+        }
+    }
+}
+
+/// Every policy version loaded by this process, plus the one in force
+#[derive(Debug)]
+pub struct PolicyRegistry {
+    current: RwLock<Arc<PolicySnapshot>>,
+    history: RwLock<HashMap<String, Arc<PolicySnapshot>>>,
+}
+
+impl Default for PolicyRegistry {
+    fn default() -> Self {
+        Self::new(Policy::default())
+    }
+}
+
+impl PolicyRegistry {
+    pub fn new(policy: Policy) -> Self {
+        let snapshot = Arc::new(PolicySnapshot::new(policy));
+        Self {
+            history: RwLock::new(HashMap::from([(
+                snapshot.version.clone(),
+                snapshot.clone(),
+            )])),
+            current: RwLock::new(snapshot),
+        }
+    }
+
+    /// Policy in force: the one pinned for the request being served, if any,
+    /// else the latest loaded
+    pub fn current(&self) -> Arc<PolicySnapshot> {
+        PINNED
+            .try_with(|pinned| pinned.borrow().clone())
+            .unwrap_or_else(|_| self.latest())
+    }
+
+    /// Latest policy loaded, whatever the request being served was pinned to
+    pub fn latest(&self) -> Arc<PolicySnapshot> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Look up a version loaded by this process or restored from the store
+    pub fn get(&self, version: &str) -> Option<Arc<PolicySnapshot>> {
+        self.history.read().unwrap().get(version).cloned()
+    }
+
+    /// Make `policy` current, keeping earlier versions retrievable; returns
+    /// the snapshot and whether its version is new. A request loading a
+    /// policy is decided under it from then on.
+    pub fn load(&self, policy: Policy) -> (Arc<PolicySnapshot>, bool) {
+        let candidate = PolicySnapshot::new(policy);
+        let mut history = self.history.write().unwrap();
+        let new = !history.contains_key(&candidate.version);
+        let snapshot = history
+            .entry(candidate.version.clone())
+            .or_insert_with(|| Arc::new(candidate))
+            .clone();
+        *self.current.write().unwrap() = snapshot.clone();
+        let _ = PINNED.try_with(|pinned| *pinned.borrow_mut() = snapshot.clone());
+        (snapshot, new)
+    }
+
+    /// Make an earlier version retrievable again, as read back from the
+    /// state store; the policy in force is unchanged
+    pub fn restore(&self, mut snapshot: PolicySnapshot) {
+        snapshot.version_id = u64::from_str_radix(&snapshot.version, 16).unwrap_or_default();
+        let mut history = self.history.write().unwrap();
+        match history.get(&snapshot.version) {
+            // Already in force since startup: keep the earlier load time
+            Some(known) if known.loaded_at > snapshot.loaded_at => {
+                let snapshot = Arc::new(snapshot);
+                let mut current = self.current.write().unwrap();
+                if current.version == snapshot.version {
+                    *current = snapshot.clone();
+                }
+                history.insert(snapshot.version.clone(), snapshot);
+            }
+            Some(_) => {}
+            None => {
+                history.insert(snapshot.version.clone(), Arc::new(snapshot));
+            }
+        }
+    }
+}
+
+tokio::task_local! {
+    /// Snapshot the request being served is decided under
+    static PINNED: RefCell<Arc<PolicySnapshot>>;
+}
+
+/// Version pinned for the request being served, if any
+pub fn pinned_version() -> Option<String> {
+    PINNED.try_with(|pinned| pinned.borrow().version.clone()).ok()
+}
+
+/// Serve `request` under `snapshot`
+pub async fn pin<F: Future>(snapshot: Arc<PolicySnapshot>, request: F) -> F::Output {
+    PINNED.scope(RefCell::new(snapshot), request).await
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions_are_content_derived_and_retained() {
+        let registry = PolicyRegistry::default();
+        let v1 = registry.current();
+        assert_eq!(v1.version, PolicySnapshot::new(Policy::default()).version);
+
+        let stricter = Policy {
+            min_coverage: 0.99,
+            ..Policy::default()
+        };
+        let (v2, new) = registry.load(stricter.clone());
+        assert!(new);
+        assert_ne!(v1.version, v2.version);
+        assert_eq!(registry.current().version, v2.version);
+        assert_eq!(registry.get(&v1.version).unwrap().policy, Policy::default());
+        assert_eq!(registry.get(&v2.version).unwrap().policy, stricter);
+        assert!(registry.get("0000000000000000").is_none());
+        assert!(!registry.load(stricter).1, "a version is only new once");
+
+        // A version of an earlier run comes back without being put in force
+        let restarted = PolicyRegistry::new(Policy::default());
+        let stored: PolicySnapshot = serde_json::from_value(serde_json::to_value(&*v2).unwrap()).unwrap();
+        restarted.restore(stored);
+        assert_eq!(restarted.get(&v2.version).unwrap().version_id, v2.version_id);
+        assert_eq!(restarted.current().version, v1.version);
+    }
+
+    #[test]
+    fn test_fields_added_later_default() {
+        // Policies saved before these fields existed still load
+        let mut saved = serde_json::to_value(Policy::default()).unwrap();
+        for field in ["min_consistency", "deleted_agent_retention_sec"] {
+            saved.as_object_mut().unwrap().remove(field);
+        }
+        let loaded: Policy = serde_json::from_value(saved).unwrap();
+        assert_eq!(loaded, Policy::default());
+    }
+
+    #[tokio::test]
+    async fn test_request_keeps_its_policy() {
+        let registry = Arc::new(PolicyRegistry::default());
+        let v1 = registry.current();
+        let stricter = Policy {
+            min_coverage: 0.99,
+            ..Policy::default()
+        };
+        let request = pin(v1.clone(), {
+            let registry = registry.clone();
+            async move {
+                let before = registry.current().version.clone();
+                tokio::task::yield_now().await;
+                (before, registry.current().version.clone())
+            }
+        });
+        let (before, after) = tokio::join!(request, async {
+            registry.load(stricter.clone());
+        })
+        .0;
+        assert_eq!((before.as_str(), after.as_str()), (v1.version.as_str(), v1.version.as_str()));
+        assert_ne!(registry.current().version, v1.version);
+
+        let loaded = pin(v1.clone(), async { registry.load(stricter).0.version == registry.current().version });
+        assert!(loaded.await, "a request loading a policy decides under it");
+    }
+}
@@ -0,0 +1,353 @@
+//! Quarantine of encrypted sends
+//!
+//! Under the `quarantine` encrypted-content policy an encrypted send is held
+//! for review and answered with 202. After review an admin releases it, which
+//! re-evaluates it through every check but the encrypted-content policy and
+//! delivers or refuses it, or discards it, which counts as a compliance
+//! violation against the sender. An agent has at most
+//! `QUARANTINE_MAX_PER_AGENT` sends held; past that its encrypted sends are
+//! refused as under `deny`.
+//!
+//! A held send's outcome can be polled at `GET /quarantine/{id}` for
+//! `QUARANTINE_RETENTION_SEC` after it resolves. If the send named a
+//! `callback_url`, the outcome is also POSTed there through the outbox (see
+//! `outbox`), as for parked sends.
+//!
+//! The quarantine does not watch the clock itself: the caller schedules each
+//! outcome's retention on the gateway's timer wheel and calls
+//! [`Quarantine::forget`] when it fires. Held sends live only in memory and
+//! are not replicated.
+
+use axum::{http::StatusCode, Json};
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, sync::Mutex};
+
+use crate::{
+    encryption::OpaqueContent,
+    env_parse,
+    language::LanguageOfRecord,
+    messages::{self, Message},
+    ApiResponse, SendMessageRequest,
+};
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+#[derive(Debug, Clone)]
+pub struct QuarantineConfig {
+    /// Sends held at once per agent
+    pub max_per_agent: usize,
+    /// Seconds a resolved send's outcome stays pollable
+    pub retention_sec: u64,
+}
+
+impl Default for QuarantineConfig {
+    fn default() -> Self {
+        Self {
+            max_per_agent: 100,
+            retention_sec: 86_400,
+        }
+    }
+}
+
+impl QuarantineConfig {
+    /// Load settings from `QUARANTINE_*` environment variables
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let parse = |name: &str| env_parse::<u64>(name);
+        Self {
+            max_per_agent: parse("QUARANTINE_MAX_PER_AGENT").map_or(defaults.max_per_agent, |n| n as usize),
+            retention_sec: parse("QUARANTINE_RETENTION_SEC").unwrap_or(defaults.retention_sec),
+        }
+    }
+}
+
+// =============================================================================
+// Held sends
+// =============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuarantineState {
+    /// Awaiting review
+    Held,
+    /// Released and being re-evaluated
+    Released,
+    Delivered,
+    Refused,
+    Discarded,
+}
+
+impl QuarantineState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Held => "held",
+            Self::Released => "released",
+            Self::Delivered => "delivered",
+            Self::Refused => "refused",
+            Self::Discarded => "discarded",
+        }
+    }
+}
+
+/// A held send as seen by `GET /quarantine/{id}` and callbacks
+#[derive(Debug, Clone, Serialize)]
+pub struct QuarantineStatus {
+    pub id: u64,
+    pub agent_id: String,
+    pub state: QuarantineState,
+    pub quarantined_at: u64,
+    pub detected: OpaqueContent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_at: Option<u64>,
+    /// HTTP status the send was answered with on re-evaluation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Response body of the re-evaluated send
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+}
+
+/// A send awaiting review, as listed by `GET /quarantine`
+#[derive(Debug, Clone, Serialize)]
+pub struct QuarantinedMessage {
+    pub id: u64,
+    pub quarantined_at: u64,
+    pub detected: OpaqueContent,
+    pub message: SendMessageRequest,
+}
+
+/// A send just held for review
+///
+/// Holding a send does not refuse it: the sender is answered 202 with code
+/// `quarantined`, and learns the outcome from `GET /quarantine/{id}` or its
+/// callback.
+#[derive(Debug, Clone, Copy)]
+pub struct Quarantined {
+    pub id: u64,
+}
+
+impl Quarantined {
+    /// The sender's answer, phrased for its language of record
+    pub fn response(self, record: Option<&LanguageOfRecord>) -> (StatusCode, Json<ApiResponse>) {
+        let message = Message::new("quarantined").arg("id", self.id);
+        let mut body = ApiResponse::success_with_message(&messages::localize(&message, record));
+        body.code = Some("quarantined");
+        (StatusCode::ACCEPTED, Json(body))
+    }
+}
+
+struct Entry {
+    status: QuarantineStatus,
+    /// The send itself, until it is released or discarded
+    req: Option<SendMessageRequest>,
+    callback_url: Option<String>,
+}
+
+#[derive(Default)]
+struct Held {
+    next_id: u64,
+    entries: BTreeMap<u64, Entry>,
+}
+
+/// Encrypted sends held for review
+#[derive(Default)]
+pub struct Quarantine {
+    config: QuarantineConfig,
+    held: Mutex<Held>,
+}
+
+impl Quarantine {
+    pub fn new(config: QuarantineConfig) -> Self {
+        Self {
+            config,
+            held: Mutex::new(Held::default()),
+        }
+    }
+
+    pub fn config(&self) -> &QuarantineConfig {
+        &self.config
+    }
+
+    /// Hold `req` for review
+    ///
+    /// Fails when the sender already has `QUARANTINE_MAX_PER_AGENT` sends held.
+    pub fn hold(&self, req: SendMessageRequest, detected: OpaqueContent, now: u64) -> Result<u64, String> {
+        let mut held = self.held.lock().unwrap();
+        let count = held
+            .entries
+            .values()
+            .filter(|e| e.req.is_some() && req.from == *e.status.agent_id)
+            .count();
+        if count >= self.config.max_per_agent {
+            return Err(format!("{count} sends already quarantined"));
+        }
+        held.next_id += 1;
+        let id = held.next_id;
+        let status = QuarantineStatus {
+            id,
+            agent_id: req.from.to_string(),
+            state: QuarantineState::Held,
+            quarantined_at: now,
+            detected,
+            resolved_at: None,
+            status: None,
+            response: None,
+        };
+        let callback_url = req.callback_url.clone();
+        held.entries.insert(
+            id,
+            Entry {
+                status,
+                req: Some(req),
+                callback_url,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Sends awaiting review, oldest first
+    pub fn held(&self) -> Vec<QuarantinedMessage> {
+        self.held
+            .lock()
+            .unwrap()
+            .entries
+            .values()
+            .filter_map(|e| {
+                Some(QuarantinedMessage {
+                    id: e.status.id,
+                    quarantined_at: e.status.quarantined_at,
+                    detected: e.status.detected,
+                    message: e.req.clone()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Take a held send for re-evaluation
+    pub fn release(&self, id: u64) -> Option<(QuarantineStatus, SendMessageRequest)> {
+        let mut held = self.held.lock().unwrap();
+        let entry = held.entries.get_mut(&id)?;
+        let req = entry.req.take()?;
+        entry.status.state = QuarantineState::Released;
+        Some((entry.status.clone(), req))
+    }
+
+    /// Record the outcome of a released send, returning its status and
+    /// callback URL
+    pub fn resolve(
+        &self,
+        id: u64,
+        state: QuarantineState,
+        status: u16,
+        response: Value,
+        now: u64,
+    ) -> Option<(QuarantineStatus, Option<String>)> {
+        let mut held = self.held.lock().unwrap();
+        let entry = held.entries.get_mut(&id)?;
+        entry.status.state = state;
+        entry.status.resolved_at = Some(now);
+        entry.status.status = Some(status);
+        entry.status.response = Some(response);
+        Some((entry.status.clone(), entry.callback_url.clone()))
+    }
+
+    /// Discard a held send, returning it with its status and callback URL
+    pub fn discard(&self, id: u64, now: u64) -> Option<(QuarantineStatus, SendMessageRequest, Option<String>)> {
+        let mut held = self.held.lock().unwrap();
+        let entry = held.entries.get_mut(&id)?;
+        let req = entry.req.take()?;
+        entry.status.state = QuarantineState::Discarded;
+        entry.status.resolved_at = Some(now);
+        Some((entry.status.clone(), req, entry.callback_url.clone()))
+    }
+
+    /// Drop a resolved send's outcome once it is no longer kept for polling
+    pub fn forget(&self, id: u64) {
+        let mut held = self.held.lock().unwrap();
+        if held.entries.get(&id).is_some_and(|e| e.status.resolved_at.is_some()) {
+            held.entries.remove(&id);
+        }
+    }
+
+    /// Drop everything held for a purged agent
+    pub fn forget_agent(&self, agent_id: &str) {
+        self.held.lock().unwrap().entries.retain(|_, e| e.status.agent_id != agent_id);
+    }
+
+    pub fn status(&self, id: u64) -> Option<QuarantineStatus> {
+        self.held.lock().unwrap().entries.get(&id).map(|e| e.status.clone())
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encryption::{self, EncryptedContentPolicy},
+        policy::Policy,
+        testing::{AgentFixture, ProtocolFixture, SendFixture, TestGateway},
+    };
+    use axum::http::{Method, StatusCode};
+
+    const SEALED: &str = "U2FsdGVkX1+vupppZksvRf5pq5g5XjFRlipRkwB0K1Y96Qsv2Lm+31cmzaAILwyt";
+
+    #[test]
+    fn test_hold_release_and_cap() {
+        let quarantine = Quarantine::new(QuarantineConfig {
+            max_per_agent: 1,
+            ..QuarantineConfig::default()
+        });
+        let detected = encryption::detect(SEALED).unwrap();
+        let id = quarantine.hold(SendFixture::english("a", "b").build(), detected, 100).unwrap();
+        assert!(quarantine.hold(SendFixture::english("a", "b").build(), detected, 101).is_err(), "capped per agent");
+        assert!(quarantine.hold(SendFixture::english("c", "b").build(), detected, 101).is_ok());
+        assert_eq!(quarantine.held().len(), 2);
+
+        let (status, req) = quarantine.release(id).unwrap();
+        assert_eq!((status.state, req.from.as_str()), (QuarantineState::Released, "a"));
+        assert!(quarantine.release(id).is_none(), "released once");
+        assert_eq!(quarantine.held().len(), 1);
+        quarantine.forget(id);
+        assert!(quarantine.status(id).is_some(), "kept until resolved");
+
+        quarantine.resolve(id, QuarantineState::Delivered, 200, Value::Null, 110).unwrap();
+        assert!(quarantine.hold(SendFixture::english("a", "b").build(), detected, 111).is_ok(), "a slot is free again");
+        quarantine.forget(id);
+        assert!(quarantine.status(id).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_released_message_is_delivered() {
+        let gw = TestGateway::with_policy(Policy {
+            encrypted_content: EncryptedContentPolicy::Quarantine,
+            ..Policy::default()
+        });
+        let coord = ProtocolFixture::new("coord", "1.0").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(coord.clone()).reported()).await;
+        let resp = gw.send(&SendFixture::novel("a", "b", &coord, SEALED).build()).await;
+        assert_eq!((resp.status, resp.body["code"].as_str()), (StatusCode::ACCEPTED, Some("quarantined")));
+        let id = gw.admin(Method::GET, "/quarantine", None::<&()>).await.body[0]["id"].as_u64().unwrap();
+
+        let resp = gw.admin(Method::POST, &format!("/quarantine/{id}/release"), None::<&()>).await;
+        assert_eq!((resp.status, resp.body["state"].as_str()), (StatusCode::ACCEPTED, Some("released")));
+        let mut status = Value::Null;
+        for _ in 0..100 {
+            status = gw.admin(Method::GET, &format!("/quarantine/{id}"), None::<&()>).await.body;
+            if status["state"] != "released" {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert_eq!((status["state"].as_str(), status["status"].as_u64()), (Some("delivered"), Some(200)), "{status}");
+        assert!(gw.admin(Method::GET, "/quarantine", None::<&()>).await.body.as_array().unwrap().is_empty());
+        let resp = gw.admin(Method::POST, &format!("/quarantine/{id}/discard"), None::<&()>).await;
+        assert_eq!(resp.status, StatusCode::NOT_FOUND, "resolved once");
+    }
+}
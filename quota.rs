@@ -0,0 +1,690 @@
+//! Per-tenant and per-agent storage quotas
+//!
+//! Usage is counted over a rolling `QUOTA_WINDOW_SEC` for each agent and for
+//! its tenant (owning team, `unassigned` otherwise):
+//!
+//! - **events**: audit events attributed to the agent (`from` / `agent_id`)
+//! - **message_bytes**: message content stored for consistency scoring or
+//!   quarantine
+//! - **reports**: reports submitted
+//!
+//! Limits come from `QUOTA_LIMITS`, e.g.
+//! `{"agent": {"events": 50000}, "tenant": {"message_bytes": 104857600},
+//! "tenants": {"red": {"reports": 10000}}}`. Per-agent and per-tenant entries
+//! override the defaults field by field; unset limits are unlimited.
+//!
+//! `QUOTA_ACTION` decides what happens once a limit is reached:
+//!
+//! - **reject**: ingestion from the agent is refused with 507, and its further
+//!   audit events are counted but not stored
+//! - **drop_content**: ingestion continues, but message content, held report
+//!   summaries, and audit event fields are replaced by SHA-256 digests
+//! - **alert**: nothing is enforced
+//!
+//! Every breach raises a `quota_exceeded` alert, at most once per window per
+//! agent or tenant and resource.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    env, fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tracing::warn;
+
+use crate::{clock::Clock, env_parse, intern::entry_mut, slo::UNASSIGNED_TENANT};
+
+/// Buckets per window; usage expires one bucket at a time
+const BUCKETS_PER_WINDOW: u64 = 60;
+
+/// A metered resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resource {
+    Events,
+    MessageBytes,
+    Reports,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Events => "events",
+            Self::MessageBytes => "message_bytes",
+            Self::Reports => "reports",
+        })
+    }
+}
+
+const RESOURCES: [Resource; 3] = [Resource::Events, Resource::MessageBytes, Resource::Reports];
+
+/// Usage of every resource
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub events: u64,
+    pub message_bytes: u64,
+    pub reports: u64,
+}
+
+impl Usage {
+    fn get(&self, resource: Resource) -> u64 {
+        match resource {
+            Resource::Events => self.events,
+            Resource::MessageBytes => self.message_bytes,
+            Resource::Reports => self.reports,
+        }
+    }
+
+    fn add(&mut self, resource: Resource, amount: u64) {
+        let field = match resource {
+            Resource::Events => &mut self.events,
+            Resource::MessageBytes => &mut self.message_bytes,
+            Resource::Reports => &mut self.reports,
+        };
+        *field = field.saturating_add(amount);
+    }
+}
+
+/// Limits per resource; `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub events: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reports: Option<u64>,
+}
+
+impl Limits {
+    fn get(&self, resource: Resource) -> Option<u64> {
+        match resource {
+            Resource::Events => self.events,
+            Resource::MessageBytes => self.message_bytes,
+            Resource::Reports => self.reports,
+        }
+    }
+
+    /// `self` with unset fields taken from `defaults`
+    fn or(self, defaults: Limits) -> Limits {
+        Limits {
+            events: self.events.or(defaults.events),
+            message_bytes: self.message_bytes.or(defaults.message_bytes),
+            reports: self.reports.or(defaults.reports),
+        }
+    }
+}
+
+/// Enforcement once a limit is reached
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaAction {
+    #[default]
+    Reject,
+    DropContent,
+    Alert,
+}
+
+impl QuotaAction {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "reject" => Some(Self::Reject),
+            "drop_content" | "drop-content" => Some(Self::DropContent),
+            "alert" => Some(Self::Alert),
+            _ => None,
+        }
+    }
+}
+
+/// `QUOTA_LIMITS` document
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuotaLimits {
+    /// Default limits for every agent
+    #[serde(default)]
+    pub agent: Limits,
+    /// Default limits for every tenant
+    #[serde(default)]
+    pub tenant: Limits,
+    #[serde(default)]
+    pub agents: HashMap<String, Limits>,
+    #[serde(default)]
+    pub tenants: HashMap<String, Limits>,
+}
+
+#[derive(Debug, Clone)]
+pub struct QuotaConfig {
+    pub window_sec: u64,
+    pub action: QuotaAction,
+    pub limits: QuotaLimits,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            window_sec: 86_400,
+            action: QuotaAction::Reject,
+            limits: QuotaLimits::default(),
+        }
+    }
+}
+
+impl QuotaConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let action = match env::var("QUOTA_ACTION") {
+            Ok(raw) => QuotaAction::parse(&raw).unwrap_or_else(|| {
+                warn!(event = "config_invalid", action = %raw, "Unknown QUOTA_ACTION, using reject");
+                defaults.action
+            }),
+            Err(_) => defaults.action,
+        };
+        let limits = match env::var("QUOTA_LIMITS") {
+            Ok(raw) if !raw.trim().is_empty() => serde_json::from_str(&raw).unwrap_or_else(|e| {
+                warn!(event = "config_invalid", error = %e, "Invalid QUOTA_LIMITS, quotas disabled");
+                QuotaLimits::default()
+            }),
+            _ => QuotaLimits::default(),
+        };
+        Self {
+            window_sec: env_parse("QUOTA_WINDOW_SEC")
+                .unwrap_or(defaults.window_sec)
+                .max(BUCKETS_PER_WINDOW),
+            action,
+            limits,
+        }
+    }
+
+    fn agent_limits(&self, agent_id: &str) -> Limits {
+        self.limits
+            .agents
+            .get(agent_id)
+            .copied()
+            .unwrap_or_default()
+            .or(self.limits.agent)
+    }
+
+    fn tenant_limits(&self, tenant: &str) -> Limits {
+        self.limits
+            .tenants
+            .get(tenant)
+            .copied()
+            .unwrap_or_default()
+            .or(self.limits.tenant)
+    }
+}
+
+/// Whether a quota belongs to an agent or a tenant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Agent,
+    Tenant,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Agent => "agent",
+            Self::Tenant => "tenant",
+        })
+    }
+}
+
+/// A limit that would be exceeded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Breach {
+    pub scope: Scope,
+    pub subject: String,
+    pub resource: Resource,
+    pub used: u64,
+    pub limit: u64,
+}
+
+impl fmt::Display for Breach {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} quota exceeded for {} ({} of {})",
+            self.scope, self.resource, self.subject, self.used, self.limit
+        )
+    }
+}
+
+/// How an audit event from an agent is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventAdmission {
+    Store,
+    /// Store with fields replaced by a digest
+    Digest,
+    Suppress,
+}
+
+/// Usage in fixed-width buckets covering one window
+#[derive(Debug, Default)]
+struct Window {
+    buckets: VecDeque<(u64, Usage)>,
+}
+
+impl Window {
+    fn expire(&mut self, now: u64, window_sec: u64) {
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(start, _)| now.saturating_sub(*start) >= window_sec)
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    fn add(&mut self, now: u64, window_sec: u64, resource: Resource, amount: u64) {
+        self.expire(now, window_sec);
+        let width = (window_sec / BUCKETS_PER_WINDOW).max(1);
+        let start = now - now % width;
+        match self.buckets.back_mut() {
+            Some((s, usage)) if *s == start => usage.add(resource, amount),
+            _ => {
+                let mut usage = Usage::default();
+                usage.add(resource, amount);
+                self.buckets.push_back((start, usage));
+            }
+        }
+    }
+
+    fn total(&mut self, now: u64, window_sec: u64) -> Usage {
+        self.expire(now, window_sec);
+        let mut total = Usage::default();
+        for (_, usage) in &self.buckets {
+            for r in RESOURCES {
+                total.add(r, usage.get(r));
+            }
+        }
+        total
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    agents: HashMap<String, Window>,
+    tenants: HashMap<String, Window>,
+    /// Agent -> owning team, mirrored from the directory
+    owners: HashMap<String, String>,
+    /// Audit events not stored per agent, since startup
+    suppressed: HashMap<String, u64>,
+    /// (scope, subject, resource) -> time of the last alert
+    alerted: HashMap<(Scope, String, Resource), u64>,
+    /// Breaches awaiting an alert
+    pending: Vec<Breach>,
+}
+
+impl Inner {
+    fn tenant_of(&self, agent_id: &str) -> &str {
+        self.owners.get(agent_id).map_or(UNASSIGNED_TENANT, String::as_str)
+    }
+}
+
+/// Usage and limits of one agent or tenant, as listed by `/admin/quotas`
+#[derive(Debug, Clone, Serialize)]
+pub struct SubjectUsage {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub usage: Usage,
+    pub limits: Limits,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exceeded: Vec<Resource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suppressed_events: Option<u64>,
+}
+
+/// Body of `GET /admin/quotas`
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaReport {
+    pub window_sec: u64,
+    pub action: QuotaAction,
+    pub tenants: Vec<SubjectUsage>,
+    pub agents: Vec<SubjectUsage>,
+}
+
+/// Usage accounting and limit checks
+#[derive(Debug, Default)]
+pub struct QuotaTracker {
+    config: QuotaConfig,
+    clock: Arc<Clock>,
+    inner: Mutex<Inner>,
+    pub rejections: AtomicU64,
+    pub suppressed_events: AtomicU64,
+}
+
+impl QuotaTracker {
+    pub fn new(config: QuotaConfig, clock: Arc<Clock>) -> Self {
+        Self {
+            config,
+            clock,
+            ..Self::default()
+        }
+    }
+
+    pub fn action(&self) -> QuotaAction {
+        self.config.action
+    }
+
+    /// Mirror an ownership change so tenant usage is attributed correctly
+    pub fn set_owner(&self, agent_id: &str, team: &str) {
+        self.inner
+            .lock()
+            .unwrap()
+            .owners
+            .insert(agent_id.to_string(), team.to_string());
+    }
+
+    /// Drop all accounting for a purged agent
+    pub fn forget_agent(&self, agent_id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.agents.remove(agent_id);
+        inner.owners.remove(agent_id);
+        inner.suppressed.remove(agent_id);
+    }
+
+    fn breach(&self, inner: &mut Inner, agent_id: &str, resource: Resource, amount: u64, now: u64) -> Option<Breach> {
+        let window = self.config.window_sec;
+        let Inner {
+            agents,
+            tenants,
+            owners,
+            alerted,
+            pending,
+            ..
+        } = inner;
+        let tenant = owners.get(agent_id).map_or(UNASSIGNED_TENANT, String::as_str);
+        let candidates = [
+            (Scope::Agent, agent_id, self.config.agent_limits(agent_id)),
+            (Scope::Tenant, tenant, self.config.tenant_limits(tenant)),
+        ];
+        for (scope, subject, limits) in candidates {
+            let Some(limit) = limits.get(resource) else {
+                continue;
+            };
+            let windows = match scope {
+                Scope::Agent => &mut *agents,
+                Scope::Tenant => &mut *tenants,
+            };
+            let used = windows.get_mut(subject).map_or(0, |w| w.total(now, window).get(resource));
+            if used.saturating_add(amount) > limit {
+                let breach = Breach {
+                    scope,
+                    subject: subject.to_string(),
+                    resource,
+                    used,
+                    limit,
+                };
+                let key = (scope, breach.subject.clone(), resource);
+                let due = alerted.get(&key).is_none_or(|at| now.saturating_sub(*at) >= window);
+                if due {
+                    alerted.insert(key, now);
+                    pending.push(breach.clone());
+                }
+                return Some(breach);
+            }
+        }
+        None
+    }
+
+    /// Whether `amount` more of `resource` from `agent_id` would exceed a limit
+    pub fn check(&self, agent_id: &str, resource: Resource, amount: u64) -> Option<Breach> {
+        let now = self.clock.now();
+        let mut inner = self.inner.lock().unwrap();
+        self.breach(&mut inner, agent_id, resource, amount, now)
+    }
+
+    /// Limits the agent or its tenant has used at least `ratio` of, short of
+    /// reaching them
+    pub fn nearing(&self, agent_id: &str, ratio: f64) -> Vec<Breach> {
+        let now = self.clock.now();
+        let window = self.config.window_sec;
+        let mut inner = self.inner.lock().unwrap();
+        let Inner {
+            agents,
+            tenants,
+            owners,
+            ..
+        } = &mut *inner;
+        let tenant = owners.get(agent_id).map_or(UNASSIGNED_TENANT, String::as_str);
+        let candidates = [
+            (Scope::Agent, agent_id, self.config.agent_limits(agent_id), agents),
+            (Scope::Tenant, tenant, self.config.tenant_limits(tenant), tenants),
+        ];
+        let mut nearing = Vec::new();
+        for (scope, subject, limits, windows) in candidates {
+            let Some(usage) = windows.get_mut(subject).map(|w| w.total(now, window)) else {
+                continue;
+            };
+            for resource in RESOURCES {
+                let Some(limit) = limits.get(resource) else {
+                    continue;
+                };
+                let used = usage.get(resource);
+                if used < limit && used as f64 >= ratio * limit as f64 {
+                    nearing.push(Breach {
+                        scope,
+                        subject: subject.to_string(),
+                        resource,
+                        used,
+                        limit,
+                    });
+                }
+            }
+        }
+        nearing
+    }
+
+    /// Headroom left in the tightest agent or tenant limit on `resource`;
+    /// none when neither is limited
+    pub fn remaining(&self, agent_id: &str, resource: Resource) -> Option<u64> {
+        let now = self.clock.now();
+        let window = self.config.window_sec;
+        let mut inner = self.inner.lock().unwrap();
+        let Inner {
+            agents,
+            tenants,
+            owners,
+            ..
+        } = &mut *inner;
+        let tenant = owners.get(agent_id).map_or(UNASSIGNED_TENANT, String::as_str);
+        let candidates = [
+            (agent_id, self.config.agent_limits(agent_id), agents),
+            (tenant, self.config.tenant_limits(tenant), tenants),
+        ];
+        candidates
+            .into_iter()
+            .filter_map(|(subject, limits, windows)| {
+                let limit = limits.get(resource)?;
+                let used = windows.get_mut(subject).map_or(0, |w| w.total(now, window).get(resource));
+                Some(limit.saturating_sub(used))
+            })
+            .min()
+    }
+
+    /// Count usage against the agent and its tenant
+    pub fn record(&self, agent_id: &str, resource: Resource, amount: u64) {
+        let now = self.clock.now();
+        let window = self.config.window_sec;
+        let mut inner = self.inner.lock().unwrap();
+        let Inner {
+            agents,
+            tenants,
+            owners,
+            ..
+        } = &mut *inner;
+        let tenant = owners.get(agent_id).map_or(UNASSIGNED_TENANT, String::as_str);
+        entry_mut(agents, agent_id).add(now, window, resource, amount);
+        entry_mut(tenants, tenant).add(now, window, resource, amount);
+    }
+
+    /// Decide how to store an audit event attributed to `agent_id`, counting it
+    pub fn admit_event(&self, agent_id: &str) -> EventAdmission {
+        let now = self.clock.now();
+        let mut inner = self.inner.lock().unwrap();
+        let admission = match self.breach(&mut inner, agent_id, Resource::Events, 1, now) {
+            None => EventAdmission::Store,
+            Some(_) => match self.config.action {
+                QuotaAction::Reject => EventAdmission::Suppress,
+                QuotaAction::DropContent => EventAdmission::Digest,
+                QuotaAction::Alert => EventAdmission::Store,
+            },
+        };
+        if admission == EventAdmission::Suppress {
+            *inner.suppressed.entry(agent_id.to_string()).or_insert(0) += 1;
+            self.suppressed_events.fetch_add(1, Ordering::Relaxed);
+        } else {
+            drop(inner);
+            self.record(agent_id, Resource::Events, 1);
+        }
+        admission
+    }
+
+    /// Breaches not yet alerted on
+    pub fn take_breaches(&self) -> Vec<Breach> {
+        std::mem::take(&mut self.inner.lock().unwrap().pending)
+    }
+
+    /// Usage of every agent and tenant seen in the current window
+    pub fn report(&self) -> QuotaReport {
+        let now = self.clock.now();
+        let window = self.config.window_sec;
+        let mut inner = self.inner.lock().unwrap();
+        let exceeded = |usage: &Usage, limits: &Limits| {
+            RESOURCES
+                .into_iter()
+                .filter(|r| limits.get(*r).is_some_and(|l| usage.get(*r) >= l))
+                .collect()
+        };
+
+        let agent_ids: Vec<String> = inner.agents.keys().cloned().collect();
+        let mut agents = BTreeMap::new();
+        for agent_id in agent_ids {
+            let tenant = inner.tenant_of(&agent_id).to_string();
+            let usage = inner.agents.get_mut(&agent_id).map(|w| w.total(now, window)).unwrap_or_default();
+            let limits = self.config.agent_limits(&agent_id);
+            agents.insert(
+                agent_id.clone(),
+                SubjectUsage {
+                    name: agent_id.clone(),
+                    tenant: Some(tenant),
+                    exceeded: exceeded(&usage, &limits),
+                    usage,
+                    limits,
+                    suppressed_events: inner.suppressed.get(&agent_id).copied(),
+                },
+            );
+        }
+        let mut tenants = BTreeMap::new();
+        for (tenant, w) in inner.tenants.iter_mut() {
+            let usage = w.total(now, window);
+            let limits = self.config.tenant_limits(tenant);
+            tenants.insert(
+                tenant.clone(),
+                SubjectUsage {
+                    name: tenant.clone(),
+                    tenant: None,
+                    exceeded: exceeded(&usage, &limits),
+                    usage,
+                    limits,
+                    suppressed_events: None,
+                },
+            );
+        }
+        QuotaReport {
+            window_sec: window,
+            action: self.config.action,
+            tenants: tenants.into_values().collect(),
+            agents: agents.into_values().collect(),
+        }
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_agent_and_tenant_limits() {
+        let clock = Arc::new(Clock::manual(1_700_000_000));
+        let limits: QuotaLimits = serde_json::from_str(
+            r#"{"agent": {"reports": 2}, "tenant": {"message_bytes": 100},
+                "agents": {"vip": {"reports": 10}}}"#,
+        )
+        .unwrap();
+        let quota = QuotaTracker::new(
+            QuotaConfig {
+                window_sec: 3_600,
+                limits,
+                ..QuotaConfig::default()
+            },
+            clock.clone(),
+        );
+        quota.set_owner("a", "red");
+        quota.set_owner("b", "red");
+
+        // Agent limit, with a per-agent override
+        quota.record("a", Resource::Reports, 2);
+        let breach = quota.check("a", Resource::Reports, 1).unwrap();
+        assert_eq!((breach.scope, breach.used, breach.limit), (Scope::Agent, 2, 2));
+        quota.record("vip", Resource::Reports, 2);
+        assert!(quota.check("vip", Resource::Reports, 1).is_none());
+
+        // Tenant limit shared by its agents
+        quota.record("a", Resource::MessageBytes, 60);
+        assert!(quota.check("b", Resource::MessageBytes, 40).is_none());
+        let breach = quota.check("b", Resource::MessageBytes, 41).unwrap();
+        assert_eq!((breach.scope, breach.subject.as_str()), (Scope::Tenant, "red"));
+
+        // Nearing a limit, short of reaching it
+        let nearing = quota.nearing("b", 0.5);
+        assert_eq!(nearing.len(), 1);
+        assert_eq!((nearing[0].scope, nearing[0].resource, nearing[0].used), (Scope::Tenant, Resource::MessageBytes, 60));
+        assert!(quota.nearing("b", 0.7).is_empty());
+        assert!(quota.nearing("a", 0.5).iter().all(|n| n.resource != Resource::Reports));
+
+        // One alert per breach per window
+        assert_eq!(quota.take_breaches().len(), 2);
+        quota.check("a", Resource::Reports, 1);
+        assert!(quota.take_breaches().is_empty());
+
+        // Usage ages out of the window
+        clock.advance(Duration::from_secs(3_600));
+        assert!(quota.check("a", Resource::Reports, 1).is_none());
+
+        let report = quota.report();
+        let red = report.tenants.iter().find(|t| t.name == "red").unwrap();
+        assert_eq!(red.usage, Usage::default());
+
+        // Events over quota are suppressed under `reject`
+        let events = QuotaTracker::new(
+            QuotaConfig {
+                limits: QuotaLimits {
+                    agent: Limits {
+                        events: Some(1),
+                        ..Limits::default()
+                    },
+                    ..QuotaLimits::default()
+                },
+                ..QuotaConfig::default()
+            },
+            clock,
+        );
+        assert_eq!(events.admit_event("a"), EventAdmission::Store);
+        assert_eq!(events.admit_event("a"), EventAdmission::Suppress);
+        let a = &events.report().agents[0];
+        assert_eq!((a.usage.events, a.suppressed_events), (1, Some(1)));
+        assert_eq!(a.exceeded, vec![Resource::Events]);
+    }
+}
@@ -0,0 +1,275 @@
+//! Quality scoring of report summaries
+//!
+//! `MIN_SUMMARY_LENGTH` alone is met by padding: a repeated phrase or a run
+//! of filler words is long enough. Every report's `english_summary` is scored
+//! in `[0, 1]` as the mean of three measures:
+//!
+//! - lexical variety: the type-token ratio, averaged over windows of
+//!   [`TTR_WINDOW`] words so long summaries are not penalised for length
+//! - stopword balance: the share of function words (`the`, `of`, `to`, ...),
+//!   full marks between [`STOPWORDS_LOW`] and [`STOPWORDS_HIGH`]; keyword
+//!   lists have almost none and filler has little else
+//! - sentence structure: mean sentence length between [`SENTENCE_WORDS_MIN`]
+//!   and [`SENTENCE_WORDS_MAX`] words, scaled by the share of sentences that
+//!   are not repeats
+//!
+//! The policy's `summary_quality` section sets a minimum score per effective
+//! risk tier; a tier not listed takes the highest minimum of a lower tier.
+//! Scores of accepted reports are stored with them and kept per agent, newest
+//! [`HISTORY`] of them, for `GET /agents/{id}/summary-quality`.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    env,
+};
+use tracing::warn;
+
+use crate::risk::RiskTier;
+
+/// Words per window of the moving type-token ratio
+pub const TTR_WINDOW: usize = 50;
+
+/// Stopword share below which a summary reads as a keyword list
+pub const STOPWORDS_LOW: f64 = 0.1;
+
+/// Stopword share above which a summary reads as filler
+pub const STOPWORDS_HIGH: f64 = 0.6;
+
+/// Shortest mean sentence, in words, that reads as prose
+pub const SENTENCE_WORDS_MIN: f64 = 4.0;
+
+/// Longest mean sentence, in words, before it reads as one run-on list
+pub const SENTENCE_WORDS_MAX: f64 = 40.0;
+
+/// Scores kept per agent
+pub const HISTORY: usize = 100;
+
+/// English function words, also ignored when comparing a summary with
+/// glosses (see `translation`)
+pub const STOPWORDS: &[&str] = &[
+    "a", "an", "the", "and", "or", "but", "nor", "so", "if", "then", "than", "of", "to", "in", "on",
+    "at", "by", "for", "with", "from", "into", "onto", "as", "about", "after", "before", "while",
+    "is", "are", "was", "were", "be", "been", "being", "has", "have", "had", "do", "does", "did",
+    "will", "would", "can", "could", "should", "may", "it", "its", "this", "that", "these",
+    "those", "he", "she", "they", "them", "their", "we", "our", "you", "your", "i", "not", "no",
+    "which", "who", "what", "all", "each", "also",
+];
+
+/// Readability measures of one summary
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Quality {
+    /// Mean of the three component scores
+    pub score: f64,
+    /// Moving type-token ratio
+    pub type_token_ratio: f64,
+    pub stopword_ratio: f64,
+    pub mean_sentence_words: f64,
+    /// Share of sentences that are not repeats of an earlier one
+    pub distinct_sentences: f64,
+}
+
+impl Quality {
+    /// Score `summary`; an empty summary scores 0
+    pub fn of(summary: &str) -> Self {
+        let words: Vec<String> = summary
+            .split(|c: char| !c.is_alphanumeric() && c != '\'')
+            .filter(|w| w.chars().any(char::is_alphanumeric))
+            .map(str::to_lowercase)
+            .collect();
+        if words.is_empty() {
+            return Self {
+                score: 0.0,
+                type_token_ratio: 0.0,
+                stopword_ratio: 0.0,
+                mean_sentence_words: 0.0,
+                distinct_sentences: 0.0,
+            };
+        }
+
+        let window = TTR_WINDOW.min(words.len());
+        let windows = words.windows(window);
+        let count = windows.len() as f64;
+        let type_token_ratio = windows
+            .map(|w| w.iter().collect::<HashSet<_>>().len() as f64 / window as f64)
+            .sum::<f64>()
+            / count;
+
+        let stopwords = words.iter().filter(|w| STOPWORDS.contains(&w.as_str())).count();
+        let stopword_ratio = stopwords as f64 / words.len() as f64;
+        let stopword_fit = if stopword_ratio < STOPWORDS_LOW {
+            stopword_ratio / STOPWORDS_LOW
+        } else if stopword_ratio > STOPWORDS_HIGH {
+            ((1.0 - stopword_ratio) / (1.0 - STOPWORDS_HIGH)).max(0.0)
+        } else {
+            1.0
+        };
+
+        let sentences: Vec<String> = summary
+            .split(['.', '!', '?', ';', '\n'])
+            .map(|s| s.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase())
+            .filter(|s| s.chars().any(char::is_alphanumeric))
+            .collect();
+        let sentence_count = sentences.len().max(1) as f64;
+        let mean_sentence_words = words.len() as f64 / sentence_count;
+        let distinct_sentences = sentences.iter().collect::<HashSet<_>>().len().max(1) as f64 / sentence_count;
+        let length_fit = if mean_sentence_words < SENTENCE_WORDS_MIN {
+            mean_sentence_words / SENTENCE_WORDS_MIN
+        } else if mean_sentence_words > SENTENCE_WORDS_MAX {
+            SENTENCE_WORDS_MAX / mean_sentence_words
+        } else {
+            1.0
+        };
+        let structure = length_fit * distinct_sentences;
+
+        Self {
+            score: (type_token_ratio + stopword_fit + structure) / 3.0,
+            type_token_ratio,
+            stopword_ratio,
+            mean_sentence_words,
+            distinct_sentences,
+        }
+    }
+}
+
+/// Minimum summary quality by effective risk tier
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QualityPolicy {
+    /// Tier -> minimum score; tiers not listed take the highest minimum of a
+    /// lower tier, or none
+    #[serde(default)]
+    pub min_score: BTreeMap<RiskTier, f64>,
+}
+
+impl QualityPolicy {
+    /// Parse `SUMMARY_QUALITY`, a JSON summary quality section; none when unset
+    pub fn from_env() -> Option<Self> {
+        let raw = env::var("SUMMARY_QUALITY").ok().filter(|v| !v.trim().is_empty())?;
+        match serde_json::from_str::<Self>(&raw).map_err(|e| e.to_string()).and_then(|q| q.validate().map(|_| q)) {
+            Ok(quality) => Some(quality),
+            Err(e) => {
+                warn!(event = "config_invalid", error = %e, "Ignoring invalid SUMMARY_QUALITY");
+                None
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self.min_score.iter().find(|(_, s)| !(0.0..=1.0).contains(*s)) {
+            Some((tier, _)) => Err(format!("summary_quality.min_score.{tier} must be within [0, 1]")),
+            None => Ok(()),
+        }
+    }
+
+    /// Minimum score for a protocol at `tier`, if any
+    pub fn minimum(&self, tier: RiskTier) -> Option<f64> {
+        self.min_score.range(..=tier).map(|(_, &s)| s).reduce(f64::max)
+    }
+}
+
+/// One accepted report's score
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Sample {
+    pub protocol: String,
+    pub accepted_at: u64,
+    #[serde(flatten)]
+    pub quality: Quality,
+}
+
+/// An agent's summary quality over its recent reports
+#[derive(Debug, Clone, Serialize)]
+pub struct Trend {
+    pub agent_id: String,
+    pub reports: usize,
+    pub mean: Option<f64>,
+    /// Change in score per report, by least squares; none under two reports
+    pub slope: Option<f64>,
+    /// Oldest first
+    pub samples: Vec<Sample>,
+}
+
+impl Trend {
+    pub fn of(agent_id: &str, samples: Option<&VecDeque<Sample>>) -> Self {
+        let samples: Vec<Sample> = samples.map(|s| s.iter().cloned().collect()).unwrap_or_default();
+        let n = samples.len() as f64;
+        let mean = (!samples.is_empty()).then(|| samples.iter().map(|s| s.quality.score).sum::<f64>() / n);
+        let slope = mean.filter(|_| samples.len() >= 2).map(|mean| {
+            let center = (n - 1.0) / 2.0;
+            let (cov, var) = samples.iter().enumerate().fold((0.0, 0.0), |(cov, var), (i, s)| {
+                let dx = i as f64 - center;
+                (cov + dx * (s.quality.score - mean), var + dx * dx)
+            });
+            cov / var
+        });
+        Self {
+            agent_id: agent_id.to_string(),
+            reports: samples.len(),
+            mean,
+            slope,
+            samples,
+        }
+    }
+}
+
+/// Keep `sample` in an agent's history, dropping the oldest past [`HISTORY`]
+pub fn record(history: &mut VecDeque<Sample>, sample: Sample) {
+    if history.len() == HISTORY {
+        history.pop_front();
+    }
+    history.push_back(sample);
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        policy::Policy,
+        testing::{AgentFixture, ProtocolFixture, ReportFixture, TestGateway},
+    };
+    use axum::http::StatusCode;
+
+    #[test]
+    fn test_padding_scores_below_prose() {
+        let prose = Quality::of(
+            "Exchanged task queue updates with agent-002. It assigned task 17 to the planner, \
+             and we acknowledged completion of task 42 after the retry.",
+        );
+        let repeated = Quality::of("Status update sent. Status update sent. Status update sent. Status update sent.");
+        let keywords = Quality::of("task queue update assign planner complete retry ack shipment eta route");
+        let filler = Quality::of("it is what it is and that is that and so it is as it was then");
+        assert!(prose.score > 0.8, "{prose:?}");
+        for padded in [repeated, keywords, filler] {
+            assert!(padded.score < prose.score - 0.2, "{padded:?}");
+        }
+        assert_eq!(Quality::of("  ...  ").score, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_padded_summary_refused_by_tier_and_scores_kept() {
+        let policy = Policy {
+            summary_quality: Some(serde_json::from_str(r#"{"min_score": {"medium": 0.7}}"#).unwrap()),
+            ..Policy::default()
+        };
+        let gw = TestGateway::with_policy(policy);
+        let high = ProtocolFixture::new("coord", "1.0").risk_tier("high").build();
+        let low = ProtocolFixture::new("chat", "1.0").risk_tier("low").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(high.clone()).protocol(low.clone())).await;
+        let padded = "Status update sent. Status update sent. Status update sent. Status update sent.";
+
+        // high takes medium's minimum; low has none
+        let refused = gw.report(&ReportFixture::new("a", &high).summary(padded).build()).await;
+        assert_eq!(refused.status, StatusCode::BAD_REQUEST);
+        assert_eq!(refused.body["code"], "summary_quality_low");
+        assert_eq!(gw.report(&ReportFixture::new("a", &low).summary(padded).build()).await.status, StatusCode::OK);
+        assert_eq!(gw.report(&ReportFixture::new("a", &high).build()).await.status, StatusCode::OK);
+
+        let trend = gw.get("/agents/a/summary-quality").await;
+        assert_eq!(trend.body["reports"], 2);
+        assert_eq!(trend.body["samples"][0]["protocol"], "chat:1.0");
+        assert!(trend.body["slope"].as_f64().unwrap() > 0.5, "{:?}", trend.body);
+    }
+}
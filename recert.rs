@@ -0,0 +1,208 @@
+//! Scheduled protocol recertification
+//!
+//! Governance recertifies every registered protocol on a fixed cycle. While
+//! the policy's `recertification` section is set, a protocol must be
+//! recertified `interval_sec` after it was registered or last recertified,
+//! its recertify-by date. Past that date it goes through these stages:
+//!
+//! - `due`: from `remind_before_sec` before the date; the owner is reminded
+//! - `restricted`: from the date; reports on the protocol are due every
+//!   `restricted_report_interval_sec` or sooner
+//! - `suspended`: `grace_sec` after the date; sends on the protocol are
+//!   refused with 403 `recertification_lapsed`
+//!
+//! Each stage is logged (`recertification_due`, `recertification_restricted`,
+//! `recertification_suspended`) and alerts the owner through the alert
+//! channels. An admin recertifies a protocol with
+//! `POST /protocols/{agent_id}/{name}/{version}/recertify`, which starts a
+//! new cycle from then. Removing the section lifts every restriction.
+
+use serde::{Deserialize, Serialize};
+
+/// Quarterly, in seconds
+pub const DEFAULT_INTERVAL_SEC: u64 = 91 * 86_400;
+
+/// Reminders and grace of two weeks, in seconds
+pub const DEFAULT_NOTICE_SEC: u64 = 14 * 86_400;
+
+/// Recertification cycle of registered protocols
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecertPolicy {
+    /// Seconds a certification lasts; quarterly by default
+    #[serde(default = "default_interval_sec")]
+    pub interval_sec: u64,
+    /// Seconds before the recertify-by date the owner is reminded
+    #[serde(default = "default_notice_sec")]
+    pub remind_before_sec: u64,
+    /// Seconds past the recertify-by date a protocol stays restricted before
+    /// it is suspended
+    #[serde(default = "default_notice_sec")]
+    pub grace_sec: u64,
+    /// Longest report interval of a restricted protocol
+    pub restricted_report_interval_sec: u64,
+}
+
+fn default_interval_sec() -> u64 {
+    DEFAULT_INTERVAL_SEC
+}
+
+fn default_notice_sec() -> u64 {
+    DEFAULT_NOTICE_SEC
+}
+
+/// Where a protocol stands in its recertification cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecertStage {
+    Certified,
+    Due,
+    Restricted,
+    Suspended,
+}
+
+/// A protocol's certification, as reported in its stats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RecertStatus {
+    pub stage: RecertStage,
+    pub certified_at: u64,
+    /// Recertify-by date
+    pub due_at: u64,
+}
+
+impl RecertPolicy {
+    /// Read `RECERT_INTERVAL_SEC`, which enables recertification, and
+    /// `RECERT_REMIND_SEC`, `RECERT_GRACE_SEC`, and `RECERT_REPORT_INTERVAL_SEC`
+    pub fn from_env(report_interval_sec: u64) -> Option<Self> {
+        use crate::env_parse as var;
+        let interval_sec = var("RECERT_INTERVAL_SEC").filter(|&s| s > 0)?;
+        Some(Self {
+            interval_sec,
+            remind_before_sec: var("RECERT_REMIND_SEC").unwrap_or(DEFAULT_NOTICE_SEC),
+            grace_sec: var("RECERT_GRACE_SEC").unwrap_or(DEFAULT_NOTICE_SEC),
+            restricted_report_interval_sec: var("RECERT_REPORT_INTERVAL_SEC")
+                .unwrap_or((report_interval_sec / 2).max(1)),
+        })
+    }
+
+    /// A certification must last, and restriction must tighten reporting
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval_sec == 0 {
+            return Err("recertification.interval_sec must be positive".to_string());
+        }
+        if self.remind_before_sec >= self.interval_sec {
+            return Err("recertification.remind_before_sec must be below interval_sec".to_string());
+        }
+        if self.restricted_report_interval_sec == 0 {
+            return Err("recertification.restricted_report_interval_sec must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// Recertify-by date of a protocol certified at `certified_at`
+    pub fn due_at(&self, certified_at: u64) -> u64 {
+        certified_at.saturating_add(self.interval_sec)
+    }
+
+    /// Stage of a protocol certified at `certified_at`, as of `now`
+    pub fn stage(&self, certified_at: u64, now: u64) -> RecertStage {
+        let due = self.due_at(certified_at);
+        if now >= due.saturating_add(self.grace_sec) {
+            RecertStage::Suspended
+        } else if now >= due {
+            RecertStage::Restricted
+        } else if now >= due.saturating_sub(self.remind_before_sec) {
+            RecertStage::Due
+        } else {
+            RecertStage::Certified
+        }
+    }
+
+    /// Certification of a protocol certified at `certified_at`, as of `now`
+    pub fn status(&self, certified_at: u64, now: u64) -> RecertStatus {
+        RecertStatus {
+            stage: self.stage(certified_at, now),
+            certified_at,
+            due_at: self.due_at(certified_at),
+        }
+    }
+
+    /// When a protocol certified at `certified_at` next changes stage after
+    /// `now`; none once it is suspended
+    pub fn next_change(&self, certified_at: u64, now: u64) -> Option<u64> {
+        let due = self.due_at(certified_at);
+        [due.saturating_sub(self.remind_before_sec), due, due.saturating_add(self.grace_sec)]
+            .into_iter()
+            .find(|&at| at > now)
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        policy::Policy,
+        testing::{AgentFixture, ProtocolFixture, SendFixture, TestGateway, ADMIN_TOKEN},
+    };
+    use axum::http::{Method, StatusCode};
+    use std::time::Duration;
+
+    fn policy() -> RecertPolicy {
+        RecertPolicy {
+            interval_sec: 1000,
+            remind_before_sec: 100,
+            grace_sec: 200,
+            restricted_report_interval_sec: 30,
+        }
+    }
+
+    #[test]
+    fn test_stages() {
+        let recert = policy();
+        assert!(recert.validate().is_ok());
+        assert!(RecertPolicy { remind_before_sec: 1000, ..policy() }.validate().is_err());
+
+        assert_eq!(recert.stage(0, 899), RecertStage::Certified);
+        assert_eq!(recert.stage(0, 900), RecertStage::Due);
+        assert_eq!(recert.stage(0, 1000), RecertStage::Restricted);
+        assert_eq!(recert.stage(0, 1200), RecertStage::Suspended);
+        assert_eq!(recert.next_change(0, 0), Some(900));
+        assert_eq!(recert.next_change(0, 900), Some(1000));
+        assert_eq!(recert.next_change(0, 1100), Some(1200));
+        assert_eq!(recert.next_change(0, 1200), None);
+    }
+
+    #[tokio::test]
+    async fn test_lapsed_protocol_suspended_until_recertified() {
+        let gw = TestGateway::with_policy(Policy {
+            report_interval_sec: 3600,
+            recertification: Some(policy()),
+            ..Policy::default()
+        });
+        let protocol = ProtocolFixture::new("coord", "1.0").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(protocol.clone()).reported()).await;
+        let send = SendFixture::novel("a", "b", &protocol, "SHP|eta=7").build();
+        let stats = "/protocols/a/coord/1.0/stats";
+
+        gw.advance(Duration::from_secs(1000));
+        assert_eq!(gw.get(stats).await.body["recertification"]["stage"], "restricted");
+        let resp = gw.send(&send).await;
+        assert_eq!(resp.body["code"], "report_overdue", "past the restricted report interval");
+
+        gw.advance(Duration::from_secs(200));
+        let resp = gw.send(&send).await;
+        assert_eq!(resp.status, StatusCode::FORBIDDEN);
+        assert_eq!(resp.body["code"], "recertification_lapsed");
+
+        let path = "/protocols/a/coord/1.0/recertify";
+        assert_eq!(gw.post(path, &()).await.status, StatusCode::UNAUTHORIZED);
+        let resp = gw.call(Method::POST, path, None::<&()>, Some(ADMIN_TOKEN)).await;
+        assert_eq!(resp.status, StatusCode::OK, "{:?}", resp.body);
+        let body = gw.get(stats).await.body;
+        assert_eq!(body["recertification"]["stage"], "certified");
+        assert_eq!(body["recertification"]["due_at"], gw.now() + 1000);
+    }
+}
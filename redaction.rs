@@ -0,0 +1,125 @@
+//! Redaction of protocol tokens quoted in report summaries
+//!
+//! A summary that quotes raw novel-language tokens carries covert content
+//! into the English record. While a report is scored, its summary is scanned
+//! for tokens of the reported protocol, matched whole and case-sensitively:
+//!
+//! - a codebook token is replaced with its gloss in brackets, so `SHP` reads
+//!   `[shipment]`
+//! - a token of the protocol's recent traffic that has no gloss and does not
+//!   read as a word (it contains a digit, or is all capitals) becomes
+//!   `[REDACTED:token]`
+//!
+//! Both versions are kept. The summary as filed is scored, stored, and shown
+//! to admins and auditors (the review queue, the state store, threads read
+//! with their tokens); team and open callers reading a thread see the
+//! redacted one. A report with anything redacted is logged as
+//! `report_redacted`, with counts but not the tokens.
+
+use std::collections::{BTreeMap, HashSet};
+
+/// Stands in for a quoted token without a gloss
+pub const REDACTED_TOKEN: &str = "[REDACTED:token]";
+
+/// A summary with its protocol tokens replaced
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redaction {
+    pub summary: String,
+    /// Tokens replaced with their gloss
+    pub glossed: usize,
+    /// Tokens replaced with [`REDACTED_TOKEN`]
+    pub redacted: usize,
+}
+
+fn is_token_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Whether a traffic token is protocol vocabulary rather than a word
+fn reads_as_code(token: &str) -> bool {
+    token.chars().any(|c| c.is_ascii_digit())
+        || (token.chars().count() > 1 && token.chars().all(|c| !c.is_lowercase()))
+}
+
+/// Redact the tokens of a protocol with `codebook` and recent `traffic`
+/// tokens quoted in `summary`; `None` when it quotes none
+pub fn redact<'a>(
+    summary: &str,
+    codebook: &BTreeMap<String, String>,
+    traffic: impl IntoIterator<Item = &'a str>,
+) -> Option<Redaction> {
+    let traffic: HashSet<&str> = traffic.into_iter().filter(|t| reads_as_code(t)).collect();
+    let mut out = Redaction {
+        summary: String::with_capacity(summary.len()),
+        glossed: 0,
+        redacted: 0,
+    };
+    let mut rest = summary;
+    while let Some(start) = rest.find(is_token_char) {
+        let (before, from) = rest.split_at(start);
+        let end = from.find(|c: char| !is_token_char(c)).unwrap_or(from.len());
+        let (token, after) = from.split_at(end);
+        out.summary.push_str(before);
+        if let Some(gloss) = codebook.get(token) {
+            out.summary.push('[');
+            out.summary.push_str(gloss);
+            out.summary.push(']');
+            out.glossed += 1;
+        } else if traffic.contains(token) {
+            out.summary.push_str(REDACTED_TOKEN);
+            out.redacted += 1;
+        } else {
+            out.summary.push_str(token);
+        }
+        rest = after;
+    }
+    out.summary.push_str(rest);
+    (out.glossed + out.redacted > 0).then_some(out)
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{AgentFixture, ProtocolFixture, ReportFixture, SendFixture, TestGateway, ADMIN_TOKEN};
+    use axum::http::{Method, StatusCode};
+
+    #[test]
+    fn test_quoted_tokens_are_glossed_or_redacted() {
+        let codebook = BTreeMap::from([("SHP".to_string(), "shipment".to_string())]);
+        let traffic = ["SHP", "eta", "7f", "QX"];
+        let redaction = redact("Sent SHP with eta=7f, then QX.", &codebook, traffic).unwrap();
+        assert_eq!(redaction.summary, "Sent [shipment] with eta=[REDACTED:token], then [REDACTED:token].");
+        assert_eq!((redaction.glossed, redaction.redacted), (1, 2));
+        assert_eq!(redact("Sent shipments (SHPX) on time", &codebook, traffic), None, "whole tokens only");
+    }
+
+    #[tokio::test]
+    async fn test_thread_readers_see_redacted_summary() {
+        let gw = TestGateway::new();
+        let protocol = ProtocolFixture::new("coord", "1.0").gloss("SHP", "shipment").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(protocol.clone()).reported()).await;
+        let send = SendFixture::novel("a", "b", &protocol, "SHP|eta=7f").thread("t1").build();
+        assert_eq!(gw.send(&send).await.status, StatusCode::OK);
+        let report = ReportFixture::new("a", &protocol)
+            .summary("Sent one SHP shipment update with an eta of 7f minutes")
+            .messages(1)
+            .thread("t1")
+            .build();
+        assert_eq!(gw.report(&report).await.status, StatusCode::OK);
+
+        let summary = |body: &serde_json::Value| body["entries"][1]["english_summary"].clone();
+        let open = gw.get("/threads/t1").await;
+        assert_eq!(summary(&open.body), "Sent one [shipment] shipment update with an eta of [REDACTED:token] minutes");
+        assert!(open.body["entries"][1].get("redacted_summary").is_none());
+        let admin = gw.call(Method::GET, "/threads/t1", None::<&()>, Some(ADMIN_TOKEN)).await;
+        assert_eq!(summary(&admin.body), "Sent one SHP shipment update with an eta of 7f minutes");
+        assert_eq!(
+            admin.body["entries"][1]["redacted_summary"],
+            "Sent one [shipment] shipment update with an eta of [REDACTED:token] minutes"
+        );
+    }
+}
@@ -0,0 +1,905 @@
+//! Protocol registry sync between gateways in different regions
+//!
+//! Every gateway serves a signed snapshot of its protocol registry at
+//! `GET /registry/snapshot` and, every `REGISTRY_SYNC_INTERVAL_SEC`, pulls
+//! the snapshot of each peer in `REGISTRY_SYNC_PEERS`. With every gateway
+//! listing the others, each registration reaches every region within one
+//! interval; a hub-and-spoke layout works the same way with the spokes
+//! listing only the hub.
+//!
+//! Snapshots are signed with this gateway's Ed25519 key (`REGISTRY_SYNC_KEY`)
+//! and a peer's snapshot is applied only when it verifies against the key
+//! pinned for it in `REGISTRY_SYNC_PEER_KEYS` and names that peer as its
+//! origin; one that does not raises a `chain_verification_failed` alert.
+//! Reading a snapshot requires `REGISTRY_SYNC_TOKEN`; sync is
+//! disabled when it is unset.
+//!
+//! Conflicts resolve by timestamp: each entry carries when and where it was
+//! last registered, and the later registration wins, ties going to the origin
+//! name that sorts last. Purging an agent leaves a tombstone that travels
+//! with the snapshots: peers purge the agent too and do not bring its
+//! protocols back, while registering again after the purge wins over it. Entries of agents
+//! soft-deleted locally are neither served nor imported. Only the registry is
+//! synced: report clocks, counters, and standing stay per gateway, so a
+//! protocol imported from a peer still needs a report here before novel sends.
+//!
+//! An imported entry passes the checks a local registration does: it is
+//! refused when another agent owns the protocol in the global namespace, when
+//! its agent is on probation here and it has no codebook, or when it revises
+//! fields `DESCRIPTOR_CHANGE_APPROVAL` guards, which only a local admin can
+//! approve. A refused entry is logged once as `registry_sync_entry_refused`
+//! and stays divergent until the peer's copy changes. Timestamps from the
+//! future are taken as now, so a peer's clock cannot pin an entry or a purge.
+//! A revision that is imported is logged as `protocol_descriptor_changed`.
+//!
+//! Imported registrations are recorded like local ones, so a warm standby
+//! follows them. Sync metadata (timestamps and tombstones) is kept in memory;
+//! after a restart entries fall back to their registration time.
+
+use axum::{extract::State, http::HeaderMap, Json};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as B64, Engine};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    sync::Mutex,
+    time::Duration,
+};
+use tracing::{info, warn};
+
+use crate::{
+    alerts::AlertKind,
+    bearer_token,
+    env_parse,
+    error::GatewayError,
+    namespace::Namespace,
+    policy::Policy,
+    protocol_key, raise_alert,
+    replication::Mutation,
+    revision::{self, ChangeControl, FieldChange},
+    signing, tokens_match, AlertSubject, AppState, InnerState, ProtocolDescriptor,
+};
+
+/// Default pause between pulls from each peer
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Per-request timeout when pulling a peer's snapshot
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+/// A gateway whose registry is pulled
+#[derive(Debug, Clone)]
+pub struct Peer {
+    pub name: String,
+    pub url: String,
+    pub key: VerifyingKey,
+}
+
+#[derive(Debug, Clone)]
+pub struct RegistrySyncConfig {
+    /// Origin name of this gateway in snapshots
+    pub name: String,
+    /// Shared secret for reading snapshots; sync is disabled when unset
+    pub token: Option<String>,
+    /// Ed25519 seed signing this gateway's snapshots; generated when unset
+    pub seed: Option<[u8; 32]>,
+    pub peers: Vec<Peer>,
+    pub interval: Duration,
+}
+
+impl Default for RegistrySyncConfig {
+    fn default() -> Self {
+        Self {
+            name: "gateway".to_string(),
+            token: None,
+            seed: None,
+            peers: Vec::new(),
+            interval: DEFAULT_INTERVAL,
+        }
+    }
+}
+
+fn decode_key<const N: usize>(encoded: &str) -> Option<[u8; N]> {
+    B64.decode(encoded.trim()).ok()?.try_into().ok()
+}
+
+impl RegistrySyncConfig {
+    /// Load `REGISTRY_SYNC_NAME`, `REGISTRY_SYNC_TOKEN`, `REGISTRY_SYNC_KEY`,
+    /// `REGISTRY_SYNC_PEERS` (`name=url,...`), `REGISTRY_SYNC_PEER_KEYS`
+    /// (`name:<base64url public key>,...`), and `REGISTRY_SYNC_INTERVAL_SEC`
+    pub fn from_env() -> Self {
+        let d = Self::default();
+        let list = |name: &str| {
+            env::var(name)
+                .unwrap_or_default()
+                .split(',')
+                .map(|e| e.trim().to_string())
+                .filter(|e| !e.is_empty())
+                .collect::<Vec<_>>()
+        };
+        let mut keys = HashMap::new();
+        for entry in list("REGISTRY_SYNC_PEER_KEYS") {
+            match entry.split_once(':').and_then(|(name, key)| Some((name, decode_key::<32>(key)?))) {
+                Some((name, key)) => match VerifyingKey::from_bytes(&key) {
+                    Ok(key) => {
+                        keys.insert(name.trim().to_string(), key);
+                    }
+                    Err(_) => warn!(event = "config_invalid", peer = name, "Ignoring invalid registry sync peer key"),
+                },
+                None => warn!(
+                    event = "config_invalid",
+                    "Ignoring REGISTRY_SYNC_PEER_KEYS entry: expected name:<base64url 32-byte public key>"
+                ),
+            }
+        }
+        let mut peers = Vec::new();
+        for entry in list("REGISTRY_SYNC_PEERS") {
+            let Some((name, url)) = entry.split_once('=') else {
+                warn!(event = "config_invalid", entry = %entry, "Ignoring REGISTRY_SYNC_PEERS entry: expected name=url");
+                continue;
+            };
+            let name = name.trim().to_string();
+            match keys.get(&name) {
+                Some(key) => peers.push(Peer {
+                    url: url.trim().trim_end_matches('/').to_string(),
+                    key: *key,
+                    name,
+                }),
+                None => warn!(event = "config_invalid", peer = %name, "Registry sync peer has no key in REGISTRY_SYNC_PEER_KEYS, ignoring it"),
+            }
+        }
+        let seed = env::var("REGISTRY_SYNC_KEY").ok().and_then(|k| {
+            let seed = decode_key::<32>(&k);
+            if seed.is_none() {
+                warn!(event = "config_invalid", "REGISTRY_SYNC_KEY is not a base64url 32-byte seed, generating a key");
+            }
+            seed
+        });
+        Self {
+            name: env::var("REGISTRY_SYNC_NAME")
+                .ok()
+                .map(|n| n.trim().to_string())
+                .filter(|n| !n.is_empty())
+                .unwrap_or(d.name),
+            token: env::var("REGISTRY_SYNC_TOKEN").ok().filter(|t| !t.is_empty()),
+            seed,
+            peers,
+            interval: env_parse("REGISTRY_SYNC_INTERVAL_SEC")
+                .filter(|&s: &u64| s > 0)
+                .map_or(d.interval, Duration::from_secs),
+        }
+    }
+}
+
+// =============================================================================
+// Snapshots
+// =============================================================================
+
+/// One registered protocol with when and where it was last registered
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryEntry {
+    pub agent_id: String,
+    pub protocol: ProtocolDescriptor,
+    pub registered_at: u64,
+    pub updated_at: u64,
+    pub origin: String,
+}
+
+/// A gateway's protocol registry at `generated_at`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrySnapshot {
+    pub origin: String,
+    pub generated_at: u64,
+    pub entries: Vec<RegistryEntry>,
+    /// agent_id -> when its registrations were purged
+    #[serde(default)]
+    pub tombstones: BTreeMap<String, u64>,
+}
+
+/// Body of `GET /registry/snapshot`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedSnapshot {
+    /// The [`RegistrySnapshot`] as JSON, signed byte for byte
+    pub snapshot: String,
+    /// base64url Ed25519 signature over `snapshot`
+    pub signature: String,
+}
+
+/// When and where an entry was last registered; later wins, then origin
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Stamp {
+    updated_at: u64,
+    origin: String,
+}
+
+#[derive(Debug, Default)]
+struct Book {
+    /// "agent_id::protocol_key" -> last registration
+    stamps: HashMap<String, Stamp>,
+    /// "agent_id::protocol_key" -> peer registration last refused
+    refused: HashMap<String, Stamp>,
+    tombstones: BTreeMap<String, u64>,
+    peers: BTreeMap<String, PeerStatus>,
+}
+
+/// Sync state of one peer, as listed by `GET /admin/registry-sync`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PeerStatus {
+    pub url: String,
+    /// `generated_at` of the last snapshot applied from the peer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub synced_to: Option<u64>,
+    /// Seconds since `synced_to`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lag_sec: Option<u64>,
+    /// Entries that differed from the peer at the last sync
+    pub divergent: u64,
+    pub imported: u64,
+    pub failures: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Body of `GET /admin/registry-sync`
+#[derive(Debug, Serialize)]
+pub struct RegistrySyncStatus {
+    pub name: String,
+    pub enabled: bool,
+    /// Public key peers pin for this gateway in `REGISTRY_SYNC_PEER_KEYS`
+    pub public_key: String,
+    pub interval_sec: u64,
+    pub tombstones: usize,
+    pub peers: BTreeMap<String, PeerStatus>,
+}
+
+/// Local registration checks imported entries must pass
+pub struct Checks<'a> {
+    pub namespace: &'a Namespace,
+    pub policy: &'a Policy,
+    pub change_control: &'a ChangeControl,
+}
+
+impl Checks<'_> {
+    /// `registration_rejected` reason and detail when `entry`, revising the
+    /// local descriptor by `changes`, would be refused here
+    fn refusal(
+        &self,
+        st: &InnerState,
+        entry: &RegistryEntry,
+        key: &str,
+        changes: &[FieldChange],
+    ) -> Option<(&'static str, String)> {
+        if let Err(owner) = self.namespace.check(st, &entry.agent_id, key, &entry.protocol) {
+            return Some(("protocol_name_taken", format!("protocol defined by {owner}")));
+        }
+        if st.reputation(self.policy, &entry.agent_id).codebook_required && entry.protocol.codebook.is_empty() {
+            return Some(("codebook_required", "agent on probation registered no codebook".to_string()));
+        }
+        let guarded = self.change_control.guarded(changes);
+        if !guarded.is_empty() {
+            return Some(("approval_required", format!("revises {} without local approval", guarded.join(","))));
+        }
+        None
+    }
+}
+
+/// A peer's entry that failed the local checks
+#[derive(Debug)]
+pub struct Refused {
+    pub agent_id: String,
+    pub protocol: String,
+    pub reason: &'static str,
+    pub detail: String,
+}
+
+/// Outcome of merging a peer's snapshot
+#[derive(Debug, Default)]
+pub struct Merge {
+    /// Registrations and purges taken from the peer, already applied
+    pub mutations: Vec<Mutation>,
+    /// "agent_id::protocol_key" -> fields an imported registration revised
+    pub changes: HashMap<String, Vec<FieldChange>>,
+    /// Entries refused since the peer last changed them
+    pub refused: Vec<Refused>,
+    pub divergent: u64,
+}
+
+/// Registry sync state and this gateway's signing key
+pub struct RegistrySync {
+    config: RegistrySyncConfig,
+    key: SigningKey,
+    client: reqwest::Client,
+    book: Mutex<Book>,
+}
+
+impl Default for RegistrySync {
+    fn default() -> Self {
+        Self::new(RegistrySyncConfig::default())
+    }
+}
+
+fn same_descriptor(a: &ProtocolDescriptor, b: &ProtocolDescriptor) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+impl RegistrySync {
+    pub fn new(config: RegistrySyncConfig) -> Self {
+        let seed = config.seed.unwrap_or_else(signing::random_bytes);
+        let peers = config
+            .peers
+            .iter()
+            .map(|p| {
+                let status = PeerStatus {
+                    url: p.url.clone(),
+                    ..PeerStatus::default()
+                };
+                (p.name.clone(), status)
+            })
+            .collect();
+        Self {
+            key: SigningKey::from_bytes(&seed),
+            client: reqwest::Client::builder()
+                .timeout(FETCH_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            book: Mutex::new(Book {
+                peers,
+                ..Book::default()
+            }),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &RegistrySyncConfig {
+        &self.config
+    }
+
+    /// base64url public key peers pin for this gateway
+    pub fn public_key(&self) -> String {
+        B64.encode(self.key.verifying_key().as_bytes())
+    }
+
+    /// Stamp a local registration of `report_key` at `now`
+    pub fn touch(&self, report_key: &str, now: u64) {
+        let stamp = Stamp {
+            updated_at: now,
+            origin: self.config.name.clone(),
+        };
+        self.book.lock().unwrap().stamps.insert(report_key.to_string(), stamp);
+    }
+
+    /// Record that `agent_id` was purged at `now`
+    pub fn tombstone(&self, agent_id: &str, now: u64) {
+        let mut book = self.book.lock().unwrap();
+        let prefix = format!("{agent_id}::");
+        book.stamps.retain(|k, _| !k.starts_with(&prefix));
+        let at = book.tombstones.entry(agent_id.to_string()).or_insert(0);
+        *at = (*at).max(now);
+    }
+
+    /// The registry in `st` as of `now`
+    pub fn snapshot(&self, st: &InnerState, now: u64) -> RegistrySnapshot {
+        let book = self.book.lock().unwrap();
+        let mut entries = Vec::new();
+        for (agent_id, protocols) in &st.protocols {
+            if st.is_deleted(agent_id) {
+                continue;
+            }
+            for (key, descriptor) in protocols {
+                let report_key = format!("{agent_id}::{key}");
+                let registered_at = st.protocol_stats.get(&report_key).map_or(0, |s| s.registered_at);
+                let stamp = self.local_stamp(&book, st, &report_key);
+                entries.push(RegistryEntry {
+                    agent_id: agent_id.clone(),
+                    protocol: descriptor.clone(),
+                    registered_at,
+                    updated_at: stamp.updated_at,
+                    origin: stamp.origin,
+                });
+            }
+        }
+        entries.sort_by(|a, b| {
+            (&a.agent_id, &a.protocol.name, &a.protocol.version).cmp(&(&b.agent_id, &b.protocol.name, &b.protocol.version))
+        });
+        RegistrySnapshot {
+            origin: self.config.name.clone(),
+            generated_at: now,
+            entries,
+            tombstones: book.tombstones.clone(),
+        }
+    }
+
+    pub fn sign(&self, snapshot: &RegistrySnapshot) -> SignedSnapshot {
+        let snapshot = serde_json::to_string(snapshot).unwrap_or_default();
+        let signature = self.sign_bytes(snapshot.as_bytes());
+        SignedSnapshot { snapshot, signature }
+    }
+
+    /// base64url Ed25519 signature of `bytes` with this gateway's key
+    pub fn sign_bytes(&self, bytes: &[u8]) -> String {
+        B64.encode(self.key.sign(bytes).to_bytes())
+    }
+
+    /// Check `signature` over `bytes` was made with `peer`'s key
+    pub fn verify_bytes(peer: &Peer, bytes: &[u8], signature: &str) -> Result<(), String> {
+        let signature = decode_key::<64>(signature)
+            .map(|bytes| Signature::from_bytes(&bytes))
+            .ok_or("malformed signature")?;
+        peer.key
+            .verify(bytes, &signature)
+            .map_err(|_| "signature does not match the peer's key".to_string())
+    }
+
+    /// The configured peer named `name`
+    pub fn peer(&self, name: &str) -> Option<&Peer> {
+        self.config.peers.iter().find(|p| p.name == name)
+    }
+
+    /// Check `signed` came from `peer` and decode it
+    pub fn verify(peer: &Peer, signed: &SignedSnapshot) -> Result<RegistrySnapshot, String> {
+        Self::verify_bytes(peer, signed.snapshot.as_bytes(), &signed.signature)?;
+        let snapshot: RegistrySnapshot =
+            serde_json::from_str(&signed.snapshot).map_err(|e| format!("malformed snapshot: {e}"))?;
+        if snapshot.origin != peer.name {
+            return Err(format!("snapshot from {} served by peer {}", snapshot.origin, peer.name));
+        }
+        Ok(snapshot)
+    }
+
+    /// Last registration of `report_key` here, or its registration time if never stamped
+    fn local_stamp(&self, book: &Book, st: &InnerState, report_key: &str) -> Stamp {
+        book.stamps.get(report_key).cloned().unwrap_or_else(|| Stamp {
+            updated_at: st.protocol_stats.get(report_key).map_or(0, |s| s.registered_at),
+            origin: self.config.name.clone(),
+        })
+    }
+
+    /// Take the peer's newer registrations and purges that pass `checks`
+    /// into `st` as of `now`
+    pub fn merge(&self, st: &mut InnerState, remote: &RegistrySnapshot, checks: &Checks, now: u64) -> Merge {
+        let mut book = self.book.lock().unwrap();
+        let tombstones: BTreeMap<&str, u64> = remote.tombstones.iter().map(|(a, &at)| (a.as_str(), at.min(now))).collect();
+        for (agent_id, &at) in &tombstones {
+            let local = book.tombstones.entry(agent_id.to_string()).or_insert(0);
+            *local = (*local).max(at);
+        }
+
+        let mut merge = Merge::default();
+        // Purges newer than every local registration of the agent
+        let purged: Vec<String> = tombstones
+            .iter()
+            .filter(|(agent_id, &at)| {
+                st.protocols.get(**agent_id).is_some_and(|protocols| {
+                    protocols
+                        .keys()
+                        .all(|key| self.local_stamp(&book, st, &format!("{agent_id}::{key}")).updated_at <= at)
+                })
+            })
+            .map(|(agent_id, _)| agent_id.to_string())
+            .collect();
+        for agent_id in purged {
+            let prefix = format!("{agent_id}::");
+            book.stamps.retain(|k, _| !k.starts_with(&prefix));
+            let mutation = Mutation::AgentPurged { agent_id };
+            mutation.clone().apply(st);
+            merge.mutations.push(mutation);
+        }
+
+        let mut seen = HashSet::new();
+        for entry in &remote.entries {
+            let key = protocol_key(&entry.protocol.name, &entry.protocol.version);
+            let report_key = format!("{}::{key}", entry.agent_id);
+            seen.insert(report_key.clone());
+            let updated_at = entry.updated_at.min(now);
+            let purged = book.tombstones.get(&entry.agent_id).is_some_and(|&at| at >= updated_at);
+            if purged || st.is_deleted(&entry.agent_id) {
+                continue;
+            }
+            let remote_stamp = Stamp {
+                updated_at,
+                origin: entry.origin.clone(),
+            };
+            let changes = match st.protocols.get(&entry.agent_id).and_then(|m| m.get(&key)) {
+                Some(local) if same_descriptor(local, &entry.protocol) => continue,
+                Some(local) => {
+                    merge.divergent += 1;
+                    if remote_stamp <= self.local_stamp(&book, st, &report_key) {
+                        continue;
+                    }
+                    revision::diff(local, &entry.protocol)
+                }
+                None => {
+                    merge.divergent += 1;
+                    Vec::new()
+                }
+            };
+            if let Some((reason, detail)) = checks.refusal(st, entry, &key, &changes) {
+                if book.refused.get(&report_key) == Some(&remote_stamp) {
+                    continue;
+                }
+                book.refused.insert(report_key, remote_stamp);
+                if reason == "protocol_name_taken" {
+                    checks.namespace.refuse(&key, &entry.agent_id);
+                }
+                merge.refused.push(Refused {
+                    agent_id: entry.agent_id.clone(),
+                    protocol: key,
+                    reason,
+                    detail,
+                });
+                continue;
+            }
+            book.refused.remove(&report_key);
+            book.stamps.insert(report_key.clone(), remote_stamp);
+            if !changes.is_empty() {
+                merge.changes.insert(report_key, changes);
+            }
+            let mutation = Mutation::ProtocolRegistered {
+                agent_id: entry.agent_id.clone(),
+                descriptor: Box::new(entry.protocol.clone()),
+                registered_at: entry.registered_at,
+                report_clock: None,
+                recertified_at: None,
+            };
+            mutation.clone().apply(st);
+            merge.mutations.push(mutation);
+        }
+
+        // Registrations the peer has yet to learn from us
+        merge.divergent += st
+            .protocols
+            .iter()
+            .filter(|(agent_id, _)| !st.is_deleted(agent_id))
+            .flat_map(|(agent_id, protocols)| protocols.keys().map(move |key| format!("{agent_id}::{key}")))
+            .filter(|report_key| !seen.contains(report_key))
+            .count() as u64;
+        merge
+    }
+
+    /// Sync state of every peer as of `now`
+    pub fn peers(&self, now: u64) -> BTreeMap<String, PeerStatus> {
+        let mut peers = self.book.lock().unwrap().peers.clone();
+        for status in peers.values_mut() {
+            status.lag_sec = status.synced_to.map(|at| now.saturating_sub(at));
+        }
+        peers
+    }
+
+    pub fn status(&self, now: u64) -> RegistrySyncStatus {
+        RegistrySyncStatus {
+            name: self.config.name.clone(),
+            enabled: self.config.token.is_some(),
+            public_key: self.public_key(),
+            interval_sec: self.config.interval.as_secs(),
+            tombstones: self.book.lock().unwrap().tombstones.len(),
+            peers: self.peers(now),
+        }
+    }
+
+    fn record_success(&self, peer: &str, generated_at: u64, divergent: u64, imported: usize) {
+        if let Some(status) = self.book.lock().unwrap().peers.get_mut(peer) {
+            status.synced_to = Some(generated_at);
+            status.divergent = divergent;
+            status.imported += imported as u64;
+            status.last_error = None;
+        }
+    }
+
+    /// Count a failed sync; returns whether it failed differently last time
+    fn record_failure(&self, peer: &str, error: &str) -> bool {
+        let mut book = self.book.lock().unwrap();
+        let Some(status) = book.peers.get_mut(peer) else {
+            return false;
+        };
+        status.failures += 1;
+        status.last_error.replace(error.to_string()).as_deref() != Some(error)
+    }
+
+    async fn fetch(&self, peer: &Peer, token: &str) -> Result<SignedSnapshot, String> {
+        self
+            .client
+            .get(format!("{}/registry/snapshot", peer.url))
+            .bearer_auth(token)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+// =============================================================================
+// Handlers and sync loop
+// =============================================================================
+
+/// This gateway's signed registry snapshot
+pub(crate) async fn snapshot(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<SignedSnapshot>, GatewayError> {
+    let sync = &state.registry_sync;
+    let Some(expected) = sync.config.token.as_deref() else {
+        return Err(GatewayError::RegistrySyncDisabled);
+    };
+    if !bearer_token(&headers).is_some_and(|t| tokens_match(t, expected)) {
+        return Err(GatewayError::InvalidRegistrySyncToken);
+    }
+    let snapshot = {
+        let st = state.inner.read().unwrap();
+        sync.snapshot(&st, state.clock.now())
+    };
+    Ok(Json(sync.sign(&snapshot)))
+}
+
+/// Pull and merge every peer's snapshot each interval
+pub async fn run(state: AppState) {
+    let sync = state.registry_sync.clone();
+    let Some(token) = sync.config.token.clone() else {
+        return;
+    };
+    if sync.config.peers.is_empty() {
+        return;
+    }
+    let mut interval = tokio::time::interval(sync.config.interval);
+    loop {
+        interval.tick().await;
+        // A standby follows the primary's registry
+        if state.replication.is_standby() {
+            continue;
+        }
+        for peer in &sync.config.peers {
+            let signed = match sync.fetch(peer, &token).await {
+                Ok(signed) => signed,
+                Err(error) => {
+                    sync.record_failure(&peer.name, &error);
+                    warn!(peer = %peer.name, error = %error, event = "registry_sync_failed", "Registry sync with peer failed");
+                    continue;
+                }
+            };
+            let remote = match RegistrySync::verify(peer, &signed) {
+                Ok(remote) => remote,
+                Err(error) => {
+                    let changed = sync.record_failure(&peer.name, &error);
+                    warn!(
+                        peer = %peer.name,
+                        error = %error,
+                        event = "registry_snapshot_unverified",
+                        "Peer registry snapshot failed verification, not merged"
+                    );
+                    if changed {
+                        let detail = format!("Registry snapshot from peer {} failed verification: {error}", peer.name);
+                        raise_alert(&state, AlertKind::ChainVerificationFailed, AlertSubject::Gateway, &detail).await;
+                    }
+                    continue;
+                }
+            };
+            let merge = {
+                let policy = state.policy.current();
+                let checks = Checks {
+                    namespace: &state.namespace,
+                    policy: &policy.policy,
+                    change_control: &state.change_control,
+                };
+                let mut st = state.inner.write().unwrap();
+                let merge = sync.merge(&mut st, &remote, &checks, state.clock.now());
+                for mutation in &merge.mutations {
+                    state.replication.record(mutation.clone());
+                }
+                merge
+            };
+            let mut imported = 0;
+            for mutation in &merge.mutations {
+                match mutation {
+                    Mutation::ProtocolRegistered { agent_id, descriptor, .. } => {
+                        let key = protocol_key(&descriptor.name, &descriptor.version);
+                        state.decision_cache.invalidate_agent(agent_id);
+                        state.registration_misses.invalidate(&format!("{agent_id}::{key}"));
+                        imported += 1;
+                        info!(
+                            agent_id = %agent_id,
+                            protocol = %key,
+                            peer = %peer.name,
+                            event = "protocol_registration_synced",
+                            "Protocol registration imported from peer"
+                        );
+                        if let Some(changes) = merge.changes.get(&format!("{agent_id}::{key}")) {
+                            info!(
+                                agent_id = %agent_id,
+                                protocol = %key,
+                                fields = %changes.iter().map(|c| c.field.as_str()).collect::<Vec<_>>().join(","),
+                                changes = %serde_json::to_string(changes).unwrap_or_default(),
+                                approved = false,
+                                peer = %peer.name,
+                                event = "protocol_descriptor_changed",
+                                "Protocol descriptor revised by peer"
+                            );
+                        }
+                    }
+                    Mutation::AgentPurged { agent_id } => {
+                        state.decision_cache.invalidate_agent(agent_id);
+                        state.translator.forget_agent(agent_id);
+                        state.slo.forget_agent(agent_id);
+                        state.quota.forget_agent(agent_id);
+                        state.soft_limit_notices.forget_agent(agent_id);
+                        warn!(agent_id = %agent_id, peer = %peer.name, event = "agent_purge_synced", "Agent purged on peer, purging here");
+                    }
+                    _ => {}
+                }
+            }
+            for refused in &merge.refused {
+                warn!(
+                    agent_id = %refused.agent_id,
+                    protocol = %refused.protocol,
+                    peer = %peer.name,
+                    reason = refused.reason,
+                    detail = %refused.detail,
+                    event = "registry_sync_entry_refused",
+                    "Peer registration failed local checks, not imported"
+                );
+            }
+            sync.record_success(&peer.name, remote.generated_at, merge.divergent, imported);
+        }
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{namespace::NamespaceMode, testing::ProtocolFixture};
+
+    fn gateway(name: &str, seed: u8) -> RegistrySync {
+        RegistrySync::new(RegistrySyncConfig {
+            name: name.to_string(),
+            seed: Some([seed; 32]),
+            ..RegistrySyncConfig::default()
+        })
+    }
+
+    fn peer(sync: &RegistrySync) -> Peer {
+        Peer {
+            name: sync.config.name.clone(),
+            url: String::new(),
+            key: sync.key.verifying_key(),
+        }
+    }
+
+    /// Merge `remote` as of its generation with the default local checks
+    fn pull(sync: &RegistrySync, st: &mut InnerState, remote: &RegistrySnapshot) -> Merge {
+        let checks = Checks {
+            namespace: &Namespace::new(NamespaceMode::Agent),
+            policy: &Policy::default(),
+            change_control: &ChangeControl::default(),
+        };
+        sync.merge(st, remote, &checks, remote.generated_at)
+    }
+
+    fn register(st: &mut InnerState, agent_id: &str, descriptor: ProtocolDescriptor, at: u64) {
+        Mutation::ProtocolRegistered {
+            agent_id: agent_id.to_string(),
+            descriptor: Box::new(descriptor),
+            registered_at: at,
+            report_clock: None,
+            recertified_at: None,
+        }
+        .apply(st);
+    }
+
+    #[test]
+    fn test_signed_snapshots_verify_only_for_their_peer() {
+        let (eu, us) = (gateway("eu", 1), gateway("us", 2));
+        let mut st = InnerState::default();
+        register(&mut st, "a", ProtocolFixture::new("coord", "1.0").build(), 100);
+        let signed = eu.sign(&eu.snapshot(&st, 200));
+
+        let snapshot = RegistrySync::verify(&peer(&eu), &signed).unwrap();
+        assert_eq!((snapshot.origin.as_str(), snapshot.entries.len()), ("eu", 1));
+        assert_eq!(snapshot.entries[0].updated_at, 100);
+
+        // Signed by another key, or tampered with
+        assert!(RegistrySync::verify(&peer(&us), &signed).is_err());
+        let tampered = SignedSnapshot {
+            snapshot: signed.snapshot.replace("coord", "coorx"),
+            ..signed.clone()
+        };
+        assert!(RegistrySync::verify(&peer(&eu), &tampered).is_err());
+        // Valid signature, but not the peer it claims to be
+        let impostor = Peer { name: "us".into(), ..peer(&eu) };
+        assert!(RegistrySync::verify(&impostor, &signed).is_err());
+    }
+
+    #[test]
+    fn test_merge_keeps_latest_registration_and_honours_tombstones() {
+        let (eu, us) = (gateway("eu", 1), gateway("us", 2));
+        let (mut eu_st, mut us_st) = (InnerState::default(), InnerState::default());
+
+        register(&mut eu_st, "a", ProtocolFixture::new("coord", "1.0").risk_tier("low").build(), 100);
+        eu.touch("a::coord:1.0", 100);
+        register(&mut us_st, "a", ProtocolFixture::new("coord", "1.0").risk_tier("high").build(), 150);
+        us.touch("a::coord:1.0", 150);
+        register(&mut us_st, "b", ProtocolFixture::new("relay", "2.0").build(), 120);
+        register(&mut eu_st, "c", ProtocolFixture::new("coord", "1.0").build(), 90);
+
+        // us registered a::coord later and has b; eu keeps c, which us lacks
+        let merge = pull(&eu, &mut eu_st, &us.snapshot(&us_st, 200));
+        assert_eq!(merge.mutations.len(), 2);
+        assert_eq!(merge.divergent, 3);
+        assert_eq!(eu_st.protocols["a"]["coord:1.0"].risk_tier, "high");
+        assert!(eu_st.protocols.contains_key("b"));
+
+        // Pulling the older side back changes nothing on us
+        let back = pull(&us, &mut us_st, &eu.snapshot(&eu_st, 210));
+        assert_eq!(back.mutations.len(), 1, "only c is new to us");
+        assert_eq!(us_st.protocols["a"]["coord:1.0"].risk_tier, "high");
+        assert_eq!(pull(&us, &mut us_st, &eu.snapshot(&eu_st, 220)).divergent, 0);
+
+        // A purge on eu is not undone by us's older copy of c
+        eu_st.purge_agent("c");
+        eu.tombstone("c", 300);
+        assert!(pull(&eu, &mut eu_st, &us.snapshot(&us_st, 310)).mutations.is_empty());
+        assert!(!eu_st.protocols.contains_key("c"));
+        // ...and reaches us, which purges its copy
+        let purge = pull(&us, &mut us_st, &eu.snapshot(&eu_st, 320));
+        assert!(matches!(purge.mutations.as_slice(), [Mutation::AgentPurged { agent_id }] if agent_id == "c"));
+        assert!(!us_st.protocols.contains_key("c"));
+        assert_eq!(us.snapshot(&us_st, 330).tombstones.get("c"), Some(&300));
+
+        // Registering again after the purge wins over the tombstone
+        register(&mut us_st, "c", ProtocolFixture::new("coord", "1.1").build(), 400);
+        us.touch("c::coord:1.1", 400);
+        assert_eq!(pull(&eu, &mut eu_st, &us.snapshot(&us_st, 410)).mutations.len(), 1);
+        assert!(eu_st.protocols["c"].contains_key("coord:1.1"));
+    }
+
+    #[test]
+    fn test_merge_applies_local_checks() {
+        let (eu, us) = (gateway("eu", 1), gateway("us", 2));
+        let (mut eu_st, mut us_st) = (InnerState::default(), InnerState::default());
+        register(&mut eu_st, "a", ProtocolFixture::new("coord", "1.0").risk_tier("low").build(), 100);
+        eu.touch("a::coord:1.0", 100);
+        register(&mut us_st, "a", ProtocolFixture::new("coord", "1.0").risk_tier("high").build(), 150);
+        us.touch("a::coord:1.0", 150);
+        register(&mut us_st, "b", ProtocolFixture::new("relay", "1.0").build(), 150);
+        register(&mut eu_st, "c", ProtocolFixture::new("relay", "1.0").risk_tier("high").build(), 90);
+        let checks = Checks {
+            namespace: &Namespace::new(NamespaceMode::Global),
+            policy: &Policy::default(),
+            change_control: &ChangeControl::new(["risk_tier"]),
+        };
+
+        // a's risk tier needs local approval; c owns relay:1.0 here
+        let merge = eu.merge(&mut eu_st, &us.snapshot(&us_st, 200), &checks, 200);
+        assert!(merge.mutations.is_empty());
+        let reasons: Vec<_> = merge.refused.iter().map(|r| (r.agent_id.as_str(), r.reason)).collect();
+        assert_eq!(reasons, [("a", "approval_required"), ("b", "protocol_name_taken")]);
+        assert_eq!(eu_st.protocols["a"]["coord:1.0"].risk_tier, "low");
+        // Refused once per peer registration, still divergent
+        let again = eu.merge(&mut eu_st, &us.snapshot(&us_st, 210), &checks, 210);
+        assert!(again.refused.is_empty());
+        assert_eq!(again.divergent, 3);
+
+        // Without the guard the revision is imported with its diff
+        let checks = Checks {
+            change_control: &ChangeControl::default(),
+            ..checks
+        };
+        let merge = eu.merge(&mut eu_st, &us.snapshot(&us_st, 220), &checks, 220);
+        assert_eq!(merge.mutations.len(), 1);
+        assert_eq!(merge.changes["a::coord:1.0"][0].field, "risk_tier");
+
+        // A registration stamped in the future counts as now
+        register(&mut us_st, "d", ProtocolFixture::new("coord", "2.0").build(), 10_000);
+        us.touch("d::coord:2.0", 10_000);
+        assert_eq!(eu.merge(&mut eu_st, &us.snapshot(&us_st, 230), &checks, 230).mutations.len(), 1);
+        assert_eq!(eu.snapshot(&eu_st, 240).entries.iter().find(|e| e.agent_id == "d").unwrap().updated_at, 230);
+    }
+}
@@ -0,0 +1,870 @@
+//! Warm-standby replication between two gateways
+//!
+//! The primary records every replicated state mutation (protocol
+//! registrations, report clocks, violation counts, protocol standing and
+//! trials, agent deletion, state repairs) in a
+//! sequenced in-memory log. Sequence numbers restart with the process, so the
+//! log also carries a random run id, stamped on every frame. A standby holds a
+//! persistent `GET /replication/stream?since=N&run=R` connection to it. The
+//! primary first replays the log after `N`, or sends a full snapshot when `R`
+//! is not its run, `N` is ahead of its log, or `N` has already rotated out of
+//! the log, then streams new mutations as they happen, with heartbeats while
+//! idle. Frames are NDJSON.
+//!
+//! The standby applies frames in sequence order and reconnects from its last
+//! applied sequence when the connection drops or a frame names another run.
+//! It serves reads but refuses writes with 503 until promoted via
+//! `POST /admin/replication/promote`; it then records mutations of its own,
+//! continuing the primary's run and sequence.
+//!
+//! The stream requires `Authorization: Bearer $REPLICATION_TOKEN` and is
+//! disabled when the token is unset.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::{
+    bearer_token,
+    env_parse,
+    error::GatewayError,
+    fsck::Repair,
+    now_unix_sec, protocol_key,
+    reputation::TrackRecord,
+    risk::RiskStanding,
+    sanctions::Standing,
+    signing,
+    store::{Persistence, Record},
+    tokens_match,
+    trial::Trial,
+    AppState, InnerState, ProtocolDescriptor, ProtocolStats,
+};
+
+const ROLE_HEADER: HeaderName = HeaderName::from_static("x-replication-role");
+
+/// Live mutations buffered per stream before a slow standby is disconnected
+const STREAM_BUFFER: usize = 4_096;
+
+/// Longest wait between reconnect attempts
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+#[derive(Debug, Clone)]
+pub struct ReplicationConfig {
+    /// Shared secret for the stream; replication is disabled when unset
+    pub token: Option<String>,
+    /// Start as a standby following this primary
+    pub primary_url: Option<String>,
+    /// Mutations kept for catch-up before a reconnecting standby needs a snapshot
+    pub log_size: usize,
+    /// Idle interval after which the primary sends a heartbeat
+    pub heartbeat: Duration,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            token: None,
+            primary_url: None,
+            log_size: 100_000,
+            heartbeat: Duration::from_secs(10),
+        }
+    }
+}
+
+impl ReplicationConfig {
+    /// Load `REPLICATION_TOKEN`, `REPLICATION_ROLE`, `REPLICATION_PRIMARY_URL`,
+    /// `REPLICATION_LOG_SIZE`, and `REPLICATION_HEARTBEAT_MS`
+    pub fn from_env() -> Self {
+        let d = Self::default();
+        let token = env::var("REPLICATION_TOKEN").ok().filter(|t| !t.is_empty());
+        let standby = env::var("REPLICATION_ROLE").is_ok_and(|r| r.trim() == "standby");
+        let primary_url = env::var("REPLICATION_PRIMARY_URL")
+            .ok()
+            .map(|u| u.trim().trim_end_matches('/').to_string())
+            .filter(|u| !u.is_empty());
+        if standby && (primary_url.is_none() || token.is_none()) {
+            warn!(
+                event = "config_invalid",
+                "REPLICATION_ROLE=standby needs REPLICATION_PRIMARY_URL and REPLICATION_TOKEN, starting as primary"
+            );
+        }
+        Self {
+            primary_url: primary_url.filter(|_| standby && token.is_some()),
+            token,
+            log_size: env_parse("REPLICATION_LOG_SIZE").unwrap_or(d.log_size).max(1),
+            heartbeat: env_parse("REPLICATION_HEARTBEAT_MS")
+                .map_or(d.heartbeat, Duration::from_millis),
+        }
+    }
+}
+
+// =============================================================================
+// Mutations and Snapshots
+// =============================================================================
+
+/// A replicated change to gateway state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Mutation {
+    ProtocolRegistered {
+        agent_id: String,
+        descriptor: Box<ProtocolDescriptor>,
+        registered_at: u64,
+        /// Report clock after registration, e.g. inherited from a compatible version
+        report_clock: Option<u64>,
+        /// Last recertification, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        recertified_at: Option<u64>,
+    },
+    ReportAccepted {
+        report_key: String,
+        ts: u64,
+    },
+    /// Absolute violation count, so replays are idempotent
+    Violations {
+        agent_id: String,
+        count: u32,
+    },
+    /// Absolute strikes and suspension of an agent protocol
+    ProtocolStanding {
+        report_key: String,
+        standing: Standing,
+    },
+    /// Absolute risk reassessment of an agent protocol
+    ProtocolRisk {
+        report_key: String,
+        risk: RiskStanding,
+    },
+    /// Trial of an agent protocol; `None` once it graduated
+    ProtocolTrial {
+        report_key: String,
+        trial: Option<Trial>,
+    },
+    /// Absolute track record of an agent, as of its last report or review
+    TrackRecord {
+        agent_id: String,
+        record: TrackRecord,
+    },
+    AgentDeleted {
+        agent_id: String,
+        ts: u64,
+    },
+    AgentRestored {
+        agent_id: String,
+    },
+    AgentPurged {
+        agent_id: String,
+    },
+    /// Repairs applied by `POST /admin/fsck`
+    StateRepaired {
+        repairs: Vec<Repair>,
+    },
+}
+
+impl Mutation {
+    pub fn apply(self, st: &mut InnerState) {
+        match self {
+            Self::ProtocolRegistered {
+                agent_id,
+                descriptor,
+                registered_at,
+                report_clock,
+                recertified_at,
+            } => {
+                let key = protocol_key(&descriptor.name, &descriptor.version);
+                let report_key = format!("{agent_id}::{key}");
+                if let Some(ts) = report_clock {
+                    st.last_report_ts.insert(report_key.clone(), ts);
+                }
+                let stats = st.protocol_stats.entry(report_key).or_insert_with(|| ProtocolStats {
+                    registered_at,
+                    ..ProtocolStats::default()
+                });
+                if recertified_at.is_some() {
+                    stats.recertified_at = recertified_at;
+                }
+                st.protocols.entry(agent_id).or_default().insert(key, *descriptor);
+            }
+            Self::ReportAccepted { report_key, ts } => {
+                st.last_report_ts.insert(report_key, ts);
+            }
+            Self::Violations { agent_id, count } => {
+                st.violations.insert(agent_id, count);
+            }
+            Self::ProtocolStanding { report_key, standing } => {
+                st.protocol_stats.entry(report_key).or_default().standing = standing;
+            }
+            Self::ProtocolRisk { report_key, risk } => {
+                st.protocol_stats.entry(report_key).or_default().risk = risk;
+            }
+            Self::ProtocolTrial { report_key, trial } => {
+                st.protocol_stats.entry(report_key).or_default().trial = trial;
+            }
+            Self::TrackRecord { agent_id, record } => {
+                st.track_records.insert(agent_id, record);
+            }
+            Self::AgentDeleted { agent_id, ts } => {
+                st.deleted_agents.insert(agent_id, ts);
+            }
+            Self::AgentRestored { agent_id } => {
+                st.deleted_agents.remove(&agent_id);
+            }
+            Self::AgentPurged { agent_id } => st.purge_agent(&agent_id),
+            Self::StateRepaired { repairs } => {
+                for repair in &repairs {
+                    repair.apply(st);
+                }
+            }
+        }
+    }
+}
+
+/// Full replicated state as of `seq` in run `run`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub run: String,
+    pub seq: u64,
+    protocols: HashMap<String, HashMap<String, ProtocolDescriptor>>,
+    last_report_ts: HashMap<String, u64>,
+    violations: HashMap<String, u32>,
+    deleted_agents: HashMap<String, u64>,
+    /// "agent_id::protocol_key" -> registration time
+    registered_at: HashMap<String, u64>,
+    /// "agent_id::protocol_key" -> strikes and suspension, when not clean
+    #[serde(default)]
+    standing: HashMap<String, Standing>,
+    #[serde(default)]
+    track_records: HashMap<String, TrackRecord>,
+    /// "agent_id::protocol_key" -> last recertification, when recertified
+    #[serde(default)]
+    recertified_at: HashMap<String, u64>,
+}
+
+impl Snapshot {
+    pub fn capture(st: &InnerState, run: String, seq: u64) -> Self {
+        Self {
+            run,
+            seq,
+            protocols: st.protocols.clone(),
+            last_report_ts: st.last_report_ts.clone(),
+            violations: st.violations.clone(),
+            deleted_agents: st.deleted_agents.clone(),
+            registered_at: st
+                .protocol_stats
+                .iter()
+                .map(|(k, s)| (k.clone(), s.registered_at))
+                .collect(),
+            standing: st
+                .protocol_stats
+                .iter()
+                .filter(|(_, s)| s.standing != Standing::default())
+                .map(|(k, s)| (k.clone(), s.standing.clone()))
+                .collect(),
+            track_records: st.track_records.clone(),
+            recertified_at: st
+                .protocol_stats
+                .iter()
+                .filter_map(|(k, s)| Some((k.clone(), s.recertified_at?)))
+                .collect(),
+        }
+    }
+
+    /// Replace the replicated parts of `st`
+    pub fn restore(self, st: &mut InnerState) {
+        st.protocols = self.protocols;
+        st.last_report_ts = self.last_report_ts;
+        st.violations = self.violations;
+        st.track_records = self.track_records;
+        st.deleted_agents = self.deleted_agents;
+        st.protocol_stats.retain(|k, _| self.registered_at.contains_key(k));
+        for (key, registered_at) in self.registered_at {
+            st.protocol_stats.entry(key).or_default().registered_at = registered_at;
+        }
+        for stats in st.protocol_stats.values_mut() {
+            stats.standing = Standing::default();
+        }
+        for (key, standing) in self.standing {
+            if let Some(stats) = st.protocol_stats.get_mut(&key) {
+                stats.standing = standing;
+            }
+        }
+        for (key, stats) in st.protocol_stats.iter_mut() {
+            stats.recertified_at = self.recertified_at.get(key).copied();
+        }
+    }
+}
+
+/// One NDJSON line of the replication stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Frame {
+    Mutation { run: String, seq: u64, mutation: Mutation },
+    Snapshot(Box<Snapshot>),
+    Heartbeat { run: String, seq: u64 },
+}
+
+impl Frame {
+    fn line(&self) -> Bytes {
+        let mut line = serde_json::to_vec(self).unwrap_or_default();
+        line.push(b'\n');
+        Bytes::from(line)
+    }
+}
+
+// =============================================================================
+// Log
+// =============================================================================
+
+#[derive(Debug)]
+struct LogInner {
+    /// Run the sequence numbers belong to
+    run: String,
+    entries: VecDeque<(u64, Mutation)>,
+    last_seq: u64,
+}
+
+/// Sequenced log of recent mutations, with live fan-out to streams
+#[derive(Debug)]
+pub struct ReplicationLog {
+    inner: Mutex<LogInner>,
+    capacity: usize,
+    tx: broadcast::Sender<(u64, Mutation)>,
+}
+
+impl ReplicationLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(LogInner {
+                run: signing::random_hex::<8>(),
+                entries: VecDeque::new(),
+                last_seq: 0,
+            }),
+            capacity,
+            tx: broadcast::channel(STREAM_BUFFER).0,
+        }
+    }
+
+    /// Append a mutation; returns its sequence number
+    ///
+    /// Call while holding the state write lock so log order matches the
+    /// order in which mutations were applied.
+    pub fn record(&self, mutation: Mutation) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_seq += 1;
+        let seq = inner.last_seq;
+        if inner.entries.len() == self.capacity {
+            inner.entries.pop_front();
+        }
+        inner.entries.push_back((seq, mutation.clone()));
+        let _ = self.tx.send((seq, mutation));
+        seq
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.inner.lock().unwrap().last_seq
+    }
+
+    pub fn run(&self) -> String {
+        self.inner.lock().unwrap().run.clone()
+    }
+
+    /// Mutations after `seq` in `run`, or `None` when `seq` belongs to
+    /// another run, is ahead of the log, or some have rotated out of the log
+    pub fn since(&self, run: &str, seq: u64) -> Option<Vec<(u64, Mutation)>> {
+        let inner = self.inner.lock().unwrap();
+        if run != inner.run || seq > inner.last_seq {
+            return None;
+        }
+        if seq == inner.last_seq {
+            return Some(Vec::new());
+        }
+        let first = inner.entries.front().map_or(inner.last_seq + 1, |(s, _)| *s);
+        (first <= seq + 1).then(|| inner.entries.iter().filter(|(s, _)| *s > seq).cloned().collect())
+    }
+
+    /// Drop all entries and continue numbering after `seq` in `run`
+    fn reset(&self, run: String, seq: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.run = run;
+        inner.entries.clear();
+        inner.last_seq = seq;
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<(u64, Mutation)> {
+        self.tx.subscribe()
+    }
+}
+
+// =============================================================================
+// Replication State
+// =============================================================================
+
+/// Role, log, and follower status of this gateway
+#[derive(Debug)]
+pub struct Replication {
+    config: ReplicationConfig,
+    pub log: ReplicationLog,
+    standby: AtomicBool,
+    connected: AtomicBool,
+    last_frame_at: AtomicU64,
+    /// State store mutations are also written to
+    store: OnceLock<Arc<Persistence>>,
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        Self::new(ReplicationConfig::default())
+    }
+}
+
+/// Replication status as returned by `GET /admin/replication`
+#[derive(Debug, Serialize)]
+pub struct ReplicationStatus {
+    pub role: &'static str,
+    pub enabled: bool,
+    /// Run the sequence number belongs to
+    pub run: String,
+    /// Last recorded (primary) or applied (standby) sequence number
+    pub seq: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary_url: Option<String>,
+    /// Whether the standby is connected to its primary
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connected: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_frame_at: Option<u64>,
+}
+
+impl Replication {
+    pub fn new(config: ReplicationConfig) -> Self {
+        Self {
+            log: ReplicationLog::new(config.log_size),
+            standby: AtomicBool::new(config.primary_url.is_some()),
+            connected: AtomicBool::new(false),
+            last_frame_at: AtomicU64::new(0),
+            store: OnceLock::new(),
+            config,
+        }
+    }
+
+    /// Write subsequent mutations to the state store too
+    pub fn set_store(&self, store: Arc<Persistence>) {
+        let _ = self.store.set(store);
+    }
+
+    /// Record a mutation for standbys and the state store; call while holding
+    /// the state write lock
+    pub fn record(&self, mutation: Mutation) -> u64 {
+        if let Some(store) = self.store.get() {
+            store.mutation(&mutation);
+        }
+        self.log.record(mutation)
+    }
+
+    /// Record a mutation for standbys, returning the state store writes that
+    /// persist it for the caller to commit along with its own; call while
+    /// holding the state write lock
+    pub fn record_unstored(&self, mutation: Mutation) -> Vec<Record> {
+        let records = match self.store.get() {
+            Some(store) if store.enabled() => Record::from_mutation(&mutation),
+            _ => Vec::new(),
+        };
+        self.log.record(mutation);
+        records
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.standby.load(Ordering::Relaxed)
+    }
+
+    /// Stop following the primary and accept writes; returns false if already primary
+    pub fn promote(&self) -> bool {
+        self.standby.swap(false, Ordering::Relaxed)
+    }
+
+    pub fn status(&self) -> ReplicationStatus {
+        let standby = self.is_standby();
+        let last_frame_at = self.last_frame_at.load(Ordering::Relaxed);
+        ReplicationStatus {
+            role: if standby { "standby" } else { "primary" },
+            enabled: self.config.token.is_some(),
+            run: self.log.run(),
+            seq: self.log.last_seq(),
+            primary_url: self.config.primary_url.clone().filter(|_| standby),
+            connected: standby.then(|| self.connected.load(Ordering::Relaxed)),
+            last_frame_at: (last_frame_at > 0).then_some(last_frame_at),
+        }
+    }
+}
+
+// =============================================================================
+// Primary: Stream Endpoint
+// =============================================================================
+
+#[derive(Debug, Default, Deserialize)]
+pub struct StreamQuery {
+    /// Last sequence number the standby has applied
+    #[serde(default)]
+    since: u64,
+    /// Run `since` belongs to
+    #[serde(default)]
+    run: String,
+}
+
+/// Stream mutations after `since`, then live mutations and heartbeats
+pub(crate) async fn stream(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<StreamQuery>,
+) -> Response {
+    let repl = state.replication.clone();
+    let Some(expected) = repl.config.token.as_deref() else {
+        return GatewayError::ReplicationDisabled.into_response();
+    };
+    if !bearer_token(&headers).is_some_and(|t| tokens_match(t, expected)) {
+        return GatewayError::InvalidReplicationToken.into_response();
+    }
+
+    // Subscribe before reading the backlog so nothing falls between the two
+    let rx = repl.log.subscribe();
+    let (backlog, run, last) = {
+        let st = state.inner.read().unwrap();
+        let run = repl.log.run();
+        let last = repl.log.last_seq();
+        let backlog = match repl.log.since(&query.run, query.since) {
+            Some(entries) => entries
+                .into_iter()
+                .map(|(seq, mutation)| {
+                    Frame::Mutation {
+                        run: run.clone(),
+                        seq,
+                        mutation,
+                    }
+                    .line()
+                })
+                .collect::<Vec<_>>(),
+            None => {
+                info!(
+                    event = "replication_snapshot_sent",
+                    since = query.since,
+                    since_run = %query.run,
+                    run = %run,
+                    seq = last,
+                    "Standby behind, ahead, or on another run, sending snapshot"
+                );
+                vec![Frame::Snapshot(Box::new(Snapshot::capture(&st, run.clone(), last))).line()]
+            }
+        };
+        (backlog, run, last)
+    };
+    info!(event = "replication_stream_opened", since = query.since, run = %run, seq = last, "Standby connected");
+
+    let heartbeat = repl.config.heartbeat;
+    let live = futures::stream::unfold((rx, last), move |(mut rx, last)| {
+        let run = run.clone();
+        async move {
+            loop {
+                tokio::select! {
+                    received = rx.recv() => match received {
+                        Ok((seq, _)) if seq <= last => continue,
+                        Ok((seq, mutation)) => {
+                            return Some((Frame::Mutation { run, seq, mutation }.line(), (rx, seq)));
+                        }
+                        // Too slow to keep up: end the stream so the standby catches up on reconnect
+                        Err(_) => return None,
+                    },
+                    _ = tokio::time::sleep(heartbeat) => {
+                        return Some((Frame::Heartbeat { run, seq: last }.line(), (rx, last)));
+                    }
+                }
+            }
+        }
+    });
+    let frames = futures::stream::iter(backlog).chain(live).map(Ok::<_, std::io::Error>);
+
+    (
+        [(axum::http::header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(frames),
+    )
+        .into_response()
+}
+
+/// Middleware refusing writes while this gateway is a standby
+pub(crate) async fn refuse_writes_on_standby(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let read = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let exempt = req.uri().path().starts_with("/admin/replication") || req.uri().path() == "/receipts/verify";
+    if !state.replication.is_standby() || read || exempt {
+        return next.run(req).await;
+    }
+    let mut response = GatewayError::Standby.into_response();
+    response
+        .headers_mut()
+        .insert(ROLE_HEADER, HeaderValue::from_static("standby"));
+    response
+}
+
+// =============================================================================
+// Standby: Follower
+// =============================================================================
+
+/// Apply one frame; returns false when the stream is out of sequence or
+/// belongs to another run
+fn apply_frame(state: &AppState, frame: Frame) -> bool {
+    let repl = &state.replication;
+    match frame {
+        Frame::Snapshot(snapshot) => {
+            let (run, seq) = (snapshot.run.clone(), snapshot.seq);
+            let mut st = state.inner.write().unwrap();
+            snapshot.restore(&mut st);
+            info!(event = "replication_snapshot_applied", run = %run, seq, "Snapshot applied");
+            repl.log.reset(run, seq);
+        }
+        Frame::Mutation { run, .. } | Frame::Heartbeat { run, .. } if run != repl.log.run() => {
+            warn!(event = "replication_run_changed", run = %run, "Primary restarted, resynchronizing");
+            return false;
+        }
+        Frame::Mutation { seq, mutation, .. } => {
+            let mut st = state.inner.write().unwrap();
+            let last = repl.log.last_seq();
+            if seq <= last {
+                return true;
+            }
+            if seq != last + 1 {
+                warn!(
+                    event = "replication_gap",
+                    expected = last + 1,
+                    received = seq,
+                    "Replication stream out of sequence"
+                );
+                return false;
+            }
+            mutation.clone().apply(&mut st);
+            repl.log.record(mutation);
+        }
+        Frame::Heartbeat { .. } => {}
+    }
+    repl.last_frame_at.store(now_unix_sec(), Ordering::Relaxed);
+    true
+}
+
+/// Hold one stream connection open until it ends, fails, or we are promoted
+async fn follow_once(state: &AppState, client: &reqwest::Client, primary: &str, token: &str) -> Result<(), String> {
+    let repl = &state.replication;
+    let since = repl.log.last_seq();
+    let mut resp = client
+        .get(format!("{primary}/replication/stream"))
+        .query(&[("since", since.to_string()), ("run", repl.log.run())])
+        .bearer_auth(token)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| e.to_string())?;
+    repl.connected.store(true, Ordering::Relaxed);
+    info!(event = "replication_connected", primary = %primary, since, "Following primary");
+
+    let idle_limit = repl.config.heartbeat * 3;
+    let mut buf: Vec<u8> = Vec::new();
+    while repl.is_standby() {
+        let chunk = match tokio::time::timeout(idle_limit, resp.chunk()).await {
+            Err(_) => return Err("no frames from primary".to_string()),
+            Ok(Err(e)) => return Err(e.to_string()),
+            Ok(Ok(None)) => return Err("stream closed by primary".to_string()),
+            Ok(Ok(Some(chunk))) => chunk,
+        };
+        buf.extend_from_slice(&chunk);
+        while let Some(end) = buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=end).collect();
+            if !repl.is_standby() {
+                return Ok(());
+            }
+            let frame: Frame = serde_json::from_slice(&line).map_err(|e| format!("bad frame: {e}"))?;
+            if !apply_frame(state, frame) {
+                return Err("stream out of sequence or from another run".to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Follow the primary until promoted, reconnecting with backoff
+pub async fn follow(state: AppState) {
+    let repl = state.replication.clone();
+    let (Some(primary), Some(token)) = (repl.config.primary_url.clone(), repl.config.token.clone()) else {
+        return;
+    };
+    let client = match reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(5))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            warn!(event = "replication_disabled", error = %e, "Cannot build replication client");
+            return;
+        }
+    };
+
+    let mut backoff = Duration::from_secs(1);
+    while repl.is_standby() {
+        let result = follow_once(&state, &client, &primary, &token).await;
+        let was_connected = repl.connected.swap(false, Ordering::Relaxed);
+        if !repl.is_standby() {
+            break;
+        }
+        if let Err(e) = result {
+            warn!(event = "replication_disconnected", primary = %primary, error = %e, "Lost primary, reconnecting");
+        }
+        if was_connected {
+            backoff = Duration::from_secs(1);
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+    }
+    info!(
+        event = "replication_follower_stopped",
+        seq = repl.log.last_seq(),
+        "Stopped following primary"
+    );
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor() -> ProtocolDescriptor {
+        serde_json::from_value(serde_json::json!({
+            "name": "coord", "version": "1.0", "purpose": "p", "scope": "s",
+            "risk_tier": "low", "translation_method": "dictionary"
+        }))
+        .unwrap()
+    }
+
+    fn registered() -> Mutation {
+        Mutation::ProtocolRegistered {
+            agent_id: "a".into(),
+            descriptor: Box::new(descriptor()),
+            registered_at: 10,
+            report_clock: Some(7),
+            recertified_at: None,
+        }
+    }
+
+    /// A log holding seqs 2 and 3, seq 1 (the registration) rotated out
+    fn rotated_log() -> ReplicationLog {
+        let log = ReplicationLog::new(2);
+        assert_eq!(log.record(registered()), 1);
+        log.record(Mutation::ReportAccepted {
+            report_key: "a::coord:1.0".into(),
+            ts: 20,
+        });
+        log.record(Mutation::Violations {
+            agent_id: "a".into(),
+            count: 2,
+        });
+        log
+    }
+
+    fn suspended() -> Standing {
+        Standing {
+            strikes: 3,
+            suspended_at: Some(25),
+        }
+    }
+
+    /// Primary state after applying the registration and the rotated log
+    fn primary(log: &ReplicationLog) -> InnerState {
+        let mut primary = InnerState::default();
+        registered().apply(&mut primary);
+        for (_, m) in log.since(&log.run(), 1).unwrap() {
+            m.apply(&mut primary);
+        }
+        Mutation::ProtocolStanding {
+            report_key: "a::coord:1.0".into(),
+            standing: suspended(),
+        }
+        .apply(&mut primary);
+        primary
+    }
+
+    #[test]
+    fn test_catch_up_until_rotated_out() {
+        let log = rotated_log();
+        let run = log.run();
+        assert!(log.since(&run, 0).is_none());
+        assert_eq!(log.since(&run, 1).unwrap().len(), 2);
+        assert!(log.since(&run, 3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_wrong_history_needs_a_snapshot() {
+        let log = rotated_log();
+        let run = log.run();
+        assert!(log.since(&run, 4).is_none(), "standby ahead of the log");
+        assert!(log.since("elsewhere", 2).is_none(), "another run");
+        assert!(ReplicationLog::new(2).since(&run, 3).is_none(), "restarted primary");
+    }
+
+    #[test]
+    fn test_mutations_apply_to_state() {
+        let primary = primary(&rotated_log());
+        assert_eq!(primary.last_report_ts["a::coord:1.0"], 20);
+        assert_eq!(primary.violations["a"], 2);
+        assert_eq!(primary.protocol_stats["a::coord:1.0"].standing, suspended());
+    }
+
+    #[test]
+    fn test_snapshot_replaces_standby_state() {
+        let log = rotated_log();
+        let run = log.run();
+        let mut standby = InnerState::default();
+        standby.violations.insert("stale".into(), 9);
+        let wire = serde_json::to_string(&Frame::Snapshot(Box::new(Snapshot::capture(&primary(&log), run.clone(), 3))))
+            .unwrap();
+        let Frame::Snapshot(snapshot) = serde_json::from_str(&wire).unwrap() else {
+            panic!("expected snapshot frame");
+        };
+        assert_eq!(snapshot.run, run);
+        snapshot.restore(&mut standby);
+        assert!(standby.protocols["a"].contains_key("coord:1.0"));
+        assert_eq!(standby.protocol_stats["a::coord:1.0"].registered_at, 10);
+        assert_eq!(standby.violations.get("stale"), None);
+        assert_eq!(standby.protocol_stats["a::coord:1.0"].standing, suspended());
+    }
+
+    #[test]
+    fn test_delete_then_purge() {
+        let mut state = primary(&rotated_log());
+        Mutation::AgentDeleted {
+            agent_id: "a".into(),
+            ts: 30,
+        }
+        .apply(&mut state);
+        assert!(state.is_deleted("a"));
+        Mutation::AgentPurged { agent_id: "a".into() }.apply(&mut state);
+        assert!(state.protocols.is_empty());
+    }
+}
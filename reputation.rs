@@ -0,0 +1,267 @@
+//! Sender reputation and probation
+//!
+//! Every agent accumulates a [`TrackRecord`]: reports filed on time and late,
+//! messages accepted, and held reports approved or rejected on review. Its
+//! reputation is the mean of three rates in `[0, 1]`:
+//!
+//! - **punctuality**: share of reports filed before the previous one expired
+//! - **conduct**: share of accepted messages among accepted messages plus
+//!   compliance violations
+//! - **reviews**: share of held reports approved; 1 while none were reviewed
+//!
+//! When the policy has a `probation` section, agents with fewer than
+//! `min_reports` reports on record, or a reputation below `min_reputation`,
+//! are on probation: their report interval is `probation.report_interval_sec`
+//! instead of the policy's, and with `require_codebook` their protocols must
+//! carry a codebook glossing their tokens, both to register and to send.
+//! Everyone else gets the standard policy. Without a `probation` section
+//! reputation is tracked and reported but changes nothing.
+//!
+//! For established agents the reputation also carries a `violation_ceiling`:
+//! how many violations, the rest of the record unchanged, would put the agent
+//! back on probation.
+
+use serde::{Deserialize, Serialize};
+
+/// Stricter terms for new and low-reputation agents
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Probation {
+    /// Reports an agent needs on record before its reputation counts
+    pub min_reports: u32,
+    /// Reputation below which an established agent is back on probation
+    pub min_reputation: f64,
+    /// Report interval while on probation
+    pub report_interval_sec: u64,
+    /// Whether protocols used on probation must carry a codebook
+    #[serde(default = "default_require_codebook")]
+    pub require_codebook: bool,
+}
+
+fn default_require_codebook() -> bool {
+    true
+}
+
+impl Probation {
+    /// Read `PROBATION_REPORT_INTERVAL_SEC`, which enables probation, and
+    /// `PROBATION_MIN_REPORTS`, `PROBATION_MIN_REPUTATION`, and
+    /// `PROBATION_REQUIRE_CODEBOOK`
+    pub fn from_env() -> Option<Self> {
+        use crate::env_parse as var;
+        Some(Self {
+            report_interval_sec: var::<u64>("PROBATION_REPORT_INTERVAL_SEC").filter(|&s| s > 0)?,
+            min_reports: var("PROBATION_MIN_REPORTS").unwrap_or(5),
+            min_reputation: var("PROBATION_MIN_REPUTATION").unwrap_or(0.8),
+            require_codebook: var("PROBATION_REQUIRE_CODEBOOK").unwrap_or(true),
+        })
+    }
+
+    pub fn validate(&self, report_interval_sec: u64) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.min_reputation) {
+            return Err("probation.min_reputation must be within [0, 1]".to_string());
+        }
+        if self.report_interval_sec == 0 || self.report_interval_sec > report_interval_sec {
+            return Err("probation.report_interval_sec must be positive and at most report_interval_sec".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// History an agent's reputation is computed from
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackRecord {
+    pub reports_on_time: u32,
+    /// Reports filed after the previous one had expired
+    pub reports_late: u32,
+    pub messages_accepted: u64,
+    pub reviews_approved: u32,
+    pub reviews_rejected: u32,
+}
+
+/// Where an agent stands under the policy in force
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tier {
+    /// Too few reports on record to judge
+    New,
+    /// Reputation below the probation threshold
+    Low,
+    Standard,
+}
+
+impl Tier {
+    pub fn on_probation(self) -> bool {
+        self != Self::Standard
+    }
+}
+
+/// An agent's reputation and what it means under the current policy
+#[derive(Debug, Clone, Serialize)]
+pub struct Reputation {
+    pub score: f64,
+    pub punctuality: f64,
+    pub conduct: f64,
+    pub reviews: f64,
+    pub tier: Tier,
+    /// Report interval that applies to the agent
+    pub report_interval_sec: u64,
+    pub codebook_required: bool,
+    pub record: TrackRecord,
+    pub violations: u32,
+    /// Violations at which the agent's reputation falls below the probation
+    /// threshold; none without probation, while new, or if no count would
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub violation_ceiling: Option<u32>,
+}
+
+fn rate(good: u64, bad: u64) -> f64 {
+    if good + bad == 0 {
+        1.0
+    } else {
+        good as f64 / (good + bad) as f64
+    }
+}
+
+impl TrackRecord {
+    pub fn reports(&self) -> u32 {
+        self.reports_on_time.saturating_add(self.reports_late)
+    }
+
+    fn score(&self, violations: u32) -> f64 {
+        let punctuality = rate(self.reports_on_time.into(), self.reports_late.into());
+        let conduct = rate(self.messages_accepted, violations.into());
+        let reviews = rate(self.reviews_approved.into(), self.reviews_rejected.into());
+        (punctuality + conduct + reviews) / 3.0
+    }
+
+    /// Fewest violations that put an established agent's reputation below
+    /// `min_reputation`, with the rest of its record as it stands
+    pub fn violation_ceiling(&self, min_reputation: f64) -> Option<u32> {
+        let punctuality = rate(self.reports_on_time.into(), self.reports_late.into());
+        let reviews = rate(self.reviews_approved.into(), self.reviews_rejected.into());
+        // Conduct the agent must stay at or above
+        let floor = 3.0 * min_reputation - punctuality - reviews;
+        if floor <= 0.0 {
+            return None;
+        }
+        if floor > 1.0 {
+            return Some(0);
+        }
+        // accepted / (accepted + v) < floor  <=>  v > accepted * (1 - floor) / floor
+        let accepted = self.messages_accepted as f64;
+        let mut ceiling = ((accepted * (1.0 - floor) / floor).floor() + 1.0).min(u32::MAX as f64) as u32;
+        // Settle float rounding against the score itself
+        while ceiling > 0 && self.score(ceiling - 1) < min_reputation {
+            ceiling -= 1;
+        }
+        while ceiling < u32::MAX && self.score(ceiling) >= min_reputation {
+            ceiling += 1;
+        }
+        Some(ceiling)
+    }
+
+    /// Reputation given the agent's `violations`; `interval` is the standard report interval
+    pub fn reputation(&self, violations: u32, probation: Option<&Probation>, interval: u64) -> Reputation {
+        let punctuality = rate(self.reports_on_time.into(), self.reports_late.into());
+        let conduct = rate(self.messages_accepted, violations.into());
+        let reviews = rate(self.reviews_approved.into(), self.reviews_rejected.into());
+        let score = (punctuality + conduct + reviews) / 3.0;
+        let tier = match probation {
+            Some(p) if self.reports() < p.min_reports => Tier::New,
+            Some(p) if score < p.min_reputation => Tier::Low,
+            _ => Tier::Standard,
+        };
+        let terms = probation.filter(|_| tier.on_probation());
+        Reputation {
+            score,
+            punctuality,
+            conduct,
+            reviews,
+            tier,
+            report_interval_sec: terms.map_or(interval, |p| p.report_interval_sec),
+            codebook_required: terms.is_some_and(|p| p.require_codebook),
+            record: self.clone(),
+            violations,
+            violation_ceiling: probation
+                .filter(|p| self.reports() >= p.min_reports)
+                .and_then(|p| self.violation_ceiling(p.min_reputation)),
+        }
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probation() -> Probation {
+        Probation {
+            min_reports: 3,
+            min_reputation: 0.8,
+            report_interval_sec: 600,
+            require_codebook: true,
+        }
+    }
+
+    #[test]
+    fn test_tiers_follow_history() {
+        let p = probation();
+        let mut record = TrackRecord::default();
+        let fresh = record.reputation(0, Some(&p), 3600);
+        assert_eq!((fresh.tier, fresh.score), (Tier::New, 1.0));
+        assert_eq!(fresh.report_interval_sec, 600);
+        assert!(fresh.codebook_required);
+
+        record.reports_on_time = 5;
+        record.messages_accepted = 100;
+        let veteran = record.reputation(1, Some(&p), 3600);
+        assert_eq!(veteran.tier, Tier::Standard);
+        assert_eq!(veteran.report_interval_sec, 3600);
+        assert!(!veteran.codebook_required);
+
+        record.reports_late = 5;
+        record.reviews_rejected = 2;
+        let slipping = record.reputation(1, Some(&p), 3600);
+        assert_eq!(slipping.tier, Tier::Low);
+        assert!((slipping.punctuality - 0.5).abs() < 1e-9);
+        assert_eq!(slipping.reviews, 0.0);
+
+        // Without probation reputation is informational
+        let unpoliced = record.reputation(1, None, 3600);
+        assert_eq!((unpoliced.tier, unpoliced.report_interval_sec), (Tier::Standard, 3600));
+    }
+
+    #[test]
+    fn test_violation_ceiling() {
+        let record = TrackRecord {
+            reports_on_time: 5,
+            messages_accepted: 100,
+            ..TrackRecord::default()
+        };
+        let strict = Probation { min_reputation: 0.9, ..probation() };
+        assert_eq!(record.violation_ceiling(0.9), Some(43));
+        assert_eq!(record.reputation(42, Some(&strict), 3600).tier, Tier::Standard);
+        assert_eq!(record.reputation(43, Some(&strict), 3600).tier, Tier::Low);
+        assert_eq!(record.reputation(0, Some(&strict), 3600).violation_ceiling, Some(43));
+
+        // Perfect punctuality and reviews carry the score on their own
+        assert_eq!(record.violation_ceiling(0.5), None);
+        // Already below whatever the conduct
+        let late = TrackRecord { reports_late: 5, ..record.clone() };
+        assert_eq!(late.violation_ceiling(0.9), Some(0));
+
+        // Not computed for new agents
+        let fresh = TrackRecord::default().reputation(0, Some(&strict), 3600);
+        assert_eq!(fresh.violation_ceiling, None);
+    }
+
+    #[test]
+    fn test_probation_validation() {
+        assert!(probation().validate(3600).is_ok());
+        assert!(probation().validate(300).is_err());
+        let lax = Probation { min_reputation: 1.5, ..probation() };
+        assert!(lax.validate(3600).is_err());
+    }
+}
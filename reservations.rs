@@ -0,0 +1,306 @@
+//! Two-phase sends: reserve, then commit or abort
+//!
+//! A relay that forwards messages after the gateway allows them can crash
+//! between the decision and the actual delivery, leaving an approved message
+//! nobody knows the fate of. `POST /send/reserve` runs the normal send
+//! pipeline and, when any recipient is allowed, answers with a reservation
+//! token. The relay delivers, then calls `POST /send/commit`, or
+//! `POST /send/abort` when delivery failed, with that token. Reservations not
+//! resolved within `RESERVATION_TTL_SEC` expire and are logged as
+//! `reservation_expired`, so every approved message ends in exactly one of
+//! `send_committed`, `send_aborted`, or `reservation_expired`.
+//!
+//! Resolving is idempotent: repeating the same call after a lost response
+//! answers the same outcome without logging it twice, while a commit after an
+//! abort or expiry (or the reverse) is refused with 409. Outcomes stay
+//! queryable for `RESERVATION_TTL_SEC` after resolution.
+//!
+//! Tokens are `<id>.<secret>`: the id appears in audit events, and the secret
+//! only in the reserve response, so reading the audit trail does not let
+//! anyone resolve a reservation. Like parked sends, reservations live only in
+//! memory and are not replicated.
+
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{env_parse, error::GatewayError, signing, tokens_match};
+
+#[derive(Debug, Clone)]
+pub struct ReservationConfig {
+    /// How long a reservation waits for commit or abort, and how long its
+    /// outcome is kept afterwards
+    pub ttl_sec: u64,
+    /// Reservations open at once per agent
+    pub max_per_agent: usize,
+}
+
+impl Default for ReservationConfig {
+    fn default() -> Self {
+        Self {
+            ttl_sec: 60,
+            max_per_agent: 1_000,
+        }
+    }
+}
+
+impl ReservationConfig {
+    /// Load settings from `RESERVATION_*` environment variables
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let parse = |name: &str| env_parse::<u64>(name);
+        Self {
+            ttl_sec: parse("RESERVATION_TTL_SEC").unwrap_or(defaults.ttl_sec).max(1),
+            max_per_agent: parse("RESERVATION_MAX_PER_AGENT").map_or(defaults.max_per_agent, |n| n as usize),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReservationState {
+    Pending,
+    /// The relay confirmed delivery
+    Committed,
+    /// The relay gave up on delivery
+    Aborted,
+    /// Neither arrived in time
+    Expired,
+}
+
+impl ReservationState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Committed => "committed",
+            Self::Aborted => "aborted",
+            Self::Expired => "expired",
+        }
+    }
+}
+
+/// Returned with an allowed reserved send
+#[derive(Debug, Clone, Serialize)]
+pub struct ReservationTicket {
+    pub id: u64,
+    /// Bearer token for commit and abort
+    pub token: String,
+    pub expires_at: u64,
+}
+
+/// A reservation as answered by commit and abort
+#[derive(Debug, Clone, Serialize)]
+pub struct ReservationStatus {
+    pub id: u64,
+    pub agent_id: String,
+    /// Recipients the send was allowed to
+    pub recipients: Vec<String>,
+    pub state: ReservationState,
+    pub reserved_at: u64,
+    pub expires_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_at: Option<u64>,
+    /// Reason given with an abort
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+struct Entry {
+    secret: String,
+    status: ReservationStatus,
+}
+
+#[derive(Default)]
+struct Book {
+    next_id: u64,
+    entries: BTreeMap<u64, Entry>,
+}
+
+/// Open and recently resolved reservations
+#[derive(Default)]
+pub struct Reservations {
+    config: ReservationConfig,
+    book: Mutex<Book>,
+    pub committed: AtomicU64,
+    pub aborted: AtomicU64,
+    pub expired: AtomicU64,
+}
+
+impl Reservations {
+    pub fn new(config: ReservationConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn config(&self) -> &ReservationConfig {
+        &self.config
+    }
+
+    /// Refuse a reservation for `agent_id` once it has `RESERVATION_MAX_PER_AGENT` open
+    pub fn check_capacity(&self, agent_id: &str) -> Result<(), GatewayError> {
+        let book = self.book.lock().unwrap();
+        let open = book
+            .entries
+            .values()
+            .filter(|e| e.status.state == ReservationState::Pending && e.status.agent_id == agent_id)
+            .count();
+        if open >= self.config.max_per_agent {
+            return Err(GatewayError::Conflict("Too many open reservations: commit or abort some first"));
+        }
+        Ok(())
+    }
+
+    /// Open a reservation for a send allowed to `recipients`
+    pub fn reserve(&self, agent_id: &str, recipients: Vec<String>, now: u64) -> ReservationTicket {
+        let secret = signing::random_hex::<16>();
+
+        let mut book = self.book.lock().unwrap();
+        book.next_id += 1;
+        let id = book.next_id;
+        let expires_at = now + self.config.ttl_sec;
+        let status = ReservationStatus {
+            id,
+            agent_id: agent_id.to_string(),
+            recipients,
+            state: ReservationState::Pending,
+            reserved_at: now,
+            expires_at,
+            resolved_at: None,
+            reason: None,
+        };
+        let token = format!("{id}.{secret}");
+        book.entries.insert(id, Entry { secret, status });
+        ReservationTicket { id, token, expires_at }
+    }
+
+    /// Commit or abort the reservation `token` names
+    ///
+    /// Returns the reservation and whether this call resolved it; `false`
+    /// means it already had that outcome.
+    pub fn resolve(
+        &self,
+        token: &str,
+        outcome: ReservationState,
+        reason: Option<String>,
+        now: u64,
+    ) -> Result<(ReservationStatus, bool), GatewayError> {
+        let unknown = GatewayError::NotFound("Unknown or forgotten reservation");
+        let (id, secret) = token.split_once('.').ok_or(unknown.clone())?;
+        let id: u64 = id.parse().map_err(|_| unknown.clone())?;
+        let mut book = self.book.lock().unwrap();
+        let entry = book
+            .entries
+            .get_mut(&id)
+            .filter(|e| tokens_match(secret, &e.secret))
+            .ok_or(unknown)?;
+        match entry.status.state {
+            ReservationState::Pending => {}
+            state if state == outcome => return Ok((entry.status.clone(), false)),
+            ReservationState::Committed => return Err(GatewayError::Conflict("Reservation already committed")),
+            ReservationState::Aborted => return Err(GatewayError::Conflict("Reservation already aborted")),
+            ReservationState::Expired => return Err(GatewayError::Conflict("Reservation expired")),
+        }
+        entry.status.state = outcome;
+        entry.status.resolved_at = Some(now);
+        entry.status.reason = reason;
+        let counter = match outcome {
+            ReservationState::Committed => &self.committed,
+            _ => &self.aborted,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        Ok((entry.status.clone(), true))
+    }
+
+    /// Expire reservation `id` if it is still pending
+    pub fn expire(&self, id: u64, now: u64) -> Option<ReservationStatus> {
+        let mut book = self.book.lock().unwrap();
+        let entry = book.entries.get_mut(&id)?;
+        if entry.status.state != ReservationState::Pending {
+            return None;
+        }
+        entry.status.state = ReservationState::Expired;
+        entry.status.resolved_at = Some(now);
+        self.expired.fetch_add(1, Ordering::Relaxed);
+        Some(entry.status.clone())
+    }
+
+    /// Drop a resolved reservation's outcome
+    pub fn forget(&self, id: u64) {
+        let mut book = self.book.lock().unwrap();
+        if book.entries.get(&id).is_some_and(|e| e.status.state != ReservationState::Pending) {
+            book.entries.remove(&id);
+        }
+    }
+
+    /// Reservations awaiting commit or abort
+    pub fn pending(&self) -> usize {
+        self.book
+            .lock()
+            .unwrap()
+            .entries
+            .values()
+            .filter(|e| e.status.state == ReservationState::Pending)
+            .count()
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_is_idempotent_and_exclusive() {
+        let book = Reservations::new(ReservationConfig { ttl_sec: 60, max_per_agent: 1 });
+        let ticket = book.reserve("a", vec!["b".to_string()], 100);
+        assert_eq!(ticket.expires_at, 160);
+        assert!(book.check_capacity("a").is_err());
+        assert!(book.check_capacity("b").is_ok());
+
+        // Wrong secret or malformed token look like unknown reservations
+        let forged = format!("{}.{}", ticket.id, "0".repeat(32));
+        for token in [forged.as_str(), "nonsense", "99.x"] {
+            let err = book.resolve(token, ReservationState::Committed, None, 110).unwrap_err();
+            assert_eq!(err.code(), "not_found");
+        }
+
+        let (status, first) = book.resolve(&ticket.token, ReservationState::Committed, None, 110).unwrap();
+        assert!(first);
+        assert_eq!((status.state, status.resolved_at), (ReservationState::Committed, Some(110)));
+        let (_, again) = book.resolve(&ticket.token, ReservationState::Committed, None, 111).unwrap();
+        assert!(!again);
+        let err = book.resolve(&ticket.token, ReservationState::Aborted, None, 112).unwrap_err();
+        assert_eq!(err.code(), "conflict");
+        assert_eq!(book.committed.load(Ordering::Relaxed), 1);
+
+        assert!(book.expire(ticket.id, 160).is_none());
+        book.forget(ticket.id);
+        assert!(book.resolve(&ticket.token, ReservationState::Committed, None, 170).is_err());
+    }
+
+    #[test]
+    fn test_expired_reservation_cannot_commit() {
+        let book = Reservations::default();
+        let ticket = book.reserve("a", vec!["b".to_string()], 100);
+        assert_eq!(book.pending(), 1);
+        let expired = book.expire(ticket.id, 160).unwrap();
+        assert_eq!(expired.state, ReservationState::Expired);
+        assert_eq!(book.pending(), 0);
+        let err = book.resolve(&ticket.token, ReservationState::Committed, None, 161).unwrap_err();
+        assert_eq!(err.code(), "conflict");
+        // A pending reservation is never forgotten
+        let open = book.reserve("a", vec![], 100);
+        book.forget(open.id);
+        assert_eq!(book.pending(), 1);
+    }
+}
@@ -0,0 +1,248 @@
+//! Retry tokens for sends refused for a barely overdue report
+//!
+//! A novel-language send refused only because its protocol's report is at
+//! most `RETRY_GRACE_SEC` seconds overdue gets a one-time `retry` token with
+//! its 429. Once the agent files the report, resubmitting the same message
+//! (same sender, recipients, and content) with `"retry_token"` sends it as a
+//! retry of the first attempt: every audit event of the retry carries
+//! `retry_of` with the first attempt's `attempt_id`, logged as
+//! `retry_offered` when the token was issued, and the retry is not counted
+//! against the sender's event quota a second time.
+//!
+//! The report must be accepted within `RETRY_WINDOW_SEC` of the refusal, and
+//! the retry sent within `RETRY_WINDOW_SEC` of the report. A retry still goes
+//! through every check; the token only links it to the first attempt.
+//!
+//! Tokens live only in memory and are not replicated.
+
+use serde::Serialize;
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::{
+    env_parse,
+    signing::{content_digest, random_hex},
+};
+
+/// Default most seconds a report may be overdue for a refusal to get a token
+const DEFAULT_GRACE_SEC: u64 = 10;
+
+/// Default seconds to file the report, and then to retry
+const DEFAULT_WINDOW_SEC: u64 = 60;
+
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// 0 disables retry tokens
+    pub grace_sec: u64,
+    pub window_sec: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            grace_sec: DEFAULT_GRACE_SEC,
+            window_sec: DEFAULT_WINDOW_SEC,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Load `RETRY_GRACE_SEC` and `RETRY_WINDOW_SEC`
+    pub fn from_env() -> Self {
+        let d = Self::default();
+        let var = |key: &str| env_parse::<u64>(key);
+        Self {
+            grace_sec: var("RETRY_GRACE_SEC").unwrap_or(d.grace_sec),
+            window_sec: var("RETRY_WINDOW_SEC").filter(|&s| s > 0).unwrap_or(d.window_sec),
+        }
+    }
+}
+
+/// Returned with a refusal the sender may retry after reporting
+#[derive(Debug, Clone, Serialize)]
+pub struct RetryTicket {
+    /// One-time token to resubmit the message with
+    pub token: String,
+    /// Id of the refused attempt, as retries carry it in `retry_of`
+    pub attempt_id: String,
+    /// Seconds to file the report, and then to retry
+    pub window_sec: u64,
+}
+
+/// The message a token was issued for
+#[derive(Debug, Clone)]
+struct Attempt {
+    id: String,
+    from: String,
+    report_key: String,
+    /// Sorted recipients
+    to: Vec<String>,
+    content_sha256: String,
+    issued_at: u64,
+}
+
+/// A redeemed token
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redeemed {
+    pub attempt_id: String,
+    pub report_key: String,
+}
+
+fn sorted(to: &[&str]) -> Vec<String> {
+    let mut to: Vec<_> = to.iter().map(|t| t.to_string()).collect();
+    to.sort();
+    to.dedup();
+    to
+}
+
+/// Outstanding retry tokens
+#[derive(Debug, Default)]
+pub struct Retries {
+    config: RetryConfig,
+    tokens: Mutex<HashMap<String, Attempt>>,
+}
+
+impl Retries {
+    pub fn new(config: RetryConfig) -> Self {
+        Self {
+            config,
+            tokens: Mutex::default(),
+        }
+    }
+
+    /// Whether a report `overdue_by` seconds overdue earns a token
+    pub fn eligible(&self, overdue_by: u64) -> bool {
+        self.config.grace_sec > 0 && overdue_by <= self.config.grace_sec
+    }
+
+    /// Issue a token for a refused send, dropping tokens that can no longer
+    /// be redeemed
+    pub fn issue(&self, from: &str, report_key: &str, to: &[&str], content: &str, now: u64) -> RetryTicket {
+        let window = self.config.window_sec;
+        let token = random_hex::<16>();
+        let attempt = Attempt {
+            id: random_hex::<16>(),
+            from: from.to_string(),
+            report_key: report_key.to_string(),
+            to: sorted(to),
+            content_sha256: content_digest(content),
+            issued_at: now,
+        };
+        let ticket = RetryTicket {
+            token: token.clone(),
+            attempt_id: attempt.id.clone(),
+            window_sec: window,
+        };
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.retain(|_, a| now <= a.issued_at + 2 * window);
+        tokens.insert(token, attempt);
+        ticket
+    }
+
+    /// Redeem `token` for a resubmitted message, given when the report on the
+    /// token's protocol was last accepted
+    ///
+    /// A token refused only because its report is not filed yet stays valid.
+    pub fn redeem(
+        &self,
+        token: &str,
+        from: &str,
+        to: &[&str],
+        content: &str,
+        last_report: impl FnOnce(&str) -> Option<u64>,
+        now: u64,
+    ) -> Result<Redeemed, String> {
+        let window = self.config.window_sec;
+        let mut tokens = self.tokens.lock().unwrap();
+        let Some(attempt) = tokens.get(token) else {
+            return Err("Unknown or already used retry_token".to_string());
+        };
+        if attempt.from != from || attempt.to != sorted(to) || attempt.content_sha256 != content_digest(content) {
+            return Err("retry_token was issued for a different message".to_string());
+        }
+        let expired = match last_report(&attempt.report_key).filter(|&ts| ts >= attempt.issued_at) {
+            Some(reported) if reported > attempt.issued_at + window => true,
+            Some(reported) => now > reported + window,
+            None if now > attempt.issued_at + window => true,
+            None => return Err("File the overdue report before retrying".to_string()),
+        };
+        let attempt = tokens.remove(token).expect("token present");
+        if expired {
+            return Err("retry_token expired".to_string());
+        }
+        Ok(Redeemed {
+            attempt_id: attempt.id,
+            report_key: attempt.report_key,
+        })
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{AgentFixture, ProtocolFixture, ReportFixture, SendFixture, TestGateway};
+    use axum::http::StatusCode;
+    use std::time::Duration;
+
+    #[test]
+    fn test_issue_and_redeem() {
+        let retries = Retries::new(RetryConfig {
+            grace_sec: 10,
+            window_sec: 60,
+        });
+        assert!(retries.eligible(10));
+        assert!(!retries.eligible(11));
+
+        let ticket = retries.issue("a", "a::coord:1.0", &["b", "c"], "x=1", 1000);
+        let redeem = |token: &str, content: &str, reported: Option<u64>, now: u64| {
+            retries.redeem(token, "a", &["c", "b"], content, |_| reported, now)
+        };
+        assert!(redeem(&ticket.token, "x=2", Some(1010), 1020).is_err(), "content must match");
+        assert_eq!(
+            redeem(&ticket.token, "x=1", Some(900), 1020).unwrap_err(),
+            "File the overdue report before retrying",
+            "a report from before the refusal does not count"
+        );
+        let redeemed = redeem(&ticket.token, "x=1", Some(1010), 1070).unwrap();
+        assert_eq!(redeemed.attempt_id, ticket.attempt_id);
+        assert!(redeem(&ticket.token, "x=1", Some(1010), 1070).is_err(), "tokens are one-time");
+
+        let late = retries.issue("a", "a::coord:1.0", &["b", "c"], "x=1", 2000);
+        assert_eq!(redeem(&late.token, "x=1", Some(2010), 2071).unwrap_err(), "retry_token expired");
+        let unreported = retries.issue("a", "a::coord:1.0", &["b", "c"], "x=1", 3000);
+        assert_eq!(redeem(&unreported.token, "x=1", None, 3061).unwrap_err(), "retry_token expired");
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_report() {
+        let gw = TestGateway::new();
+        let protocol = ProtocolFixture::new("coord", "1.0").gloss("SHP", "shipment").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(protocol.clone()).reported()).await;
+        let send = SendFixture::novel("a", "b", &protocol, "SHP|eta=7f;q=0x3e;z=9");
+
+        // Five seconds past the 60s report interval
+        gw.advance(Duration::from_secs(65));
+        let refused = gw.send(&send.clone().build()).await;
+        assert_eq!(refused.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(refused.body["code"], "report_overdue");
+        let token = refused.body["retry"]["token"].as_str().unwrap().to_string();
+
+        let early = gw.send(&send.clone().retry_token(&token).build()).await;
+        assert_eq!(early.status, StatusCode::BAD_REQUEST, "the report comes first");
+        let report = ReportFixture::new("a", &protocol).summary("One shipment status update sent to agent b");
+        assert_eq!(gw.report(&report.build()).await.status, StatusCode::OK);
+        let retried = gw.send(&send.clone().retry_token(&token).build()).await;
+        assert_eq!(retried.status, StatusCode::OK, "{:?}", retried.body);
+        let again = gw.send(&send.clone().retry_token(&token).build()).await;
+        assert_eq!(again.status, StatusCode::BAD_REQUEST, "tokens are one-time");
+
+        // Thirty seconds overdue is past the grace period
+        gw.advance(Duration::from_secs(90));
+        let late = gw.send(&send.build()).await;
+        assert_eq!(late.status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(late.body.get("retry").is_none());
+    }
+}
@@ -0,0 +1,167 @@
+//! Revisions of registered protocol descriptors
+//!
+//! Registering a `name:version` an agent already registered revises its
+//! descriptor in place. Each revision is diffed field by field against the
+//! descriptor in force: nested objects such as `codebook` or `encryption`
+//! by key (`codebook.ETA`), lists and plain values as a whole. The diff is
+//! returned in the registration response and logged as
+//! `protocol_descriptor_changed`. A registration identical to the one in
+//! force changes nothing and logs no diff.
+//!
+//! `DESCRIPTOR_CHANGE_APPROVAL` lists the fields an agent may not revise on
+//! its own: any of `purpose`, `scope`, and `risk_tier`. A revision touching
+//! one of them is not applied. It is queued as a `change_protocol_descriptor`
+//! proposal by `agent:<id>` (see [`crate::approvals`]) and answered with 202
+//! and the proposal's id. An admin approving it with
+//! `POST /admin/approvals/{id}/approve` registers the proposed descriptor.
+//! A peer's revision of a guarded field is not imported by registry sync (see
+//! [`crate::registry_sync`]): approval is given per gateway.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::BTreeSet, env};
+use tracing::warn;
+
+/// Descriptor fields that can be placed under admin approval
+pub const GUARDABLE: &[&str] = &["purpose", "scope", "risk_tier"];
+
+/// One field that differs between two descriptors
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange {
+    /// Dotted path of the field, e.g. `purpose` or `codebook.ETA`
+    pub field: String,
+    /// `null` when the field was not set
+    pub before: Value,
+    /// `null` when the field is no longer set
+    pub after: Value,
+}
+
+/// Fields of `after` that differ from `before`, sorted by path
+pub fn diff(before: &impl Serialize, after: &impl Serialize) -> Vec<FieldChange> {
+    let (before, after) = (
+        serde_json::to_value(before).unwrap_or_default(),
+        serde_json::to_value(after).unwrap_or_default(),
+    );
+    let mut changes = Vec::new();
+    walk("", &before, &after, &mut changes);
+    changes
+}
+
+fn walk(path: &str, before: &Value, after: &Value, changes: &mut Vec<FieldChange>) {
+    if before == after {
+        return;
+    }
+    let (Value::Object(b), Value::Object(a)) = (before, after) else {
+        return changes.push(change(path, before, after));
+    };
+    let keys: BTreeSet<&String> = b.keys().chain(a.keys()).collect();
+    for key in keys {
+        let field = if path.is_empty() { key.clone() } else { format!("{path}.{key}") };
+        walk(&field, b.get(key).unwrap_or(&Value::Null), a.get(key).unwrap_or(&Value::Null), changes);
+    }
+}
+
+fn change(path: &str, before: &Value, after: &Value) -> FieldChange {
+    FieldChange {
+        field: path.to_string(),
+        before: before.clone(),
+        after: after.clone(),
+    }
+}
+
+/// Descriptor fields whose revision waits for an admin
+#[derive(Debug, Clone, Default)]
+pub struct ChangeControl {
+    fields: BTreeSet<&'static str>,
+}
+
+impl ChangeControl {
+    pub fn new<'a>(fields: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut guarded = BTreeSet::new();
+        for name in fields.into_iter().map(str::trim).filter(|f| !f.is_empty()) {
+            match GUARDABLE.iter().find(|f| **f == name) {
+                Some(field) => {
+                    guarded.insert(*field);
+                }
+                None => warn!(
+                    field = %name,
+                    event = "config_invalid",
+                    "Ignoring descriptor field that cannot require approval"
+                ),
+            }
+        }
+        Self { fields: guarded }
+    }
+
+    /// Read `DESCRIPTOR_CHANGE_APPROVAL` (comma-separated)
+    pub fn from_env() -> Self {
+        Self::new(env::var("DESCRIPTOR_CHANGE_APPROVAL").unwrap_or_default().split(','))
+    }
+
+    /// Guarded fields among `changes`
+    pub fn guarded(&self, changes: &[FieldChange]) -> Vec<&'static str> {
+        self.fields
+            .iter()
+            .copied()
+            .filter(|f| changes.iter().any(|c| c.field == *f))
+            .collect()
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{AgentFixture, ProtocolFixture, TestGateway};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_by_field() {
+        let before = json!({"purpose": "coordination", "codebook": {"ETA": "arrival"}, "docs": [1]});
+        let after = json!({"purpose": "billing", "codebook": {"ETA": "arrival", "SHP": "shipment"}, "docs": [1, 2],
+                           "encryption": {"scheme": "age"}});
+        let changes = diff(&before, &after);
+        let fields: Vec<&str> = changes.iter().map(|c| c.field.as_str()).collect();
+        assert_eq!(fields, ["codebook.SHP", "docs", "encryption", "purpose"]);
+        assert_eq!(changes[0].before, Value::Null);
+        assert_eq!(changes[3].after, "billing");
+        assert!(diff(&before, &before).is_empty());
+
+        let control = ChangeControl::new(["risk_tier", " purpose", "codebook"]);
+        assert_eq!(control.guarded(&changes), ["purpose"]);
+    }
+
+    #[tokio::test]
+    async fn test_guarded_revision_waits_for_an_admin() {
+        let gw = TestGateway::with_descriptor_approval(&["risk_tier"]);
+        let protocol = ProtocolFixture::new("coord", "1.0").risk_tier("low").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(protocol.clone())).await;
+
+        // Unguarded fields change at once, with the diff returned
+        let mut revised = protocol.clone();
+        revised.purpose = "Shipment coordination".to_string();
+        let applied = gw.register("a", &revised).await;
+        assert_eq!(applied.status, StatusCode::OK);
+        assert_eq!(applied.body["changes"][0]["field"], "purpose");
+        assert_eq!(gw.register("a", &revised).await.body.get("changes"), None, "nothing changed");
+
+        revised.risk_tier = "high".to_string();
+        let held = gw.register("a", &revised).await;
+        assert_eq!(held.status, StatusCode::ACCEPTED);
+        assert_eq!(held.body["changes"][0]["after"], "high");
+        let descriptor = |gw: &TestGateway| gw.state().inner.read().unwrap().protocols["a"]["coord:1.0"].clone();
+        assert_eq!(descriptor(&gw).risk_tier, "low");
+
+        let queue = gw.admin(Method::GET, "/admin/approvals", None::<&()>).await;
+        let proposal = &queue.body["pending"][0];
+        assert_eq!(proposal["action"], "change_protocol_descriptor");
+        assert_eq!(proposal["proposed_by"], "agent:a");
+        let path = format!("/admin/approvals/{}/approve", held.body["approval_id"]);
+        assert_eq!(gw.admin(Method::POST, &path, None::<&()>).await.status, StatusCode::OK);
+        assert_eq!(descriptor(&gw).risk_tier, "high");
+    }
+}
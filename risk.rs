@@ -0,0 +1,322 @@
+//! Protocol risk reassessment
+//!
+//! Agents declare a `risk_tier` when they register a protocol, but a protocol
+//! registered as `low` can drift into high-risk territory. The policy's `risk`
+//! section lists triggers that raise a protocol's effective tier when they
+//! fire:
+//!
+//! - `volume`: more than `messages` sends within a `window_sec` window
+//! - `recipients`: a send to a new recipient past `count` distinct recipients
+//! - `recipient_class`: a send to a recipient of `class` (see [`crate::routing`])
+//! - `anomaly`: a protocol-mismatch or report-fraud alert on the protocol
+//!
+//! The effective tier is the registered one or the highest tier a trigger
+//! raised the protocol to, whichever is higher. It selects the decision
+//! webhook rule, and `report_interval_sec` maps tiers to report intervals: an
+//! agent reports on a raised protocol at the tighter of the tier's interval
+//! and its own. A tier without an interval takes the tightest one listed for
+//! the tiers below it.
+//!
+//! Raises never lapse on their own; an admin clears them with
+//! `POST /protocols/{agent_id}/{name}/{version}/risk/reset`.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    env, fmt,
+};
+use tracing::warn;
+
+/// Protocol risk tiers, lowest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskTier {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl RiskTier {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "low" => Some(Self::Low),
+            "medium" => Some(Self::Medium),
+            "high" => Some(Self::High),
+            "critical" => Some(Self::Critical),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+impl fmt::Display for RiskTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A condition that raises a protocol to `tier`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "on", rename_all = "snake_case")]
+pub enum Trigger {
+    Volume { messages: u64, window_sec: u64, tier: RiskTier },
+    Recipients { count: usize, tier: RiskTier },
+    RecipientClass { class: String, tier: RiskTier },
+    Anomaly { tier: RiskTier },
+}
+
+impl Trigger {
+    pub fn tier(&self) -> RiskTier {
+        match self {
+            Self::Volume { tier, .. }
+            | Self::Recipients { tier, .. }
+            | Self::RecipientClass { tier, .. }
+            | Self::Anomaly { tier } => *tier,
+        }
+    }
+
+    /// Why the trigger fired, for the audit log and the owner's alert
+    pub fn reason(&self) -> String {
+        match self {
+            Self::Volume { messages, window_sec, .. } => {
+                format!("over {messages} messages within {window_sec}s")
+            }
+            Self::Recipients { count, .. } => format!("over {count} distinct recipients"),
+            Self::RecipientClass { class, .. } => format!("sent to recipient class {class}"),
+            Self::Anomaly { .. } => "anomaly alert".to_string(),
+        }
+    }
+}
+
+/// Reassessment triggers and the report interval of each tier
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RiskPolicy {
+    /// Tier -> report interval; tiers not listed take the tightest interval
+    /// of a lower tier, or none
+    #[serde(default)]
+    pub report_interval_sec: BTreeMap<RiskTier, u64>,
+    #[serde(default)]
+    pub triggers: Vec<Trigger>,
+}
+
+impl RiskPolicy {
+    /// Parse `PROTOCOL_RISK`, a JSON risk section; none when unset
+    pub fn from_env() -> Option<Self> {
+        let raw = env::var("PROTOCOL_RISK").ok().filter(|v| !v.trim().is_empty())?;
+        match serde_json::from_str::<Self>(&raw).map_err(|e| e.to_string()).and_then(|r| r.validate().map(|_| r)) {
+            Ok(risk) => Some(risk),
+            Err(e) => {
+                warn!(event = "config_invalid", error = %e, "Ignoring invalid PROTOCOL_RISK");
+                None
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some((tier, _)) = self.report_interval_sec.iter().find(|(_, &s)| s == 0) {
+            return Err(format!("risk.report_interval_sec.{tier} must be positive"));
+        }
+        for (i, trigger) in self.triggers.iter().enumerate() {
+            if let Trigger::Volume { messages: 0, .. } | Trigger::Volume { window_sec: 0, .. } = trigger {
+                return Err(format!("risk.triggers[{i}]: messages and window_sec must be positive"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Report interval for a protocol at `tier`, if the tier tightens it
+    pub fn interval(&self, tier: RiskTier) -> Option<u64> {
+        self.report_interval_sec.range(..=tier).map(|(_, &s)| s).min()
+    }
+
+    /// Highest trigger fired by a send of `sent` messages to recipients in
+    /// `classes`; `recipients` is the protocol's distinct recipients so far
+    /// when the send added one, so a reset protocol is raised again only by
+    /// further recipients
+    pub fn on_send(
+        &self,
+        volume: &mut Volume,
+        sent: u64,
+        recipients: Option<usize>,
+        classes: &BTreeSet<String>,
+        now: u64,
+    ) -> Option<&Trigger> {
+        let windows: BTreeSet<u64> = self
+            .triggers
+            .iter()
+            .filter_map(|t| match t {
+                Trigger::Volume { window_sec, .. } => Some(*window_sec),
+                _ => None,
+            })
+            .collect();
+        for window_sec in windows {
+            volume.record(window_sec, sent, now);
+        }
+        self.triggers
+            .iter()
+            .filter(|t| match t {
+                Trigger::Volume { messages, window_sec, .. } => volume.count(*window_sec) > *messages,
+                Trigger::Recipients { count, .. } => recipients.is_some_and(|r| r > *count),
+                Trigger::RecipientClass { class, .. } => classes.contains(class.as_str()),
+                Trigger::Anomaly { .. } => false,
+            })
+            .max_by_key(|t| t.tier())
+    }
+
+    /// Highest anomaly trigger
+    pub fn on_anomaly(&self) -> Option<&Trigger> {
+        self.triggers
+            .iter()
+            .filter(|t| matches!(t, Trigger::Anomaly { .. }))
+            .max_by_key(|t| t.tier())
+    }
+}
+
+/// Sends per fixed window, for each window length a trigger counts over
+#[derive(Debug, Clone, Default)]
+pub struct Volume {
+    /// Window length -> (window start, sends in it)
+    windows: HashMap<u64, (u64, u64)>,
+}
+
+impl Volume {
+    fn record(&mut self, window_sec: u64, sent: u64, now: u64) {
+        let start = now - now % window_sec;
+        let window = self.windows.entry(window_sec).or_insert((start, 0));
+        if window.0 != start {
+            *window = (start, 0);
+        }
+        window.1 += sent;
+    }
+
+    fn count(&self, window_sec: u64) -> u64 {
+        self.windows.get(&window_sec).map_or(0, |w| w.1)
+    }
+}
+
+/// Reassessment of one agent protocol
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RiskStanding {
+    /// Highest tier a trigger raised the protocol to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raised_to: Option<RiskTier>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raised_at: Option<u64>,
+    /// Why each raise happened, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reasons: Vec<String>,
+}
+
+impl RiskStanding {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Effective tier given the `registered` one; none when the registered
+    /// tier is not a known tier and nothing raised it
+    pub fn tier(&self, registered: &str) -> Option<RiskTier> {
+        RiskTier::parse(registered).max(self.raised_to)
+    }
+
+    /// Effective tier name, the registered one verbatim unless raised past it
+    pub fn effective(&self, registered: &str) -> String {
+        match self.raised_to {
+            Some(raised) if RiskTier::parse(registered).is_none_or(|r| raised > r) => raised.to_string(),
+            _ => registered.to_string(),
+        }
+    }
+
+    /// Raise the protocol to `trigger`'s tier; returns whether its effective
+    /// tier rose
+    pub fn raise(&mut self, registered: &str, trigger: &Trigger, now: u64) -> bool {
+        if self.tier(registered).is_some_and(|t| t >= trigger.tier()) {
+            return false;
+        }
+        self.raised_to = Some(trigger.tier());
+        self.raised_at = Some(now);
+        self.reasons.push(format!("{}: {}", trigger.tier(), trigger.reason()));
+        true
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RiskPolicy {
+        serde_json::from_value(serde_json::json!({
+            "report_interval_sec": {"medium": 1800, "high": 600},
+            "triggers": [
+                {"on": "volume", "messages": 3, "window_sec": 60, "tier": "medium"},
+                {"on": "recipients", "count": 2, "tier": "medium"},
+                {"on": "recipient_class", "class": "human", "tier": "high"},
+                {"on": "anomaly", "tier": "critical"}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_triggers_fire() {
+        let risk = policy();
+        assert!(risk.validate().is_ok());
+        let mut volume = Volume::default();
+        let none = BTreeSet::new();
+        assert!(risk.on_send(&mut volume, 3, Some(1), &none, 100).is_none());
+        let fired = risk.on_send(&mut volume, 1, Some(1), &none, 110).unwrap();
+        assert_eq!(fired.reason(), "over 3 messages within 60s");
+        // A new window starts the count over
+        assert!(risk.on_send(&mut volume, 1, Some(1), &none, 120).is_none());
+
+        assert_eq!(risk.on_send(&mut volume, 1, Some(3), &none, 130).unwrap().tier(), RiskTier::Medium);
+        let human = BTreeSet::from(["human".to_string()]);
+        assert_eq!(risk.on_send(&mut volume, 1, Some(3), &human, 140).unwrap().tier(), RiskTier::High);
+        assert_eq!(risk.on_anomaly().unwrap().tier(), RiskTier::Critical);
+
+        assert_eq!(risk.interval(RiskTier::Low), None);
+        assert_eq!(risk.interval(RiskTier::High), Some(600));
+        assert_eq!(risk.interval(RiskTier::Critical), Some(600));
+
+        let bad = RiskPolicy {
+            triggers: vec![Trigger::Volume { messages: 10, window_sec: 0, tier: RiskTier::High }],
+            ..RiskPolicy::default()
+        };
+        assert!(bad.validate().is_err());
+    }
+
+    #[test]
+    fn test_raise_only_upward() {
+        let risk = policy();
+        let mut standing = RiskStanding::default();
+        assert_eq!(standing.tier("low"), Some(RiskTier::Low));
+        assert!(standing.raise("low", &risk.triggers[1], 10));
+        assert_eq!(standing.effective("low"), "medium");
+        // Already medium: a second medium trigger changes nothing
+        assert!(!standing.raise("low", &risk.triggers[0], 20));
+        assert!(standing.raise("low", &risk.triggers[3], 30));
+        assert_eq!((standing.tier("low"), standing.raised_at), (Some(RiskTier::Critical), Some(30)));
+        assert_eq!(standing.reasons, ["medium: over 2 distinct recipients", "critical: anomaly alert"]);
+
+        // Registered above the trigger's tier
+        let mut high = RiskStanding::default();
+        assert!(!high.raise("high", &risk.triggers[1], 10));
+        assert_eq!(high.effective("high"), "high");
+        // Unknown registered tiers are kept until raised
+        assert_eq!(RiskStanding::default().effective("exotic"), "exotic");
+        assert!(high.raise("exotic", &risk.triggers[1], 10));
+        assert_eq!(high.effective("exotic"), "medium");
+    }
+}
@@ -0,0 +1,195 @@
+//! Recipient classes and the policy pipelines they select
+//!
+//! Recipients are not all alike: a human-facing service must only ever see
+//! English, a logger can take protocol traffic as long as it can be glossed,
+//! and another agent gets the standard gate. The policy's `routing` section
+//! names recipient classes, the [`Pipeline`] each one selects, and the rules
+//! that sort recipients into classes.
+//!
+//! A recipient's class comes from the agent directory when discovery lists
+//! one (the `recipient_class` Consul meta key, or the
+//! `agent-gateway/recipient-class` Kubernetes label), and otherwise from the
+//! first rule that matches it, by agent id pattern or owning team.
+//! Unclassified recipients, and classes without a pipeline, get the standard
+//! pipeline.
+//!
+//! Pipelines run per recipient after the sender-side checks, so a broadcast
+//! to a human-facing service and an agent can be refused for one and
+//! delivered to the other.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env};
+use tracing::warn;
+
+/// Checks applied to sends addressed to a recipient class
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pipeline {
+    /// Sender-side checks only
+    #[default]
+    Standard,
+    /// English only, whatever protocols the sender has registered
+    EnglishOnly,
+    /// Novel-language sends only under protocols with a codebook
+    CodebookRequired,
+}
+
+impl Pipeline {
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Standard => "standard",
+            Self::EnglishOnly => "english_only",
+            Self::CodebookRequired => "codebook_required",
+        }
+    }
+}
+
+/// Sorts matching recipients into `class`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteRule {
+    /// Agent id pattern; `*` matches any run of characters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// Owning team
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+    pub class: String,
+}
+
+impl RouteRule {
+    fn matches(&self, agent_id: &str, team: Option<&str>) -> bool {
+        self.pattern.as_deref().is_none_or(|p| glob(p, agent_id))
+            && self.team.as_deref().is_none_or(|t| team == Some(t))
+    }
+}
+
+/// Recipient classes, their pipelines, and the rules assigning them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Routing {
+    /// Class -> pipeline applied to its recipients
+    #[serde(default)]
+    pub classes: BTreeMap<String, Pipeline>,
+    /// Checked in order; the first match classifies a recipient
+    #[serde(default)]
+    pub rules: Vec<RouteRule>,
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any run of characters
+fn glob(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+impl Routing {
+    /// Parse `RECIPIENT_ROUTING`, a JSON routing section; none when unset
+    pub fn from_env() -> Option<Self> {
+        let raw = env::var("RECIPIENT_ROUTING").ok().filter(|v| !v.trim().is_empty())?;
+        match serde_json::from_str::<Self>(&raw).map_err(|e| e.to_string()).and_then(|r| r.validate().map(|_| r)) {
+            Ok(routing) => Some(routing),
+            Err(e) => {
+                warn!(event = "config_invalid", error = %e, "Ignoring invalid RECIPIENT_ROUTING");
+                None
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (i, rule) in self.rules.iter().enumerate() {
+            if rule.pattern.is_none() && rule.team.is_none() {
+                return Err(format!("routing.rules[{i}] needs a pattern or a team"));
+            }
+            if !self.classes.contains_key(&rule.class) {
+                return Err(format!("routing.rules[{i}]: unknown class {}", rule.class));
+            }
+        }
+        Ok(())
+    }
+
+    /// Class of `agent_id`: its directory class, else the first matching rule's
+    pub fn classify<'a>(&'a self, agent_id: &str, listed: Option<&'a str>, team: Option<&str>) -> Option<&'a str> {
+        listed.or_else(|| {
+            self.rules
+                .iter()
+                .find(|r| r.matches(agent_id, team))
+                .map(|r| r.class.as_str())
+        })
+    }
+
+    pub fn pipeline(&self, class: &str) -> Pipeline {
+        self.classes.get(class).copied().unwrap_or_default()
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn routing() -> Routing {
+        serde_json::from_str(
+            r#"{
+                "classes": {"human": "english_only", "logger": "codebook_required", "agent": "standard"},
+                "rules": [
+                    {"pattern": "ui-*", "class": "human"},
+                    {"team": "observability", "class": "logger"},
+                    {"pattern": "*-log", "team": "support", "class": "human"}
+                ]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("ui-*", "ui-chat"));
+        assert!(glob("*-log", "audit-log"));
+        assert!(glob("a*b*c", "aXXbYYc"));
+        assert!(glob("*", ""));
+        assert!(glob("exact", "exact"));
+        assert!(!glob("exact", "exactly"));
+        assert!(!glob("ui-*", "gui-chat"));
+        assert!(!glob("a*b*c", "acb"));
+    }
+
+    #[test]
+    fn test_directory_class_then_first_matching_rule() {
+        let r = routing();
+        assert!(r.validate().is_ok());
+        assert_eq!(r.classify("ui-chat", None, Some("observability")), Some("human"));
+        assert_eq!(r.classify("ui-chat", Some("agent"), None), Some("agent"));
+        assert_eq!(r.classify("sink", None, Some("observability")), Some("logger"));
+        assert_eq!(r.classify("audit-log", None, Some("support")), Some("human"));
+        assert_eq!(r.classify("audit-log", None, None), None);
+
+        assert_eq!(r.pipeline("human"), Pipeline::EnglishOnly);
+        assert_eq!(r.pipeline("unlisted"), Pipeline::Standard);
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut r = routing();
+        r.rules.push(RouteRule { pattern: None, team: None, class: "human".into() });
+        assert!(r.validate().is_err());
+        r.rules.pop();
+        r.rules.push(RouteRule { pattern: Some("x".into()), team: None, class: "robot".into() });
+        assert!(r.validate().unwrap_err().contains("robot"));
+    }
+}
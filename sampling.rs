@@ -0,0 +1,200 @@
+//! Per-event sampling of log and audit volume
+//!
+//! At high send rates one `msg_accepted` line per recipient floods both the
+//! console log and the audit trail, while rejections and violations are rare
+//! and each one matters. `LOG_SAMPLING` keeps only a share of the events
+//! named in it:
+//!
+//! ```text
+//! LOG_SAMPLING=msg_accepted=0.01,report_accepted=0.1
+//! ```
+//!
+//! Events not listed are always kept. A sampled event is kept or dropped for
+//! the console log and the audit trail alike, so the two never disagree.
+//! Sampling is deterministic: at rate `r`, the first event is kept and then
+//! one in every `1 / r`.
+//!
+//! Metrics are counted where the gateway acts, not from log lines, so
+//! counters such as `english_messages_total` stay exact. The
+//! `log_events_total` metric reports how many events of each sampled kind
+//! were kept and sampled out.
+
+use std::{
+    collections::BTreeMap,
+    env, fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// Sampling rate per event name, from `LOG_SAMPLING`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SamplingConfig {
+    pub rates: BTreeMap<String, f64>,
+}
+
+impl SamplingConfig {
+    /// Parse `LOG_SAMPLING` (`event=rate,...`); empty when unset
+    pub fn from_env() -> Result<Self, String> {
+        env::var("LOG_SAMPLING").map_or(Ok(Self::default()), |v| Self::parse(&v))
+    }
+
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut rates = BTreeMap::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (event, rate) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("LOG_SAMPLING entry {entry:?} is not event=rate"))?;
+            let rate: f64 = rate
+                .trim()
+                .parse()
+                .ok()
+                .filter(|r| (0.0..=1.0).contains(r))
+                .ok_or_else(|| format!("LOG_SAMPLING rate for {} must be within [0, 1]", event.trim()))?;
+            rates.insert(event.trim().to_string(), rate);
+        }
+        Ok(Self { rates })
+    }
+}
+
+/// Events of one sampled kind
+#[derive(Debug, Default)]
+struct Counts {
+    seen: AtomicU64,
+    kept: AtomicU64,
+}
+
+/// Sampling decisions and their counts
+#[derive(Debug, Default)]
+pub struct Sampler {
+    rates: BTreeMap<String, (f64, Counts)>,
+}
+
+impl Sampler {
+    pub fn new(config: SamplingConfig) -> Self {
+        Self {
+            rates: config
+                .rates
+                .into_iter()
+                .map(|(event, rate)| (event, (rate, Counts::default())))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+
+    pub fn rates(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.rates.iter().map(|(event, (rate, _))| (event.as_str(), *rate))
+    }
+
+    /// Whether to keep the next `event`
+    pub fn keep(&self, event: &str) -> bool {
+        let Some((rate, counts)) = self.rates.get(event) else {
+            return true;
+        };
+        let n = counts.seen.fetch_add(1, Ordering::Relaxed) + 1;
+        let keep = (n as f64 * rate).ceil() > ((n - 1) as f64 * rate).ceil();
+        if keep {
+            counts.kept.fetch_add(1, Ordering::Relaxed);
+        }
+        keep
+    }
+
+    /// (event, kept, sampled out) for each sampled kind
+    pub fn counts(&self) -> impl Iterator<Item = (&str, u64, u64)> {
+        self.rates.iter().map(|(event, (_, counts))| {
+            let kept = counts.kept.load(Ordering::Relaxed);
+            (event.as_str(), kept, counts.seen.load(Ordering::Relaxed) - kept)
+        })
+    }
+}
+
+/// The `event` field of a log point
+#[derive(Default)]
+struct EventName(Option<String>);
+
+impl Visit for EventName {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "event" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
+
+/// Drops sampled-out events before any other layer sees them
+pub struct SamplingLayer {
+    sampler: Arc<Sampler>,
+}
+
+impl SamplingLayer {
+    pub fn new(sampler: Arc<Sampler>) -> Self {
+        Self { sampler }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for SamplingLayer {
+    fn event_enabled(&self, event: &Event<'_>, _ctx: Context<'_, S>) -> bool {
+        if self.sampler.is_empty() {
+            return true;
+        }
+        let mut name = EventName::default();
+        event.record(&mut name);
+        name.0.is_none_or(|event| self.sampler.keep(&event))
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::{AuditLayer, AuditLog};
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn test_parse() {
+        let config = SamplingConfig::parse("msg_accepted=0.01, report_accepted = 1").unwrap();
+        assert_eq!(config.rates["msg_accepted"], 0.01);
+        assert_eq!(config.rates["report_accepted"], 1.0);
+        assert!(SamplingConfig::parse("").unwrap().rates.is_empty());
+        assert!(SamplingConfig::parse("msg_accepted").is_err());
+        assert!(SamplingConfig::parse("msg_accepted=2").is_err());
+    }
+
+    #[test]
+    fn test_sampled_events_skip_audit_and_keep_counts() {
+        let sampler = Arc::new(Sampler::new(SamplingConfig::parse("msg_accepted=0.25").unwrap()));
+        let log = Arc::new(AuditLog::new(100));
+        let subscriber = tracing_subscriber::registry()
+            .with(SamplingLayer::new(sampler.clone()))
+            .with(AuditLayer::new(log.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            for n in 0..10u64 {
+                tracing::info!(event = "msg_accepted", n, "Accepted");
+                tracing::warn!(event = "msg_rejected", n, "Rejected");
+            }
+        });
+
+        let events = log.read_page(0, u64::MAX, 100);
+        let accepted: Vec<_> = events
+            .iter()
+            .filter(|e| e.event == "msg_accepted")
+            .map(|e| e.fields["n"].as_u64().unwrap())
+            .collect();
+        assert_eq!(accepted, [0, 4, 8]);
+        assert_eq!(events.iter().filter(|e| e.event == "msg_rejected").count(), 10);
+        assert_eq!(sampler.counts().collect::<Vec<_>>(), [("msg_accepted", 3, 7)]);
+    }
+}
@@ -0,0 +1,111 @@
+//! Protocol-level sanctions for repeated report fraud
+//!
+//! A report whose claims contradict the observed traffic is held for review
+//! (see [`crate::consistency`]) and counts as a strike against the agent
+//! protocol it covers. A report that passes the consistency check clears the
+//! strikes. Once the policy's `report_strike_limit` consecutive strikes are
+//! reached the agent protocol is `suspended_for_review`: sends under it are
+//! refused with `protocol_suspended` while the agent's other protocols keep
+//! working. Approving one of its held reports, or an admin calling
+//! `POST /protocols/{agent_id}/{name}/{version}/reinstate`, lifts the
+//! suspension and clears the strikes.
+
+use serde::{Deserialize, Serialize};
+
+/// Standing of one agent protocol
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Standing {
+    /// Consecutive reports held for low consistency
+    pub strikes: u32,
+    /// When the protocol was suspended for review
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suspended_at: Option<u64>,
+}
+
+/// Whether sends under an agent protocol are allowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProtocolStatus {
+    Active,
+    SuspendedForReview,
+}
+
+impl Standing {
+    pub fn is_suspended(&self) -> bool {
+        self.suspended_at.is_some()
+    }
+
+    pub fn status(&self) -> ProtocolStatus {
+        if self.is_suspended() {
+            ProtocolStatus::SuspendedForReview
+        } else {
+            ProtocolStatus::Active
+        }
+    }
+
+    /// Count a held report; returns true when this strike suspends the
+    /// protocol. A `limit` of 0 never suspends.
+    pub fn strike(&mut self, limit: u32, now: u64) -> bool {
+        self.strikes = self.strikes.saturating_add(1);
+        if limit == 0 || self.is_suspended() || self.strikes < limit {
+            return false;
+        }
+        self.suspended_at = Some(now);
+        true
+    }
+
+    /// A report passed the consistency check; strikes only reset while the
+    /// protocol is in good standing, so a suspension waits for a reviewer
+    pub fn clear_strikes(&mut self) -> bool {
+        if self.is_suspended() || self.strikes == 0 {
+            return false;
+        }
+        self.strikes = 0;
+        true
+    }
+
+    /// Lift a suspension and clear the strikes; returns whether it was suspended
+    pub fn reinstate(&mut self) -> bool {
+        let was_suspended = self.is_suspended();
+        *self = Self::default();
+        was_suspended
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strikes_suspend_until_reinstated() {
+        let mut standing = Standing::default();
+        assert!(!standing.strike(3, 10));
+        assert!(standing.clear_strikes());
+        assert_eq!(standing.strikes, 0);
+
+        assert!(!standing.strike(3, 20));
+        assert!(!standing.strike(3, 30));
+        assert!(standing.strike(3, 40));
+        assert_eq!(standing.status(), ProtocolStatus::SuspendedForReview);
+        assert_eq!(standing.suspended_at, Some(40));
+
+        // Further strikes and clean reports leave the suspension in place
+        assert!(!standing.strike(3, 50));
+        assert!(!standing.clear_strikes());
+        assert_eq!((standing.strikes, standing.suspended_at), (4, Some(40)));
+
+        assert!(standing.reinstate());
+        assert_eq!(standing, Standing::default());
+        assert!(!standing.reinstate());
+
+        // A limit of 0 disables suspension
+        for ts in 0..10 {
+            assert!(!standing.strike(0, ts));
+        }
+        assert_eq!(standing.status(), ProtocolStatus::Active);
+    }
+}
@@ -0,0 +1,243 @@
+//! Declarative scenario tests
+//!
+//! Each YAML file in `scenarios/` is a sequence of API calls run against a
+//! fresh [`TestGateway`], with the expected status, error `code`, and response
+//! body fields of each call. Policy changes can be checked against the whole
+//! scenario library without writing Rust for every case.
+//!
+//! ```yaml
+//! name: Overdue report blocks novel sends
+//! policy: {report_interval_sec: 60}     # overrides of the default policy
+//! steps:
+//!   - register: {agent_id: a, protocol: {name: coord, version: "1.0"}}
+//!   - send: {from: a, to: b, content: "SHP|q=7f", protocol: {name: coord, version: "1.0"}}
+//!     expect: {status: 429, code: report_overdue}
+//!   - advance: 61                       # seconds on the manual clock
+//!   - get: /protocols/a/coord/1.0/stats
+//!     expect: {body: {messages_sent: 0}}
+//!   - admin: {method: PUT, path: /agents/a/owner, body: {team: red}}
+//! ```
+//!
+//! `register`, `send`, and `report` bodies are merged over the defaults of the
+//! matching fixture builder, so a step only names what it cares about. A step
+//! without `expect` must succeed with a 2xx status. `body` matches when every
+//! field it lists is present in the response with the same value; arrays and
+//! scalars compare exactly.
+
+use axum::http::Method;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{fs, path::Path, time::Duration};
+
+use crate::{
+    policy::Policy,
+    testing::{ProtocolFixture, ReportFixture, SendFixture, TestGateway, TestResponse},
+};
+
+/// Directory holding the scenario files, relative to the manifest
+const SCENARIO_DIR: &str = "scenarios";
+
+/// One scenario file
+#[derive(Debug, Deserialize)]
+struct Scenario {
+    name: String,
+    #[serde(default)]
+    policy: Map<String, Value>,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Step {
+    #[serde(flatten)]
+    action: Action,
+    #[serde(default)]
+    expect: Option<Expect>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    Register(Value),
+    Send(Value),
+    Report(Value),
+    /// Move the manual clock forward by this many seconds
+    Advance(u64),
+    Get(String),
+    /// Request authenticated with the admin token
+    Admin {
+        method: String,
+        path: String,
+        #[serde(default)]
+        body: Option<Value>,
+    },
+}
+
+impl Action {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Register(_) => "register",
+            Self::Send(_) => "send",
+            Self::Report(_) => "report",
+            Self::Advance(_) => "advance",
+            Self::Get(_) => "get",
+            Self::Admin { .. } => "admin",
+        }
+    }
+}
+
+/// Expected outcome of a call
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Expect {
+    status: Option<u16>,
+    code: Option<String>,
+    body: Option<Value>,
+}
+
+impl Expect {
+    fn check(&self, response: &TestResponse) -> Result<(), String> {
+        let status = response.status;
+        match self.status {
+            Some(expected) if status.as_u16() != expected => {
+                return Err(format!("expected status {expected}, got {status}: {}", response.body));
+            }
+            None if !status.is_success() => {
+                return Err(format!("expected success, got {status}: {}", response.body));
+            }
+            _ => {}
+        }
+        if let Some(code) = &self.code {
+            if response.body["code"].as_str() != Some(code) {
+                return Err(format!("expected code {code:?}, got {}", response.body));
+            }
+        }
+        if let Some(body) = &self.body {
+            if !body_matches(&response.body, body) {
+                return Err(format!("body {} does not match {body}", response.body));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether every field of `expected` appears in `actual` with the same value
+fn body_matches(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => expected
+            .iter()
+            .all(|(k, v)| actual.get(k).is_some_and(|a| body_matches(a, v))),
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        _ => actual == expected,
+    }
+}
+
+/// `base` with the fields of `overrides` merged in, recursing into objects
+fn merged(mut base: Value, overrides: &Value) -> Value {
+    match (&mut base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (k, v) in overrides {
+                let slot = base.remove(k).unwrap_or(Value::Null);
+                base.insert(k.clone(), merged(slot, v));
+            }
+        }
+        (base, overrides) => *base = overrides.clone(),
+    }
+    base
+}
+
+fn fixture(value: impl serde::Serialize) -> Value {
+    serde_json::to_value(value).unwrap()
+}
+
+/// Placeholder for ids a step is expected to name
+const UNSET: &str = "unset";
+
+async fn run(scenario: &Scenario) -> Result<(), String> {
+    let policy = merged(fixture(Policy::default()), &Value::Object(scenario.policy.clone()));
+    let policy: Policy = serde_json::from_value(policy).map_err(|e| format!("policy: {e}"))?;
+    let gw = TestGateway::with_policy(policy);
+    let protocol = ProtocolFixture::new(UNSET, UNSET).build();
+
+    for (i, step) in scenario.steps.iter().enumerate() {
+        let fail = |e: String| format!("step {} ({}): {e}", i + 1, step.action.label());
+        let response = match &step.action {
+            Action::Register(body) => {
+                let defaults = serde_json::json!({ "agent_id": UNSET, "protocol": fixture(&protocol) });
+                gw.post("/register_protocol_for_agent", &merged(defaults, body)).await
+            }
+            Action::Send(body) => {
+                let defaults = fixture(SendFixture::english(UNSET, UNSET).build());
+                gw.post("/send", &merged(defaults, body)).await
+            }
+            Action::Report(body) => {
+                let defaults = fixture(ReportFixture::new(UNSET, &protocol).build());
+                gw.post("/report", &merged(defaults, body)).await
+            }
+            Action::Advance(secs) => {
+                if step.expect.is_some() {
+                    return Err(fail("advance takes no expect".to_string()));
+                }
+                gw.advance(Duration::from_secs(*secs));
+                continue;
+            }
+            Action::Get(path) => gw.get(path).await,
+            Action::Admin { method, path, body } => {
+                let method = Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                    .map_err(|e| fail(format!("method: {e}")))?;
+                gw.admin(method, path, body.as_ref()).await
+            }
+        };
+        step.expect.as_ref().unwrap_or(&Expect::default()).check(&response).map_err(fail)?;
+    }
+    Ok(())
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_and_match() {
+        let base = serde_json::json!({ "a": 1, "b": { "c": 2, "d": 3 } });
+        let over = serde_json::json!({ "b": { "c": 4 }, "e": [5] });
+        let merged = merged(base, &over);
+        assert_eq!(merged, serde_json::json!({ "a": 1, "b": { "c": 4, "d": 3 }, "e": [5] }));
+
+        assert!(body_matches(&merged, &serde_json::json!({ "b": { "d": 3.0 } })));
+        assert!(!body_matches(&merged, &serde_json::json!({ "b": { "x": 3 } })));
+        assert!(!body_matches(&merged, &serde_json::json!({ "e": [] })));
+    }
+
+    /// Run every scenario in `scenarios/`, reporting all failures together
+    #[tokio::test]
+    async fn test_scenarios() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(SCENARIO_DIR);
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|x| x == "yaml" || x == "yml"))
+            .collect();
+        files.sort();
+        assert!(!files.is_empty(), "no scenarios in {}", dir.display());
+
+        let mut failures = Vec::new();
+        for path in &files {
+            let file = path.file_name().unwrap().to_string_lossy();
+            let scenario = fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|yaml| serde_yaml::from_str::<Scenario>(&yaml).map_err(|e| e.to_string()));
+            let outcome = match &scenario {
+                Ok(scenario) => run(scenario).await.map_err(|e| format!("{file} ({}): {e}", scenario.name)),
+                Err(e) => Err(format!("{file}: {e}")),
+            };
+            if let Err(e) = outcome {
+                failures.push(e);
+            }
+        }
+        assert!(failures.is_empty(), "{} of {} scenarios failed:\n{}", failures.len(), files.len(), failures.join("\n"));
+    }
+}
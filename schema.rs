@@ -0,0 +1,390 @@
+//! Message schemas for structured protocols
+//!
+//! Many novel languages are really structured JSON payloads. A protocol can
+//! register a JSON Schema as its `message_schema`; every novel-language send
+//! under it must then be a JSON document the schema accepts. A message that is
+//! not is a vocabulary breach: it is refused with `schema_violation` and
+//! counts as a violation against the sender, like an undeclared protocol.
+//!
+//! The schema is compiled at registration, so an unusable one is refused
+//! there rather than on the first send. Supported keywords:
+//!
+//! - `type` (a name or a list of names), `enum`, `const`
+//! - objects: `properties`, `required`, `additionalProperties` (a boolean or
+//!   a schema)
+//! - arrays: `items`, `minItems`, `maxItems`
+//! - strings: `minLength`, `maxLength`, `pattern`
+//! - numbers: `minimum`, `maximum`
+//!
+//! `$schema`, `$id`, `title`, `description`, `examples`, and `$comment` are
+//! accepted and ignored. Any other keyword is refused at registration, so a
+//! schema never silently checks less than its author intended.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// Validation errors reported per message; the rest are summarised
+const MAX_ERRORS: usize = 5;
+
+/// Keywords that annotate a schema without constraining it
+const ANNOTATIONS: [&str; 6] = ["$schema", "$id", "title", "description", "examples", "$comment"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JsonType {
+    Object,
+    Array,
+    String,
+    Number,
+    Integer,
+    Boolean,
+    Null,
+}
+
+impl JsonType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "object" => Self::Object,
+            "array" => Self::Array,
+            "string" => Self::String,
+            "number" => Self::Number,
+            "integer" => Self::Integer,
+            "boolean" => Self::Boolean,
+            "null" => Self::Null,
+            _ => return None,
+        })
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Object => "object",
+            Self::Array => "array",
+            Self::String => "string",
+            Self::Number => "number",
+            Self::Integer => "integer",
+            Self::Boolean => "boolean",
+            Self::Null => "null",
+        }
+    }
+
+    fn matches(self, value: &Value) -> bool {
+        match (self, value) {
+            (Self::Object, Value::Object(_))
+            | (Self::Array, Value::Array(_))
+            | (Self::String, Value::String(_))
+            | (Self::Number, Value::Number(_))
+            | (Self::Boolean, Value::Bool(_))
+            | (Self::Null, Value::Null) => true,
+            (Self::Integer, Value::Number(n)) => n.is_i64() || n.is_u64() || n.as_f64().is_some_and(|f| f.fract() == 0.0),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+enum Additional {
+    #[default]
+    Allowed,
+    Forbidden,
+    Schema(Box<Node>),
+}
+
+/// One compiled (sub)schema
+#[derive(Debug, Clone, Default)]
+struct Node {
+    /// Accepted types; any when empty
+    types: Vec<JsonType>,
+    allowed: Option<Vec<Value>>,
+    properties: BTreeMap<String, Node>,
+    required: Vec<String>,
+    additional: Additional,
+    items: Option<Box<Node>>,
+    min_items: Option<usize>,
+    max_items: Option<usize>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    pattern: Option<Regex>,
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+}
+
+fn count(value: &Value, at: &str) -> Result<usize, String> {
+    value
+        .as_u64()
+        .map(|n| n as usize)
+        .ok_or_else(|| format!("{at} must be a non-negative integer"))
+}
+
+fn number(value: &Value, at: &str) -> Result<f64, String> {
+    value.as_f64().ok_or_else(|| format!("{at} must be a number"))
+}
+
+impl Node {
+    fn compile(schema: &Value, at: &str) -> Result<Self, String> {
+        let Value::Object(schema) = schema else {
+            return Err(format!("{at} must be an object"));
+        };
+        let mut node = Self::default();
+        for (keyword, value) in schema {
+            let here = format!("{at}/{keyword}");
+            match keyword.as_str() {
+                "type" => {
+                    let names: Vec<&Value> = match value {
+                        Value::Array(names) => names.iter().collect(),
+                        name => vec![name],
+                    };
+                    for name in names {
+                        let ty = name
+                            .as_str()
+                            .and_then(JsonType::parse)
+                            .ok_or_else(|| format!("{here}: unknown type {name}"))?;
+                        node.types.push(ty);
+                    }
+                }
+                "enum" => {
+                    let Value::Array(values) = value else {
+                        return Err(format!("{here} must be an array"));
+                    };
+                    node.allowed = Some(values.clone());
+                }
+                "const" => node.allowed = Some(vec![value.clone()]),
+                "properties" => {
+                    let Value::Object(properties) = value else {
+                        return Err(format!("{here} must be an object"));
+                    };
+                    for (name, property) in properties {
+                        node.properties.insert(name.clone(), Self::compile(property, &format!("{here}/{name}"))?);
+                    }
+                }
+                "required" => {
+                    node.required = value
+                        .as_array()
+                        .and_then(|names| names.iter().map(|n| n.as_str().map(str::to_string)).collect())
+                        .ok_or_else(|| format!("{here} must be an array of property names"))?;
+                }
+                "additionalProperties" => {
+                    node.additional = match value {
+                        Value::Bool(true) => Additional::Allowed,
+                        Value::Bool(false) => Additional::Forbidden,
+                        schema => Additional::Schema(Box::new(Self::compile(schema, &here)?)),
+                    };
+                }
+                "items" => node.items = Some(Box::new(Self::compile(value, &here)?)),
+                "minItems" => node.min_items = Some(count(value, &here)?),
+                "maxItems" => node.max_items = Some(count(value, &here)?),
+                "minLength" => node.min_length = Some(count(value, &here)?),
+                "maxLength" => node.max_length = Some(count(value, &here)?),
+                "pattern" => {
+                    let pattern = value.as_str().ok_or_else(|| format!("{here} must be a string"))?;
+                    node.pattern = Some(Regex::new(pattern).map_err(|e| format!("{here}: {e}"))?);
+                }
+                "minimum" => node.minimum = Some(number(value, &here)?),
+                "maximum" => node.maximum = Some(number(value, &here)?),
+                k if ANNOTATIONS.contains(&k) => {}
+                _ => return Err(format!("{here}: unsupported keyword")),
+            }
+        }
+        Ok(node)
+    }
+
+    fn validate(&self, value: &Value, at: &str, errors: &mut Vec<String>) {
+        if !self.types.is_empty() && !self.types.iter().any(|t| t.matches(value)) {
+            let expected: Vec<&str> = self.types.iter().map(|t| t.as_str()).collect();
+            errors.push(format!("{}: expected {}", pointer(at), expected.join(" or ")));
+            return;
+        }
+        if let Some(allowed) = &self.allowed {
+            if !allowed.contains(value) {
+                errors.push(format!("{}: not one of the allowed values", pointer(at)));
+            }
+        }
+        match value {
+            Value::Object(fields) => self.validate_object(fields, at, errors),
+            Value::Array(items) => {
+                if self.min_items.is_some_and(|min| items.len() < min) || self.max_items.is_some_and(|max| items.len() > max) {
+                    errors.push(format!("{}: {} items out of bounds", pointer(at), items.len()));
+                }
+                if let Some(schema) = &self.items {
+                    for (i, item) in items.iter().enumerate() {
+                        schema.validate(item, &format!("{at}/{i}"), errors);
+                    }
+                }
+            }
+            Value::String(s) => {
+                let len = s.chars().count();
+                if self.min_length.is_some_and(|min| len < min) || self.max_length.is_some_and(|max| len > max) {
+                    errors.push(format!("{}: length {len} out of bounds", pointer(at)));
+                }
+                if self.pattern.as_ref().is_some_and(|p| !p.is_match(s)) {
+                    errors.push(format!("{}: does not match pattern", pointer(at)));
+                }
+            }
+            Value::Number(n) => {
+                let n = n.as_f64().unwrap_or_default();
+                if self.minimum.is_some_and(|min| n < min) || self.maximum.is_some_and(|max| n > max) {
+                    errors.push(format!("{}: out of range", pointer(at)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn validate_object(&self, fields: &Map<String, Value>, at: &str, errors: &mut Vec<String>) {
+        for name in &self.required {
+            if !fields.contains_key(name) {
+                errors.push(format!("{}: missing required property {name}", pointer(at)));
+            }
+        }
+        for (name, field) in fields {
+            let here = format!("{at}/{name}");
+            match (self.properties.get(name), &self.additional) {
+                (Some(schema), _) => schema.validate(field, &here, errors),
+                (None, Additional::Schema(schema)) => schema.validate(field, &here, errors),
+                (None, Additional::Forbidden) => errors.push(format!("{}: unexpected property", pointer(&here))),
+                (None, Additional::Allowed) => {}
+            }
+        }
+    }
+}
+
+/// JSON pointer for error messages; `/` for the document itself
+fn pointer(at: &str) -> &str {
+    if at.is_empty() {
+        "/"
+    } else {
+        at
+    }
+}
+
+/// A registered protocol's message schema, compiled
+///
+/// Serializes as the schema document it was compiled from.
+#[derive(Debug, Clone)]
+pub struct MessageSchema {
+    source: Value,
+    root: Node,
+}
+
+impl MessageSchema {
+    pub fn compile(source: Value) -> Result<Self, String> {
+        let root = Node::compile(&source, "message_schema")?;
+        Ok(Self { source, root })
+    }
+
+    /// Check one message; the errors say where and why it does not conform
+    pub fn check(&self, content: &str) -> Result<(), Vec<String>> {
+        let value: Value = serde_json::from_str(content).map_err(|_| vec!["/: message is not JSON".to_string()])?;
+        let mut errors = Vec::new();
+        self.root.validate(&value, "", &mut errors);
+        if errors.is_empty() {
+            return Ok(());
+        }
+        if errors.len() > MAX_ERRORS {
+            let more = errors.len() - MAX_ERRORS;
+            errors.truncate(MAX_ERRORS);
+            errors.push(format!("and {more} more"));
+        }
+        Err(errors)
+    }
+}
+
+/// Schemas are equal when compiled from the same document
+impl PartialEq for MessageSchema {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Serialize for MessageSchema {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.source.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MessageSchema {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::compile(Value::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Schema validation counts in protocol analytics
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SchemaStats {
+    pub passed: u64,
+    pub failed: u64,
+    pub pass_rate: f64,
+}
+
+impl SchemaStats {
+    /// `None` until a message was validated
+    pub fn new(passed: u64, failed: u64) -> Option<Self> {
+        let total = passed + failed;
+        (total > 0).then(|| Self {
+            passed,
+            failed,
+            pass_rate: passed as f64 / total as f64,
+        })
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn shipment() -> MessageSchema {
+        MessageSchema::compile(json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "type": "object",
+            "required": ["op", "eta"],
+            "additionalProperties": false,
+            "properties": {
+                "op": {"enum": ["SHP", "RCV"]},
+                "eta": {"type": "integer", "minimum": 0},
+                "dock": {"type": "string", "pattern": "^D[0-9]+$"},
+                "items": {"type": "array", "maxItems": 2, "items": {"type": ["string", "null"]}}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_conforming_and_breaching_messages() {
+        let schema = shipment();
+        assert!(schema.check(r#"{"op": "SHP", "eta": 7, "dock": "D4", "items": ["a", null]}"#).is_ok());
+
+        let errors = schema.check(r#"{"op": "FLY", "eta": -1.5, "dock": "X", "items": [1, 2, 3], "z": 0}"#).unwrap_err();
+        assert_eq!(errors.len(), MAX_ERRORS + 1);
+        assert_eq!(errors[..2], ["/dock: does not match pattern", "/eta: expected integer"]);
+        assert_eq!(errors.last().unwrap(), "and 3 more");
+
+        assert_eq!(schema.check(r#"{"op": "SHP"}"#).unwrap_err(), vec!["/: missing required property eta"]);
+        assert_eq!(schema.check("[1]").unwrap_err(), vec!["/: expected object"]);
+        assert_eq!(schema.check("SHP|eta=7").unwrap_err(), vec!["/: message is not JSON"]);
+    }
+
+    #[test]
+    fn test_unusable_schemas_are_refused() {
+        let err = MessageSchema::compile(json!({"type": "object", "oneOf": []})).unwrap_err();
+        assert_eq!(err, "message_schema/oneOf: unsupported keyword");
+        assert!(MessageSchema::compile(json!({"properties": {"a": {"type": "text"}}})).is_err());
+        assert!(MessageSchema::compile(json!({"pattern": "("})).is_err());
+        assert!(MessageSchema::compile(json!("object")).is_err());
+
+        // Round-trips as the document it was compiled from
+        let schema = shipment();
+        let again: MessageSchema = serde_json::from_value(serde_json::to_value(&schema).unwrap()).unwrap();
+        assert_eq!(again.source, schema.source);
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(SchemaStats::new(0, 0), None);
+        assert_eq!(SchemaStats::new(3, 1).unwrap().pass_rate, 0.75);
+    }
+}
@@ -0,0 +1,363 @@
+//! Scrubbing of message content from diagnostic logs
+//!
+//! Console logs are for operating the gateway, not for reading agent traffic,
+//! and they end up in log pipelines with far wider access than the audit
+//! trail. Every log line is therefore written by [`ScrubbedJson`], which
+//! replaces the value of any field that carries message content (`content`,
+//! `english_summary`, `notes`, ..., see [`is_content_field`]) with its length
+//! and a SHA-256 prefix, in event and span fields alike:
+//!
+//! ```text
+//! "content": "[scrubbed len=21 sha256=5d1c0e7a93b2]"
+//! ```
+//!
+//! This holds for any log point, including debugging code added later, so
+//! logging a request body cannot leak what agents said. The hash still lets
+//! an operator match a line to a known message. The audit trail is captured
+//! separately by [`AuditLayer`](crate::audit::AuditLayer) and is unaffected.
+//!
+//! For incident response an admin can switch on verbose content logging with
+//! `PUT /admin/logging`. The switch needs a reason, turns itself off after
+//! `duration_sec` (at most [`MAX_VERBOSE_SEC`]), and every change is an audit
+//! event. It is off at startup and cannot be enabled from the environment.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    fmt,
+    sync::{Arc, RwLock},
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Subscriber,
+};
+use tracing_subscriber::{
+    fmt::{
+        format::Writer,
+        time::{FormatTime, SystemTime},
+        FmtContext, FormatEvent, FormatFields, FormattedFields,
+    },
+    field::RecordFields,
+    registry::LookupSpan,
+};
+
+use crate::{now_unix_sec, signing::content_digest};
+
+/// Default length of a verbose-logging window
+pub const DEFAULT_VERBOSE_SEC: u64 = 3_600;
+
+/// Longest verbose-logging window
+pub const MAX_VERBOSE_SEC: u64 = 86_400;
+
+/// Hex digits of the content hash kept in a scrubbed value
+const HASH_PREFIX: usize = 12;
+
+/// Field names that carry message or report content
+const CONTENT_FIELDS: [&str; 7] = ["content", "english_summary", "notes", "summary", "gloss", "body", "payload"];
+
+/// Whether the field `name` carries content to scrub
+pub fn is_content_field(name: &str) -> bool {
+    CONTENT_FIELDS.contains(&name) || name.ends_with("_content")
+}
+
+/// Length and hash standing in for scrubbed content
+pub fn scrubbed(value: &str) -> String {
+    let digest = content_digest(value);
+    format!("[scrubbed len={} sha256={}]", value.len(), &digest[..HASH_PREFIX])
+}
+
+/// Verbose content logging, as set through `PUT /admin/logging`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ContentLogging {
+    pub verbose_content: bool,
+    /// Why content is logged; required to enable it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// How long verbose logging stays on, in seconds; request only
+    #[serde(default, skip_serializing)]
+    pub duration_sec: Option<u64>,
+    /// When verbose logging turns itself off; set by the gateway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+}
+
+/// Runtime switch deciding whether log lines keep message content
+#[derive(Debug, Default)]
+pub struct LogScrubber {
+    config: RwLock<ContentLogging>,
+}
+
+impl LogScrubber {
+    /// The switch as of `now`, reporting an expired window as off
+    pub fn config(&self, now: u64) -> ContentLogging {
+        let config = self.config.read().unwrap().clone();
+        if config.until.is_some_and(|until| now >= until) {
+            return ContentLogging::default();
+        }
+        config
+    }
+
+    /// Replace the switch; enabling requires a reason and opens a window of
+    /// `duration_sec` from `now`
+    pub fn set_config(&self, mut config: ContentLogging, now: u64) -> Result<ContentLogging, String> {
+        if config.verbose_content {
+            if config.reason.as_deref().is_none_or(|r| r.trim().is_empty()) {
+                return Err("reason is required to enable verbose content logging".to_string());
+            }
+            let duration = config.duration_sec.unwrap_or(DEFAULT_VERBOSE_SEC);
+            if duration == 0 || duration > MAX_VERBOSE_SEC {
+                return Err(format!("duration_sec must be within 1-{MAX_VERBOSE_SEC}"));
+            }
+            config.until = Some(now + duration);
+        } else {
+            config = ContentLogging::default();
+        }
+        config.duration_sec = None;
+        *self.config.write().unwrap() = config.clone();
+        Ok(config)
+    }
+
+    /// Whether content is currently logged verbatim
+    pub fn verbose(&self, now: u64) -> bool {
+        let config = self.config.read().unwrap();
+        config.verbose_content && config.until.is_none_or(|until| now < until)
+    }
+}
+
+/// Field visitor collecting into a JSON object, scrubbing content fields
+struct FieldMap {
+    fields: Map<String, Value>,
+    verbose: bool,
+}
+
+impl FieldMap {
+    fn new(verbose: bool) -> Self {
+        Self {
+            fields: Map::new(),
+            verbose,
+        }
+    }
+
+    fn insert(&mut self, field: &Field, value: Value) {
+        self.fields.insert(field.name().to_string(), value);
+    }
+
+    fn insert_text(&mut self, field: &Field, text: String) {
+        let text = if self.verbose || !is_content_field(field.name()) {
+            text
+        } else {
+            scrubbed(&text)
+        };
+        self.insert(field, Value::from(text));
+    }
+}
+
+impl Visit for FieldMap {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert_text(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert_text(field, format!("{value:?}"));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, Value::from(value));
+    }
+}
+
+/// Span field formatter producing scrubbed JSON objects
+pub struct ScrubbedFields {
+    scrubber: Arc<LogScrubber>,
+}
+
+impl<'writer> FormatFields<'writer> for ScrubbedFields {
+    fn format_fields<R: RecordFields>(&self, mut writer: Writer<'writer>, fields: R) -> fmt::Result {
+        let mut visitor = FieldMap::new(self.scrubber.verbose(now_unix_sec()));
+        fields.record(&mut visitor);
+        write!(writer, "{}", Value::Object(visitor.fields))
+    }
+
+    fn add_fields(&self, current: &'writer mut FormattedFields<Self>, fields: &span::Record<'_>) -> fmt::Result {
+        let mut visitor = FieldMap::new(self.scrubber.verbose(now_unix_sec()));
+        visitor.fields = serde_json::from_str(&current.fields).unwrap_or_default();
+        fields.record(&mut visitor);
+        current.fields = Value::Object(visitor.fields).to_string();
+        Ok(())
+    }
+}
+
+/// JSON log lines in the shape of `tracing_subscriber`'s JSON format, with
+/// content fields scrubbed
+pub struct ScrubbedJson {
+    scrubber: Arc<LogScrubber>,
+}
+
+impl ScrubbedJson {
+    /// Event and span formatters sharing `scrubber`
+    pub fn new(scrubber: Arc<LogScrubber>) -> (Self, ScrubbedFields) {
+        (
+            Self {
+                scrubber: scrubber.clone(),
+            },
+            ScrubbedFields { scrubber },
+        )
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for ScrubbedJson
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        let mut fields = FieldMap::new(self.scrubber.verbose(now_unix_sec()));
+        event.record(&mut fields);
+
+        let mut line = Map::new();
+        line.insert("timestamp".into(), Value::from(timestamp));
+        line.insert("level".into(), Value::from(event.metadata().level().as_str()));
+        line.insert("fields".into(), Value::Object(fields.fields));
+        line.insert("target".into(), Value::from(event.metadata().target()));
+        if let Some(scope) = ctx.event_scope() {
+            let spans: Vec<Value> = scope
+                .from_root()
+                .map(|span| {
+                    let mut fields: Map<String, Value> = span
+                        .extensions()
+                        .get::<FormattedFields<N>>()
+                        .and_then(|f| serde_json::from_str(&f.fields).ok())
+                        .unwrap_or_default();
+                    fields.insert("name".into(), Value::from(span.name()));
+                    Value::Object(fields)
+                })
+                .collect();
+            if let Some(current) = spans.last() {
+                line.insert("span".into(), current.clone());
+            }
+            line.insert("spans".into(), Value::Array(spans));
+        }
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io, sync::Mutex};
+    use tracing_subscriber::{fmt::MakeWriter, prelude::*};
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self {
+            self.clone()
+        }
+    }
+
+    fn lines(captured: &Captured) -> Vec<Value> {
+        let bytes = captured.0.lock().unwrap().clone();
+        String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_content_fields_are_scrubbed_unless_verbose() {
+        let scrubber = Arc::new(LogScrubber::default());
+        let (format, fields) = ScrubbedJson::new(scrubber.clone());
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .event_format(format)
+                .fmt_fields(fields)
+                .with_writer(captured.clone()),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", body = "secret body", route = "/send");
+            let _entered = span.enter();
+            tracing::info!(from = "a", content = "SHP|q=7f", count = 3, "Debugging a send");
+
+            let now = now_unix_sec();
+            let enabled = ContentLogging {
+                verbose_content: true,
+                reason: Some("INC-12".into()),
+                duration_sec: Some(60),
+                until: None,
+            };
+            scrubber.set_config(enabled, now).unwrap();
+            tracing::info!(content = "SHP|q=7f", "Investigating");
+        });
+
+        let lines = lines(&captured);
+        assert_eq!(lines[0]["fields"]["content"], scrubbed("SHP|q=7f"));
+        assert!(lines[0]["fields"]["content"].as_str().unwrap().starts_with("[scrubbed len=8 sha256="));
+        assert_eq!(lines[0]["fields"]["from"], "a");
+        assert_eq!(lines[0]["fields"]["count"], 3);
+        assert_eq!(lines[0]["fields"]["message"], "Debugging a send");
+        assert_eq!(lines[0]["span"]["body"], scrubbed("secret body"));
+        assert_eq!(lines[0]["span"]["route"], "/send");
+        assert_eq!(lines[0]["spans"][0]["name"], "request");
+        assert_eq!(lines[1]["fields"]["content"], "SHP|q=7f");
+    }
+
+    #[test]
+    fn test_verbose_switch_needs_reason_and_expires() {
+        let scrubber = LogScrubber::default();
+        assert!(!scrubber.verbose(100));
+        let mut config = ContentLogging {
+            verbose_content: true,
+            ..ContentLogging::default()
+        };
+        assert!(scrubber.set_config(config.clone(), 100).is_err());
+        config.reason = Some("INC-12".into());
+        config.duration_sec = Some(MAX_VERBOSE_SEC + 1);
+        assert!(scrubber.set_config(config.clone(), 100).is_err());
+
+        config.duration_sec = None;
+        let set = scrubber.set_config(config, 100).unwrap();
+        assert_eq!(set.until, Some(100 + DEFAULT_VERBOSE_SEC));
+        assert!(scrubber.verbose(100));
+        assert!(!scrubber.verbose(100 + DEFAULT_VERBOSE_SEC));
+        assert_eq!(scrubber.config(100 + DEFAULT_VERBOSE_SEC), ContentLogging::default());
+
+        scrubber.set_config(ContentLogging::default(), 200).unwrap();
+        assert!(!scrubber.verbose(200));
+        assert!(is_content_field("encrypted_content") && !is_content_field("content_sha256"));
+    }
+}
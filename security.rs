@@ -0,0 +1,211 @@
+//! CORS and security-header configuration
+//!
+//! The default is locked down: no cross-origin access and the standard
+//! security headers on every response. Cross-origin callers are enabled by
+//! listing them in `CORS_ALLOWED_ORIGINS`. Passing `--dev` on the command line
+//! restores the permissive development setup (any origin, no HSTS).
+
+use axum::{
+    http::{header, HeaderName, HeaderValue, Method},
+    Router,
+};
+use std::{env, time::Duration};
+use tower_http::{
+    cors::{AllowOrigin, Any, CorsLayer},
+    set_header::SetResponseHeaderLayer,
+};
+
+use crate::env_parse;
+
+/// HTTP-level security settings
+#[derive(Debug, Clone)]
+pub struct SecurityConfig {
+    /// Permissive development mode (`--dev`)
+    pub dev: bool,
+    /// Origins allowed to make cross-origin requests
+    pub allowed_origins: Vec<HeaderValue>,
+    /// Whether cross-origin requests may carry credentials
+    pub allow_credentials: bool,
+    /// How long browsers may cache preflight results
+    pub max_age: Duration,
+    /// `Strict-Transport-Security` max-age; zero disables the header
+    pub hsts_max_age: Duration,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            dev: false,
+            allowed_origins: Vec::new(),
+            allow_credentials: false,
+            max_age: Duration::from_secs(600),
+            hsts_max_age: Duration::from_secs(365 * 24 * 60 * 60),
+        }
+    }
+}
+
+impl SecurityConfig {
+    /// Load from `CORS_*`/`HSTS_*` environment variables and the `--dev` flag
+    pub fn from_env_and_args() -> Result<Self, String> {
+        let mut cfg = Self {
+            dev: env::args().skip(1).any(|a| a == "--dev"),
+            ..Self::default()
+        };
+        if let Ok(origins) = env::var("CORS_ALLOWED_ORIGINS") {
+            cfg.allowed_origins = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|o| !o.is_empty())
+                .map(|o| HeaderValue::from_str(o).map_err(|_| invalid_origin(o)))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Ok(v) = env::var("CORS_ALLOW_CREDENTIALS") {
+            cfg.allow_credentials = matches!(v.trim(), "1" | "true" | "yes");
+        }
+        if let Some(secs) = env_parse("CORS_MAX_AGE_SEC") {
+            cfg.max_age = Duration::from_secs(secs);
+        }
+        if let Some(secs) = env_parse("HSTS_MAX_AGE_SEC") {
+            cfg.hsts_max_age = Duration::from_secs(secs);
+        }
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    /// Refuse origin lists the CORS layer cannot be built from
+    fn validate(&self) -> Result<(), String> {
+        if self.dev {
+            return Ok(());
+        }
+        if let Some(origin) = self.allowed_origins.iter().find(|o| *o != "*" && !is_origin(o)) {
+            return Err(invalid_origin(&String::from_utf8_lossy(origin.as_bytes())));
+        }
+        if !self.allowed_origins.iter().any(|o| o == "*") {
+            return Ok(());
+        }
+        if self.allow_credentials {
+            return Err("CORS_ALLOWED_ORIGINS cannot contain `*` with CORS_ALLOW_CREDENTIALS set".to_string());
+        }
+        Err("CORS_ALLOWED_ORIGINS must list origins, not `*`; run with --dev to allow any origin".to_string())
+    }
+
+    /// CORS layer for this configuration
+    pub fn cors_layer(&self) -> CorsLayer {
+        if self.dev {
+            return CorsLayer::new()
+                .allow_origin(Any)
+                .allow_methods(Any)
+                .allow_headers(Any);
+        }
+        CorsLayer::new()
+            .allow_origin(AllowOrigin::list(self.allowed_origins.clone()))
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+            .allow_headers([header::CONTENT_TYPE, header::ACCEPT, header::AUTHORIZATION])
+            .allow_credentials(self.allow_credentials)
+            .max_age(self.max_age)
+    }
+
+    /// Headers added to every response unless a handler already set them
+    pub fn security_headers(&self) -> Vec<(HeaderName, HeaderValue)> {
+        let mut headers = vec![
+            (
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            ),
+            (header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY")),
+            (
+                header::REFERRER_POLICY,
+                HeaderValue::from_static("no-referrer"),
+            ),
+        ];
+        if !self.dev && !self.hsts_max_age.is_zero() {
+            let hsts = format!("max-age={}; includeSubDomains", self.hsts_max_age.as_secs());
+            if let Ok(v) = HeaderValue::from_str(&hsts) {
+                headers.push((header::STRICT_TRANSPORT_SECURITY, v));
+            }
+        }
+        headers
+    }
+
+    /// Wrap `router` with the CORS and security-header layers
+    pub fn apply<S: Clone + Send + Sync + 'static>(&self, router: Router<S>) -> Router<S> {
+        let mut router = router;
+        for (name, value) in self.security_headers() {
+            router = router.layer(SetResponseHeaderLayer::if_not_present(name, value));
+        }
+        router.layer(self.cors_layer())
+    }
+}
+
+/// Whether `value` is a serialized origin: `scheme://host[:port]`, nothing
+/// more, as browsers send it in `Origin`
+fn is_origin(value: &HeaderValue) -> bool {
+    let Ok(value) = value.to_str() else {
+        return false;
+    };
+    reqwest::Url::parse(value)
+        .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.origin().ascii_serialization() == value)
+}
+
+fn invalid_origin(origin: &str) -> String {
+    format!("CORS_ALLOWED_ORIGINS entry {origin:?} is not an origin like https://app.example.com")
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hsts_only_outside_dev() {
+        let prod = SecurityConfig::default();
+        assert!(prod
+            .security_headers()
+            .iter()
+            .any(|(n, _)| n == header::STRICT_TRANSPORT_SECURITY));
+
+        let dev = SecurityConfig {
+            dev: true,
+            ..SecurityConfig::default()
+        };
+        let names: Vec<_> = dev.security_headers().into_iter().map(|(n, _)| n).collect();
+        assert!(names.contains(&header::X_CONTENT_TYPE_OPTIONS));
+        assert!(!names.contains(&header::STRICT_TRANSPORT_SECURITY));
+    }
+
+    #[test]
+    fn test_wildcard_origin_refused() {
+        let mut cfg = SecurityConfig {
+            allowed_origins: vec![HeaderValue::from_static("https://a.example"), HeaderValue::from_static("*")],
+            ..SecurityConfig::default()
+        };
+        assert!(cfg.validate().unwrap_err().contains("--dev"));
+        cfg.allow_credentials = true;
+        assert!(cfg.validate().unwrap_err().contains("CORS_ALLOW_CREDENTIALS"));
+
+        cfg.allowed_origins.pop();
+        assert!(cfg.validate().is_ok());
+        let _ = cfg.apply(Router::<()>::new());
+    }
+
+    #[test]
+    fn test_invalid_origin_refused() {
+        for origin in ["https://a.example", "http://localhost:3000"] {
+            let cfg = SecurityConfig {
+                allowed_origins: vec![HeaderValue::from_static(origin)],
+                ..SecurityConfig::default()
+            };
+            assert!(cfg.validate().is_ok(), "{origin}");
+        }
+        for origin in ["a.example", "https://a.example/", "https://a.example/app", "ftp://a.example"] {
+            let cfg = SecurityConfig {
+                allowed_origins: vec![HeaderValue::from_static("https://b.example"), HeaderValue::from_static(origin)],
+                ..SecurityConfig::default()
+            };
+            assert!(cfg.validate().unwrap_err().contains(origin), "{origin}");
+        }
+    }
+}
@@ -0,0 +1,514 @@
+//! Signed receipts and signing-key rotation
+//!
+//! Accepted sends and reports carry a `receipt`: a compact JWS (`alg` EdDSA)
+//! whose header names the signing key by `kid`. Verifiers fetch public keys
+//! from `GET /.well-known/jwks.json`, or post a receipt to `/receipts/verify`.
+//!
+//! The key ring holds three kinds of key:
+//!
+//! - **active**: signs new receipts
+//! - **next**: published ahead of use so verifiers that cache the JWKS already
+//!   know it when it takes over
+//! - **retired**: no longer signs, but stays published for
+//!   `SIGNING_KEY_RETENTION_SEC` so older receipts remain verifiable
+//!
+//! Every `SIGNING_ROTATE_SEC` (or on `POST /admin/keys/rotate`) the active key
+//! retires, the next key becomes active, and a new next key is generated.
+//!
+//! Generated keys live only in memory. To keep receipts verifiable across
+//! restarts, and on a promoted standby, set `SIGNING_KEYS` to
+//! `kid:seed,...` on every gateway, with each seed a base64url 32-byte
+//! Ed25519 seed. The first listed key signs; the rest are retired keys kept
+//! for verification and stay published for as long as they are listed.
+//!
+//! Rotating would replace configured keys with generated ones that no other
+//! gateway, and no restart, knows, so with `SIGNING_KEYS` set there is no
+//! next key, scheduled rotation is off, and `POST /admin/keys/rotate` is
+//! refused. Rotate by putting a new key first in `SIGNING_KEYS` on every
+//! gateway.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as B64, Engine};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+};
+use tracing::warn;
+
+use crate::env_parse;
+
+/// JWS algorithm of every receipt
+pub const RECEIPT_ALG: &str = "EdDSA";
+
+/// Key-ring settings
+#[derive(Debug, Clone)]
+pub struct SigningConfig {
+    /// Keys from `SIGNING_KEYS`: (kid, seed), signing key first
+    pub keys: Vec<(String, [u8; 32])>,
+    /// Active key lifetime; 0 disables scheduled rotation, as do configured
+    /// keys
+    pub rotate_sec: u64,
+    /// How long a retired key stays published
+    pub retain_sec: u64,
+}
+
+impl Default for SigningConfig {
+    fn default() -> Self {
+        Self {
+            keys: Vec::new(),
+            rotate_sec: 86_400,
+            retain_sec: 30 * 86_400,
+        }
+    }
+}
+
+impl SigningConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let parse = |name: &str| env_parse::<u64>(name);
+        let keys = env::var("SIGNING_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .filter_map(|entry| {
+                let parsed = entry.trim().split_once(':').and_then(|(kid, seed)| {
+                    let seed: [u8; 32] = B64.decode(seed.trim()).ok()?.try_into().ok()?;
+                    (!kid.is_empty()).then(|| (kid.to_string(), seed))
+                });
+                if parsed.is_none() {
+                    warn!(event = "config_invalid", "Ignoring SIGNING_KEYS entry: expected kid:<base64url 32-byte seed>");
+                }
+                parsed
+            })
+            .collect::<Vec<_>>();
+        let rotate_sec = match parse("SIGNING_ROTATE_SEC") {
+            Some(sec) if sec > 0 && !keys.is_empty() => {
+                warn!(event = "config_invalid", "Ignoring SIGNING_ROTATE_SEC: keys from SIGNING_KEYS are rotated by changing SIGNING_KEYS");
+                0
+            }
+            _ if !keys.is_empty() => 0,
+            sec => sec.unwrap_or(defaults.rotate_sec),
+        };
+        Self {
+            keys,
+            rotate_sec,
+            retain_sec: parse("SIGNING_KEY_RETENTION_SEC").unwrap_or(defaults.retain_sec),
+        }
+    }
+}
+
+/// Lifecycle stage of a signing key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyStatus {
+    Next,
+    Active,
+    Retired,
+}
+
+#[derive(Debug)]
+struct KeyEntry {
+    kid: String,
+    key: SigningKey,
+    status: KeyStatus,
+    created_at: u64,
+    /// When the key started signing
+    activated_at: Option<u64>,
+    retired_at: Option<u64>,
+}
+
+/// Key metadata as listed by `/admin/keys`
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyInfo {
+    pub kid: String,
+    pub status: KeyStatus,
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub activated_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retired_at: Option<u64>,
+}
+
+/// Public key in JWK form (RFC 8037)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub x: String,
+    pub kid: String,
+    pub alg: String,
+    #[serde(rename = "use")]
+    pub use_: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JwsHeader {
+    alg: String,
+    kid: String,
+    typ: String,
+}
+
+/// A verified receipt
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifiedReceipt {
+    pub kid: String,
+    pub claims: serde_json::Value,
+}
+
+/// Hex-encoded SHA-256 of `content`, for receipts that attest to a message
+/// without repeating it
+pub fn content_digest(content: &str) -> String {
+    hex(&Sha256::digest(content.as_bytes()))
+}
+
+/// Lowercase hex of `bytes`, in a single allocation
+pub fn hex(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for &b in bytes {
+        out.push(char::from(DIGITS[usize::from(b >> 4)]));
+        out.push(char::from(DIGITS[usize::from(b & 0xf)]));
+    }
+    out
+}
+
+/// `N` bytes from the OS random number generator
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).expect("OS random number generator unavailable");
+    bytes
+}
+
+/// `N` random bytes as lowercase hex, for ids and tokens
+pub fn random_hex<const N: usize>() -> String {
+    hex(&random_bytes::<N>())
+}
+
+fn generate_key() -> SigningKey {
+    SigningKey::from_bytes(&random_bytes())
+}
+
+/// RFC 7638 thumbprint of an Ed25519 public key
+fn thumbprint(key: &VerifyingKey) -> String {
+    let canonical = format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, B64.encode(key.as_bytes()));
+    B64.encode(Sha256::digest(canonical.as_bytes()))
+}
+
+fn generated_entry(status: KeyStatus, now: u64) -> KeyEntry {
+    let key = generate_key();
+    KeyEntry {
+        kid: thumbprint(&key.verifying_key()),
+        key,
+        status,
+        created_at: now,
+        activated_at: (status == KeyStatus::Active).then_some(now),
+        retired_at: None,
+    }
+}
+
+/// Signing keys and their rotation schedule
+#[derive(Debug)]
+pub struct KeyRing {
+    config: SigningConfig,
+    keys: RwLock<Vec<KeyEntry>>,
+    pub rotations: AtomicU64,
+}
+
+impl Default for KeyRing {
+    fn default() -> Self {
+        Self::new(SigningConfig::default(), 0)
+    }
+}
+
+impl KeyRing {
+    pub fn new(mut config: SigningConfig, now: u64) -> Self {
+        if !config.keys.is_empty() {
+            config.rotate_sec = 0;
+        }
+        let mut keys: Vec<KeyEntry> = config
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(i, (kid, seed))| {
+                let status = if i == 0 { KeyStatus::Active } else { KeyStatus::Retired };
+                KeyEntry {
+                    kid: kid.clone(),
+                    key: SigningKey::from_bytes(seed),
+                    status,
+                    created_at: now,
+                    activated_at: (i == 0).then_some(now),
+                    retired_at: (i > 0).then_some(now),
+                }
+            })
+            .collect();
+        if keys.is_empty() {
+            keys.push(generated_entry(KeyStatus::Active, now));
+        }
+        if config.rotate_sec > 0 {
+            keys.push(generated_entry(KeyStatus::Next, now));
+        }
+        Self {
+            config,
+            keys: RwLock::new(keys),
+            rotations: AtomicU64::new(0),
+        }
+    }
+
+    pub fn config(&self) -> &SigningConfig {
+        &self.config
+    }
+
+    /// Kid of the key currently signing
+    pub fn active_kid(&self) -> String {
+        let keys = self.keys.read().unwrap();
+        keys.iter()
+            .find(|k| k.status == KeyStatus::Active)
+            .map(|k| k.kid.clone())
+            .unwrap_or_default()
+    }
+
+    /// Whether the active key has outlived `SIGNING_ROTATE_SEC`
+    pub fn rotation_due(&self, now: u64) -> bool {
+        self.config.rotate_sec > 0
+            && self
+                .keys
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|k| k.status == KeyStatus::Active)
+                .filter_map(|k| k.activated_at)
+                .all(|at| now.saturating_sub(at) >= self.config.rotate_sec)
+    }
+
+    /// Whether keys come from `SIGNING_KEYS` and so are never rotated here
+    pub fn is_configured(&self) -> bool {
+        !self.config.keys.is_empty()
+    }
+
+    /// Retire the active key, promote the next one, and publish a new next
+    /// key; retired keys past retention are dropped. Returns the new active
+    /// kid, or `None` when keys come from `SIGNING_KEYS`.
+    pub fn rotate(&self, now: u64) -> Option<String> {
+        if self.is_configured() {
+            return None;
+        }
+        let mut keys = self.keys.write().unwrap();
+        for k in keys.iter_mut().filter(|k| k.status == KeyStatus::Active) {
+            k.status = KeyStatus::Retired;
+            k.retired_at = Some(now);
+        }
+        match keys.iter_mut().find(|k| k.status == KeyStatus::Next) {
+            Some(next) => {
+                next.status = KeyStatus::Active;
+                next.activated_at = Some(now);
+            }
+            None => keys.push(generated_entry(KeyStatus::Active, now)),
+        }
+        keys.push(generated_entry(KeyStatus::Next, now));
+        let retain = self.config.retain_sec;
+        keys.retain(|k| k.retired_at.is_none_or(|at| now.saturating_sub(at) < retain));
+        self.rotations.fetch_add(1, Ordering::Relaxed);
+        keys.iter().find(|k| k.status == KeyStatus::Active).map(|k| k.kid.clone())
+    }
+
+    /// Sign `claims` as a compact JWS with the active key
+    pub fn sign<T: Serialize>(&self, claims: &T) -> String {
+        let keys = self.keys.read().unwrap();
+        let active = keys
+            .iter()
+            .find(|k| k.status == KeyStatus::Active)
+            .expect("key ring always has an active key");
+        let header = JwsHeader {
+            alg: RECEIPT_ALG.to_string(),
+            kid: active.kid.clone(),
+            typ: "JWT".to_string(),
+        };
+        let signing_input = format!(
+            "{}.{}",
+            B64.encode(serde_json::to_vec(&header).unwrap_or_default()),
+            B64.encode(serde_json::to_vec(claims).unwrap_or_default())
+        );
+        let signature = active.key.sign(signing_input.as_bytes());
+        format!("{signing_input}.{}", B64.encode(signature.to_bytes()))
+    }
+
+    /// Check a receipt against every published key
+    pub fn verify(&self, receipt: &str) -> Result<VerifiedReceipt, String> {
+        let mut parts = receipt.trim().split('.');
+        let (Some(header), Some(claims), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err("receipt is not a compact JWS".to_string());
+        };
+        let decoded: JwsHeader = B64
+            .decode(header)
+            .ok()
+            .and_then(|h| serde_json::from_slice(&h).ok())
+            .ok_or("malformed receipt header")?;
+        if decoded.alg != RECEIPT_ALG {
+            return Err(format!("unsupported algorithm {}", decoded.alg));
+        }
+        let signature: [u8; 64] = B64
+            .decode(signature)
+            .ok()
+            .and_then(|s| s.try_into().ok())
+            .ok_or("malformed receipt signature")?;
+        let keys = self.keys.read().unwrap();
+        let key = keys
+            .iter()
+            .find(|k| k.kid == decoded.kid)
+            .ok_or_else(|| format!("unknown or expired key {}", decoded.kid))?;
+        key.key
+            .verifying_key()
+            .verify(format!("{header}.{claims}").as_bytes(), &Signature::from_bytes(&signature))
+            .map_err(|_| "signature does not match".to_string())?;
+        let claims = B64
+            .decode(claims)
+            .ok()
+            .and_then(|c| serde_json::from_slice(&c).ok())
+            .ok_or("malformed receipt claims")?;
+        Ok(VerifiedReceipt { kid: decoded.kid, claims })
+    }
+
+    /// Every published public key: next, active, and retained
+    pub fn jwks(&self) -> Jwks {
+        let keys = self.keys.read().unwrap();
+        Jwks {
+            keys: keys
+                .iter()
+                .map(|k| Jwk {
+                    kty: "OKP".to_string(),
+                    crv: "Ed25519".to_string(),
+                    x: B64.encode(k.key.verifying_key().as_bytes()),
+                    kid: k.kid.clone(),
+                    alg: RECEIPT_ALG.to_string(),
+                    use_: "sig".to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn list(&self) -> Vec<KeyInfo> {
+        self.keys
+            .read()
+            .unwrap()
+            .iter()
+            .map(|k| KeyInfo {
+                kid: k.kid.clone(),
+                status: k.status,
+                created_at: k.created_at,
+                activated_at: k.activated_at,
+                retired_at: k.retired_at,
+            })
+            .collect()
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const T0: u64 = 1_700_000_000;
+
+    /// Ring rotating hourly and retaining retired keys for two hours
+    fn rotating_ring() -> KeyRing {
+        KeyRing::new(
+            SigningConfig {
+                rotate_sec: 3_600,
+                retain_sec: 7_200,
+                ..SigningConfig::default()
+            },
+            T0,
+        )
+    }
+
+    #[test]
+    fn test_receipt_verifies_with_its_claims() {
+        let ring = rotating_ring();
+        let verified = ring.verify(&ring.sign(&serde_json::json!({"sub": "agent-001", "iat": T0}))).unwrap();
+        assert_eq!(verified.kid, ring.active_kid());
+        assert_eq!(verified.claims["sub"], "agent-001");
+        assert_eq!(content_digest("abc").len(), 64);
+    }
+
+    #[test]
+    fn test_next_key_is_published_before_it_signs() {
+        let ring = rotating_ring();
+        let first = ring.active_kid();
+        let published: Vec<_> = ring.jwks().keys.into_iter().map(|k| k.kid).collect();
+        assert_eq!(published.len(), 2);
+        assert!(!ring.rotation_due(T0 + 3_599));
+        assert!(ring.rotation_due(T0 + 3_600));
+        let second = ring.rotate(T0 + 3_600).unwrap();
+        assert_eq!(second, published[1]);
+        assert_ne!(second, first);
+    }
+
+    #[test]
+    fn test_receipts_survive_rotation() {
+        let ring = rotating_ring();
+        let first = ring.active_kid();
+        let receipt = ring.sign(&1);
+        let second = ring.rotate(T0 + 3_600).unwrap();
+        assert_eq!(ring.verify(&receipt).unwrap().kid, first);
+        assert_eq!(ring.verify(&ring.sign(&1)).unwrap().kid, second);
+        assert_eq!(ring.jwks().keys.len(), 3);
+    }
+
+    #[test]
+    fn test_tampered_receipt_is_rejected() {
+        let ring = rotating_ring();
+        let receipt = ring.sign(&1);
+        let (signed, _) = receipt.rsplit_once('.').unwrap();
+        let forged = format!("{signed}.{}", B64.encode([0u8; 64]));
+        assert!(ring.verify(&forged).is_err());
+    }
+
+    #[test]
+    fn test_retired_key_is_unknown_after_retention() {
+        let ring = rotating_ring();
+        let receipt = ring.sign(&1);
+        ring.rotate(T0 + 3_600);
+        ring.rotate(T0 + 3_600 + 7_200);
+        assert!(ring.verify(&receipt).unwrap_err().contains("unknown or expired"));
+    }
+
+    #[test]
+    fn test_configured_keys_survive_restarts() {
+        let config = SigningConfig {
+            keys: vec![("k1".to_string(), [7u8; 32]), ("k0".to_string(), [6u8; 32])],
+            ..SigningConfig::default()
+        };
+        let ring = KeyRing::new(config.clone(), T0);
+        let signed = ring.sign(&1);
+        assert_eq!(KeyRing::new(config, T0 + 60).verify(&signed).unwrap().kid, "k1");
+    }
+
+    #[test]
+    fn test_configured_keys_are_never_rotated() {
+        let ring = KeyRing::new(
+            SigningConfig {
+                keys: vec![("k1".to_string(), [7u8; 32]), ("k0".to_string(), [6u8; 32])],
+                ..SigningConfig::default()
+            },
+            T0,
+        );
+        assert!(!ring.rotation_due(T0 + 10 * 86_400));
+        assert_eq!(ring.rotate(T0 + 10 * 86_400), None);
+        let published: Vec<_> = ring.jwks().keys.into_iter().map(|k| k.kid).collect();
+        assert_eq!(published, ["k1", "k0"], "no generated next key");
+    }
+}
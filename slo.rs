@@ -0,0 +1,325 @@
+//! Compliance SLOs with burn-rate alerting
+//!
+//! The SLI is the share of accepted novel-language messages that were covered
+//! by an accepted report within `SLO_REPORT_WITHIN_SEC` of being sent. A
+//! message counts once its outcome is known: good when covered in time, bad
+//! when covered late or still uncovered past the deadline. Messages still
+//! inside the deadline are pending and not counted.
+//!
+//! Tenants are owning teams (`unassigned` for agents without an owner). Each
+//! tenant has an objective (`SLO_OBJECTIVE`, overridable per team via
+//! `SLO_TENANT_OBJECTIVES=team:0.999,...`). Burn rate is the observed error
+//! rate divided by the error budget (`1 - objective`). An alert fires when
+//! both the long (`SLO_WINDOW_SEC`) and short (`SLO_FAST_WINDOW_SEC`) windows
+//! burn faster than `SLO_BURN_RATE_ALERT`, at most once per long window per
+//! tenant.
+
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    env,
+    sync::Mutex,
+};
+use tracing::warn;
+
+use crate::env_parse;
+
+/// Tenant for agents without an owning team
+pub const UNASSIGNED_TENANT: &str = "unassigned";
+
+/// Most messages tracked at once; the oldest are dropped beyond this
+const MAX_TRACKED: usize = 100_000;
+
+/// SLO targets and alerting thresholds
+#[derive(Debug, Clone)]
+pub struct SloConfig {
+    /// Default objective, e.g. 0.99
+    pub objective: f64,
+    /// Per-tenant objectives overriding the default
+    pub tenant_objectives: BTreeMap<String, f64>,
+    /// Deadline for a message to be covered by a report
+    pub report_within_sec: u64,
+    /// Long burn-rate window
+    pub window_sec: u64,
+    /// Short burn-rate window
+    pub fast_window_sec: u64,
+    /// Burn rate at which both windows must be burning to alert
+    pub burn_rate_alert: f64,
+}
+
+impl Default for SloConfig {
+    fn default() -> Self {
+        Self {
+            objective: 0.99,
+            tenant_objectives: BTreeMap::new(),
+            report_within_sec: 90,
+            window_sec: 3600,
+            fast_window_sec: 300,
+            burn_rate_alert: 14.4,
+        }
+    }
+}
+
+fn valid_objective(objective: f64) -> bool {
+    objective > 0.0 && objective < 1.0
+}
+
+impl SloConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let objective = match env_parse::<f64>("SLO_OBJECTIVE") {
+            Some(o) if valid_objective(o) => o,
+            Some(o) => {
+                warn!(event = "config_invalid", objective = o, "SLO_OBJECTIVE must be within (0, 1), using default");
+                defaults.objective
+            }
+            None => defaults.objective,
+        };
+        let tenant_objectives = env::var("SLO_TENANT_OBJECTIVES")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| entry.trim().split_once(':'))
+            .filter_map(|(tenant, o)| match o.trim().parse::<f64>() {
+                Ok(o) if valid_objective(o) && !tenant.is_empty() => Some((tenant.to_string(), o)),
+                _ => {
+                    warn!(event = "config_invalid", tenant = %tenant, "Ignoring invalid SLO_TENANT_OBJECTIVES entry");
+                    None
+                }
+            })
+            .collect();
+        Self {
+            objective,
+            tenant_objectives,
+            report_within_sec: env_parse("SLO_REPORT_WITHIN_SEC").unwrap_or(defaults.report_within_sec),
+            window_sec: env_parse("SLO_WINDOW_SEC").unwrap_or(defaults.window_sec).max(1),
+            fast_window_sec: env_parse("SLO_FAST_WINDOW_SEC").unwrap_or(defaults.fast_window_sec).max(1),
+            burn_rate_alert: env_parse("SLO_BURN_RATE_ALERT").unwrap_or(defaults.burn_rate_alert),
+        }
+    }
+
+    pub fn objective_for(&self, tenant: &str) -> f64 {
+        self.tenant_objectives.get(tenant).copied().unwrap_or(self.objective)
+    }
+}
+
+/// One accepted novel-language message awaiting or given report coverage
+#[derive(Debug, Clone)]
+struct Tracked {
+    sent_at: u64,
+    tenant: String,
+    /// `agent_id::name:version`
+    report_key: String,
+    covered_at: Option<u64>,
+}
+
+/// SLI over one window
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WindowSli {
+    pub window_sec: u64,
+    /// Messages with a known outcome
+    pub total: u64,
+    pub good: u64,
+    /// `good / total`; absent when nothing has resolved yet
+    pub sli: Option<f64>,
+    pub burn_rate: f64,
+}
+
+/// SLO status for one tenant
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantSlo {
+    pub tenant: String,
+    pub objective: f64,
+    pub report_within_sec: u64,
+    /// Messages still inside the coverage deadline
+    pub pending: u64,
+    pub long: WindowSli,
+    pub short: WindowSli,
+    /// Share of the long window's error budget left (negative once overspent)
+    pub error_budget_remaining: f64,
+    /// Both windows burning faster than the alert threshold
+    pub burning: bool,
+}
+
+/// Continuously updated SLI state for every tenant
+#[derive(Debug, Default)]
+pub struct SloTracker {
+    config: SloConfig,
+    messages: Mutex<VecDeque<Tracked>>,
+    /// Tenant -> time of its last burn-rate alert
+    last_alert: Mutex<HashMap<String, u64>>,
+}
+
+impl SloTracker {
+    pub fn new(config: SloConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn config(&self) -> &SloConfig {
+        &self.config
+    }
+
+    /// Record an accepted novel-language message
+    pub fn record_send(&self, tenant: &str, report_key: &str, now: u64) {
+        let horizon = self.config.window_sec + self.config.report_within_sec;
+        let mut messages = self.messages.lock().unwrap();
+        while messages
+            .front()
+            .is_some_and(|m| now.saturating_sub(m.sent_at) > horizon)
+            || messages.len() >= MAX_TRACKED
+        {
+            messages.pop_front();
+        }
+        messages.push_back(Tracked {
+            sent_at: now,
+            tenant: tenant.to_string(),
+            report_key: report_key.to_string(),
+            covered_at: None,
+        });
+    }
+
+    /// Mark every uncovered message of `report_key` sent by `now` as covered
+    pub fn record_report(&self, report_key: &str, now: u64) {
+        let mut messages = self.messages.lock().unwrap();
+        for m in messages
+            .iter_mut()
+            .filter(|m| m.covered_at.is_none() && m.report_key == report_key && m.sent_at <= now)
+        {
+            m.covered_at = Some(now);
+        }
+    }
+
+    /// Drop tracked messages of a purged agent
+    pub fn forget_agent(&self, agent_id: &str) {
+        let prefix = format!("{agent_id}::");
+        self.messages
+            .lock()
+            .unwrap()
+            .retain(|m| !m.report_key.starts_with(&prefix));
+    }
+
+    fn window(&self, messages: &[&Tracked], window_sec: u64, objective: f64, now: u64) -> WindowSli {
+        let within = self.config.report_within_sec;
+        let (mut total, mut good) = (0, 0);
+        for m in messages.iter().filter(|m| now.saturating_sub(m.sent_at) <= window_sec) {
+            match m.covered_at {
+                Some(at) => {
+                    total += 1;
+                    good += u64::from(at.saturating_sub(m.sent_at) <= within);
+                }
+                None if now.saturating_sub(m.sent_at) > within => total += 1,
+                None => {}
+            }
+        }
+        let sli = (total > 0).then(|| good as f64 / total as f64);
+        WindowSli {
+            window_sec,
+            total,
+            good,
+            sli,
+            burn_rate: sli.map_or(0.0, |s| (1.0 - s) / (1.0 - objective)),
+        }
+    }
+
+    /// Status of every tenant with tracked messages
+    pub fn status(&self, now: u64) -> Vec<TenantSlo> {
+        let messages = self.messages.lock().unwrap();
+        let mut by_tenant: BTreeMap<&str, Vec<&Tracked>> = BTreeMap::new();
+        for m in messages.iter() {
+            by_tenant.entry(m.tenant.as_str()).or_default().push(m);
+        }
+        by_tenant
+            .into_iter()
+            .map(|(tenant, tracked)| {
+                let objective = self.config.objective_for(tenant);
+                let long = self.window(&tracked, self.config.window_sec, objective, now);
+                let short = self.window(&tracked, self.config.fast_window_sec, objective, now);
+                let pending = tracked
+                    .iter()
+                    .filter(|m| m.covered_at.is_none() && now.saturating_sub(m.sent_at) <= self.config.report_within_sec)
+                    .count() as u64;
+                let threshold = self.config.burn_rate_alert;
+                TenantSlo {
+                    tenant: tenant.to_string(),
+                    objective,
+                    report_within_sec: self.config.report_within_sec,
+                    pending,
+                    error_budget_remaining: 1.0 - long.burn_rate,
+                    burning: long.burn_rate >= threshold && short.burn_rate >= threshold,
+                    long,
+                    short,
+                }
+            })
+            .collect()
+    }
+
+    /// Burning tenants not alerted within the last long window
+    pub fn due_alerts(&self, now: u64) -> Vec<TenantSlo> {
+        let mut last_alert = self.last_alert.lock().unwrap();
+        self.status(now)
+            .into_iter()
+            .filter(|t| t.burning)
+            .filter(|t| {
+                let due = last_alert
+                    .get(&t.tenant)
+                    .is_none_or(|at| now.saturating_sub(*at) >= self.config.window_sec);
+                if due {
+                    last_alert.insert(t.tenant.clone(), now);
+                }
+                due
+            })
+            .collect()
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sli_and_burn_rate() {
+        let slo = SloTracker::new(SloConfig {
+            tenant_objectives: BTreeMap::from([("red".to_string(), 0.9)]),
+            ..SloConfig::default()
+        });
+        let t0 = 1_000_000;
+        // red: 3 covered in time, 1 covered late; blue: 1 never covered
+        for i in 0..4 {
+            slo.record_send("red", "a::p:1", t0 + i);
+        }
+        slo.record_report("a::p:1", t0 + 60);
+        slo.record_send("red", "a::p:1", t0 + 61);
+        slo.record_send("blue", "b::p:1", t0 + 61);
+        slo.record_report("a::p:1", t0 + 200);
+        // Sent after the last report and still within the deadline
+        slo.record_send("red", "a::p:1", t0 + 250);
+
+        let status = slo.status(t0 + 300);
+        let red = status.iter().find(|t| t.tenant == "red").unwrap();
+        assert_eq!((red.long.total, red.long.good, red.pending), (5, 4, 1));
+        assert!((red.long.burn_rate - 2.0).abs() < 1e-9);
+        assert!((red.error_budget_remaining + 1.0).abs() < 1e-9);
+        assert!(!red.burning);
+
+        let blue = status.iter().find(|t| t.tenant == "blue").unwrap();
+        assert_eq!((blue.long.total, blue.long.good), (1, 0));
+        assert!(blue.burning, "100% errors at 99% objective burns at 100x");
+
+        assert_eq!(slo.due_alerts(t0 + 300).len(), 1);
+        assert!(slo.due_alerts(t0 + 310).is_empty(), "alert once per window");
+
+        // A clock stepped back leaves messages sent in the future; they count
+        // as just sent
+        let stepped_back = slo.status(t0);
+        assert_eq!(stepped_back.iter().find(|t| t.tenant == "red").unwrap().pending, 1);
+
+        slo.forget_agent("b");
+        assert!(slo.status(t0 + 300).iter().all(|t| t.tenant != "blue"));
+    }
+}
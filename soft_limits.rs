@@ -0,0 +1,247 @@
+//! Advance warning before an agent reaches a hard limit
+//!
+//! Quotas, the probation threshold, and the report strike limit all act at
+//! the cliff: the send is refused, the agent is back on probation, the
+//! protocol is suspended. The policy's `soft_limits` section sets where an
+//! agent is warned first:
+//!
+//! - **quota**: at `ratio` of any agent or tenant quota
+//! - **probation**: at `ratio` of the violations that would put an
+//!   established agent back on probation (its reputation's
+//!   `violation_ceiling`)
+//! - **suspension**: with `strikes_remaining` or fewer held reports left
+//!   before a protocol is suspended for review
+//!
+//! Warnings ride along as `advisories` on send and report responses, and on
+//! `/send/stream` decision frames, for as long as they apply. The first one
+//! of each kind per agent in [`NOTICE_INTERVAL_SEC`] is also logged as a
+//! `soft_limit_approached` audit event and raises a `soft_limit_approached`
+//! alert.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, sync::Mutex};
+
+use crate::{
+    quota::{Breach, Scope},
+    sanctions::Standing,
+};
+
+/// Seconds before a repeated advisory is notified again
+pub const NOTICE_INTERVAL_SEC: u64 = 3_600;
+
+/// Where agents are warned short of a hard limit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SoftLimits {
+    /// Share of a quota or violation ceiling that triggers a warning
+    #[serde(default = "default_ratio")]
+    pub ratio: f64,
+    /// Held reports left before suspension that trigger a warning
+    #[serde(default = "default_strikes_remaining")]
+    pub strikes_remaining: u32,
+}
+
+fn default_ratio() -> f64 {
+    0.8
+}
+
+fn default_strikes_remaining() -> u32 {
+    1
+}
+
+impl Default for SoftLimits {
+    fn default() -> Self {
+        Self {
+            ratio: default_ratio(),
+            strikes_remaining: default_strikes_remaining(),
+        }
+    }
+}
+
+impl SoftLimits {
+    /// Defaults overridden by `SOFT_LIMIT_RATIO` and `SOFT_LIMIT_STRIKES_REMAINING`
+    pub fn from_env() -> Self {
+        use crate::env_parse as var;
+        let d = Self::default();
+        let limits = Self {
+            ratio: var("SOFT_LIMIT_RATIO").unwrap_or(d.ratio),
+            strikes_remaining: var("SOFT_LIMIT_STRIKES_REMAINING").unwrap_or(d.strikes_remaining),
+        };
+        if limits.validate().is_ok() {
+            limits
+        } else {
+            d
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.ratio > 0.0 && self.ratio <= 1.0) {
+            return Err("soft_limits.ratio must be within (0, 1]".to_string());
+        }
+        Ok(())
+    }
+
+    /// Advisories for quotas the agent or its tenant is nearing
+    pub fn quota(&self, nearing: &[Breach]) -> Vec<Advisory> {
+        nearing
+            .iter()
+            .map(|n| Advisory {
+                kind: AdvisoryKind::Quota,
+                subject: format!("{} {}", n.scope, n.resource),
+                used: n.used,
+                limit: n.limit,
+                message: match n.scope {
+                    Scope::Agent => format!("{} of {} {} used this window", n.used, n.limit, n.resource),
+                    Scope::Tenant => format!(
+                        "{} of {} {} used this window by tenant {}",
+                        n.used, n.limit, n.resource, n.subject
+                    ),
+                },
+            })
+            .collect()
+    }
+
+    /// Advisory for an established agent nearing its violation ceiling
+    pub fn violations(&self, violations: u32, ceiling: Option<u32>) -> Option<Advisory> {
+        let ceiling = ceiling.filter(|&c| c > violations)?;
+        (f64::from(violations) >= self.ratio * f64::from(ceiling)).then(|| Advisory {
+            kind: AdvisoryKind::Probation,
+            subject: "violations".to_string(),
+            used: violations.into(),
+            limit: ceiling.into(),
+            message: format!("{violations} of the {ceiling} violations that put the agent on probation"),
+        })
+    }
+
+    /// Advisory for a protocol nearing suspension; `limit` 0 disables suspension
+    pub fn strikes(&self, protocol: &str, standing: &Standing, limit: u32) -> Option<Advisory> {
+        if limit == 0 || standing.is_suspended() || standing.strikes == 0 || standing.strikes >= limit {
+            return None;
+        }
+        (limit - standing.strikes <= self.strikes_remaining).then(|| Advisory {
+            kind: AdvisoryKind::Suspension,
+            subject: protocol.to_string(),
+            used: standing.strikes.into(),
+            limit: limit.into(),
+            message: format!(
+                "{} of the {limit} held reports that suspend {protocol} for review",
+                standing.strikes
+            ),
+        })
+    }
+}
+
+/// Which hard limit an agent is nearing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdvisoryKind {
+    Quota,
+    Probation,
+    Suspension,
+}
+
+impl fmt::Display for AdvisoryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Quota => "quota",
+            Self::Probation => "probation",
+            Self::Suspension => "suspension",
+        })
+    }
+}
+
+/// A hard limit the agent is nearing
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Advisory {
+    pub kind: AdvisoryKind,
+    /// Quota scope and resource, `violations`, or the protocol
+    pub subject: String,
+    pub used: u64,
+    pub limit: u64,
+    pub message: String,
+}
+
+/// When each agent was last notified of each advisory
+#[derive(Debug, Default)]
+pub struct Notices {
+    /// (agent, kind, subject) -> time of the last notice
+    sent: Mutex<HashMap<(String, AdvisoryKind, String), u64>>,
+}
+
+impl Notices {
+    /// Whether `advisory` is due a notice for `agent_id`, marking it sent
+    pub fn due(&self, agent_id: &str, advisory: &Advisory, now: u64) -> bool {
+        let key = (agent_id.to_string(), advisory.kind, advisory.subject.clone());
+        let mut sent = self.sent.lock().unwrap();
+        let due = sent.get(&key).is_none_or(|at| now.saturating_sub(*at) >= NOTICE_INTERVAL_SEC);
+        if due {
+            sent.insert(key, now);
+        }
+        due
+    }
+
+    /// Drop notice history for a purged agent
+    pub fn forget_agent(&self, agent_id: &str) {
+        self.sent.lock().unwrap().retain(|(agent, ..), _| agent != agent_id);
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quota::Resource;
+
+    #[test]
+    fn test_thresholds() {
+        let limits = SoftLimits::default();
+        assert!(limits.is_default() && limits.validate().is_ok());
+        assert!(SoftLimits { ratio: 0.0, ..limits.clone() }.validate().is_err());
+
+        let nearing = [Breach {
+            scope: Scope::Tenant,
+            subject: "red".into(),
+            resource: Resource::Reports,
+            used: 85,
+            limit: 100,
+        }];
+        let quota = limits.quota(&nearing);
+        assert_eq!((quota[0].kind, quota[0].subject.as_str()), (AdvisoryKind::Quota, "tenant reports"));
+        assert!(quota[0].message.contains("tenant red"));
+
+        assert!(limits.violations(7, Some(10)).is_none());
+        let probation = limits.violations(8, Some(10)).unwrap();
+        assert_eq!((probation.used, probation.limit), (8, 10));
+        assert!(limits.violations(10, Some(10)).is_none());
+        assert!(limits.violations(9, None).is_none());
+
+        let mut standing = Standing::default();
+        assert!(limits.strikes("p:1", &standing, 3).is_none());
+        standing.strikes = 1;
+        assert!(limits.strikes("p:1", &standing, 3).is_none());
+        standing.strikes = 2;
+        assert_eq!(limits.strikes("p:1", &standing, 3).unwrap().kind, AdvisoryKind::Suspension);
+        assert!(limits.strikes("p:1", &standing, 0).is_none());
+        standing.suspended_at = Some(1);
+        assert!(limits.strikes("p:1", &standing, 3).is_none());
+    }
+
+    #[test]
+    fn test_notices_once_per_interval() {
+        let notices = Notices::default();
+        let advisory = SoftLimits::default().violations(8, Some(10)).unwrap();
+        assert!(notices.due("a", &advisory, 1_000));
+        assert!(!notices.due("a", &advisory, 1_000 + NOTICE_INTERVAL_SEC - 1));
+        assert!(notices.due("b", &advisory, 1_000));
+        assert!(notices.due("a", &advisory, 1_000 + NOTICE_INTERVAL_SEC));
+
+        notices.forget_agent("a");
+        assert!(notices.due("a", &advisory, 1_001 + NOTICE_INTERVAL_SEC));
+    }
+}
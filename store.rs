@@ -0,0 +1,1183 @@
+//! Pluggable durable storage
+//!
+//! The gateway works from memory. A [`StateStore`] keeps what must outlive a
+//! restart: protocol registrations, report clocks, violation counts, the
+//! audit trail, accepted reports, undelivered callbacks (see `outbox`), and
+//! every policy version loaded (see `policy`). `STATE_STORE` selects one:
+//!
+//! - `file:<path>`: [`FileStore`], an append-only JSON lines file replayed at
+//!   startup
+//! - `http://...` or `https://...`: [`RemoteStore`], which appends each
+//!   [`Record`] with `POST {url}/records` and reads them back, in append
+//!   order, from `GET {url}/records`, authenticated with `STATE_STORE_TOKEN`;
+//!   a `batch` record must be stored whole or refused
+//!
+//! [`MemoryStore`] keeps everything in process and backs the tests. Other
+//! stores (DynamoDB, FoundationDB) implement [`StateStore`] and must pass
+//! the suite in `conformance.rs` (built with the `test-harness` feature),
+//! which pins down the semantics the gateway relies on.
+//!
+//! Writes are queued and applied by one background task in the order the
+//! gateway made them, so requests never wait on the store; a failing store
+//! is retried with backoff and the queue drained on shutdown. The task writes
+//! the records waiting in the queue together, up to `STATE_STORE_BATCH_MAX`
+//! of them in one atomic [`put_batch`](StateStore::put_batch), and with
+//! `STATE_STORE_FLUSH_INTERVAL_MS` set waits that long for a batch to fill,
+//! trading how much a crash can lose for fewer store round trips. The queue holds
+//! `STATE_STORE_QUEUE_CAPACITY` records. While it is full, new records are
+//! shed and counted rather than held in memory without bound; the gateway
+//! alerts on them (see `store_writes_shed`). Outbox entries and their
+//! settlement are never shed: `STATE_STORE_QUEUE_RESERVE` more slots are kept
+//! for them, and beyond those they wait in memory. Records are
+//! read back through the legacy deserializers in [`ids`], which rewrite ids
+//! stored before they were validated. At startup the gateway loads
+//! registrations, report clocks, violation counts, the outbox, earlier policy
+//! versions, and the summary quality scores of stored reports, and continues
+//! audit sequence numbers after the last stored event. Protocol standing, risk, trials,
+//! track records, and soft deletes are not stored; a warm standby keeps those
+//! (see `replication`).
+
+use axum::async_trait;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+
+use crate::{
+    audit::AuditEvent,
+    ids,
+    intern::entry_mut,
+    outbox::OutboxEntry,
+    policy::PolicySnapshot,
+    protocol_key,
+    readability::{self, Quality, Sample},
+    replication::Mutation,
+    AppState, ProtocolDescriptor,
+};
+
+/// Per-request timeout of a [`RemoteStore`]
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest wait between retries of a failing write
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Records queued for the store at most, unless `STATE_STORE_QUEUE_CAPACITY`
+/// says otherwise
+pub const DEFAULT_QUEUE_CAPACITY: usize = 100_000;
+
+/// Queue slots kept for records that are never shed, unless
+/// `STATE_STORE_QUEUE_RESERVE` says otherwise
+pub const DEFAULT_QUEUE_RESERVE: usize = 10_000;
+
+/// Records written in one batch at most, unless `STATE_STORE_BATCH_MAX` says
+/// otherwise
+pub const DEFAULT_BATCH_MAX: usize = 500;
+
+// =============================================================================
+// Records
+// =============================================================================
+
+/// A protocol registered by an agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Registration {
+    #[serde(deserialize_with = "ids::legacy_agent_id")]
+    pub agent_id: String,
+    #[serde(deserialize_with = "legacy_descriptor")]
+    pub descriptor: ProtocolDescriptor,
+    pub registered_at: u64,
+    /// Last recertification, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recertified_at: Option<u64>,
+}
+
+/// Deserialize a stored descriptor, rewriting a legacy name or version with
+/// [`ids::migrate`]
+fn legacy_descriptor<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ProtocolDescriptor, D::Error> {
+    let mut raw = Value::deserialize(deserializer)?;
+    for (field, what) in [("name", "protocol name"), ("version", "protocol version")] {
+        if let Some(Value::String(part)) = raw.get_mut(field) {
+            if ids::validate(field, part, ids::MAX_PROTOCOL_PART_LEN).is_ok() {
+                ids::note_loaded(what, part, part);
+            } else if let Some(migrated) = ids::migrate(part, ids::MAX_PROTOCOL_PART_LEN) {
+                warn!(from = %part, to = %migrated, event = "legacy_id_migrated", "Stored protocol {field} rewritten");
+                ids::note_loaded(what, part, &migrated);
+                *part = migrated;
+            }
+        }
+    }
+    serde_json::from_value(raw).map_err(de::Error::custom)
+}
+
+impl Registration {
+    fn key(&self) -> (String, String) {
+        let key = protocol_key(&self.descriptor.name, &self.descriptor.version);
+        (self.agent_id.clone(), key)
+    }
+}
+
+/// An accepted English report
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredReport {
+    #[serde(deserialize_with = "ids::legacy_agent_id")]
+    pub agent_id: String,
+    /// Resolved protocol key
+    #[serde(deserialize_with = "ids::legacy_protocol_key")]
+    pub protocol: String,
+    pub accepted_at: u64,
+    pub english_summary: String,
+    /// Summary with its quoted protocol tokens redacted, if it quotes any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redacted_summary: Option<String>,
+    pub coverage: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub message_ids: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<String>,
+    /// Readability of the summary (see `readability`); none on reports stored
+    /// before summaries were scored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<Quality>,
+}
+
+/// One write to a store, as queued by the gateway and as sent to a
+/// [`RemoteStore`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Record {
+    Registration(Box<Registration>),
+    ReportClock {
+        #[serde(deserialize_with = "ids::legacy_report_key")]
+        report_key: String,
+        ts: u64,
+    },
+    Violations {
+        #[serde(deserialize_with = "ids::legacy_agent_id")]
+        agent_id: String,
+        count: u32,
+    },
+    AgentRemoved {
+        #[serde(deserialize_with = "ids::legacy_agent_id")]
+        agent_id: String,
+    },
+    Audit(AuditEvent),
+    Report(StoredReport),
+    /// A delivery queued in the outbox
+    Outbox(Box<OutboxEntry>),
+    /// An outbox delivery made or abandoned
+    OutboxSettled {
+        id: String,
+    },
+    /// A policy version loaded
+    Policy(Box<PolicySnapshot>),
+    /// Writes stored together or not at all
+    Batch {
+        records: Vec<Record>,
+    },
+}
+
+impl Record {
+    /// Apply the write to `store`
+    pub async fn write(&self, store: &dyn StateStore) -> Result<(), String> {
+        match self {
+            Self::Registration(registration) => store.put_registration(registration).await,
+            Self::ReportClock { report_key, ts } => store.set_report_clock(report_key, *ts).await,
+            Self::Violations { agent_id, count } => store.set_violations(agent_id, *count).await,
+            Self::AgentRemoved { agent_id } => store.remove_agent(agent_id).await,
+            Self::Audit(event) => store.append_audit(event).await,
+            Self::Report(report) => store.put_report(report).await,
+            Self::Outbox(entry) => store.put_outbox(entry).await,
+            Self::OutboxSettled { id } => store.settle_outbox(id).await,
+            Self::Policy(snapshot) => store.put_policy(snapshot).await,
+            Self::Batch { records } => store.put_batch(records).await,
+        }
+    }
+
+    /// The writes that persist a replicated mutation, if it is stored
+    pub(crate) fn from_mutation(mutation: &Mutation) -> Vec<Self> {
+        match mutation {
+            Mutation::ProtocolRegistered {
+                agent_id,
+                descriptor,
+                registered_at,
+                report_clock,
+                recertified_at,
+            } => {
+                let mut records = vec![Self::Registration(Box::new(Registration {
+                    agent_id: agent_id.clone(),
+                    descriptor: (**descriptor).clone(),
+                    registered_at: *registered_at,
+                    recertified_at: *recertified_at,
+                }))];
+                if let Some(ts) = report_clock {
+                    let key = protocol_key(&descriptor.name, &descriptor.version);
+                    records.push(Self::ReportClock {
+                        report_key: format!("{agent_id}::{key}"),
+                        ts: *ts,
+                    });
+                }
+                records
+            }
+            Mutation::ReportAccepted { report_key, ts } => vec![Self::ReportClock {
+                report_key: report_key.clone(),
+                ts: *ts,
+            }],
+            Mutation::Violations { agent_id, count } => vec![Self::Violations {
+                agent_id: agent_id.clone(),
+                count: *count,
+            }],
+            Mutation::AgentPurged { agent_id } => vec![Self::AgentRemoved { agent_id: agent_id.clone() }],
+            _ => Vec::new(),
+        }
+    }
+}
+
+// =============================================================================
+// Trait
+// =============================================================================
+
+/// Durable home of gateway state
+///
+/// Writes must be durable once they return `Ok`. Reads are made at startup,
+/// before the gateway serves requests.
+#[async_trait]
+pub trait StateStore: Send + Sync {
+    /// Store a registration, replacing one with the same agent, name, and
+    /// version
+    async fn put_registration(&self, registration: &Registration) -> Result<(), String>;
+
+    /// Every registration, ordered by agent id then protocol key
+    async fn registrations(&self) -> Result<Vec<Registration>, String>;
+
+    /// When a report was last accepted for `report_key` (`agent_id::protocol_key`)
+    async fn set_report_clock(&self, report_key: &str, ts: u64) -> Result<(), String>;
+
+    async fn report_clocks(&self) -> Result<BTreeMap<String, u64>, String>;
+
+    /// An agent's absolute violation count; 0 clears it
+    async fn set_violations(&self, agent_id: &str, count: u32) -> Result<(), String>;
+
+    async fn violations(&self) -> Result<BTreeMap<String, u32>, String>;
+
+    /// Drop an agent's registrations, report clocks, violations, and reports;
+    /// its audit events stay
+    async fn remove_agent(&self, agent_id: &str) -> Result<(), String>;
+
+    /// Append an audit event; one whose sequence number is already stored is
+    /// ignored
+    async fn append_audit(&self, event: &AuditEvent) -> Result<(), String>;
+
+    /// Up to `limit` audit events from sequence number `from` on, ascending
+    async fn audit(&self, from: u64, limit: usize) -> Result<Vec<AuditEvent>, String>;
+
+    /// Highest stored audit sequence number, 0 when there is none
+    async fn last_audit_seq(&self) -> Result<u64, String>;
+
+    async fn put_report(&self, report: &StoredReport) -> Result<(), String>;
+
+    /// An agent's reports, oldest first
+    async fn reports(&self, agent_id: &str) -> Result<Vec<StoredReport>, String>;
+
+    /// Store an outbox entry, replacing one with the same id
+    async fn put_outbox(&self, entry: &OutboxEntry) -> Result<(), String>;
+
+    /// Drop the outbox entry `id`, if stored
+    async fn settle_outbox(&self, id: &str) -> Result<(), String>;
+
+    /// Outbox entries not yet settled, ordered by id
+    async fn outbox(&self) -> Result<Vec<OutboxEntry>, String>;
+
+    /// Store a policy version; one already stored keeps its first load time
+    async fn put_policy(&self, snapshot: &PolicySnapshot) -> Result<(), String>;
+
+    /// Every stored policy version, ordered by version
+    async fn policies(&self) -> Result<Vec<PolicySnapshot>, String>;
+
+    /// Apply `records` in order, all of them or, should the write fail, none
+    async fn put_batch(&self, records: &[Record]) -> Result<(), String>;
+}
+
+// =============================================================================
+// In-memory tables
+// =============================================================================
+
+/// Stored state, as folded from [`Record`]s
+#[derive(Debug, Default)]
+struct Tables {
+    registrations: BTreeMap<(String, String), Registration>,
+    report_clocks: BTreeMap<String, u64>,
+    violations: BTreeMap<String, u32>,
+    audit: BTreeMap<u64, AuditEvent>,
+    reports: Vec<StoredReport>,
+    outbox: BTreeMap<String, OutboxEntry>,
+    policies: BTreeMap<String, PolicySnapshot>,
+}
+
+impl Tables {
+    fn fold(records: impl IntoIterator<Item = Record>) -> Self {
+        let mut tables = Self::default();
+        for record in records {
+            tables.apply(record);
+        }
+        tables
+    }
+
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Registration(registration) => {
+                self.registrations.insert(registration.key(), *registration);
+            }
+            Record::ReportClock { report_key, ts } => {
+                self.report_clocks.insert(report_key, ts);
+            }
+            Record::Violations { agent_id, count: 0 } => {
+                self.violations.remove(&agent_id);
+            }
+            Record::Violations { agent_id, count } => {
+                self.violations.insert(agent_id, count);
+            }
+            Record::AgentRemoved { agent_id } => {
+                let prefix = format!("{agent_id}::");
+                self.registrations.retain(|(agent, _), _| *agent != agent_id);
+                self.report_clocks.retain(|key, _| !key.starts_with(&prefix));
+                self.violations.remove(&agent_id);
+                self.reports.retain(|r| r.agent_id != agent_id);
+            }
+            Record::Audit(event) => {
+                self.audit.entry(event.seq).or_insert(event);
+            }
+            Record::Report(report) => self.reports.push(report),
+            Record::Outbox(entry) => {
+                self.outbox.insert(entry.id.clone(), *entry);
+            }
+            Record::OutboxSettled { id } => {
+                self.outbox.remove(&id);
+            }
+            Record::Policy(snapshot) => {
+                self.policies.entry(snapshot.version.clone()).or_insert(*snapshot);
+            }
+            Record::Batch { records } => {
+                for record in records {
+                    self.apply(record);
+                }
+            }
+        }
+    }
+
+    fn registrations(&self) -> Vec<Registration> {
+        self.registrations.values().cloned().collect()
+    }
+
+    fn audit(&self, from: u64, limit: usize) -> Vec<AuditEvent> {
+        self.audit.range(from..).take(limit).map(|(_, e)| e.clone()).collect()
+    }
+
+    fn last_audit_seq(&self) -> u64 {
+        self.audit.keys().next_back().copied().unwrap_or(0)
+    }
+
+    fn reports(&self, agent_id: &str) -> Vec<StoredReport> {
+        self.reports.iter().filter(|r| r.agent_id == agent_id).cloned().collect()
+    }
+}
+
+// =============================================================================
+// Memory store
+// =============================================================================
+
+/// Store kept in process; nothing survives a restart
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    tables: Mutex<Tables>,
+}
+
+impl MemoryStore {
+    fn apply(&self, record: Record) -> Result<(), String> {
+        self.tables.lock().unwrap().apply(record);
+        Ok(())
+    }
+
+    fn read<T>(&self, f: impl FnOnce(&Tables) -> T) -> Result<T, String> {
+        Ok(f(&self.tables.lock().unwrap()))
+    }
+}
+
+#[async_trait]
+impl StateStore for MemoryStore {
+    async fn put_registration(&self, registration: &Registration) -> Result<(), String> {
+        self.apply(Record::Registration(Box::new(registration.clone())))
+    }
+
+    async fn registrations(&self) -> Result<Vec<Registration>, String> {
+        self.read(Tables::registrations)
+    }
+
+    async fn set_report_clock(&self, report_key: &str, ts: u64) -> Result<(), String> {
+        self.apply(Record::ReportClock {
+            report_key: report_key.to_string(),
+            ts,
+        })
+    }
+
+    async fn report_clocks(&self) -> Result<BTreeMap<String, u64>, String> {
+        self.read(|t| t.report_clocks.clone())
+    }
+
+    async fn set_violations(&self, agent_id: &str, count: u32) -> Result<(), String> {
+        self.apply(Record::Violations {
+            agent_id: agent_id.to_string(),
+            count,
+        })
+    }
+
+    async fn violations(&self) -> Result<BTreeMap<String, u32>, String> {
+        self.read(|t| t.violations.clone())
+    }
+
+    async fn remove_agent(&self, agent_id: &str) -> Result<(), String> {
+        self.apply(Record::AgentRemoved { agent_id: agent_id.to_string() })
+    }
+
+    async fn append_audit(&self, event: &AuditEvent) -> Result<(), String> {
+        self.apply(Record::Audit(event.clone()))
+    }
+
+    async fn audit(&self, from: u64, limit: usize) -> Result<Vec<AuditEvent>, String> {
+        self.read(|t| t.audit(from, limit))
+    }
+
+    async fn last_audit_seq(&self) -> Result<u64, String> {
+        self.read(Tables::last_audit_seq)
+    }
+
+    async fn put_report(&self, report: &StoredReport) -> Result<(), String> {
+        self.apply(Record::Report(report.clone()))
+    }
+
+    async fn reports(&self, agent_id: &str) -> Result<Vec<StoredReport>, String> {
+        self.read(|t| t.reports(agent_id))
+    }
+
+    async fn put_outbox(&self, entry: &OutboxEntry) -> Result<(), String> {
+        self.apply(Record::Outbox(Box::new(entry.clone())))
+    }
+
+    async fn settle_outbox(&self, id: &str) -> Result<(), String> {
+        self.apply(Record::OutboxSettled { id: id.to_string() })
+    }
+
+    async fn outbox(&self) -> Result<Vec<OutboxEntry>, String> {
+        self.read(|t| t.outbox.values().cloned().collect())
+    }
+
+    async fn put_policy(&self, snapshot: &PolicySnapshot) -> Result<(), String> {
+        self.apply(Record::Policy(Box::new(snapshot.clone())))
+    }
+
+    async fn policies(&self) -> Result<Vec<PolicySnapshot>, String> {
+        self.read(|t| t.policies.values().cloned().collect())
+    }
+
+    async fn put_batch(&self, records: &[Record]) -> Result<(), String> {
+        self.apply(Record::Batch { records: records.to_vec() })
+    }
+}
+
+// =============================================================================
+// File store
+// =============================================================================
+
+/// Append-only JSON lines file of [`Record`]s, replayed into memory on open
+///
+/// A torn last line, left by a crash mid-write, is dropped on open; any other
+/// unreadable line fails the open, as do stored ids that collide once
+/// migrated (see [`ids::refuse_collisions`]).
+#[derive(Debug)]
+pub struct FileStore {
+    memory: MemoryStore,
+    file: Mutex<File>,
+}
+
+impl FileStore {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let error = |e: std::io::Error| format!("{path}: {e}");
+        let file = OpenOptions::new().create(true).read(true).append(true).open(path).map_err(error)?;
+        let mut tables = Tables::default();
+        // Length of the file up to the end of the last complete record
+        let mut intact = 0;
+        let mut torn = false;
+        ids::refuse_collisions(|| {
+            let mut lines = BufReader::new(&file).split(b'\n').enumerate().peekable();
+            while let Some((n, line)) = lines.next() {
+                let line = line.map_err(|e| e.to_string())?;
+                let last = lines.peek().is_none();
+                if !line.iter().all(u8::is_ascii_whitespace) {
+                    match serde_json::from_slice(&line) {
+                        Ok(record) => tables.apply(record),
+                        Err(_) if last => {
+                            torn = true;
+                            break;
+                        }
+                        Err(e) => return Err(format!("line {}: {e}", n + 1)),
+                    }
+                }
+                intact += line.len() as u64 + 1;
+            }
+            Ok(())
+        })
+        .map_err(|e| format!("{path}: {e}"))?;
+        if torn {
+            warn!(path = %path, event = "store_torn_write", "Dropping the torn last record of the state file");
+            file.set_len(intact).map_err(error)?;
+        }
+        Ok(Self {
+            memory: MemoryStore { tables: Mutex::new(tables) },
+            file: Mutex::new(file),
+        })
+    }
+
+    fn append(&self, record: Record) -> Result<(), String> {
+        let mut line = serde_json::to_vec(&record).map_err(|e| e.to_string())?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&line).and_then(|_| file.sync_data()).map_err(|e| e.to_string())?;
+        self.memory.apply(record)
+    }
+}
+
+#[async_trait]
+impl StateStore for FileStore {
+    async fn put_registration(&self, registration: &Registration) -> Result<(), String> {
+        self.append(Record::Registration(Box::new(registration.clone())))
+    }
+
+    async fn registrations(&self) -> Result<Vec<Registration>, String> {
+        self.memory.registrations().await
+    }
+
+    async fn set_report_clock(&self, report_key: &str, ts: u64) -> Result<(), String> {
+        self.append(Record::ReportClock {
+            report_key: report_key.to_string(),
+            ts,
+        })
+    }
+
+    async fn report_clocks(&self) -> Result<BTreeMap<String, u64>, String> {
+        self.memory.report_clocks().await
+    }
+
+    async fn set_violations(&self, agent_id: &str, count: u32) -> Result<(), String> {
+        self.append(Record::Violations {
+            agent_id: agent_id.to_string(),
+            count,
+        })
+    }
+
+    async fn violations(&self) -> Result<BTreeMap<String, u32>, String> {
+        self.memory.violations().await
+    }
+
+    async fn remove_agent(&self, agent_id: &str) -> Result<(), String> {
+        self.append(Record::AgentRemoved { agent_id: agent_id.to_string() })
+    }
+
+    async fn append_audit(&self, event: &AuditEvent) -> Result<(), String> {
+        if self.memory.read(|t| t.audit.contains_key(&event.seq))? {
+            return Ok(());
+        }
+        self.append(Record::Audit(event.clone()))
+    }
+
+    async fn audit(&self, from: u64, limit: usize) -> Result<Vec<AuditEvent>, String> {
+        self.memory.audit(from, limit).await
+    }
+
+    async fn last_audit_seq(&self) -> Result<u64, String> {
+        self.memory.last_audit_seq().await
+    }
+
+    async fn put_report(&self, report: &StoredReport) -> Result<(), String> {
+        self.append(Record::Report(report.clone()))
+    }
+
+    async fn reports(&self, agent_id: &str) -> Result<Vec<StoredReport>, String> {
+        self.memory.reports(agent_id).await
+    }
+
+    async fn put_outbox(&self, entry: &OutboxEntry) -> Result<(), String> {
+        self.append(Record::Outbox(Box::new(entry.clone())))
+    }
+
+    async fn settle_outbox(&self, id: &str) -> Result<(), String> {
+        self.append(Record::OutboxSettled { id: id.to_string() })
+    }
+
+    async fn outbox(&self) -> Result<Vec<OutboxEntry>, String> {
+        self.memory.outbox().await
+    }
+
+    async fn put_policy(&self, snapshot: &PolicySnapshot) -> Result<(), String> {
+        if self.memory.read(|t| t.policies.contains_key(&snapshot.version))? {
+            return Ok(());
+        }
+        self.append(Record::Policy(Box::new(snapshot.clone())))
+    }
+
+    async fn policies(&self) -> Result<Vec<PolicySnapshot>, String> {
+        self.memory.policies().await
+    }
+
+    async fn put_batch(&self, records: &[Record]) -> Result<(), String> {
+        // One line, so a torn write drops the whole batch
+        self.append(Record::Batch { records: records.to_vec() })
+    }
+}
+
+// =============================================================================
+// Remote store
+// =============================================================================
+
+/// Store behind an HTTP service keeping an append-only log of [`Record`]s
+///
+/// Reads fetch the whole log and fold it, which suits the startup load they
+/// serve.
+#[derive(Debug)]
+pub struct RemoteStore {
+    url: String,
+    token: Option<String>,
+    client: reqwest::Client,
+}
+
+impl RemoteStore {
+    pub fn new(url: &str, token: Option<String>) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            token,
+            client: reqwest::Client::builder()
+                .timeout(REMOTE_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method) -> reqwest::RequestBuilder {
+        let request = self.client.request(method, format!("{}/records", self.url));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn append(&self, record: Record) -> Result<(), String> {
+        self.request(reqwest::Method::POST)
+            .json(&record)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn fetch(&self) -> Result<Tables, String> {
+        let body = self
+            .request(reqwest::Method::GET)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?
+            .bytes()
+            .await
+            .map_err(|e| e.to_string())?;
+        let records: Vec<Record> = ids::refuse_collisions(|| serde_json::from_slice(&body).map_err(|e| e.to_string()))?;
+        Ok(Tables::fold(records))
+    }
+}
+
+#[async_trait]
+impl StateStore for RemoteStore {
+    async fn put_registration(&self, registration: &Registration) -> Result<(), String> {
+        self.append(Record::Registration(Box::new(registration.clone()))).await
+    }
+
+    async fn registrations(&self) -> Result<Vec<Registration>, String> {
+        Ok(self.fetch().await?.registrations())
+    }
+
+    async fn set_report_clock(&self, report_key: &str, ts: u64) -> Result<(), String> {
+        self.append(Record::ReportClock {
+            report_key: report_key.to_string(),
+            ts,
+        })
+        .await
+    }
+
+    async fn report_clocks(&self) -> Result<BTreeMap<String, u64>, String> {
+        Ok(self.fetch().await?.report_clocks)
+    }
+
+    async fn set_violations(&self, agent_id: &str, count: u32) -> Result<(), String> {
+        self.append(Record::Violations {
+            agent_id: agent_id.to_string(),
+            count,
+        })
+        .await
+    }
+
+    async fn violations(&self) -> Result<BTreeMap<String, u32>, String> {
+        Ok(self.fetch().await?.violations)
+    }
+
+    async fn remove_agent(&self, agent_id: &str) -> Result<(), String> {
+        self.append(Record::AgentRemoved { agent_id: agent_id.to_string() }).await
+    }
+
+    async fn append_audit(&self, event: &AuditEvent) -> Result<(), String> {
+        // Duplicates are dropped when the log is folded
+        self.append(Record::Audit(event.clone())).await
+    }
+
+    async fn audit(&self, from: u64, limit: usize) -> Result<Vec<AuditEvent>, String> {
+        Ok(self.fetch().await?.audit(from, limit))
+    }
+
+    async fn last_audit_seq(&self) -> Result<u64, String> {
+        Ok(self.fetch().await?.last_audit_seq())
+    }
+
+    async fn put_report(&self, report: &StoredReport) -> Result<(), String> {
+        self.append(Record::Report(report.clone())).await
+    }
+
+    async fn reports(&self, agent_id: &str) -> Result<Vec<StoredReport>, String> {
+        Ok(self.fetch().await?.reports(agent_id))
+    }
+
+    async fn put_outbox(&self, entry: &OutboxEntry) -> Result<(), String> {
+        self.append(Record::Outbox(Box::new(entry.clone()))).await
+    }
+
+    async fn settle_outbox(&self, id: &str) -> Result<(), String> {
+        self.append(Record::OutboxSettled { id: id.to_string() }).await
+    }
+
+    async fn outbox(&self) -> Result<Vec<OutboxEntry>, String> {
+        Ok(self.fetch().await?.outbox.into_values().collect())
+    }
+
+    async fn put_policy(&self, snapshot: &PolicySnapshot) -> Result<(), String> {
+        // A version stored before keeps its first load time when folded
+        self.append(Record::Policy(Box::new(snapshot.clone()))).await
+    }
+
+    async fn policies(&self) -> Result<Vec<PolicySnapshot>, String> {
+        Ok(self.fetch().await?.policies.into_values().collect())
+    }
+
+    async fn put_batch(&self, records: &[Record]) -> Result<(), String> {
+        self.append(Record::Batch { records: records.to_vec() }).await
+    }
+}
+
+/// Open the store named by `STATE_STORE`, if any
+pub fn open_from_env() -> Result<Option<Arc<dyn StateStore>>, String> {
+    let Some(spec) = env::var("STATE_STORE").ok().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()) else {
+        return Ok(None);
+    };
+    if let Some(path) = spec.strip_prefix("file:") {
+        return Ok(Some(Arc::new(FileStore::open(path)?)));
+    }
+    if spec.starts_with("http://") || spec.starts_with("https://") {
+        let token = env::var("STATE_STORE_TOKEN").ok().filter(|t| !t.is_empty());
+        return Ok(Some(Arc::new(RemoteStore::new(&spec, token))));
+    }
+    Err(format!("STATE_STORE must be file:<path> or an http(s) URL, got {spec}"))
+}
+
+// =============================================================================
+// Write queue
+// =============================================================================
+
+/// How the background writer groups queued records into store writes
+#[derive(Debug, Clone, Copy)]
+pub struct Batching {
+    /// Records written together at most
+    pub max_records: usize,
+    /// How long a batch waits for more records; zero writes what is queued
+    pub interval: Duration,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Self {
+            max_records: DEFAULT_BATCH_MAX,
+            interval: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Queued {
+    Record(Record),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Queue of writes to the configured store; writes are dropped when no store
+/// is configured
+///
+/// [`write`](Self::write) sheds a record rather than wait for room.
+/// [`commit`](Self::commit) never does: `reserve` slots of the queue are kept
+/// free of shedable records for it, and once those are taken its records
+/// wait, in order, for a forwarder to queue them as room frees up.
+#[derive(Debug, Default)]
+pub struct Persistence {
+    tx: Option<mpsc::Sender<Queued>>,
+    /// Records that must not be shed, waiting for room in `tx`
+    held: Option<mpsc::UnboundedSender<Queued>>,
+    /// How many wait in `held`; committed records queue behind them
+    holding: Arc<AtomicU64>,
+    /// Slots of `tx` that [`write`](Self::write) leaves free
+    reserve: usize,
+    /// Records dropped because the queue was full
+    pub shed: AtomicU64,
+    /// Of those, the ones not yet alerted on
+    unreported: AtomicU64,
+}
+
+impl Persistence {
+    /// Start the background writer for `store`, queueing up to `capacity`
+    /// shedable records plus `reserve` that are never shed
+    pub fn start(store: Arc<dyn StateStore>, capacity: usize, reserve: usize, batching: Batching) -> Self {
+        let (tx, rx) = mpsc::channel(capacity.max(1) + reserve);
+        tokio::spawn(drain(store, rx, batching));
+        Self::queue(tx, reserve)
+    }
+
+    fn queue(tx: mpsc::Sender<Queued>, reserve: usize) -> Self {
+        let (held, mut forward) = mpsc::unbounded_channel();
+        let holding = Arc::new(AtomicU64::new(0));
+        let (queue, forwarding) = (tx.clone(), holding.clone());
+        tokio::spawn(async move {
+            while let Some(queued) = forward.recv().await {
+                if queue.send(queued).await.is_err() {
+                    break;
+                }
+                forwarding.fetch_sub(1, Ordering::AcqRel);
+            }
+        });
+        Self {
+            tx: Some(tx),
+            held: Some(held),
+            holding,
+            reserve,
+            shed: AtomicU64::new(0),
+            unreported: AtomicU64::new(0),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    /// Queue `record`, shedding it when the queue is full short of its
+    /// reserve
+    pub fn write(&self, record: Record) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        if tx.capacity() <= self.reserve || tx.try_send(Queued::Record(record)).is_err() {
+            self.shed.fetch_add(1, Ordering::Relaxed);
+            self.unreported.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Queue `record` behind the records committed before it, never shedding
+    /// it; it waits in memory while the queue is full
+    pub fn commit(&self, record: Record) {
+        let (Some(tx), Some(held)) = (&self.tx, &self.held) else {
+            return;
+        };
+        let queued = match self.holding.load(Ordering::Acquire) {
+            0 => match tx.try_send(Queued::Record(record)) {
+                Ok(()) => return,
+                Err(e) => e.into_inner(),
+            },
+            _ => Queued::Record(record),
+        };
+        self.hold(held, queued);
+    }
+
+    fn hold(&self, held: &mpsc::UnboundedSender<Queued>, queued: Queued) {
+        self.holding.fetch_add(1, Ordering::AcqRel);
+        if held.send(queued).is_err() {
+            self.holding.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    /// Records waiting to be written
+    pub fn depth(&self) -> usize {
+        self.tx.as_ref().map_or(0, |tx| tx.max_capacity() - tx.capacity())
+    }
+
+    /// Records shed since the last call
+    pub fn take_shed(&self) -> u64 {
+        self.unreported.swap(0, Ordering::Relaxed)
+    }
+
+    /// Queue the writes persisting `mutation`
+    pub fn mutation(&self, mutation: &Mutation) {
+        if self.enabled() {
+            for record in Record::from_mutation(mutation) {
+                self.write(record);
+            }
+        }
+    }
+
+    /// Wait until every write queued or committed so far is stored
+    pub async fn flush(&self) {
+        let Some(held) = &self.held else {
+            return;
+        };
+        // Behind both the records already queued and those still held
+        let (done, flushed) = oneshot::channel();
+        self.hold(held, Queued::Flush(done));
+        let _ = flushed.await;
+    }
+}
+
+/// Apply queued writes in order and in batches, retrying a failing store
+/// with backoff
+async fn drain(store: Arc<dyn StateStore>, mut rx: mpsc::Receiver<Queued>, batching: Batching) {
+    let max_records = batching.max_records.max(1);
+    let mut batch = Vec::new();
+    let mut flushes = Vec::new();
+    while let Some(queued) = rx.recv().await {
+        let deadline = tokio::time::Instant::now() + batching.interval;
+        let mut next = Some(queued);
+        while let Some(queued) = next.take() {
+            match queued {
+                Queued::Record(record) => batch.push(record),
+                // A flush is answered once what was queued before it is written
+                Queued::Flush(done) => {
+                    flushes.push(done);
+                    break;
+                }
+            }
+            if batch.len() >= max_records {
+                break;
+            }
+            next = match rx.try_recv() {
+                Ok(queued) => Some(queued),
+                Err(_) if batching.interval.is_zero() => None,
+                Err(_) => tokio::time::timeout_at(deadline, rx.recv()).await.ok().flatten(),
+            };
+        }
+        if !batch.is_empty() {
+            write_batch(&*store, &batch, &rx).await;
+            batch.clear();
+        }
+        for done in flushes.drain(..) {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Write `batch` whole, one record on its own, retrying until the store takes it
+async fn write_batch(store: &dyn StateStore, batch: &[Record], rx: &mpsc::Receiver<Queued>) {
+    let mut backoff = Duration::from_millis(100);
+    let mut failing = false;
+    loop {
+        let written = match batch {
+            [record] => record.write(store).await,
+            records => store.put_batch(records).await,
+        };
+        let Err(error) = written else {
+            break;
+        };
+        // Logged once per outage: the warning is itself an audit event
+        if !failing {
+            warn!(error = %error, event = "store_write_failed", "State store write failed, retrying");
+            failing = true;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+    }
+    if failing {
+        info!(queued = rx.len(), event = "store_write_recovered", "State store writes resumed");
+    }
+}
+
+/// Load registrations, report clocks, violation counts, undelivered outbox
+/// entries, and earlier policy versions from `store` into the gateway
+pub async fn load(state: &AppState, store: &dyn StateStore) -> Result<(), String> {
+    let policies = store.policies().await?;
+    let registrations = store.registrations().await?;
+    let report_clocks = store.report_clocks().await?;
+    let violations = store.violations().await?;
+    let outbox = store.outbox().await?;
+    let (loaded, flagged, undelivered) = (registrations.len(), violations.len(), outbox.len());
+    let agents: BTreeSet<String> = registrations.iter().map(|r| r.agent_id.clone()).collect();
+    let mut scored = Vec::new();
+    for agent_id in agents {
+        let reports = store.reports(&agent_id).await?;
+        scored.extend(reports.into_iter().filter_map(|r| {
+            let quality = r.quality?;
+            Some((r.agent_id, Sample { protocol: r.protocol, accepted_at: r.accepted_at, quality }))
+        }));
+    }
+    for entry in outbox {
+        state.outbox.insert(entry);
+    }
+    let current = state.policy.latest();
+    if !policies.iter().any(|p| p.version == current.version) {
+        state.store.write(Record::Policy(Box::new((*current).clone())));
+    }
+    let versions = policies.len();
+    for snapshot in policies {
+        state.policy.restore(snapshot);
+    }
+    {
+        let mut st = state.inner.write().unwrap();
+        for registration in registrations {
+            Mutation::ProtocolRegistered {
+                agent_id: registration.agent_id,
+                descriptor: Box::new(registration.descriptor),
+                registered_at: registration.registered_at,
+                report_clock: None,
+                recertified_at: registration.recertified_at,
+            }
+            .apply(&mut st);
+        }
+        for (report_key, ts) in report_clocks {
+            Mutation::ReportAccepted { report_key, ts }.apply(&mut st);
+        }
+        for (agent_id, count) in violations {
+            Mutation::Violations { agent_id, count }.apply(&mut st);
+        }
+        for (agent_id, sample) in scored {
+            readability::record(entry_mut(&mut st.summary_quality, &agent_id), sample);
+        }
+    }
+    info!(
+        registrations = loaded,
+        agents_with_violations = flagged,
+        outbox = undelivered,
+        policy_versions = versions,
+        event = "store_loaded",
+        "Gateway state loaded from the state store"
+    );
+    Ok(())
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_full_queue_sheds_records() {
+        // No writer draining it, as with a store that stopped answering
+        let (tx, _rx) = mpsc::channel(2);
+        let persistence = Persistence::queue(tx, 0);
+        for agent_id in ["a", "b", "c", "d"] {
+            persistence.write(Record::AgentRemoved { agent_id: agent_id.to_string() });
+        }
+        assert_eq!(persistence.depth(), 2);
+        assert_eq!(persistence.shed.load(Ordering::Relaxed), 2);
+        assert_eq!(persistence.take_shed(), 2);
+        assert_eq!(persistence.take_shed(), 0, "alerted once");
+    }
+
+    #[tokio::test]
+    async fn test_reserve_is_kept_for_committed_records() {
+        let (tx, _rx) = mpsc::channel(3);
+        let persistence = Persistence::queue(tx, 1);
+        for agent_id in ["a", "b", "c"] {
+            persistence.write(Record::AgentRemoved { agent_id: agent_id.to_string() });
+        }
+        assert_eq!(persistence.take_shed(), 1, "the last slot is reserved");
+        persistence.commit(Record::OutboxSettled { id: "o1".to_string() });
+        tokio::task::yield_now().await;
+        assert_eq!(persistence.depth(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_committed_records_wait_for_room() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let persistence = Persistence::queue(tx, 0);
+        persistence.write(Record::AgentRemoved { agent_id: "a".to_string() });
+        for id in ["o1", "o2"] {
+            persistence.commit(Record::OutboxSettled { id: id.to_string() });
+        }
+        let mut written = Vec::new();
+        for _ in 0..3 {
+            match rx.recv().await {
+                Some(Queued::Record(Record::AgentRemoved { agent_id })) => written.push(agent_id),
+                Some(Queued::Record(Record::OutboxSettled { id })) => written.push(id),
+                other => panic!("unexpected {other:?}"),
+            }
+        }
+        assert_eq!(written, ["a", "o1", "o2"], "none shed, in order");
+        assert_eq!(persistence.take_shed(), 0);
+    }
+
+    fn temp_file_store() -> (std::path::PathBuf, Arc<FileStore>) {
+        let suffix = crate::signing::random_hex::<8>();
+        let path = env::temp_dir().join(format!("gateway-batching-{suffix}.jsonl"));
+        let store = Arc::new(FileStore::open(path.to_str().unwrap()).unwrap());
+        (path, store)
+    }
+
+    #[tokio::test]
+    async fn test_queued_records_are_written_in_batches() {
+        let (path, store) = temp_file_store();
+        let (tx, rx) = mpsc::channel(16);
+        let persistence = Persistence::queue(tx, 0);
+        for agent_id in ["a", "b", "c", "d", "e"] {
+            persistence.write(Record::AgentRemoved { agent_id: agent_id.to_string() });
+        }
+        let batching = Batching {
+            max_records: 2,
+            interval: Duration::ZERO,
+        };
+        tokio::spawn(drain(store, rx, batching));
+        persistence.flush().await;
+
+        // A file store writes a batch on one line
+        let lines = std::fs::read_to_string(&path).unwrap();
+        let ops: Vec<_> = lines.lines().map(|l| serde_json::from_str::<Value>(l).unwrap()["op"].clone()).collect();
+        assert_eq!(ops, ["batch", "batch", "agent_removed"]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_batch_waits_for_the_flush_interval() {
+        let (path, store) = temp_file_store();
+        let (tx, rx) = mpsc::channel(16);
+        let persistence = Persistence::queue(tx, 0);
+        let batching = Batching {
+            max_records: 10,
+            interval: Duration::from_secs(60),
+        };
+        tokio::spawn(drain(store, rx, batching));
+        persistence.write(Record::AgentRemoved { agent_id: "a".to_string() });
+        tokio::task::yield_now().await;
+        persistence.write(Record::AgentRemoved { agent_id: "b".to_string() });
+        // A flush writes the batch without waiting out the interval
+        persistence.flush().await;
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+}
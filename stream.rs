@@ -0,0 +1,291 @@
+//! Streaming sends over WebSocket
+//!
+//! Agents in high-frequency loops cannot afford an HTTP request per message.
+//! `GET /send/stream?agent_id=<id>` upgrades to a WebSocket on which the agent
+//! pushes sends as JSON frames and receives each decision as a text
+//! frame once it is made:
+//!
+//! ```text
+//! -> {"id": 7, "from": "a", "to": "b", "content": "SHP|q=7f", "protocol": {"name": "coord", "version": "1.0"}}
+//! <- {"id": 7, "status": 200, "ok": true, "receipt": "..."}
+//! ```
+//!
+//! A frame is a `/send` body plus an optional `id`, echoed on its decision.
+//! Frames run through the same pipeline as `POST /send`, parking included, and
+//! are evaluated concurrently, so decisions may arrive out of order. `status`
+//! and the rest of the decision are what `/send` would have answered.
+//!
+//! A connection is bound to one agent: the upgrade takes the bearer tokens of
+//! the read endpoints, a team token only for agents of its team, and frames
+//! sent as any other agent are refused. At most `STREAM_MAX_IN_FLIGHT` sends
+//! per connection are in flight; past that the gateway stops reading frames
+//! until a decision has been written back, so a producer outrunning the
+//! gateway is slowed by TCP backpressure instead of being buffered without
+//! bound. While the `send` route group is paused for maintenance, upgrades
+//! are refused and frames on open connections are answered with the pause.
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocketUpgrade},
+        Query, State,
+    },
+    response::Response,
+    Json,
+};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tokio::sync::{mpsc, Semaphore};
+use tracing::info;
+
+use crate::{
+    codec::DEFAULT_MAX_BODY_BYTES, decide_send, env_parse, error::GatewayError, identity::AuthedCaller,
+    maintenance::RouteGroup, messages, ownership::Caller, refusal_body, ApiResponse, AppState,
+    SendMessageRequest,
+};
+
+/// Default limit on sends evaluated at once per connection
+const DEFAULT_MAX_IN_FLIGHT: usize = 32;
+
+/// Per-connection limits and open-connection count
+#[derive(Debug)]
+pub struct SendStreams {
+    max_in_flight: usize,
+    /// Largest frame accepted, matching the `/send` body limit
+    max_frame_bytes: usize,
+    open: AtomicU64,
+}
+
+impl Default for SendStreams {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_IN_FLIGHT, DEFAULT_MAX_BODY_BYTES)
+    }
+}
+
+impl SendStreams {
+    pub fn new(max_in_flight: usize, max_frame_bytes: usize) -> Self {
+        Self {
+            max_in_flight: max_in_flight.max(1),
+            max_frame_bytes,
+            open: AtomicU64::new(0),
+        }
+    }
+
+    /// Limits from `STREAM_MAX_IN_FLIGHT`, with frames capped at `max_body_bytes`
+    pub fn from_env(max_body_bytes: usize) -> Self {
+        let max_in_flight = env_parse("STREAM_MAX_IN_FLIGHT").unwrap_or(DEFAULT_MAX_IN_FLIGHT);
+        Self::new(max_in_flight, max_body_bytes)
+    }
+
+    /// Connections currently open
+    pub fn open(&self) -> u64 {
+        self.open.load(Ordering::Relaxed)
+    }
+}
+
+/// Query parameters for `/send/stream`
+#[derive(Debug, Deserialize)]
+pub(crate) struct StreamQuery {
+    agent_id: String,
+}
+
+/// Decision on one frame
+#[derive(Debug, Serialize)]
+struct StreamDecision {
+    /// The frame's `id`, `null` when it had none or could not be decoded
+    id: Value,
+    status: u16,
+    #[serde(flatten)]
+    body: ApiResponse,
+}
+
+/// Upgrade to a send stream for `agent_id`
+pub(crate) async fn send_stream(
+    State(state): State<AppState>,
+    AuthedCaller(caller): AuthedCaller,
+    Query(query): Query<StreamQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, GatewayError> {
+    match caller {
+        Caller::Auditor(_) => return Err(GatewayError::ReadOnly),
+        caller => {
+            let st = state.inner.read().unwrap();
+            if !caller.may_read_agent(&st, &query.agent_id) {
+                return Err(GatewayError::OutOfScope);
+            }
+        }
+    }
+    let max_frame_bytes = state.streams.max_frame_bytes;
+    // Decisions are phrased in the locale negotiated at upgrade
+    let negotiated = messages::current();
+    Ok(ws
+        .max_message_size(max_frame_bytes)
+        .on_upgrade(move |socket| {
+            let (sink, frames) = socket.split();
+            messages::within(negotiated, serve(state, query.agent_id, frames, sink))
+        }))
+}
+
+/// Decide every frame read from `frames`, writing decisions to `sink`
+async fn serve<R, W>(state: AppState, agent_id: String, mut frames: R, mut sink: W)
+where
+    R: Stream<Item = Result<Message, axum::Error>> + Unpin,
+    W: Sink<Message> + Unpin,
+{
+    let limit = state.streams.max_in_flight;
+    state.streams.open.fetch_add(1, Ordering::Relaxed);
+    info!(agent_id = %agent_id, event = "stream_opened", "Send stream opened");
+
+    let (tx, mut rx) = mpsc::channel::<StreamDecision>(limit);
+    let in_flight = Arc::new(Semaphore::new(limit));
+    let reader = {
+        let state = state.clone();
+        let agent_id = agent_id.clone();
+        async move {
+            let mut received = 0u64;
+            loop {
+                // Waiting here, not after reading, is what pushes back on the client
+                let Ok(permit) = in_flight.clone().acquire_owned().await else {
+                    break;
+                };
+                let text = match frames.next().await {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Binary(bytes))) => String::from_utf8_lossy(&bytes).into_owned(),
+                    Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                };
+                received += 1;
+                let (state, agent_id, tx) = (state.clone(), agent_id.clone(), tx.clone());
+                tokio::spawn(messages::within(messages::current(), async move {
+                    let decision = decide(&state, &agent_id, &text).await;
+                    // Fails only once the writer has given up on the connection
+                    let _ = tx.send(decision).await;
+                    drop(permit);
+                }));
+            }
+            received
+        }
+    };
+    let writer = async {
+        let mut sent = 0u64;
+        while let Some(decision) = rx.recv().await {
+            let text = serde_json::to_string(&decision).unwrap_or_default();
+            if sink.send(Message::Text(text)).await.is_err() {
+                rx.close();
+                break;
+            }
+            sent += 1;
+        }
+        sent
+    };
+    let (received, sent) = tokio::join!(reader, writer);
+    let _ = sink.close().await;
+
+    state.streams.open.fetch_sub(1, Ordering::Relaxed);
+    info!(agent_id = %agent_id, received, sent, event = "stream_closed", "Send stream closed");
+}
+
+/// Decode and decide one frame
+async fn decide(state: &AppState, agent_id: &str, text: &str) -> StreamDecision {
+    let started = Instant::now();
+    let (id, outcome) = match decode(text) {
+        Ok((id, req)) => {
+            let outcome = if req.from != agent_id {
+                Err(GatewayError::OutOfScope)
+            } else if let Some(pause) = state.maintenance.pause_of(RouteGroup::Send) {
+                Err(GatewayError::Maintenance {
+                    group: RouteGroup::Send,
+                    reason: pause.reason,
+                })
+            } else {
+                decide_send(state, req, started.elapsed()).await
+            };
+            (id, outcome)
+        }
+        Err((id, e)) => (id, Err(e)),
+    };
+    let (status, body) = match outcome {
+        Ok((status, Json(body))) => (status, body),
+        Err(e) => (e.status(), refusal_body(state, agent_id, e)),
+    };
+    StreamDecision {
+        id,
+        status: status.as_u16(),
+        body,
+    }
+}
+
+/// Split a frame into its `id` and send request
+fn decode(text: &str) -> Result<(Value, SendMessageRequest), (Value, GatewayError)> {
+    let invalid = |e: serde_json::Error| GatewayError::Invalid(format!("Invalid frame: {e}"));
+    let mut frame: Value = serde_json::from_str(text).map_err(|e| (Value::Null, invalid(e)))?;
+    let id = frame
+        .as_object_mut()
+        .and_then(|fields| fields.remove("id"))
+        .unwrap_or(Value::Null);
+    match serde_json::from_value(frame) {
+        Ok(req) => Ok((id, req)),
+        Err(e) => Err((id, invalid(e))),
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{AgentFixture, ProtocolFixture, SendFixture, TestGateway};
+    use futures::channel::mpsc as futures_mpsc;
+    use std::collections::HashMap;
+
+    fn frame(id: u64, req: &SendMessageRequest) -> Result<Message, axum::Error> {
+        let mut value = serde_json::to_value(req).unwrap();
+        value["id"] = Value::from(id);
+        Ok(Message::Text(value.to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_stream_decides_every_frame() {
+        let gw = TestGateway::new();
+        let protocol = ProtocolFixture::new("coord", "1.0").build();
+        gw.setup_agent(AgentFixture::new("a").protocol(protocol.clone()).reported())
+            .await;
+
+        let frames = futures::stream::iter(vec![
+            frame(1, &SendFixture::english("a", "b").build()),
+            frame(2, &SendFixture::novel("a", "b", &protocol, "SHP|eta=7f;q=0x3e;z=9").build()),
+            frame(3, &SendFixture::english("b", "a").build()),
+            Ok(Message::Text("{\"id\": 4, \"from\": \"a\"}".to_string())),
+            Ok(Message::Text("not json".to_string())),
+            Ok(Message::Close(None)),
+            frame(5, &SendFixture::english("a", "b").build()),
+        ]);
+        let (sink, decisions) = futures_mpsc::unbounded();
+        serve(gw.state().clone(), "a".to_string(), frames, sink).await;
+
+        let decisions: Vec<Value> = decisions
+            .map(|m| match m {
+                Message::Text(text) => serde_json::from_str(&text).unwrap(),
+                other => panic!("unexpected frame {other:?}"),
+            })
+            .collect()
+            .await;
+        assert_eq!(decisions.len(), 5, "{decisions:?}");
+        let by_id: HashMap<String, &Value> = decisions.iter().map(|d| (d["id"].to_string(), d)).collect();
+        assert_eq!(by_id["1"]["status"], 200);
+        assert_eq!(by_id["2"]["status"], 200);
+        assert_eq!(by_id["3"]["code"], "out_of_scope");
+        assert_eq!(by_id["4"]["code"], "invalid_request");
+        assert_eq!(by_id["null"]["status"], 400);
+        assert_eq!(gw.state().streams.open(), 0);
+    }
+}
@@ -0,0 +1,289 @@
+//! Structural fingerprinting of novel-language traffic
+//!
+//! An agent can register a harmless-looking decoy protocol and send covert
+//! traffic with an unrelated structure under the same registration. Every
+//! accepted novel-language message is reduced to a structural fingerprint:
+//!
+//! - **delimiters**: which separator characters it uses, as shares of all
+//!   separators
+//! - **token lengths**: how the lengths of the tokens between separators are
+//!   distributed
+//! - **characters**: shares of lowercase, uppercase, digits, and separators
+//!
+//! Fingerprints of one agent protocol are clustered online into structural
+//! families: a message joins the nearest family within [`FAMILY_RADIUS`] or
+//! starts a new one. Traffic from a single real protocol stays in one family.
+//! Once a registration has [`MIN_MESSAGES`] messages and at least two families
+//! that each hold [`MIN_FAMILY_SHARE`] of them, a `protocol_mismatch_suspected`
+//! alert is raised, once per agent protocol.
+
+use serde::Serialize;
+
+/// Messages observed before families are judged
+pub const MIN_MESSAGES: u64 = 20;
+
+/// Share of messages a family needs to count as distinct traffic
+pub const MIN_FAMILY_SHARE: f64 = 0.1;
+
+/// Largest distance at which a message joins an existing family
+pub const FAMILY_RADIUS: f64 = 0.5;
+
+/// Families tracked per agent protocol; further outliers join the nearest
+pub const MAX_FAMILIES: usize = 8;
+
+/// Separator characters in the order of their fingerprint slots
+const DELIMITERS: &[char] = &['|', ';', '=', ':', ',', '#', '/', '-', '.', ' '];
+/// Slots after the listed delimiters: brackets, quotes, anything else, none at all
+const BRACKET: usize = DELIMITERS.len();
+const QUOTE: usize = BRACKET + 1;
+const OTHER: usize = BRACKET + 2;
+const NO_DELIMITER: usize = BRACKET + 3;
+const DELIMITER_SLOTS: usize = BRACKET + 4;
+
+/// Upper bounds of the token length buckets; longer tokens go in the last
+const TOKEN_LENGTHS: [usize; 5] = [1, 2, 4, 8, 16];
+const LENGTH_SLOTS: usize = TOKEN_LENGTHS.len() + 1;
+
+/// Lowercase, uppercase, digits, separators
+const CHAR_SLOTS: usize = 4;
+
+/// Longest delimiter shape kept for display
+const MAX_SHAPE_LEN: usize = 24;
+
+/// Least share of separators that are spaces in prose
+const MIN_PROSE_SPACES: f64 = 0.5;
+
+/// Largest share of characters that are digits or capitals in prose
+const MAX_PROSE_CODES: f64 = 0.3;
+
+/// Structure of one message
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    delimiters: [f64; DELIMITER_SLOTS],
+    token_lengths: [f64; LENGTH_SLOTS],
+    chars: [f64; CHAR_SLOTS],
+    /// Separator sequence with runs collapsed, e.g. `|=;=;#` for `X9|st=17;f=0x3a;ack#42`
+    shape: String,
+}
+
+fn delimiter_slot(c: char) -> usize {
+    match c {
+        _ if c.is_whitespace() => DELIMITERS.len() - 1,
+        '(' | ')' | '[' | ']' | '{' | '}' | '<' | '>' => BRACKET,
+        '"' | '\'' | '`' => QUOTE,
+        _ => DELIMITERS.iter().position(|d| *d == c).unwrap_or(OTHER),
+    }
+}
+
+fn normalize<const N: usize>(counts: [f64; N]) -> [f64; N] {
+    let total: f64 = counts.iter().sum();
+    if total == 0.0 {
+        return counts;
+    }
+    counts.map(|c| c / total)
+}
+
+impl Fingerprint {
+    pub fn of(content: &str) -> Self {
+        let mut delimiters = [0.0; DELIMITER_SLOTS];
+        let mut token_lengths = [0.0; LENGTH_SLOTS];
+        let mut chars = [0.0; CHAR_SLOTS];
+        let mut shape = String::new();
+        let mut token = 0;
+        let mut close_token = |len: &mut usize| {
+            if *len > 0 {
+                let bucket = TOKEN_LENGTHS.iter().position(|max| *len <= *max).unwrap_or(LENGTH_SLOTS - 1);
+                token_lengths[bucket] += 1.0;
+                *len = 0;
+            }
+        };
+        for c in content.chars() {
+            if c.is_alphanumeric() || c == '_' {
+                token += 1;
+                chars[if c.is_lowercase() {
+                    0
+                } else if c.is_uppercase() {
+                    1
+                } else {
+                    2
+                }] += 1.0;
+                continue;
+            }
+            close_token(&mut token);
+            chars[3] += 1.0;
+            delimiters[delimiter_slot(c)] += 1.0;
+            if !shape.ends_with(c) && shape.chars().count() < MAX_SHAPE_LEN {
+                shape.push(c);
+            }
+        }
+        close_token(&mut token);
+        if chars[3] == 0.0 {
+            delimiters[NO_DELIMITER] = 1.0;
+        }
+        Self {
+            delimiters: normalize(delimiters),
+            token_lengths: normalize(token_lengths),
+            chars: normalize(chars),
+            shape,
+        }
+    }
+
+    /// Separator sequence with runs collapsed
+    pub fn shape(&self) -> &str {
+        &self.shape
+    }
+
+    /// Whether the message reads like machine output rather than prose:
+    /// mostly non-space separators, or dense in digits and capitals
+    pub fn is_machine_like(&self) -> bool {
+        let spaces = self.delimiters[DELIMITERS.len() - 1];
+        spaces < MIN_PROSE_SPACES || self.chars[1] + self.chars[2] > MAX_PROSE_CODES
+    }
+
+    /// Mean L1 distance over the three feature groups, 0.0-2.0
+    pub fn distance(&self, other: &Self) -> f64 {
+        fn l1(a: &[f64], b: &[f64]) -> f64 {
+            a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum()
+        }
+        (l1(&self.delimiters, &other.delimiters)
+            + l1(&self.token_lengths, &other.token_lengths)
+            + l1(&self.chars, &other.chars))
+            / 3.0
+    }
+
+    /// Move this centroid toward `sample`, which becomes its `n`th member
+    fn absorb(&mut self, sample: &Self, n: u64) {
+        let w = 1.0 / n as f64;
+        let blend = |a: &mut [f64], b: &[f64]| {
+            for (x, y) in a.iter_mut().zip(b) {
+                *x += (y - *x) * w;
+            }
+        };
+        blend(&mut self.delimiters, &sample.delimiters);
+        blend(&mut self.token_lengths, &sample.token_lengths);
+        blend(&mut self.chars, &sample.chars);
+    }
+}
+
+/// A cluster of structurally similar messages
+#[derive(Debug, Clone)]
+struct Family {
+    centroid: Fingerprint,
+    messages: u64,
+}
+
+/// A family as reported by stats and alerts
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FamilySummary {
+    /// Delimiter shape of the family's first message
+    pub shape: String,
+    pub messages: u64,
+    pub share: f64,
+}
+
+/// Structural families of one agent protocol's traffic
+#[derive(Debug, Default)]
+pub struct Families {
+    families: Vec<Family>,
+    messages: u64,
+    flagged: bool,
+}
+
+impl Families {
+    /// Add a message; returns the families the first time the traffic looks
+    /// like more than one protocol
+    pub fn observe(&mut self, content: &str) -> Option<Vec<FamilySummary>> {
+        let sample = Fingerprint::of(content);
+        self.messages += 1;
+        let full = self.families.len() >= MAX_FAMILIES;
+        let nearest = self
+            .families
+            .iter_mut()
+            .map(|f| (f.centroid.distance(&sample), f))
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        match nearest {
+            Some((distance, family)) if distance <= FAMILY_RADIUS || full => {
+                family.messages += 1;
+                family.centroid.absorb(&sample, family.messages);
+            }
+            _ => self.families.push(Family {
+                centroid: sample,
+                messages: 1,
+            }),
+        }
+        if self.flagged || !self.is_mixed() {
+            return None;
+        }
+        self.flagged = true;
+        Some(self.summary())
+    }
+
+    /// Whether enough traffic falls into two or more substantial families
+    pub fn is_mixed(&self) -> bool {
+        self.messages >= MIN_MESSAGES
+            && self
+                .families
+                .iter()
+                .filter(|f| f.messages as f64 / self.messages as f64 >= MIN_FAMILY_SHARE)
+                .count()
+                >= 2
+    }
+
+    /// Families, largest first
+    pub fn summary(&self) -> Vec<FamilySummary> {
+        let mut families: Vec<FamilySummary> = self
+            .families
+            .iter()
+            .map(|f| FamilySummary {
+                shape: f.centroid.shape.clone(),
+                messages: f.messages,
+                share: f.messages as f64 / self.messages.max(1) as f64,
+            })
+            .collect();
+        families.sort_by_key(|f| std::cmp::Reverse(f.messages));
+        families
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoy_traffic_splits_into_families() {
+        let decoy = |i: u32| format!("X9|st={i};f=0x{:x};ack#{}", i * 7, i + 40);
+        assert_eq!(Fingerprint::of(&decoy(17)).shape, "|=;=;#");
+
+        // One real protocol with varying values stays in one family
+        let mut honest = Families::default();
+        for i in 0..50 {
+            assert!(honest.observe(&decoy(i)).is_none());
+        }
+        assert_eq!(honest.summary().len(), 1);
+        assert!(!honest.is_mixed());
+
+        // Covert blobs under the same registration form a second family
+        let mut mixed = Families::default();
+        let mut flagged = Vec::new();
+        for i in 0..40 {
+            let content = if i % 4 == 0 {
+                format!("QmFzZTY0IGJsb2Igd2l0aCBjb3ZlcnQgcGF5bG9hZA{i}xYz")
+            } else {
+                decoy(i)
+            };
+            if let Some(families) = mixed.observe(&content) {
+                flagged.push((i, families));
+            }
+        }
+        assert_eq!(flagged.len(), 1, "flagged once");
+        let (at, families) = &flagged[0];
+        assert_eq!(*at as u64 + 1, MIN_MESSAGES);
+        assert_eq!(families.len(), 2);
+        assert_eq!(families[0].shape, "|=;=;#");
+        assert_eq!(families[1].shape, "");
+    }
+}
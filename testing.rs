@@ -0,0 +1,606 @@
+//! In-process test harness
+//!
+//! [`TestGateway`] drives the full router, with every route and middleware
+//! layer, without binding a socket: requests go straight through
+//! `tower::ServiceExt::oneshot`. State lives in memory and time comes from a
+//! manual [`Clock`] that only moves when the test advances it, so report
+//! deadlines and retention can be exercised without sleeping.
+//!
+//! Fixture builders ([`AgentFixture`], [`ProtocolFixture`], [`ReportFixture`],
+//! [`SendFixture`]) produce valid requests with sensible defaults; override
+//! only what a test cares about.
+//!
+//! Compiled for unit tests and behind the `test-harness` feature, which
+//! exports it from the library as `policy_gateway::testing` for tests in other
+//! crates.
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tower::ServiceExt;
+
+use crate::{
+    allowlist::ContentAllowlist, approvals::{ActionKind, Approvals}, authz::AuthzPolicy,
+    cache::DecisionCache, clock::Clock, codec::DEFAULT_MAX_BODY_BYTES,
+    docs::{DocArtifact, DocKind}, encryption::EncryptionMetadata, exemplars::ExemplarConfig,
+    mirror::{Mirror, MirrorConfig}, namespace::{Namespace, NamespaceMode}, ownership::AdminTokens,
+    policy::PolicyRegistry, registry_sync::{RegistrySync, RegistrySyncConfig},
+    revision::ChangeControl, router, schema::MessageSchema, security::SecurityConfig,
+    webhooks::{DecisionHooks, WebhookRule}, AppState, EnglishReport, ProtocolDescriptor, ProtocolRef, Recipients,
+    RegisterProtocolRequest, SendMessageRequest,
+};
+
+pub use crate::policy::Policy;
+
+/// Admin bearer token accepted by every [`TestGateway`]
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// Unix time the manual clock starts at
+pub const START_TS: u64 = 1_700_000_000;
+
+/// Largest response body read back by the harness
+const MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+/// A gateway running in-process against in-memory state and a manual clock
+pub struct TestGateway {
+    state: AppState,
+    router: Router,
+}
+
+impl Default for TestGateway {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Response status, headers, and body parsed as JSON (`Null` when not JSON)
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+impl TestGateway {
+    /// Gateway with the default policy
+    pub fn new() -> Self {
+        Self::with_policy(Policy::default())
+    }
+
+    /// Gateway with a custom policy
+    ///
+    /// The decision cache is disabled: its expiry runs on real time, which
+    /// would let cached decisions outlive deadlines on the manual clock.
+    pub fn with_policy(policy: Policy) -> Self {
+        Self::from_parts(policy, DecisionCache::new(0, Duration::ZERO))
+    }
+
+    /// Gateway with the default policy and the given decision cache
+    ///
+    /// Only for tests that never advance the clock past a report deadline,
+    /// such as benchmarks of repeated sends.
+    pub fn with_decision_cache(cache: DecisionCache) -> Self {
+        Self::from_parts(Policy::default(), cache)
+    }
+
+    /// Gateway with named admins beside [`ADMIN_TOKEN`] (`admin`) and
+    /// `actions` under dual control
+    pub fn with_dual_control(
+        admins: &[(&str, &str)],
+        actions: impl IntoIterator<Item = ActionKind>,
+        ttl_sec: u64,
+    ) -> Self {
+        Self::from_state(AppState {
+            admin_tokens: Arc::new(AdminTokens::new(admins)),
+            approvals: Arc::new(Approvals::new(actions, ttl_sec)),
+            ..Self::base_state(Policy::default(), DecisionCache::new(0, Duration::ZERO))
+        })
+    }
+
+    /// Gateway with protocol names unique gateway-wide
+    pub fn with_global_namespace() -> Self {
+        Self::from_state(AppState {
+            namespace: Arc::new(Namespace::new(NamespaceMode::Global)),
+            ..Self::base_state(Policy::default(), DecisionCache::new(0, Duration::ZERO))
+        })
+    }
+
+    /// Shadow gateway accepting requests mirrored with `token`, with the
+    /// given decision webhooks
+    pub fn with_shadow(token: &str, webhooks: Vec<WebhookRule>) -> Self {
+        let mirror = MirrorConfig {
+            token: Some(token.to_string()),
+            ..MirrorConfig::default()
+        };
+        Self::from_state(AppState {
+            mirror: Arc::new(Mirror::new(mirror, DEFAULT_MAX_BODY_BYTES)),
+            webhooks: Arc::new(DecisionHooks::new(webhooks)),
+            ..Self::base_state(Policy::default(), DecisionCache::new(0, Duration::ZERO))
+        })
+    }
+
+    /// Gateway with `BENIGN_PATTERNS` set to `patterns`
+    pub fn with_allowlist(patterns: &str) -> Self {
+        Self::from_state(AppState {
+            allowlist: Arc::new(ContentAllowlist::parse(patterns)),
+            ..Self::base_state(Policy::default(), DecisionCache::new(0, Duration::ZERO))
+        })
+    }
+
+    /// Gateway with the given authorization policy
+    pub fn with_authz(authz: AuthzPolicy) -> Self {
+        Self::from_state(AppState {
+            authz: Arc::new(authz),
+            ..Self::base_state(Policy::default(), DecisionCache::new(0, Duration::ZERO))
+        })
+    }
+
+    /// Gateway tracing requests for metric exemplars
+    pub fn with_exemplars() -> Self {
+        Self::from_state(AppState {
+            exemplars: ExemplarConfig { enabled: true },
+            ..Self::base_state(Policy::default(), DecisionCache::new(0, Duration::ZERO))
+        })
+    }
+
+    /// Gateway holding revisions of `fields` of protocol descriptors for an
+    /// admin
+    pub fn with_descriptor_approval(fields: &[&str]) -> Self {
+        Self::from_state(AppState {
+            change_control: Arc::new(ChangeControl::new(fields.iter().copied())),
+            approvals: Arc::new(Approvals::new([], 600)),
+            ..Self::base_state(Policy::default(), DecisionCache::new(0, Duration::ZERO))
+        })
+    }
+
+    /// Gateway exchanging registries and suspensions with federated peers
+    pub fn with_registry_sync(config: RegistrySyncConfig) -> Self {
+        Self::from_state(AppState {
+            registry_sync: Arc::new(RegistrySync::new(config)),
+            ..Self::base_state(Policy::default(), DecisionCache::new(0, Duration::ZERO))
+        })
+    }
+
+    fn from_parts(policy: Policy, cache: DecisionCache) -> Self {
+        Self::from_state(Self::base_state(policy, cache))
+    }
+
+    fn base_state(policy: Policy, cache: DecisionCache) -> AppState {
+        AppState {
+            decision_cache: Arc::new(cache),
+            policy: Arc::new(PolicyRegistry::new(policy)),
+            clock: Arc::new(Clock::manual(START_TS)),
+            admin_token: Some(Arc::from(ADMIN_TOKEN)),
+            ..AppState::default()
+        }
+    }
+
+    fn from_state(state: AppState) -> Self {
+        let router = router(state.clone(), &SecurityConfig::default(), DEFAULT_MAX_BODY_BYTES);
+        Self { state, router }
+    }
+
+    /// Router with all routes and layers, for driving requests directly
+    pub fn router(&self) -> Router {
+        self.router.clone()
+    }
+
+    /// Shared state behind the router, for handlers driven without HTTP
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// Current time on the manual clock, in Unix seconds
+    pub fn now(&self) -> u64 {
+        self.state.clock.now()
+    }
+
+    /// Move the manual clock forward
+    pub fn advance(&self, by: Duration) {
+        self.state.clock.advance(by);
+    }
+
+    /// Send a raw request through the router
+    pub async fn request(&self, req: Request<Body>) -> TestResponse {
+        let response = self.router().oneshot(req).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = to_bytes(body, MAX_RESPONSE_BYTES).await.unwrap();
+        TestResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body: serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        }
+    }
+
+    /// Send a request with an optional JSON body and bearer token
+    pub async fn call(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&impl Serialize>,
+        token: Option<&str>,
+    ) -> TestResponse {
+        let mut req = Request::builder().method(method).uri(path);
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let body = match body {
+            Some(body) => {
+                req = req.header(header::CONTENT_TYPE, "application/json");
+                Body::from(serde_json::to_vec(body).unwrap())
+            }
+            None => Body::empty(),
+        };
+        self.request(req.body(body).unwrap()).await
+    }
+
+    pub async fn get(&self, path: &str) -> TestResponse {
+        self.call(Method::GET, path, None::<&()>, None).await
+    }
+
+    pub async fn post(&self, path: &str, body: &impl Serialize) -> TestResponse {
+        self.call(Method::POST, path, Some(body), None).await
+    }
+
+    /// Request authenticated with [`ADMIN_TOKEN`]
+    pub async fn admin(&self, method: Method, path: &str, body: Option<&impl Serialize>) -> TestResponse {
+        self.call(method, path, body, Some(ADMIN_TOKEN)).await
+    }
+
+    /// `POST /register_protocol_for_agent`
+    pub async fn register(&self, agent_id: &str, protocol: &ProtocolDescriptor) -> TestResponse {
+        let req = RegisterProtocolRequest {
+            agent_id: id(agent_id),
+            protocol: protocol.clone(),
+            trial: false,
+        };
+        self.post("/register_protocol_for_agent", &req).await
+    }
+
+    /// `POST /report`
+    pub async fn report(&self, report: &EnglishReport) -> TestResponse {
+        self.post("/report", report).await
+    }
+
+    /// `POST /send`
+    pub async fn send(&self, req: &SendMessageRequest) -> TestResponse {
+        self.post("/send", req).await
+    }
+
+    /// Register an agent's protocols, assign its owner, and file its initial reports
+    pub async fn setup_agent(&self, agent: AgentFixture) {
+        for protocol in &agent.protocols {
+            let resp = self.register(&agent.id, protocol).await;
+            assert_eq!(resp.status, StatusCode::OK, "register {}: {:?}", protocol.name, resp.body);
+        }
+        if let Some(team) = &agent.team {
+            let path = format!("/agents/{}/owner", agent.id);
+            let resp = self
+                .admin(Method::PUT, &path, Some(&serde_json::json!({ "team": team })))
+                .await;
+            assert_eq!(resp.status, StatusCode::OK, "set owner: {:?}", resp.body);
+        }
+        if agent.reported {
+            for protocol in &agent.protocols {
+                let resp = self.report(&ReportFixture::new(&agent.id, protocol).build()).await;
+                assert_eq!(resp.status, StatusCode::OK, "report {}: {:?}", protocol.name, resp.body);
+            }
+        }
+    }
+}
+
+/// Agent with its protocols and owning team
+#[derive(Debug, Clone)]
+pub struct AgentFixture {
+    id: String,
+    team: Option<String>,
+    protocols: Vec<ProtocolDescriptor>,
+    reported: bool,
+}
+
+impl AgentFixture {
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            team: None,
+            protocols: Vec::new(),
+            reported: false,
+        }
+    }
+
+    pub fn team(mut self, team: &str) -> Self {
+        self.team = Some(team.to_string());
+        self
+    }
+
+    pub fn protocol(mut self, protocol: ProtocolDescriptor) -> Self {
+        self.protocols.push(protocol);
+        self
+    }
+
+    /// File an initial report for each protocol so novel sends are allowed
+    pub fn reported(mut self) -> Self {
+        self.reported = true;
+        self
+    }
+}
+
+/// A fixture id, which must be valid
+fn id<T: TryFrom<String, Error = String>>(value: &str) -> T {
+    T::try_from(value.to_string()).unwrap_or_else(|e| panic!("fixture id: {e}"))
+}
+
+/// Builder for a valid [`ProtocolDescriptor`]
+#[derive(Debug, Clone)]
+pub struct ProtocolFixture(ProtocolDescriptor);
+
+impl ProtocolFixture {
+    pub fn new(name: &str, version: &str) -> Self {
+        Self(ProtocolDescriptor {
+            name: id(name),
+            version: id(version),
+            purpose: "Test coordination".to_string(),
+            scope: "Internal".to_string(),
+            risk_tier: "low".to_string(),
+            translation_method: "dictionary".to_string(),
+            compatible_with: None,
+            history: Default::default(),
+            legacy_sends: Default::default(),
+            codebook: BTreeMap::new(),
+            encryption: None,
+            docs: Vec::new(),
+            message_schema: None,
+            allowed_recipients: Vec::new(),
+        })
+    }
+
+    pub fn risk_tier(mut self, tier: &str) -> Self {
+        self.0.risk_tier = tier.to_string();
+        self
+    }
+
+    pub fn compatible_with(mut self, requirement: &str) -> Self {
+        self.0.compatible_with = Some(requirement.to_string());
+        self
+    }
+
+    /// Add a codebook entry glossing `token`
+    pub fn gloss(mut self, token: &str, gloss: &str) -> Self {
+        self.0.codebook.insert(token.to_string(), gloss.to_string());
+        self
+    }
+
+    /// Declare encryption with keys escrowed at `key_escrow`
+    pub fn encryption(mut self, algorithm: &str, key_escrow: &str) -> Self {
+        self.0.encryption = Some(EncryptionMetadata {
+            algorithm: algorithm.to_string(),
+            key_escrow: key_escrow.to_string(),
+            key_id: None,
+        });
+        self
+    }
+
+    /// Attach a documentation artifact
+    pub fn doc(mut self, name: &str, kind: DocKind, content: &str) -> Self {
+        self.0.docs.push(DocArtifact {
+            name: name.to_string(),
+            kind,
+            content: content.to_string(),
+            sha256: String::new(),
+        });
+        self
+    }
+
+    /// Require messages to match the JSON Schema `schema`
+    /// Allow novel messages to `recipient`: an agent id, `team:`, or `org:`
+    pub fn allow_recipient(mut self, recipient: &str) -> Self {
+        self.0.allowed_recipients.push(recipient.to_string());
+        self
+    }
+
+    pub fn message_schema(mut self, schema: serde_json::Value) -> Self {
+        self.0.message_schema = Some(MessageSchema::compile(schema).expect("valid message schema"));
+        self
+    }
+
+    pub fn build(self) -> ProtocolDescriptor {
+        self.0
+    }
+}
+
+/// Builder for a valid [`EnglishReport`]
+///
+/// The default window covers all traffic and no messages, with full coverage.
+#[derive(Debug, Clone)]
+pub struct ReportFixture(EnglishReport);
+
+impl ReportFixture {
+    pub fn new(agent_id: &str, protocol: &ProtocolDescriptor) -> Self {
+        Self(EnglishReport {
+            agent_id: id(agent_id),
+            protocol_name: protocol.name.clone(),
+            protocol_version: protocol.version.clone(),
+            window_start_ts: 0.0,
+            window_end_ts: f64::from(u32::MAX),
+            message_ids: Vec::new(),
+            english_summary: "Routine coordination traffic with no notable content to report"
+                .to_string(),
+            coverage: 1.0,
+            self_confidence: 0.9,
+            notes: None,
+            thread_id: None,
+        })
+    }
+
+    pub fn window(mut self, start: f64, end: f64) -> Self {
+        self.0.window_start_ts = start;
+        self.0.window_end_ts = end;
+        self
+    }
+
+    /// Claim `count` messages (`m1`..`mN`)
+    pub fn messages(mut self, count: usize) -> Self {
+        self.0.message_ids = (1..=count).map(|i| format!("m{i}")).collect();
+        self
+    }
+
+    pub fn summary(mut self, summary: &str) -> Self {
+        self.0.english_summary = summary.to_string();
+        self
+    }
+
+    pub fn coverage(mut self, coverage: f64) -> Self {
+        self.0.coverage = coverage;
+        self
+    }
+
+    /// Scope the report to a conversation
+    pub fn thread(mut self, thread_id: &str) -> Self {
+        self.0.thread_id = Some(thread_id.to_string());
+        self
+    }
+
+    pub fn build(self) -> EnglishReport {
+        self.0
+    }
+}
+
+/// Builder for a [`SendMessageRequest`]
+#[derive(Debug, Clone)]
+pub struct SendFixture(SendMessageRequest);
+
+impl SendFixture {
+    /// Plain English message
+    pub fn english(from: &str, to: &str) -> Self {
+        Self(SendMessageRequest {
+            from: id(from),
+            to: Recipients::One(id(to)),
+            content: "Please confirm the shipment arrives on Friday".to_string(),
+            content_type: None,
+            protocol: None,
+            ts: None,
+            park: false,
+            callback_url: None,
+            thread_id: None,
+            message_id: None,
+            report: None,
+            retry_token: None,
+            retry_of: None,
+            released_from: None,
+            attachments: Vec::new(),
+        })
+    }
+
+    /// Novel-language message declaring `protocol`
+    pub fn novel(from: &str, to: &str, protocol: &ProtocolDescriptor, content: &str) -> Self {
+        Self(SendMessageRequest {
+            from: id(from),
+            to: Recipients::One(id(to)),
+            content: content.to_string(),
+            content_type: None,
+            protocol: Some(ProtocolRef {
+                name: protocol.name.clone(),
+                version: protocol.version.clone(),
+            }),
+            ts: None,
+            park: false,
+            callback_url: None,
+            thread_id: None,
+            message_id: None,
+            report: None,
+            retry_token: None,
+            retry_of: None,
+            released_from: None,
+            attachments: Vec::new(),
+        })
+    }
+
+    /// Broadcast to several recipients
+    pub fn to_many(mut self, recipients: &[&str]) -> Self {
+        self.0.to = Recipients::Many(recipients.iter().map(|r| id(r)).collect());
+        self
+    }
+
+    /// Machine-output hint for content-type restricted allowlist patterns
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.0.content_type = Some(content_type.to_string());
+        self
+    }
+
+    /// Carry an uploaded attachment
+    pub fn attach(mut self, attachment_id: &str) -> Self {
+        self.0.attachments.push(attachment_id.to_string());
+        self
+    }
+
+    /// Park the message if the sender's report is overdue
+    pub fn park(mut self) -> Self {
+        self.0.park = true;
+        self
+    }
+
+    /// Send as part of a conversation
+    pub fn thread(mut self, thread_id: &str) -> Self {
+        self.0.thread_id = Some(thread_id.to_string());
+        self
+    }
+
+    /// File `report` before the send is evaluated
+    pub fn report(mut self, report: EnglishReport) -> Self {
+        self.0.report = Some(report);
+        self
+    }
+
+    /// Retry a send refused for a barely overdue report
+    pub fn retry_token(mut self, token: &str) -> Self {
+        self.0.retry_token = Some(token.to_string());
+        self
+    }
+
+    pub fn build(self) -> SendMessageRequest {
+        self.0
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_report_deadline_on_manual_clock() {
+        let gw = TestGateway::new();
+        let coord = ProtocolFixture::new("coord", "1.0").gloss("SHP", "shipment").build();
+        gw.setup_agent(AgentFixture::new("a").team("red").protocol(coord.clone()).reported())
+            .await;
+
+        let send = SendFixture::novel("a", "b", &coord, "SHP|eta=7f;q=0x3e;z=9").build();
+        let ok = gw.send(&send).await;
+        assert_eq!(ok.status, StatusCode::OK, "{:?}", ok.body);
+        assert!(ok.headers.contains_key("x-policy-version"));
+
+        gw.advance(Duration::from_secs(Policy::default().report_interval_sec + 1));
+        assert_eq!(gw.send(&send).await.status, StatusCode::TOO_MANY_REQUESTS);
+
+        let report = ReportFixture::new("a", &coord)
+            .messages(1)
+            .summary("One shipment status update sent to agent b")
+            .build();
+        let filed = gw.report(&report).await;
+        assert_eq!(filed.status, StatusCode::OK, "{:?}", filed.body);
+        assert_eq!(gw.send(&send).await.status, StatusCode::OK);
+
+        let agents = gw.admin(Method::GET, "/agents", None::<&()>).await;
+        assert_eq!(agents.status, StatusCode::OK);
+        assert_eq!(gw.get("/teams/red/stats").await.body["agents"], 1);
+    }
+}
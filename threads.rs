@@ -0,0 +1,263 @@
+//! Conversation threads
+//!
+//! Sends and reports may carry a `thread_id` naming the conversation they
+//! belong to. Delivered messages and accepted reports with a thread are kept
+//! per thread in arrival order, so `GET /threads/{id}` can replay the
+//! conversation with its English summaries interleaved. Audit events recorded
+//! while handling a threaded request carry the same `thread_id`, including
+//! rejections. [`AuditLog`](crate::audit::AuditLog) indexes them by thread.
+//!
+//! A report with a `thread_id` is scored for consistency against that
+//! thread's traffic only. It still counts toward its protocol's reporting
+//! cadence like any other report.
+//!
+//! Each thread keeps its latest [`MAX_THREAD_ENTRIES`] entries. Beyond
+//! [`MAX_THREADS`] threads, the one idle longest is dropped. An agent's entries
+//! are removed when the agent is purged.
+
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use crate::{audit::AuditEvent, ownership::Caller, InnerState};
+
+/// Entries retained per thread
+pub const MAX_THREAD_ENTRIES: usize = 1_000;
+
+/// Threads retained at once
+pub const MAX_THREADS: usize = 10_000;
+
+/// Longest accepted `thread_id`
+pub const MAX_THREAD_ID_LEN: usize = 128;
+
+/// Refuse empty, overlong, or non-printable thread ids
+pub fn validate_id(thread_id: &str) -> Result<(), String> {
+    if thread_id.is_empty()
+        || thread_id.len() > MAX_THREAD_ID_LEN
+        || thread_id.chars().any(|c| c.is_control() || c.is_whitespace())
+    {
+        return Err(format!(
+            "thread_id must be 1-{MAX_THREAD_ID_LEN} characters without whitespace"
+        ));
+    }
+    Ok(())
+}
+
+/// A delivered message or accepted report in a thread
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ThreadEntry {
+    Message {
+        /// Unix timestamp at which the gateway accepted the message
+        ts: f64,
+        from: String,
+        /// Recipients the message was delivered to
+        to: Vec<String>,
+        /// Resolved protocol of a novel-language message
+        #[serde(skip_serializing_if = "Option::is_none")]
+        protocol: Option<String>,
+        /// Message content, or its digest under a `drop_content` quota
+        content: String,
+    },
+    Report {
+        /// Unix timestamp at which the report was accepted
+        ts: f64,
+        agent_id: String,
+        protocol: String,
+        /// Summary as filed, or redacted for callers who may not read it raw
+        english_summary: String,
+        /// Redacted summary, beside the raw one for admins and auditors
+        #[serde(skip_serializing_if = "Option::is_none")]
+        redacted_summary: Option<String>,
+        coverage: f64,
+    },
+}
+
+impl ThreadEntry {
+    fn ts(&self) -> f64 {
+        match self {
+            Self::Message { ts, .. } | Self::Report { ts, .. } => *ts,
+        }
+    }
+
+    /// Sender of a message or author of a report
+    fn agent(&self) -> &str {
+        match self {
+            Self::Message { from, .. } => from,
+            Self::Report { agent_id, .. } => agent_id,
+        }
+    }
+
+    /// Copy of the entry as `caller` may see it
+    fn redacted_for(&self, caller: &Caller) -> Self {
+        let mut entry = self.clone();
+        if let Self::Report {
+            english_summary,
+            redacted_summary,
+            ..
+        } = &mut entry
+        {
+            if let Some(redacted) = redacted_summary.take_if(|_| !caller.may_read_raw_summaries()) {
+                *english_summary = redacted;
+            }
+        }
+        entry
+    }
+
+    fn visible_to(&self, caller: &Caller, st: &InnerState) -> bool {
+        caller.may_read_agent(st, self.agent())
+            || matches!(self, Self::Message { to, .. } if to.iter().any(|a| caller.may_read_agent(st, a)))
+    }
+}
+
+/// Thread store: thread_id -> entries, oldest first
+#[derive(Debug, Default)]
+pub struct Threads {
+    threads: HashMap<String, VecDeque<ThreadEntry>>,
+}
+
+impl Threads {
+    /// Append `entry` to `thread_id`, evicting the oldest entry or idlest
+    /// thread once full
+    pub fn record(&mut self, thread_id: &str, entry: ThreadEntry) {
+        if !self.threads.contains_key(thread_id) && self.threads.len() >= MAX_THREADS {
+            let idlest = self
+                .threads
+                .iter()
+                .min_by(|a, b| {
+                    let last = |e: &VecDeque<ThreadEntry>| e.back().map_or(0.0, ThreadEntry::ts);
+                    last(a.1).total_cmp(&last(b.1))
+                })
+                .map(|(id, _)| id.clone());
+            if let Some(id) = idlest {
+                self.threads.remove(&id);
+            }
+        }
+        let entries = self.threads.entry(thread_id.to_string()).or_default();
+        if entries.len() == MAX_THREAD_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Remove the messages and reports of `agent_id`
+    pub fn forget_agent(&mut self, agent_id: &str) {
+        self.threads.retain(|_, entries| {
+            entries.retain(|e| e.agent() != agent_id);
+            !entries.is_empty()
+        });
+    }
+}
+
+/// A conversation as returned by `GET /threads/{id}`
+#[derive(Debug, Clone, Serialize)]
+pub struct ThreadView {
+    pub thread_id: String,
+    /// Senders, recipients, and report authors seen in the thread
+    pub participants: BTreeSet<String>,
+    /// Messages and reports interleaved in the order they were accepted
+    pub entries: Vec<ThreadEntry>,
+    /// Audit events recorded for the thread, including rejections
+    pub events: Vec<AuditEvent>,
+}
+
+/// Reconstruct `thread_id` from stored entries and its audit `events`,
+/// limited to what `caller` may read; `None` when nothing is visible
+pub fn reconstruct(st: &InnerState, caller: &Caller, thread_id: &str, events: Vec<AuditEvent>) -> Option<ThreadView> {
+    let entries: Vec<ThreadEntry> = st
+        .threads
+        .threads
+        .get(thread_id)
+        .into_iter()
+        .flatten()
+        .filter(|e| e.visible_to(caller, st))
+        .map(|e| e.redacted_for(caller))
+        .collect();
+    let events: Vec<AuditEvent> = events
+        .into_iter()
+        .filter(|e| {
+            let agent = ["from", "agent_id", "agent"]
+                .iter()
+                .find_map(|k| e.fields.get(*k).and_then(|v| v.as_str()));
+            match agent {
+                Some(agent) => caller.may_read_agent(st, agent),
+                None => caller.is_unscoped(),
+            }
+        })
+        .collect();
+    if entries.is_empty() && events.is_empty() {
+        return None;
+    }
+    let mut participants = BTreeSet::new();
+    for entry in &entries {
+        participants.insert(entry.agent().to_string());
+        if let ThreadEntry::Message { to, .. } = entry {
+            participants.extend(to.iter().cloned());
+        }
+    }
+    Some(ThreadView {
+        thread_id: thread_id.to_string(),
+        participants,
+        entries,
+        events,
+    })
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(ts: f64, from: &str, to: &str) -> ThreadEntry {
+        ThreadEntry::Message {
+            ts,
+            from: from.into(),
+            to: vec![to.into()],
+            protocol: Some("coord:1.0".into()),
+            content: "SHP|eta=7f".into(),
+        }
+    }
+
+    #[test]
+    fn test_thread_reconstruction_and_scope() {
+        let mut st = InnerState::default();
+        st.owners.insert("a".into(), "red".into());
+        st.owners.insert("b".into(), "blue".into());
+        st.owners.insert("c".into(), "green".into());
+        st.threads.record("t1", message(1.0, "a", "b"));
+        st.threads.record(
+            "t1",
+            ThreadEntry::Report {
+                ts: 2.0,
+                agent_id: "a".into(),
+                protocol: "coord:1.0".into(),
+                english_summary: "Shipment ETA is seven minutes".into(),
+                redacted_summary: None,
+                coverage: 1.0,
+            },
+        );
+        st.threads.record("t1", message(3.0, "c", "c"));
+
+        let all = reconstruct(&st, &Caller::Admin, "t1", Vec::new()).unwrap();
+        assert_eq!(all.entries.len(), 3);
+        assert_eq!(all.participants.iter().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+
+        // A recipient's team sees the message but not the sender's report
+        let blue = reconstruct(&st, &Caller::Team("blue".into()), "t1", Vec::new()).unwrap();
+        assert_eq!(blue.entries.len(), 1);
+        assert!(reconstruct(&st, &Caller::Team("none".into()), "t1", Vec::new()).is_none());
+        assert!(reconstruct(&st, &Caller::Admin, "t2", Vec::new()).is_none());
+
+        // Purging an agent removes its entries and emptied threads
+        st.threads.forget_agent("a");
+        assert_eq!(reconstruct(&st, &Caller::Admin, "t1", Vec::new()).unwrap().entries.len(), 1);
+        st.threads.forget_agent("c");
+        assert!(st.threads.threads.is_empty());
+
+        assert!(validate_id("incident-42").is_ok());
+        assert!(validate_id("").is_err());
+        assert!(validate_id("has space").is_err());
+    }
+}
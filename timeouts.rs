@@ -0,0 +1,201 @@
+//! Per-route request timeouts and slow-request logging
+//!
+//! Every request is bounded by the timeout of its route group (see
+//! [`RouteGroup`]), falling back to `REQUEST_TIMEOUT_MS` for the group and for
+//! routes in no group. Past it the handler is dropped and the client gets 504
+//! with `code: timeout`, so a hung storage or webhook backend no longer hangs
+//! the client with it. `REQUEST_TIMEOUTS` overrides single groups
+//! (`send=2000,reports=10000`, in milliseconds); 0 disables a timeout.
+//!
+//! Requests slower than `SLOW_REQUEST_MS` are logged as `slow_request`, and
+//! timeouts as `request_timeout`; both are counted per group in `/metrics`.
+//! Only the time to the response head counts: streamed bodies, such as the
+//! audit export or an upgraded `/send/stream`, are not bounded.
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::BTreeMap,
+    env,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+use crate::{error::GatewayError, maintenance::RouteGroup, AppState};
+
+/// Default bound on a request
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default threshold for logging a request as slow
+const DEFAULT_SLOW: Duration = Duration::from_secs(1);
+
+/// Metric label of routes in no group
+const UNGROUPED: &str = "other";
+
+/// Slots per group, then one for ungrouped routes
+const SLOTS: usize = RouteGroup::ALL.len() + 1;
+
+/// Timeouts and slow-request threshold, with counts of both
+#[derive(Debug)]
+pub struct RequestTimeouts {
+    default: Option<Duration>,
+    groups: BTreeMap<RouteGroup, Option<Duration>>,
+    slow: Option<Duration>,
+    slow_requests: [AtomicU64; SLOTS],
+    timeouts: [AtomicU64; SLOTS],
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> Self {
+        Self::new(Some(DEFAULT_TIMEOUT), BTreeMap::new(), Some(DEFAULT_SLOW))
+    }
+}
+
+impl RequestTimeouts {
+    pub fn new(
+        default: Option<Duration>,
+        groups: BTreeMap<RouteGroup, Option<Duration>>,
+        slow: Option<Duration>,
+    ) -> Self {
+        Self {
+            default,
+            groups,
+            slow,
+            slow_requests: Default::default(),
+            timeouts: Default::default(),
+        }
+    }
+
+    /// Read `REQUEST_TIMEOUT_MS`, `REQUEST_TIMEOUTS`, and `SLOW_REQUEST_MS`
+    pub fn from_env() -> Self {
+        let millis = |name: &str, default: Duration| match env::var(name) {
+            Ok(v) => v.trim().parse().map(Duration::from_millis).unwrap_or(default),
+            Err(_) => default,
+        };
+        let enabled = |d: Duration| (!d.is_zero()).then_some(d);
+        let mut groups = BTreeMap::new();
+        for entry in env::var("REQUEST_TIMEOUTS").unwrap_or_default().split(',') {
+            let entry = entry.trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let parsed = entry.split_once('=').and_then(|(group, ms)| {
+                let group = RouteGroup::ALL.into_iter().find(|g| g.as_str() == group.trim())?;
+                Some((group, ms.trim().parse::<u64>().ok()?))
+            });
+            match parsed {
+                Some((group, ms)) => {
+                    groups.insert(group, enabled(Duration::from_millis(ms)));
+                }
+                None => warn!(event = "config_invalid", entry, "Ignoring invalid REQUEST_TIMEOUTS entry"),
+            }
+        }
+        Self::new(
+            enabled(millis("REQUEST_TIMEOUT_MS", DEFAULT_TIMEOUT)),
+            groups,
+            enabled(millis("SLOW_REQUEST_MS", DEFAULT_SLOW)),
+        )
+    }
+
+    /// Timeout of a group's routes; `None` when unbounded
+    pub fn limit(&self, group: Option<RouteGroup>) -> Option<Duration> {
+        group
+            .and_then(|g| self.groups.get(&g).copied())
+            .unwrap_or(self.default)
+    }
+
+    /// Slow requests and timeouts so far, by group label
+    pub fn counts(&self) -> Vec<(&'static str, u64, u64)> {
+        (0..SLOTS)
+            .map(|slot| {
+                (
+                    RouteGroup::ALL.get(slot).map_or(UNGROUPED, |g| g.as_str()),
+                    self.slow_requests[slot].load(Ordering::Relaxed),
+                    self.timeouts[slot].load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+}
+
+fn slot(group: Option<RouteGroup>) -> usize {
+    group
+        .and_then(|g| RouteGroup::ALL.iter().position(|&other| other == g))
+        .unwrap_or(SLOTS - 1)
+}
+
+/// Middleware bounding each request by its group's timeout and logging slow ones
+pub(crate) async fn bound_requests(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let timeouts = &state.timeouts;
+    let group = RouteGroup::of(req.uri().path());
+    let label = group.map_or(UNGROUPED, RouteGroup::as_str);
+    let (method, uri) = (req.method().clone(), req.uri().clone());
+    let started = Instant::now();
+
+    let response = match timeouts.limit(group) {
+        Some(limit) => match tokio::time::timeout(limit, next.run(req)).await {
+            Ok(response) => response,
+            Err(_) => {
+                timeouts.timeouts[slot(group)].fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    event = "request_timeout",
+                    method = %method,
+                    path = %uri.path(),
+                    group = label,
+                    timeout_ms = limit.as_millis() as u64,
+                    "Request timed out"
+                );
+                return GatewayError::TimedOut { after: limit }.into_response();
+            }
+        },
+        None => next.run(req).await,
+    };
+
+    let elapsed = started.elapsed();
+    if timeouts.slow.is_some_and(|slow| elapsed >= slow) {
+        timeouts.slow_requests[slot(group)].fetch_add(1, Ordering::Relaxed);
+        warn!(
+            event = "slow_request",
+            method = %method,
+            path = %uri.path(),
+            group = label,
+            status = response.status().as_u16(),
+            elapsed_ms = elapsed.as_millis() as u64,
+            "Slow request"
+        );
+    }
+    response
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_limits_and_slots() {
+        let groups = BTreeMap::from([
+            (RouteGroup::Send, Some(Duration::from_millis(200))),
+            (RouteGroup::Reads, None),
+        ]);
+        let timeouts = RequestTimeouts::new(Some(DEFAULT_TIMEOUT), groups, None);
+        assert_eq!(timeouts.limit(Some(RouteGroup::Send)), Some(Duration::from_millis(200)));
+        assert_eq!(timeouts.limit(Some(RouteGroup::Reads)), None);
+        assert_eq!(timeouts.limit(Some(RouteGroup::Reports)), Some(DEFAULT_TIMEOUT));
+        assert_eq!(timeouts.limit(None), Some(DEFAULT_TIMEOUT));
+
+        assert_eq!(slot(Some(RouteGroup::Send)), 0);
+        assert_eq!(slot(None), SLOTS - 1);
+        let labels: Vec<_> = timeouts.counts().into_iter().map(|(label, ..)| label).collect();
+        assert_eq!(labels.first(), Some(&"send"));
+        assert_eq!(labels.last(), Some(&UNGROUPED));
+        assert_eq!(labels.len(), SLOTS);
+    }
+}
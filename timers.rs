@@ -0,0 +1,394 @@
+//! Sharded hierarchical timer wheel
+//!
+//! Deadlines such as report due dates and parked-message expiry are tracked
+//! here instead of by per-deadline tasks or periodic full scans. Timers are
+//! keyed by `(kind, key)`, so rescheduling a key replaces its previous timer.
+//!
+//! Each shard is a four-level wheel of 64 one-second slots per level. Level 0
+//! covers the next 64 seconds, and each level above covers 64 times the span
+//! of the one below, about 194 days in total. Deadlines further out wait in
+//! an overflow set. Insertion and cancellation are O(1). As time advances,
+//! a higher-level slot is cascaded into the levels below when the wheel
+//! reaches it, and level-0 slots fire in batches. A jump of more than
+//! [`REBUILD_AFTER_TICKS`] seconds, such as a manual clock in tests or the
+//! first advance after startup, re-places every timer instead of walking each
+//! tick.
+//!
+//! Keys are spread over [`SHARDS`] independently locked wheels, so scheduling
+//! from request handlers rarely contends with the expiry task.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const LEVELS: usize = 4;
+
+/// Independently locked wheels
+pub const SHARDS: usize = 16;
+
+/// Jumps longer than this re-place every timer instead of walking each tick
+pub const REBUILD_AFTER_TICKS: u64 = 4_096;
+
+/// What a timer is for; each kind is handled by its own subsystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimerKind {
+    /// An agent protocol's next report is due; key is "agent_id::protocol_key"
+    ReportDeadline,
+    /// A parked message expires; key is the park id
+    ParkExpiry,
+    /// A resolved parked message's outcome is forgotten; key is the park id
+    ParkRetention,
+    /// A resolved quarantined message's outcome is forgotten; key is the
+    /// quarantine id
+    QuarantineRetention,
+    /// A two-phase send's reservation expires; key is the reservation id
+    ReservationExpiry,
+    /// A resolved reservation's outcome is forgotten; key is the reservation id
+    ReservationRetention,
+    /// A tracked message's unconfirmed recipients are flagged; key is the message id
+    DeliveryTimeout,
+    /// A tracked message is forgotten; key is the message id
+    DeliveryRetention,
+    /// A protocol's trial runs out; key is "agent_id::protocol_key"
+    TrialEnd,
+    /// An investigation hold runs out; key is the agent id
+    HoldExpiry,
+    /// A protocol reaches its next recertification stage; key is
+    /// "agent_id::protocol_key"
+    Recertification,
+}
+
+impl TimerKind {
+    pub const ALL: [Self; 11] = [
+        Self::ReportDeadline,
+        Self::ParkExpiry,
+        Self::ParkRetention,
+        Self::QuarantineRetention,
+        Self::ReservationExpiry,
+        Self::ReservationRetention,
+        Self::DeliveryTimeout,
+        Self::DeliveryRetention,
+        Self::TrialEnd,
+        Self::HoldExpiry,
+        Self::Recertification,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ReportDeadline => "report_deadline",
+            Self::ParkExpiry => "park_expiry",
+            Self::ParkRetention => "park_retention",
+            Self::QuarantineRetention => "quarantine_retention",
+            Self::ReservationExpiry => "reservation_expiry",
+            Self::ReservationRetention => "reservation_retention",
+            Self::DeliveryTimeout => "delivery_timeout",
+            Self::DeliveryRetention => "delivery_retention",
+            Self::TrialEnd => "trial_end",
+            Self::HoldExpiry => "hold_expiry",
+            Self::Recertification => "recertification",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A timer that reached its deadline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expired {
+    pub kind: TimerKind,
+    pub key: String,
+    /// Deadline it was scheduled for, in Unix seconds
+    pub at: u64,
+}
+
+type TimerKey = (TimerKind, String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    /// Already due; fires on the next advance
+    Due,
+    Wheel { level: usize, slot: usize },
+    Overflow,
+}
+
+struct Wheel {
+    /// Last tick processed, in Unix seconds
+    now: u64,
+    /// Deadline and location of every pending timer
+    timers: HashMap<TimerKey, (u64, Slot)>,
+    levels: Vec<Vec<HashSet<TimerKey>>>,
+    due: HashSet<TimerKey>,
+    overflow: HashSet<TimerKey>,
+}
+
+impl Wheel {
+    fn new(now: u64) -> Self {
+        Self {
+            now,
+            timers: HashMap::new(),
+            levels: (0..LEVELS).map(|_| vec![HashSet::new(); SLOTS]).collect(),
+            due: HashSet::new(),
+            overflow: HashSet::new(),
+        }
+    }
+
+    /// Slot for a deadline, by the highest 6-bit group where it differs from now
+    fn locate(&self, at: u64) -> Slot {
+        if at <= self.now {
+            return Slot::Due;
+        }
+        let level = ((63 - (at ^ self.now).leading_zeros()) / SLOT_BITS) as usize;
+        if level >= LEVELS {
+            return Slot::Overflow;
+        }
+        let slot = ((at >> (SLOT_BITS as usize * level)) as usize) & (SLOTS - 1);
+        Slot::Wheel { level, slot }
+    }
+
+    fn bucket(&mut self, slot: Slot) -> &mut HashSet<TimerKey> {
+        match slot {
+            Slot::Due => &mut self.due,
+            Slot::Wheel { level, slot } => &mut self.levels[level][slot],
+            Slot::Overflow => &mut self.overflow,
+        }
+    }
+
+    /// Insert or move a timer; returns whether it is new
+    fn insert(&mut self, key: TimerKey, at: u64) -> bool {
+        let existed = self.remove(&key).is_some();
+        let slot = self.locate(at);
+        self.bucket(slot).insert(key.clone());
+        self.timers.insert(key, (at, slot));
+        !existed
+    }
+
+    fn remove(&mut self, key: &TimerKey) -> Option<u64> {
+        let (at, slot) = self.timers.remove(key)?;
+        self.bucket(slot).remove(key);
+        Some(at)
+    }
+
+    /// Re-place the timers of a bucket relative to the current tick
+    fn replace(&mut self, keys: HashSet<TimerKey>) {
+        for key in keys {
+            if let Some(&(at, _)) = self.timers.get(&key) {
+                let slot = self.locate(at);
+                self.bucket(slot).insert(key.clone());
+                self.timers.insert(key, (at, slot));
+            }
+        }
+    }
+
+    fn fire(&mut self, keys: HashSet<TimerKey>, out: &mut Vec<Expired>) {
+        for key in keys {
+            if let Some((at, _)) = self.timers.remove(&key) {
+                out.push(Expired { kind: key.0, key: key.1, at });
+            }
+        }
+    }
+
+    fn advance(&mut self, now: u64, out: &mut Vec<Expired>) {
+        let due = std::mem::take(&mut self.due);
+        self.fire(due, out);
+        if now <= self.now {
+            return;
+        }
+        if now - self.now > REBUILD_AFTER_TICKS {
+            self.now = now;
+            let mut all: HashSet<TimerKey> = std::mem::take(&mut self.overflow);
+            for level in &mut self.levels {
+                for slot in level.iter_mut() {
+                    all.extend(std::mem::take(slot));
+                }
+            }
+            self.replace(all);
+            let due = std::mem::take(&mut self.due);
+            self.fire(due, out);
+            return;
+        }
+        while self.now < now {
+            self.now += 1;
+            let t = self.now;
+            // Cascade from the top so timers can fall through several levels
+            if t.trailing_zeros() >= SLOT_BITS * LEVELS as u32 {
+                let overflow = std::mem::take(&mut self.overflow);
+                self.replace(overflow);
+            }
+            for level in (1..LEVELS).rev() {
+                let shift = SLOT_BITS as usize * level;
+                if t & ((1 << shift) - 1) == 0 {
+                    let slot = ((t >> shift) as usize) & (SLOTS - 1);
+                    let keys = std::mem::take(&mut self.levels[level][slot]);
+                    self.replace(keys);
+                }
+            }
+            let slot = (t as usize) & (SLOTS - 1);
+            let keys = std::mem::take(&mut self.levels[0][slot]);
+            self.fire(keys, out);
+            let due = std::mem::take(&mut self.due);
+            self.fire(due, out);
+        }
+    }
+}
+
+/// Deadline tracking shared by every timed subsystem
+pub struct Timers {
+    shards: Vec<Mutex<Wheel>>,
+    pending: [AtomicU64; TimerKind::ALL.len()],
+    fired: [AtomicU64; TimerKind::ALL.len()],
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Timers {
+    /// Wheels starting at `now` (Unix seconds)
+    pub fn new(now: u64) -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Mutex::new(Wheel::new(now))).collect(),
+            pending: Default::default(),
+            fired: Default::default(),
+        }
+    }
+
+    fn shard(&self, kind: TimerKind, key: &str) -> &Mutex<Wheel> {
+        let mut hasher = DefaultHasher::new();
+        (kind, key).hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+
+    /// Fire `(kind, key)` at `at`, replacing any timer already set for it
+    pub fn schedule(&self, kind: TimerKind, key: &str, at: u64) {
+        let added = self
+            .shard(kind, key)
+            .lock()
+            .unwrap()
+            .insert((kind, key.to_string()), at);
+        if added {
+            self.pending[kind.index()].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Drop a pending timer; returns whether one was set
+    pub fn cancel(&self, kind: TimerKind, key: &str) -> bool {
+        let removed = self
+            .shard(kind, key)
+            .lock()
+            .unwrap()
+            .remove(&(kind, key.to_string()))
+            .is_some();
+        if removed {
+            self.pending[kind.index()].fetch_sub(1, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    /// Deadline of a pending timer
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn deadline(&self, kind: TimerKind, key: &str) -> Option<u64> {
+        let wheel = self.shard(kind, key).lock().unwrap();
+        wheel.timers.get(&(kind, key.to_string())).map(|(at, _)| *at)
+    }
+
+    /// Advance every shard to `now`, returning the timers that fired
+    pub fn advance(&self, now: u64) -> Vec<Expired> {
+        let mut expired = Vec::new();
+        for shard in &self.shards {
+            shard.lock().unwrap().advance(now, &mut expired);
+        }
+        for timer in &expired {
+            self.pending[timer.kind.index()].fetch_sub(1, Ordering::Relaxed);
+            self.fired[timer.kind.index()].fetch_add(1, Ordering::Relaxed);
+        }
+        expired
+    }
+
+    /// Pending timers of `kind`
+    pub fn pending(&self, kind: TimerKind) -> u64 {
+        self.pending[kind.index()].load(Ordering::Relaxed)
+    }
+
+    /// Timers of `kind` fired so far
+    pub fn fired(&self, kind: TimerKind) -> u64 {
+        self.fired[kind.index()].load(Ordering::Relaxed)
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const T0: u64 = 1_700_000_000;
+
+    /// One timer per wheel level, one in overflow, and one already due
+    const OFFSETS: [u64; 9] = [0, 5, 63, 64, 100, 4_000, 300_000, 20_000_000, 900_000_000];
+
+    fn scheduled() -> Timers {
+        let timers = Timers::new(T0);
+        for (i, offset) in OFFSETS.iter().enumerate() {
+            timers.schedule(TimerKind::ReportDeadline, &format!("k{i}"), T0 + offset);
+        }
+        timers
+    }
+
+    #[test]
+    fn test_cancel_removes_a_timer_once() {
+        let timers = Timers::new(T0);
+        timers.schedule(TimerKind::ParkExpiry, "cancelled", T0 + 10);
+        assert!(timers.cancel(TimerKind::ParkExpiry, "cancelled"));
+        assert!(!timers.cancel(TimerKind::ParkExpiry, "cancelled"));
+        assert_eq!(timers.pending(TimerKind::ParkExpiry), 0);
+        assert!(timers.advance(T0 + 10).is_empty());
+    }
+
+    #[test]
+    fn test_rescheduling_replaces_the_deadline() {
+        let timers = scheduled();
+        timers.schedule(TimerKind::ReportDeadline, "k1", T0 + 6);
+        assert_eq!(timers.deadline(TimerKind::ReportDeadline, "k1"), Some(T0 + 6));
+        assert_eq!(timers.pending(TimerKind::ReportDeadline), OFFSETS.len() as u64);
+    }
+
+    #[test]
+    fn test_timers_fire_on_time_across_levels() {
+        let timers = scheduled();
+        // Walk tick by tick past the level-1 boundary
+        let mut fired = Vec::new();
+        for t in T0..=T0 + 200 {
+            for e in timers.advance(t) {
+                assert_eq!(e.at, t, "{} fired late", e.key);
+                fired.push(e.key);
+            }
+        }
+        assert_eq!(fired, vec!["k0", "k1", "k2", "k3", "k4"]);
+    }
+
+    #[test]
+    fn test_large_jumps_fire_what_is_due_and_keep_the_rest() {
+        let timers = scheduled();
+        let fired: Vec<_> = timers.advance(T0 + 400_000).into_iter().map(|e| e.key).collect();
+        assert_eq!(fired.len(), 7);
+        assert!(fired.contains(&"k5".to_string()) && fired.contains(&"k6".to_string()));
+        assert_eq!(timers.deadline(TimerKind::ReportDeadline, "k7"), Some(T0 + 20_000_000));
+        assert!(timers.advance(T0 + 400_001).is_empty());
+        assert_eq!(timers.advance(T0 + 1_000_000_000).len(), 2);
+        assert_eq!(timers.pending(TimerKind::ReportDeadline), 0);
+        assert_eq!(timers.fired(TimerKind::ReportDeadline), OFFSETS.len() as u64);
+    }
+}
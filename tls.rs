@@ -0,0 +1,267 @@
+//! TLS termination with certificate hot reload
+//!
+//! With `TLS_CERT_PATH` and `TLS_KEY_PATH` set, the gateway serves HTTPS with
+//! the PEM certificate chain and private key at those paths. Both are
+//! watched (inotify on Linux): when either changes, the pair is read again
+//! and swapped into the listener's rustls config. Connections accepted
+//! before the swap, long-lived WebSocket streams included, keep their
+//! session; only new handshakes see the new certificate.
+//!
+//! The watch is on the parent directories, so rotations that replace the
+//! files (a rename over them, or the `..data` symlink swap of a mounted
+//! Kubernetes secret) are seen as well as writes in place. Changes within
+//! `TLS_RELOAD_DEBOUNCE_MS` are coalesced, so a certificate and key written
+//! one after the other are loaded together, and a change that leaves both
+//! files as they were is ignored. A pair that cannot be loaded (missing
+//! file, malformed PEM) leaves the current certificate in place until the
+//! next change.
+//!
+//! Every reload is audited as `tls_reloaded` and every failure as
+//! `tls_reload_failed`.
+
+use axum::Router;
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use notify::{
+    event::{AccessKind, AccessMode},
+    Event, EventKind, RecursiveMode, Watcher,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeSet,
+    env,
+    ffi::OsStr,
+    future::Future,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::env_parse;
+
+/// Default time to wait for further changes before reloading
+const DEFAULT_DEBOUNCE_MS: u64 = 500;
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    pub debounce: Duration,
+}
+
+impl TlsConfig {
+    /// Load `TLS_CERT_PATH`, `TLS_KEY_PATH`, and `TLS_RELOAD_DEBOUNCE_MS`;
+    /// `None` serves plain HTTP
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let path = |key: &str| env::var(key).ok().filter(|v| !v.trim().is_empty()).map(PathBuf::from);
+        let (cert_path, key_path) = match (path("TLS_CERT_PATH"), path("TLS_KEY_PATH")) {
+            (Some(cert), Some(key)) => (cert, key),
+            (None, None) => return Ok(None),
+            _ => return Err("TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string()),
+        };
+        let debounce_ms = env_parse("TLS_RELOAD_DEBOUNCE_MS").unwrap_or(DEFAULT_DEBOUNCE_MS);
+        Ok(Some(Self {
+            cert_path,
+            key_path,
+            debounce: Duration::from_millis(debounce_ms),
+        }))
+    }
+
+    fn read(&self) -> Result<(Vec<u8>, Vec<u8>), String> {
+        let read = |path: &Path| std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()));
+        Ok((read(&self.cert_path)?, read(&self.key_path)?))
+    }
+
+    /// Directories to watch
+    fn dirs(&self) -> BTreeSet<PathBuf> {
+        [&self.cert_path, &self.key_path]
+            .into_iter()
+            .map(|p| match p.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                _ => PathBuf::from("."),
+            })
+            .collect()
+    }
+
+    /// Whether `event` may have changed the certificate or key
+    fn affects(&self, event: &Event) -> bool {
+        let writes = matches!(
+            event.kind,
+            EventKind::Create(_)
+                | EventKind::Modify(_)
+                | EventKind::Remove(_)
+                | EventKind::Access(AccessKind::Close(AccessMode::Write))
+        );
+        let names = [self.cert_path.file_name(), self.key_path.file_name()];
+        writes
+            && event.paths.iter().any(|p| {
+                let name = p.file_name();
+                names.contains(&name) || name.and_then(OsStr::to_str).is_some_and(|n| n.starts_with(".."))
+            })
+    }
+}
+
+/// Reload outcomes, for `/metrics`
+#[derive(Debug, Default)]
+pub struct TlsReloads {
+    pub reloads: AtomicU64,
+    pub failures: AtomicU64,
+}
+
+fn sha256_hex(parts: &[&[u8]]) -> String {
+    let digest = parts.iter().fold(Sha256::new(), |h, part| h.chain_update(part)).finalize();
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Load the certificate and key for the listener
+pub async fn load(config: &TlsConfig) -> Result<RustlsConfig, String> {
+    let (cert, key) = config.read()?;
+    RustlsConfig::from_pem(cert, key).await.map_err(|e| e.to_string())
+}
+
+/// Watch the certificate and key, swapping changes into `rustls` until the
+/// process exits
+pub fn watch(config: TlsConfig, rustls: RustlsConfig, counters: Arc<TlsReloads>) -> Result<(), String> {
+    let (tx, mut rx) = mpsc::channel::<()>(1);
+    let filter = config.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+        // A full channel already has a reload pending
+        Ok(event) if filter.affects(&event) => _ = tx.try_send(()),
+        Ok(_) => {}
+        Err(e) => warn!(event = "tls_watch_error", error = %e, "Certificate watch error"),
+    })
+    .map_err(|e| e.to_string())?;
+    for dir in config.dirs() {
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .map_err(|e| format!("{}: {e}", dir.display()))?;
+    }
+
+    // Taken before the task first runs, so a change made meanwhile is not
+    // mistaken for the pair already loaded
+    let mut current = config.read().ok().map(|(cert, key)| sha256_hex(&[&cert, &key]));
+    tokio::spawn(async move {
+        let _watcher = watcher;
+        while rx.recv().await.is_some() {
+            tokio::time::sleep(config.debounce).await;
+            while rx.try_recv().is_ok() {}
+            reload(&config, &rustls, &counters, &mut current).await;
+        }
+    });
+    Ok(())
+}
+
+/// Swap in the pair on disk unless it is the one already loaded
+async fn reload(config: &TlsConfig, rustls: &RustlsConfig, counters: &TlsReloads, current: &mut Option<String>) {
+    let loaded = match config.read() {
+        Ok((cert, key)) => {
+            let pair = sha256_hex(&[&cert, &key]);
+            if current.as_deref() == Some(pair.as_str()) {
+                return;
+            }
+            let cert_sha256 = sha256_hex(&[&cert]);
+            match rustls.reload_from_pem(cert, key).await {
+                Ok(()) => Ok((pair, cert_sha256)),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    match loaded {
+        Ok((pair, cert_sha256)) => {
+            counters.reloads.fetch_add(1, Ordering::Relaxed);
+            info!(
+                cert_path = %config.cert_path.display(),
+                cert_sha256 = %cert_sha256,
+                event = "tls_reloaded",
+                "TLS certificate reloaded"
+            );
+            *current = Some(pair);
+        }
+        Err(e) => {
+            counters.failures.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                cert_path = %config.cert_path.display(),
+                error = %e,
+                event = "tls_reload_failed",
+                "TLS certificate reload failed, keeping the current certificate"
+            );
+        }
+    }
+}
+
+/// Serve `app` over TLS until `shutdown` resolves, then drain connections
+pub async fn serve(
+    addr: SocketAddr,
+    rustls: RustlsConfig,
+    app: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) {
+    let handle = Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            shutdown.await;
+            handle.graceful_shutdown(None);
+        }
+    });
+    axum_server::bind_rustls(addr, rustls)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn write_pair(config: &TlsConfig) {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(&config.cert_path, generated.cert.pem()).unwrap();
+        std::fs::write(&config.key_path, generated.key_pair.serialize_pem()).unwrap();
+    }
+
+    async fn wait_for(counter: &AtomicU64, value: u64) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while counter.load(Ordering::Relaxed) < value {
+            assert!(Instant::now() < deadline, "counter stayed at {}", counter.load(Ordering::Relaxed));
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reload_on_change() {
+        let suffix = crate::signing::random_hex::<8>();
+        let dir = env::temp_dir().join(format!("gateway-tls-{suffix}"));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = TlsConfig {
+            cert_path: dir.join("tls.crt"),
+            key_path: dir.join("tls.key"),
+            debounce: Duration::from_millis(50),
+        };
+        write_pair(&config);
+        let rustls = load(&config).await.unwrap();
+        let counters = Arc::new(TlsReloads::default());
+        watch(config.clone(), rustls.clone(), counters.clone()).unwrap();
+
+        // A broken key is refused and the loaded pair kept
+        std::fs::write(&config.key_path, "not a key").unwrap();
+        wait_for(&counters.failures, 1).await;
+        assert_eq!(counters.reloads.load(Ordering::Relaxed), 0);
+
+        write_pair(&config);
+        wait_for(&counters.reloads, 1).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
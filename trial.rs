@@ -0,0 +1,147 @@
+//! Opt-in trial period for new protocols
+//!
+//! An agent tuning its translation pipeline for a new protocol rarely meets
+//! the full report thresholds on day one. Registering with `"trial": true`
+//! puts the protocol on trial while the policy's `trial` section is set:
+//! reports on it are held to the trial's `min_coverage` and
+//! `min_summary_length` instead of the policy's, and every audit event of its
+//! sends and reports carries `trial: true`. Reporting cadence, registration,
+//! and every other check apply as usual.
+//!
+//! The protocol graduates to full enforcement `duration_sec` after
+//! registration or once `max_messages` messages were accepted under it,
+//! whichever comes first (0 disables either bound). Graduation is logged as
+//! `protocol_graduated` and its owner is alerted. Removing the `trial`
+//! section graduates every protocol on trial at its next send or report.
+//!
+//! Only a first registration starts a trial; registering the protocol again
+//! neither starts nor restarts one.
+
+use serde::{Deserialize, Serialize};
+
+/// Relaxed report thresholds for protocols on trial
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrialPolicy {
+    /// Seconds after registration a trial lasts; 0 for no time bound
+    #[serde(default)]
+    pub duration_sec: u64,
+    /// Accepted messages after which a trial ends; 0 for no message bound
+    #[serde(default)]
+    pub max_messages: u64,
+    /// Minimum report coverage while on trial
+    pub min_coverage: f64,
+    /// Minimum English summary length while on trial
+    pub min_summary_length: usize,
+}
+
+impl TrialPolicy {
+    /// Read `TRIAL_DURATION_SEC` and `TRIAL_MAX_MESSAGES`, either of which
+    /// enables trials, and `TRIAL_MIN_COVERAGE` and `TRIAL_MIN_SUMMARY_LENGTH`
+    pub fn from_env() -> Option<Self> {
+        use crate::env_parse as var;
+        let duration_sec = var("TRIAL_DURATION_SEC").unwrap_or(0);
+        let max_messages = var("TRIAL_MAX_MESSAGES").unwrap_or(0);
+        if duration_sec == 0 && max_messages == 0 {
+            return None;
+        }
+        Some(Self {
+            duration_sec,
+            max_messages,
+            min_coverage: var("TRIAL_MIN_COVERAGE").unwrap_or(0.5),
+            min_summary_length: var("TRIAL_MIN_SUMMARY_LENGTH").unwrap_or(10),
+        })
+    }
+
+    /// Trials must end, and may only relax the full thresholds
+    pub fn validate(&self, min_coverage: f64, min_summary_length: usize) -> Result<(), String> {
+        if self.duration_sec == 0 && self.max_messages == 0 {
+            return Err("trial needs a positive duration_sec or max_messages".to_string());
+        }
+        if !(0.0..=min_coverage).contains(&self.min_coverage) {
+            return Err("trial.min_coverage must be within [0, min_coverage]".to_string());
+        }
+        if self.min_summary_length > min_summary_length {
+            return Err("trial.min_summary_length must be at most min_summary_length".to_string());
+        }
+        Ok(())
+    }
+
+    /// When a trial started at `started_at` runs out of time, if it does
+    pub fn ends_at(&self, started_at: u64) -> Option<u64> {
+        (self.duration_sec > 0).then(|| started_at + self.duration_sec)
+    }
+}
+
+/// A protocol's trial, while it lasts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trial {
+    pub started_at: u64,
+}
+
+/// Why a protocol left its trial
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Graduation {
+    /// `duration_sec` elapsed
+    Elapsed,
+    /// `max_messages` messages were accepted
+    Messages,
+    /// The policy no longer has trials
+    TrialsEnded,
+}
+
+impl Graduation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Elapsed => "elapsed",
+            Self::Messages => "messages",
+            Self::TrialsEnded => "trials_ended",
+        }
+    }
+}
+
+impl Trial {
+    /// Whether the trial is over after `messages` accepted messages as of `now`
+    pub fn graduation(&self, policy: Option<&TrialPolicy>, messages: u64, now: u64) -> Option<Graduation> {
+        let Some(policy) = policy else {
+            return Some(Graduation::TrialsEnded);
+        };
+        if policy.ends_at(self.started_at).is_some_and(|end| now >= end) {
+            Some(Graduation::Elapsed)
+        } else if policy.max_messages > 0 && messages >= policy.max_messages {
+            Some(Graduation::Messages)
+        } else {
+            None
+        }
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graduation() {
+        let policy = TrialPolicy {
+            duration_sec: 3600,
+            max_messages: 100,
+            min_coverage: 0.5,
+            min_summary_length: 10,
+        };
+        assert!(policy.validate(0.95, 30).is_ok());
+        assert!(policy.validate(0.4, 30).is_err(), "a trial may not be stricter");
+
+        let trial = Trial { started_at: 1000 };
+        assert_eq!(trial.graduation(Some(&policy), 99, 4599), None);
+        assert_eq!(trial.graduation(Some(&policy), 100, 4599), Some(Graduation::Messages));
+        assert_eq!(trial.graduation(Some(&policy), 0, 4600), Some(Graduation::Elapsed));
+        assert_eq!(trial.graduation(None, 0, 1000), Some(Graduation::TrialsEnded));
+
+        let unbounded = TrialPolicy { duration_sec: 0, ..policy };
+        assert_eq!(unbounded.ends_at(1000), None);
+        assert_eq!(trial.graduation(Some(&unbounded), 99, u64::MAX), None);
+    }
+}
@@ -0,0 +1,134 @@
+// Polls the gateway's read endpoints with the token entered on the page.
+"use strict";
+
+const POLL_MS = 5000;
+const DECISIONS_KEPT = 50;
+// Audit events read on connect, so the page opens on recent history
+const BACKLOG = 500;
+const DECISION_EVENTS = new Set(["msg_accepted", "msg_rejected", "msg_quarantined"]);
+
+let cursor = null;
+let timer = null;
+const decisions = [];
+// "agent_id::protocol" -> report_deadline_passed event
+const overdue = new Map();
+
+const $ = (id) => document.getElementById(id);
+
+function headers() {
+  const token = sessionStorage.getItem("gateway-token");
+  return token ? { Authorization: `Bearer ${token}` } : {};
+}
+
+async function fetchOk(path) {
+  const response = await fetch(path, { headers: headers() });
+  if (!response.ok) {
+    const body = await response.json().catch(() => ({}));
+    throw new Error(`${path}: ${response.status} ${body.code || ""}`.trim());
+  }
+  return response;
+}
+
+function cell(row, text, className) {
+  const td = row.insertCell();
+  td.textContent = text ?? "";
+  if (className) td.className = className;
+}
+
+function time(ts) {
+  return new Date(ts * 1000).toLocaleTimeString();
+}
+
+async function refreshAgents() {
+  const agents = (await (await fetchOk("/agents")).json()).filter((a) => !a.deleted);
+  const body = $("agents");
+  body.replaceChildren();
+  for (const agent of agents.sort((a, b) => b.violations - a.violations)) {
+    const row = body.insertRow();
+    cell(row, agent.agent_id);
+    cell(row, agent.team);
+    cell(row, agent.protocols);
+    cell(row, agent.violations);
+    cell(row, agent.alerts);
+  }
+  $("agents-count").textContent = agents.length;
+  $("violations-count").textContent = agents.reduce((sum, a) => sum + a.violations, 0);
+}
+
+function record(event) {
+  const f = event.fields;
+  if (DECISION_EVENTS.has(event.event)) {
+    decisions.unshift(event);
+    decisions.length = Math.min(decisions.length, DECISIONS_KEPT);
+  } else if (event.event === "report_deadline_passed") {
+    overdue.set(`${f.agent_id}::${f.protocol}`, event);
+  } else if (event.event === "report_accepted") {
+    overdue.delete(`${f.agent_id}::${f.protocol}`);
+  }
+}
+
+async function refreshEvents() {
+  if (cursor === null) {
+    const head = await fetchOk("/audit/export?limit=0");
+    const end = Number(head.headers.get("x-audit-cursor-end"));
+    const first = Number(head.headers.get("x-audit-first-seq"));
+    cursor = Math.max(first, end - BACKLOG);
+  }
+  const response = await fetchOk(`/audit/export?cursor=${cursor}`);
+  const text = await response.text();
+  for (const line of text.split("\n")) {
+    if (line) record(JSON.parse(line));
+  }
+  cursor = Number(response.headers.get("x-audit-cursor-end"));
+
+  const rows = $("decisions");
+  rows.replaceChildren();
+  for (const event of decisions) {
+    const f = event.fields;
+    const row = rows.insertRow();
+    cell(row, time(event.ts));
+    cell(row, event.event.replace("msg_", ""), event.event);
+    cell(row, f.from);
+    cell(row, f.to);
+    cell(row, f.protocol);
+    cell(row, f.reason);
+  }
+
+  const late = $("overdue");
+  late.replaceChildren();
+  for (const event of overdue.values()) {
+    const row = late.insertRow();
+    cell(row, event.fields.agent_id);
+    cell(row, event.fields.protocol);
+    cell(row, `${event.fields.seconds_since_report}s`);
+    cell(row, time(event.ts));
+  }
+  $("overdue-count").textContent = overdue.size;
+}
+
+async function poll() {
+  try {
+    await Promise.all([refreshAgents(), refreshEvents()]);
+    $("status").textContent = `Updated ${new Date().toLocaleTimeString()}`;
+  } catch (e) {
+    $("status").textContent = e.message;
+  }
+}
+
+function connect() {
+  cursor = null;
+  decisions.length = 0;
+  overdue.clear();
+  clearInterval(timer);
+  poll();
+  timer = setInterval(poll, POLL_MS);
+}
+
+$("auth").addEventListener("submit", (e) => {
+  e.preventDefault();
+  sessionStorage.setItem("gateway-token", $("token").value);
+  $("token").value = "";
+  connect();
+});
+
+if (sessionStorage.getItem("gateway-token")) connect();
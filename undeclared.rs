@@ -0,0 +1,352 @@
+//! Discovery of undeclared protocols in "English" traffic
+//!
+//! An agent can slip novel-language content past the English heuristic a
+//! little at a time, e.g. code strings wrapped in a sentence of prose. With
+//! `UNDECLARED_SCAN_SEC` set, the structural fingerprint (see [`structure`])
+//! of every accepted English message of at least [`MIN_CONTENT_LEN`] bytes is
+//! kept, the last `UNDECLARED_WINDOW` per sender. Allowlisted machine output
+//! is left out.
+//!
+//! Every `UNDECLARED_SCAN_SEC` each sender's window is clustered: a
+//! fingerprint joins the first cluster within [`CLUSTER_RADIUS`] of its leader
+//! or leads a new one. A cluster of at least `UNDECLARED_MIN_CLUSTER`
+//! near-identical, machine-like messages is filed for review as a
+//! `possible_undeclared_protocol` finding (logged and alerted under that
+//! name), once per sender and delimiter shape:
+//!
+//! - `GET /reviews/findings` lists open findings
+//! - `POST /reviews/findings/{id}/confirm` counts a violation against the
+//!   sender (`undeclared_protocol_confirmed`) and clears its window, so the
+//!   same traffic is filed again only once it recurs
+//! - `POST /reviews/findings/{id}/dismiss` closes the finding; the shape is not
+//!   filed again for that sender (`undeclared_finding_dismissed`)
+//!
+//! Windows and findings live in memory, and a standby scans nothing.
+//!
+//! [`structure`]: crate::structure
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+use tracing::{info, warn};
+
+use crate::{
+    alerts::AlertKind,
+    env_parse,
+    error::GatewayError,
+    identity::{AuthedAdmin, AuthedAuditor},
+    metrics::Metrics,
+    raise_alert,
+    replication::Mutation,
+    structure::Fingerprint,
+    AlertSubject, ApiResponse, AppState,
+};
+
+/// Shortest message fingerprinted; shorter ones carry too little structure
+pub const MIN_CONTENT_LEN: usize = 16;
+
+/// Largest distance from a cluster's leader at which a message joins it
+pub const CLUSTER_RADIUS: f64 = 0.15;
+
+/// Default fingerprints kept per sender
+const DEFAULT_WINDOW: usize = 200;
+
+/// Default messages in a cluster before it is filed
+const DEFAULT_MIN_CLUSTER: usize = 10;
+
+/// Kind of finding filed for review
+const FINDING_KIND: &str = "possible_undeclared_protocol";
+
+#[derive(Debug, Clone)]
+pub struct UndeclaredConfig {
+    /// Seconds between scans; 0 disables discovery
+    pub scan_sec: u64,
+    pub window: usize,
+    pub min_cluster: usize,
+}
+
+impl Default for UndeclaredConfig {
+    fn default() -> Self {
+        Self {
+            scan_sec: 0,
+            window: DEFAULT_WINDOW,
+            min_cluster: DEFAULT_MIN_CLUSTER,
+        }
+    }
+}
+
+impl UndeclaredConfig {
+    /// Load `UNDECLARED_SCAN_SEC`, `UNDECLARED_WINDOW`, and
+    /// `UNDECLARED_MIN_CLUSTER`
+    pub fn from_env() -> Self {
+        let d = Self::default();
+        let var = |key: &str| env_parse::<u64>(key);
+        let positive = |key: &str| var(key).filter(|&n| n > 0).map(|n| n as usize);
+        Self {
+            scan_sec: var("UNDECLARED_SCAN_SEC").unwrap_or(d.scan_sec),
+            window: positive("UNDECLARED_WINDOW").unwrap_or(d.window),
+            min_cluster: positive("UNDECLARED_MIN_CLUSTER").unwrap_or(d.min_cluster),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.scan_sec > 0
+    }
+}
+
+/// A cluster of machine-like "English" messages awaiting review
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    pub id: u64,
+    pub kind: &'static str,
+    pub agent_id: String,
+    /// Delimiter shape of the cluster's first message
+    pub shape: String,
+    pub messages: usize,
+    /// Share of the sender's window the cluster holds
+    pub share: f64,
+    pub found_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct Findings {
+    open: BTreeMap<u64, Finding>,
+    /// Sender and shape of open and dismissed findings
+    filed: HashSet<(String, String)>,
+    next_id: u64,
+}
+
+/// Recent fingerprints per sender, and the findings filed from them
+#[derive(Debug, Default)]
+pub struct Undeclared {
+    config: UndeclaredConfig,
+    windows: Mutex<HashMap<String, VecDeque<Fingerprint>>>,
+    findings: Mutex<Findings>,
+}
+
+impl Undeclared {
+    pub fn new(config: UndeclaredConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled()
+    }
+
+    /// Keep the fingerprint of an accepted English message
+    pub fn record(&self, agent_id: &str, content: &str) {
+        if content.len() < MIN_CONTENT_LEN {
+            return;
+        }
+        let sample = Fingerprint::of(content);
+        let mut windows = self.windows.lock().unwrap();
+        let window = windows.entry(agent_id.to_string()).or_default();
+        if window.len() >= self.config.window {
+            window.pop_front();
+        }
+        window.push_back(sample);
+    }
+
+    /// Cluster every window and file the clusters not filed before
+    pub fn scan(&self, now: u64) -> Vec<Finding> {
+        let mut suspicious = Vec::new();
+        for (agent_id, window) in self.windows.lock().unwrap().iter() {
+            let mut clusters: Vec<(&Fingerprint, usize)> = Vec::new();
+            for sample in window {
+                match clusters.iter_mut().find(|(leader, _)| leader.distance(sample) <= CLUSTER_RADIUS) {
+                    Some((_, members)) => *members += 1,
+                    None => clusters.push((sample, 1)),
+                }
+            }
+            suspicious.extend(
+                clusters
+                    .into_iter()
+                    .filter(|(leader, members)| *members >= self.config.min_cluster && leader.is_machine_like())
+                    .map(|(leader, members)| {
+                        (agent_id.clone(), leader.shape().to_string(), members, members as f64 / window.len() as f64)
+                    }),
+            );
+        }
+
+        let mut findings = self.findings.lock().unwrap();
+        let mut filed = Vec::new();
+        for (agent_id, shape, messages, share) in suspicious {
+            if !findings.filed.insert((agent_id.clone(), shape.clone())) {
+                continue;
+            }
+            findings.next_id += 1;
+            let finding = Finding {
+                id: findings.next_id,
+                kind: FINDING_KIND,
+                agent_id,
+                shape,
+                messages,
+                share,
+                found_at: now,
+            };
+            findings.open.insert(finding.id, finding.clone());
+            filed.push(finding);
+        }
+        filed
+    }
+
+    /// Open findings, oldest first
+    pub fn open(&self) -> Vec<Finding> {
+        self.findings.lock().unwrap().open.values().cloned().collect()
+    }
+
+    /// Close a finding; a confirmed one may be filed again on fresh traffic
+    fn resolve(&self, id: u64, confirmed: bool) -> Option<Finding> {
+        let mut findings = self.findings.lock().unwrap();
+        let finding = findings.open.remove(&id)?;
+        if confirmed {
+            findings.filed.remove(&(finding.agent_id.clone(), finding.shape.clone()));
+            self.windows.lock().unwrap().remove(&finding.agent_id);
+        }
+        Some(finding)
+    }
+}
+
+/// Scan the windows and file what they turn up
+pub async fn sweep(state: &AppState, now: u64) -> Vec<Finding> {
+    let filed = state.undeclared.scan(now);
+    for finding in &filed {
+        warn!(
+            agent_id = %finding.agent_id,
+            finding_id = finding.id,
+            shape = %finding.shape,
+            messages = finding.messages,
+            share = finding.share,
+            event = FINDING_KIND,
+            "Machine-like messages accepted as English cluster together"
+        );
+        let detail = format!(
+            "{} near-identical machine-like messages ({:.0}% of recent English traffic) share the shape {:?}; \
+             see finding {} in GET /reviews/findings",
+            finding.messages,
+            finding.share * 100.0,
+            finding.shape,
+            finding.id
+        );
+        raise_alert(state, AlertKind::UndeclaredProtocolSuspected, AlertSubject::Agent(&finding.agent_id), &detail).await;
+    }
+    filed
+}
+
+/// Scan every `UNDECLARED_SCAN_SEC` while discovery is enabled
+pub async fn run(state: AppState) {
+    if !state.undeclared.enabled() {
+        return;
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(state.undeclared.config.scan_sec));
+    loop {
+        interval.tick().await;
+        // A standby files nothing; the primary files for both
+        if state.replication.is_standby() {
+            continue;
+        }
+        sweep(&state, state.clock.now()).await;
+    }
+}
+
+/// Open `possible_undeclared_protocol` findings
+pub async fn list(_: AuthedAuditor, State(state): State<AppState>) -> Json<Vec<Finding>> {
+    Json(state.undeclared.open())
+}
+
+/// Confirm a finding: the sender used an undeclared protocol
+pub async fn confirm(
+    AuthedAdmin(admin): AuthedAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let Some(finding) = state.undeclared.resolve(id, true) else {
+        return Err(GatewayError::NotFound("Unknown finding id"));
+    };
+    {
+        let mut st = state.inner.write().unwrap();
+        let count = st.add_violation(&finding.agent_id);
+        state.replication.record(Mutation::Violations { agent_id: finding.agent_id.clone(), count });
+    }
+    state.decision_cache.invalidate_agent(&finding.agent_id);
+    Metrics::inc(&state.metrics.violations);
+    warn!(
+        agent_id = %finding.agent_id,
+        finding_id = id,
+        shape = %finding.shape,
+        admin = %admin,
+        event = "undeclared_protocol_confirmed",
+        violation = true,
+        "Undeclared protocol confirmed on review"
+    );
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+/// Dismiss a finding; the sender's shape is not filed again
+pub async fn dismiss(
+    AuthedAdmin(admin): AuthedAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<(StatusCode, Json<ApiResponse>), GatewayError> {
+    let Some(finding) = state.undeclared.resolve(id, false) else {
+        return Err(GatewayError::NotFound("Unknown finding id"));
+    };
+    info!(
+        agent_id = %finding.agent_id,
+        finding_id = id,
+        shape = %finding.shape,
+        admin = %admin,
+        event = "undeclared_finding_dismissed",
+        "Undeclared protocol finding dismissed"
+    );
+    Ok((StatusCode::OK, Json(ApiResponse::success())))
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestGateway, ADMIN_TOKEN};
+    use axum::http::Method;
+
+    #[tokio::test]
+    async fn test_machine_like_cluster_filed_for_review() {
+        let gw = TestGateway::new();
+        let undeclared = &gw.state().undeclared;
+        for i in 0..12 {
+            undeclared.record("a", &format!("Status update: X9|st={i};f=0x{:x};ack#{}", i * 7, i + 40));
+            undeclared.record("a", &format!("Thanks, I will send the summary for batch {i} tomorrow."));
+            undeclared.record("b", &format!("Meeting notes for item {i} are attached below."));
+        }
+        undeclared.record("a", "ok");
+
+        let filed = sweep(gw.state(), gw.now()).await;
+        assert_eq!(filed.len(), 1, "{filed:?}");
+        assert_eq!((filed[0].agent_id.as_str(), filed[0].messages), ("a", 12));
+        assert!(sweep(gw.state(), gw.now()).await.is_empty(), "filed once");
+
+        let listed = gw.call(Method::GET, "/reviews/findings", None::<&()>, Some(ADMIN_TOKEN)).await;
+        assert_eq!(listed.body[0]["kind"], "possible_undeclared_protocol");
+        let path = format!("/reviews/findings/{}/confirm", filed[0].id);
+        let confirmed = gw.call(Method::POST, &path, None::<&()>, Some(ADMIN_TOKEN)).await;
+        assert_eq!(confirmed.status, StatusCode::OK);
+        assert_eq!(gw.state().inner.read().unwrap().violations.get("a").copied(), Some(1));
+        assert!(undeclared.open().is_empty());
+        let again = gw.call(Method::POST, &path, None::<&()>, Some(ADMIN_TOKEN)).await;
+        assert_eq!(again.status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,233 @@
+//! Protocol version compatibility
+//!
+//! A descriptor may declare `compatible_with` (e.g. `"1.x"`) to mark itself as
+//! the successor of earlier versions of the same protocol. On registration the
+//! new version either inherits the newest report clock among the compatible
+//! versions or starts fresh (`history`). Sends and reports that declare an
+//! older, still-registered version are then handled per the successor's
+//! `legacy_sends` policy: allowed as-is, upgraded to the successor, or rejected.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+use crate::{protocol_key, InnerState};
+
+/// Whether a new version carries over report history from compatible versions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryPolicy {
+    #[default]
+    Inherit,
+    Reset,
+}
+
+/// How sends declaring a superseded version are treated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LegacySendPolicy {
+    /// Evaluate against the declared (older) registration
+    #[default]
+    Allow,
+    /// Evaluate against the successor registration
+    Upgrade,
+    /// Refuse until the sender declares the successor version
+    Reject,
+}
+
+/// Outcome of resolving a declared protocol version
+#[derive(Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// Use this protocol key; `upgraded_from` is set when the declared version was upgraded
+    Use {
+        key: String,
+        upgraded_from: Option<String>,
+    },
+    /// Declared version is superseded and its successor rejects legacy use
+    Superseded { successor: String },
+}
+
+/// Compare dotted version strings numerically where possible
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut left = a.split('.');
+    let mut right = b.split('.');
+    loop {
+        match (left.next(), right.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => {
+                let ord = match (x.parse::<u64>(), y.parse::<u64>()) {
+                    (Ok(x), Ok(y)) => x.cmp(&y),
+                    _ => x.cmp(y),
+                };
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+        }
+    }
+}
+
+/// Whether `version` satisfies a requirement such as `1.x`, `1.2.*`, or `1.0`
+pub fn version_matches(requirement: &str, version: &str) -> bool {
+    let requirement = requirement.trim();
+    if requirement == "*" || requirement == "x" {
+        return true;
+    }
+    let mut actual = version.split('.');
+    for part in requirement.split('.') {
+        if part == "x" || part == "*" {
+            return true;
+        }
+        if actual.next() != Some(part) {
+            return false;
+        }
+    }
+    actual.next().is_none()
+}
+
+/// Report clock a newly registered version should start with, if any
+pub fn inherited_report_ts(
+    st: &InnerState,
+    agent_id: &str,
+    name: &str,
+    version: &str,
+    requirement: &str,
+) -> Option<u64> {
+    st.protocols
+        .get(agent_id)?
+        .values()
+        .filter(|d| d.name == name && d.version != version)
+        .filter(|d| version_matches(requirement, &d.version))
+        .filter_map(|d| {
+            let key = protocol_key(&d.name, &d.version);
+            st.last_report_ts
+                .get(&format!("{agent_id}::{key}"))
+                .copied()
+        })
+        .max()
+}
+
+/// Resolve the protocol key a send or report should be evaluated against
+pub fn resolve(st: &InnerState, agent_id: &str, name: &str, version: &str) -> Resolution {
+    let declared = protocol_key(name, version);
+    let successor = st.protocols.get(agent_id).and_then(|m| {
+        m.values()
+            .filter(|d| {
+                d.name == name && compare_versions(&d.version, version) == Ordering::Greater
+            })
+            .filter(|d| {
+                d.compatible_with
+                    .as_deref()
+                    .is_some_and(|req| version_matches(req, version))
+            })
+            .max_by(|a, b| compare_versions(&a.version, &b.version))
+    });
+
+    match successor {
+        Some(d) if d.legacy_sends == LegacySendPolicy::Upgrade => Resolution::Use {
+            key: protocol_key(&d.name, &d.version),
+            upgraded_from: Some(declared),
+        },
+        Some(d) if d.legacy_sends == LegacySendPolicy::Reject => Resolution::Superseded {
+            successor: protocol_key(&d.name, &d.version),
+        },
+        _ => Resolution::Use {
+            key: declared,
+            upgraded_from: None,
+        },
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProtocolDescriptor;
+
+    fn descriptor(
+        version: &str,
+        compatible_with: Option<&str>,
+        legacy: LegacySendPolicy,
+    ) -> ProtocolDescriptor {
+        ProtocolDescriptor {
+            name: "coord".try_into().unwrap(),
+            version: version.try_into().unwrap(),
+            purpose: "Coordination".into(),
+            scope: "Internal".into(),
+            risk_tier: "low".into(),
+            translation_method: "dictionary".into(),
+            compatible_with: compatible_with.map(String::from),
+            history: HistoryPolicy::Inherit,
+            legacy_sends: legacy,
+            codebook: Default::default(),
+            encryption: None,
+            docs: Vec::new(),
+            message_schema: None,
+            allowed_recipients: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_version_matching() {
+        assert!(version_matches("1.x", "1.0"));
+        assert!(version_matches("1.x", "1.4.2"));
+        assert!(version_matches("1.2.*", "1.2.9"));
+        assert!(version_matches("1.0", "1.0"));
+        assert!(!version_matches("1.0", "1.0.1"));
+        assert!(!version_matches("1.x", "2.0"));
+        assert!(!version_matches("1.x", "10.0"));
+        assert_eq!(compare_versions("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("2.0", "2.0.1"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_resolve_legacy_sends() {
+        let mut st = InnerState::default();
+        let agent = st.protocols.entry("a".into()).or_default();
+        agent.insert(
+            "coord:1.0".into(),
+            descriptor("1.0", None, LegacySendPolicy::Allow),
+        );
+        agent.insert(
+            "coord:2.0".into(),
+            descriptor("2.0", Some("1.x"), LegacySendPolicy::Upgrade),
+        );
+        st.last_report_ts.insert("a::coord:1.0".into(), 42);
+
+        assert_eq!(
+            resolve(&st, "a", "coord", "1.0"),
+            Resolution::Use {
+                key: "coord:2.0".into(),
+                upgraded_from: Some("coord:1.0".into())
+            }
+        );
+        assert_eq!(
+            inherited_report_ts(&st, "a", "coord", "2.0", "1.x"),
+            Some(42)
+        );
+
+        st.protocols
+            .get_mut("a")
+            .unwrap()
+            .get_mut("coord:2.0")
+            .unwrap()
+            .legacy_sends = LegacySendPolicy::Reject;
+        assert_eq!(
+            resolve(&st, "a", "coord", "1.0"),
+            Resolution::Superseded {
+                successor: "coord:2.0".into()
+            }
+        );
+        assert_eq!(
+            resolve(&st, "a", "coord", "2.0"),
+            Resolution::Use {
+                key: "coord:2.0".into(),
+                upgraded_from: None
+            }
+        );
+    }
+}
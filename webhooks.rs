@@ -0,0 +1,260 @@
+//! Decision webhooks: external veto over novel-language sends
+//!
+//! `DECISION_WEBHOOKS` lists rules that route sends on matching protocols or
+//! risk tiers to an external decider, such as a human-review queue or a DLP
+//! system, e.g.
+//! `[{"risk_tier": "high", "url": "https://dlp.internal/decide", "on_failure": "deny"}]`.
+//! The first matching rule applies. A rule with neither `protocol` nor
+//! `risk_tier` matches every novel-language send.
+//!
+//! The webhook is called synchronously, after the gateway's own checks passed
+//! and before the message is recorded as delivered. An answer of `deny`
+//! refuses the send. A timeout, an error status, or a malformed answer falls
+//! back to the rule's `on_failure`, which is `deny` (fail closed) unless set to
+//! `allow` (fail open).
+//!
+//! # Decision protocol
+//! `POST {url}` with
+//! `{"from": "...", "to": ["..."], "protocol": "name:version", "risk_tier": "...", "content": "..."}`,
+//! expecting `{"decision": "allow" | "deny", "reason": "..."}` within the
+//! rule's `timeout_ms`. `reason` is optional and is passed back to the sender
+//! on deny.
+//!
+//! A mirrored send on a shadow gateway is never sent to its webhook (see
+//! [`crate::mirror`]).
+
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::mirror;
+
+/// Per-call timeout for rules without `timeout_ms`
+pub const DEFAULT_TIMEOUT_MS: u64 = 2_000;
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+/// What a send gets when its webhook cannot decide
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureMode {
+    /// Fail open: deliver as if allowed
+    Allow,
+    /// Fail closed: refuse the send
+    #[default]
+    Deny,
+}
+
+/// One `DECISION_WEBHOOKS` entry
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookRule {
+    /// Protocol as `name` or `name:version`; any protocol when unset
+    #[serde(default)]
+    pub protocol: Option<String>,
+    /// Risk tier declared at registration; any tier when unset
+    #[serde(default)]
+    pub risk_tier: Option<String>,
+    pub url: String,
+    /// Sent as `Authorization: Bearer ...`
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default)]
+    pub on_failure: FailureMode,
+}
+
+fn default_timeout_ms() -> u64 {
+    DEFAULT_TIMEOUT_MS
+}
+
+impl WebhookRule {
+    fn matches(&self, protocol: &str, risk_tier: &str) -> bool {
+        let name = protocol.split_once(':').map_or(protocol, |(name, _)| name);
+        self.protocol.as_deref().is_none_or(|p| p == protocol || p == name)
+            && self.risk_tier.as_deref().is_none_or(|t| t.eq_ignore_ascii_case(risk_tier))
+    }
+}
+
+/// Load rules from `DECISION_WEBHOOKS`
+///
+/// Fails on invalid JSON: running without the webhooks would deliver sends
+/// they were meant to gate.
+pub fn rules_from_env() -> Result<Vec<WebhookRule>, String> {
+    match env::var("DECISION_WEBHOOKS") {
+        Ok(raw) if !raw.trim().is_empty() => serde_json::from_str(&raw).map_err(|e| e.to_string()),
+        _ => Ok(Vec::new()),
+    }
+}
+
+// =============================================================================
+// Decisions
+// =============================================================================
+
+/// Message context sent to the webhook
+#[derive(Debug, Serialize)]
+pub struct DecisionRequest<'a> {
+    pub from: &'a str,
+    /// Recipients that passed the gateway's own checks
+    pub to: Vec<&'a str>,
+    pub protocol: &'a str,
+    pub risk_tier: &'a str,
+    pub content: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Verdict {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Deserialize)]
+struct DecisionResponse {
+    decision: Verdict,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// What the gateway does with a send after consulting its webhook
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// No rule matched the protocol
+    Unchecked,
+    /// A rule matched, but the send is a mirrored copy
+    Skipped,
+    Allowed,
+    Denied { reason: Option<String> },
+    /// The webhook failed and the rule fails open
+    FailedOpen { error: String },
+    /// The webhook failed and the rule fails closed
+    FailedClosed { error: String },
+}
+
+/// Counters exported via `/metrics`
+#[derive(Debug, Default)]
+pub struct WebhookCounters {
+    pub allowed: AtomicU64,
+    pub denied: AtomicU64,
+    pub failed_open: AtomicU64,
+    pub failed_closed: AtomicU64,
+}
+
+/// Configured decision webhooks
+pub struct DecisionHooks {
+    rules: Vec<WebhookRule>,
+    client: reqwest::Client,
+    pub counters: WebhookCounters,
+}
+
+impl Default for DecisionHooks {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl DecisionHooks {
+    pub fn new(rules: Vec<WebhookRule>) -> Self {
+        Self {
+            rules,
+            client: reqwest::Client::new(),
+            counters: WebhookCounters::default(),
+        }
+    }
+
+    pub fn rules(&self) -> &[WebhookRule] {
+        &self.rules
+    }
+
+    /// Ask the first rule matching the send's protocol and risk tier
+    pub async fn decide(&self, req: &DecisionRequest<'_>) -> Decision {
+        let Some(rule) = self.rules.iter().find(|r| r.matches(req.protocol, req.risk_tier)) else {
+            return Decision::Unchecked;
+        };
+        if mirror::is_mirrored() {
+            return Decision::Skipped;
+        }
+        let decision = match self.call(rule, req).await {
+            Ok(DecisionResponse { decision: Verdict::Allow, .. }) => Decision::Allowed,
+            Ok(DecisionResponse { decision: Verdict::Deny, reason }) => Decision::Denied { reason },
+            Err(error) => match rule.on_failure {
+                FailureMode::Allow => Decision::FailedOpen { error },
+                FailureMode::Deny => Decision::FailedClosed { error },
+            },
+        };
+        let counter = match &decision {
+            Decision::Unchecked | Decision::Skipped => return decision,
+            Decision::Allowed => &self.counters.allowed,
+            Decision::Denied { .. } => &self.counters.denied,
+            Decision::FailedOpen { .. } => &self.counters.failed_open,
+            Decision::FailedClosed { .. } => &self.counters.failed_closed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        decision
+    }
+
+    async fn call(&self, rule: &WebhookRule, req: &DecisionRequest<'_>) -> Result<DecisionResponse, String> {
+        let mut call = self
+            .client
+            .post(&rule.url)
+            .timeout(Duration::from_millis(rule.timeout_ms))
+            .json(req);
+        if let Some(token) = &rule.token {
+            call = call.bearer_auth(token);
+        }
+        let resp = call
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?;
+        resp.json::<DecisionResponse>().await.map_err(|e| e.to_string())
+    }
+}
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rule_matching_and_failure_modes() {
+        let rules: Vec<WebhookRule> = serde_json::from_str(
+            r#"[
+                {"protocol": "coord:2.0", "url": "http://127.0.0.1:1/closed"},
+                {"risk_tier": "high", "url": "http://127.0.0.1:1/open", "timeout_ms": 200, "on_failure": "allow"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(rules[0].timeout_ms, DEFAULT_TIMEOUT_MS);
+        assert!(rules[0].matches("coord:2.0", "low"));
+        assert!(!rules[0].matches("coord:1.0", "low"));
+        assert!(rules[1].matches("coord:1.0", "HIGH"));
+
+        let hooks = DecisionHooks::new(rules);
+        let req = |protocol, risk_tier| DecisionRequest {
+            from: "a",
+            to: vec!["b"],
+            protocol,
+            risk_tier,
+            content: "SHP|eta=7f",
+            content_type: None,
+        };
+        assert_eq!(hooks.decide(&req("coord:1.0", "low")).await, Decision::Unchecked);
+        // Nothing listens on port 1, so both rules fall back to `on_failure`
+        assert!(matches!(hooks.decide(&req("coord:2.0", "high")).await, Decision::FailedClosed { .. }));
+        assert!(matches!(hooks.decide(&req("coord:1.0", "high")).await, Decision::FailedOpen { .. }));
+        assert_eq!(hooks.counters.failed_closed.load(Ordering::Relaxed), 1);
+        assert_eq!(hooks.counters.failed_open.load(Ordering::Relaxed), 1);
+    }
+}